@@ -2,7 +2,7 @@ use anyhow::{Result, anyhow};
 use proc_macro2::{Ident, TokenStream};
 use quote::{TokenStreamExt, ToTokens};
 use syn::{Field, GenericArgument, PathArguments, PathSegment, Type};
-use syn::punctuated::{Pair, Punctuated};
+use syn::punctuated::Punctuated;
 use syn::token::{Colon2, Comma};
 
 /// Generates an implementation for the `from_kafka_bytes` function in the `KafkaEncodable` trait
@@ -34,7 +34,7 @@ pub(crate) fn generate_from_kafka_bytes_impl(struct_name: &Ident, fields: Vec<Fi
 /// by the protocol, each field in such a struct must be wrapped in an `Option`.
 pub(crate) fn generate_from_kafka_bytes_impl_for_tagged_fields(struct_name: &Ident, fields: Vec<Field>) -> Result<TokenStream> {
     // we'll match on tag numbers to identify which field is being deserialized
-    let mut field_tags: Vec<u32> = (0..fields.len() as u32).collect();
+    let field_tags: Vec<u32> = (0..fields.len() as u32).collect();
 
     let (field_idents, field_types) = split_fields_into_idents_and_types(fields)?;
     let (inner_field_types, inner_field_type_paths) = extract_inner_types_and_paths_from_option_field(field_types)?;
@@ -1,11 +1,8 @@
-use std::iter::Map;
 use proc_macro2::{Ident, TokenStream};
-use quote::{quote, TokenStreamExt, ToTokens};
-use quote::__private::ext::RepToTokensExt;
-use syn;
-use syn::{Attribute, Data, Field, Fields, GenericArgument, parse_macro_input, PathArguments, PathSegment, Type};
+use quote::quote;
+use syn::{Attribute, Data, Field, Fields, parse_macro_input, PathSegment};
 use syn::DeriveInput;
-use syn::punctuated::{Iter, Pair, Punctuated};
+use syn::punctuated::Pair;
 use syn::token::Colon2;
 use anyhow::{anyhow, Result};
 use crate::from_kafka_bytes_impl::{generate_from_kafka_bytes_impl, generate_from_kafka_bytes_impl_for_tagged_fields};
@@ -35,7 +32,6 @@ fn generate_kafka_encodable_impl(syntax_tree: DeriveInput) -> Result<TokenStream
         |attribute: &Attribute| attribute.path.segments.pairs()
             .any(|pair: Pair<&PathSegment, &Colon2>| pair.value().ident.to_string() == "kafka_encodable_tagged_fields")
     );
-    dbg!(has_kafka_encodable_tagged_fields_attr);
     match syntax_tree.data {
         Data::Struct(data_struct) => {
             match data_struct.fields {
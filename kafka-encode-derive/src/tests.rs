@@ -66,7 +66,7 @@ fn test_derive_kafka_encodable_for_tagged_fields() {
                 self,
                 writer: &mut W
             ) -> anyhow::Result<()> {
-                let mut num_tagged_fields: i32 = 0;
+                let mut num_tagged_fields: u32 = 0;
                 match self.string {
                     Some(_) => num_tagged_fields += 1,
                     None => {}
@@ -79,7 +79,7 @@ fn test_derive_kafka_encodable_for_tagged_fields() {
                     Some(_) => num_tagged_fields += 1,
                     None => {}
                 };
-                num_tagged_fields.to_kafka_bytes(writer)?;
+                UnsignedVarInt32(num_tagged_fields).to_kafka_bytes(writer)?;
                 match self.string {
                     Some(field) => {
                         let tag: UnsignedVarInt32 = UnsignedVarInt32(0u32);
@@ -88,7 +88,7 @@ fn test_derive_kafka_encodable_for_tagged_fields() {
                         let size: UnsignedVarInt32 = UnsignedVarInt32(buffer.len() as u32);
                         tag.to_kafka_bytes(writer)?;
                         size.to_kafka_bytes(writer)?;
-                        buffer.to_kafka_bytes(writer)?;
+                        writer.write_all(&buffer)?;
                     },
                     None => {}
                 };
@@ -100,7 +100,7 @@ fn test_derive_kafka_encodable_for_tagged_fields() {
                         let size: UnsignedVarInt32 = UnsignedVarInt32(buffer.len() as u32);
                         tag.to_kafka_bytes(writer)?;
                         size.to_kafka_bytes(writer)?;
-                        buffer.to_kafka_bytes(writer)?;
+                        writer.write_all(&buffer)?;
                     },
                     None => {}
                 };
@@ -112,7 +112,7 @@ fn test_derive_kafka_encodable_for_tagged_fields() {
                         let size: UnsignedVarInt32 = UnsignedVarInt32(buffer.len() as u32);
                         tag.to_kafka_bytes(writer)?;
                         size.to_kafka_bytes(writer)?;
-                        buffer.to_kafka_bytes(writer)?;
+                        writer.write_all(&buffer)?;
                     },
                     None => {}
                 };
@@ -4,7 +4,7 @@ use syn::Field;
 
 /// Generates an implementation for the `to_kafka_bytes` function in the `KafkaEncodable` trait
 /// for structs with named fields.
-pub(crate) fn generate_to_kafka_bytes_impl(struct_name: &Ident, fields: Vec<Field>) -> Result<proc_macro2::TokenStream> {
+pub(crate) fn generate_to_kafka_bytes_impl(_struct_name: &Ident, fields: Vec<Field>) -> Result<proc_macro2::TokenStream> {
     let field_names: Vec<Ident> = fields.into_iter()
         .map(|field| field.ident.expect("Field did not have an ident"))
         .collect();
@@ -22,7 +22,7 @@ pub(crate) fn generate_to_kafka_bytes_impl(struct_name: &Ident, fields: Vec<Fiel
 /// Generates an implementation for the `to_kafka_bytes` function in the `KafkaEncodable` trait
 /// for structs whose primary purpose is to hold tagged fields. Because no tagged fields are required
 /// by the protocol, each field in such a struct must be wrapped in an `Option`.
-pub(crate) fn generate_to_kafka_bytes_impl_for_tagged_fields(struct_name: &Ident, fields: Vec<Field>) -> Result<proc_macro2::TokenStream> {
+pub(crate) fn generate_to_kafka_bytes_impl_for_tagged_fields(_struct_name: &Ident, fields: Vec<Field>) -> Result<proc_macro2::TokenStream> {
     let field_names: Vec<Ident> = fields.into_iter()
         .map(|field| field.ident.expect("Field did not have an ident"))
         .collect();
@@ -42,7 +42,7 @@ pub(crate) fn generate_to_kafka_bytes_impl_for_tagged_fields(struct_name: &Ident
 
                     tag.to_kafka_bytes(writer)?;
                     size.to_kafka_bytes(writer)?;
-                    buffer.to_kafka_bytes(writer)?;
+                    writer.write_all(&buffer)?;
                 },
                 None => {}
             };
@@ -52,14 +52,14 @@ pub(crate) fn generate_to_kafka_bytes_impl_for_tagged_fields(struct_name: &Ident
     return Ok(quote::quote! {
         #[tracing::instrument]
         fn to_kafka_bytes<W: std::io::Write + std::fmt::Debug>(self, writer: &mut W) -> anyhow::Result<()> {
-            let mut num_tagged_fields: i32 = 0;
+            let mut num_tagged_fields: u32 = 0;
             #(
                 match self.#field_names {
                     Some(_) => num_tagged_fields += 1,
                     None => {}
                 };
             )*
-            num_tagged_fields.to_kafka_bytes(writer)?;
+            UnsignedVarInt32(num_tagged_fields).to_kafka_bytes(writer)?;
 
             #(
                 #lines_to_serialize_fields
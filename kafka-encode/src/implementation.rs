@@ -1,11 +1,11 @@
 use anyhow::{anyhow, Result};
 use std::fmt::Debug;
-use std::io::{Read, Write, ErrorKind, Error};
+use std::io::{Read, Write, Error};
 use integer_encoding::{VarIntReader, VarIntWriter};
 use tracing::{instrument, trace};
 use uuid::Uuid;
 use crate::KafkaEncodable;
-use crate::primitives::{NullableArray, CompactNullableArray, CompactBytes, CompactNullableBytes, CompactNullableString, CompactString, NullableBytes, NullableString, UnsignedVarInt32, VarI32, VarI64, Array, CompactArray, VarArray};
+use crate::primitives::{NullableArray, CompactNullableArray, CompactBytes, CompactNullableBytes, CompactNullableString, CompactString, NullableBytes, NullableString, NullableStruct, UnsignedVarInt32, VarI32, VarI64, Array, CompactArray, VarArray, VarIntArray, VarIntNullableBytes, VarIntString};
 
 // BOOLEAN
 impl KafkaEncodable for bool {
@@ -162,7 +162,7 @@ impl KafkaEncodable for VarI32 {
         bytes_vec.write_varint(*self)?;
 
         trace!("VarI32 bytes: {:?}", bytes_vec);
-        writer.write_all(&*bytes_vec);
+        writer.write_all(&*bytes_vec)?;
         Ok(())
     }
 
@@ -183,7 +183,7 @@ impl KafkaEncodable for VarI64 {
         bytes_vec.write_varint(*self)?;
 
         trace!("VarI64 bytes: {:?}", bytes_vec);
-        writer.write_all(&*bytes_vec);
+        writer.write_all(&*bytes_vec)?;
         Ok(())
     }
 
@@ -317,7 +317,7 @@ impl KafkaEncodable for UnsignedVarInt32 {
         bytes.write_varint(*self)?;
         trace!("UnsignedVarInt32 bytes: {:?}", bytes);
 
-        writer.write_all(&*bytes);
+        writer.write_all(&*bytes)?;
         Ok(())
     }
 
@@ -529,7 +529,7 @@ impl<T: KafkaEncodable + Debug> KafkaEncodable for Array<T> {
             element.to_kafka_bytes(&mut bytes)?;
             trace!("element bytes: {:?}", bytes);
 
-            writer.write_all(&*bytes);
+            writer.write_all(&*bytes)?;
             bytes.clear();
         }
         Ok(())
@@ -570,7 +570,7 @@ impl<T: KafkaEncodable + Debug> KafkaEncodable for NullableArray<T> {
             element.to_kafka_bytes(&mut bytes)?;
             trace!("element bytes: {:?}", bytes);
 
-            writer.write_all(&*bytes);
+            writer.write_all(&*bytes)?;
             bytes.clear();
         }
         Ok(())
@@ -612,7 +612,7 @@ impl<T: KafkaEncodable + Debug> KafkaEncodable for CompactArray<T> {
             element.to_kafka_bytes(&mut bytes)?;
             trace!("element bytes: {:?}", bytes);
 
-            writer.write_all(&*bytes);
+            writer.write_all(&*bytes)?;
             bytes.clear();
         }
         Ok(())
@@ -655,7 +655,7 @@ impl<T: KafkaEncodable + Debug> KafkaEncodable for CompactNullableArray<T> {
             element.to_kafka_bytes(&mut bytes)?;
             trace!("element bytes: {:?}", bytes);
 
-            writer.write_all(&*bytes);
+            writer.write_all(&*bytes)?;
             bytes.clear();
         }
         Ok(())
@@ -697,7 +697,7 @@ impl<T: KafkaEncodable + Debug> KafkaEncodable for VarArray<T> {
             element.to_kafka_bytes(&mut bytes)?;
             trace!("element bytes: {:?}", bytes);
 
-            writer.write_all(&*bytes);
+            writer.write_all(&*bytes)?;
             bytes.clear();
         }
         Ok(())
@@ -718,3 +718,116 @@ impl<T: KafkaEncodable + Debug> KafkaEncodable for VarArray<T> {
         Ok(VarArray::<T>::new(elements))
     }
 }
+
+// VARINT-prefixed nullable bytes, as used by the record format
+impl KafkaEncodable for VarIntNullableBytes {
+    #[instrument]
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        match self.0 {
+            None => VarI32(-1).to_kafka_bytes(writer),
+            Some(bytes) => {
+                trace!("VarIntNullableBytes bytes: {:?}", bytes);
+                VarI32(bytes.len() as i32).to_kafka_bytes(writer)?;
+                writer.write_all(&bytes)?;
+                Ok(())
+            }
+        }
+    }
+
+    #[instrument]
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<VarIntNullableBytes> {
+        let bytes_length: VarI32 = VarI32::from_kafka_bytes(reader)?;
+        if *bytes_length < 0 {
+            return Ok(VarIntNullableBytes(None));
+        }
+
+        let mut bytes: Vec<u8> = vec![0; *bytes_length as usize];
+        reader.read_exact(&mut bytes)?;
+
+        trace!("VarIntNullableBytes bytes: {:?}", bytes);
+        Ok(VarIntNullableBytes(Some(bytes)))
+    }
+}
+
+// VARINT-prefixed string, as used by record header keys
+impl KafkaEncodable for VarIntString {
+    #[instrument]
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        let bytes: &[u8] = self.as_bytes();
+        trace!("VarIntString bytes: {:?}", bytes);
+
+        VarI32(bytes.len() as i32).to_kafka_bytes(writer)?;
+        writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    #[instrument]
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<VarIntString> {
+        let bytes_length: VarI32 = VarI32::from_kafka_bytes(reader)?;
+        if *bytes_length < 0 {
+            return Err(anyhow!("Invalid VarIntString length: {}", *bytes_length));
+        }
+
+        let mut bytes: Vec<u8> = vec![0; *bytes_length as usize];
+        reader.read_exact(&mut bytes)?;
+        trace!("VarIntString bytes: {:?}", bytes);
+
+        Ok(VarIntString(String::from_utf8(bytes)?))
+    }
+}
+
+// VARINT-prefixed array, as used by record headers
+impl<T: KafkaEncodable + Debug> KafkaEncodable for VarIntArray<T> {
+    #[instrument]
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        let elements: Vec<T> = self.0;
+        let num_elements: i32 = elements.len() as i32;
+        trace!(num_elements);
+        VarI32(num_elements).to_kafka_bytes(writer)?;
+
+        for element in elements {
+            trace!("element: {:?}", element);
+            element.to_kafka_bytes(writer)?;
+        }
+        Ok(())
+    }
+
+    #[instrument]
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<VarIntArray<T>> {
+        let num_elements: VarI32 = VarI32::from_kafka_bytes(reader)?;
+        trace!(num_elements = *num_elements);
+
+        let mut elements: Vec<T> = Vec::new();
+        for _ in 0..*num_elements {
+            let element: T = T::from_kafka_bytes(reader)?;
+            trace!("element: {:?}", element);
+            elements.push(element);
+        }
+        Ok(VarIntArray::<T>::new(elements))
+    }
+}
+
+// nullable struct
+impl<T: KafkaEncodable + Debug> KafkaEncodable for NullableStruct<T> {
+    #[instrument]
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        match self.0 {
+            Some(value) => {
+                1i8.to_kafka_bytes(writer)?;
+                value.to_kafka_bytes(writer)
+            },
+            None => (-1i8).to_kafka_bytes(writer)
+        }
+    }
+
+    #[instrument]
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<NullableStruct<T>> {
+        let marker: i8 = i8::from_kafka_bytes(reader)?;
+        trace!(marker);
+        match marker {
+            -1 => Ok(NullableStruct(None)),
+            1 => Ok(NullableStruct(Some(T::from_kafka_bytes(reader)?))),
+            _ => Err(anyhow!("Invalid nullable struct marker: {}", marker))
+        }
+    }
+}
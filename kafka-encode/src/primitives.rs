@@ -163,3 +163,46 @@ impl<T: KafkaEncodable + Debug> Deref for VarArray<T> {
         &self.0
     }
 }
+
+/// The record format described in the "Record" section of Kafka's message format docs
+/// (https://kafka.apache.org/documentation/#record) prefixes keys, values and headers with a
+/// zigzag-encoded VARINT size instead of the UNSIGNED_VARINT used by the compact types.
+/// A size of -1 represents null.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VarIntNullableBytes(pub Option<Vec<u8>>);
+impl_deref_for_single_field_tuple_struct!(VarIntNullableBytes, Option<Vec<u8>>);
+
+/// A string prefixed with its size as a zigzag-encoded VARINT, as used by record header keys.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VarIntString(pub String);
+impl_deref_for_single_field_tuple_struct!(VarIntString, String);
+
+/// An array prefixed with its number of elements as a zigzag-encoded VARINT, as used by record headers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VarIntArray<T: KafkaEncodable + Debug>(pub Vec<T>);
+
+impl<T: KafkaEncodable + Debug> VarIntArray<T> {
+    pub(crate) fn new(v: Vec<T>) -> Self {
+        VarIntArray(v)
+    }
+}
+
+impl<T: KafkaEncodable + Debug> Deref for VarIntArray<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A struct field which may be null, written as an INT8 of -1 for null or 1 followed by the struct.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NullableStruct<T: KafkaEncodable + Debug>(pub Option<T>);
+
+impl<T: KafkaEncodable + Debug> Deref for NullableStruct<T> {
+    type Target = Option<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
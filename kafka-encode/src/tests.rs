@@ -3,7 +3,7 @@ use tracing::Level;
 use uuid::Uuid;
 use ctor::ctor;
 use crate::KafkaEncodable;
-use crate::primitives::{NullableArray, CompactNullableArray, CompactBytes, CompactNullableBytes, CompactNullableString, CompactString, NullableBytes, NullableString, UnsignedVarInt32, VarI32, VarI64, Array, CompactArray};
+use crate::primitives::{NullableArray, CompactNullableArray, CompactBytes, CompactNullableBytes, CompactNullableString, CompactString, NullableBytes, NullableString, NullableStruct, UnsignedVarInt32, VarI32, VarI64, Array, CompactArray, VarIntArray, VarIntNullableBytes, VarIntString};
 
 // TODO: figure out how to make the writer to stdout not deadlock with multiple tests
 // #[ctor]
@@ -369,3 +369,57 @@ fn test_deserialize_compact_nullable_array() {
     test_deserialize!(vec![3, 2, 84], CompactNullableArray::<VarI32>, CompactNullableArray::<VarI32>::new(Some(vec![VarI32(1), VarI32(42)])));
     test_deserialize!(vec![0], CompactNullableArray::<VarI32>, CompactNullableArray::<VarI32>::new(None));
 }
+
+// VARINT-prefixed NULLABLE_BYTES
+#[test]
+fn test_serialize_var_int_nullable_bytes() {
+    test_serialize!(VarIntNullableBytes(Some(vec![9, 8, 7, 6, 5])), vec![10, 9, 8, 7, 6, 5]);
+    test_serialize!(VarIntNullableBytes(Some(vec![])), vec![0]);
+    test_serialize!(VarIntNullableBytes(None), vec![1]);
+}
+
+#[test]
+fn test_deserialize_var_int_nullable_bytes() {
+    test_deserialize!(vec![10, 9, 8, 7, 6, 5], VarIntNullableBytes, VarIntNullableBytes(Some(vec![9, 8, 7, 6, 5])));
+    test_deserialize!(vec![0], VarIntNullableBytes, VarIntNullableBytes(Some(vec![])));
+    test_deserialize!(vec![1], VarIntNullableBytes, VarIntNullableBytes(None));
+}
+
+// VARINT-prefixed STRING
+#[test]
+fn test_serialize_var_int_string() {
+    test_serialize!(VarIntString(String::from("foo")), vec![6, 102, 111, 111]);
+}
+
+#[test]
+fn test_deserialize_var_int_string() {
+    test_deserialize!(vec![6, 102, 111, 111], VarIntString, VarIntString(String::from("foo")));
+    assert!(VarIntString::from_kafka_bytes(&mut &*vec![1u8]).is_err());
+}
+
+// VARINT-prefixed ARRAY
+#[test]
+fn test_serialize_var_int_array() {
+    test_serialize!(VarIntArray::<VarI32>::new(vec![VarI32(1), VarI32(42)]), vec![4, 2, 84]);
+    test_serialize!(VarIntArray::<VarI32>::new(vec![]), vec![0]);
+}
+
+#[test]
+fn test_deserialize_var_int_array() {
+    test_deserialize!(vec![4, 2, 84], VarIntArray::<VarI32>, VarIntArray::<VarI32>::new(vec![VarI32(1), VarI32(42)]));
+    test_deserialize!(vec![0], VarIntArray::<VarI32>, VarIntArray::<VarI32>::new(vec![]));
+}
+
+// nullable struct
+#[test]
+fn test_serialize_nullable_struct() {
+    test_serialize!(NullableStruct(Some(42i32)), vec![1, 0, 0, 0, 42]);
+    test_serialize!(NullableStruct::<i32>(None), vec![255]);
+}
+
+#[test]
+fn test_deserialize_nullable_struct() {
+    test_deserialize!(vec![1, 0, 0, 0, 42], NullableStruct::<i32>, NullableStruct(Some(42i32)));
+    test_deserialize!(vec![255], NullableStruct::<i32>, NullableStruct::<i32>(None));
+    assert!(NullableStruct::<i32>::from_kafka_bytes(&mut &*vec![0u8]).is_err());
+}
@@ -0,0 +1,243 @@
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use anyhow::{anyhow, Result};
+use tracing::{debug, warn};
+
+/// `client.dns.lookup`: how the hostnames of bootstrap servers and brokers are turned into addresses.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ClientDnsLookup {
+    /// Every IP address a hostname resolves to is tried in turn until a connection succeeds.
+    #[default]
+    UseAllDnsIps,
+    /// Each bootstrap hostname is resolved, each resulting address is reverse-resolved to its
+    /// canonical hostname, and those canonical hostnames are then resolved with `UseAllDnsIps`.
+    /// This suits bootstrap servers behind a DNS alias, such as when authenticating with Kerberos.
+    ResolveCanonicalBootstrapServersOnly
+}
+
+/// One `host:port` entry of a `bootstrap.servers` list.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct BootstrapServer {
+    pub host: String,
+    pub port: u16
+}
+
+impl BootstrapServer {
+    /// Parses `host:port`, where an IPv6 host is written in brackets, as in `[::1]:9092`.
+    pub fn parse(server: &str) -> Result<Self> {
+        let (host, port) = server.rsplit_once(':')
+            .ok_or_else(|| anyhow!("Bootstrap server {} has no port", server))?;
+        let host: &str = match host.strip_prefix('[') {
+            Some(bracketed_host) => bracketed_host.strip_suffix(']')
+                .ok_or_else(|| anyhow!("Bootstrap server {} has an unterminated IPv6 address", server))?,
+            None => host
+        };
+        if host.is_empty() {
+            return Err(anyhow!("Bootstrap server {} has no host", server));
+        }
+        let port: u16 = port.parse()
+            .map_err(|_| anyhow!("Bootstrap server {} has an invalid port", server))?;
+        Ok(BootstrapServer {
+            host: String::from(host),
+            port
+        })
+    }
+
+    fn resolve(&self) -> Result<Vec<ResolvedAddress>> {
+        Ok((self.host.as_str(), self.port).to_socket_addrs()?
+            .map(|address| ResolvedAddress { host: self.host.clone(), address })
+            .collect())
+    }
+}
+
+/// An address to connect to, along with the hostname it was resolved from. TLS verifies the
+/// server's certificate against the hostname.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResolvedAddress {
+    pub host: String,
+    pub address: SocketAddr
+}
+
+/// Parses a comma-separated `bootstrap.servers` list, ignoring whitespace and empty entries.
+pub fn parse_bootstrap_servers(bootstrap_servers: &str) -> Result<Vec<BootstrapServer>> {
+    let servers: Vec<BootstrapServer> = bootstrap_servers.split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+        .map(BootstrapServer::parse)
+        .collect::<Result<Vec<BootstrapServer>>>()?;
+    if servers.is_empty() {
+        return Err(anyhow!("No bootstrap servers were given"));
+    }
+    Ok(servers)
+}
+
+/// Resolves every bootstrap server to the addresses which should be tried, in order. Servers which
+/// can't be resolved are skipped, so that one bad DNS entry doesn't prevent bootstrapping.
+pub fn resolve_bootstrap_servers(servers: &[BootstrapServer], client_dns_lookup: ClientDnsLookup) -> Result<Vec<ResolvedAddress>> {
+    let mut addresses: Vec<ResolvedAddress> = Vec::new();
+    for server in servers {
+        let resolved: Result<Vec<ResolvedAddress>> = match client_dns_lookup {
+            ClientDnsLookup::UseAllDnsIps => server.resolve(),
+            ClientDnsLookup::ResolveCanonicalBootstrapServersOnly => resolve_canonical(server)
+        };
+        match resolved {
+            Ok(resolved) => {
+                for address in resolved {
+                    if !addresses.contains(&address) {
+                        addresses.push(address);
+                    }
+                }
+            },
+            Err(e) => warn!("Couldn't resolve bootstrap server {}:{}: {:?}", server.host, server.port, e)
+        }
+    }
+    if addresses.is_empty() {
+        return Err(anyhow!("None of the bootstrap servers could be resolved: {:?}", servers));
+    }
+    Ok(addresses)
+}
+
+fn resolve_canonical(server: &BootstrapServer) -> Result<Vec<ResolvedAddress>> {
+    let mut addresses: Vec<ResolvedAddress> = Vec::new();
+    for resolved in server.resolve()? {
+        let canonical_host: String = canonical_host_name(resolved.address.ip());
+        debug!("Bootstrap server {} resolved to canonical hostname {}", server.host, canonical_host);
+        let canonical_server: BootstrapServer = BootstrapServer {
+            host: canonical_host,
+            port: server.port
+        };
+        addresses.extend(canonical_server.resolve()?);
+    }
+    Ok(addresses)
+}
+
+/// Like Java's `InetAddress.getCanonicalHostName`, falls back to the textual address if the
+/// reverse lookup fails.
+fn canonical_host_name(ip: IpAddr) -> String {
+    dns_lookup::lookup_addr(&ip).unwrap_or_else(|_| ip.to_string())
+}
+
+/// The addresses of a set of servers, and which one to try next. After a failed attempt the next
+/// address is tried, and after a successful one the same address is used again.
+#[derive(Debug, Clone)]
+pub struct ResolvedAddresses {
+    addresses: Vec<ResolvedAddress>,
+    next: usize
+}
+
+impl ResolvedAddresses {
+    pub fn new(addresses: Vec<ResolvedAddress>) -> Self {
+        ResolvedAddresses {
+            addresses,
+            next: 0
+        }
+    }
+
+    pub fn resolve_bootstrap_servers(bootstrap_servers: &str, client_dns_lookup: ClientDnsLookup) -> Result<Self> {
+        let servers: Vec<BootstrapServer> = parse_bootstrap_servers(bootstrap_servers)?;
+        Ok(ResolvedAddresses::new(resolve_bootstrap_servers(&servers, client_dns_lookup)?))
+    }
+
+    /// Resolves a broker's advertised host to all of its addresses.
+    pub fn resolve_host(host: &str, port: u16) -> Result<Self> {
+        let server: BootstrapServer = BootstrapServer {
+            host: String::from(host),
+            port
+        };
+        let addresses: Vec<ResolvedAddress> = server.resolve()?;
+        if addresses.is_empty() {
+            return Err(anyhow!("Could not resolve {}:{}", host, port));
+        }
+        Ok(ResolvedAddresses::new(addresses))
+    }
+
+    pub fn addresses(&self) -> &[ResolvedAddress] {
+        &self.addresses
+    }
+
+    /// Calls `connect` with each address in turn, starting from the current one, until it succeeds.
+    /// Fails with the last error once every address has been tried.
+    pub fn try_each<T, F>(&mut self, mut connect: F) -> Result<T> where F: FnMut(&ResolvedAddress) -> Result<T> {
+        let mut last_error: Option<anyhow::Error> = None;
+        for _ in 0..self.addresses.len() {
+            let address: &ResolvedAddress = &self.addresses[self.next];
+            match connect(address) {
+                Ok(connected) => return Ok(connected),
+                Err(e) => {
+                    debug!("Failed to use address {} ({}): {:?}", address.address, address.host, e);
+                    last_error = Some(e);
+                    self.next = (self.next + 1) % self.addresses.len();
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("There are no addresses to connect to")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use anyhow::anyhow;
+    use crate::bootstrap::{ResolvedAddress, ResolvedAddresses, BootstrapServer, ClientDnsLookup, parse_bootstrap_servers, resolve_bootstrap_servers};
+
+    #[test]
+    fn test_parse_bootstrap_servers() {
+        let servers: Vec<BootstrapServer> = parse_bootstrap_servers(" broker-1:9092, 10.0.0.2:9093,,[::1]:9094 ").unwrap();
+        assert_eq!(servers, vec![
+            BootstrapServer { host: String::from("broker-1"), port: 9092 },
+            BootstrapServer { host: String::from("10.0.0.2"), port: 9093 },
+            BootstrapServer { host: String::from("::1"), port: 9094 }
+        ]);
+        assert!(parse_bootstrap_servers("broker-1").is_err());
+        assert!(parse_bootstrap_servers("broker-1:port").is_err());
+        assert!(parse_bootstrap_servers(" , ").is_err());
+    }
+
+    #[test]
+    fn test_resolve_skips_unresolvable_servers() {
+        let servers: Vec<BootstrapServer> = parse_bootstrap_servers("does-not-exist.invalid:9092,127.0.0.1:9092,127.0.0.1:9092").unwrap();
+        let addresses: Vec<ResolvedAddress> = resolve_bootstrap_servers(&servers, ClientDnsLookup::UseAllDnsIps).unwrap();
+        assert_eq!(addresses, vec![
+            ResolvedAddress { host: String::from("127.0.0.1"), address: "127.0.0.1:9092".parse::<SocketAddr>().unwrap() }
+        ]);
+
+        let servers: Vec<BootstrapServer> = parse_bootstrap_servers("does-not-exist.invalid:9092").unwrap();
+        assert!(resolve_bootstrap_servers(&servers, ClientDnsLookup::UseAllDnsIps).is_err());
+    }
+
+    #[test]
+    fn test_resolve_canonical_bootstrap_servers() {
+        let servers: Vec<BootstrapServer> = parse_bootstrap_servers("127.0.0.1:9092").unwrap();
+        let addresses: Vec<ResolvedAddress> = resolve_bootstrap_servers(&servers, ClientDnsLookup::ResolveCanonicalBootstrapServersOnly).unwrap();
+        assert!(addresses.iter().all(|resolved| resolved.address.ip().is_loopback() && resolved.address.port() == 9092));
+    }
+
+    #[test]
+    fn test_try_each_rotates_through_addresses() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let live_address: SocketAddr = listener.local_addr().unwrap();
+        let dead_address: SocketAddr = {
+            let dead_listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+            dead_listener.local_addr().unwrap()
+        };
+
+        let mut addresses: ResolvedAddresses = ResolvedAddresses::new(vec![
+            ResolvedAddress { host: String::from("dead"), address: dead_address },
+            ResolvedAddress { host: String::from("live"), address: live_address }
+        ]);
+        let mut attempted: Vec<SocketAddr> = Vec::new();
+        let stream: TcpStream = addresses.try_each(|resolved| {
+            attempted.push(resolved.address);
+            Ok(TcpStream::connect(resolved.address)?)
+        }).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live_address);
+        assert_eq!(attempted, vec![dead_address, live_address]);
+
+        // the address which worked is tried first next time
+        let mut attempted: Vec<SocketAddr> = Vec::new();
+        addresses.try_each(|resolved| {
+            attempted.push(resolved.address);
+            Err::<(), anyhow::Error>(anyhow!("refused"))
+        }).unwrap_err();
+        assert_eq!(attempted, vec![live_address, dead_address]);
+    }
+}
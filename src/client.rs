@@ -1,5 +0,0 @@
-struct KafkaConsumer<'a, K, V> {
-    group_id: &'a str,
-    client_id: &'a str,
-
-}
\ No newline at end of file
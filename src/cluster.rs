@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use tracing::debug;
+use uuid::Uuid;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::metadata::{AUTHORIZED_OPERATIONS_OMITTED, MetadataResponseBrokerV1V8, MetadataResponseBrokerV9V12, MetadataResponsePartitionV0V4, MetadataResponsePartitionV5V6, MetadataResponsePartitionV7V8, MetadataResponsePartitionV9V12, MetadataResponseV0, MetadataResponseV1, MetadataResponseV10, MetadataResponseV11V12, MetadataResponseV2, MetadataResponseV3V4, MetadataResponseV5V6, MetadataResponseV7, MetadataResponseV8, MetadataResponseV9};
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct TopicPartition {
+    pub topic: String,
+    pub partition: i32
+}
+
+impl TopicPartition {
+    pub fn new(topic: &str, partition: i32) -> Self {
+        TopicPartition {
+            topic: String::from(topic),
+            partition
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Node {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: Option<String>
+}
+
+impl Node {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// The operations which may appear in an authorized-operations bitfield. The value of each variant
+/// is the index of the bit which represents it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AclOperation {
+    Unknown = 0,
+    Any = 1,
+    All = 2,
+    Read = 3,
+    Write = 4,
+    Create = 5,
+    Delete = 6,
+    Alter = 7,
+    Describe = 8,
+    ClusterAction = 9,
+    DescribeConfigs = 10,
+    AlterConfigs = 11,
+    IdempotentWrite = 12,
+    CreateTokens = 13,
+    DescribeTokens = 14
+}
+
+impl AclOperation {
+    const ALL_OPERATIONS: [AclOperation; 15] = [
+        AclOperation::Unknown, AclOperation::Any, AclOperation::All, AclOperation::Read,
+        AclOperation::Write, AclOperation::Create, AclOperation::Delete, AclOperation::Alter,
+        AclOperation::Describe, AclOperation::ClusterAction, AclOperation::DescribeConfigs,
+        AclOperation::AlterConfigs, AclOperation::IdempotentWrite, AclOperation::CreateTokens,
+        AclOperation::DescribeTokens
+    ];
+
+    /// Decodes an authorized-operations bitfield from a Metadata response. Returns `None` if the
+    /// broker omitted the bitfield because it was not requested.
+    pub fn from_bitfield(bitfield: i32) -> Option<Vec<AclOperation>> {
+        if bitfield == AUTHORIZED_OPERATIONS_OMITTED {
+            return None;
+        }
+        Some(
+            AclOperation::ALL_OPERATIONS.iter()
+                .filter(|operation| bitfield & (1 << **operation as i32) != 0)
+                .copied()
+                .collect()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PartitionMetadata {
+    pub error_code: ErrorCode,
+    pub partition: i32,
+    /// -1 if the partition currently has no leader.
+    pub leader_id: i32,
+    /// Only present in responses of version 7 and above.
+    pub leader_epoch: Option<i32>,
+    pub replica_nodes: Vec<i32>,
+    pub isr_nodes: Vec<i32>,
+    pub offline_replicas: Vec<i32>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TopicMetadata {
+    pub error_code: ErrorCode,
+    pub name: String,
+    /// Only present in responses of version 10 and above.
+    pub topic_id: Option<Uuid>,
+    pub is_internal: bool,
+    pub partitions: Vec<PartitionMetadata>,
+    pub authorized_operations: Option<Vec<AclOperation>>
+}
+
+/// A typed view of a Metadata response which can answer which node leads and replicates each
+/// topic-partition.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct ClusterMetadata {
+    pub cluster_id: Option<String>,
+    pub controller_id: Option<i32>,
+    pub brokers: HashMap<i32, Node>,
+    pub topics: HashMap<String, TopicMetadata>,
+    pub authorized_operations: Option<Vec<AclOperation>>
+}
+
+impl ClusterMetadata {
+    fn new(cluster_id: Option<String>, controller_id: Option<i32>, brokers: Vec<Node>,
+           topics: Vec<TopicMetadata>, cluster_authorized_operations: i32) -> Self {
+        let mut topics_by_name: HashMap<String, TopicMetadata> = HashMap::with_capacity(topics.len());
+        for topic in topics {
+            topics_by_name.insert(topic.name.clone(), topic);
+        }
+        ClusterMetadata {
+            cluster_id,
+            controller_id: controller_id.filter(|id| *id >= 0),
+            brokers: brokers.into_iter().map(|node| (node.node_id, node)).collect(),
+            topics: topics_by_name,
+            authorized_operations: AclOperation::from_bitfield(cluster_authorized_operations)
+        }
+    }
+
+    pub fn node(&self, node_id: i32) -> Option<&Node> {
+        self.brokers.get(&node_id)
+    }
+
+    pub fn controller(&self) -> Option<&Node> {
+        self.controller_id.and_then(|id| self.node(id))
+    }
+
+    pub fn topic(&self, topic: &str) -> Option<&TopicMetadata> {
+        self.topics.get(topic)
+    }
+
+    pub fn topic_by_id(&self, topic_id: &Uuid) -> Option<&TopicMetadata> {
+        self.topics.values().find(|topic| topic.topic_id.as_ref() == Some(topic_id))
+    }
+
+    pub fn partition(&self, topic_partition: &TopicPartition) -> Option<&PartitionMetadata> {
+        self.topic(&topic_partition.topic)?
+            .partitions.iter()
+            .find(|partition| partition.partition == topic_partition.partition)
+    }
+
+    /// All partitions known for a topic, in partition order.
+    pub fn partitions_for_topic(&self, topic: &str) -> Vec<TopicPartition> {
+        let mut partitions: Vec<TopicPartition> = match self.topic(topic) {
+            Some(topic_metadata) => topic_metadata.partitions.iter()
+                .map(|partition| TopicPartition::new(topic, partition.partition))
+                .collect(),
+            None => Vec::new()
+        };
+        partitions.sort();
+        partitions
+    }
+
+    /// The partitions of a topic which currently have a leader, in partition order.
+    pub fn available_partitions_for_topic(&self, topic: &str) -> Vec<TopicPartition> {
+        self.partitions_for_topic(topic).into_iter()
+            .filter(|topic_partition| self.leader(topic_partition).is_some())
+            .collect()
+    }
+
+    /// The node leading a partition, or `None` if the partition is unknown or leaderless.
+    pub fn leader(&self, topic_partition: &TopicPartition) -> Option<&Node> {
+        let partition: &PartitionMetadata = self.partition(topic_partition)?;
+        self.node(partition.leader_id)
+    }
+
+    /// The nodes replicating a partition which are present in the broker list.
+    pub fn replicas(&self, topic_partition: &TopicPartition) -> Vec<&Node> {
+        match self.partition(topic_partition) {
+            Some(partition) => partition.replica_nodes.iter()
+                .filter_map(|node_id| self.node(*node_id))
+                .collect(),
+            None => Vec::new()
+        }
+    }
+}
+
+fn node_from_broker_v1_v8(broker: MetadataResponseBrokerV1V8) -> Node {
+    Node {
+        node_id: broker.node_id,
+        host: broker.host,
+        port: broker.port,
+        rack: broker.rack.0
+    }
+}
+
+fn node_from_broker_v9_v12(broker: MetadataResponseBrokerV9V12) -> Node {
+    Node {
+        node_id: broker.node_id,
+        host: broker.host.0,
+        port: broker.port,
+        rack: broker.rack.0
+    }
+}
+
+fn partition_from_v0_v4(partition: MetadataResponsePartitionV0V4) -> PartitionMetadata {
+    PartitionMetadata {
+        error_code: partition.error_code,
+        partition: partition.partition_index,
+        leader_id: partition.leader_id,
+        leader_epoch: None,
+        replica_nodes: partition.replica_nodes.0,
+        isr_nodes: partition.isr_nodes.0,
+        offline_replicas: Vec::new()
+    }
+}
+
+fn partition_from_v5_v6(partition: MetadataResponsePartitionV5V6) -> PartitionMetadata {
+    PartitionMetadata {
+        error_code: partition.error_code,
+        partition: partition.partition_index,
+        leader_id: partition.leader_id,
+        leader_epoch: None,
+        replica_nodes: partition.replica_nodes.0,
+        isr_nodes: partition.isr_nodes.0,
+        offline_replicas: partition.offline_replicas.0
+    }
+}
+
+fn partition_from_v7_v8(partition: MetadataResponsePartitionV7V8) -> PartitionMetadata {
+    PartitionMetadata {
+        error_code: partition.error_code,
+        partition: partition.partition_index,
+        leader_id: partition.leader_id,
+        leader_epoch: Some(partition.leader_epoch),
+        replica_nodes: partition.replica_nodes.0,
+        isr_nodes: partition.isr_nodes.0,
+        offline_replicas: partition.offline_replicas.0
+    }
+}
+
+fn partition_from_v9_v12(partition: MetadataResponsePartitionV9V12) -> PartitionMetadata {
+    PartitionMetadata {
+        error_code: partition.error_code,
+        partition: partition.partition_index,
+        leader_id: partition.leader_id,
+        leader_epoch: Some(partition.leader_epoch),
+        replica_nodes: partition.replica_nodes.0,
+        isr_nodes: partition.isr_nodes.0,
+        offline_replicas: partition.offline_replicas.0
+    }
+}
+
+impl From<MetadataResponseV0> for ClusterMetadata {
+    fn from(response: MetadataResponseV0) -> Self {
+        let brokers: Vec<Node> = response.brokers.0.into_iter()
+            .map(|broker| Node { node_id: broker.node_id, host: broker.host, port: broker.port, rack: None })
+            .collect();
+        let topics: Vec<TopicMetadata> = response.topics.0.into_iter()
+            .map(|topic| TopicMetadata {
+                error_code: topic.error_code,
+                name: topic.name,
+                topic_id: None,
+                is_internal: false,
+                partitions: topic.partitions.0.into_iter().map(partition_from_v0_v4).collect(),
+                authorized_operations: None
+            })
+            .collect();
+        ClusterMetadata::new(None, None, brokers, topics, AUTHORIZED_OPERATIONS_OMITTED)
+    }
+}
+
+macro_rules! impl_from_metadata_response_v1_v4 {
+    ($response_type:ty, $response:ident, $cluster_id:expr) => {
+        impl From<$response_type> for ClusterMetadata {
+            fn from($response: $response_type) -> Self {
+                let cluster_id: Option<String> = $cluster_id;
+                let brokers: Vec<Node> = $response.brokers.0.into_iter().map(node_from_broker_v1_v8).collect();
+                let topics: Vec<TopicMetadata> = $response.topics.0.into_iter()
+                    .map(|topic| TopicMetadata {
+                        error_code: topic.error_code,
+                        name: topic.name,
+                        topic_id: None,
+                        is_internal: topic.is_internal,
+                        partitions: topic.partitions.0.into_iter().map(partition_from_v0_v4).collect(),
+                        authorized_operations: None
+                    })
+                    .collect();
+                ClusterMetadata::new(cluster_id, Some($response.controller_id), brokers, topics, AUTHORIZED_OPERATIONS_OMITTED)
+            }
+        }
+    }
+}
+
+impl_from_metadata_response_v1_v4!(MetadataResponseV1, response, None);
+impl_from_metadata_response_v1_v4!(MetadataResponseV2, response, response.cluster_id.0.clone());
+impl_from_metadata_response_v1_v4!(MetadataResponseV3V4, response, response.cluster_id.0.clone());
+
+impl From<MetadataResponseV5V6> for ClusterMetadata {
+    fn from(response: MetadataResponseV5V6) -> Self {
+        let brokers: Vec<Node> = response.brokers.0.into_iter().map(node_from_broker_v1_v8).collect();
+        let topics: Vec<TopicMetadata> = response.topics.0.into_iter()
+            .map(|topic| TopicMetadata {
+                error_code: topic.error_code,
+                name: topic.name,
+                topic_id: None,
+                is_internal: topic.is_internal,
+                partitions: topic.partitions.0.into_iter().map(partition_from_v5_v6).collect(),
+                authorized_operations: None
+            })
+            .collect();
+        ClusterMetadata::new(response.cluster_id.0, Some(response.controller_id), brokers, topics, AUTHORIZED_OPERATIONS_OMITTED)
+    }
+}
+
+impl From<MetadataResponseV7> for ClusterMetadata {
+    fn from(response: MetadataResponseV7) -> Self {
+        let brokers: Vec<Node> = response.brokers.0.into_iter().map(node_from_broker_v1_v8).collect();
+        let topics: Vec<TopicMetadata> = response.topics.0.into_iter()
+            .map(|topic| TopicMetadata {
+                error_code: topic.error_code,
+                name: topic.name,
+                topic_id: None,
+                is_internal: topic.is_internal,
+                partitions: topic.partitions.0.into_iter().map(partition_from_v7_v8).collect(),
+                authorized_operations: None
+            })
+            .collect();
+        ClusterMetadata::new(response.cluster_id.0, Some(response.controller_id), brokers, topics, AUTHORIZED_OPERATIONS_OMITTED)
+    }
+}
+
+impl From<MetadataResponseV8> for ClusterMetadata {
+    fn from(response: MetadataResponseV8) -> Self {
+        let brokers: Vec<Node> = response.brokers.0.into_iter().map(node_from_broker_v1_v8).collect();
+        let topics: Vec<TopicMetadata> = response.topics.0.into_iter()
+            .map(|topic| TopicMetadata {
+                error_code: topic.error_code,
+                name: topic.name,
+                topic_id: None,
+                is_internal: topic.is_internal,
+                partitions: topic.partitions.0.into_iter().map(partition_from_v7_v8).collect(),
+                authorized_operations: AclOperation::from_bitfield(topic.topic_authorized_operations)
+            })
+            .collect();
+        ClusterMetadata::new(response.cluster_id.0, Some(response.controller_id), brokers, topics, response.cluster_authorized_operations)
+    }
+}
+
+impl From<MetadataResponseV9> for ClusterMetadata {
+    fn from(response: MetadataResponseV9) -> Self {
+        let brokers: Vec<Node> = response.brokers.0.into_iter().map(node_from_broker_v9_v12).collect();
+        let topics: Vec<TopicMetadata> = response.topics.0.into_iter()
+            .map(|topic| TopicMetadata {
+                error_code: topic.error_code,
+                name: topic.name.0,
+                topic_id: None,
+                is_internal: topic.is_internal,
+                partitions: topic.partitions.0.into_iter().map(partition_from_v9_v12).collect(),
+                authorized_operations: AclOperation::from_bitfield(topic.topic_authorized_operations)
+            })
+            .collect();
+        ClusterMetadata::new(response.cluster_id.0, Some(response.controller_id), brokers, topics, response.cluster_authorized_operations)
+    }
+}
+
+macro_rules! impl_from_metadata_response_v10_v12 {
+    ($response_type:ty, $response:ident, $cluster_authorized_operations:expr) => {
+        impl From<$response_type> for ClusterMetadata {
+            fn from($response: $response_type) -> Self {
+                let cluster_authorized_operations: i32 = $cluster_authorized_operations;
+                let brokers: Vec<Node> = $response.brokers.0.into_iter().map(node_from_broker_v9_v12).collect();
+                let mut topics: Vec<TopicMetadata> = Vec::with_capacity($response.topics.len());
+                for topic in $response.topics.0 {
+                    // topics requested by id may come back without a name; they can't be addressed
+                    // by a TopicPartition, so there is nothing useful to store for them
+                    let name: String = match topic.name.0 {
+                        Some(name) => name,
+                        None => {
+                            debug!("Skipping topic without a name in metadata response: {:?}", topic.topic_id);
+                            continue;
+                        }
+                    };
+                    topics.push(TopicMetadata {
+                        error_code: topic.error_code,
+                        name,
+                        topic_id: Some(topic.topic_id).filter(|id| !id.is_nil()),
+                        is_internal: topic.is_internal,
+                        partitions: topic.partitions.0.into_iter().map(partition_from_v9_v12).collect(),
+                        authorized_operations: AclOperation::from_bitfield(topic.topic_authorized_operations)
+                    });
+                }
+                ClusterMetadata::new($response.cluster_id.0, Some($response.controller_id), brokers, topics, cluster_authorized_operations)
+            }
+        }
+    }
+}
+
+impl_from_metadata_response_v10_v12!(MetadataResponseV10, response, response.cluster_authorized_operations);
+impl_from_metadata_response_v10_v12!(MetadataResponseV11V12, response, AUTHORIZED_OPERATIONS_OMITTED);
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableString, CompactString, NullableString};
+    use uuid::Uuid;
+    use crate::cluster::{AclOperation, ClusterMetadata, Node, PartitionMetadata, TopicMetadata, TopicPartition};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::metadata::{MetadataResponseBrokerV1V8, MetadataResponseBrokerV9V12, MetadataResponsePartitionV5V6, MetadataResponsePartitionV9V12, MetadataResponseTopicV10V12, MetadataResponseTopicV5V6, MetadataResponseV11V12, MetadataResponseV5V6};
+    use crate::protocol::mock_broker::MockBroker;
+    use crate::protocol::tags::TaggedFields;
+
+    /// A cluster with one topic whose partitions are led by the given nodes, where -1 means no
+    /// leader. Each leader is the only replica of its partitions.
+    pub(crate) fn one_topic_cluster(topic: &str, leaders: &[i32]) -> ClusterMetadata {
+        let partitions: Vec<PartitionMetadata> = leaders.iter().enumerate()
+            .map(|(partition, leader_id)| PartitionMetadata {
+                error_code: ErrorCode::None,
+                partition: partition as i32,
+                leader_id: *leader_id,
+                leader_epoch: Some(0),
+                replica_nodes: vec![*leader_id],
+                isr_nodes: vec![*leader_id],
+                offline_replicas: vec![]
+            })
+            .collect();
+        let brokers: HashMap<i32, Node> = leaders.iter()
+            .filter(|leader_id| **leader_id >= 0)
+            .map(|node_id| (*node_id, Node { node_id: *node_id, host: format!("broker-{}", node_id), port: 9092, rack: None }))
+            .collect();
+        let mut topics: HashMap<String, TopicMetadata> = HashMap::new();
+        topics.insert(String::from(topic), TopicMetadata {
+            error_code: ErrorCode::None,
+            name: String::from(topic),
+            topic_id: None,
+            is_internal: false,
+            partitions,
+            authorized_operations: None
+        });
+        ClusterMetadata { brokers, topics, ..ClusterMetadata::default() }
+    }
+
+    /// `one_topic_cluster`, but with brokers listening on 127.0.0.1 at the given ports.
+    pub(crate) fn local_cluster(topic: &str, ports: &[(i32, i32)], leaders: &[i32]) -> ClusterMetadata {
+        let mut cluster: ClusterMetadata = one_topic_cluster(topic, leaders);
+        cluster.brokers = ports.iter()
+            .map(|(node_id, port)| (*node_id, Node { node_id: *node_id, host: String::from("127.0.0.1"), port: *port, rack: None }))
+            .collect();
+        cluster
+    }
+
+    /// A cluster of mock brokers with one topic, whose partitions are led by the given nodes.
+    pub(crate) fn mock_broker_cluster(topic: &str, brokers: &[(i32, &MockBroker)], leaders: &[i32]) -> ClusterMetadata {
+        let ports: Vec<(i32, i32)> = brokers.iter().map(|(node_id, broker)| (*node_id, broker.port())).collect();
+        local_cluster(topic, &ports, leaders)
+    }
+
+    fn broker_v9_v12(node_id: i32) -> MetadataResponseBrokerV9V12 {
+        MetadataResponseBrokerV9V12 {
+            node_id,
+            host: CompactString(format!("broker-{}", node_id)),
+            port: 9092,
+            rack: CompactNullableString(None),
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    fn partition_v9_v12(partition_index: i32, leader_id: i32, replica_nodes: Vec<i32>) -> MetadataResponsePartitionV9V12 {
+        MetadataResponsePartitionV9V12 {
+            error_code: ErrorCode::None,
+            partition_index,
+            leader_id,
+            leader_epoch: 7,
+            replica_nodes: CompactArray(replica_nodes.clone()),
+            isr_nodes: CompactArray(replica_nodes),
+            offline_replicas: CompactArray(vec![]),
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    #[test]
+    fn test_cluster_metadata_from_response_v12() {
+        let topic_id: Uuid = Uuid::from_u128(42);
+        let response: MetadataResponseV11V12 = MetadataResponseV11V12 {
+            throttle_time_ms: 0,
+            brokers: CompactArray(vec![broker_v9_v12(1), broker_v9_v12(2), broker_v9_v12(3)]),
+            cluster_id: CompactNullableString(Some(String::from("cluster"))),
+            controller_id: 2,
+            topics: CompactArray(vec![
+                MetadataResponseTopicV10V12 {
+                    error_code: ErrorCode::None,
+                    name: CompactNullableString(Some(String::from("foo"))),
+                    topic_id,
+                    is_internal: false,
+                    partitions: CompactArray(vec![
+                        partition_v9_v12(1, 3, vec![3, 1]),
+                        partition_v9_v12(0, 1, vec![1, 2])
+                    ]),
+                    topic_authorized_operations: (1 << AclOperation::Read as i32) | (1 << AclOperation::Describe as i32),
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            tag_buffer: TaggedFields::new()
+        };
+
+        let cluster: ClusterMetadata = ClusterMetadata::from(response);
+        assert_eq!(cluster.cluster_id, Some(String::from("cluster")));
+        assert_eq!(cluster.controller().map(|node| node.node_id), Some(2));
+        assert_eq!(
+            cluster.partitions_for_topic("foo"),
+            vec![TopicPartition::new("foo", 0), TopicPartition::new("foo", 1)]
+        );
+        assert_eq!(
+            cluster.leader(&TopicPartition::new("foo", 1)),
+            Some(&Node { node_id: 3, host: String::from("broker-3"), port: 9092, rack: None })
+        );
+        assert_eq!(
+            cluster.replicas(&TopicPartition::new("foo", 0)).iter().map(|node| node.node_id).collect::<Vec<i32>>(),
+            vec![1, 2]
+        );
+        assert_eq!(cluster.partition(&TopicPartition::new("foo", 0)).unwrap().leader_epoch, Some(7));
+        assert_eq!(cluster.topic_by_id(&topic_id).map(|topic| topic.name.as_str()), Some("foo"));
+        assert_eq!(
+            cluster.topic("foo").unwrap().authorized_operations,
+            Some(vec![AclOperation::Read, AclOperation::Describe])
+        );
+        assert_eq!(cluster.authorized_operations, None);
+        assert_eq!(cluster.leader(&TopicPartition::new("foo", 2)), None);
+        assert_eq!(cluster.leader(&TopicPartition::new("bar", 0)), None);
+    }
+
+    #[test]
+    fn test_cluster_metadata_from_response_v5_without_leader() {
+        let response: MetadataResponseV5V6 = MetadataResponseV5V6 {
+            throttle_time_ms: 0,
+            brokers: Array(vec![
+                MetadataResponseBrokerV1V8 {
+                    node_id: 1,
+                    host: String::from("localhost"),
+                    port: 9092,
+                    rack: NullableString(Some(String::from("rack-a")))
+                }
+            ]),
+            cluster_id: NullableString(None),
+            controller_id: -1,
+            topics: Array(vec![
+                MetadataResponseTopicV5V6 {
+                    error_code: ErrorCode::None,
+                    name: String::from("foo"),
+                    is_internal: false,
+                    partitions: Array(vec![
+                        MetadataResponsePartitionV5V6 {
+                            error_code: ErrorCode::LeaderNotAvailable,
+                            partition_index: 0,
+                            leader_id: -1,
+                            replica_nodes: Array(vec![1, 2]),
+                            isr_nodes: Array(vec![]),
+                            offline_replicas: Array(vec![1])
+                        }
+                    ])
+                }
+            ])
+        };
+
+        let cluster: ClusterMetadata = ClusterMetadata::from(response);
+        let topic_partition: TopicPartition = TopicPartition::new("foo", 0);
+        assert_eq!(cluster.controller(), None);
+        assert_eq!(cluster.leader(&topic_partition), None);
+        assert_eq!(cluster.partition(&topic_partition).unwrap().offline_replicas, vec![1]);
+        assert_eq!(cluster.partition(&topic_partition).unwrap().leader_epoch, None);
+        // node 2 is a replica but isn't in the broker list, so it can't be resolved
+        assert_eq!(cluster.replicas(&topic_partition).len(), 1);
+        assert_eq!(cluster.node(1).unwrap().rack, Some(String::from("rack-a")));
+    }
+}
@@ -0,0 +1,748 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use tracing::{debug, warn};
+use crate::bootstrap::ResolvedAddresses;
+use crate::cluster::{ClusterMetadata, Node};
+use crate::metadata_cache::MetadataCache;
+use crate::metrics::ThrottleMetrics;
+use crate::protocol::networking::{ConnectionConfig, KafkaConnection, NetworkError};
+use crate::protocol::{KafkaRequest, KafkaResponse, SupportedApiVersions};
+use crate::retry;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionPoolConfig {
+    pub connection: ConnectionConfig,
+    /// `reconnect.backoff.ms`: the backoff after the first failed connection attempt to a node.
+    pub reconnect_backoff: Duration,
+    /// `reconnect.backoff.max.ms`: the backoff doubles with every consecutive failure up to this.
+    pub reconnect_backoff_max: Duration,
+    /// `connections.max.idle.ms`: connections unused for this long are closed.
+    pub connections_max_idle: Duration
+}
+
+impl Default for ConnectionPoolConfig {
+    fn default() -> Self {
+        ConnectionPoolConfig {
+            connection: ConnectionConfig::default(),
+            reconnect_backoff: Duration::from_millis(50),
+            reconnect_backoff_max: Duration::from_millis(1000),
+            connections_max_idle: Duration::from_millis(540_000)
+        }
+    }
+}
+
+impl ConnectionPoolConfig {
+    /// The backoff after `failed_attempts` consecutive failures, before jitter.
+    fn reconnect_backoff(&self, failed_attempts: u32) -> Duration {
+        retry::exponential_backoff(self.reconnect_backoff, self.reconnect_backoff_max, failed_attempts)
+    }
+}
+
+/// The connection to one node, shared by every thread which sends requests to that node.
+#[derive(Debug)]
+struct NodeConnection {
+    connection: Mutex<Option<KafkaConnection>>,
+    /// Requests which are being sent or waiting for the connection.
+    in_flight: AtomicUsize
+}
+
+#[derive(Debug)]
+struct NodeState {
+    connection: Arc<NodeConnection>,
+    failed_attempts: u32,
+    reconnect_after: Option<Instant>,
+    /// Until when the node asked for nothing more to be sent to it, kept here so that it can be
+    /// checked without waiting for the connection.
+    throttled_until: Option<Instant>
+}
+
+impl NodeState {
+    fn new() -> Self {
+        NodeState {
+            connection: Arc::new(NodeConnection {
+                connection: Mutex::new(None),
+                in_flight: AtomicUsize::new(0)
+            }),
+            failed_attempts: 0,
+            reconnect_after: None,
+            throttled_until: None
+        }
+    }
+
+    fn is_backing_off(&self, now: Instant) -> bool {
+        self.reconnect_after.map(|reconnect_after| now < reconnect_after).unwrap_or(false)
+    }
+
+    fn throttle_remaining(&self, now: Instant) -> Duration {
+        self.throttled_until
+            .map(|throttled_until| throttled_until.saturating_duration_since(now))
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Decrements a node's in-flight count when a request finishes, however it finishes.
+struct InFlightGuard(Arc<NodeConnection>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Connections to the brokers in the cluster, keyed by node id. Connections are opened the first
+/// time a node is used, and node addresses come from the metadata cache.
+#[derive(Debug)]
+pub struct ConnectionPool {
+    config: ConnectionPoolConfig,
+    metadata: Arc<MetadataCache>,
+    nodes: Mutex<HashMap<i32, NodeState>>,
+    throttle_metrics: ThrottleMetrics
+}
+
+impl ConnectionPool {
+    pub fn new(metadata: Arc<MetadataCache>, config: ConnectionPoolConfig) -> Self {
+        ConnectionPool {
+            config,
+            metadata,
+            nodes: Mutex::new(HashMap::new()),
+            throttle_metrics: ThrottleMetrics::default()
+        }
+    }
+
+    fn lock_nodes(&self) -> MutexGuard<'_, HashMap<i32, NodeState>> {
+        self.nodes.lock().expect("Connection pool lock was poisoned")
+    }
+
+    /// How long requests wait for their responses, unless sent with `send_within`.
+    pub fn request_timeout(&self) -> Duration {
+        self.config.connection.request_timeout
+    }
+
+    /// Sends a request to a node and waits for the response, connecting first if necessary.
+    /// A failed send closes the connection, and the next request to the node reconnects.
+    pub(crate) fn send<Request: KafkaRequest, Response: KafkaResponse>(&self, node_id: i32, request: Request) -> Result<Response> {
+        self.send_within(node_id, request, self.request_timeout())
+    }
+
+    /// Like `send`, but waits up to `timeout` for the response rather than the request timeout,
+    /// for requests the broker holds on to, such as JoinGroup during a rebalance.
+    pub(crate) fn send_within<Request: KafkaRequest, Response: KafkaResponse>(&self, node_id: i32, request: Request, timeout: Duration) -> Result<Response> {
+        let response: Response = self.with_connection(node_id, |connection| connection.send_request_and_get_response_within(request, timeout))?;
+        let throttle_time: Duration = Duration::from_millis(response.throttle_time_ms().max(0) as u64);
+        self.throttle_metrics.record(Request::get_api_key(), throttle_time);
+        Ok(response)
+    }
+
+    /// Sends several requests to a node on one connection without waiting for each response in
+    /// turn, and returns the responses in the order of the requests.
+    pub(crate) fn send_pipelined<Request: KafkaRequest, Response: KafkaResponse>(&self, node_id: i32, requests: Vec<Request>) -> Result<Vec<Response>> {
+        let responses: Vec<Response> = self.with_connection(node_id, |connection| connection.send_requests_and_get_responses(requests))?;
+        for response in &responses {
+            let throttle_time: Duration = Duration::from_millis(response.throttle_time_ms().max(0) as u64);
+            self.throttle_metrics.record(Request::get_api_key(), throttle_time);
+        }
+        Ok(responses)
+    }
+
+    /// Sends a request which the broker won't answer, such as a Produce request with acks=0.
+    pub(crate) fn send_without_response<Request: KafkaRequest>(&self, node_id: i32, request: Request) -> Result<()> {
+        self.with_connection(node_id, |connection| connection.send_request(request))
+    }
+
+    /// The versions of each API the node supports, asked for once per connection.
+    pub(crate) fn api_versions(&self, node_id: i32) -> Result<SupportedApiVersions> {
+        self.with_connection(node_id, |connection| connection.api_versions().cloned())
+    }
+
+    /// Runs `send` on the node's connection, connecting first if necessary. If `send` fails, the
+    /// connection is closed. A throttled node fails the request straight away, rather than hold
+    /// on to the connection until the throttle ends.
+    fn with_connection<T, F>(&self, node_id: i32, send: F) -> Result<T> where F: FnOnce(&mut KafkaConnection) -> Result<T> {
+        self.close_idle_connections();
+
+        let node_connection: Arc<NodeConnection> = {
+            let mut nodes: MutexGuard<HashMap<i32, NodeState>> = self.lock_nodes();
+            let node_state: &mut NodeState = nodes.entry(node_id).or_insert_with(NodeState::new);
+            let now: Instant = Instant::now();
+            if node_state.is_backing_off(now) {
+                return Err(anyhow::Error::new(NetworkError::NodeBackingOff { node_id, failed_attempts: node_state.failed_attempts }));
+            }
+            let throttle_remaining: Duration = node_state.throttle_remaining(now);
+            if !throttle_remaining.is_zero() {
+                return Err(anyhow::Error::new(NetworkError::NodeThrottled { node_id, remaining: throttle_remaining }));
+            }
+            node_state.connection.clone()
+        };
+        node_connection.in_flight.fetch_add(1, Ordering::SeqCst);
+        let _in_flight_guard: InFlightGuard = InFlightGuard(node_connection.clone());
+
+        let mut connection: MutexGuard<Option<KafkaConnection>> = node_connection.connection.lock()
+            .expect("Connection lock was poisoned");
+        if connection.as_ref().is_some_and(KafkaConnection::is_session_expired) {
+            debug!("Reconnecting to node {} because its SASL session expired", node_id);
+            *connection = None;
+        }
+        if connection.is_none() {
+            match self.connect(node_id) {
+                Ok(new_connection) => {
+                    self.record_connection_success(node_id);
+                    *connection = Some(new_connection);
+                },
+                Err(e) => {
+                    self.record_connection_failure(node_id);
+                    return Err(e);
+                }
+            }
+        }
+
+        // a request which had the connection before this one may have been throttled
+        let throttle_remaining: Duration = connection.as_ref().map_or(Duration::ZERO, KafkaConnection::throttle_remaining);
+        if !throttle_remaining.is_zero() {
+            return Err(anyhow::Error::new(NetworkError::NodeThrottled { node_id, remaining: throttle_remaining }));
+        }
+
+        let result: Result<T> = send(connection.as_mut().expect("Connection was just opened"));
+        match &result {
+            Ok(_) => {
+                let throttle_remaining: Duration = connection.as_ref().map_or(Duration::ZERO, KafkaConnection::throttle_remaining);
+                self.record_throttle(node_id, throttle_remaining);
+            },
+            Err(e) => {
+                warn!("Closing connection to node {} after a failed request: {:?}", node_id, e);
+                *connection = None;
+                // a response which couldn't be decoded or a broker error doesn't mean the node is down
+                if is_connection_failure(e, self.request_timeout()) {
+                    self.record_connection_failure(node_id);
+                }
+            }
+        }
+        result
+    }
+
+    fn connect(&self, node_id: i32) -> Result<KafkaConnection> {
+        let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        let node: &Node = cluster.node(node_id)
+            .ok_or_else(|| anyhow!("Node {} is not in the cluster metadata", node_id))?;
+        let port: u16 = u16::try_from(node.port)
+            .map_err(|_| anyhow!("Node {} has an invalid port: {}", node_id, node.port))?;
+        ResolvedAddresses::resolve_host(&node.host, port)?.try_each(|address| {
+            KafkaConnection::connect(node_id, address, &self.config.connection)
+        })
+    }
+
+    fn record_connection_success(&self, node_id: i32) {
+        if let Some(node_state) = self.lock_nodes().get_mut(&node_id) {
+            node_state.failed_attempts = 0;
+            node_state.reconnect_after = None;
+        }
+    }
+
+    fn record_throttle(&self, node_id: i32, throttle_remaining: Duration) {
+        if let Some(node_state) = self.lock_nodes().get_mut(&node_id) {
+            node_state.throttled_until = match throttle_remaining.is_zero() {
+                true => None,
+                false => Some(Instant::now() + throttle_remaining)
+            };
+        }
+    }
+
+    fn record_connection_failure(&self, node_id: i32) {
+        if let Some(node_state) = self.lock_nodes().get_mut(&node_id) {
+            node_state.failed_attempts += 1;
+            let backoff: Duration = retry::with_jitter(self.config.reconnect_backoff(node_state.failed_attempts));
+            debug!("Backing off node {} for {:?} after {} failed attempts", node_id, backoff, node_state.failed_attempts);
+            node_state.reconnect_after = Some(Instant::now() + backoff);
+        }
+    }
+
+    /// Closes connections which have been idle for longer than `connections_max_idle`. Connections
+    /// with requests in flight are left alone.
+    pub fn close_idle_connections(&self) {
+        let nodes: MutexGuard<HashMap<i32, NodeState>> = self.lock_nodes();
+        for (node_id, node_state) in nodes.iter() {
+            if node_state.connection.in_flight.load(Ordering::SeqCst) > 0 {
+                continue;
+            }
+            if let Ok(mut connection) = node_state.connection.connection.try_lock() {
+                let is_idle: bool = connection.as_ref()
+                    .map(|connection| connection.idle_time() >= self.config.connections_max_idle)
+                    .unwrap_or(false);
+                if is_idle {
+                    debug!("Closing idle connection to node {}", node_id);
+                    *connection = None;
+                }
+            }
+        }
+    }
+
+    /// Closes the connection to a node, if there is one.
+    pub fn disconnect(&self, node_id: i32) {
+        let node_connection: Option<Arc<NodeConnection>> = self.lock_nodes().get(&node_id)
+            .map(|node_state| node_state.connection.clone());
+        if let Some(node_connection) = node_connection {
+            *node_connection.connection.lock().expect("Connection lock was poisoned") = None;
+        }
+    }
+
+    pub fn is_connected(&self, node_id: i32) -> bool {
+        let node_connection: Option<Arc<NodeConnection>> = self.lock_nodes().get(&node_id)
+            .map(|node_state| node_state.connection.clone());
+        match node_connection {
+            Some(node_connection) => match node_connection.connection.try_lock() {
+                Ok(connection) => connection.is_some(),
+                // someone is using the connection right now
+                Err(_) => true
+            },
+            None => false
+        }
+    }
+
+    pub fn is_backing_off(&self, node_id: i32) -> bool {
+        self.lock_nodes().get(&node_id)
+            .map(|node_state| node_state.is_backing_off(Instant::now()))
+            .unwrap_or(false)
+    }
+
+    /// Whether the node asked for nothing more to be sent on its connection for now (KIP-219).
+    pub fn is_throttled(&self, node_id: i32) -> bool {
+        self.lock_nodes().get(&node_id)
+            .map(|node_state| !node_state.throttle_remaining(Instant::now()).is_zero())
+            .unwrap_or(false)
+    }
+
+    /// The throttle times brokers have reported in responses sent through this pool.
+    pub fn throttle_metrics(&self) -> &ThrottleMetrics {
+        &self.throttle_metrics
+    }
+
+    pub fn in_flight_request_count(&self, node_id: i32) -> usize {
+        self.lock_nodes().get(&node_id)
+            .map(|node_state| node_state.connection.in_flight.load(Ordering::SeqCst))
+            .unwrap_or(0)
+    }
+
+    /// Picks a broker for a request which any broker can serve, such as Metadata. A connected node
+    /// with nothing in flight wins outright. Otherwise the node with the fewest requests in flight
+    /// wins, preferring connected nodes to ones which need a new connection. Throttled nodes are
+    /// only picked when no other node can be, and nodes which are backing off are skipped. The
+    /// search starts at a random node to spread load between clients.
+    pub fn least_loaded_node(&self) -> Option<i32> {
+        let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        let mut node_ids: Vec<i32> = cluster.brokers.keys().copied().collect();
+        if node_ids.is_empty() {
+            return None;
+        }
+        node_ids.sort();
+        let offset: usize = rand::thread_rng().gen_range(0..node_ids.len());
+        node_ids.rotate_left(offset);
+
+        let now: Instant = Instant::now();
+        let mut best: Option<(bool, usize, bool, i32)> = None;
+        for node_id in node_ids {
+            if self.lock_nodes().get(&node_id).map(|node_state| node_state.is_backing_off(now)).unwrap_or(false) {
+                continue;
+            }
+            let in_flight: usize = self.in_flight_request_count(node_id);
+            let is_connected: bool = self.is_connected(node_id);
+            let is_throttled: bool = self.is_throttled(node_id);
+            if is_connected && in_flight == 0 && !is_throttled {
+                return Some(node_id);
+            }
+            let is_better: bool = match best {
+                Some((best_is_throttled, best_in_flight, best_is_connected, _)) =>
+                    (is_throttled, in_flight, !is_connected) < (best_is_throttled, best_in_flight, !best_is_connected),
+                None => true
+            };
+            if is_better {
+                best = Some((is_throttled, in_flight, is_connected, node_id));
+            }
+        }
+        best.map(|(_, _, _, node_id)| node_id)
+    }
+
+    /// Sends a request to the least-loaded node. Returns the node which answered with its response.
+    /// Fails with a retriable error if no node is known yet or every node is backing off.
+    pub(crate) fn send_to_any<Request: KafkaRequest, Response: KafkaResponse>(&self, request: Request) -> Result<(i32, Response)> {
+        let Some(node_id) = self.least_loaded_node() else {
+            self.metadata.request_update();
+            return Err(anyhow::Error::new(NetworkError::NoNodeAvailable));
+        };
+        Ok((node_id, self.send(node_id, request)?))
+    }
+}
+
+/// Whether a request failed because of the connection, rather than because of its response.
+/// A request given less time than the request timeout which runs out of it only ran out of its
+/// caller's time, such as the rest of a delivery timeout.
+fn is_connection_failure(error: &anyhow::Error, request_timeout: Duration) -> bool {
+    error.chain().any(|cause| match cause.downcast_ref::<NetworkError>() {
+        Some(NetworkError::RequestTimedOut { timeout, .. }) => *timeout >= request_timeout,
+        Some(_) => true,
+        None => cause.is::<std::io::Error>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use kafka_encode::primitives::{CompactArray, CompactString};
+    use crate::cluster::ClusterMetadata;
+    use crate::cluster::tests::local_cluster;
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::metrics::ThrottleStats;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::api_versions::{ApiVersionsRequestV3, ApiVersionsResponseV3, ApiVersionsResponseV3TaggedFields};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::networking::{ConnectionConfig, NetworkError};
+    use crate::protocol::tags::TaggedFields;
+    use crate::retry::{is_retriable, RetryPolicy};
+    use crate::tls::tests::TestPki;
+
+    fn metadata_with_nodes(ports: &[(i32, i32)]) -> Arc<MetadataCache> {
+        let cluster: ClusterMetadata = local_cluster("foo", ports, &[]);
+        let cache: MetadataCache = MetadataCache::new(Box::new(StaticFetcher(cluster.clone())), MetadataCacheConfig {
+            metadata_max_age: Duration::from_secs(60),
+            retry_backoff: Duration::from_secs(60)
+        });
+        cache.update(cluster);
+        Arc::new(cache)
+    }
+
+    fn api_versions_request() -> ApiVersionsRequestV3 {
+        ApiVersionsRequestV3 {
+            client_software_name: CompactString(String::from("kafkart")),
+            client_software_version: CompactString(String::from("0.0.1")),
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    fn api_versions_broker() -> MockBroker {
+        MockBroker::start(|request: &MockRequest| {
+            request.decode::<ApiVersionsRequestV3>().unwrap();
+            Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(ApiVersionsResponseV3 {
+                error_code: ErrorCode::None,
+                api_keys: CompactArray(vec![]),
+                throttle_time_ms: 0,
+                tag_buffer: ApiVersionsResponseV3TaggedFields {
+                    supported_features: None,
+                    finalized_features_epoch: None,
+                    finalized_features: None
+                }
+            }))
+        })
+    }
+
+    fn config() -> ConnectionPoolConfig {
+        ConnectionPoolConfig {
+            reconnect_backoff: Duration::from_millis(20),
+            reconnect_backoff_max: Duration::from_millis(80),
+            ..ConnectionPoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_connections_are_opened_lazily_and_reused() {
+        let broker: MockBroker = api_versions_broker();
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), config());
+        assert!(!pool.is_connected(1));
+        assert_eq!(broker.accepted_connections(), 0);
+
+        for _ in 0..3 {
+            let response: ApiVersionsResponseV3 = pool.send(1, api_versions_request()).unwrap();
+            assert_eq!(response.error_code, ErrorCode::None);
+        }
+        assert!(pool.is_connected(1));
+        assert_eq!(broker.accepted_connections(), 1);
+    }
+
+    #[test]
+    fn test_unknown_node_fails() {
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[]), config());
+        assert!(pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(7, api_versions_request()).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_backoff_grows_and_expires() {
+        let config: ConnectionPoolConfig = config();
+        assert_eq!(config.reconnect_backoff(1), Duration::from_millis(20));
+        assert_eq!(config.reconnect_backoff(2), Duration::from_millis(40));
+        assert_eq!(config.reconnect_backoff(3), Duration::from_millis(80));
+        assert_eq!(config.reconnect_backoff(30), Duration::from_millis(80));
+
+        // nothing listens on the port of a dropped broker
+        let port: i32 = api_versions_broker().port();
+        thread::sleep(Duration::from_millis(20));
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, port)]), config);
+        assert!(pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).is_err());
+        assert!(pool.is_backing_off(1));
+        assert!(pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).is_err());
+        thread::sleep(Duration::from_millis(30));
+        assert!(!pool.is_backing_off(1));
+    }
+
+    #[test]
+    fn test_undecodable_responses_close_the_connection_without_backing_off() {
+        let broker: MockBroker = MockBroker::start(|request: &MockRequest| {
+            let mut response: Vec<u8> = request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_response(ErrorCode::None));
+            // the correlation id and error code, but not the API keys
+            response.truncate(6);
+            Some(response)
+        });
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), config());
+        let error: anyhow::Error = pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).unwrap_err();
+        assert!(!is_retriable(&error));
+        assert!(!pool.is_connected(1));
+        assert!(!pool.is_backing_off(1));
+    }
+
+    #[test]
+    fn test_idle_connections_are_closed() {
+        let broker: MockBroker = api_versions_broker();
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), ConnectionPoolConfig {
+            connections_max_idle: Duration::from_millis(20),
+            ..config()
+        });
+        let _: ApiVersionsResponseV3 = pool.send(1, api_versions_request()).unwrap();
+        assert!(pool.is_connected(1));
+        thread::sleep(Duration::from_millis(30));
+        pool.close_idle_connections();
+        assert!(!pool.is_connected(1));
+
+        let _: ApiVersionsResponseV3 = pool.send(1, api_versions_request()).unwrap();
+        assert_eq!(broker.accepted_connections(), 2);
+    }
+
+    #[test]
+    fn test_least_loaded_node_prefers_connected_nodes() {
+        let first_broker: MockBroker = api_versions_broker();
+        let second_broker: MockBroker = api_versions_broker();
+        let pool: ConnectionPool = ConnectionPool::new(
+            metadata_with_nodes(&[(1, first_broker.port()), (2, second_broker.port())]), config()
+        );
+        assert!(pool.least_loaded_node().is_some());
+
+        let _: ApiVersionsResponseV3 = pool.send(2, api_versions_request()).unwrap();
+        for _ in 0..10 {
+            assert_eq!(pool.least_loaded_node(), Some(2));
+        }
+        let (node_id, _): (i32, ApiVersionsResponseV3) = pool.send_to_any(api_versions_request()).unwrap();
+        assert_eq!(node_id, 2);
+    }
+
+    #[test]
+    fn test_least_loaded_node_skips_nodes_backing_off() {
+        let broker: MockBroker = api_versions_broker();
+        let dead_port: i32 = api_versions_broker().port();
+        thread::sleep(Duration::from_millis(20));
+        let pool: ConnectionPool = ConnectionPool::new(
+            metadata_with_nodes(&[(1, dead_port), (2, broker.port())]), ConnectionPoolConfig {
+                reconnect_backoff: Duration::from_secs(60),
+                reconnect_backoff_max: Duration::from_secs(60),
+                ..config()
+            }
+        );
+        assert!(pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).is_err());
+        for _ in 0..10 {
+            assert_eq!(pool.least_loaded_node(), Some(2));
+        }
+
+        // until metadata names a broker, requests for any broker can be retried
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[]), config());
+        let error: anyhow::Error = pool.send_to_any::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_request()).unwrap_err();
+        assert!(matches!(error.downcast_ref::<NetworkError>(), Some(NetworkError::NoNodeAvailable)));
+        assert!(is_retriable(&error));
+    }
+
+    #[test]
+    fn test_tls_connections() {
+        let pki: TestPki = TestPki::new("127.0.0.1");
+        let broker: MockBroker = MockBroker::start_with_tls(Some(pki.server_config(false)), |request: &MockRequest| {
+            Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(ApiVersionsResponseV3 {
+                error_code: ErrorCode::None,
+                api_keys: CompactArray(vec![]),
+                throttle_time_ms: 0,
+                tag_buffer: ApiVersionsResponseV3TaggedFields {
+                    supported_features: None,
+                    finalized_features_epoch: None,
+                    finalized_features: None
+                }
+            }))
+        });
+
+        let plaintext_pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), config());
+        assert!(plaintext_pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).is_err());
+
+        let tls_pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), ConnectionPoolConfig {
+            connection: ConnectionConfig {
+                tls: Some(pki.client_config().build().unwrap()),
+                ..ConnectionConfig::default()
+            },
+            ..config()
+        });
+        for _ in 0..2 {
+            let response: ApiVersionsResponseV3 = tls_pool.send(1, api_versions_request()).unwrap();
+            assert_eq!(response.error_code, ErrorCode::None);
+        }
+    }
+
+    fn api_versions_response(error_code: ErrorCode) -> ApiVersionsResponseV3 {
+        ApiVersionsResponseV3 {
+            error_code,
+            api_keys: CompactArray(vec![]),
+            throttle_time_ms: 0,
+            tag_buffer: ApiVersionsResponseV3TaggedFields {
+                supported_features: None,
+                finalized_features_epoch: None,
+                finalized_features: None
+            }
+        }
+    }
+
+    #[test]
+    fn test_requests_time_out() {
+        let broker: MockBroker = MockBroker::start(|request: &MockRequest| {
+            thread::sleep(Duration::from_millis(300));
+            Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_response(ErrorCode::None)))
+        });
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), ConnectionPoolConfig {
+            connection: ConnectionConfig {
+                request_timeout: Duration::from_millis(50),
+                ..ConnectionConfig::default()
+            },
+            ..config()
+        });
+        let started: Instant = Instant::now();
+        let error: anyhow::Error = pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).unwrap_err();
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert!(matches!(error.downcast_ref::<NetworkError>(), Some(NetworkError::RequestTimedOut { node_id: 1, .. })));
+        assert!(is_retriable(&error));
+        assert!(!pool.is_connected(1));
+        assert!(pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).is_err_and(|e| matches!(e.downcast_ref::<NetworkError>(), Some(NetworkError::NodeBackingOff { .. }))));
+    }
+
+    #[test]
+    fn test_requests_cut_short_by_their_caller_do_not_back_off_the_node() {
+        let broker: MockBroker = MockBroker::start(|request: &MockRequest| {
+            thread::sleep(Duration::from_millis(100));
+            Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_response(ErrorCode::None)))
+        });
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), config());
+        let error: anyhow::Error = pool.send_within::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request(), Duration::from_millis(10)).unwrap_err();
+        assert!(matches!(error.downcast_ref::<NetworkError>(), Some(NetworkError::RequestTimedOut { node_id: 1, .. })));
+        assert!(!pool.is_connected(1));
+        assert!(pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).is_ok());
+    }
+
+    #[test]
+    fn test_retried_sends_reconnect() {
+        // the first connection is dropped, then the broker is briefly unavailable
+        let requests: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let broker_requests: Arc<AtomicUsize> = requests.clone();
+        let broker: MockBroker = MockBroker::start(move |request: &MockRequest| {
+            match broker_requests.fetch_add(1, Ordering::SeqCst) {
+                0 => None,
+                1 => Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_response(ErrorCode::CoordinatorNotAvailable))),
+                _ => Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_response(ErrorCode::None)))
+            }
+        });
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), config());
+        let policy: RetryPolicy = RetryPolicy {
+            retry_backoff: Duration::from_millis(10),
+            retry_backoff_max: Duration::from_millis(40),
+            ..RetryPolicy::default()
+        };
+        let check = |response: &ApiVersionsResponseV3| match &response.error_code {
+            ErrorCode::None => Ok(()),
+            error_code => Err(anyhow::Error::new(error_code.clone()))
+        };
+        let send_with_retries = |check: &dyn Fn(&ApiVersionsResponseV3) -> anyhow::Result<()>| policy.run(|_| {
+            let response: ApiVersionsResponseV3 = pool.send(1, api_versions_request())?;
+            check(&response)?;
+            Ok(response)
+        });
+        let response: ApiVersionsResponseV3 = send_with_retries(&check).unwrap();
+        assert_eq!(response.error_code, ErrorCode::None);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(broker.accepted_connections(), 2);
+
+        let fatal: anyhow::Error = send_with_retries(&|_: &ApiVersionsResponseV3| {
+            Err(anyhow::Error::new(ErrorCode::ClusterAuthorizationFailed))
+        }).unwrap_err();
+        assert_eq!(fatal.downcast_ref::<ErrorCode>(), Some(&ErrorCode::ClusterAuthorizationFailed));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_pipelined_responses_are_in_request_order() {
+        let broker: MockBroker = MockBroker::start(|request: &MockRequest| {
+            let error_code: ErrorCode = match request.correlation_id % 2 {
+                0 => ErrorCode::None,
+                _ => ErrorCode::UnsupportedVersion
+            };
+            Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(api_versions_response(error_code)))
+        });
+        let pool: ConnectionPool = ConnectionPool::new(metadata_with_nodes(&[(1, broker.port())]), config());
+
+        let requests: Vec<ApiVersionsRequestV3> = (0..5).map(|_| api_versions_request()).collect();
+        let responses: Vec<ApiVersionsResponseV3> = pool.send_pipelined(1, requests).unwrap();
+        let error_codes: Vec<ErrorCode> = responses.into_iter().map(|response| response.error_code).collect();
+        assert_eq!(error_codes, vec![
+            ErrorCode::None, ErrorCode::UnsupportedVersion, ErrorCode::None, ErrorCode::UnsupportedVersion, ErrorCode::None
+        ]);
+        assert_eq!(broker.accepted_connections(), 1);
+        assert_eq!(pool.throttle_metrics().for_api_key(&ApiKey::ApiVersions).responses, 5);
+    }
+
+    #[test]
+    fn test_throttled_nodes_are_skipped_and_measured() {
+        let broker: MockBroker = MockBroker::start(|request: &MockRequest| {
+            Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(ApiVersionsResponseV3 {
+                throttle_time_ms: 150,
+                ..api_versions_response(ErrorCode::None)
+            }))
+        });
+        let other_broker: MockBroker = api_versions_broker();
+        let pool: ConnectionPool = ConnectionPool::new(
+            metadata_with_nodes(&[(1, broker.port()), (2, other_broker.port())]), config()
+        );
+
+        let started: Instant = Instant::now();
+        let _: ApiVersionsResponseV3 = pool.send(1, api_versions_request()).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(150));
+        assert!(pool.is_throttled(1));
+        // requests to a throttled node fail rather than wait for the throttle to end
+        let error: anyhow::Error = pool.send::<ApiVersionsRequestV3, ApiVersionsResponseV3>(1, api_versions_request()).unwrap_err();
+        assert!(matches!(error.downcast_ref::<NetworkError>(), Some(NetworkError::NodeThrottled { node_id: 1, .. })));
+        assert!(is_retriable(&error));
+        assert!(pool.is_connected(1));
+        assert!(!pool.is_backing_off(1));
+        // an idle node is preferred to a throttled one
+        let _: ApiVersionsResponseV3 = pool.send(2, api_versions_request()).unwrap();
+        for _ in 0..10 {
+            assert_eq!(pool.least_loaded_node(), Some(2));
+        }
+
+        while pool.is_throttled(1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        let _: ApiVersionsResponseV3 = pool.send(1, api_versions_request()).unwrap();
+        assert_eq!(broker.accepted_connections(), 1);
+
+        let stats: ThrottleStats = pool.throttle_metrics().for_api_key(&ApiKey::ApiVersions);
+        assert_eq!(stats.responses, 3);
+        assert_eq!(stats.throttled_responses, 2);
+        assert_eq!(stats.max_throttle_time, Duration::from_millis(150));
+        assert_eq!(stats.avg_throttle_time(), Duration::from_millis(100));
+    }
+}
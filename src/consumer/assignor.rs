@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use kafka_encode::primitives::{Array, NullableBytes, NullableString};
+use crate::cluster::TopicPartition;
+use crate::protocol::consumer_protocol::{ConsumerProtocolAssignmentV0, ConsumerProtocolSubscriptionV0, ConsumerProtocolSubscriptionV1, ConsumerProtocolSubscriptionV2, ConsumerProtocolSubscriptionV3, ConsumerProtocolTopicPartitionsV0, decode_versioned, encode_versioned, split_version};
+
+/// The version of the consumer protocol schemas this member writes.
+const CONSUMER_PROTOCOL_VERSION: i16 = 3;
+
+/// How members give up partitions when their group rebalances.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RebalanceProtocol {
+    /// Every member revokes all of its partitions before it rejoins.
+    Eager,
+    /// Members keep their partitions while they rejoin, and only revoke the ones which move to
+    /// another member (KIP-429).
+    Cooperative
+}
+
+/// What a member tells the group leader when it joins, decoded from any version of
+/// `ConsumerProtocolSubscription`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Subscription {
+    pub topics: Vec<String>,
+    /// Data for the assignor, from `PartitionAssignor::subscription_user_data`.
+    pub user_data: Option<Vec<u8>>,
+    /// The partitions the member holds, which members only send under the cooperative protocol.
+    pub owned_partitions: Vec<TopicPartition>,
+    /// The generation the member held its partitions in, if it has been in one.
+    pub generation_id: Option<i32>,
+    pub rack_id: Option<String>
+}
+
+impl Subscription {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        encode_versioned(CONSUMER_PROTOCOL_VERSION, ConsumerProtocolSubscriptionV3 {
+            topics: Array(self.topics.clone()),
+            user_data: NullableBytes(self.user_data.clone()),
+            owned_partitions: Array(group_by_topic(&self.owned_partitions)),
+            generation_id: self.generation_id.unwrap_or(-1),
+            rack_id: NullableString(self.rack_id.clone())
+        })
+    }
+
+    /// Decodes a subscription of any version, reading only the fields this member knows of.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let subscription: ConsumerProtocolSubscriptionV3 = match split_version(bytes)?.0 {
+            0 => {
+                let (_, v0): (i16, ConsumerProtocolSubscriptionV0) = decode_versioned(bytes)?;
+                ConsumerProtocolSubscriptionV3 {
+                    topics: v0.topics,
+                    user_data: v0.user_data,
+                    owned_partitions: Array(Vec::new()),
+                    generation_id: -1,
+                    rack_id: NullableString(None)
+                }
+            },
+            1 => {
+                let (_, v1): (i16, ConsumerProtocolSubscriptionV1) = decode_versioned(bytes)?;
+                ConsumerProtocolSubscriptionV3 {
+                    topics: v1.topics,
+                    user_data: v1.user_data,
+                    owned_partitions: v1.owned_partitions,
+                    generation_id: -1,
+                    rack_id: NullableString(None)
+                }
+            },
+            2 => {
+                let (_, v2): (i16, ConsumerProtocolSubscriptionV2) = decode_versioned(bytes)?;
+                ConsumerProtocolSubscriptionV3 {
+                    topics: v2.topics,
+                    user_data: v2.user_data,
+                    owned_partitions: v2.owned_partitions,
+                    generation_id: v2.generation_id,
+                    rack_id: NullableString(None)
+                }
+            },
+            _ => decode_versioned(bytes)?.1
+        };
+        Ok(Subscription {
+            topics: subscription.topics.0,
+            user_data: subscription.user_data.0,
+            owned_partitions: ungroup(subscription.owned_partitions.0),
+            generation_id: Some(subscription.generation_id).filter(|generation_id| *generation_id >= 0),
+            rack_id: subscription.rack_id.0
+        })
+    }
+}
+
+/// The partitions the group leader assigned to a member, decoded from `ConsumerProtocolAssignment`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Assignment {
+    pub partitions: Vec<TopicPartition>,
+    pub user_data: Option<Vec<u8>>
+}
+
+impl Assignment {
+    pub fn new(partitions: Vec<TopicPartition>) -> Self {
+        Assignment { partitions, user_data: None }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        encode_versioned(CONSUMER_PROTOCOL_VERSION, ConsumerProtocolAssignmentV0 {
+            assigned_partitions: Array(group_by_topic(&self.partitions)),
+            user_data: NullableBytes(self.user_data.clone())
+        })
+    }
+
+    /// Decodes an assignment from SyncGroup. A member the leader left out gets no bytes, and so
+    /// no partitions.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() {
+            return Ok(Assignment::default());
+        }
+        let (_, assignment): (i16, ConsumerProtocolAssignmentV0) = decode_versioned(bytes)?;
+        Ok(Assignment {
+            partitions: ungroup(assignment.assigned_partitions.0),
+            user_data: assignment.user_data.0
+        })
+    }
+}
+
+/// Shares out the partitions of a group's topics between its members, like
+/// `partition.assignment.strategy`. The group leader runs the assignor which every member
+/// supports, so each member must configure assignors with the same names as the Java client's
+/// for them to be used in the same group.
+pub trait PartitionAssignor: Send + Sync + Debug {
+    /// The protocol name the assignor is known by in the group.
+    fn name(&self) -> &str;
+
+    /// The rebalance protocols the assignor's assignments are safe under.
+    fn supported_protocols(&self) -> Vec<RebalanceProtocol> {
+        vec![RebalanceProtocol::Eager]
+    }
+
+    /// Data the member sends to the leader in its subscription.
+    fn subscription_user_data(&self, _topics: &[String]) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Assigns the partitions of each subscribed topic between the members, by member id. Every
+    /// member should be given an assignment, even an empty one.
+    fn assign(&self, partitions_per_topic: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Assignment>;
+
+    /// Called with the member's assignment once it has synced with the group.
+    fn on_assignment(&self, _assignment: &Assignment, _generation_id: i32) {}
+}
+
+/// Shares out each topic's partitions in ranges between the members subscribed to it, in member
+/// id order. Earlier members get one extra partition when a topic's partitions don't divide
+/// evenly.
+#[derive(Debug, Default)]
+pub struct RangeAssignor;
+
+impl PartitionAssignor for RangeAssignor {
+    fn name(&self) -> &str {
+        "range"
+    }
+
+    fn assign(&self, partitions_per_topic: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Assignment> {
+        let mut assignment: BTreeMap<String, Vec<TopicPartition>> = empty_assignment(subscriptions);
+        for (topic, topic_partitions) in partitions_per_topic {
+            let members: Vec<&String> = subscribers(subscriptions, topic);
+            if members.is_empty() {
+                continue;
+            }
+            let per_member: usize = topic_partitions.len() / members.len();
+            let extra: usize = topic_partitions.len() % members.len();
+            for (index, member_id) in members.into_iter().enumerate() {
+                let start: usize = index * per_member + index.min(extra);
+                let length: usize = per_member + usize::from(index < extra);
+                assignment.get_mut(member_id).expect("Every member has an assignment")
+                    .extend_from_slice(&topic_partitions[start..start + length]);
+            }
+        }
+        to_assignments(assignment)
+    }
+}
+
+/// Deals the partitions of every subscribed topic out one at a time to the members in turn,
+/// skipping members which don't subscribe to a partition's topic.
+#[derive(Debug, Default)]
+pub struct RoundRobinAssignor;
+
+impl PartitionAssignor for RoundRobinAssignor {
+    fn name(&self) -> &str {
+        "roundrobin"
+    }
+
+    fn assign(&self, partitions_per_topic: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Assignment> {
+        let mut assignment: BTreeMap<String, Vec<TopicPartition>> = empty_assignment(subscriptions);
+        let members: Vec<&String> = subscriptions.keys().collect();
+        let mut next: usize = 0;
+        for (topic, topic_partitions) in partitions_per_topic {
+            if subscribers(subscriptions, topic).is_empty() {
+                continue;
+            }
+            for topic_partition in topic_partitions {
+                while !subscriptions[members[next % members.len()]].topics.contains(topic) {
+                    next += 1;
+                }
+                assignment.get_mut(members[next % members.len()]).expect("Every member has an assignment")
+                    .push(topic_partition.clone());
+                next += 1;
+            }
+        }
+        to_assignments(assignment)
+    }
+}
+
+/// The protocol a member rebalances with: cooperative if every one of its assignors supports it,
+/// since the group may choose any of them.
+pub(crate) fn rebalance_protocol(assignors: &[Arc<dyn PartitionAssignor>]) -> Result<RebalanceProtocol> {
+    for protocol in [RebalanceProtocol::Cooperative, RebalanceProtocol::Eager] {
+        if assignors.iter().all(|assignor| assignor.supported_protocols().contains(&protocol)) {
+            return Ok(protocol);
+        }
+    }
+    Err(anyhow!("The assignors in partition.assignment.strategy share no rebalance protocol"))
+}
+
+/// The members subscribed to a topic, in member id order.
+pub(crate) fn subscribers<'a>(subscriptions: &'a BTreeMap<String, Subscription>, topic: &String) -> Vec<&'a String> {
+    subscriptions.iter()
+        .filter(|(_, subscription)| subscription.topics.contains(topic))
+        .map(|(member_id, _)| member_id)
+        .collect()
+}
+
+pub(crate) fn empty_assignment(subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Vec<TopicPartition>> {
+    subscriptions.keys()
+        .map(|member_id| (member_id.clone(), Vec::new()))
+        .collect()
+}
+
+pub(crate) fn to_assignments(assignment: BTreeMap<String, Vec<TopicPartition>>) -> BTreeMap<String, Assignment> {
+    assignment.into_iter()
+        .map(|(member_id, partitions)| (member_id, Assignment::new(partitions)))
+        .collect()
+}
+
+/// Groups partitions by topic, in topic order, for the consumer protocol schemas.
+pub(crate) fn group_by_topic(partitions: &[TopicPartition]) -> Vec<ConsumerProtocolTopicPartitionsV0> {
+    let mut topics: BTreeMap<&String, Vec<i32>> = BTreeMap::new();
+    for topic_partition in partitions {
+        topics.entry(&topic_partition.topic).or_default().push(topic_partition.partition);
+    }
+    topics.into_iter()
+        .map(|(topic, partitions)| ConsumerProtocolTopicPartitionsV0 { topic: topic.clone(), partitions: Array(partitions) })
+        .collect()
+}
+
+pub(crate) fn ungroup(topics: Vec<ConsumerProtocolTopicPartitionsV0>) -> Vec<TopicPartition> {
+    topics.into_iter()
+        .flat_map(|topic| topic.partitions.0.into_iter().map(move |partition| TopicPartition::new(&topic.topic, partition)))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::Arc;
+    use kafka_encode::primitives::{Array, NullableBytes};
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::cluster::TopicPartition;
+    use crate::consumer::assignor::{Assignment, PartitionAssignor, RangeAssignor, rebalance_protocol, RebalanceProtocol, RoundRobinAssignor, Subscription};
+    use crate::consumer::sticky_assignor::CooperativeStickyAssignor;
+    use crate::protocol::consumer_protocol::{ConsumerProtocolSubscriptionV0, encode_versioned};
+
+    pub(crate) fn partitions(topic: &str, partitions: &[i32]) -> Vec<TopicPartition> {
+        partitions.iter().map(|partition| TopicPartition::new(topic, *partition)).collect()
+    }
+
+    pub(crate) fn subscription(topics: &[&str]) -> Subscription {
+        Subscription { topics: topics.iter().map(|topic| String::from(*topic)).collect(), ..Subscription::default() }
+    }
+
+    /// Up to `max_topics` topics named "t0", "t1" and so on, each with up to `max_partitions`
+    /// partitions.
+    pub(crate) fn random_topics(rng: &mut StdRng, max_topics: usize, max_partitions: i32) -> BTreeMap<String, Vec<TopicPartition>> {
+        (0..rng.gen_range(1..=max_topics))
+            .map(|index| {
+                let topic: String = format!("t{}", index);
+                let count: i32 = rng.gen_range(1..=max_partitions);
+                let topic_partitions: Vec<TopicPartition> = partitions(&topic, &(0..count).collect::<Vec<i32>>());
+                (topic, topic_partitions)
+            })
+            .collect()
+    }
+
+    /// Checks that no partition is assigned twice or to a member which isn't subscribed to its
+    /// topic, and returns how many partitions each member has.
+    pub(crate) fn check_valid(subscriptions: &BTreeMap<String, Subscription>, assignment: &BTreeMap<String, Assignment>) -> Vec<usize> {
+        let mut assigned: BTreeSet<&TopicPartition> = BTreeSet::new();
+        for (member_id, member_assignment) in assignment {
+            for topic_partition in &member_assignment.partitions {
+                assert!(subscriptions[member_id].topics.contains(&topic_partition.topic), "{} isn't subscribed to {:?}", member_id, topic_partition);
+                assert!(assigned.insert(topic_partition), "{:?} is assigned twice", topic_partition);
+            }
+        }
+        assignment.values().map(|member_assignment| member_assignment.partitions.len()).collect()
+    }
+
+    #[test]
+    fn test_subscription_and_assignment_round_trip() {
+        let subscription: Subscription = Subscription {
+            topics: vec![String::from("foo"), String::from("bar")],
+            user_data: Some(vec![1, 2]),
+            owned_partitions: [partitions("bar", &[1]), partitions("foo", &[0, 2])].concat(),
+            generation_id: Some(3),
+            rack_id: Some(String::from("a"))
+        };
+        assert_eq!(Subscription::decode(&subscription.encode().unwrap()).unwrap(), subscription);
+        let unjoined: Subscription = Subscription { generation_id: None, ..subscription };
+        assert_eq!(Subscription::decode(&unjoined.encode().unwrap()).unwrap(), unjoined);
+
+        let v0: Vec<u8> = encode_versioned(0, ConsumerProtocolSubscriptionV0 {
+            topics: Array(vec![String::from("foo")]),
+            user_data: NullableBytes(None)
+        }).unwrap();
+        assert_eq!(Subscription::decode(&v0).unwrap(), self::subscription(&["foo"]));
+
+        let assignment: Assignment = Assignment { partitions: partitions("foo", &[0, 1]), user_data: Some(vec![7]) };
+        assert_eq!(Assignment::decode(&assignment.encode().unwrap()).unwrap(), assignment);
+        assert_eq!(Assignment::decode(&[]).unwrap(), Assignment::default());
+    }
+
+    #[test]
+    fn test_range_assignment() {
+        let mut subscriptions: BTreeMap<String, Subscription> = BTreeMap::new();
+        subscriptions.insert(String::from("a"), subscription(&["foo", "bar"]));
+        subscriptions.insert(String::from("b"), subscription(&["foo"]));
+        subscriptions.insert(String::from("c"), subscription(&["foo"]));
+        let mut topic_partitions: BTreeMap<String, Vec<TopicPartition>> = BTreeMap::new();
+        topic_partitions.insert(String::from("foo"), partitions("foo", &[0, 1, 2, 3, 4]));
+        topic_partitions.insert(String::from("bar"), partitions("bar", &[0, 1]));
+
+        let assignment: BTreeMap<String, Assignment> = RangeAssignor.assign(&topic_partitions, &subscriptions);
+        assert_eq!(assignment["a"].partitions, [partitions("bar", &[0, 1]), partitions("foo", &[0, 1])].concat());
+        assert_eq!(assignment["b"].partitions, partitions("foo", &[2, 3]));
+        assert_eq!(assignment["c"].partitions, partitions("foo", &[4]));
+    }
+
+    #[test]
+    fn test_round_robin_assignment_skips_members_not_subscribed_to_a_topic() {
+        let mut subscriptions: BTreeMap<String, Subscription> = BTreeMap::new();
+        subscriptions.insert(String::from("a"), subscription(&["foo"]));
+        subscriptions.insert(String::from("b"), subscription(&["foo", "bar"]));
+        let mut topic_partitions: BTreeMap<String, Vec<TopicPartition>> = BTreeMap::new();
+        topic_partitions.insert(String::from("foo"), partitions("foo", &[0, 1, 2]));
+        topic_partitions.insert(String::from("bar"), partitions("bar", &[0, 1]));
+
+        let assignment: BTreeMap<String, Assignment> = RoundRobinAssignor.assign(&topic_partitions, &subscriptions);
+        assert_eq!(assignment["a"].partitions, partitions("foo", &[0, 2]));
+        assert_eq!(assignment["b"].partitions, [partitions("bar", &[0, 1]), partitions("foo", &[1])].concat());
+    }
+
+    #[test]
+    fn test_rebalance_protocol_is_cooperative_only_if_every_assignor_supports_it() {
+        let cooperative: Arc<dyn PartitionAssignor> = Arc::new(CooperativeStickyAssignor::new());
+        assert_eq!(rebalance_protocol(std::slice::from_ref(&cooperative)).unwrap(), RebalanceProtocol::Cooperative);
+        assert_eq!(rebalance_protocol(&[Arc::new(RangeAssignor), cooperative]).unwrap(), RebalanceProtocol::Eager);
+    }
+
+    #[test]
+    fn test_round_robin_assignment_is_balanced() {
+        let mut rng: StdRng = StdRng::seed_from_u64(46);
+        for _ in 0..200 {
+            let topic_partitions: BTreeMap<String, Vec<TopicPartition>> = random_topics(&mut rng, 4, 10);
+            let topics: Vec<&str> = topic_partitions.keys().map(String::as_str).collect();
+            let subscriptions: BTreeMap<String, Subscription> = (0..rng.gen_range(1..8))
+                .map(|member| (format!("member-{}", member), subscription(&topics)))
+                .collect();
+
+            let assignment: BTreeMap<String, Assignment> = RoundRobinAssignor.assign(&topic_partitions, &subscriptions);
+            let counts: Vec<usize> = check_valid(&subscriptions, &assignment);
+            let total: usize = topic_partitions.values().map(Vec::len).sum();
+            assert_eq!(counts.iter().sum::<usize>(), total);
+            assert!(counts.iter().max().unwrap() - counts.iter().min().unwrap() <= 1, "{:?}", counts);
+        }
+    }
+}
@@ -0,0 +1,1001 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use anyhow::anyhow;
+use kafka_encode::primitives::{CompactArray, CompactBytes, CompactNullableString, CompactString};
+use tracing::{debug, info, warn};
+use crate::cluster::{ClusterMetadata, TopicPartition};
+use crate::connection_pool::ConnectionPool;
+use crate::consumer::ConsumerConfig;
+use crate::consumer::assignor::{Assignment, PartitionAssignor, rebalance_protocol, RebalanceProtocol, Subscription};
+use crate::coordinator::{ConsumerGroupMetadata, Coordinator};
+use crate::metadata_cache::MetadataCache;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::find_coordinator::COORDINATOR_TYPE_GROUP;
+use crate::protocol::heartbeat::{HeartbeatRequestV4, HeartbeatResponseV4};
+use crate::protocol::join_group::{JoinGroupRequestProtocolV9, JoinGroupRequestV9, JoinGroupResponseMemberV9, JoinGroupResponseV9, PROTOCOL_TYPE_CONSUMER};
+use crate::protocol::leave_group::{LeaveGroupRequestMemberV5, LeaveGroupRequestV5, LeaveGroupResponseV5};
+use crate::protocol::sync_group::{SyncGroupRequestAssignmentV5, SyncGroupRequestV5, SyncGroupResponseV5};
+use crate::protocol::tags::TaggedFields;
+use crate::retry::RetryPolicy;
+
+/// How much longer than the rebalance timeout a JoinGroup request waits, since the coordinator
+/// holds on to it until every member has rejoined.
+const JOIN_GROUP_TIMEOUT_MARGIN: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum MemberState {
+    /// Not in the group, or removed from it.
+    Unjoined,
+    /// Joining or syncing with the group.
+    Rebalancing,
+    /// Holds an assignment for the current generation, and heartbeats to keep it.
+    Stable
+}
+
+/// How the coordinator hands partitions to the consumer and takes them away as its group
+/// rebalances. Each call happens on the thread which polls, before the partitions change hands.
+pub(crate) trait RebalanceHandler {
+    /// Takes partitions from the consumer, which still owns them.
+    fn revoke(&self, partitions: &[TopicPartition]);
+
+    /// Takes partitions from a consumer which fell out of its group, and may not own them any more.
+    fn lose(&self, partitions: &[TopicPartition]);
+
+    /// Gives the consumer its assignment for a new generation, of which `added` are new to it.
+    fn assign(&self, assignment: &[TopicPartition], added: &[TopicPartition]);
+}
+
+/// A consumer's membership of its group, under either group protocol. The consumer calls `poll`
+/// on the thread which polls, and `run_heartbeats` on a thread of its own.
+pub(crate) trait GroupMembership: Send + Sync + Debug {
+    /// Replaces the subscription, which the group takes up on the next `poll`.
+    fn subscribe(&self, topics: Vec<String>);
+
+    fn subscription(&self) -> Vec<String>;
+
+    fn group_metadata(&self) -> ConsumerGroupMetadata;
+
+    /// Joins the group if the member must, and hands the consumer its partitions through `handler`.
+    fn poll(&self, handler: &dyn RebalanceHandler) -> Result<()>;
+
+    /// Sends heartbeats while the member is in the group, until it is closed.
+    fn run_heartbeats(&self);
+
+    /// Gives up the member's partitions and leaves the group.
+    fn leave_group(&self, reason: &str, handler: &dyn RebalanceHandler);
+
+    /// Leaves the group and stops the heartbeat thread.
+    fn close(&self, handler: &dyn RebalanceHandler);
+}
+
+#[derive(Debug)]
+struct GroupState {
+    member_state: MemberState,
+    generation_id: i32,
+    /// Empty until the coordinator gives the member an id.
+    member_id: String,
+    /// The topics the consumer subscribed to.
+    subscription: Vec<String>,
+    /// The partitions the consumer was assigned and hasn't given up yet.
+    owned_partitions: Vec<TopicPartition>,
+    /// Set when the member must rejoin, such as after the coordinator reported a rebalance.
+    rejoin_needed: bool,
+    /// An error the heartbeat thread can't recover from, which every `poll` returns.
+    fatal_error: Option<ErrorCode>,
+    /// When the coordinator last accepted a heartbeat.
+    last_heartbeat: Instant,
+    next_heartbeat: Instant,
+    closed: bool
+}
+
+/// A consumer's membership of its group under the classic group protocol. `poll` joins and syncs
+/// with the group on the application thread, and a heartbeat thread keeps the membership alive
+/// between polls and notices when the group starts to rebalance.
+///
+/// Under the eager protocol the consumer revokes every partition before it rejoins. Under the
+/// cooperative protocol (KIP-429) it keeps them while it rejoins, revokes only the ones the leader
+/// moved to another member, and then rejoins so that the leader can assign those.
+#[derive(Debug)]
+pub(crate) struct ConsumerCoordinator {
+    group_id: String,
+    group_instance_id: Option<String>,
+    session_timeout: Duration,
+    rebalance_timeout: Duration,
+    heartbeat_interval: Duration,
+    default_api_timeout: Duration,
+    retry: RetryPolicy,
+    assignors: Vec<Arc<dyn PartitionAssignor>>,
+    protocol: RebalanceProtocol,
+    coordinator: Coordinator,
+    pool: Arc<ConnectionPool>,
+    metadata: Arc<MetadataCache>,
+    state: Mutex<GroupState>,
+    changed: Condvar
+}
+
+impl ConsumerCoordinator {
+    pub fn new(group_id: &str, config: &ConsumerConfig, pool: Arc<ConnectionPool>, metadata: Arc<MetadataCache>) -> Result<Self> {
+        Ok(ConsumerCoordinator {
+            group_id: String::from(group_id),
+            group_instance_id: config.group_instance_id.clone(),
+            session_timeout: config.session_timeout,
+            rebalance_timeout: config.max_poll_interval,
+            heartbeat_interval: config.heartbeat_interval,
+            default_api_timeout: config.default_api_timeout,
+            retry: config.retry.clone(),
+            assignors: config.partition_assignors.clone(),
+            protocol: rebalance_protocol(&config.partition_assignors)?,
+            coordinator: Coordinator::new(COORDINATOR_TYPE_GROUP, group_id),
+            pool,
+            metadata,
+            state: Mutex::new(GroupState {
+                member_state: MemberState::Unjoined,
+                generation_id: -1,
+                member_id: String::new(),
+                subscription: Vec::new(),
+                owned_partitions: Vec::new(),
+                rejoin_needed: false,
+                fatal_error: None,
+                last_heartbeat: Instant::now(),
+                next_heartbeat: Instant::now(),
+                closed: false
+            }),
+            changed: Condvar::new()
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, GroupState> {
+        self.state.lock().expect("Consumer coordinator lock was poisoned")
+    }
+
+    /// Joins and syncs with the group until the member has an assignment for a generation.
+    fn join_group(&self, handler: &dyn RebalanceHandler) -> Result<()> {
+        loop {
+            self.prepare_join(handler);
+            let (member_id, subscription, generation_id, owned_partitions): (String, Vec<String>, i32, Vec<TopicPartition>) = {
+                let mut state: MutexGuard<GroupState> = self.lock();
+                state.member_state = MemberState::Rebalancing;
+                state.rejoin_needed = false;
+                (state.member_id.clone(), state.subscription.clone(), state.generation_id, state.owned_partitions.clone())
+            };
+            debug!("Joining group {} as member {:?}", self.group_id, member_id);
+            let join: JoinGroupResponseV9 = self.send_join_group(member_id, subscription, generation_id, owned_partitions)?;
+            match join.error_code {
+                ErrorCode::None => {},
+                ErrorCode::MemberIdRequired => {
+                    debug!("Group {} requires member id {} to join", self.group_id, join.member_id.0);
+                    self.lock().member_id = join.member_id.0;
+                    continue;
+                },
+                ErrorCode::UnknownMemberId | ErrorCode::IllegalGeneration | ErrorCode::RebalanceInProgress => {
+                    self.reset_generation(&join.error_code);
+                    continue;
+                },
+                error_code => return Err(self.group_error(error_code, "join"))
+            }
+            {
+                let mut state: MutexGuard<GroupState> = self.lock();
+                state.generation_id = join.generation_id;
+                state.member_id = join.member_id.0.clone();
+            }
+            let assignor: &Arc<dyn PartitionAssignor> = self.assignor(&join.protocol_name)?;
+            let assignments: Vec<SyncGroupRequestAssignmentV5> = if join.leader.0 == join.member_id.0 && !join.skip_assignment {
+                self.assign(assignor.as_ref(), &join.members.0)?
+            } else {
+                Vec::new()
+            };
+
+            let sync: SyncGroupResponseV5 = self.send_sync_group(&join, assignments)?;
+            match sync.error_code {
+                ErrorCode::None => {},
+                ErrorCode::RebalanceInProgress => {
+                    debug!("Group {} started another rebalance before generation {} synced", self.group_id, join.generation_id);
+                    continue;
+                },
+                ErrorCode::UnknownMemberId | ErrorCode::IllegalGeneration => {
+                    self.reset_generation(&sync.error_code);
+                    continue;
+                },
+                error_code => return Err(self.group_error(error_code, "sync with"))
+            }
+            let assignment: Assignment = Assignment::decode(&sync.assignment.0)?;
+            assignor.on_assignment(&assignment, join.generation_id);
+            info!("Joined group {} in generation {} as member {} with partitions {:?}", self.group_id, join.generation_id, join.member_id.0, assignment.partitions);
+            self.complete_join(handler, assignment.partitions);
+            return Ok(());
+        }
+    }
+
+    /// Gives up the partitions the member can't keep while it rejoins: every partition under the
+    /// eager protocol, and those of topics it no longer subscribes to under the cooperative one.
+    /// A member which fell out of its generation has lost its partitions rather than revoked them.
+    fn prepare_join(&self, handler: &dyn RebalanceHandler) {
+        let (revoked, lost): (Vec<TopicPartition>, Vec<TopicPartition>) = {
+            let mut state: MutexGuard<GroupState> = self.lock();
+            let owned_partitions: Vec<TopicPartition> = std::mem::take(&mut state.owned_partitions);
+            if state.generation_id < 0 {
+                (Vec::new(), owned_partitions)
+            } else {
+                let subscription: &[String] = &state.subscription;
+                let (kept, revoked): (Vec<TopicPartition>, Vec<TopicPartition>) = owned_partitions.into_iter()
+                    .partition(|topic_partition| self.protocol == RebalanceProtocol::Cooperative && subscription.contains(&topic_partition.topic));
+                state.owned_partitions = kept;
+                (revoked, Vec::new())
+            }
+        };
+        if !revoked.is_empty() {
+            info!("Revoking partitions {:?} of group {} before rejoining", revoked, self.group_id);
+            handler.revoke(&revoked);
+        }
+        if !lost.is_empty() {
+            info!("Lost partitions {:?} of group {}", lost, self.group_id);
+            handler.lose(&lost);
+        }
+    }
+
+    /// Takes up the member's assignment for the generation it synced. Under the cooperative
+    /// protocol, partitions the member owns but wasn't assigned are revoked, and the member
+    /// rejoins so that the leader can give them to their new owners.
+    fn complete_join(&self, handler: &dyn RebalanceHandler, assignment: Vec<TopicPartition>) {
+        let (revoked, added): (Vec<TopicPartition>, Vec<TopicPartition>) = {
+            let mut state: MutexGuard<GroupState> = self.lock();
+            let owned_partitions: Vec<TopicPartition> = std::mem::replace(&mut state.owned_partitions, assignment.clone());
+            let revoked: Vec<TopicPartition> = owned_partitions.iter()
+                .filter(|topic_partition| !assignment.contains(topic_partition))
+                .cloned()
+                .collect();
+            let added: Vec<TopicPartition> = assignment.iter()
+                .filter(|topic_partition| !owned_partitions.contains(topic_partition))
+                .cloned()
+                .collect();
+            state.member_state = MemberState::Stable;
+            state.rejoin_needed = !revoked.is_empty();
+            state.last_heartbeat = Instant::now();
+            state.next_heartbeat = state.last_heartbeat + self.heartbeat_interval;
+            self.changed.notify_all();
+            (revoked, added)
+        };
+        if !revoked.is_empty() {
+            info!("Revoking partitions {:?} of group {}, which moved to other members", revoked, self.group_id);
+            handler.revoke(&revoked);
+        }
+        handler.assign(&assignment, &added);
+    }
+
+    /// Joins with one protocol for each assignor, in order of preference. Members only send the
+    /// partitions they own under the cooperative protocol.
+    fn send_join_group(&self, member_id: String, subscription: Vec<String>, generation_id: i32, owned_partitions: Vec<TopicPartition>) -> Result<JoinGroupResponseV9> {
+        let protocols: Vec<JoinGroupRequestProtocolV9> = self.assignors.iter()
+            .map(|assignor| {
+                let metadata: Vec<u8> = Subscription {
+                    topics: subscription.clone(),
+                    user_data: assignor.subscription_user_data(&subscription),
+                    owned_partitions: owned_partitions.clone(),
+                    generation_id: Some(generation_id).filter(|generation_id| *generation_id >= 0),
+                    rack_id: None
+                }.encode()?;
+                Ok(JoinGroupRequestProtocolV9 {
+                    name: CompactString(String::from(assignor.name())),
+                    metadata: CompactBytes(metadata),
+                    tag_buffer: TaggedFields::new()
+                })
+            })
+            .collect::<Result<Vec<JoinGroupRequestProtocolV9>>>()?;
+        let request: JoinGroupRequestV9 = JoinGroupRequestV9 {
+            group_id: CompactString(self.group_id.clone()),
+            session_timeout_ms: self.session_timeout.as_millis() as i32,
+            rebalance_timeout_ms: self.rebalance_timeout.as_millis() as i32,
+            member_id: CompactString(member_id),
+            group_instance_id: CompactNullableString(self.group_instance_id.clone()),
+            protocol_type: CompactString(String::from(PROTOCOL_TYPE_CONSUMER)),
+            protocols: CompactArray(protocols),
+            reason: CompactNullableString(None),
+            tag_buffer: TaggedFields::new()
+        };
+        let timeout: Duration = self.pool.request_timeout().max(self.rebalance_timeout + JOIN_GROUP_TIMEOUT_MARGIN);
+        self.coordinator.send_within(&self.pool, request, timeout, &self.retry, |response: &JoinGroupResponseV9| coordinator_error(&response.error_code))
+    }
+
+    fn send_sync_group(&self, join: &JoinGroupResponseV9, assignments: Vec<SyncGroupRequestAssignmentV5>) -> Result<SyncGroupResponseV5> {
+        let request: SyncGroupRequestV5 = SyncGroupRequestV5 {
+            group_id: CompactString(self.group_id.clone()),
+            generation_id: join.generation_id,
+            member_id: join.member_id.clone(),
+            group_instance_id: CompactNullableString(self.group_instance_id.clone()),
+            protocol_type: CompactNullableString(Some(String::from(PROTOCOL_TYPE_CONSUMER))),
+            protocol_name: join.protocol_name.clone(),
+            assignments: CompactArray(assignments),
+            tag_buffer: TaggedFields::new()
+        };
+        self.coordinator.send(&self.pool, request, &self.retry, |response: &SyncGroupResponseV5| coordinator_error(&response.error_code))
+    }
+
+    /// The assignor the coordinator chose, which every member supports.
+    fn assignor(&self, protocol_name: &CompactNullableString) -> Result<&Arc<dyn PartitionAssignor>> {
+        self.assignors.iter()
+            .find(|assignor| Some(assignor.name()) == protocol_name.0.as_deref())
+            .ok_or_else(|| anyhow!("Group {} chose assignor {:?}, which the consumer doesn't have", self.group_id, protocol_name.0))
+    }
+
+    /// Computes every member's assignment, as the group leader.
+    fn assign(&self, assignor: &dyn PartitionAssignor, members: &[JoinGroupResponseMemberV9]) -> Result<Vec<SyncGroupRequestAssignmentV5>> {
+        let mut subscriptions: BTreeMap<String, Subscription> = BTreeMap::new();
+        for member in members {
+            subscriptions.insert(member.member_id.0.clone(), Subscription::decode(&member.metadata.0)?);
+        }
+        let topics: BTreeSet<&String> = subscriptions.values().flat_map(|subscription| &subscription.topics).collect();
+        let cluster: Arc<ClusterMetadata> = self.cluster_with_topics(&topics);
+        let partitions: BTreeMap<String, Vec<TopicPartition>> = topics.into_iter()
+            .map(|topic| (topic.clone(), cluster.partitions_for_topic(topic)))
+            .collect();
+
+        debug!("Assigning the partitions of group {} with the {} assignor", self.group_id, assignor.name());
+        assignor.assign(&partitions, &subscriptions).into_iter()
+            .map(|(member_id, assignment)| Ok(SyncGroupRequestAssignmentV5 {
+                member_id: CompactString(member_id),
+                assignment: CompactBytes(assignment.encode()?),
+                tag_buffer: TaggedFields::new()
+            }))
+            .collect()
+    }
+
+    /// Metadata which includes the topics the group subscribes to, waiting for one refresh if
+    /// other members subscribe to topics this consumer doesn't know yet. Topics which don't exist
+    /// get no partitions.
+    fn cluster_with_topics(&self, topics: &BTreeSet<&String>) -> Arc<ClusterMetadata> {
+        let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        if topics.iter().all(|topic| cluster.topic(topic).is_some()) {
+            return cluster;
+        }
+        let version: u64 = self.metadata.version();
+        for topic in topics {
+            self.metadata.add_topic(topic);
+        }
+        self.metadata.request_update();
+        if !self.metadata.wait_for_update(version, self.default_api_timeout) {
+            warn!("Assigning the partitions of group {} without metadata for every subscribed topic", self.group_id);
+        }
+        self.metadata.cluster()
+    }
+
+    /// Forgets the generation after the coordinator rejected it. The member keeps its id unless
+    /// the coordinator no longer knows it.
+    fn reset_generation(&self, error_code: &ErrorCode) {
+        debug!("Rejoining group {} after {:?}", self.group_id, error_code);
+        let mut state: MutexGuard<GroupState> = self.lock();
+        state.generation_id = -1;
+        if *error_code == ErrorCode::UnknownMemberId {
+            state.member_id = String::new();
+        }
+    }
+
+    fn group_error(&self, error_code: ErrorCode, action: &str) -> anyhow::Error {
+        anyhow::Error::new(error_code).context(format!("Failed to {} group {}", action, self.group_id))
+    }
+
+    fn handle_heartbeat(&self, state: &mut GroupState, response: Result<HeartbeatResponseV4>) {
+        let now: Instant = Instant::now();
+        let error_code: ErrorCode = match response {
+            Ok(response) => response.error_code,
+            Err(e) => {
+                debug!("Failed to send a heartbeat to the coordinator of group {}: {}", self.group_id, e);
+                state.next_heartbeat = now + self.retry.retry_backoff;
+                if now.duration_since(state.last_heartbeat) > self.session_timeout {
+                    warn!("No heartbeat reached the coordinator of group {} within the session timeout, so the consumer must rejoin", self.group_id);
+                    state.rejoin_needed = true;
+                }
+                return;
+            }
+        };
+        match error_code {
+            ErrorCode::None => {
+                state.last_heartbeat = now;
+                state.next_heartbeat = now + self.heartbeat_interval;
+            },
+            ErrorCode::RebalanceInProgress => {
+                // keep heartbeating until the application polls and rejoins
+                debug!("Group {} is rebalancing", self.group_id);
+                state.rejoin_needed = true;
+                state.last_heartbeat = now;
+                state.next_heartbeat = now + self.heartbeat_interval;
+            },
+            ErrorCode::IllegalGeneration | ErrorCode::UnknownMemberId => {
+                debug!("The coordinator of group {} fenced generation {} after {:?}", self.group_id, state.generation_id, error_code);
+                state.member_state = MemberState::Unjoined;
+                state.generation_id = -1;
+                if error_code == ErrorCode::UnknownMemberId {
+                    state.member_id = String::new();
+                }
+                state.rejoin_needed = true;
+            },
+            ErrorCode::NotCoordinator | ErrorCode::CoordinatorNotAvailable | ErrorCode::CoordinatorLoadInProgress => {
+                self.coordinator.mark_unknown();
+                state.next_heartbeat = now + self.retry.retry_backoff;
+            },
+            error_code => {
+                warn!("The consumer left group {} after a heartbeat failed with {:?}", self.group_id, error_code);
+                state.member_state = MemberState::Unjoined;
+                state.fatal_error = Some(error_code);
+            }
+        }
+    }
+}
+
+impl GroupMembership for ConsumerCoordinator {
+    /// Replaces the subscription, and rejoins the group with it on the next `poll`.
+    fn subscribe(&self, topics: Vec<String>) {
+        let mut state: MutexGuard<GroupState> = self.lock();
+        state.subscription = topics;
+        state.rejoin_needed = true;
+    }
+
+    fn subscription(&self) -> Vec<String> {
+        self.lock().subscription.clone()
+    }
+
+    fn group_metadata(&self) -> ConsumerGroupMetadata {
+        let state: MutexGuard<GroupState> = self.lock();
+        ConsumerGroupMetadata {
+            group_id: self.group_id.clone(),
+            generation_id: state.generation_id,
+            member_id: state.member_id.clone(),
+            group_instance_id: self.group_instance_id.clone()
+        }
+    }
+
+    /// Joins the group if the member isn't in it or must rejoin, and hands the consumer its
+    /// partitions through `handler`.
+    fn poll(&self, handler: &dyn RebalanceHandler) -> Result<()> {
+        {
+            let mut state: MutexGuard<GroupState> = self.lock();
+            if let Some(error_code) = state.fatal_error.clone() {
+                let lost: Vec<TopicPartition> = std::mem::take(&mut state.owned_partitions);
+                drop(state);
+                if !lost.is_empty() {
+                    handler.lose(&lost);
+                }
+                return Err(anyhow::Error::new(error_code).context(format!("The consumer is no longer a member of group {}", self.group_id)));
+            }
+            let joined: bool = state.member_state == MemberState::Stable;
+            if state.subscription.is_empty() || (joined && !state.rejoin_needed) {
+                return Ok(());
+            }
+        }
+        let joined: Result<()> = self.join_group(handler);
+        if joined.is_err() {
+            // try again on the next poll
+            let mut state: MutexGuard<GroupState> = self.lock();
+            state.member_state = MemberState::Unjoined;
+            state.rejoin_needed = true;
+        }
+        joined
+    }
+
+    /// Sends heartbeats while the member holds an assignment, until the coordinator is closed.
+    fn run_heartbeats(&self) {
+        let mut state: MutexGuard<GroupState> = self.lock();
+        loop {
+            if state.closed {
+                return;
+            }
+            let now: Instant = Instant::now();
+            if state.member_state != MemberState::Stable {
+                state = self.changed.wait(state).expect("Consumer coordinator lock was poisoned");
+                continue;
+            }
+            if now < state.next_heartbeat {
+                let wait: Duration = state.next_heartbeat - now;
+                state = self.changed.wait_timeout(state, wait).expect("Consumer coordinator lock was poisoned").0;
+                continue;
+            }
+            let request: HeartbeatRequestV4 = HeartbeatRequestV4 {
+                group_id: CompactString(self.group_id.clone()),
+                generation_id: state.generation_id,
+                member_id: CompactString(state.member_id.clone()),
+                group_instance_id: CompactNullableString(self.group_instance_id.clone()),
+                tag_buffer: TaggedFields::new()
+            };
+            let generation_id: i32 = state.generation_id;
+            drop(state);
+            let response: Result<HeartbeatResponseV4> = self.coordinator.node_id(&self.pool)
+                .and_then(|node_id| self.pool.send(node_id, request))
+                .inspect_err(|_| self.coordinator.mark_unknown());
+            state = self.lock();
+            if state.member_state == MemberState::Stable && state.generation_id == generation_id {
+                self.handle_heartbeat(&mut state, response);
+            }
+        }
+    }
+
+    /// Gives up the member's partitions and leaves the group, so that it rebalances without
+    /// waiting for the session to time out. Static members stay, since they are expected to come
+    /// back with the same instance id.
+    ///
+    /// The partitions are revoked while the member is still in its generation, so that the
+    /// coordinator accepts the offsets committed as they are revoked.
+    fn leave_group(&self, reason: &str, handler: &dyn RebalanceHandler) {
+        let (generation_id, owned_partitions): (i32, Vec<TopicPartition>) = {
+            let mut state: MutexGuard<GroupState> = self.lock();
+            (state.generation_id, std::mem::take(&mut state.owned_partitions))
+        };
+        if !owned_partitions.is_empty() {
+            if generation_id < 0 {
+                handler.lose(&owned_partitions);
+            } else {
+                handler.revoke(&owned_partitions);
+            }
+        }
+        let member_id: String = {
+            let mut state: MutexGuard<GroupState> = self.lock();
+            state.generation_id = -1;
+            state.member_state = MemberState::Unjoined;
+            state.rejoin_needed = false;
+            std::mem::take(&mut state.member_id)
+        };
+        if member_id.is_empty() || self.group_instance_id.is_some() {
+            return;
+        }
+        info!("Member {} is leaving group {}: {}", member_id, self.group_id, reason);
+        let request: LeaveGroupRequestV5 = LeaveGroupRequestV5 {
+            group_id: CompactString(self.group_id.clone()),
+            members: CompactArray(vec![LeaveGroupRequestMemberV5 {
+                member_id: CompactString(member_id),
+                group_instance_id: CompactNullableString(None),
+                reason: CompactNullableString(Some(String::from(reason))),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        // a member which fails to leave is removed when its session times out
+        let response: Result<LeaveGroupResponseV5> = self.coordinator.node_id(&self.pool)
+            .and_then(|node_id| self.pool.send(node_id, request));
+        match response {
+            Ok(response) if response.error_code == ErrorCode::None => {},
+            Ok(response) => warn!("Failed to leave group {}: {:?}", self.group_id, response.error_code),
+            Err(e) => warn!("Failed to leave group {}: {}", self.group_id, e)
+        }
+    }
+
+    /// Leaves the group and stops the heartbeat thread.
+    fn close(&self, handler: &dyn RebalanceHandler) {
+        self.leave_group("the consumer is being closed", handler);
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Picks out the errors which `Coordinator::send` retries after looking the coordinator up again.
+/// Group errors are left for the membership to handle.
+fn coordinator_error(error_code: &ErrorCode) -> ErrorCode {
+    match error_code {
+        ErrorCode::NotCoordinator | ErrorCode::CoordinatorNotAvailable | ErrorCode::CoordinatorLoadInProgress => error_code.clone(),
+        _ => ErrorCode::None
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use kafka_encode::primitives::{Array, CompactArray, CompactBytes, CompactNullableString, CompactString, NullableBytes};
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::cluster::tests::mock_broker_cluster;
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use crate::consumer::ConsumerConfig;
+    use crate::consumer::assignor::{Assignment, Subscription};
+    use crate::consumer::consumer_coordinator::{ConsumerCoordinator, GroupMembership, RebalanceHandler};
+    use crate::consumer::sticky_assignor::CooperativeStickyAssignor;
+    use crate::coordinator::{ConsumerGroupMetadata, OffsetAndMetadata};
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::consumer_protocol::{ConsumerProtocolSubscriptionV0, encode_versioned};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::fetch::{FetchRequestV12, FetchResponseV12};
+    use crate::protocol::find_coordinator::{FindCoordinatorRequestV3, FindCoordinatorResponseV3};
+    use crate::protocol::heartbeat::{HeartbeatRequestV4, HeartbeatResponseV4};
+    use crate::protocol::join_group::{JoinGroupRequestV9, JoinGroupResponseMemberV9, JoinGroupResponseV9};
+    use crate::protocol::leave_group::{LeaveGroupRequestV5, LeaveGroupResponseV5};
+    use crate::protocol::list_offsets::{ListOffsetsRequestV6V7, ListOffsetsResponsePartitionV6V7, ListOffsetsResponseTopicV6V7, ListOffsetsResponseV6V7};
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::offset_commit::{OffsetCommitRequestV8, OffsetCommitResponsePartitionV8V9, OffsetCommitResponseTopicV8V9, OffsetCommitResponseV8V9};
+    use crate::protocol::offset_fetch::{OffsetFetchRequestV8, OffsetFetchResponseGroupV8V9, OffsetFetchResponsePartitionV6V9, OffsetFetchResponseTopicV6V9, OffsetFetchResponseV8V9};
+    use crate::protocol::sync_group::{SyncGroupRequestV5, SyncGroupResponseV5};
+    use crate::protocol::tags::TaggedFields;
+    use crate::retry::RetryPolicy;
+
+    /// A group coordinator's side of a group: the other members in it, the errors to answer
+    /// heartbeats and commits with, the offsets committed, and every request it has received.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockGroup {
+        pub other_members: Vec<(String, Vec<String>)>,
+        pub heartbeat_errors: VecDeque<ErrorCode>,
+        pub commit_errors: VecDeque<ErrorCode>,
+        pub committed: HashMap<TopicPartition, OffsetAndMetadata>,
+        pub joins: Vec<JoinGroupRequestV9>,
+        pub syncs: Vec<SyncGroupRequestV5>,
+        pub heartbeats: Vec<HeartbeatRequestV4>,
+        pub leaves: Vec<LeaveGroupRequestV5>
+    }
+
+    /// A broker which is node `node_id` and coordinates one group. A member joining without a
+    /// member id is given one and asked to rejoin, and the last member to join leads the group.
+    /// Each join starts a new generation, and syncs hand each member what the leader assigned it.
+    /// Commits succeed unless there is a commit error queued, and fetches find no records since
+    /// every partition is empty.
+    pub(crate) fn group_broker(node_id: i32, group: Arc<Mutex<MockGroup>>) -> MockBroker {
+        MockBroker::start(move |request: &MockRequest| {
+            if request.api_key == ApiKey::Fetch as i16 {
+                // wait as if `fetch.max.wait.ms` passed
+                thread::sleep(Duration::from_millis(10));
+                return Some(request.respond::<FetchRequestV12, FetchResponseV12>(FetchResponseV12 {
+                    throttle_time_ms: 0,
+                    error_code: ErrorCode::None,
+                    session_id: 0,
+                    responses: CompactArray(vec![]),
+                    tag_buffer: TaggedFields::new()
+                }));
+            }
+            if request.api_key == ApiKey::ListOffsets as i16 {
+                let list_offsets: ListOffsetsRequestV6V7 = request.decode().unwrap();
+                return Some(request.respond::<ListOffsetsRequestV6V7, ListOffsetsResponseV6V7>(ListOffsetsResponseV6V7 {
+                    throttle_time_ms: 0,
+                    topics: CompactArray(list_offsets.topics.0.into_iter()
+                        .map(|topic| ListOffsetsResponseTopicV6V7 {
+                            name: topic.name,
+                            partitions: CompactArray(topic.partitions.0.iter()
+                                .map(|partition| ListOffsetsResponsePartitionV6V7 {
+                                    partition_index: partition.partition_index,
+                                    error_code: ErrorCode::None,
+                                    timestamp: -1,
+                                    offset: 0,
+                                    leader_epoch: 0,
+                                    tag_buffer: TaggedFields::new()
+                                })
+                                .collect()),
+                            tag_buffer: TaggedFields::new()
+                        })
+                        .collect()),
+                    tag_buffer: TaggedFields::new()
+                }));
+            }
+            let mut group: MutexGuard<MockGroup> = group.lock().unwrap();
+            match request.api_key {
+                key if key == ApiKey::FindCoordinator as i16 => Some(request.respond::<FindCoordinatorRequestV3, FindCoordinatorResponseV3>(FindCoordinatorResponseV3 {
+                    throttle_time_ms: 0,
+                    error_code: ErrorCode::None,
+                    error_message: CompactNullableString(None),
+                    node_id,
+                    host: CompactString(String::from("127.0.0.1")),
+                    port: 0,
+                    tag_buffer: TaggedFields::new()
+                })),
+                key if key == ApiKey::JoinGroup as i16 => {
+                    let join: JoinGroupRequestV9 = request.decode().unwrap();
+                    group.joins.push(join.clone());
+                    let generation_id: i32 = group.joins.len() as i32;
+                    let (error_code, member_id): (ErrorCode, String) = match join.member_id.0.as_str() {
+                        "" => (ErrorCode::MemberIdRequired, format!("member-{}", generation_id)),
+                        member_id => (ErrorCode::None, String::from(member_id))
+                    };
+                    let mut members: Vec<JoinGroupResponseMemberV9> = group.other_members.iter()
+                        .map(|(member_id, topics)| JoinGroupResponseMemberV9 {
+                            member_id: CompactString(member_id.clone()),
+                            group_instance_id: CompactNullableString(None),
+                            metadata: CompactBytes(encode_versioned(0, ConsumerProtocolSubscriptionV0 {
+                                topics: Array(topics.clone()),
+                                user_data: NullableBytes(None)
+                            }).unwrap()),
+                            tag_buffer: TaggedFields::new()
+                        })
+                        .collect();
+                    members.push(JoinGroupResponseMemberV9 {
+                        member_id: CompactString(member_id.clone()),
+                        group_instance_id: join.group_instance_id.clone(),
+                        metadata: join.protocols.0[0].metadata.clone(),
+                        tag_buffer: TaggedFields::new()
+                    });
+                    Some(request.respond::<JoinGroupRequestV9, JoinGroupResponseV9>(JoinGroupResponseV9 {
+                        throttle_time_ms: 0,
+                        error_code,
+                        generation_id,
+                        protocol_type: CompactNullableString(Some(join.protocol_type.0.clone())),
+                        protocol_name: CompactNullableString(Some(join.protocols.0[0].name.0.clone())),
+                        leader: CompactString(member_id.clone()),
+                        skip_assignment: false,
+                        member_id: CompactString(member_id),
+                        members: CompactArray(members),
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                key if key == ApiKey::SyncGroup as i16 => {
+                    let sync: SyncGroupRequestV5 = request.decode().unwrap();
+                    group.syncs.push(sync.clone());
+                    let assignment: Vec<u8> = sync.assignments.0.iter()
+                        .find(|assignment| assignment.member_id == sync.member_id)
+                        .map(|assignment| assignment.assignment.0.clone())
+                        .unwrap_or_default();
+                    Some(request.respond::<SyncGroupRequestV5, SyncGroupResponseV5>(SyncGroupResponseV5 {
+                        throttle_time_ms: 0,
+                        error_code: ErrorCode::None,
+                        protocol_type: sync.protocol_type.clone(),
+                        protocol_name: sync.protocol_name.clone(),
+                        assignment: CompactBytes(assignment),
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                key if key == ApiKey::Heartbeat as i16 => {
+                    group.heartbeats.push(request.decode().unwrap());
+                    let error_code: ErrorCode = group.heartbeat_errors.pop_front().unwrap_or(ErrorCode::None);
+                    Some(request.respond::<HeartbeatRequestV4, HeartbeatResponseV4>(HeartbeatResponseV4 {
+                        throttle_time_ms: 0,
+                        error_code,
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                key if key == ApiKey::LeaveGroup as i16 => {
+                    group.leaves.push(request.decode().unwrap());
+                    Some(request.respond::<LeaveGroupRequestV5, LeaveGroupResponseV5>(LeaveGroupResponseV5 {
+                        throttle_time_ms: 0,
+                        error_code: ErrorCode::None,
+                        members: CompactArray(vec![]),
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                key if key == ApiKey::OffsetCommit as i16 => {
+                    let commit: OffsetCommitRequestV8 = request.decode().unwrap();
+                    let error_code: ErrorCode = group.commit_errors.pop_front().unwrap_or(ErrorCode::None);
+                    let topics: Vec<OffsetCommitResponseTopicV8V9> = commit.topics.0.into_iter()
+                        .map(|topic| OffsetCommitResponseTopicV8V9 {
+                            partitions: CompactArray(topic.partitions.0.into_iter()
+                                .map(|partition| {
+                                    if error_code == ErrorCode::None {
+                                        group.committed.insert(TopicPartition::new(&topic.name.0, partition.partition_index), OffsetAndMetadata {
+                                            offset: partition.committed_offset,
+                                            leader_epoch: Some(partition.committed_leader_epoch).filter(|epoch| *epoch >= 0),
+                                            metadata: partition.committed_metadata.0.unwrap_or_default()
+                                        });
+                                    }
+                                    OffsetCommitResponsePartitionV8V9 {
+                                        partition_index: partition.partition_index,
+                                        error_code: error_code.clone(),
+                                        tag_buffer: TaggedFields::new()
+                                    }
+                                })
+                                .collect()),
+                            name: topic.name,
+                            tag_buffer: TaggedFields::new()
+                        })
+                        .collect();
+                    Some(request.respond::<OffsetCommitRequestV8, OffsetCommitResponseV8V9>(OffsetCommitResponseV8V9 {
+                        throttle_time_ms: 0,
+                        topics: CompactArray(topics),
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                key if key == ApiKey::OffsetFetch as i16 => {
+                    let fetch: OffsetFetchRequestV8 = request.decode().unwrap();
+                    let groups: Vec<OffsetFetchResponseGroupV8V9> = fetch.groups.0.into_iter()
+                        .map(|fetch_group| OffsetFetchResponseGroupV8V9 {
+                            group_id: fetch_group.group_id,
+                            topics: CompactArray(fetch_group.topics.0.unwrap_or_default().into_iter()
+                                .map(|topic| OffsetFetchResponseTopicV6V9 {
+                                    partitions: CompactArray(topic.partition_indexes.0.iter()
+                                        .map(|partition_index| {
+                                            let committed: Option<&OffsetAndMetadata> = group.committed.get(&TopicPartition::new(&topic.name.0, *partition_index));
+                                            OffsetFetchResponsePartitionV6V9 {
+                                                partition_index: *partition_index,
+                                                committed_offset: committed.map_or(-1, |committed| committed.offset),
+                                                committed_leader_epoch: committed.and_then(|committed| committed.leader_epoch).unwrap_or(-1),
+                                                metadata: CompactNullableString(committed.map(|committed| committed.metadata.clone())),
+                                                error_code: ErrorCode::None,
+                                                tag_buffer: TaggedFields::new()
+                                            }
+                                        })
+                                        .collect()),
+                                    name: topic.name,
+                                    tag_buffer: TaggedFields::new()
+                                })
+                                .collect()),
+                            error_code: ErrorCode::None,
+                            tag_buffer: TaggedFields::new()
+                        })
+                        .collect();
+                    Some(request.respond::<OffsetFetchRequestV8, OffsetFetchResponseV8V9>(OffsetFetchResponseV8V9 {
+                        throttle_time_ms: 0,
+                        groups: CompactArray(groups),
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                _ => None
+            }
+        })
+    }
+
+    /// A cluster of one broker, which leads every partition of topic "foo".
+    pub(crate) fn group_cluster(broker: &MockBroker, partitions: i32) -> ClusterMetadata {
+        mock_broker_cluster("foo", &[(1, broker)], &vec![1; partitions as usize])
+    }
+
+    fn config() -> ConsumerConfig {
+        ConsumerConfig {
+            group_id: Some(String::from("group")),
+            heartbeat_interval: Duration::from_millis(10),
+            retry: RetryPolicy { retry_backoff: Duration::from_millis(10), ..RetryPolicy::default() },
+            ..ConsumerConfig::new("unused:9092")
+        }
+    }
+
+    fn coordinator(cluster: ClusterMetadata, config: &ConsumerConfig) -> Arc<ConsumerCoordinator> {
+        let metadata: Arc<MetadataCache> = Arc::new(MetadataCache::new(Box::new(StaticFetcher(cluster.clone())), MetadataCacheConfig::default()));
+        metadata.update(cluster);
+        let pool: Arc<ConnectionPool> = Arc::new(ConnectionPool::new(metadata.clone(), ConnectionPoolConfig::default()));
+        Arc::new(ConsumerCoordinator::new("group", config, pool, metadata).unwrap())
+    }
+
+    pub(crate) type Event = (&'static str, Vec<TopicPartition>);
+
+    /// Records what the coordinator hands the consumer. Assignments are recorded by the
+    /// partitions they add.
+    #[derive(Debug, Default)]
+    pub(crate) struct RecordingHandler {
+        events: Mutex<Vec<Event>>
+    }
+
+    impl RecordingHandler {
+        pub fn take(&self) -> Vec<Event> {
+            std::mem::take(&mut *self.events.lock().unwrap())
+        }
+    }
+
+    impl RebalanceHandler for RecordingHandler {
+        fn revoke(&self, partitions: &[TopicPartition]) {
+            self.events.lock().unwrap().push(("revoked", partitions.to_vec()));
+        }
+
+        fn lose(&self, partitions: &[TopicPartition]) {
+            self.events.lock().unwrap().push(("lost", partitions.to_vec()));
+        }
+
+        fn assign(&self, _assignment: &[TopicPartition], added: &[TopicPartition]) {
+            self.events.lock().unwrap().push(("assigned", added.to_vec()));
+        }
+    }
+
+    /// Waits until the group has received `count` heartbeats.
+    fn wait_for_heartbeats(group: &Mutex<MockGroup>, count: usize) {
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        while group.lock().unwrap().heartbeats.len() < count && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn partitions(topic: &str, partitions: &[i32]) -> Vec<TopicPartition> {
+        partitions.iter().map(|partition| TopicPartition::new(topic, *partition)).collect()
+    }
+
+    #[test]
+    fn test_leader_joins_with_the_required_member_id_and_assigns_every_member() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup {
+            other_members: vec![(String::from("member-0"), vec![String::from("foo")])],
+            ..MockGroup::default()
+        }));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let coordinator: Arc<ConsumerCoordinator> = coordinator(group_cluster(&broker, 3), &config());
+        let handler: RecordingHandler = RecordingHandler::default();
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![]);
+        coordinator.subscribe(vec![String::from("foo")]);
+
+        coordinator.poll(&handler).unwrap();
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("assigned", partitions("foo", &[2]))]);
+        let group: MutexGuard<MockGroup> = group.lock().unwrap();
+        let member_ids: Vec<&str> = group.joins.iter().map(|join| join.member_id.0.as_str()).collect();
+        assert_eq!(member_ids, vec!["", "member-1"]);
+        assert_eq!((group.syncs.len(), group.syncs[0].generation_id, group.syncs[0].assignments.0.len()), (1, 2, 2));
+        let metadata: ConsumerGroupMetadata = coordinator.group_metadata();
+        assert_eq!((metadata.generation_id, metadata.member_id.as_str()), (2, "member-1"));
+    }
+
+    #[test]
+    fn test_heartbeat_errors_make_the_member_rejoin() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup {
+            heartbeat_errors: VecDeque::from([ErrorCode::None, ErrorCode::RebalanceInProgress]),
+            ..MockGroup::default()
+        }));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let coordinator: Arc<ConsumerCoordinator> = coordinator(group_cluster(&broker, 2), &config());
+        let heartbeat_coordinator: Arc<ConsumerCoordinator> = coordinator.clone();
+        let heartbeat_thread: thread::JoinHandle<()> = thread::spawn(move || heartbeat_coordinator.run_heartbeats());
+        let handler: RecordingHandler = RecordingHandler::default();
+        coordinator.subscribe(vec![String::from("foo")]);
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("assigned", partitions("foo", &[0, 1]))]);
+
+        // a rebalance keeps the member id, and the eager protocol revokes every partition first
+        wait_for_heartbeats(&group, 2);
+        thread::sleep(Duration::from_millis(20));
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("revoked", partitions("foo", &[0, 1])), ("assigned", partitions("foo", &[0, 1]))]);
+        assert_eq!(group.lock().unwrap().joins.last().unwrap().member_id.0, "member-1");
+
+        // an unknown member has lost its partitions, and rejoins from scratch
+        group.lock().unwrap().heartbeat_errors.push_back(ErrorCode::UnknownMemberId);
+        let heartbeats: usize = group.lock().unwrap().heartbeats.len();
+        wait_for_heartbeats(&group, heartbeats + 1);
+        thread::sleep(Duration::from_millis(20));
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("lost", partitions("foo", &[0, 1])), ("assigned", partitions("foo", &[0, 1]))]);
+        let member_ids: Vec<String> = group.lock().unwrap().joins.iter().map(|join| join.member_id.0.clone()).collect();
+        assert_eq!(member_ids[3..], [String::new(), String::from("member-4")]);
+
+        // a fenced static member can't rejoin
+        group.lock().unwrap().heartbeat_errors.push_back(ErrorCode::FencedInstanceId);
+        let heartbeats: usize = group.lock().unwrap().heartbeats.len();
+        wait_for_heartbeats(&group, heartbeats + 1);
+        thread::sleep(Duration::from_millis(20));
+        let error: anyhow::Error = coordinator.poll(&handler).unwrap_err();
+        assert_eq!(error.downcast_ref::<ErrorCode>(), Some(&ErrorCode::FencedInstanceId));
+        assert_eq!(handler.take(), vec![("lost", partitions("foo", &[0, 1]))]);
+
+        coordinator.close(&handler);
+        heartbeat_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_leaves_the_group_unless_the_member_is_static() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let dynamic: Arc<ConsumerCoordinator> = coordinator(group_cluster(&broker, 1), &config());
+        let handler: RecordingHandler = RecordingHandler::default();
+        dynamic.subscribe(vec![String::from("foo")]);
+        dynamic.poll(&handler).unwrap();
+        dynamic.close(&handler);
+        assert_eq!(handler.take(), vec![("assigned", partitions("foo", &[0])), ("revoked", partitions("foo", &[0]))]);
+        let leaves: Vec<LeaveGroupRequestV5> = group.lock().unwrap().leaves.clone();
+        assert_eq!(leaves.len(), 1);
+        assert_eq!(leaves[0].members.0[0].member_id.0, "member-1");
+
+        let static_config: ConsumerConfig = ConsumerConfig { group_instance_id: Some(String::from("instance")), ..config() };
+        let static_member: Arc<ConsumerCoordinator> = coordinator(group_cluster(&broker, 1), &static_config);
+        static_member.subscribe(vec![String::from("foo")]);
+        static_member.poll(&handler).unwrap();
+        static_member.close(&handler);
+        assert_eq!(group.lock().unwrap().leaves.len(), 1);
+        assert_eq!(group.lock().unwrap().joins.last().unwrap().group_instance_id.0.as_deref(), Some("instance"));
+    }
+
+    #[test]
+    fn test_cooperative_rebalance_revokes_only_the_partitions_which_move() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let config: ConsumerConfig = ConsumerConfig { partition_assignors: vec![Arc::new(CooperativeStickyAssignor::new())], ..config() };
+        let coordinator: Arc<ConsumerCoordinator> = coordinator(group_cluster(&broker, 4), &config);
+        let handler: RecordingHandler = RecordingHandler::default();
+        coordinator.subscribe(vec![String::from("foo")]);
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("assigned", partitions("foo", &[0, 1, 2, 3]))]);
+
+        // the member keeps its partitions while it rejoins, and gives up the ones moving to the new member
+        group.lock().unwrap().other_members.push((String::from("member-0"), vec![String::from("foo")]));
+        coordinator.subscribe(vec![String::from("foo")]);
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("revoked", partitions("foo", &[2, 3])), ("assigned", vec![])]);
+        let subscription: Subscription = Subscription::decode(&group.lock().unwrap().joins.last().unwrap().protocols.0[0].metadata.0).unwrap();
+        assert_eq!((subscription.owned_partitions, subscription.generation_id), (partitions("foo", &[0, 1, 2, 3]), Some(2)));
+
+        // then rejoins, so that the leader can assign the revoked partitions
+        coordinator.poll(&handler).unwrap();
+        assert_eq!(handler.take(), vec![("assigned", vec![])]);
+        let group: MutexGuard<MockGroup> = group.lock().unwrap();
+        let sync: &SyncGroupRequestV5 = group.syncs.last().unwrap();
+        let other: Assignment = Assignment::decode(&sync.assignments.0.iter().find(|assignment| assignment.member_id.0 == "member-0").unwrap().assignment.0).unwrap();
+        assert_eq!(other.partitions, partitions("foo", &[2, 3]));
+    }
+}
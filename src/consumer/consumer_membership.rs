@@ -0,0 +1,657 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use kafka_encode::primitives::{CompactArray, CompactNullableArray, CompactNullableString, CompactString};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use crate::cluster::{ClusterMetadata, TopicPartition};
+use crate::connection_pool::ConnectionPool;
+use crate::consumer::ConsumerConfig;
+use crate::consumer::consumer_coordinator::{GroupMembership, RebalanceHandler};
+use crate::coordinator::{ConsumerGroupMetadata, Coordinator};
+use crate::metadata_cache::MetadataCache;
+use crate::protocol::consumer_group_heartbeat::{ConsumerGroupHeartbeatRequestV0, ConsumerGroupHeartbeatResponseV0, ConsumerGroupHeartbeatTopicPartitionsV0};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::find_coordinator::COORDINATOR_TYPE_GROUP;
+use crate::protocol::tags::TaggedFields;
+use crate::retry::RetryPolicy;
+
+/// The member epoch a member joins with.
+const JOIN_EPOCH: i32 = 0;
+/// The member epoch a dynamic member leaves with.
+const LEAVE_EPOCH: i32 = -1;
+/// The member epoch a static member leaves with, which keeps its partitions for it until its
+/// session times out.
+const STATIC_LEAVE_EPOCH: i32 = -2;
+
+#[derive(Debug)]
+struct MembershipState {
+    /// Empty until the coordinator gives the member an id.
+    member_id: String,
+    /// `JOIN_EPOCH` until the coordinator accepts the member.
+    member_epoch: i32,
+    /// The topics the consumer subscribed to.
+    subscription: Vec<String>,
+    /// Set by the first `poll` after subscribing, so that the member joins when the application
+    /// polls, and cleared when it leaves.
+    active: bool,
+    /// Set when the next heartbeat must send every field, such as when joining or after a heartbeat
+    /// failed, rather than only those which changed.
+    send_full: bool,
+    /// The partitions of each topic id the coordinator last assigned, until `poll` takes them up.
+    target_assignment: Option<BTreeMap<Uuid, Vec<i32>>>,
+    /// The partitions the consumer took up and hasn't given up yet.
+    owned_partitions: Vec<TopicPartition>,
+    /// Set when the consumer took up a new assignment, which the next heartbeat reports.
+    acknowledge: bool,
+    /// Set when the coordinator fenced the member, which loses its partitions on the next `poll`
+    /// before it rejoins.
+    fenced: bool,
+    /// An error the heartbeat thread can't recover from, which every `poll` returns.
+    fatal_error: Option<ErrorCode>,
+    /// How often the coordinator asked the member to heartbeat.
+    heartbeat_interval: Duration,
+    next_heartbeat: Instant,
+    closed: bool
+}
+
+/// A consumer's membership of its group under the consumer group protocol (KIP-848). The group
+/// coordinator assigns the partitions, so no assignor runs on the client. A heartbeat thread sends
+/// ConsumerGroupHeartbeat requests, which join the group and bring back the member's target
+/// assignment, and `poll` reconciles the consumer with it on the application thread: it revokes
+/// the partitions the coordinator took away, takes up the ones it added, and the next heartbeat
+/// acknowledges the new assignment.
+#[derive(Debug)]
+pub(crate) struct ConsumerMembership {
+    group_id: String,
+    group_instance_id: Option<String>,
+    rebalance_timeout: Duration,
+    server_assignor: Option<String>,
+    default_api_timeout: Duration,
+    retry: RetryPolicy,
+    coordinator: Coordinator,
+    pool: Arc<ConnectionPool>,
+    metadata: Arc<MetadataCache>,
+    state: Mutex<MembershipState>,
+    changed: Condvar
+}
+
+impl ConsumerMembership {
+    pub fn new(group_id: &str, config: &ConsumerConfig, pool: Arc<ConnectionPool>, metadata: Arc<MetadataCache>) -> Self {
+        ConsumerMembership {
+            group_id: String::from(group_id),
+            group_instance_id: config.group_instance_id.clone(),
+            rebalance_timeout: config.max_poll_interval,
+            server_assignor: config.group_remote_assignor.clone(),
+            default_api_timeout: config.default_api_timeout,
+            retry: config.retry.clone(),
+            coordinator: Coordinator::new(COORDINATOR_TYPE_GROUP, group_id),
+            pool,
+            metadata,
+            state: Mutex::new(MembershipState {
+                member_id: String::new(),
+                member_epoch: JOIN_EPOCH,
+                subscription: Vec::new(),
+                active: false,
+                send_full: true,
+                target_assignment: None,
+                owned_partitions: Vec::new(),
+                acknowledge: false,
+                fenced: false,
+                fatal_error: None,
+                heartbeat_interval: config.heartbeat_interval,
+                next_heartbeat: Instant::now(),
+                closed: false
+            }),
+            changed: Condvar::new()
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MembershipState> {
+        self.state.lock().expect("Consumer membership lock was poisoned")
+    }
+
+    /// The partitions of a target assignment, or `None` if the metadata doesn't name every topic
+    /// in it yet. Waits for one metadata refresh if it must.
+    fn resolve(&self, target: &BTreeMap<Uuid, Vec<i32>>) -> Option<Vec<TopicPartition>> {
+        let mut cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        if !target.keys().all(|topic_id| cluster.topic_by_id(topic_id).is_some()) {
+            debug!("Waiting for metadata to name the topics assigned to the consumer by group {}", self.group_id);
+            let version: u64 = self.metadata.version();
+            self.metadata.request_update();
+            self.metadata.wait_for_update(version, self.default_api_timeout);
+            cluster = self.metadata.cluster();
+        }
+        let mut assignment: Vec<TopicPartition> = Vec::new();
+        for (topic_id, partitions) in target {
+            let topic: String = cluster.topic_by_id(topic_id)?.name.clone();
+            assignment.extend(partitions.iter().map(|partition| TopicPartition::new(&topic, *partition)));
+        }
+        assignment.sort();
+        Some(assignment)
+    }
+
+    /// Moves the consumer to its target assignment: revokes the partitions the coordinator took
+    /// away, then takes up the new assignment for the next heartbeat to acknowledge.
+    fn reconcile(&self, handler: &dyn RebalanceHandler, target: BTreeMap<Uuid, Vec<i32>>) {
+        let Some(assignment) = self.resolve(&target) else {
+            // try again on the next poll, unless a newer assignment has arrived
+            self.lock().target_assignment.get_or_insert(target);
+            return;
+        };
+        let owned_partitions: Vec<TopicPartition> = self.lock().owned_partitions.clone();
+        let revoked: Vec<TopicPartition> = owned_partitions.iter()
+            .filter(|topic_partition| !assignment.contains(topic_partition))
+            .cloned()
+            .collect();
+        let added: Vec<TopicPartition> = assignment.iter()
+            .filter(|topic_partition| !owned_partitions.contains(topic_partition))
+            .cloned()
+            .collect();
+        if !revoked.is_empty() {
+            info!("Revoking partitions {:?} of group {}, which the coordinator took away", revoked, self.group_id);
+            handler.revoke(&revoked);
+        }
+        {
+            let mut state: MutexGuard<MembershipState> = self.lock();
+            state.owned_partitions = assignment.clone();
+            state.acknowledge = true;
+            state.next_heartbeat = Instant::now();
+            self.changed.notify_all();
+        }
+        info!("Took up partitions {:?} of group {} in epoch {}", assignment, self.group_id, self.lock().member_epoch);
+        handler.assign(&assignment, &added);
+    }
+
+    /// The next heartbeat, which only sends the fields which changed unless it must send every one.
+    /// `reported` are the partitions it tells the coordinator the member owns, if any.
+    fn heartbeat_request(&self, state: &MembershipState, reported: Option<&[TopicPartition]>) -> ConsumerGroupHeartbeatRequestV0 {
+        let full: bool = state.send_full;
+        ConsumerGroupHeartbeatRequestV0 {
+            group_id: CompactString(self.group_id.clone()),
+            member_id: CompactString(state.member_id.clone()),
+            member_epoch: state.member_epoch,
+            instance_id: CompactNullableString(self.group_instance_id.clone().filter(|_| full)),
+            rack_id: CompactNullableString(None),
+            rebalance_timeout_ms: if full { self.rebalance_timeout.as_millis() as i32 } else { -1 },
+            subscribed_topic_names: CompactNullableArray(full.then(|| state.subscription.iter().map(|topic| CompactString(topic.clone())).collect())),
+            server_assignor: CompactNullableString(self.server_assignor.clone().filter(|_| full)),
+            topic_partitions: CompactNullableArray(reported.map(|partitions| self.topic_partitions(partitions))),
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    /// Groups partitions by topic id. Topics the metadata has no id for are left out.
+    fn topic_partitions(&self, partitions: &[TopicPartition]) -> Vec<ConsumerGroupHeartbeatTopicPartitionsV0> {
+        let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        let mut by_topic: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+        for topic_partition in partitions {
+            by_topic.entry(&topic_partition.topic).or_default().push(topic_partition.partition);
+        }
+        by_topic.into_iter()
+            .filter_map(|(topic, partitions)| Some(ConsumerGroupHeartbeatTopicPartitionsV0 {
+                topic_id: cluster.topic(topic)?.topic_id?,
+                partitions: CompactArray(partitions),
+                tag_buffer: TaggedFields::new()
+            }))
+            .collect()
+    }
+
+    fn handle_heartbeat(&self, state: &mut MembershipState, response: Result<ConsumerGroupHeartbeatResponseV0>, reported: Option<Vec<TopicPartition>>) {
+        let now: Instant = Instant::now();
+        let response: ConsumerGroupHeartbeatResponseV0 = match response {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Failed to send a heartbeat to the coordinator of group {}: {}", self.group_id, e);
+                state.send_full = true;
+                state.next_heartbeat = now + self.retry.retry_backoff;
+                return;
+            }
+        };
+        match response.error_code {
+            ErrorCode::None => {
+                if let Some(member_id) = response.member_id.0 {
+                    state.member_id = member_id;
+                }
+                if state.member_epoch != response.member_epoch {
+                    debug!("Member {} of group {} moved to epoch {}", state.member_id, self.group_id, response.member_epoch);
+                }
+                state.member_epoch = response.member_epoch;
+                state.heartbeat_interval = Duration::from_millis(response.heartbeat_interval_ms.max(0) as u64);
+                state.send_full = false;
+                if reported.as_ref() == Some(&state.owned_partitions) {
+                    state.acknowledge = false;
+                }
+                if let Some(assignment) = response.assignment.0 {
+                    state.target_assignment = Some(assignment.topic_partitions.0.into_iter()
+                        .map(|topic_partitions| (topic_partitions.topic_id, topic_partitions.partitions.0))
+                        .collect());
+                }
+                state.next_heartbeat = now + state.heartbeat_interval;
+            },
+            ErrorCode::FencedMemberEpoch | ErrorCode::UnknownMemberId => {
+                warn!("The coordinator of group {} fenced member {} in epoch {} after {:?}, so it must rejoin", self.group_id, state.member_id, state.member_epoch, response.error_code);
+                state.member_epoch = JOIN_EPOCH;
+                if response.error_code == ErrorCode::UnknownMemberId {
+                    state.member_id = String::new();
+                }
+                state.fenced = true;
+                state.target_assignment = None;
+                state.acknowledge = false;
+                state.send_full = true;
+            },
+            ErrorCode::NotCoordinator | ErrorCode::CoordinatorNotAvailable | ErrorCode::CoordinatorLoadInProgress => {
+                self.coordinator.mark_unknown();
+                state.next_heartbeat = now + self.retry.retry_backoff;
+            },
+            error_code @ (ErrorCode::UnreleasedInstanceId | ErrorCode::UnsupportedAssignor | ErrorCode::GroupMaxSizeReached
+                | ErrorCode::GroupAuthorizationFailed | ErrorCode::InvalidRequest | ErrorCode::UnsupportedVersion | ErrorCode::FencedInstanceId) => {
+                warn!("The consumer left group {} after a heartbeat failed with {:?}: {:?}", self.group_id, error_code, response.error_message.0);
+                state.fatal_error = Some(error_code);
+            },
+            error_code => {
+                debug!("A heartbeat to the coordinator of group {} failed with {:?}: {:?}", self.group_id, error_code, response.error_message.0);
+                state.send_full = true;
+                state.next_heartbeat = now + self.retry.retry_backoff;
+            }
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl GroupMembership for ConsumerMembership {
+    /// Replaces the subscription, which the next heartbeat sends.
+    fn subscribe(&self, topics: Vec<String>) {
+        let mut state: MutexGuard<MembershipState> = self.lock();
+        state.subscription = topics;
+        state.send_full = true;
+        state.next_heartbeat = Instant::now();
+        self.changed.notify_all();
+    }
+
+    fn subscription(&self) -> Vec<String> {
+        self.lock().subscription.clone()
+    }
+
+    /// The member epoch stands in for the generation, which the coordinator fences offset commits
+    /// with in the same way.
+    fn group_metadata(&self) -> ConsumerGroupMetadata {
+        let state: MutexGuard<MembershipState> = self.lock();
+        ConsumerGroupMetadata {
+            group_id: self.group_id.clone(),
+            generation_id: if state.member_epoch > JOIN_EPOCH { state.member_epoch } else { -1 },
+            member_id: state.member_id.clone(),
+            group_instance_id: self.group_instance_id.clone()
+        }
+    }
+
+    /// Loses the partitions of a fenced member, waits for the coordinator to accept a joining
+    /// member, and reconciles the consumer with its latest target assignment.
+    fn poll(&self, handler: &dyn RebalanceHandler) -> Result<()> {
+        let mut state: MutexGuard<MembershipState> = self.lock();
+        if let Some(error_code) = state.fatal_error.clone() {
+            let lost: Vec<TopicPartition> = std::mem::take(&mut state.owned_partitions);
+            drop(state);
+            if !lost.is_empty() {
+                handler.lose(&lost);
+            }
+            return Err(anyhow::Error::new(error_code).context(format!("The consumer is no longer a member of group {}", self.group_id)));
+        }
+        if state.fenced {
+            let lost: Vec<TopicPartition> = std::mem::take(&mut state.owned_partitions);
+            state.fenced = false;
+            state.next_heartbeat = Instant::now();
+            self.changed.notify_all();
+            drop(state);
+            if !lost.is_empty() {
+                info!("Lost partitions {:?} of group {}", lost, self.group_id);
+                handler.lose(&lost);
+            }
+            state = self.lock();
+        }
+        if state.subscription.is_empty() {
+            return Ok(());
+        }
+        if !state.active {
+            state.active = true;
+            state.send_full = true;
+            state.next_heartbeat = Instant::now();
+            self.changed.notify_all();
+        }
+        if state.member_epoch == JOIN_EPOCH {
+            debug!("Joining group {} as member {:?}", self.group_id, state.member_id);
+            state = self.changed
+                .wait_timeout_while(state, self.default_api_timeout, |state| {
+                    state.member_epoch == JOIN_EPOCH && state.fatal_error.is_none() && !state.fenced && !state.closed
+                })
+                .expect("Consumer membership lock was poisoned").0;
+        }
+        let target: Option<BTreeMap<Uuid, Vec<i32>>> = state.target_assignment.take();
+        drop(state);
+        if let Some(target) = target {
+            self.reconcile(handler, target);
+        }
+        Ok(())
+    }
+
+    /// Sends heartbeats while the member is in the group, until the membership is closed. A fenced
+    /// member waits for `poll` to lose its partitions before it rejoins.
+    fn run_heartbeats(&self) {
+        let mut state: MutexGuard<MembershipState> = self.lock();
+        loop {
+            if state.closed {
+                return;
+            }
+            let now: Instant = Instant::now();
+            if !state.active || state.fenced || state.fatal_error.is_some() {
+                state = self.changed.wait(state).expect("Consumer membership lock was poisoned");
+                continue;
+            }
+            if now < state.next_heartbeat {
+                let wait: Duration = state.next_heartbeat - now;
+                state = self.changed.wait_timeout(state, wait).expect("Consumer membership lock was poisoned").0;
+                continue;
+            }
+            let reported: Option<Vec<TopicPartition>> = (state.send_full || state.acknowledge).then(|| state.owned_partitions.clone());
+            let request: ConsumerGroupHeartbeatRequestV0 = self.heartbeat_request(&state, reported.as_deref());
+            let member_epoch: i32 = state.member_epoch;
+            drop(state);
+            let response: Result<ConsumerGroupHeartbeatResponseV0> = self.coordinator.node_id(&self.pool)
+                .and_then(|node_id| self.pool.send(node_id, request))
+                .inspect_err(|_| self.coordinator.mark_unknown());
+            state = self.lock();
+            if state.active && state.member_epoch == member_epoch {
+                self.handle_heartbeat(&mut state, response, reported);
+            }
+        }
+    }
+
+    /// Gives up the member's partitions and leaves the group. A static member leaves with an epoch
+    /// which keeps its partitions for it, since it is expected to come back with the same
+    /// instance id.
+    ///
+    /// The partitions are revoked while the member still has its epoch, so that the coordinator
+    /// accepts the offsets committed as they are revoked.
+    fn leave_group(&self, reason: &str, handler: &dyn RebalanceHandler) {
+        let (fenced, owned_partitions): (bool, Vec<TopicPartition>) = {
+            let mut state: MutexGuard<MembershipState> = self.lock();
+            let fenced: bool = std::mem::take(&mut state.fenced);
+            (fenced, std::mem::take(&mut state.owned_partitions))
+        };
+        if !owned_partitions.is_empty() {
+            if fenced {
+                handler.lose(&owned_partitions);
+            } else {
+                handler.revoke(&owned_partitions);
+            }
+        }
+        let (member_id, member_epoch): (String, i32) = {
+            let mut state: MutexGuard<MembershipState> = self.lock();
+            state.active = false;
+            state.send_full = true;
+            state.target_assignment = None;
+            state.acknowledge = false;
+            (std::mem::take(&mut state.member_id), std::mem::replace(&mut state.member_epoch, JOIN_EPOCH))
+        };
+        if member_id.is_empty() || member_epoch == JOIN_EPOCH {
+            return;
+        }
+        info!("Member {} is leaving group {}: {}", member_id, self.group_id, reason);
+        let request: ConsumerGroupHeartbeatRequestV0 = ConsumerGroupHeartbeatRequestV0 {
+            group_id: CompactString(self.group_id.clone()),
+            member_id: CompactString(member_id),
+            member_epoch: if self.group_instance_id.is_some() { STATIC_LEAVE_EPOCH } else { LEAVE_EPOCH },
+            instance_id: CompactNullableString(self.group_instance_id.clone()),
+            rack_id: CompactNullableString(None),
+            rebalance_timeout_ms: -1,
+            subscribed_topic_names: CompactNullableArray(None),
+            server_assignor: CompactNullableString(None),
+            topic_partitions: CompactNullableArray(None),
+            tag_buffer: TaggedFields::new()
+        };
+        // a member which fails to leave is removed when its session times out
+        let response: Result<ConsumerGroupHeartbeatResponseV0> = self.coordinator.node_id(&self.pool)
+            .and_then(|node_id| self.pool.send(node_id, request));
+        match response {
+            Ok(response) if response.error_code == ErrorCode::None => {},
+            Ok(response) => warn!("Failed to leave group {}: {:?}", self.group_id, response.error_code),
+            Err(e) => warn!("Failed to leave group {}: {}", self.group_id, e)
+        }
+    }
+
+    /// Leaves the group and stops the heartbeat thread.
+    fn close(&self, handler: &dyn RebalanceHandler) {
+        self.leave_group("the consumer is being closed", handler);
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex, MutexGuard};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString, NullableStruct};
+    use uuid::Uuid;
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use crate::consumer::{ConsumerConfig, GroupProtocol};
+    use crate::consumer::consumer_coordinator::GroupMembership;
+    use crate::consumer::consumer_coordinator::tests::{Event, RecordingHandler, group_cluster};
+    use crate::consumer::consumer_membership::ConsumerMembership;
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::consumer_group_heartbeat::{ConsumerGroupHeartbeatAssignmentV0, ConsumerGroupHeartbeatRequestV0, ConsumerGroupHeartbeatResponseV0, ConsumerGroupHeartbeatTopicPartitionsV0};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::find_coordinator::{FindCoordinatorRequestV3, FindCoordinatorResponseV3};
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::tags::TaggedFields;
+    use crate::retry::RetryPolicy;
+
+    const FOO_ID: Uuid = Uuid::from_u128(7);
+
+    /// A group coordinator's side of a group under the consumer group protocol, with one member.
+    #[derive(Debug, Default)]
+    struct MockConsumerGroup {
+        /// The partitions of topic "foo" the coordinator assigns the member.
+        assignment: Vec<i32>,
+        errors: VecDeque<ErrorCode>,
+        heartbeats: Vec<ConsumerGroupHeartbeatRequestV0>,
+        epoch: i32,
+        /// The assignment last sent to the member.
+        sent: Option<Vec<i32>>
+    }
+
+    /// A broker which coordinates one group. A member joining without a member id is given one,
+    /// and each new assignment is sent with the next epoch.
+    fn consumer_group_broker(group: Arc<Mutex<MockConsumerGroup>>) -> MockBroker {
+        MockBroker::start(move |request: &MockRequest| {
+            let mut group: MutexGuard<MockConsumerGroup> = group.lock().unwrap();
+            match request.api_key {
+                key if key == ApiKey::FindCoordinator as i16 => Some(request.respond::<FindCoordinatorRequestV3, FindCoordinatorResponseV3>(FindCoordinatorResponseV3 {
+                    throttle_time_ms: 0,
+                    error_code: ErrorCode::None,
+                    error_message: CompactNullableString(None),
+                    node_id: 1,
+                    host: CompactString(String::from("127.0.0.1")),
+                    port: 0,
+                    tag_buffer: TaggedFields::new()
+                })),
+                key if key == ApiKey::ConsumerGroupHeartbeat as i16 => {
+                    let heartbeat: ConsumerGroupHeartbeatRequestV0 = request.decode().unwrap();
+                    group.heartbeats.push(heartbeat.clone());
+                    let mut response: ConsumerGroupHeartbeatResponseV0 = ConsumerGroupHeartbeatResponseV0 {
+                        throttle_time_ms: 0,
+                        error_code: ErrorCode::None,
+                        error_message: CompactNullableString(None),
+                        member_id: CompactNullableString(None),
+                        member_epoch: heartbeat.member_epoch,
+                        heartbeat_interval_ms: 10,
+                        assignment: NullableStruct(None),
+                        tag_buffer: TaggedFields::new()
+                    };
+                    if let Some(error_code) = group.errors.pop_front() {
+                        response.error_code = error_code;
+                    } else if heartbeat.member_epoch >= 0 {
+                        if heartbeat.member_epoch == 0 {
+                            group.sent = None;
+                        }
+                        if group.sent.as_ref() != Some(&group.assignment) {
+                            group.epoch += 1;
+                            group.sent = Some(group.assignment.clone());
+                            response.assignment = NullableStruct(Some(ConsumerGroupHeartbeatAssignmentV0 {
+                                topic_partitions: CompactArray(vec![ConsumerGroupHeartbeatTopicPartitionsV0 {
+                                    topic_id: FOO_ID,
+                                    partitions: CompactArray(group.assignment.clone()),
+                                    tag_buffer: TaggedFields::new()
+                                }]),
+                                tag_buffer: TaggedFields::new()
+                            }));
+                        }
+                        response.member_id = match heartbeat.member_id.0.as_str() {
+                            "" => CompactNullableString(Some(format!("member-{}", group.heartbeats.len()))),
+                            member_id => CompactNullableString(Some(String::from(member_id)))
+                        };
+                        response.member_epoch = group.epoch;
+                    }
+                    Some(request.respond::<ConsumerGroupHeartbeatRequestV0, ConsumerGroupHeartbeatResponseV0>(response))
+                },
+                _ => None
+            }
+        })
+    }
+
+    fn config() -> ConsumerConfig {
+        ConsumerConfig {
+            group_id: Some(String::from("group")),
+            group_protocol: GroupProtocol::Consumer,
+            retry: RetryPolicy { retry_backoff: Duration::from_millis(10), ..RetryPolicy::default() },
+            ..ConsumerConfig::new("unused:9092")
+        }
+    }
+
+    /// A membership whose heartbeat thread is running, with topic "foo" of four partitions.
+    fn membership(broker: &MockBroker, config: &ConsumerConfig) -> (Arc<ConsumerMembership>, thread::JoinHandle<()>) {
+        let mut cluster: ClusterMetadata = group_cluster(broker, 4);
+        cluster.topics.get_mut("foo").unwrap().topic_id = Some(FOO_ID);
+        let metadata: Arc<MetadataCache> = Arc::new(MetadataCache::new(Box::new(StaticFetcher(cluster.clone())), MetadataCacheConfig::default()));
+        metadata.update(cluster);
+        let pool: Arc<ConnectionPool> = Arc::new(ConnectionPool::new(metadata.clone(), ConnectionPoolConfig::default()));
+        let membership: Arc<ConsumerMembership> = Arc::new(ConsumerMembership::new("group", config, pool, metadata));
+        let heartbeat_membership: Arc<ConsumerMembership> = membership.clone();
+        (membership, thread::spawn(move || heartbeat_membership.run_heartbeats()))
+    }
+
+    /// Polls until the membership hands the consumer partitions or takes some away.
+    fn poll_for_events(membership: &ConsumerMembership, handler: &RecordingHandler) -> Vec<Event> {
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        loop {
+            membership.poll(handler).unwrap();
+            let events: Vec<Event> = handler.take();
+            if !events.is_empty() || Instant::now() >= deadline {
+                return events;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn wait_until<F>(condition: F) where F: Fn() -> bool {
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        while !condition() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn partitions(partitions: &[i32]) -> Vec<TopicPartition> {
+        partitions.iter().map(|partition| TopicPartition::new("foo", *partition)).collect()
+    }
+
+    fn topic_partitions(partitions: &[i32]) -> Option<Vec<ConsumerGroupHeartbeatTopicPartitionsV0>> {
+        Some(vec![ConsumerGroupHeartbeatTopicPartitionsV0 {
+            topic_id: FOO_ID,
+            partitions: CompactArray(partitions.to_vec()),
+            tag_buffer: TaggedFields::new()
+        }])
+    }
+
+    #[test]
+    fn test_member_reconciles_and_acknowledges_the_assignments_in_heartbeat_responses() {
+        let group: Arc<Mutex<MockConsumerGroup>> = Arc::new(Mutex::new(MockConsumerGroup { assignment: vec![0, 1], ..MockConsumerGroup::default() }));
+        let broker: MockBroker = consumer_group_broker(group.clone());
+        let config: ConsumerConfig = ConsumerConfig { group_remote_assignor: Some(String::from("uniform")), ..config() };
+        let (membership, heartbeat_thread): (Arc<ConsumerMembership>, thread::JoinHandle<()>) = membership(&broker, &config);
+        let handler: RecordingHandler = RecordingHandler::default();
+        membership.subscribe(vec![String::from("foo")]);
+        assert_eq!(poll_for_events(&membership, &handler), vec![("assigned", partitions(&[0, 1]))]);
+        {
+            let group: MutexGuard<MockConsumerGroup> = group.lock().unwrap();
+            let join: &ConsumerGroupHeartbeatRequestV0 = &group.heartbeats[0];
+            assert_eq!((join.member_id.0.as_str(), join.member_epoch, join.rebalance_timeout_ms), ("", 0, 300_000));
+            assert_eq!(join.subscribed_topic_names.0, Some(vec![CompactString(String::from("foo"))]));
+            assert_eq!(join.server_assignor.0.as_deref(), Some("uniform"));
+            assert_eq!(join.topic_partitions.0, Some(vec![]));
+        }
+        assert_eq!((membership.group_metadata().generation_id, membership.group_metadata().member_id), (1, String::from("member-1")));
+
+        // the next heartbeat acknowledges the assignment, and the ones after only say the member is alive
+        wait_until(|| group.lock().unwrap().heartbeats.iter().any(|heartbeat| heartbeat.topic_partitions.0 == topic_partitions(&[0, 1])));
+        wait_until(|| group.lock().unwrap().heartbeats.last().unwrap().topic_partitions.0.is_none());
+        let heartbeat: ConsumerGroupHeartbeatRequestV0 = group.lock().unwrap().heartbeats.last().unwrap().clone();
+        assert_eq!((heartbeat.member_epoch, heartbeat.rebalance_timeout_ms, heartbeat.subscribed_topic_names.0), (1, -1, None));
+
+        // only the partition the coordinator took away is revoked
+        group.lock().unwrap().assignment = vec![1, 2];
+        assert_eq!(poll_for_events(&membership, &handler), vec![("revoked", partitions(&[0])), ("assigned", partitions(&[2]))]);
+        wait_until(|| group.lock().unwrap().heartbeats.iter().any(|heartbeat| heartbeat.topic_partitions.0 == topic_partitions(&[1, 2])));
+        assert_eq!(membership.group_metadata().generation_id, 2);
+
+        membership.close(&handler);
+        heartbeat_thread.join().unwrap();
+        assert_eq!(handler.take(), vec![("revoked", partitions(&[1, 2]))]);
+        let leave: ConsumerGroupHeartbeatRequestV0 = group.lock().unwrap().heartbeats.last().unwrap().clone();
+        assert_eq!((leave.member_id.0.as_str(), leave.member_epoch), ("member-1", -1));
+    }
+
+    #[test]
+    fn test_fenced_member_loses_its_partitions_before_it_rejoins() {
+        let group: Arc<Mutex<MockConsumerGroup>> = Arc::new(Mutex::new(MockConsumerGroup { assignment: vec![0], ..MockConsumerGroup::default() }));
+        let broker: MockBroker = consumer_group_broker(group.clone());
+        let config: ConsumerConfig = ConsumerConfig { group_instance_id: Some(String::from("instance")), ..config() };
+        let (membership, heartbeat_thread): (Arc<ConsumerMembership>, thread::JoinHandle<()>) = membership(&broker, &config);
+        let handler: RecordingHandler = RecordingHandler::default();
+        membership.subscribe(vec![String::from("foo")]);
+        assert_eq!(poll_for_events(&membership, &handler), vec![("assigned", partitions(&[0]))]);
+
+        // a fenced member keeps its id, and rejoins once it has lost its partitions
+        group.lock().unwrap().errors.push_back(ErrorCode::FencedMemberEpoch);
+        assert_eq!(poll_for_events(&membership, &handler), vec![("lost", partitions(&[0])), ("assigned", partitions(&[0]))]);
+        {
+            let group: MutexGuard<MockConsumerGroup> = group.lock().unwrap();
+            let rejoin: &ConsumerGroupHeartbeatRequestV0 = group.heartbeats.iter().rev().find(|heartbeat| heartbeat.member_epoch == 0).unwrap();
+            assert_eq!((rejoin.member_id.0.as_str(), rejoin.instance_id.0.as_deref()), ("member-1", Some("instance")));
+            assert_eq!(rejoin.topic_partitions.0, Some(vec![]));
+        }
+
+        // an assignor the coordinator doesn't have can't be fixed by rejoining
+        group.lock().unwrap().errors.push_back(ErrorCode::UnsupportedAssignor);
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        let error: anyhow::Error = loop {
+            match membership.poll(&handler) {
+                Err(error) => break error,
+                Ok(()) if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+                Ok(()) => panic!("The membership didn't fail")
+            }
+        };
+        assert_eq!(error.downcast_ref::<ErrorCode>(), Some(&ErrorCode::UnsupportedAssignor));
+        assert_eq!(handler.take(), vec![("lost", partitions(&[0]))]);
+
+        // a static member leaves with the epoch which keeps its partitions for it
+        membership.close(&handler);
+        heartbeat_thread.join().unwrap();
+        assert_eq!(group.lock().unwrap().heartbeats.last().unwrap().member_epoch, -2);
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use kafka_encode::primitives::{CompactArray, CompactString};
+use tracing::{debug, warn};
+use crate::cluster::{ClusterMetadata, TopicPartition};
+use crate::connection_pool::ConnectionPool;
+use crate::consumer::ConsumerError;
+use crate::consumer::subscription_state::{FetchPosition, SubscriptionState};
+use crate::metadata_cache::MetadataCache;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::fetch::{CONSUMER_REPLICA_ID, FetchPartitionDataV12V13, FetchPartitionV12V13, FetchRequestV12, FetchResponseV12, FetchTopicV12, IsolationLevel};
+use crate::protocol::records::{Record, RecordBatch};
+use crate::protocol::tags::TaggedFields;
+
+/// The longest the fetcher thread sleeps with nothing to fetch, so that new positions are noticed.
+const MAX_IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// A session epoch of -1 asks for a full fetch, without a fetch session (KIP-227).
+const FINAL_EPOCH: i32 = -1;
+
+/// The records fetched for one partition, which are handed to the application as it polls.
+/// Control batches are skipped, as are the batches of aborted transactions when reading committed
+/// records.
+#[derive(Debug)]
+pub(crate) struct CompletedFetch {
+    pub topic_partition: TopicPartition,
+    /// The offset of the next record to consume, which starts as the offset fetched from.
+    pub next_offset: i64,
+    /// The leader epoch of the last batch read.
+    pub leader_epoch: Option<i32>,
+    /// Set instead of records if the partition failed to fetch, or after the records before a
+    /// batch which can't be read. It is raised once those records are consumed.
+    pub error: Option<ConsumerError>,
+    batches: Vec<RecordBatch>,
+    batch_index: usize,
+    record_index: usize,
+    read_committed: bool,
+    /// The producer id and first offset of each aborted transaction, by first offset.
+    aborted_transactions: VecDeque<(i64, i64)>,
+    /// The producers whose transaction is aborted at the current batch.
+    aborted_producer_ids: HashSet<i64>
+}
+
+impl CompletedFetch {
+    pub fn new(topic_partition: TopicPartition, fetch_offset: i64, batches: Vec<RecordBatch>, isolation_level: IsolationLevel, mut aborted_transactions: Vec<(i64, i64)>) -> Self {
+        aborted_transactions.sort_by_key(|(_, first_offset)| *first_offset);
+        CompletedFetch {
+            topic_partition,
+            next_offset: fetch_offset,
+            leader_epoch: None,
+            error: None,
+            batches,
+            batch_index: 0,
+            record_index: 0,
+            read_committed: isolation_level == IsolationLevel::ReadCommitted,
+            aborted_transactions: VecDeque::from(aborted_transactions),
+            aborted_producer_ids: HashSet::new()
+        }
+    }
+
+    pub fn failed(topic_partition: TopicPartition, fetch_offset: i64, error: ConsumerError) -> Self {
+        CompletedFetch {
+            error: Some(error),
+            ..CompletedFetch::new(topic_partition, fetch_offset, Vec::new(), IsolationLevel::ReadUncommitted, Vec::new())
+        }
+    }
+
+    /// The error to raise at the current position, once no records are left before it.
+    pub fn pending_error(&mut self) -> Option<ConsumerError> {
+        if self.peek().is_some() {
+            return None;
+        }
+        self.error.clone()
+    }
+
+    /// The next record to consume, if any are left.
+    pub fn peek(&mut self) -> Option<(&RecordBatch, &Record)> {
+        if !self.skip_to_next_record() {
+            return None;
+        }
+        let batch: &RecordBatch = &self.batches[self.batch_index];
+        Some((batch, &batch.records.0[self.record_index]))
+    }
+
+    /// Moves the batch and record indexes to the next record to consume, and returns whether there
+    /// is one.
+    fn skip_to_next_record(&mut self) -> bool {
+        while self.batch_index < self.batches.len() {
+            let last_offset: i64 = self.batches[self.batch_index].last_offset();
+            let record_count: usize = self.batches[self.batch_index].records.0.len();
+            if (self.record_index == 0 && !self.enter_batch()) || self.record_index >= record_count {
+                self.next_offset = self.next_offset.max(last_offset + 1);
+                self.batch_index += 1;
+                self.record_index = 0;
+                continue;
+            }
+            let batch: &RecordBatch = &self.batches[self.batch_index];
+            // a batch can start before the offset which was fetched
+            if batch.base_offset + (batch.records.0[self.record_index].offset_delta.0 as i64) < self.next_offset {
+                self.record_index += 1;
+                continue;
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Moves past the record returned by `peek`, which is at `offset`.
+    pub fn advance(&mut self, offset: i64) {
+        self.next_offset = offset + 1;
+        self.record_index += 1;
+    }
+
+    /// Keeps track of aborted transactions at the start of the current batch, and returns whether
+    /// its records should be read.
+    fn enter_batch(&mut self) -> bool {
+        let batch: &RecordBatch = &self.batches[self.batch_index];
+        if batch.partition_leader_epoch >= 0 {
+            self.leader_epoch = Some(batch.partition_leader_epoch);
+        }
+        if self.read_committed {
+            while let Some((producer_id, first_offset)) = self.aborted_transactions.front() {
+                if *first_offset > batch.last_offset() {
+                    break;
+                }
+                self.aborted_producer_ids.insert(*producer_id);
+                self.aborted_transactions.pop_front();
+            }
+        }
+        if batch.is_control_batch() {
+            // the marker ends its producer's transaction
+            self.aborted_producer_ids.remove(&batch.producer_id);
+            return false;
+        }
+        !(self.read_committed && batch.is_transactional() && self.aborted_producer_ids.contains(&batch.producer_id))
+    }
+}
+
+#[derive(Debug, Default)]
+struct FetchBufferState {
+    completed: VecDeque<CompletedFetch>,
+    /// The partition of the fetch the application has taken out of the buffer to consume.
+    consuming: Option<TopicPartition>,
+    closed: bool
+}
+
+/// Fetches which have arrived and haven't been consumed yet, at most one per partition. A
+/// partition isn't fetched again until its fetch has been consumed, so the next fetch is sent
+/// while the application processes the records of the last one.
+#[derive(Debug, Default)]
+pub(crate) struct FetchBuffer {
+    state: Mutex<FetchBufferState>,
+    changed: Condvar
+}
+
+impl FetchBuffer {
+    fn lock(&self) -> MutexGuard<'_, FetchBufferState> {
+        self.state.lock().expect("Fetch buffer lock was poisoned")
+    }
+
+    pub fn add(&self, fetches: Vec<CompletedFetch>) {
+        if fetches.is_empty() {
+            return;
+        }
+        self.lock().completed.extend(fetches);
+        self.changed.notify_all();
+    }
+
+    /// The partitions which have a fetch waiting to be consumed.
+    pub fn partitions(&self) -> HashSet<TopicPartition> {
+        let state: MutexGuard<FetchBufferState> = self.lock();
+        state.completed.iter()
+            .map(|fetch| fetch.topic_partition.clone())
+            .chain(state.consuming.clone())
+            .collect()
+    }
+
+    /// Takes the oldest fetch out to consume. It must be handed back with `put_back`.
+    pub fn take(&self) -> Option<CompletedFetch> {
+        let mut state: MutexGuard<FetchBufferState> = self.lock();
+        let fetch: CompletedFetch = state.completed.pop_front()?;
+        state.consuming = Some(fetch.topic_partition.clone());
+        Some(fetch)
+    }
+
+    /// Hands back the fetch taken with `take`, which goes first again if it has records left.
+    pub fn put_back(&self, fetch: Option<CompletedFetch>) {
+        let mut state: MutexGuard<FetchBufferState> = self.lock();
+        state.consuming = None;
+        if let Some(fetch) = fetch {
+            state.completed.push_front(fetch);
+        }
+        self.changed.notify_all();
+    }
+
+    /// Drops the fetches of partitions which aren't in `partitions`.
+    pub fn retain(&self, partitions: &[TopicPartition]) {
+        self.lock().completed.retain(|fetch| partitions.contains(&fetch.topic_partition));
+        self.changed.notify_all();
+    }
+
+    /// Drops the fetch of a partition whose position has moved.
+    pub fn remove(&self, topic_partition: &TopicPartition) {
+        self.lock().completed.retain(|fetch| fetch.topic_partition != *topic_partition);
+        self.changed.notify_all();
+    }
+
+    /// Blocks until a fetch arrives, the buffer changes, or the timeout passes.
+    pub fn wait_for_change(&self, timeout: Duration) {
+        let state: MutexGuard<FetchBufferState> = self.lock();
+        let _ = self.changed.wait_timeout(state, timeout).expect("Fetch buffer lock was poisoned");
+    }
+
+    /// Blocks until there is a fetch to consume, or the timeout passes.
+    pub fn wait_for_fetches(&self, timeout: Duration) {
+        let state: MutexGuard<FetchBufferState> = self.lock();
+        let _ = self.changed.wait_timeout_while(state, timeout, |state| state.completed.is_empty() && !state.closed)
+            .expect("Fetch buffer lock was poisoned");
+    }
+
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+}
+
+/// Sends Fetch requests to the leaders of the assigned partitions from their positions, and adds
+/// the responses to the fetch buffer. Partitions with a fetch in the buffer are left out until it
+/// has been consumed.
+#[derive(Debug)]
+pub(crate) struct Fetcher {
+    pub metadata: Arc<MetadataCache>,
+    pub pool: Arc<ConnectionPool>,
+    pub subscriptions: Arc<SubscriptionState>,
+    pub buffer: Arc<FetchBuffer>,
+    /// `fetch.min.bytes`
+    pub min_bytes: usize,
+    /// `fetch.max.bytes`
+    pub max_bytes: usize,
+    /// `fetch.max.wait.ms`
+    pub max_wait: Duration,
+    /// `max.partition.fetch.bytes`
+    pub max_partition_bytes: usize,
+    /// `isolation.level`
+    pub isolation_level: IsolationLevel,
+    /// `check.crcs`
+    pub check_crcs: bool,
+    /// `retry.backoff.ms`
+    pub retry_backoff: Duration
+}
+
+impl Fetcher {
+    /// Fetches until the consumer is closed.
+    pub fn run(&self) {
+        while !self.buffer.is_closed() {
+            self.run_once();
+        }
+        debug!("The consumer's fetcher thread has finished");
+    }
+
+    fn run_once(&self) {
+        let buffered: HashSet<TopicPartition> = self.buffer.partitions();
+        let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        let mut requests: HashMap<i32, Vec<(TopicPartition, FetchPosition)>> = HashMap::new();
+        let mut unknown_leaders: bool = false;
+        for (topic_partition, position) in self.subscriptions.fetchable_partitions() {
+            if buffered.contains(&topic_partition) {
+                continue;
+            }
+            match cluster.leader(&topic_partition) {
+                Some(leader) if !self.pool.is_backing_off(leader.node_id) && !self.pool.is_throttled(leader.node_id) => {
+                    requests.entry(leader.node_id).or_default().push((topic_partition, position));
+                },
+                Some(_) => {},
+                None => unknown_leaders = true
+            }
+        }
+        if unknown_leaders {
+            self.metadata.request_update();
+        }
+        if requests.is_empty() {
+            self.buffer.wait_for_change(if unknown_leaders { self.retry_backoff } else { MAX_IDLE_WAIT });
+            return;
+        }
+        // the leaders are fetched from at the same time
+        let backoff: bool = thread::scope(|scope| {
+            let fetches: Vec<thread::ScopedJoinHandle<bool>> = requests.into_iter()
+                .map(|(node_id, partitions)| {
+                    let cluster: &ClusterMetadata = &cluster;
+                    scope.spawn(move || self.fetch(node_id, partitions, cluster))
+                })
+                .collect();
+            fetches.into_iter().any(|fetch| fetch.join().unwrap_or(true))
+        });
+        if backoff || unknown_leaders {
+            self.buffer.wait_for_change(self.retry_backoff);
+        }
+    }
+
+    /// Fetches partitions from their leader. Returns true if the fetcher should back off before
+    /// fetching again, because the request or some of its partitions failed.
+    fn fetch(&self, node_id: i32, partitions: Vec<(TopicPartition, FetchPosition)>, cluster: &ClusterMetadata) -> bool {
+        let mut topics: BTreeMap<String, Vec<FetchPartitionV12V13>> = BTreeMap::new();
+        for (topic_partition, position) in &partitions {
+            let current_leader_epoch: i32 = cluster.partition(topic_partition)
+                .and_then(|partition| partition.leader_epoch)
+                .unwrap_or(-1);
+            topics.entry(topic_partition.topic.clone()).or_default().push(FetchPartitionV12V13 {
+                partition: topic_partition.partition,
+                current_leader_epoch,
+                fetch_offset: position.offset,
+                last_fetched_epoch: -1,
+                log_start_offset: -1,
+                partition_max_bytes: to_i32(self.max_partition_bytes),
+                tag_buffer: TaggedFields::new()
+            });
+        }
+        let request: FetchRequestV12 = FetchRequestV12 {
+            replica_id: CONSUMER_REPLICA_ID,
+            max_wait_ms: self.max_wait.as_millis().min(i32::MAX as u128) as i32,
+            min_bytes: to_i32(self.min_bytes),
+            max_bytes: to_i32(self.max_bytes),
+            isolation_level: self.isolation_level,
+            session_id: 0,
+            session_epoch: FINAL_EPOCH,
+            topics: CompactArray(topics.into_iter()
+                .map(|(topic, partitions)| FetchTopicV12 {
+                    topic: CompactString(topic),
+                    partitions: CompactArray(partitions),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            forgotten_topics_data: CompactArray(vec![]),
+            rack_id: CompactString(String::new()),
+            tag_buffer: TaggedFields::new()
+        };
+        let response: FetchResponseV12 = match self.pool.send::<FetchRequestV12, FetchResponseV12>(node_id, request) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Fetch request to node {} failed: {:#}", node_id, e);
+                // the leader may have moved
+                self.metadata.request_update();
+                return true;
+            }
+        };
+        if response.error_code != ErrorCode::None {
+            warn!("Fetch request to node {} failed: {}", node_id, response.error_code);
+            return true;
+        }
+        let fetch_offsets: HashMap<TopicPartition, i64> = partitions.into_iter()
+            .map(|(topic_partition, position)| (topic_partition, position.offset))
+            .collect();
+        let mut backoff: bool = false;
+        let mut fetches: Vec<CompletedFetch> = Vec::new();
+        for topic in response.responses.0 {
+            for partition in topic.partitions.0 {
+                let topic_partition: TopicPartition = TopicPartition::new(&topic.topic.0, partition.partition_index);
+                let Some(fetch_offset) = fetch_offsets.get(&topic_partition) else {
+                    continue;
+                };
+                match self.completed_fetch(topic_partition, *fetch_offset, partition) {
+                    Ok(Some(fetch)) => fetches.push(fetch),
+                    Ok(None) => {},
+                    Err(()) => backoff = true
+                }
+            }
+        }
+        self.buffer.add(fetches);
+        backoff
+    }
+
+    /// The fetch for a partition of a response, or `None` if nothing was fetched. Errors which
+    /// fresh metadata can fix are `Err` after an update has been requested. Other errors are
+    /// handed to the application.
+    fn completed_fetch(&self, topic_partition: TopicPartition, fetch_offset: i64, partition: FetchPartitionDataV12V13) -> Result<Option<CompletedFetch>, ()> {
+        let error: ConsumerError = match partition.error_code {
+            ErrorCode::None => {
+                let mut batches: Vec<RecordBatch> = partition.records.0.unwrap_or_default();
+                if batches.is_empty() {
+                    return Ok(None);
+                }
+                // Fail the partition at the first batch this crate can't read, but only once the
+                // records before it are consumed. Batches before the fetch offset are skipped anyway.
+                let unreadable: Option<(usize, ConsumerError)> = batches.iter().enumerate()
+                    .filter(|(_, batch)| batch.last_offset() >= fetch_offset)
+                    .find_map(|(index, batch)| self.batch_error(&topic_partition, batch).map(|error| (index, error)));
+                let error: Option<ConsumerError> = unreadable.map(|(index, error)| {
+                    batches.truncate(index);
+                    error
+                });
+                let aborted_transactions: Vec<(i64, i64)> = partition.aborted_transactions.0.unwrap_or_default().into_iter()
+                    .map(|aborted| (aborted.producer_id, aborted.first_offset))
+                    .collect();
+                return Ok(Some(CompletedFetch {
+                    error,
+                    ..CompletedFetch::new(topic_partition, fetch_offset, batches, self.isolation_level, aborted_transactions)
+                }));
+            },
+            error_code if self.metadata.handle_error_code(&error_code) => {
+                debug!("Failed to fetch {:?}: {}", topic_partition, error_code);
+                return Err(());
+            },
+            ErrorCode::OffsetOutOfRange => ConsumerError::OffsetOutOfRange {
+                topic: topic_partition.topic.clone(),
+                partition: topic_partition.partition,
+                offset: fetch_offset
+            },
+            error_code => ConsumerError::FetchFailed {
+                topic: topic_partition.topic.clone(),
+                partition: topic_partition.partition,
+                message: error_code.to_string(),
+                error_code: Some(error_code)
+            }
+        };
+        Ok(Some(CompletedFetch::failed(topic_partition, fetch_offset, error)))
+    }
+
+    /// Why the records of a batch can't be read: it is in a format older than v2, it is compressed
+    /// with a codec this crate doesn't know, or it fails its CRC check when `check.crcs` is set.
+    fn batch_error(&self, topic_partition: &TopicPartition, batch: &RecordBatch) -> Option<ConsumerError> {
+        if let Some(message) = &batch.decode_error {
+            return Some(ConsumerError::UnreadableRecordBatch {
+                topic: topic_partition.topic.clone(),
+                partition: topic_partition.partition,
+                offset: batch.base_offset,
+                message: message.clone()
+            });
+        }
+        if self.check_crcs && !batch.is_valid().unwrap_or(false) {
+            return Some(ConsumerError::CorruptRecord {
+                topic: topic_partition.topic.clone(),
+                partition: topic_partition.partition,
+                offset: batch.base_offset
+            });
+        }
+        None
+    }
+}
+
+fn to_i32(bytes: usize) -> i32 {
+    bytes.min(i32::MAX as usize) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::TopicPartition;
+    use crate::consumer::fetcher::CompletedFetch;
+    use crate::protocol::fetch::IsolationLevel;
+    use crate::protocol::records::{Record, RecordBatch};
+
+    const TRANSACTIONAL: i16 = 0x10;
+    const CONTROL: i16 = 0x30;
+
+    fn batch(base_offset: i64, producer_id: i64, attributes: i16, count: i32) -> RecordBatch {
+        let records: Vec<Record> = (0..count)
+            .map(|offset_delta| Record::new(offset_delta, 0, None, Some(vec![offset_delta as u8]), vec![]).unwrap())
+            .collect();
+        RecordBatch {
+            base_offset,
+            producer_id,
+            attributes,
+            partition_leader_epoch: 3,
+            ..RecordBatch::new(0, records)
+        }
+    }
+
+    fn offsets(fetch: &mut CompletedFetch) -> Vec<i64> {
+        let mut offsets: Vec<i64> = Vec::new();
+        while let Some((batch, record)) = fetch.peek() {
+            let offset: i64 = batch.base_offset + record.offset_delta.0 as i64;
+            fetch.advance(offset);
+            offsets.push(offset);
+        }
+        offsets
+    }
+
+    #[test]
+    fn test_records_before_the_fetch_offset_and_control_batches_are_skipped() {
+        let batches: Vec<RecordBatch> = vec![batch(0, -1, 0, 3), batch(3, 7, TRANSACTIONAL, 2), batch(5, 7, CONTROL, 1), batch(6, -1, 0, 1)];
+        let mut fetch: CompletedFetch = CompletedFetch::new(TopicPartition::new("foo", 0), 1, batches, IsolationLevel::ReadUncommitted, vec![(7, 3)]);
+        assert_eq!(offsets(&mut fetch), vec![1, 2, 3, 4, 6]);
+        assert_eq!((fetch.next_offset, fetch.leader_epoch), (7, Some(3)));
+    }
+
+    #[test]
+    fn test_aborted_transactions_are_skipped_when_reading_committed() {
+        let batches: Vec<RecordBatch> = vec![
+            batch(0, 7, TRANSACTIONAL, 2), batch(2, 8, TRANSACTIONAL, 1), batch(3, 7, CONTROL, 1),
+            batch(4, 8, CONTROL, 1), batch(5, 7, TRANSACTIONAL, 1)
+        ];
+        let mut fetch: CompletedFetch = CompletedFetch::new(TopicPartition::new("foo", 0), 0, batches, IsolationLevel::ReadCommitted, vec![(7, 0)]);
+        // producer 7 aborted, producer 8 committed, and producer 7's next transaction is open
+        assert_eq!(offsets(&mut fetch), vec![2, 5]);
+        assert_eq!(fetch.next_offset, 6);
+    }
+}
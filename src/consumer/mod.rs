@@ -0,0 +1,1270 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+use tracing::{debug, info, warn};
+use crate::bootstrap::ClientDnsLookup;
+use crate::cluster::TopicPartition;
+use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+use crate::consumer::consumer_coordinator::{ConsumerCoordinator, GroupMembership, RebalanceHandler};
+use crate::consumer::consumer_membership::ConsumerMembership;
+use crate::consumer::fetcher::{CompletedFetch, FetchBuffer, Fetcher};
+use crate::consumer::offset_fetcher::OffsetFetcher;
+use crate::consumer::offsets::GroupOffsets;
+use crate::consumer::subscription_state::{FetchPosition, SubscriptionState};
+use crate::coordinator::{ConsumerGroupMetadata, OffsetAndMetadata};
+use crate::metadata_cache::{MetadataCache, MetadataCacheConfig, MetadataFetcher, NetworkMetadataFetcher};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::fetch::IsolationLevel;
+use crate::protocol::list_offsets::{EARLIEST_TIMESTAMP, LATEST_TIMESTAMP};
+use crate::protocol::records::{Record, RecordBatch};
+use crate::retry::{self, RetryPolicy};
+use crate::serialization::{BytesSerde, Deserializer, Header, SerializationError};
+
+mod assignor;
+mod consumer_coordinator;
+mod consumer_membership;
+mod fetcher;
+mod offset_fetcher;
+mod offsets;
+mod rebalance_listener;
+mod sticky_assignor;
+mod subscription_state;
+
+pub use crate::consumer::assignor::{Assignment, PartitionAssignor, RangeAssignor, RebalanceProtocol, RoundRobinAssignor, Subscription};
+pub use crate::consumer::offsets::OffsetCommitCallback;
+pub use crate::consumer::rebalance_listener::RebalanceListener;
+pub use crate::consumer::sticky_assignor::{CooperativeStickyAssignor, StickyAssignor};
+
+/// A record read from a partition. The key and value are `None` when they are null.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConsumerRecord<K, V> {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    /// Milliseconds since the epoch.
+    pub timestamp: i64,
+    pub headers: Vec<Header>,
+    pub key: Option<K>,
+    pub value: Option<V>
+}
+
+/// Why records couldn't be consumed.
+#[derive(Debug, Clone, Error, Eq, PartialEq)]
+pub enum ConsumerError {
+    #[error("Failed to deserialize the {field} of the record at offset {offset} of partition {partition} of topic {topic}: {error}")]
+    Deserialization { topic: String, partition: i32, offset: i64, field: &'static str, error: SerializationError },
+    #[error("Partition {partition} of topic {topic} is not assigned to the consumer")]
+    NotAssigned { topic: String, partition: i32 },
+    #[error("Offset {offset} is out of range for partition {partition} of topic {topic}")]
+    OffsetOutOfRange { topic: String, partition: i32, offset: i64 },
+    #[error("The record batch at offset {offset} of partition {partition} of topic {topic} failed its CRC check")]
+    CorruptRecord { topic: String, partition: i32, offset: i64 },
+    #[error("The record batch at offset {offset} of partition {partition} of topic {topic} can't be read: {message}")]
+    UnreadableRecordBatch { topic: String, partition: i32, offset: i64, message: String },
+    #[error("Failed to fetch partition {partition} of topic {topic}: {message}")]
+    FetchFailed { topic: String, partition: i32, error_code: Option<ErrorCode>, message: String },
+    #[error("Partition {partition} of topic {topic} has no position to read from, and auto.offset.reset is none")]
+    NoOffsetForPartition { topic: String, partition: i32 }
+}
+
+/// The offset of a record found by its timestamp.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OffsetAndTimestamp {
+    pub offset: i64,
+    /// Milliseconds since the epoch.
+    pub timestamp: i64,
+    pub leader_epoch: Option<i32>
+}
+
+/// `auto.offset.reset`: where the consumer reads a partition from when it has no committed offset,
+/// or when its position is out of range.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OffsetResetStrategy {
+    /// The first record the partition holds.
+    Earliest,
+    /// The end of the partition, so that only new records are read.
+    #[default]
+    Latest,
+    /// `poll` fails until the consumer seeks.
+    None
+}
+
+/// `group.protocol`: how the consumer's group shares out its partitions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum GroupProtocol {
+    /// Members join and sync with the group, and one of them assigns every member its partitions
+    /// with a `PartitionAssignor`.
+    #[default]
+    Classic,
+    /// The group coordinator assigns the partitions, and members take up their assignment from
+    /// ConsumerGroupHeartbeat responses (KIP-848). Needs brokers of Kafka 4.0 or later.
+    Consumer
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    /// `bootstrap.servers`: a comma-separated list of `host:port` pairs.
+    pub bootstrap_servers: String,
+    pub client_dns_lookup: ClientDnsLookup,
+    /// `group.id`: `None` for a consumer which is given its partitions with `assign`.
+    pub group_id: Option<String>,
+    /// `group.instance.id`: makes the consumer a static member of its group (KIP-345), which keeps
+    /// its partitions across restarts within the session timeout.
+    pub group_instance_id: Option<String>,
+    pub group_protocol: GroupProtocol,
+    /// `group.remote.assignor`: the assignor the group coordinator uses under the consumer group
+    /// protocol, or `None` for the broker's default.
+    pub group_remote_assignor: Option<String>,
+    /// `enable.auto.commit`: commit the positions of the consumer's partitions every
+    /// `auto.commit.interval.ms` as it polls, before its group takes partitions away, and as it is
+    /// closed. Needs a `group.id`.
+    pub enable_auto_commit: bool,
+    /// `auto.commit.interval.ms`: how often positions are committed when `enable.auto.commit` is on.
+    pub auto_commit_interval: Duration,
+    pub auto_offset_reset: OffsetResetStrategy,
+    /// `session.timeout.ms`: how long the group coordinator waits for a heartbeat before it
+    /// removes the consumer from the group. The consumer group protocol uses the broker's setting.
+    pub session_timeout: Duration,
+    /// `heartbeat.interval.ms`: how often the consumer heartbeats to the group coordinator. Under
+    /// the consumer group protocol, the coordinator tells the consumer how often instead.
+    pub heartbeat_interval: Duration,
+    /// `max.poll.interval.ms`: how long the group waits for the consumer to rejoin during a rebalance.
+    pub max_poll_interval: Duration,
+    /// `default.api.timeout.ms`: how long blocking calls wait when not given a timeout.
+    pub default_api_timeout: Duration,
+    /// `partition.assignment.strategy`: the assignors the consumer can share out its group's
+    /// partitions with, in order of preference. The group uses the first one every member has, and
+    /// rebalances cooperatively only if every assignor supports it. Only the classic group
+    /// protocol uses them.
+    pub partition_assignors: Vec<Arc<dyn PartitionAssignor>>,
+    /// `fetch.min.bytes`: the least data a leader returns for a fetch, unless `fetch.max.wait.ms`
+    /// passes first.
+    pub fetch_min_bytes: usize,
+    /// `fetch.max.bytes`: the most data a leader returns for a fetch. A batch larger than this is
+    /// still returned, so that the consumer can make progress.
+    pub fetch_max_bytes: usize,
+    /// `fetch.max.wait.ms`: how long a leader waits for `fetch.min.bytes` of data.
+    pub fetch_max_wait: Duration,
+    /// `max.partition.fetch.bytes`: the most data a leader returns for one partition.
+    pub max_partition_fetch_bytes: usize,
+    /// `max.poll.records`: the most records one `poll` returns.
+    pub max_poll_records: usize,
+    /// `isolation.level`: whether records of transactions which are open or aborted are read.
+    pub isolation_level: IsolationLevel,
+    /// `check.crcs`: check the CRC of every fetched batch.
+    pub check_crcs: bool,
+    pub retry: RetryPolicy,
+    /// The connections to brokers, including `client.id` and `request.timeout.ms`.
+    pub connection_pool: ConnectionPoolConfig,
+    pub metadata: MetadataCacheConfig
+}
+
+impl ConsumerConfig {
+    pub fn new(bootstrap_servers: &str) -> Self {
+        ConsumerConfig {
+            bootstrap_servers: String::from(bootstrap_servers),
+            client_dns_lookup: ClientDnsLookup::default(),
+            group_id: None,
+            group_instance_id: None,
+            group_protocol: GroupProtocol::Classic,
+            group_remote_assignor: None,
+            enable_auto_commit: true,
+            auto_commit_interval: Duration::from_millis(5_000),
+            auto_offset_reset: OffsetResetStrategy::Latest,
+            session_timeout: Duration::from_millis(45_000),
+            heartbeat_interval: Duration::from_millis(3_000),
+            max_poll_interval: Duration::from_millis(300_000),
+            default_api_timeout: Duration::from_millis(60_000),
+            partition_assignors: vec![Arc::new(RangeAssignor), Arc::new(CooperativeStickyAssignor::new())],
+            fetch_min_bytes: 1,
+            fetch_max_bytes: 52_428_800,
+            fetch_max_wait: Duration::from_millis(500),
+            max_partition_fetch_bytes: 1_048_576,
+            max_poll_records: 500,
+            isolation_level: IsolationLevel::ReadUncommitted,
+            check_crcs: true,
+            retry: RetryPolicy::default(),
+            connection_pool: ConnectionPoolConfig::default(),
+            metadata: MetadataCacheConfig::default()
+        }
+    }
+}
+
+/// Reads records from Kafka. A background thread fetches from the leaders of the assigned
+/// partitions, and keeps fetching the next records while the application processes the ones it
+/// has polled. Keys and values are deserialized as they are polled.
+///
+/// Partitions are either assigned with `assign`, or shared out between the members of the
+/// consumer's group with `subscribe`. A consumer with a `group.id` starts reading a new partition
+/// from its group's committed offset, and other partitions start where `auto.offset.reset` says.
+#[derive(Debug)]
+pub struct KafkaConsumer<K = Vec<u8>, V = Vec<u8>> {
+    config: ConsumerConfig,
+    key_deserializer: Box<dyn Deserializer<K>>,
+    value_deserializer: Box<dyn Deserializer<V>>,
+    metadata: Arc<MetadataCache>,
+    subscriptions: Arc<SubscriptionState>,
+    fetch_buffer: Arc<FetchBuffer>,
+    fetcher_thread: Option<JoinHandle<()>>,
+    offset_fetcher: OffsetFetcher,
+    /// Set when the consumer has a `group.id`.
+    coordinator: Option<Arc<dyn GroupMembership>>,
+    heartbeat_thread: Option<JoinHandle<()>>,
+    /// Set when the consumer has a `group.id`.
+    offsets: Option<Arc<GroupOffsets>>,
+    committer_thread: Option<JoinHandle<()>>,
+    /// Told about the partitions the group gives the consumer and takes away.
+    listener: Mutex<Option<Arc<dyn RebalanceListener<K, V>>>>
+}
+
+impl KafkaConsumer {
+    /// A consumer of records whose keys and values are left as bytes.
+    pub fn new(config: ConsumerConfig) -> Result<Self> {
+        KafkaConsumer::with_deserializers(config, BytesSerde, BytesSerde)
+    }
+}
+
+impl<K, V> KafkaConsumer<K, V> {
+    pub fn with_deserializers<KD, VD>(config: ConsumerConfig, key_deserializer: KD, value_deserializer: VD) -> Result<Self>
+        where KD: Deserializer<K> + 'static, VD: Deserializer<V> + 'static {
+        let mut fetcher: NetworkMetadataFetcher = NetworkMetadataFetcher::new(
+            &config.bootstrap_servers, &config.connection_pool.connection.client_id, config.client_dns_lookup
+        )?;
+        fetcher.connection = config.connection_pool.connection.clone();
+        KafkaConsumer::with_metadata_fetcher_and_deserializers(Box::new(fetcher), config, Box::new(key_deserializer), Box::new(value_deserializer))
+    }
+
+    pub(crate) fn with_metadata_fetcher_and_deserializers(
+        fetcher: Box<dyn MetadataFetcher>,
+        config: ConsumerConfig,
+        key_deserializer: Box<dyn Deserializer<K>>,
+        value_deserializer: Box<dyn Deserializer<V>>
+    ) -> Result<Self> {
+        if config.max_poll_records == 0 {
+            return Err(anyhow!("max.poll.records must be at least 1"));
+        }
+        if config.heartbeat_interval >= config.session_timeout {
+            return Err(anyhow!("heartbeat.interval.ms must be lower than session.timeout.ms"));
+        }
+        if config.partition_assignors.is_empty() {
+            return Err(anyhow!("partition.assignment.strategy must name at least one assignor"));
+        }
+        let metadata: Arc<MetadataCache> = Arc::new(MetadataCache::new(fetcher, config.metadata.clone()));
+        let pool: Arc<ConnectionPool> = Arc::new(ConnectionPool::new(metadata.clone(), config.connection_pool.clone()));
+        let subscriptions: Arc<SubscriptionState> = Arc::new(SubscriptionState::default());
+        let fetch_buffer: Arc<FetchBuffer> = Arc::new(FetchBuffer::default());
+        let fetcher: Fetcher = Fetcher {
+            metadata: metadata.clone(),
+            pool: pool.clone(),
+            subscriptions: subscriptions.clone(),
+            buffer: fetch_buffer.clone(),
+            min_bytes: config.fetch_min_bytes,
+            max_bytes: config.fetch_max_bytes,
+            max_wait: config.fetch_max_wait,
+            max_partition_bytes: config.max_partition_fetch_bytes,
+            isolation_level: config.isolation_level,
+            check_crcs: config.check_crcs,
+            retry_backoff: config.retry.retry_backoff
+        };
+        let offset_fetcher: OffsetFetcher = OffsetFetcher {
+            metadata: metadata.clone(),
+            pool: pool.clone(),
+            isolation_level: config.isolation_level
+        };
+        let fetcher_thread: JoinHandle<()> = thread::Builder::new()
+            .name(String::from("kafkart-consumer-fetcher"))
+            .spawn(move || fetcher.run())?;
+        let coordinator: Option<Arc<dyn GroupMembership>> = match (&config.group_id, config.group_protocol) {
+            (Some(group_id), GroupProtocol::Classic) => Some(Arc::new(ConsumerCoordinator::new(group_id, &config, pool.clone(), metadata.clone())?)),
+            (Some(group_id), GroupProtocol::Consumer) => Some(Arc::new(ConsumerMembership::new(group_id, &config, pool.clone(), metadata.clone()))),
+            (None, _) => None
+        };
+        let offsets: Option<Arc<GroupOffsets>> = config.group_id.as_ref()
+            .map(|group_id| Arc::new(GroupOffsets::new(group_id, &config, pool)));
+        let heartbeat_thread: Option<JoinHandle<()>> = match &coordinator {
+            Some(coordinator) => {
+                let coordinator: Arc<dyn GroupMembership> = coordinator.clone();
+                Some(thread::Builder::new()
+                    .name(String::from("kafkart-consumer-heartbeat"))
+                    .spawn(move || coordinator.run_heartbeats())?)
+            },
+            None => None
+        };
+        let committer_thread: Option<JoinHandle<()>> = match &offsets {
+            Some(offsets) => {
+                let offsets: Arc<GroupOffsets> = offsets.clone();
+                Some(thread::Builder::new()
+                    .name(String::from("kafkart-consumer-committer"))
+                    .spawn(move || offsets.run_async_commits())?)
+            },
+            None => None
+        };
+        Ok(KafkaConsumer {
+            config,
+            key_deserializer,
+            value_deserializer,
+            metadata,
+            subscriptions,
+            fetch_buffer,
+            fetcher_thread: Some(fetcher_thread),
+            offset_fetcher,
+            coordinator,
+            heartbeat_thread,
+            offsets,
+            committer_thread,
+            listener: Mutex::new(None)
+        })
+    }
+
+    /// Joins the consumer's group with a subscription to `topics`, and reads the partitions the
+    /// group assigns to the consumer. The group is joined by the next `poll`, and new partitions
+    /// are read from the group's committed offsets. Subscribing to no topics unsubscribes.
+    pub fn subscribe(&self, topics: &[&str]) -> Result<()> {
+        self.subscribe_with(topics, None)
+    }
+
+    /// Subscribes to `topics` like `subscribe`, and tells `listener` whenever the group gives the
+    /// consumer partitions or takes them away.
+    pub fn subscribe_with_listener(&self, topics: &[&str], listener: Arc<dyn RebalanceListener<K, V>>) -> Result<()> {
+        self.subscribe_with(topics, Some(listener))
+    }
+
+    fn subscribe_with(&self, topics: &[&str], listener: Option<Arc<dyn RebalanceListener<K, V>>>) -> Result<()> {
+        let Some(coordinator) = &self.coordinator else {
+            return Err(anyhow!("Subscribing to topics requires a group.id"));
+        };
+        if topics.is_empty() {
+            self.unsubscribe();
+            return Ok(());
+        }
+        for topic in topics {
+            self.metadata.add_topic(topic);
+        }
+        *self.listener.lock().expect("Rebalance listener lock was poisoned") = listener;
+        coordinator.subscribe(topics.iter().map(|topic| String::from(*topic)).collect());
+        Ok(())
+    }
+
+    /// The topics the consumer subscribed to.
+    pub fn subscription(&self) -> Vec<String> {
+        self.coordinator.as_ref().map(|coordinator| coordinator.subscription()).unwrap_or_default()
+    }
+
+    /// Leaves the group, if the consumer subscribed to topics, and gives up every partition.
+    pub fn unsubscribe(&self) {
+        if let Some(coordinator) = &self.coordinator {
+            coordinator.subscribe(Vec::new());
+            coordinator.leave_group("the consumer unsubscribed", self);
+        }
+        self.assign(&[]);
+    }
+
+    /// The consumer's membership of its group, to send with offsets committed in a transaction.
+    pub fn group_metadata(&self) -> Option<ConsumerGroupMetadata> {
+        self.coordinator.as_ref().map(|coordinator| coordinator.group_metadata())
+    }
+
+    /// Replaces the partitions the consumer reads. Partitions which stay assigned keep their
+    /// positions, and new ones start where `auto.offset.reset` says, unless they are sought first.
+    pub fn assign(&self, partitions: &[TopicPartition]) {
+        for topic_partition in partitions {
+            self.metadata.add_topic(&topic_partition.topic);
+        }
+        self.subscriptions.assign(partitions);
+        self.fetch_buffer.retain(partitions);
+    }
+
+    pub fn assignment(&self) -> Vec<TopicPartition> {
+        self.subscriptions.assigned_partitions()
+    }
+
+    /// Makes the next `poll` read an assigned partition from `offset`.
+    pub fn seek(&self, topic_partition: &TopicPartition, offset: i64) -> Result<()> {
+        if offset < 0 {
+            return Err(anyhow!("Cannot seek to negative offset {}", offset));
+        }
+        if !self.subscriptions.seek(topic_partition, FetchPosition::new(offset)) {
+            return Err(anyhow::Error::new(ConsumerError::NotAssigned { topic: topic_partition.topic.clone(), partition: topic_partition.partition }));
+        }
+        // anything fetched from the old position is stale
+        self.fetch_buffer.remove(topic_partition);
+        Ok(())
+    }
+
+    /// Makes the next `poll` read assigned partitions from their first record.
+    pub fn seek_to_beginning(&self, partitions: &[TopicPartition]) -> Result<()> {
+        self.seek_with_strategy(partitions, OffsetResetStrategy::Earliest)
+    }
+
+    /// Makes the next `poll` read assigned partitions from their end, so that only records
+    /// produced from then on are read.
+    pub fn seek_to_end(&self, partitions: &[TopicPartition]) -> Result<()> {
+        self.seek_with_strategy(partitions, OffsetResetStrategy::Latest)
+    }
+
+    /// The offset of the next record `poll` returns from a partition, if it has a position. A
+    /// partition which is awaiting a reset has none until the next `poll`.
+    pub fn position(&self, topic_partition: &TopicPartition) -> Option<i64> {
+        self.subscriptions.position(topic_partition).map(|position| position.offset)
+    }
+
+    /// The offset of the first record of each partition whose timestamp, in milliseconds since the
+    /// epoch, is at or after the one given. Partitions without such a record map to `None`. Waits
+    /// up to `default.api.timeout.ms`, and the partitions needn't be assigned.
+    pub fn offsets_for_times(&self, timestamps: &HashMap<TopicPartition, i64>) -> Result<HashMap<TopicPartition, Option<OffsetAndTimestamp>>> {
+        if let Some((topic_partition, timestamp)) = timestamps.iter().find(|(_, timestamp)| **timestamp < 0) {
+            return Err(anyhow!("Cannot look up the offset of {:?} for negative timestamp {}", topic_partition, timestamp));
+        }
+        self.list_offsets(timestamps)
+    }
+
+    /// The offset of the first record of each partition. Waits up to `default.api.timeout.ms`.
+    pub fn beginning_offsets(&self, partitions: &[TopicPartition]) -> Result<HashMap<TopicPartition, i64>> {
+        self.offsets_at(partitions, EARLIEST_TIMESTAMP)
+    }
+
+    /// The offset after the last record of each partition, or after the last stable record when
+    /// reading committed records. Waits up to `default.api.timeout.ms`.
+    pub fn end_offsets(&self, partitions: &[TopicPartition]) -> Result<HashMap<TopicPartition, i64>> {
+        self.offsets_at(partitions, LATEST_TIMESTAMP)
+    }
+
+    /// Commits the positions of every assigned partition, and waits for the commit.
+    pub fn commit_sync(&self) -> Result<()> {
+        self.commit_offsets_sync(&self.consumed_offsets())
+    }
+
+    /// Commits `offsets`, which are the offsets of the next records to consume, and waits up to
+    /// `default.api.timeout.ms` for the commit. Asynchronous commits made before are completed,
+    /// and their callbacks run, first.
+    pub fn commit_offsets_sync(&self, offsets: &HashMap<TopicPartition, OffsetAndMetadata>) -> Result<()> {
+        let (group_offsets, group_metadata): (&GroupOffsets, ConsumerGroupMetadata) = self.group()?;
+        group_offsets.wait_for_async_commits();
+        group_offsets.invoke_completed_callbacks();
+        group_offsets.commit(offsets, &group_metadata, &self.api_retry())
+    }
+
+    /// Commits the positions of every assigned partition in the background, and tells `callback`
+    /// whether they were committed during a later `poll`.
+    pub fn commit_async(&self, callback: Option<OffsetCommitCallback>) -> Result<()> {
+        self.commit_offsets_async(self.consumed_offsets(), callback)
+    }
+
+    /// Commits `offsets` in the background, after any commits made before, and tells `callback`
+    /// whether they were committed during a later `poll`. Failed commits aren't retried.
+    pub fn commit_offsets_async(&self, offsets: HashMap<TopicPartition, OffsetAndMetadata>, callback: Option<OffsetCommitCallback>) -> Result<()> {
+        let (group_offsets, group_metadata): (&GroupOffsets, ConsumerGroupMetadata) = self.group()?;
+        group_offsets.commit_async(offsets, group_metadata, callback);
+        Ok(())
+    }
+
+    /// The offsets the consumer's group has committed for `partitions`. Partitions without a
+    /// committed offset are left out.
+    pub fn committed(&self, partitions: &[TopicPartition]) -> Result<HashMap<TopicPartition, OffsetAndMetadata>> {
+        let (group_offsets, group_metadata): (&GroupOffsets, ConsumerGroupMetadata) = self.group()?;
+        group_offsets.fetch_committed(partitions, &group_metadata, &self.api_retry())
+    }
+
+    /// Returns up to `max.poll.records` fetched records, waiting up to `timeout` for some to arrive.
+    /// Positions move past the returned records. A partition which failed to fetch, or a record
+    /// which can't be deserialized, fails the `poll` which reaches it, and every `poll` after until
+    /// the consumer seeks past it.
+    ///
+    /// A consumer which subscribed to topics joins its group first if it must, which can take
+    /// longer than `timeout` while the group rebalances. Partitions without a position start from
+    /// their group's committed offset, or are reset with `auto.offset.reset`, as are partitions
+    /// whose position is out of range. The callbacks of completed asynchronous commits run.
+    pub fn poll(&self, timeout: Duration) -> Result<Vec<ConsumerRecord<K, V>>> {
+        let deadline: Instant = Instant::now() + timeout;
+        loop {
+            if let Some(coordinator) = &self.coordinator {
+                coordinator.poll(self)?;
+            }
+            if let Some(offsets) = &self.offsets {
+                offsets.invoke_completed_callbacks();
+                if offsets.auto_commit_due() {
+                    self.commit_async(None)?;
+                }
+            }
+            self.update_fetch_positions(deadline)?;
+            self.reset_positions(deadline)?;
+            let records: Vec<ConsumerRecord<K, V>> = self.collect_records()?;
+            let now: Instant = Instant::now();
+            if !records.is_empty() || now >= deadline {
+                return Ok(records);
+            }
+            self.fetch_buffer.wait_for_fetches(deadline - now);
+        }
+    }
+
+    /// Consumes records from the fetch buffer. An error is returned only if no records come before it.
+    fn collect_records(&self) -> Result<Vec<ConsumerRecord<K, V>>, ConsumerError> {
+        let mut records: Vec<ConsumerRecord<K, V>> = Vec::new();
+        while records.len() < self.config.max_poll_records {
+            let Some(mut fetch) = self.fetch_buffer.take() else {
+                break;
+            };
+            let position: Option<FetchPosition> = self.subscriptions.position(&fetch.topic_partition);
+            if position.map(|position| position.offset) != Some(fetch.next_offset) {
+                debug!("Discarding records fetched for {:?}, since its position has moved", fetch.topic_partition);
+                self.fetch_buffer.put_back(None);
+                continue;
+            }
+            if let Some(error) = fetch.pending_error() {
+                if matches!(error, ConsumerError::OffsetOutOfRange { .. }) && self.config.auto_offset_reset != OffsetResetStrategy::None {
+                    info!("{}, so resetting it with {:?}", error, self.config.auto_offset_reset);
+                    self.subscriptions.request_reset(&fetch.topic_partition, self.config.auto_offset_reset);
+                    self.fetch_buffer.put_back(None);
+                    continue;
+                }
+                if records.is_empty() {
+                    self.fetch_buffer.put_back(None);
+                    return Err(error);
+                }
+                self.fetch_buffer.put_back(Some(fetch));
+                break;
+            }
+            let drained: Result<bool, ConsumerError> = self.drain(&mut fetch, &mut records);
+            self.subscriptions.seek(&fetch.topic_partition, FetchPosition { offset: fetch.next_offset, leader_epoch: fetch.leader_epoch });
+            match drained {
+                Ok(true) if fetch.error.is_none() => self.fetch_buffer.put_back(None),
+                // a fetch which ends at an unreadable batch stays to raise its error
+                Ok(_) => self.fetch_buffer.put_back(Some(fetch)),
+                Err(error) => {
+                    // the record stays next, so the error comes back until the consumer seeks past it
+                    self.fetch_buffer.put_back(Some(fetch));
+                    if records.is_empty() {
+                        return Err(error);
+                    }
+                    break;
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Moves records from a fetch into `records` until it is full. Returns whether the fetch has
+    /// no records left.
+    fn drain(&self, fetch: &mut CompletedFetch, records: &mut Vec<ConsumerRecord<K, V>>) -> Result<bool, ConsumerError> {
+        let topic_partition: TopicPartition = fetch.topic_partition.clone();
+        while records.len() < self.config.max_poll_records {
+            let Some((batch, record)) = fetch.peek() else {
+                return Ok(true);
+            };
+            let record: ConsumerRecord<K, V> = self.deserialize(&topic_partition.topic, topic_partition.partition, batch, record)?;
+            fetch.advance(record.offset);
+            records.push(record);
+        }
+        Ok(fetch.peek().is_none())
+    }
+
+    /// Deserializes a record from a fetched batch. A record which can't be deserialized fails on
+    /// its own, so that the consumer can seek past it to the records after it.
+    fn deserialize(&self, topic: &str, partition: i32, batch: &RecordBatch, record: &Record) -> Result<ConsumerRecord<K, V>, ConsumerError> {
+        let offset: i64 = batch.base_offset + record.offset_delta.0 as i64;
+        let headers: Vec<Header> = record.headers.0.iter()
+            .map(|header| (header.key.0.clone(), header.value.0.clone()))
+            .collect();
+        let error = |field: &'static str, error: SerializationError| ConsumerError::Deserialization {
+            topic: String::from(topic), partition, offset, field, error
+        };
+        let key: Option<K> = match &record.key.0 {
+            Some(key) => Some(self.key_deserializer.deserialize(topic, &headers, key).map_err(|e| error("key", e))?),
+            None => None
+        };
+        let value: Option<V> = match &record.value.0 {
+            Some(value) => Some(self.value_deserializer.deserialize(topic, &headers, value).map_err(|e| error("value", e))?),
+            None => None
+        };
+        let timestamp: i64 = if batch.is_log_append_time() {
+            batch.max_timestamp
+        } else {
+            batch.base_timestamp + record.timestamp_delta.0
+        };
+        Ok(ConsumerRecord {
+            topic: String::from(topic),
+            partition,
+            offset,
+            timestamp,
+            headers,
+            key,
+            value
+        })
+    }
+
+    /// Commits the consumer's positions if `enable.auto.commit` is on, leaves its group and stops
+    /// the background threads.
+    pub fn close(self) {}
+}
+
+impl<K, V> KafkaConsumer<K, V> {
+    /// The consumer's group offsets and its current membership, which commits are fenced with.
+    fn group(&self) -> Result<(&GroupOffsets, ConsumerGroupMetadata)> {
+        match (&self.offsets, &self.coordinator) {
+            (Some(offsets), Some(coordinator)) => Ok((offsets, coordinator.group_metadata())),
+            _ => Err(anyhow!("Committing and fetching offsets requires a group.id"))
+        }
+    }
+
+    /// Retries requests the application waits for until `default.api.timeout.ms` passes.
+    fn api_retry(&self) -> RetryPolicy {
+        RetryPolicy { delivery_timeout: self.config.default_api_timeout, ..self.config.retry.clone() }
+    }
+
+    /// The position of every assigned partition which has one, as offsets to commit.
+    fn consumed_offsets(&self) -> HashMap<TopicPartition, OffsetAndMetadata> {
+        self.subscriptions.fetchable_partitions().into_iter()
+            .map(|(topic_partition, position)| (topic_partition, OffsetAndMetadata {
+                offset: position.offset,
+                leader_epoch: position.leader_epoch,
+                metadata: String::new()
+            }))
+            .collect()
+    }
+
+    /// Commits the positions of `partitions` before the consumer gives them up, if
+    /// `enable.auto.commit` is on.
+    fn auto_commit_sync(&self, partitions: &[TopicPartition]) {
+        if !self.offsets.as_ref().is_some_and(|offsets| offsets.auto_commit_enabled()) {
+            return;
+        }
+        let offsets: HashMap<TopicPartition, OffsetAndMetadata> = self.consumed_offsets().into_iter()
+            .filter(|(topic_partition, _)| partitions.contains(topic_partition))
+            .collect();
+        if let Err(e) = self.commit_offsets_sync(&offsets) {
+            warn!("Failed to commit the offsets of partitions {:?}: {:#}", partitions, e);
+        }
+    }
+
+    /// Gives the assigned partitions without a position one: their group's committed offset, if
+    /// there is one. The rest wait for a reset. A coordinator which can't be reached before
+    /// `deadline` is tried again on the next poll.
+    fn update_fetch_positions(&self, deadline: Instant) -> Result<()> {
+        let partitions: Vec<TopicPartition> = self.subscriptions.partitions_needing_position();
+        if partitions.is_empty() {
+            return Ok(());
+        }
+        let Ok((offsets, group_metadata)) = self.group() else {
+            for topic_partition in &partitions {
+                self.subscriptions.request_reset(topic_partition, self.config.auto_offset_reset);
+            }
+            return Ok(());
+        };
+        let policy: RetryPolicy = RetryPolicy { delivery_timeout: deadline.saturating_duration_since(Instant::now()), ..self.config.retry.clone() };
+        let committed: HashMap<TopicPartition, OffsetAndMetadata> = match offsets.fetch_committed(&partitions, &group_metadata, &policy) {
+            Ok(committed) => committed,
+            Err(e) if retry::is_retriable(&e) => {
+                debug!("Will fetch the committed offsets of {:?} again: {:#}", partitions, e);
+                return Ok(());
+            },
+            Err(e) => return Err(e)
+        };
+        for topic_partition in &partitions {
+            match committed.get(topic_partition) {
+                Some(offset) => {
+                    debug!("Reading {:?} from its committed offset {}", topic_partition, offset.offset);
+                    self.subscriptions.seek(topic_partition, FetchPosition { offset: offset.offset, leader_epoch: offset.leader_epoch });
+                },
+                None => self.subscriptions.request_reset(topic_partition, self.config.auto_offset_reset)
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the partitions awaiting a reset to their beginning or end. Leaders which can't be
+    /// reached before `deadline` are tried again on the next poll.
+    fn reset_positions(&self, deadline: Instant) -> Result<()> {
+        let partitions: Vec<(TopicPartition, OffsetResetStrategy)> = self.subscriptions.partitions_awaiting_reset();
+        if partitions.is_empty() {
+            return Ok(());
+        }
+        let mut timestamps: HashMap<TopicPartition, i64> = HashMap::new();
+        for (topic_partition, strategy) in partitions {
+            let timestamp: i64 = match strategy {
+                OffsetResetStrategy::Earliest => EARLIEST_TIMESTAMP,
+                OffsetResetStrategy::Latest => LATEST_TIMESTAMP,
+                OffsetResetStrategy::None => return Err(anyhow::Error::new(ConsumerError::NoOffsetForPartition {
+                    topic: topic_partition.topic,
+                    partition: topic_partition.partition
+                }))
+            };
+            timestamps.insert(topic_partition, timestamp);
+        }
+        let policy: RetryPolicy = RetryPolicy { delivery_timeout: deadline.saturating_duration_since(Instant::now()), ..self.config.retry.clone() };
+        let offsets: HashMap<TopicPartition, Option<OffsetAndTimestamp>> = match self.offset_fetcher.list_offsets(&timestamps, &policy) {
+            Ok(offsets) => offsets,
+            Err(e) if retry::is_retriable(&e) => {
+                debug!("Will reset the positions of {:?} again: {:#}", timestamps.keys(), e);
+                return Ok(());
+            },
+            Err(e) => return Err(e)
+        };
+        for (topic_partition, offset) in offsets {
+            // the earliest and latest offsets are always found
+            let Some(offset) = offset else {
+                continue;
+            };
+            info!("Resetting the position of {:?} to offset {}", topic_partition, offset.offset);
+            self.subscriptions.seek(&topic_partition, FetchPosition { offset: offset.offset, leader_epoch: offset.leader_epoch });
+        }
+        Ok(())
+    }
+
+    /// Marks assigned partitions to be reset with `strategy` by the next `poll`.
+    fn seek_with_strategy(&self, partitions: &[TopicPartition], strategy: OffsetResetStrategy) -> Result<()> {
+        if let Some(topic_partition) = partitions.iter().find(|topic_partition| !self.subscriptions.is_assigned(topic_partition)) {
+            return Err(anyhow::Error::new(ConsumerError::NotAssigned { topic: topic_partition.topic.clone(), partition: topic_partition.partition }));
+        }
+        for topic_partition in partitions {
+            self.subscriptions.request_reset(topic_partition, strategy);
+            self.fetch_buffer.remove(topic_partition);
+        }
+        Ok(())
+    }
+
+    /// Looks up offsets by timestamp for the application, waiting up to `default.api.timeout.ms`.
+    fn list_offsets(&self, timestamps: &HashMap<TopicPartition, i64>) -> Result<HashMap<TopicPartition, Option<OffsetAndTimestamp>>> {
+        for topic_partition in timestamps.keys() {
+            self.metadata.add_topic(&topic_partition.topic);
+        }
+        self.offset_fetcher.list_offsets(timestamps, &self.api_retry())
+    }
+
+    /// The offsets of `partitions` at `EARLIEST_TIMESTAMP` or `LATEST_TIMESTAMP`.
+    fn offsets_at(&self, partitions: &[TopicPartition], timestamp: i64) -> Result<HashMap<TopicPartition, i64>> {
+        let timestamps: HashMap<TopicPartition, i64> = partitions.iter()
+            .map(|topic_partition| (topic_partition.clone(), timestamp))
+            .collect();
+        Ok(self.list_offsets(&timestamps)?.into_iter()
+            .filter_map(|(topic_partition, offset)| offset.map(|offset| (topic_partition, offset.offset)))
+            .collect())
+    }
+
+    fn listener(&self) -> Option<Arc<dyn RebalanceListener<K, V>>> {
+        self.listener.lock().expect("Rebalance listener lock was poisoned").clone()
+    }
+
+    /// Stops reading `partitions`, and keeps the rest of the assignment.
+    fn unassign(&self, partitions: &[TopicPartition]) {
+        let assignment: Vec<TopicPartition> = self.assignment().into_iter()
+            .filter(|topic_partition| !partitions.contains(topic_partition))
+            .collect();
+        self.assign(&assignment);
+    }
+}
+
+impl<K, V> RebalanceHandler for KafkaConsumer<K, V> {
+    fn revoke(&self, partitions: &[TopicPartition]) {
+        self.auto_commit_sync(partitions);
+        if let Some(listener) = self.listener() {
+            listener.on_partitions_revoked(self, partitions);
+        }
+        self.unassign(partitions);
+    }
+
+    fn lose(&self, partitions: &[TopicPartition]) {
+        if let Some(listener) = self.listener() {
+            listener.on_partitions_lost(self, partitions);
+        }
+        self.unassign(partitions);
+    }
+
+    fn assign(&self, assignment: &[TopicPartition], added: &[TopicPartition]) {
+        KafkaConsumer::assign(self, assignment);
+        if let Some(listener) = self.listener() {
+            listener.on_partitions_assigned(self, added);
+        }
+    }
+}
+
+impl<K, V> Drop for KafkaConsumer<K, V> {
+    fn drop(&mut self) {
+        if let Some(offsets) = &self.offsets {
+            offsets.close();
+        }
+        if let Some(committer_thread) = self.committer_thread.take() {
+            let _ = committer_thread.join();
+        }
+        // a subscribed consumer commits as its partitions are revoked on leaving the group
+        if self.subscription().is_empty() {
+            self.auto_commit_sync(&self.assignment());
+        }
+        if let Some(coordinator) = &self.coordinator {
+            coordinator.close(&*self);
+        }
+        if let Some(offsets) = &self.offsets {
+            offsets.invoke_completed_callbacks();
+        }
+        if let Some(heartbeat_thread) = self.heartbeat_thread.take() {
+            let _ = heartbeat_thread.join();
+        }
+        self.fetch_buffer.close();
+        if let Some(fetcher_thread) = self.fetcher_thread.take() {
+            let _ = fetcher_thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use kafka_encode::primitives::{CompactArray, CompactNullableArray, VarIntNullableBytes, VarIntString};
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::cluster::tests::mock_broker_cluster as cluster;
+    use crate::consumer::{ConsumerConfig, ConsumerError, ConsumerRecord, KafkaConsumer, OffsetAndTimestamp, OffsetResetStrategy};
+    use crate::consumer::consumer_coordinator::tests::{MockGroup, group_broker, group_cluster};
+    use crate::consumer::rebalance_listener::RebalanceListener;
+    use crate::coordinator::OffsetAndMetadata;
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::fetch::{FetchPartitionDataV12V13, FetchPartitionDataV12V13TaggedFields, FetchRequestV12, FetchResponseV12, FetchableTopicResponseV12};
+    use crate::protocol::list_offsets::{EARLIEST_TIMESTAMP, LATEST_TIMESTAMP, ListOffsetsRequestV6V7, ListOffsetsResponsePartitionV6V7, ListOffsetsResponseTopicV6V7, ListOffsetsResponseV6V7};
+    use crate::protocol::compression::CompressionType;
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::records::{CompactRecords, Record, RecordBatch, RecordHeader};
+    use crate::protocol::records::tests::compressed;
+    use crate::protocol::tags::TaggedFields;
+    use crate::retry::RetryPolicy;
+    use crate::serialization::{BytesSerde, IntegerSerde, StringSerde};
+
+    /// The partition and offset of each fetch a `log_broker` has received.
+    type Fetches = Arc<Mutex<Vec<(i32, i64)>>>;
+
+    /// The batch a `log_broker` returns for a fetch from `offset`: two records, or one at the end
+    /// of the log. Values name their partition and offset.
+    fn log_batch(partition: i32, offset: i64, count: i64) -> RecordBatch {
+        let records: Vec<Record> = (offset..count.min(offset + 2))
+            .map(|record_offset| {
+                let header: RecordHeader = RecordHeader { key: VarIntString(String::from("h")), value: VarIntNullableBytes(Some(vec![partition as u8])) };
+                let value: Vec<u8> = format!("{}-{}", partition, record_offset).into_bytes();
+                Record::new((record_offset - offset) as i32, record_offset - offset, None, Some(value), vec![header]).unwrap()
+            })
+            .collect();
+        let mut batch: RecordBatch = RecordBatch::new(1000 + offset, records);
+        batch.base_offset = offset;
+        batch.partition_leader_epoch = 0;
+        batch.seal().unwrap()
+    }
+
+    /// A broker whose partitions each hold `count` records, and which keeps the partition and offset
+    /// of every fetch. Fetches from the end of the log return nothing after a short wait, as if
+    /// `fetch.max.wait.ms` passed, and offsets from 100 are out of range. Record timestamps are
+    /// 1000 plus their offset.
+    fn log_broker(count: i64) -> (MockBroker, Fetches) {
+        encoded_log_broker(count, |_, batch| vec![batch])
+    }
+
+    /// A `log_broker` which passes each batch through `encode`, which returns the batches to send
+    /// in its place.
+    fn encoded_log_broker(count: i64, encode: fn(i32, RecordBatch) -> Vec<RecordBatch>) -> (MockBroker, Fetches) {
+        let fetches: Fetches = Arc::new(Mutex::new(Vec::new()));
+        let broker_fetches: Fetches = fetches.clone();
+        let broker: MockBroker = MockBroker::start(move |request: &MockRequest| {
+            if request.api_key == ApiKey::ListOffsets as i16 {
+                return Some(list_offsets(request, count));
+            }
+            let fetch_request: FetchRequestV12 = request.decode().unwrap();
+            let mut fetched_records: bool = false;
+            let responses: Vec<FetchableTopicResponseV12> = fetch_request.topics.0.into_iter()
+                .map(|topic| FetchableTopicResponseV12 {
+                    topic: topic.topic,
+                    partitions: CompactArray(topic.partitions.0.into_iter()
+                        .map(|partition| {
+                            broker_fetches.lock().unwrap().push((partition.partition, partition.fetch_offset));
+                            let (error_code, batches): (ErrorCode, Vec<RecordBatch>) = match partition.fetch_offset {
+                                offset if offset >= 100 => (ErrorCode::OffsetOutOfRange, vec![]),
+                                offset if offset < count => (ErrorCode::None, encode(partition.partition, log_batch(partition.partition, offset, count))),
+                                _ => (ErrorCode::None, vec![])
+                            };
+                            fetched_records |= !batches.is_empty();
+                            FetchPartitionDataV12V13 {
+                                partition_index: partition.partition,
+                                error_code,
+                                high_watermark: count,
+                                last_stable_offset: count,
+                                log_start_offset: 0,
+                                aborted_transactions: CompactNullableArray(None),
+                                preferred_read_replica: -1,
+                                records: CompactRecords(Some(batches)),
+                                tag_buffer: FetchPartitionDataV12V13TaggedFields::new()
+                            }
+                        })
+                        .collect()),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect();
+            if !fetched_records {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Some(request.respond::<FetchRequestV12, FetchResponseV12>(FetchResponseV12 {
+                throttle_time_ms: 0,
+                error_code: ErrorCode::None,
+                session_id: 0,
+                responses: CompactArray(responses),
+                tag_buffer: TaggedFields::new()
+            }))
+        });
+        (broker, fetches)
+    }
+
+    /// Answers a ListOffsets request for partitions which each hold `count` records.
+    fn list_offsets(request: &MockRequest, count: i64) -> Vec<u8> {
+        let list_offsets: ListOffsetsRequestV6V7 = request.decode().unwrap();
+        let topics: Vec<ListOffsetsResponseTopicV6V7> = list_offsets.topics.0.into_iter()
+            .map(|topic| ListOffsetsResponseTopicV6V7 {
+                name: topic.name,
+                partitions: CompactArray(topic.partitions.0.into_iter()
+                    .map(|partition| {
+                        let (timestamp, offset): (i64, i64) = match partition.timestamp {
+                            EARLIEST_TIMESTAMP => (-1, 0),
+                            LATEST_TIMESTAMP => (-1, count),
+                            timestamp if timestamp - 1000 >= count => (-1, -1),
+                            timestamp => (timestamp.max(1000), (timestamp - 1000).max(0))
+                        };
+                        ListOffsetsResponsePartitionV6V7 {
+                            partition_index: partition.partition_index,
+                            error_code: ErrorCode::None,
+                            timestamp,
+                            offset,
+                            leader_epoch: 0,
+                            tag_buffer: TaggedFields::new()
+                        }
+                    })
+                    .collect()),
+                tag_buffer: TaggedFields::new()
+            })
+            .collect();
+        request.respond::<ListOffsetsRequestV6V7, ListOffsetsResponseV6V7>(ListOffsetsResponseV6V7 {
+            throttle_time_ms: 0,
+            topics: CompactArray(topics),
+            tag_buffer: TaggedFields::new()
+        })
+    }
+
+    fn config() -> ConsumerConfig {
+        ConsumerConfig {
+            retry: RetryPolicy {
+                retry_backoff: Duration::from_millis(10),
+                ..RetryPolicy::default()
+            },
+            ..ConsumerConfig::new("unused:9092")
+        }
+    }
+
+    fn consumer(cluster: ClusterMetadata, config: ConsumerConfig) -> KafkaConsumer<Vec<u8>, String> {
+        KafkaConsumer::with_metadata_fetcher_and_deserializers(Box::new(StaticFetcher(cluster)), config, Box::new(BytesSerde), Box::new(StringSerde)).unwrap()
+    }
+
+    /// Polls until `count` records have been returned, or five seconds have passed.
+    fn poll_records(consumer: &KafkaConsumer<Vec<u8>, String>, count: usize) -> Vec<ConsumerRecord<Vec<u8>, String>> {
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        let mut records: Vec<ConsumerRecord<Vec<u8>, String>> = Vec::new();
+        while records.len() < count && Instant::now() < deadline {
+            records.extend(consumer.poll(Duration::from_millis(100)).unwrap());
+        }
+        records
+    }
+
+    #[test]
+    fn test_records_are_deserialized_one_at_a_time() {
+        let consumer: KafkaConsumer<i32, String> = KafkaConsumer::with_metadata_fetcher_and_deserializers(
+            Box::new(StaticFetcher(ClusterMetadata::default())), config(), Box::new(IntegerSerde), Box::new(StringSerde)
+        ).unwrap();
+        let records: Vec<Record> = vec![
+            Record::new(0, 0, Some(vec![0, 0, 0, 7]), Some(b"seven".to_vec()), vec![]).unwrap(),
+            Record::new(1, 5, None, Some(vec![0xff]), vec![]).unwrap(),
+            Record::new(2, 10, Some(vec![0, 0, 0, 9]), None, vec![]).unwrap()
+        ];
+        let mut batch: RecordBatch = RecordBatch::new(1000, records.clone());
+        batch.base_offset = 40;
+
+        let first: ConsumerRecord<i32, String> = consumer.deserialize("foo", 0, &batch, &records[0]).unwrap();
+        assert_eq!((first.offset, first.timestamp, first.key, first.value), (40, 1000, Some(7), Some(String::from("seven"))));
+        let invalid: ConsumerError = consumer.deserialize("foo", 0, &batch, &records[1]).unwrap_err();
+        assert!(matches!(invalid, ConsumerError::Deserialization { offset: 41, field: "value", .. }));
+        // a tombstone has no value to deserialize
+        let tombstone: ConsumerRecord<i32, String> = consumer.deserialize("foo", 0, &batch, &records[2]).unwrap();
+        assert_eq!((tombstone.offset, tombstone.timestamp, tombstone.key, tombstone.value), (42, 1010, Some(9), None));
+    }
+
+    #[test]
+    fn test_poll_fetches_from_the_leader_of_each_partition() {
+        let (first_broker, first_fetches) = log_broker(3);
+        let (second_broker, second_fetches) = log_broker(3);
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &first_broker), (2, &second_broker)], &[1, 2]), config());
+        let partitions: Vec<TopicPartition> = vec![TopicPartition::new("foo", 0), TopicPartition::new("foo", 1)];
+        consumer.assign(&partitions);
+        assert_eq!(consumer.assignment(), partitions);
+        // new partitions start from the end of the log
+        assert!(consumer.poll(Duration::from_millis(50)).unwrap().is_empty());
+        assert_eq!((consumer.position(&partitions[0]), consumer.position(&partitions[1])), (Some(3), Some(3)));
+        consumer.seek(&partitions[0], 0).unwrap();
+        consumer.seek(&partitions[1], 1).unwrap();
+
+        let records: Vec<ConsumerRecord<Vec<u8>, String>> = poll_records(&consumer, 5);
+        let mut values: Vec<(i32, i64, String)> = records.iter()
+            .map(|record| (record.partition, record.offset, record.value.clone().unwrap()))
+            .collect();
+        values.sort();
+        assert_eq!(values, vec![
+            (0, 0, String::from("0-0")), (0, 1, String::from("0-1")), (0, 2, String::from("0-2")),
+            (1, 1, String::from("1-1")), (1, 2, String::from("1-2"))
+        ]);
+        let record: &ConsumerRecord<Vec<u8>, String> = records.iter().find(|record| record.partition == 0 && record.offset == 2).unwrap();
+        assert_eq!((record.timestamp, record.headers.clone()), (1002, vec![(String::from("h"), Some(vec![0]))]));
+        assert_eq!((consumer.position(&partitions[0]), consumer.position(&partitions[1])), (Some(3), Some(3)));
+        let first_fetches: Vec<(i32, i64)> = first_fetches.lock().unwrap().iter().filter(|(_, offset)| *offset != 3).cloned().collect();
+        let second_fetches: Vec<(i32, i64)> = second_fetches.lock().unwrap().iter().filter(|(_, offset)| *offset != 3).cloned().collect();
+        assert_eq!(first_fetches[..2], [(0, 0), (0, 2)]);
+        assert_eq!(second_fetches[0], (1, 1));
+    }
+
+    #[test]
+    fn test_the_next_records_are_fetched_before_the_application_polls() {
+        let (broker, fetches) = log_broker(4);
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &broker)], &[1]), ConsumerConfig { max_poll_records: 2, ..config() });
+        let partition: TopicPartition = TopicPartition::new("foo", 0);
+        consumer.assign(std::slice::from_ref(&partition));
+        consumer.seek(&partition, 0).unwrap();
+        let offsets: Vec<i64> = poll_records(&consumer, 2).iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, vec![0, 1]);
+
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        while !consumer.fetch_buffer.partitions().contains(&partition) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let offsets: Vec<i64> = consumer.poll(Duration::ZERO).unwrap().iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, vec![2, 3]);
+        assert_eq!(fetches.lock().unwrap()[..2], [(0, 0), (0, 2)]);
+    }
+
+    #[test]
+    fn test_offset_out_of_range_fails_polls_until_the_consumer_seeks() {
+        let (broker, _) = log_broker(3);
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &broker)], &[1]), ConsumerConfig { auto_offset_reset: OffsetResetStrategy::None, ..config() });
+        let partition: TopicPartition = TopicPartition::new("foo", 0);
+        let error: anyhow::Error = consumer.seek(&partition, 0).unwrap_err();
+        assert_eq!(error.downcast_ref::<ConsumerError>(), Some(&ConsumerError::NotAssigned { topic: String::from("foo"), partition: 0 }));
+        consumer.assign(std::slice::from_ref(&partition));
+        consumer.seek(&partition, 100).unwrap();
+
+        for _ in 0..2 {
+            let error: anyhow::Error = consumer.poll(Duration::from_secs(5)).unwrap_err();
+            assert_eq!(error.downcast_ref::<ConsumerError>(), Some(&ConsumerError::OffsetOutOfRange { topic: String::from("foo"), partition: 0, offset: 100 }));
+        }
+        consumer.seek(&partition, 1).unwrap();
+        let offsets: Vec<i64> = poll_records(&consumer, 2).iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, vec![1, 2]);
+
+        // a partition without a position can't be reset either
+        consumer.assign(&[TopicPartition::new("foo", 0), TopicPartition::new("foo", 1)]);
+        let error: anyhow::Error = consumer.poll(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(error.downcast_ref::<ConsumerError>(), Some(&ConsumerError::NoOffsetForPartition { topic: String::from("foo"), partition: 1 }));
+    }
+
+    #[test]
+    fn test_compressed_batches_are_read_and_unreadable_ones_fail_their_partition_after_the_records_before_them() {
+        // partition 0 is compressed with gzip, and partition 1 from offset 2 on with a codec which
+        // doesn't exist
+        let (broker, _) = encoded_log_broker(4, |partition, batch| {
+            let unreadable: RecordBatch = RecordBatch {
+                base_offset: 2,
+                attributes: 0x07,
+                ..compressed(batch.clone(), CompressionType::Lz4)
+            };
+            match (partition, batch.base_offset) {
+                (0, _) => vec![compressed(batch, CompressionType::Gzip).seal().unwrap()],
+                (_, 0) => vec![batch, unreadable.seal().unwrap()],
+                _ => vec![unreadable.seal().unwrap()]
+            }
+        });
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &broker)], &[1, 1]), config());
+        let partitions: Vec<TopicPartition> = vec![TopicPartition::new("foo", 0), TopicPartition::new("foo", 1)];
+        consumer.assign(&partitions[..1]);
+        consumer.seek(&partitions[0], 0).unwrap();
+        let values: Vec<String> = poll_records(&consumer, 4).into_iter().map(|record| record.value.unwrap()).collect();
+        assert_eq!(values, vec![String::from("0-0"), String::from("0-1"), String::from("0-2"), String::from("0-3")]);
+
+        consumer.assign(&partitions[1..]);
+        consumer.seek(&partitions[1], 0).unwrap();
+        let values: Vec<String> = poll_records(&consumer, 2).into_iter().map(|record| record.value.unwrap()).collect();
+        assert_eq!(values, vec![String::from("1-0"), String::from("1-1")]);
+        for _ in 0..2 {
+            let error: anyhow::Error = consumer.poll(Duration::from_secs(5)).unwrap_err();
+            assert_eq!(error.downcast_ref::<ConsumerError>(), Some(&ConsumerError::UnreadableRecordBatch {
+                topic: String::from("foo"),
+                partition: 1,
+                offset: 2,
+                message: String::from("Unsupported record batch compression codec: 7")
+            }));
+        }
+        assert_eq!(consumer.position(&partitions[1]), Some(2));
+    }
+
+    #[test]
+    fn test_batches_failing_their_crc_check_fail_only_fetches_which_read_them() {
+        // the batch at offset 0 is corrupt, and fetches from offset 2 start with it too
+        let (broker, _) = encoded_log_broker(4, |partition, batch| {
+            let corrupt = |batch: RecordBatch| RecordBatch { crc: batch.crc ^ 1, ..batch };
+            match batch.base_offset {
+                0 => vec![corrupt(batch)],
+                _ => vec![corrupt(log_batch(partition, 0, 4)), batch]
+            }
+        });
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &broker)], &[1]), config());
+        let partition: TopicPartition = TopicPartition::new("foo", 0);
+        consumer.assign(std::slice::from_ref(&partition));
+        consumer.seek(&partition, 0).unwrap();
+        let error: anyhow::Error = consumer.poll(Duration::from_secs(5)).unwrap_err();
+        assert_eq!(error.downcast_ref::<ConsumerError>(), Some(&ConsumerError::CorruptRecord { topic: String::from("foo"), partition: 0, offset: 0 }));
+
+        consumer.seek(&partition, 2).unwrap();
+        let offsets: Vec<i64> = poll_records(&consumer, 2).iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_positions_are_reset_when_out_of_range_and_by_seeking_to_either_end() {
+        let (broker, _) = log_broker(3);
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &broker)], &[1]), ConsumerConfig { auto_offset_reset: OffsetResetStrategy::Earliest, ..config() });
+        let partition: TopicPartition = TopicPartition::new("foo", 0);
+        assert!(consumer.seek_to_end(std::slice::from_ref(&partition)).is_err());
+        consumer.assign(std::slice::from_ref(&partition));
+        consumer.seek(&partition, 100).unwrap();
+
+        let offsets: Vec<i64> = poll_records(&consumer, 3).iter().map(|record| record.offset).collect();
+        assert_eq!(offsets, vec![0, 1, 2]);
+        consumer.seek_to_end(std::slice::from_ref(&partition)).unwrap();
+        assert_eq!(consumer.position(&partition), None);
+        assert!(consumer.poll(Duration::from_millis(50)).unwrap().is_empty());
+        assert_eq!(consumer.position(&partition), Some(3));
+        consumer.seek_to_beginning(std::slice::from_ref(&partition)).unwrap();
+        assert_eq!(poll_records(&consumer, 1)[0].offset, 0);
+    }
+
+    #[test]
+    fn test_offsets_are_looked_up_by_timestamp_for_unassigned_partitions() {
+        let (broker, _) = log_broker(3);
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(cluster("foo", &[(1, &broker)], &[1, 1]), config());
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        let foo_1: TopicPartition = TopicPartition::new("foo", 1);
+        let partitions: Vec<TopicPartition> = vec![foo_0.clone(), foo_1.clone()];
+
+        let beginning: HashMap<TopicPartition, i64> = consumer.beginning_offsets(&partitions).unwrap();
+        let end: HashMap<TopicPartition, i64> = consumer.end_offsets(&partitions).unwrap();
+        assert_eq!((beginning[&foo_0], beginning[&foo_1], end[&foo_0], end[&foo_1]), (0, 0, 3, 3));
+        let offsets: HashMap<TopicPartition, Option<OffsetAndTimestamp>> = consumer.offsets_for_times(&HashMap::from([(foo_0.clone(), 1001), (foo_1.clone(), 5000)])).unwrap();
+        assert_eq!(offsets[&foo_0], Some(OffsetAndTimestamp { offset: 1, timestamp: 1001, leader_epoch: Some(0) }));
+        assert_eq!(offsets[&foo_1], None);
+        assert!(consumer.offsets_for_times(&HashMap::from([(foo_0, -1)])).is_err());
+    }
+
+    #[test]
+    fn test_subscribed_consumer_reads_the_partitions_its_group_assigns() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        assert!(consumer(group_cluster(&broker, 2), config()).subscribe(&["foo"]).is_err());
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(group_cluster(&broker, 2), ConsumerConfig { group_id: Some(String::from("group")), ..config() });
+        consumer.subscribe(&["foo"]).unwrap();
+        assert_eq!(consumer.subscription(), vec![String::from("foo")]);
+
+        assert!(consumer.poll(Duration::from_millis(10)).unwrap().is_empty());
+        assert_eq!(consumer.assignment(), vec![TopicPartition::new("foo", 0), TopicPartition::new("foo", 1)]);
+        assert_eq!(consumer.group_metadata().unwrap().member_id, "member-1");
+        consumer.unsubscribe();
+        assert!(consumer.assignment().is_empty());
+        consumer.close();
+        assert_eq!(group.lock().unwrap().leaves.len(), 1);
+    }
+
+    /// Starts every assigned partition from offset 0, and records the partitions it is told about.
+    #[derive(Debug, Default)]
+    struct SeekingListener {
+        events: Mutex<Vec<(&'static str, Vec<TopicPartition>)>>
+    }
+
+    impl RebalanceListener<Vec<u8>, String> for SeekingListener {
+        fn on_partitions_revoked(&self, consumer: &KafkaConsumer<Vec<u8>, String>, partitions: &[TopicPartition]) {
+            assert_eq!(consumer.assignment(), partitions);
+            self.events.lock().unwrap().push(("revoked", partitions.to_vec()));
+        }
+
+        fn on_partitions_assigned(&self, consumer: &KafkaConsumer<Vec<u8>, String>, partitions: &[TopicPartition]) {
+            for topic_partition in partitions {
+                consumer.seek(topic_partition, 0).unwrap();
+            }
+            self.events.lock().unwrap().push(("assigned", partitions.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_rebalance_listener_is_told_about_partitions_on_the_polling_thread() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(group_cluster(&broker, 1), ConsumerConfig { group_id: Some(String::from("group")), ..config() });
+        let listener: Arc<SeekingListener> = Arc::new(SeekingListener::default());
+        consumer.subscribe_with_listener(&["foo"], listener.clone()).unwrap();
+        // the coordinator is found through a broker the metadata names
+        consumer.metadata.wait_for_topic("foo", Duration::from_secs(5)).unwrap();
+
+        consumer.poll(Duration::from_millis(10)).unwrap();
+        assert_eq!(consumer.position(&TopicPartition::new("foo", 0)), Some(0));
+        consumer.unsubscribe();
+        let foo: Vec<TopicPartition> = vec![TopicPartition::new("foo", 0)];
+        assert_eq!(*listener.events.lock().unwrap(), vec![("assigned", foo.clone()), ("revoked", foo)]);
+        // the position was committed as the partition was revoked
+        assert_eq!(group.lock().unwrap().committed[&TopicPartition::new("foo", 0)].offset, 0);
+    }
+
+    #[test]
+    fn test_partitions_start_from_committed_offsets_and_positions_are_committed() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        let foo_1: TopicPartition = TopicPartition::new("foo", 1);
+        group.lock().unwrap().committed.insert(foo_0.clone(), OffsetAndMetadata { offset: 1, leader_epoch: Some(0), metadata: String::from("m") });
+        assert!(consumer(group_cluster(&broker, 2), config()).commit_sync().is_err());
+        let consumer: KafkaConsumer<Vec<u8>, String> = consumer(group_cluster(&broker, 2), ConsumerConfig { group_id: Some(String::from("group")), ..config() });
+        consumer.assign(&[foo_0.clone(), foo_1.clone()]);
+        consumer.metadata.wait_for_topic("foo", Duration::from_secs(5)).unwrap();
+
+        // positions are looked up within each poll's timeout, so it may take a few polls
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        while (consumer.position(&foo_0).is_none() || consumer.position(&foo_1).is_none()) && Instant::now() < deadline {
+            consumer.poll(Duration::from_millis(10)).unwrap();
+        }
+        // the partition without a committed offset is reset to the end of the log
+        assert_eq!((consumer.position(&foo_0), consumer.position(&foo_1)), (Some(1), Some(0)));
+        consumer.seek(&foo_1, 4).unwrap();
+        consumer.commit_sync().unwrap();
+        let committed: HashMap<TopicPartition, OffsetAndMetadata> = consumer.committed(&[foo_0.clone(), foo_1.clone()]).unwrap();
+        assert_eq!((committed[&foo_0].offset, committed[&foo_0].leader_epoch, committed[&foo_1].offset), (1, Some(0), 4));
+
+        let results: Arc<Mutex<Vec<bool>>> = Arc::new(Mutex::new(Vec::new()));
+        let callback_results: Arc<Mutex<Vec<bool>>> = results.clone();
+        let mut offsets: HashMap<TopicPartition, OffsetAndMetadata> = HashMap::new();
+        offsets.insert(foo_1.clone(), OffsetAndMetadata::new(5));
+        consumer.commit_offsets_async(offsets, Some(Box::new(move |_: &HashMap<TopicPartition, OffsetAndMetadata>, result: &anyhow::Result<()>| {
+            callback_results.lock().unwrap().push(result.is_ok());
+        }))).unwrap();
+        let deadline: Instant = Instant::now() + Duration::from_secs(5);
+        while results.lock().unwrap().is_empty() && Instant::now() < deadline {
+            consumer.poll(Duration::from_millis(10)).unwrap();
+        }
+        assert_eq!(*results.lock().unwrap(), vec![true]);
+
+        // closing a consumer with assigned partitions commits their positions
+        consumer.seek(&foo_0, 2).unwrap();
+        consumer.close();
+        let committed: HashMap<TopicPartition, OffsetAndMetadata> = group.lock().unwrap().committed.clone();
+        assert_eq!((committed[&foo_0].offset, committed[&foo_1].offset), (2, 4));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Result;
+use kafka_encode::primitives::{CompactArray, CompactString};
+use tracing::debug;
+use crate::cluster::{ClusterMetadata, TopicPartition};
+use crate::connection_pool::ConnectionPool;
+use crate::consumer::OffsetAndTimestamp;
+use crate::metadata_cache::MetadataCache;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::fetch::{CONSUMER_REPLICA_ID, IsolationLevel};
+use crate::protocol::list_offsets::{ListOffsetsRequestPartitionV6V7, ListOffsetsRequestTopicV6V7, ListOffsetsRequestV6V7, ListOffsetsResponseV6V7};
+use crate::protocol::tags::TaggedFields;
+use crate::retry::{self, Attempt, RetryPolicy};
+
+/// The offset a leader found for a partition, or the error it failed with.
+type ListedOffset = (TopicPartition, Result<Option<OffsetAndTimestamp>, ErrorCode>);
+
+/// Looks up the offsets of partitions by timestamp with ListOffsets requests to their leaders.
+#[derive(Debug)]
+pub(crate) struct OffsetFetcher {
+    pub metadata: Arc<MetadataCache>,
+    pub pool: Arc<ConnectionPool>,
+    /// With `ReadCommitted`, the latest offset is the last stable offset rather than the high
+    /// watermark.
+    pub isolation_level: IsolationLevel
+}
+
+impl OffsetFetcher {
+    /// The offset of the first record of each partition whose timestamp is at or after the one
+    /// given, or `None` if there is no such record. The timestamp may also be one of
+    /// `EARLIEST_TIMESTAMP`, `LATEST_TIMESTAMP` or `MAX_TIMESTAMP`. Partitions whose leader is
+    /// unknown, or which fail with a retriable error, are looked up again under `policy`.
+    pub fn list_offsets(&self, timestamps: &HashMap<TopicPartition, i64>, policy: &RetryPolicy) -> Result<HashMap<TopicPartition, Option<OffsetAndTimestamp>>> {
+        let mut remaining: HashMap<TopicPartition, i64> = timestamps.clone();
+        let mut listed: HashMap<TopicPartition, Option<OffsetAndTimestamp>> = HashMap::new();
+        policy.run(|attempt: Attempt| {
+            let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+            let mut requests: BTreeMap<i32, Vec<(TopicPartition, i64)>> = BTreeMap::new();
+            let mut retriable_error: Option<anyhow::Error> = None;
+            for (topic_partition, timestamp) in &remaining {
+                match cluster.leader(topic_partition) {
+                    Some(leader) => requests.entry(leader.node_id).or_default().push((topic_partition.clone(), *timestamp)),
+                    None => {
+                        self.metadata.request_update();
+                        retriable_error = Some(anyhow::Error::new(ErrorCode::LeaderNotAvailable)
+                            .context(format!("The leader of {:?} is unknown", topic_partition)));
+                    }
+                }
+            }
+            for (node_id, partitions) in requests {
+                let results: Vec<ListedOffset> = match self.send(node_id, &partitions, &cluster, attempt.timeout(self.pool.request_timeout())) {
+                    Ok(results) => results,
+                    Err(e) => {
+                        // the leader may have moved
+                        self.metadata.request_update();
+                        if !retry::is_retriable(&e) {
+                            return Err(e);
+                        }
+                        retriable_error = Some(e);
+                        continue;
+                    }
+                };
+                for (topic_partition, result) in results {
+                    match result {
+                        Ok(offset) => {
+                            remaining.remove(&topic_partition);
+                            listed.insert(topic_partition, offset);
+                        },
+                        Err(error_code) => {
+                            let invalid_metadata: bool = self.metadata.handle_error_code(&error_code);
+                            if !invalid_metadata && !error_code.is_retriable() {
+                                return Err(anyhow::Error::new(error_code).context(format!("Failed to list the offsets of {:?}", topic_partition)));
+                            }
+                            debug!("Will list the offsets of {:?} again: {}", topic_partition, error_code);
+                            retriable_error = Some(anyhow::Error::new(error_code));
+                        }
+                    }
+                }
+            }
+            match retriable_error {
+                Some(e) => Err(e),
+                None => Ok(())
+            }
+        })?;
+        Ok(listed)
+    }
+
+    /// Sends one ListOffsets request to a leader, and returns the result for each partition.
+    fn send(&self, node_id: i32, partitions: &[(TopicPartition, i64)], cluster: &ClusterMetadata, timeout: Duration) -> Result<Vec<ListedOffset>> {
+        let mut topics: BTreeMap<String, Vec<ListOffsetsRequestPartitionV6V7>> = BTreeMap::new();
+        for (topic_partition, timestamp) in partitions {
+            let current_leader_epoch: i32 = cluster.partition(topic_partition)
+                .and_then(|partition| partition.leader_epoch)
+                .unwrap_or(-1);
+            topics.entry(topic_partition.topic.clone()).or_default().push(ListOffsetsRequestPartitionV6V7 {
+                partition_index: topic_partition.partition,
+                current_leader_epoch,
+                timestamp: *timestamp,
+                tag_buffer: TaggedFields::new()
+            });
+        }
+        let request: ListOffsetsRequestV6V7 = ListOffsetsRequestV6V7 {
+            replica_id: CONSUMER_REPLICA_ID,
+            isolation_level: self.isolation_level,
+            topics: CompactArray(topics.into_iter()
+                .map(|(name, partitions)| ListOffsetsRequestTopicV6V7 {
+                    name: CompactString(name),
+                    partitions: CompactArray(partitions),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            tag_buffer: TaggedFields::new()
+        };
+        let response: ListOffsetsResponseV6V7 = self.pool.send_within::<ListOffsetsRequestV6V7, ListOffsetsResponseV6V7>(node_id, request, timeout)?;
+        let mut results: Vec<ListedOffset> = Vec::new();
+        for topic in response.topics.0 {
+            for partition in topic.partitions.0 {
+                let topic_partition: TopicPartition = TopicPartition::new(&topic.name.0, partition.partition_index);
+                let result: Result<Option<OffsetAndTimestamp>, ErrorCode> = match partition.error_code {
+                    // -1 means no record has a timestamp that late
+                    ErrorCode::None if partition.offset < 0 => Ok(None),
+                    ErrorCode::None => Ok(Some(OffsetAndTimestamp {
+                        offset: partition.offset,
+                        timestamp: partition.timestamp,
+                        leader_epoch: Some(partition.leader_epoch).filter(|epoch| *epoch >= 0)
+                    })),
+                    error_code => Err(error_code)
+                };
+                results.push((topic_partition, result));
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use kafka_encode::primitives::CompactArray;
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use crate::consumer::OffsetAndTimestamp;
+    use crate::consumer::consumer_coordinator::tests::group_cluster;
+    use crate::consumer::offset_fetcher::OffsetFetcher;
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::fetch::IsolationLevel;
+    use crate::protocol::list_offsets::{EARLIEST_TIMESTAMP, ListOffsetsRequestV6V7, ListOffsetsResponsePartitionV6V7, ListOffsetsResponseTopicV6V7, ListOffsetsResponseV6V7};
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::tags::TaggedFields;
+    use crate::retry::RetryPolicy;
+
+    /// A broker which answers the first ListOffsets request with NotLeaderOrFollower. After that,
+    /// every partition starts at offset 5, and has no record later than timestamp 2000.
+    fn broker(requests: Arc<AtomicUsize>) -> MockBroker {
+        MockBroker::start(move |request: &MockRequest| {
+            let list_offsets: ListOffsetsRequestV6V7 = request.decode().unwrap();
+            let first: bool = requests.fetch_add(1, Ordering::SeqCst) == 0;
+            let topics: Vec<ListOffsetsResponseTopicV6V7> = list_offsets.topics.0.into_iter()
+                .map(|topic| ListOffsetsResponseTopicV6V7 {
+                    name: topic.name,
+                    partitions: CompactArray(topic.partitions.0.into_iter()
+                        .map(|partition| {
+                            let (error_code, offset): (ErrorCode, i64) = match partition.timestamp {
+                                _ if first => (ErrorCode::NotLeaderOrFollower, -1),
+                                EARLIEST_TIMESTAMP => (ErrorCode::None, 5),
+                                timestamp if timestamp > 2000 => (ErrorCode::None, -1),
+                                timestamp => (ErrorCode::None, timestamp - 1000)
+                            };
+                            ListOffsetsResponsePartitionV6V7 {
+                                partition_index: partition.partition_index,
+                                error_code,
+                                timestamp: partition.timestamp.max(-1),
+                                offset,
+                                leader_epoch: partition.current_leader_epoch,
+                                tag_buffer: TaggedFields::new()
+                            }
+                        })
+                        .collect()),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect();
+            Some(request.respond::<ListOffsetsRequestV6V7, ListOffsetsResponseV6V7>(ListOffsetsResponseV6V7 {
+                throttle_time_ms: 0,
+                topics: CompactArray(topics),
+                tag_buffer: TaggedFields::new()
+            }))
+        })
+    }
+
+    #[test]
+    fn test_offsets_are_listed_again_after_a_leader_change() {
+        let requests: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let broker: MockBroker = broker(requests.clone());
+        let cluster: ClusterMetadata = group_cluster(&broker, 3);
+        let metadata: Arc<MetadataCache> = Arc::new(MetadataCache::new(Box::new(StaticFetcher(cluster.clone())), MetadataCacheConfig::default()));
+        metadata.update(cluster);
+        let pool: Arc<ConnectionPool> = Arc::new(ConnectionPool::new(metadata.clone(), ConnectionPoolConfig::default()));
+        let fetcher: OffsetFetcher = OffsetFetcher { metadata, pool, isolation_level: IsolationLevel::ReadUncommitted };
+        let policy: RetryPolicy = RetryPolicy { retry_backoff: Duration::from_millis(1), delivery_timeout: Duration::from_secs(5), ..RetryPolicy::default() };
+
+        let timestamps: HashMap<TopicPartition, i64> = HashMap::from([
+            (TopicPartition::new("foo", 0), EARLIEST_TIMESTAMP),
+            (TopicPartition::new("foo", 1), 1500),
+            (TopicPartition::new("foo", 2), 3000)
+        ]);
+        let offsets: HashMap<TopicPartition, Option<OffsetAndTimestamp>> = fetcher.list_offsets(&timestamps, &policy).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(offsets.len(), 3);
+        assert_eq!(offsets[&TopicPartition::new("foo", 0)], Some(OffsetAndTimestamp { offset: 5, timestamp: -1, leader_epoch: Some(0) }));
+        assert_eq!(offsets[&TopicPartition::new("foo", 1)], Some(OffsetAndTimestamp { offset: 500, timestamp: 1500, leader_epoch: Some(0) }));
+        assert_eq!(offsets[&TopicPartition::new("foo", 2)], None);
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use kafka_encode::primitives::{CompactArray, CompactNullableArray, CompactNullableString, CompactString};
+use tracing::{debug, warn};
+use crate::cluster::TopicPartition;
+use crate::connection_pool::ConnectionPool;
+use crate::consumer::{ConsumerConfig, GroupProtocol};
+use crate::coordinator::{ConsumerGroupMetadata, Coordinator, OffsetAndMetadata};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::fetch::IsolationLevel;
+use crate::protocol::find_coordinator::COORDINATOR_TYPE_GROUP;
+use crate::protocol::offset_commit::{OffsetCommitRequestPartitionV8V9, OffsetCommitRequestTopicV8V9, OffsetCommitRequestV8, OffsetCommitRequestV9, OffsetCommitResponseV8V9};
+use crate::protocol::offset_fetch::{OffsetFetchRequestGroupV8, OffsetFetchRequestGroupV9, OffsetFetchRequestTopicV6V9, OffsetFetchRequestV8, OffsetFetchRequestV9, OffsetFetchResponseV8V9};
+use crate::protocol::tags::TaggedFields;
+use crate::retry::RetryPolicy;
+
+/// Told on the thread which polls whether offsets committed with `commit_async` were committed.
+pub type OffsetCommitCallback = Box<dyn FnOnce(&HashMap<TopicPartition, OffsetAndMetadata>, &Result<()>) + Send>;
+
+struct AsyncCommit {
+    offsets: HashMap<TopicPartition, OffsetAndMetadata>,
+    group_metadata: ConsumerGroupMetadata,
+    callback: Option<OffsetCommitCallback>
+}
+
+impl Debug for AsyncCommit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncCommit")
+            .field("offsets", &self.offsets)
+            .field("group_metadata", &self.group_metadata)
+            .field("has_callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CommitQueue {
+    pending: VecDeque<AsyncCommit>,
+    /// Commits which are done, whose callbacks wait for the next poll.
+    completed: Vec<(AsyncCommit, Result<()>)>,
+    in_flight: bool,
+    closed: bool
+}
+
+/// Commits a group's offsets to its coordinator and fetches them back. Asynchronous commits are
+/// sent in order by a committer thread, and their callbacks are run by the thread which polls.
+#[derive(Debug)]
+pub(crate) struct GroupOffsets {
+    group_id: String,
+    group_protocol: GroupProtocol,
+    /// Set when reading committed records, so that offsets which open transactions committed are
+    /// waited for rather than read.
+    require_stable: bool,
+    /// `None` when `enable.auto.commit` is off.
+    auto_commit_interval: Option<Duration>,
+    next_auto_commit: Mutex<Instant>,
+    retry: RetryPolicy,
+    coordinator: Coordinator,
+    pool: Arc<ConnectionPool>,
+    commits: Mutex<CommitQueue>,
+    changed: Condvar
+}
+
+impl GroupOffsets {
+    pub fn new(group_id: &str, config: &ConsumerConfig, pool: Arc<ConnectionPool>) -> Self {
+        GroupOffsets {
+            group_id: String::from(group_id),
+            group_protocol: config.group_protocol,
+            require_stable: config.isolation_level == IsolationLevel::ReadCommitted,
+            auto_commit_interval: config.enable_auto_commit.then_some(config.auto_commit_interval),
+            next_auto_commit: Mutex::new(Instant::now() + config.auto_commit_interval),
+            retry: config.retry.clone(),
+            coordinator: Coordinator::new(COORDINATOR_TYPE_GROUP, group_id),
+            pool,
+            commits: Mutex::new(CommitQueue::default()),
+            changed: Condvar::new()
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CommitQueue> {
+        self.commits.lock().expect("Commit queue lock was poisoned")
+    }
+
+    pub fn auto_commit_enabled(&self) -> bool {
+        self.auto_commit_interval.is_some()
+    }
+
+    /// Whether `auto.commit.interval.ms` has passed since the last automatic commit, in which case
+    /// the next one is due an interval from now.
+    pub fn auto_commit_due(&self) -> bool {
+        let Some(interval) = self.auto_commit_interval else {
+            return false;
+        };
+        let mut next_auto_commit: MutexGuard<Instant> = self.next_auto_commit.lock().expect("Auto commit lock was poisoned");
+        let now: Instant = Instant::now();
+        if now < *next_auto_commit {
+            return false;
+        }
+        *next_auto_commit = now + interval;
+        true
+    }
+
+    /// Commits offsets for the consumer's membership of its group. Under the consumer group
+    /// protocol the membership's generation is its member epoch.
+    pub fn commit(&self, offsets: &HashMap<TopicPartition, OffsetAndMetadata>, group_metadata: &ConsumerGroupMetadata, policy: &RetryPolicy) -> Result<()> {
+        if offsets.is_empty() {
+            return Ok(());
+        }
+        let mut topics: BTreeMap<String, Vec<OffsetCommitRequestPartitionV8V9>> = BTreeMap::new();
+        for (topic_partition, offset) in offsets {
+            topics.entry(topic_partition.topic.clone()).or_default().push(OffsetCommitRequestPartitionV8V9 {
+                partition_index: topic_partition.partition,
+                committed_offset: offset.offset,
+                committed_leader_epoch: offset.leader_epoch.unwrap_or(-1),
+                committed_metadata: CompactNullableString(Some(offset.metadata.clone())),
+                tag_buffer: TaggedFields::new()
+            });
+        }
+        let topics: CompactArray<OffsetCommitRequestTopicV8V9> = CompactArray(topics.into_iter()
+            .map(|(name, partitions)| OffsetCommitRequestTopicV8V9 {
+                name: CompactString(name),
+                partitions: CompactArray(partitions),
+                tag_buffer: TaggedFields::new()
+            })
+            .collect());
+        let error_code = |response: &OffsetCommitResponseV8V9| {
+            response.topics.0.iter()
+                .flat_map(|topic| topic.partitions.0.iter())
+                .find(|partition| partition.error_code != ErrorCode::None)
+                .map_or(ErrorCode::None, |partition| partition.error_code.clone())
+        };
+        let response: Result<OffsetCommitResponseV8V9> = match self.group_protocol {
+            GroupProtocol::Classic => self.coordinator.send(&self.pool, OffsetCommitRequestV8 {
+                group_id: CompactString(self.group_id.clone()),
+                generation_id: group_metadata.generation_id,
+                member_id: CompactString(group_metadata.member_id.clone()),
+                group_instance_id: CompactNullableString(group_metadata.group_instance_id.clone()),
+                topics,
+                tag_buffer: TaggedFields::new()
+            }, policy, error_code),
+            GroupProtocol::Consumer => self.coordinator.send(&self.pool, OffsetCommitRequestV9 {
+                group_id: CompactString(self.group_id.clone()),
+                generation_id_or_member_epoch: group_metadata.generation_id,
+                member_id: CompactString(group_metadata.member_id.clone()),
+                group_instance_id: CompactNullableString(group_metadata.group_instance_id.clone()),
+                topics,
+                tag_buffer: TaggedFields::new()
+            }, policy, error_code)
+        };
+        response.map_err(|e| e.context(format!("Failed to commit offsets for group {}", self.group_id)))?;
+        debug!("Committed offsets {:?} for group {}", offsets, self.group_id);
+        Ok(())
+    }
+
+    /// The group's committed offsets for `partitions`. Partitions the group has no offset for are
+    /// left out.
+    pub fn fetch_committed(&self, partitions: &[TopicPartition], group_metadata: &ConsumerGroupMetadata, policy: &RetryPolicy) -> Result<HashMap<TopicPartition, OffsetAndMetadata>> {
+        if partitions.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut topics: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+        for topic_partition in partitions {
+            topics.entry(topic_partition.topic.clone()).or_default().push(topic_partition.partition);
+        }
+        let topics: CompactNullableArray<OffsetFetchRequestTopicV6V9> = CompactNullableArray(Some(topics.into_iter()
+            .map(|(name, partition_indexes)| OffsetFetchRequestTopicV6V9 {
+                name: CompactString(name),
+                partition_indexes: CompactArray(partition_indexes),
+                tag_buffer: TaggedFields::new()
+            })
+            .collect()));
+        let error_code = |response: &OffsetFetchResponseV8V9| {
+            let Some(group) = response.groups.0.first() else {
+                return ErrorCode::None;
+            };
+            if group.error_code != ErrorCode::None {
+                return group.error_code.clone();
+            }
+            group.topics.0.iter()
+                .flat_map(|topic| topic.partitions.0.iter())
+                .find(|partition| partition.error_code != ErrorCode::None)
+                .map_or(ErrorCode::None, |partition| partition.error_code.clone())
+        };
+        let response: Result<OffsetFetchResponseV8V9> = match self.group_protocol {
+            GroupProtocol::Classic => self.coordinator.send(&self.pool, OffsetFetchRequestV8 {
+                groups: CompactArray(vec![OffsetFetchRequestGroupV8 {
+                    group_id: CompactString(self.group_id.clone()),
+                    topics,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                require_stable: self.require_stable,
+                tag_buffer: TaggedFields::new()
+            }, policy, error_code),
+            GroupProtocol::Consumer => self.coordinator.send(&self.pool, OffsetFetchRequestV9 {
+                groups: CompactArray(vec![OffsetFetchRequestGroupV9 {
+                    group_id: CompactString(self.group_id.clone()),
+                    member_id: CompactNullableString(Some(group_metadata.member_id.clone()).filter(|member_id| !member_id.is_empty())),
+                    member_epoch: group_metadata.generation_id,
+                    topics,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                require_stable: self.require_stable,
+                tag_buffer: TaggedFields::new()
+            }, policy, error_code)
+        };
+        let response: OffsetFetchResponseV8V9 = response
+            .map_err(|e| e.context(format!("Failed to fetch the committed offsets of group {}", self.group_id)))?;
+        let mut committed: HashMap<TopicPartition, OffsetAndMetadata> = HashMap::new();
+        for group in response.groups.0 {
+            for topic in group.topics.0 {
+                for partition in topic.partitions.0 {
+                    // -1 means nothing was committed
+                    if partition.committed_offset < 0 {
+                        continue;
+                    }
+                    committed.insert(TopicPartition::new(&topic.name.0, partition.partition_index), OffsetAndMetadata {
+                        offset: partition.committed_offset,
+                        leader_epoch: Some(partition.committed_leader_epoch).filter(|epoch| *epoch >= 0),
+                        metadata: partition.metadata.0.unwrap_or_default()
+                    });
+                }
+            }
+        }
+        Ok(committed)
+    }
+
+    /// Queues offsets for the committer thread, which commits them after the ones queued before.
+    /// Once closed, commits fail straight away.
+    pub fn commit_async(&self, offsets: HashMap<TopicPartition, OffsetAndMetadata>, group_metadata: ConsumerGroupMetadata, callback: Option<OffsetCommitCallback>) {
+        let mut commits: MutexGuard<CommitQueue> = self.lock();
+        let commit: AsyncCommit = AsyncCommit { offsets, group_metadata, callback };
+        if commits.closed {
+            commits.completed.push((commit, Err(anyhow!("The consumer is closed"))));
+            return;
+        }
+        commits.pending.push_back(commit);
+        self.changed.notify_all();
+    }
+
+    /// Commits queued offsets until closed, and then commits the ones still queued. Asynchronous
+    /// commits aren't retried, since a later commit may already have moved the offsets on.
+    pub fn run_async_commits(&self) {
+        let policy: RetryPolicy = RetryPolicy { retries: 0, ..self.retry.clone() };
+        let mut commits: MutexGuard<CommitQueue> = self.lock();
+        loop {
+            let Some(commit) = commits.pending.pop_front() else {
+                if commits.closed {
+                    return;
+                }
+                commits = self.changed.wait(commits).expect("Commit queue lock was poisoned");
+                continue;
+            };
+            commits.in_flight = true;
+            drop(commits);
+            let result: Result<()> = self.commit(&commit.offsets, &commit.group_metadata, &policy);
+            commits = self.lock();
+            commits.in_flight = false;
+            commits.completed.push((commit, result));
+            self.changed.notify_all();
+        }
+    }
+
+    /// Waits for the committer thread to commit every queued offset, so that a synchronous commit
+    /// isn't overtaken by an earlier asynchronous one.
+    pub fn wait_for_async_commits(&self) {
+        let mut commits: MutexGuard<CommitQueue> = self.lock();
+        while !commits.pending.is_empty() || commits.in_flight {
+            commits = self.changed.wait(commits).expect("Commit queue lock was poisoned");
+        }
+    }
+
+    /// Runs the callbacks of completed asynchronous commits.
+    pub fn invoke_completed_callbacks(&self) {
+        let completed: Vec<(AsyncCommit, Result<()>)> = std::mem::take(&mut self.lock().completed);
+        for (commit, result) in completed {
+            match commit.callback {
+                Some(callback) => callback(&commit.offsets, &result),
+                None => if let Err(e) = &result {
+                    warn!("Asynchronous commit of offsets {:?} failed: {:#}", commit.offsets, e);
+                }
+            }
+        }
+    }
+
+    /// Stops the committer thread once it has committed the offsets already queued.
+    pub fn close(&self) {
+        let mut commits: MutexGuard<CommitQueue> = self.lock();
+        commits.closed = true;
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+    use anyhow::Result;
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use crate::consumer::ConsumerConfig;
+    use crate::consumer::consumer_coordinator::tests::{MockGroup, group_broker, group_cluster};
+    use crate::consumer::offsets::GroupOffsets;
+    use crate::coordinator::{ConsumerGroupMetadata, OffsetAndMetadata};
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::mock_broker::MockBroker;
+    use crate::retry::RetryPolicy;
+
+    fn group_offsets(broker: &MockBroker) -> Arc<GroupOffsets> {
+        let cluster: ClusterMetadata = group_cluster(broker, 2);
+        let metadata: Arc<MetadataCache> = Arc::new(MetadataCache::new(Box::new(StaticFetcher(cluster.clone())), MetadataCacheConfig::default()));
+        metadata.update(cluster);
+        let pool: Arc<ConnectionPool> = Arc::new(ConnectionPool::new(metadata, ConnectionPoolConfig::default()));
+        Arc::new(GroupOffsets::new("group", &ConsumerConfig::new("unused:9092"), pool))
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { retry_backoff: Duration::from_millis(1), delivery_timeout: Duration::from_secs(5), ..RetryPolicy::default() }
+    }
+
+    #[test]
+    fn test_committed_offsets_are_fetched_back_with_their_leader_epoch_and_metadata() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let offsets: Arc<GroupOffsets> = group_offsets(&broker);
+        let group_metadata: ConsumerGroupMetadata = ConsumerGroupMetadata::new("group");
+        let mut committed: HashMap<TopicPartition, OffsetAndMetadata> = HashMap::new();
+        committed.insert(TopicPartition::new("foo", 0), OffsetAndMetadata { offset: 5, leader_epoch: Some(2), metadata: String::from("m") });
+        offsets.commit(&committed, &group_metadata, &policy()).unwrap();
+
+        let partitions: Vec<TopicPartition> = vec![TopicPartition::new("foo", 0), TopicPartition::new("foo", 1)];
+        assert_eq!(offsets.fetch_committed(&partitions, &group_metadata, &policy()).unwrap(), committed);
+
+        group.lock().unwrap().commit_errors.push_back(ErrorCode::IllegalGeneration);
+        let error: anyhow::Error = offsets.commit(&committed, &group_metadata, &policy()).unwrap_err();
+        assert_eq!(error.downcast_ref::<ErrorCode>(), Some(&ErrorCode::IllegalGeneration));
+        assert_eq!(error.to_string(), "Failed to commit offsets for group group");
+    }
+
+    #[test]
+    fn test_async_commits_complete_in_order_and_call_back_when_invoked() {
+        let group: Arc<Mutex<MockGroup>> = Arc::new(Mutex::new(MockGroup::default()));
+        let broker: MockBroker = group_broker(1, group.clone());
+        let offsets: Arc<GroupOffsets> = group_offsets(&broker);
+        let committer: Arc<GroupOffsets> = offsets.clone();
+        let committer_thread: JoinHandle<()> = thread::spawn(move || committer.run_async_commits());
+
+        let results: Arc<Mutex<Vec<(i64, bool)>>> = Arc::new(Mutex::new(Vec::new()));
+        group.lock().unwrap().commit_errors.push_back(ErrorCode::None);
+        group.lock().unwrap().commit_errors.push_back(ErrorCode::RebalanceInProgress);
+        for offset in [1, 2, 3] {
+            let mut commit: HashMap<TopicPartition, OffsetAndMetadata> = HashMap::new();
+            commit.insert(TopicPartition::new("foo", 0), OffsetAndMetadata::new(offset));
+            let results: Arc<Mutex<Vec<(i64, bool)>>> = results.clone();
+            offsets.commit_async(commit, ConsumerGroupMetadata::new("group"), Some(Box::new(move |offsets: &HashMap<TopicPartition, OffsetAndMetadata>, result: &Result<()>| {
+                results.lock().unwrap().push((offsets[&TopicPartition::new("foo", 0)].offset, result.is_ok()));
+            })));
+        }
+        offsets.wait_for_async_commits();
+        assert!(results.lock().unwrap().is_empty());
+        offsets.invoke_completed_callbacks();
+        assert_eq!(*results.lock().unwrap(), vec![(1, true), (2, false), (3, true)]);
+        assert_eq!(group.lock().unwrap().committed[&TopicPartition::new("foo", 0)].offset, 3);
+
+        offsets.close();
+        committer_thread.join().unwrap();
+    }
+}
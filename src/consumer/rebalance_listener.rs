@@ -0,0 +1,24 @@
+use std::fmt::Debug;
+use crate::cluster::TopicPartition;
+use crate::consumer::KafkaConsumer;
+
+/// Told when the consumer's group gives it partitions or takes them away, like
+/// `ConsumerRebalanceListener`. Callbacks run on the thread which calls `poll`, and are given the
+/// consumer so that they can seek, or commit offsets before another member takes a partition over.
+pub trait RebalanceListener<K, V>: Send + Sync + Debug {
+    /// Called before partitions are taken from the consumer, while it still owns them. Under the
+    /// eager protocol every partition is revoked before the consumer rejoins its group, and under
+    /// the cooperative protocol only the ones moving to another member are.
+    fn on_partitions_revoked(&self, consumer: &KafkaConsumer<K, V>, partitions: &[TopicPartition]);
+
+    /// Called with the partitions the group newly gave the consumer, once it has synced with the
+    /// group. Under the cooperative protocol this can be empty.
+    fn on_partitions_assigned(&self, consumer: &KafkaConsumer<K, V>, partitions: &[TopicPartition]);
+
+    /// Called instead of `on_partitions_revoked` when the consumer fell out of its group before it
+    /// could give its partitions up, such as when its session timed out. Other members may own them
+    /// already, so offsets can no longer be committed for them.
+    fn on_partitions_lost(&self, consumer: &KafkaConsumer<K, V>, partitions: &[TopicPartition]) {
+        self.on_partitions_revoked(consumer, partitions);
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Mutex, MutexGuard};
+use anyhow::Result;
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::Array;
+use tracing::warn;
+use crate::cluster::TopicPartition;
+use crate::consumer::assignor::{Assignment, empty_assignment, group_by_topic, PartitionAssignor, RebalanceProtocol, subscribers, Subscription, to_assignments, ungroup};
+use crate::protocol::consumer_protocol::{StickyAssignorUserDataV0, StickyAssignorUserDataV1};
+
+/// The partitions a member held before the rebalance, and the generation it held them in.
+#[derive(Debug, Default)]
+struct MemberData {
+    partitions: Vec<TopicPartition>,
+    generation: Option<i32>
+}
+
+/// Keeps as many partitions as it can with the members which held them, while keeping the
+/// assignment as balanced as the subscriptions allow. Members send the partitions they held in
+/// their user data, since members revoke everything before they rejoin under the eager protocol.
+#[derive(Debug, Default)]
+pub struct StickyAssignor {
+    /// The member's assignment and its generation, once it has synced with the group.
+    member_assignment: Mutex<Option<(Vec<TopicPartition>, i32)>>
+}
+
+impl StickyAssignor {
+    pub fn new() -> Self {
+        StickyAssignor::default()
+    }
+}
+
+impl PartitionAssignor for StickyAssignor {
+    fn name(&self) -> &str {
+        "sticky"
+    }
+
+    fn subscription_user_data(&self, _topics: &[String]) -> Option<Vec<u8>> {
+        let member_assignment: MutexGuard<Option<(Vec<TopicPartition>, i32)>> = self.member_assignment.lock().expect("Sticky assignor lock was poisoned");
+        let (partitions, generation): &(Vec<TopicPartition>, i32) = member_assignment.as_ref()?;
+        encode_user_data(partitions, *generation)
+            .inspect_err(|e| warn!("Failed to encode the sticky assignor's user data: {}", e))
+            .ok()
+    }
+
+    fn assign(&self, partitions_per_topic: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Assignment> {
+        let members: BTreeMap<String, MemberData> = subscriptions.iter()
+            .map(|(member_id, subscription)| {
+                let member_data: MemberData = match subscription.user_data.as_deref() {
+                    None | Some([]) => MemberData::default(),
+                    Some(user_data) => decode_user_data(user_data).unwrap_or_else(|e| {
+                        warn!("Ignoring the sticky assignor's user data from member {}: {}", member_id, e);
+                        MemberData::default()
+                    })
+                };
+                (member_id.clone(), member_data)
+            })
+            .collect();
+        to_assignments(sticky_assign(partitions_per_topic, subscriptions, &members))
+    }
+
+    fn on_assignment(&self, assignment: &Assignment, generation_id: i32) {
+        *self.member_assignment.lock().expect("Sticky assignor lock was poisoned") = Some((assignment.partitions.clone(), generation_id));
+    }
+}
+
+/// The sticky assignor for the cooperative protocol, under which members keep their partitions
+/// while they rejoin and send them as their owned partitions. A partition which moves between
+/// members is left unassigned until its old owner has revoked it and rejoined.
+#[derive(Debug)]
+pub struct CooperativeStickyAssignor {
+    /// The generation of the member's assignment, or -1 before it has one.
+    generation: Mutex<i32>
+}
+
+impl CooperativeStickyAssignor {
+    pub fn new() -> Self {
+        CooperativeStickyAssignor { generation: Mutex::new(-1) }
+    }
+}
+
+impl Default for CooperativeStickyAssignor {
+    fn default() -> Self {
+        CooperativeStickyAssignor::new()
+    }
+}
+
+impl PartitionAssignor for CooperativeStickyAssignor {
+    fn name(&self) -> &str {
+        "cooperative-sticky"
+    }
+
+    fn supported_protocols(&self) -> Vec<RebalanceProtocol> {
+        vec![RebalanceProtocol::Cooperative, RebalanceProtocol::Eager]
+    }
+
+    /// The generation, for members whose subscriptions are too old to carry it.
+    fn subscription_user_data(&self, _topics: &[String]) -> Option<Vec<u8>> {
+        Some(self.generation.lock().expect("Cooperative sticky assignor lock was poisoned").to_be_bytes().to_vec())
+    }
+
+    fn assign(&self, partitions_per_topic: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Assignment> {
+        let members: BTreeMap<String, MemberData> = subscriptions.iter()
+            .map(|(member_id, subscription)| {
+                let encoded_generation: Option<i32> = subscription.user_data.as_deref()
+                    .and_then(|user_data| <[u8; 4]>::try_from(user_data).ok())
+                    .map(i32::from_be_bytes)
+                    .filter(|generation| *generation >= 0);
+                (member_id.clone(), MemberData {
+                    partitions: subscription.owned_partitions.clone(),
+                    generation: subscription.generation_id.or(encoded_generation)
+                })
+            })
+            .collect();
+        let mut assignment: BTreeMap<String, Vec<TopicPartition>> = sticky_assign(partitions_per_topic, subscriptions, &members);
+
+        // a partition can't be given to a new member until its owner has revoked it
+        for (member_id, partitions) in &mut assignment {
+            partitions.retain(|topic_partition| !subscriptions.iter()
+                .any(|(owner_id, subscription)| owner_id != member_id && subscription.owned_partitions.contains(topic_partition)));
+        }
+        to_assignments(assignment)
+    }
+
+    fn on_assignment(&self, _assignment: &Assignment, generation_id: i32) {
+        *self.generation.lock().expect("Cooperative sticky assignor lock was poisoned") = generation_id;
+    }
+}
+
+fn encode_user_data(partitions: &[TopicPartition], generation: i32) -> Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    StickyAssignorUserDataV1 {
+        previous_assignment: Array(group_by_topic(partitions)),
+        generation
+    }.to_kafka_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Decodes either version of the sticky assignor's user data. Version 0 has no generation.
+fn decode_user_data(user_data: &[u8]) -> Result<MemberData> {
+    let mut reader: &[u8] = user_data;
+    if let Ok(v1) = StickyAssignorUserDataV1::from_kafka_bytes(&mut reader) {
+        return Ok(MemberData { partitions: ungroup(v1.previous_assignment.0), generation: Some(v1.generation) });
+    }
+    let mut reader: &[u8] = user_data;
+    let v0: StickyAssignorUserDataV0 = StickyAssignorUserDataV0::from_kafka_bytes(&mut reader)?;
+    Ok(MemberData { partitions: ungroup(v0.previous_assignment.0), generation: None })
+}
+
+/// Each partition stays with the member from the latest generation which held it, if that member
+/// is still subscribed to its topic. The rest go to the eligible members with the fewest
+/// partitions, and then partitions move from the most to the least loaded members until no move
+/// would make the assignment more balanced.
+fn sticky_assign(partitions_per_topic: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>, members: &BTreeMap<String, MemberData>) -> BTreeMap<String, Vec<TopicPartition>> {
+    let mut assignment: BTreeMap<String, Vec<TopicPartition>> = empty_assignment(subscriptions);
+    let mut unassigned: BTreeSet<TopicPartition> = partitions_per_topic.iter()
+        .filter(|(topic, _)| !subscribers(subscriptions, topic).is_empty())
+        .flat_map(|(_, topic_partitions)| topic_partitions.iter().cloned())
+        .collect();
+
+    let mut owners: BTreeMap<&TopicPartition, (i32, &String)> = BTreeMap::new();
+    for (member_id, member_data) in members {
+        let generation: i32 = member_data.generation.unwrap_or(-1);
+        for topic_partition in &member_data.partitions {
+            if !unassigned.contains(topic_partition) || !subscriptions[member_id].topics.contains(&topic_partition.topic) {
+                continue;
+            }
+            match owners.get(topic_partition) {
+                Some((owner_generation, _)) if *owner_generation >= generation => {},
+                _ => {
+                    owners.insert(topic_partition, (generation, member_id));
+                }
+            }
+        }
+    }
+    for (topic_partition, (_, member_id)) in owners {
+        unassigned.remove(topic_partition);
+        assignment.get_mut(member_id).expect("Every member has an assignment").push(topic_partition.clone());
+    }
+
+    for topic_partition in unassigned {
+        let member_id: String = assignment.iter()
+            .filter(|(member_id, _)| subscriptions[*member_id].topics.contains(&topic_partition.topic))
+            .min_by_key(|(_, partitions)| partitions.len())
+            .map(|(member_id, _)| member_id.clone())
+            .expect("Only subscribed topics are assigned");
+        assignment.get_mut(&member_id).expect("Every member has an assignment").push(topic_partition);
+    }
+
+    while let Some((from, to, index)) = next_move(&assignment, subscriptions) {
+        let topic_partition: TopicPartition = assignment.get_mut(&from).expect("Every member has an assignment").remove(index);
+        assignment.get_mut(&to).expect("Every member has an assignment").push(topic_partition);
+    }
+    for partitions in assignment.values_mut() {
+        partitions.sort();
+    }
+    assignment
+}
+
+/// A partition which can move to a member with at least two fewer partitions than its owner, as
+/// its owner, the member to move it to and its index in the owner's partitions.
+fn next_move(assignment: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> Option<(String, String, usize)> {
+    let mut members: Vec<(&String, usize)> = assignment.iter()
+        .map(|(member_id, partitions)| (member_id, partitions.len()))
+        .collect();
+    members.sort_by_key(|(_, count)| *count);
+    for (to, to_count) in &members {
+        for (from, from_count) in members.iter().rev() {
+            if *from_count <= to_count + 1 {
+                break;
+            }
+            let index: Option<usize> = assignment[*from].iter()
+                .rposition(|topic_partition| subscriptions[*to].topics.contains(&topic_partition.topic));
+            if let Some(index) = index {
+                return Some(((*from).clone(), (*to).clone(), index));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::Array;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::cluster::TopicPartition;
+    use crate::consumer::assignor::{Assignment, group_by_topic, PartitionAssignor, Subscription};
+    use crate::consumer::assignor::tests::{check_valid, partitions, random_topics, subscription};
+    use crate::consumer::sticky_assignor::{CooperativeStickyAssignor, decode_user_data, encode_user_data, MemberData, StickyAssignor};
+    use crate::protocol::consumer_protocol::StickyAssignorUserDataV0;
+
+    fn member_ids(members: usize) -> Vec<String> {
+        (0..members).map(|member| format!("member-{}", member)).collect()
+    }
+
+    /// The subscriptions of members which held `held` partitions in `generation`, sent the way
+    /// the assignor expects: as user data for the sticky assignor, and as owned partitions for
+    /// the cooperative one.
+    fn subscriptions(member_ids: &[String], topics: &BTreeMap<String, Vec<TopicPartition>>, held: &BTreeMap<String, Vec<TopicPartition>>, generation: i32, cooperative: bool) -> BTreeMap<String, Subscription> {
+        let topics: Vec<&str> = topics.keys().map(String::as_str).collect();
+        member_ids.iter()
+            .map(|member_id| {
+                let partitions: Vec<TopicPartition> = held.get(member_id).cloned().unwrap_or_default();
+                let member_subscription: Subscription = if cooperative {
+                    Subscription { owned_partitions: partitions, generation_id: Some(generation), ..subscription(&topics) }
+                } else {
+                    Subscription { user_data: Some(encode_user_data(&partitions, generation).unwrap()), ..subscription(&topics) }
+                };
+                (member_id.clone(), member_subscription)
+            })
+            .collect()
+    }
+
+    fn assign(assignor: &dyn PartitionAssignor, topics: &BTreeMap<String, Vec<TopicPartition>>, subscriptions: &BTreeMap<String, Subscription>) -> BTreeMap<String, Vec<TopicPartition>> {
+        let assignment: BTreeMap<String, Assignment> = assignor.assign(topics, subscriptions);
+        let counts: Vec<usize> = check_valid(subscriptions, &assignment);
+        assert_eq!(counts.len(), subscriptions.len());
+        assignment.into_iter().map(|(member_id, assignment)| (member_id, assignment.partitions)).collect()
+    }
+
+    /// Rebalances until no partition is waiting to be revoked, as a cooperative group does, and
+    /// returns the assignment of each round.
+    fn rebalance(assignor: &dyn PartitionAssignor, member_ids: &[String], topics: &BTreeMap<String, Vec<TopicPartition>>, held: &BTreeMap<String, Vec<TopicPartition>>, generation: i32, cooperative: bool) -> Vec<BTreeMap<String, Vec<TopicPartition>>> {
+        let mut rounds: Vec<BTreeMap<String, Vec<TopicPartition>>> = vec![assign(assignor, topics, &subscriptions(member_ids, topics, held, generation, cooperative))];
+        let total: usize = topics.values().map(Vec::len).sum();
+        while cooperative && rounds.last().unwrap().values().map(Vec::len).sum::<usize>() < total {
+            assert!(rounds.len() < 3, "Partitions were still unassigned after {:?}", rounds);
+            let last: BTreeMap<String, Vec<TopicPartition>> = rounds.last().unwrap().clone();
+            rounds.push(assign(assignor, topics, &subscriptions(member_ids, topics, &last, generation + rounds.len() as i32, cooperative)));
+        }
+        rounds
+    }
+
+    fn check_balanced(assignment: &BTreeMap<String, Vec<TopicPartition>>, topics: &BTreeMap<String, Vec<TopicPartition>>) {
+        let counts: Vec<usize> = assignment.values().map(Vec::len).collect();
+        assert_eq!(counts.iter().sum::<usize>(), topics.values().map(Vec::len).sum::<usize>());
+        assert!(counts.iter().max().unwrap() - counts.iter().min().unwrap() <= 1, "{:?}", counts);
+    }
+
+    fn is_subset(partitions: &[TopicPartition], of: &[TopicPartition]) -> bool {
+        let of: BTreeSet<&TopicPartition> = of.iter().collect();
+        partitions.iter().all(|topic_partition| of.contains(topic_partition))
+    }
+
+    #[test]
+    fn test_sticky_user_data_round_trip() {
+        let assignor: StickyAssignor = StickyAssignor::new();
+        assert_eq!(assignor.subscription_user_data(&[]), None);
+        assignor.on_assignment(&Assignment::new(partitions("foo", &[0, 2])), 5);
+        let member_data: MemberData = decode_user_data(&assignor.subscription_user_data(&[]).unwrap()).unwrap();
+        assert_eq!((member_data.partitions, member_data.generation), (partitions("foo", &[0, 2]), Some(5)));
+
+        let mut v0: Vec<u8> = Vec::new();
+        StickyAssignorUserDataV0 { previous_assignment: Array(group_by_topic(&partitions("foo", &[1]))) }.to_kafka_bytes(&mut v0).unwrap();
+        let member_data: MemberData = decode_user_data(&v0).unwrap();
+        assert_eq!((member_data.partitions, member_data.generation), (partitions("foo", &[1]), None));
+
+        let cooperative: CooperativeStickyAssignor = CooperativeStickyAssignor::new();
+        assert_eq!(cooperative.subscription_user_data(&[]), Some(vec![255, 255, 255, 255]));
+        cooperative.on_assignment(&Assignment::default(), 2);
+        assert_eq!(cooperative.subscription_user_data(&[]), Some(vec![0, 0, 0, 2]));
+    }
+
+    #[test]
+    fn test_sticky_assignment_keeps_partitions_with_the_latest_generation() {
+        let topics: BTreeMap<String, Vec<TopicPartition>> = BTreeMap::from([(String::from("foo"), partitions("foo", &[0, 1, 2, 3]))]);
+        let mut subscriptions: BTreeMap<String, Subscription> = BTreeMap::new();
+        subscriptions.insert(String::from("a"), Subscription { user_data: Some(encode_user_data(&partitions("foo", &[0, 1]), 1).unwrap()), ..subscription(&["foo"]) });
+        subscriptions.insert(String::from("b"), Subscription { user_data: Some(encode_user_data(&partitions("foo", &[1, 3]), 2).unwrap()), ..subscription(&["foo"]) });
+
+        let assignment: BTreeMap<String, Vec<TopicPartition>> = assign(&StickyAssignor::new(), &topics, &subscriptions);
+        assert_eq!(assignment["a"], partitions("foo", &[0, 2]));
+        assert_eq!(assignment["b"], partitions("foo", &[1, 3]));
+    }
+
+    #[test]
+    fn test_cooperative_sticky_assignment_waits_for_moved_partitions_to_be_revoked() {
+        let topics: BTreeMap<String, Vec<TopicPartition>> = BTreeMap::from([(String::from("foo"), partitions("foo", &[0, 1, 2, 3]))]);
+        let member_ids: Vec<String> = vec![String::from("a"), String::from("b")];
+        let held: BTreeMap<String, Vec<TopicPartition>> = BTreeMap::from([(String::from("a"), partitions("foo", &[0, 1, 2, 3]))]);
+
+        let rounds: Vec<BTreeMap<String, Vec<TopicPartition>>> = rebalance(&CooperativeStickyAssignor::new(), &member_ids, &topics, &held, 1, true);
+        assert_eq!(rounds.len(), 2);
+        assert_eq!((rounds[0]["a"].clone(), rounds[0]["b"].clone()), (partitions("foo", &[0, 1]), vec![]));
+        assert_eq!((rounds[1]["a"].clone(), rounds[1]["b"].clone()), (partitions("foo", &[0, 1]), partitions("foo", &[2, 3])));
+    }
+
+    #[test]
+    fn test_sticky_assignment_of_mixed_subscriptions_is_valid() {
+        let mut rng: StdRng = StdRng::seed_from_u64(46);
+        for _ in 0..200 {
+            let topics: BTreeMap<String, Vec<TopicPartition>> = random_topics(&mut rng, 4, 8);
+            let topic_names: Vec<&str> = topics.keys().map(String::as_str).collect();
+            let subscriptions: BTreeMap<String, Subscription> = (0..rng.gen_range(1..6))
+                .map(|member| {
+                    let subscribed: Vec<&str> = topic_names.iter().copied().filter(|_| rng.gen_bool(0.6)).collect();
+                    (format!("member-{}", member), subscription(&subscribed))
+                })
+                .collect();
+            let subscribed: BTreeSet<&String> = subscriptions.values().flat_map(|subscription| &subscription.topics).collect();
+
+            let assignment: BTreeMap<String, Vec<TopicPartition>> = assign(&StickyAssignor::new(), &topics, &subscriptions);
+            let total: usize = subscribed.iter().map(|topic| topics[*topic].len()).sum();
+            assert_eq!(assignment.values().map(Vec::len).sum::<usize>(), total);
+        }
+    }
+
+    #[test]
+    fn test_sticky_assignment_is_balanced_and_sticky() {
+        let mut rng: StdRng = StdRng::seed_from_u64(46);
+        for cooperative in [false, true] {
+            for _ in 0..100 {
+                let assignor: Box<dyn PartitionAssignor> = if cooperative {
+                    Box::new(CooperativeStickyAssignor::new())
+                } else {
+                    Box::new(StickyAssignor::new())
+                };
+                let topics: BTreeMap<String, Vec<TopicPartition>> = random_topics(&mut rng, 3, 10);
+                let member_ids: Vec<String> = member_ids(rng.gen_range(2..7));
+                let rounds: Vec<BTreeMap<String, Vec<TopicPartition>>> = rebalance(assignor.as_ref(), &member_ids, &topics, &BTreeMap::new(), 1, cooperative);
+                let assignment: &BTreeMap<String, Vec<TopicPartition>> = rounds.last().unwrap();
+                check_balanced(assignment, &topics);
+
+                // rebalancing the same members changes nothing
+                let again: Vec<BTreeMap<String, Vec<TopicPartition>>> = rebalance(assignor.as_ref(), &member_ids, &topics, assignment, 5, cooperative);
+                assert_eq!(again, vec![assignment.clone()]);
+
+                // the members which stay keep their partitions when one leaves
+                let leaving: String = member_ids[rng.gen_range(0..member_ids.len())].clone();
+                let staying: Vec<String> = member_ids.iter().filter(|member_id| **member_id != leaving).cloned().collect();
+                let mut held: BTreeMap<String, Vec<TopicPartition>> = assignment.clone();
+                held.remove(&leaving);
+                let after_leave: Vec<BTreeMap<String, Vec<TopicPartition>>> = rebalance(assignor.as_ref(), &staying, &topics, &held, 10, cooperative);
+                check_balanced(after_leave.last().unwrap(), &topics);
+                for member_id in &staying {
+                    assert!(is_subset(&assignment[member_id], &after_leave.last().unwrap()[member_id]), "{} lost partitions when {} left", member_id, leaving);
+                }
+
+                // the members which stay only give up partitions when one joins
+                let mut joined: Vec<String> = member_ids.clone();
+                joined.push(String::from("member-new"));
+                let after_join: Vec<BTreeMap<String, Vec<TopicPartition>>> = rebalance(assignor.as_ref(), &joined, &topics, assignment, 20, cooperative);
+                check_balanced(after_join.last().unwrap(), &topics);
+                for member_id in &member_ids {
+                    assert!(is_subset(&after_join.last().unwrap()[member_id], &assignment[member_id]), "{} gained partitions when a member joined", member_id);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use crate::cluster::TopicPartition;
+use crate::consumer::OffsetResetStrategy;
+
+/// Where the consumer reads a partition from next.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FetchPosition {
+    pub offset: i64,
+    /// The leader epoch of the last record consumed, which lets the leader tell whether the log was
+    /// truncated underneath the consumer.
+    pub leader_epoch: Option<i32>
+}
+
+impl FetchPosition {
+    pub fn new(offset: i64) -> Self {
+        FetchPosition { offset, leader_epoch: None }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TopicPartitionState {
+    /// `None` until the position is set, and the partition isn't fetched until then.
+    position: Option<FetchPosition>,
+    /// Set when the position must be looked up with this strategy: because the group has no
+    /// committed offset, the position fell out of range, or the application asked to seek to the
+    /// beginning or end. Cleared by a `seek`.
+    reset_strategy: Option<OffsetResetStrategy>
+}
+
+/// The partitions assigned to a consumer and its position in each. The application thread moves
+/// the positions as it consumes and seeks, and the fetcher thread fetches from them.
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionState {
+    assignment: Mutex<BTreeMap<TopicPartition, TopicPartitionState>>
+}
+
+impl SubscriptionState {
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<TopicPartition, TopicPartitionState>> {
+        self.assignment.lock().expect("Subscription state lock was poisoned")
+    }
+
+    /// Replaces the assignment. Partitions which stay assigned keep their positions.
+    pub fn assign(&self, partitions: &[TopicPartition]) {
+        let mut assignment: MutexGuard<BTreeMap<TopicPartition, TopicPartitionState>> = self.lock();
+        assignment.retain(|topic_partition, _| partitions.contains(topic_partition));
+        for topic_partition in partitions {
+            assignment.entry(topic_partition.clone()).or_default();
+        }
+    }
+
+    pub fn assigned_partitions(&self) -> Vec<TopicPartition> {
+        self.lock().keys().cloned().collect()
+    }
+
+    pub fn is_assigned(&self, topic_partition: &TopicPartition) -> bool {
+        self.lock().contains_key(topic_partition)
+    }
+
+    /// Moves the position of an assigned partition. Returns false if the partition isn't assigned.
+    pub fn seek(&self, topic_partition: &TopicPartition, position: FetchPosition) -> bool {
+        match self.lock().get_mut(topic_partition) {
+            Some(state) => {
+                state.position = Some(position);
+                state.reset_strategy = None;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn position(&self, topic_partition: &TopicPartition) -> Option<FetchPosition> {
+        self.lock().get(topic_partition).and_then(|state| state.position)
+    }
+
+    /// The assigned partitions which have no position, and which aren't awaiting a reset.
+    pub fn partitions_needing_position(&self) -> Vec<TopicPartition> {
+        self.lock().iter()
+            .filter(|(_, state)| state.position.is_none() && state.reset_strategy.is_none())
+            .map(|(topic_partition, _)| topic_partition.clone())
+            .collect()
+    }
+
+    /// Clears the position of an assigned partition, which isn't fetched again until it is reset
+    /// with `strategy` or sought.
+    pub fn request_reset(&self, topic_partition: &TopicPartition, strategy: OffsetResetStrategy) {
+        if let Some(state) = self.lock().get_mut(topic_partition) {
+            state.position = None;
+            state.reset_strategy = Some(strategy);
+        }
+    }
+
+    /// The assigned partitions awaiting a reset, with the strategy to reset each with.
+    pub fn partitions_awaiting_reset(&self) -> Vec<(TopicPartition, OffsetResetStrategy)> {
+        self.lock().iter()
+            .filter_map(|(topic_partition, state)| state.reset_strategy.map(|strategy| (topic_partition.clone(), strategy)))
+            .collect()
+    }
+
+    /// The assigned partitions which have a position to fetch from.
+    pub fn fetchable_partitions(&self) -> Vec<(TopicPartition, FetchPosition)> {
+        self.lock().iter()
+            .filter_map(|(topic_partition, state)| state.position.map(|position| (topic_partition.clone(), position)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::TopicPartition;
+    use crate::consumer::OffsetResetStrategy;
+    use crate::consumer::subscription_state::{FetchPosition, SubscriptionState};
+
+    #[test]
+    fn test_reassigned_partitions_keep_their_positions() {
+        let subscriptions: SubscriptionState = SubscriptionState::default();
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        let foo_1: TopicPartition = TopicPartition::new("foo", 1);
+        subscriptions.assign(&[foo_0.clone(), foo_1.clone()]);
+        assert!(subscriptions.seek(&foo_0, FetchPosition::new(5)));
+        assert!(subscriptions.fetchable_partitions().iter().all(|(topic_partition, _)| *topic_partition == foo_0));
+
+        subscriptions.assign(&[foo_0.clone(), TopicPartition::new("bar", 0)]);
+        assert_eq!(subscriptions.position(&foo_0), Some(FetchPosition::new(5)));
+        assert!(!subscriptions.seek(&foo_1, FetchPosition::new(1)));
+        assert_eq!(subscriptions.assigned_partitions(), vec![TopicPartition::new("bar", 0), foo_0]);
+    }
+
+    #[test]
+    fn test_seek_clears_a_requested_reset() {
+        let subscriptions: SubscriptionState = SubscriptionState::default();
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        let foo_1: TopicPartition = TopicPartition::new("foo", 1);
+        subscriptions.assign(&[foo_0.clone(), foo_1.clone()]);
+        assert_eq!(subscriptions.partitions_needing_position(), vec![foo_0.clone(), foo_1.clone()]);
+
+        subscriptions.request_reset(&foo_0, OffsetResetStrategy::Earliest);
+        assert_eq!(subscriptions.partitions_needing_position(), vec![foo_1.clone()]);
+        assert!(subscriptions.seek(&foo_1, FetchPosition::new(3)));
+        assert!(subscriptions.partitions_needing_position().is_empty());
+        assert_eq!(subscriptions.partitions_awaiting_reset(), vec![(foo_0.clone(), OffsetResetStrategy::Earliest)]);
+        assert!(subscriptions.seek(&foo_0, FetchPosition::new(0)));
+        assert!(subscriptions.partitions_awaiting_reset().is_empty());
+
+        subscriptions.request_reset(&foo_1, OffsetResetStrategy::Latest);
+        assert_eq!(subscriptions.position(&foo_1), None);
+        assert_eq!(subscriptions.partitions_awaiting_reset(), vec![(foo_1, OffsetResetStrategy::Latest)]);
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use anyhow::Result;
+use kafka_encode::primitives::CompactString;
+use tracing::debug;
+use crate::connection_pool::ConnectionPool;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::find_coordinator::{FindCoordinatorRequestV3, FindCoordinatorResponseV3};
+use crate::protocol::tags::TaggedFields;
+use crate::protocol::{KafkaRequest, KafkaResponse};
+use crate::retry::{Attempt, RetryPolicy};
+
+/// A consumer group's position in a partition, as committed to the group coordinator.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OffsetAndMetadata {
+    /// The offset of the next record to consume.
+    pub offset: i64,
+    pub leader_epoch: Option<i32>,
+    pub metadata: String
+}
+
+impl OffsetAndMetadata {
+    pub fn new(offset: i64) -> Self {
+        OffsetAndMetadata { offset, leader_epoch: None, metadata: String::new() }
+    }
+}
+
+/// A consumer's membership of its group, which lets the group coordinator fence offsets committed
+/// by a consumer which has been replaced (KIP-447).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConsumerGroupMetadata {
+    pub group_id: String,
+    /// -1 outside of a generation.
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>
+}
+
+impl ConsumerGroupMetadata {
+    /// Metadata for a group without any membership, which the coordinator doesn't fence.
+    pub fn new(group_id: &str) -> Self {
+        ConsumerGroupMetadata {
+            group_id: String::from(group_id),
+            generation_id: -1,
+            member_id: String::new(),
+            group_instance_id: None
+        }
+    }
+}
+
+/// The broker which coordinates a consumer group or a transactional id. It is looked up with
+/// FindCoordinator when first needed, and again after it moves.
+#[derive(Debug)]
+pub(crate) struct Coordinator {
+    /// One of the `COORDINATOR_TYPE_*` constants.
+    key_type: i8,
+    key: String,
+    node_id: Mutex<Option<i32>>
+}
+
+impl Coordinator {
+    pub fn new(key_type: i8, key: &str) -> Self {
+        Coordinator { key_type, key: String::from(key), node_id: Mutex::new(None) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<i32>> {
+        self.node_id.lock().expect("Coordinator lock was poisoned")
+    }
+
+    /// The coordinator's node id, looking it up if it isn't known.
+    pub fn node_id(&self, pool: &ConnectionPool) -> Result<i32> {
+        if let Some(node_id) = *self.lock() {
+            return Ok(node_id);
+        }
+        let request: FindCoordinatorRequestV3 = FindCoordinatorRequestV3 {
+            key: CompactString(self.key.clone()),
+            key_type: self.key_type,
+            tag_buffer: TaggedFields::new()
+        };
+        let (_, response) = pool.send_to_any::<FindCoordinatorRequestV3, FindCoordinatorResponseV3>(request)?;
+        if response.error_code != ErrorCode::None {
+            let error: anyhow::Error = anyhow::Error::new(response.error_code);
+            return Err(match response.error_message.0 {
+                Some(message) => error.context(message),
+                None => error
+            });
+        }
+        debug!("The coordinator for {} is node {}", self.key, response.node_id);
+        *self.lock() = Some(response.node_id);
+        Ok(response.node_id)
+    }
+
+    /// Forgets the coordinator, so that the next request looks it up again.
+    pub fn mark_unknown(&self) {
+        *self.lock() = None;
+    }
+
+    /// Sends a request to the coordinator, retrying as the policy allows. `error_code` picks the
+    /// error out of the response. The coordinator is looked up again after it has moved or the
+    /// connection to it has failed.
+    pub fn send<Request, Response, F>(&self, pool: &ConnectionPool, request: Request, policy: &RetryPolicy, error_code: F) -> Result<Response>
+        where Request: KafkaRequest + Clone, Response: KafkaResponse, F: Fn(&Response) -> ErrorCode {
+        self.send_within(pool, request, pool.request_timeout(), policy, error_code)
+    }
+
+    /// Like `send`, but each attempt waits up to `timeout` for the response, or until the policy's
+    /// delivery timeout if that is sooner.
+    pub fn send_within<Request, Response, F>(&self, pool: &ConnectionPool, request: Request, timeout: Duration, policy: &RetryPolicy, error_code: F) -> Result<Response>
+        where Request: KafkaRequest + Clone, Response: KafkaResponse, F: Fn(&Response) -> ErrorCode {
+        policy.run(|attempt: Attempt| {
+            let node_id: i32 = self.node_id(pool)?;
+            let response: Response = pool.send_within(node_id, request.clone(), attempt.timeout(timeout)).inspect_err(|_| self.mark_unknown())?;
+            match error_code(&response) {
+                ErrorCode::None => Ok(response),
+                error_code => {
+                    if matches!(error_code, ErrorCode::NotCoordinator | ErrorCode::CoordinatorNotAvailable) {
+                        self.mark_unknown();
+                    }
+                    Err(anyhow::Error::new(error_code))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use kafka_encode::primitives::{CompactNullableString, CompactString};
+    use crate::cluster::ClusterMetadata;
+    use crate::cluster::tests::mock_broker_cluster;
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use crate::coordinator::Coordinator;
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::end_txn::{EndTxnRequestV3, EndTxnResponseV3};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::find_coordinator::{COORDINATOR_TYPE_TRANSACTION, FindCoordinatorRequestV3, FindCoordinatorResponseV3};
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::tags::TaggedFields;
+    use crate::retry::RetryPolicy;
+
+    /// A broker which says the coordinator is node 1 the first time it is asked, and node 2 after
+    /// that, and which answers EndTxn with `error_code`.
+    fn broker(lookups: Arc<AtomicUsize>, error_code: ErrorCode) -> MockBroker {
+        MockBroker::start(move |request: &MockRequest| {
+            if request.api_key == ApiKey::FindCoordinator as i16 {
+                let node_id: i32 = if lookups.fetch_add(1, Ordering::SeqCst) == 0 { 1 } else { 2 };
+                return Some(request.respond::<FindCoordinatorRequestV3, FindCoordinatorResponseV3>(FindCoordinatorResponseV3 {
+                    throttle_time_ms: 0,
+                    error_code: ErrorCode::None,
+                    error_message: CompactNullableString(None),
+                    node_id,
+                    host: CompactString(String::from("127.0.0.1")),
+                    port: 0,
+                    tag_buffer: TaggedFields::new()
+                }));
+            }
+            Some(request.respond::<EndTxnRequestV3, EndTxnResponseV3>(EndTxnResponseV3 {
+                throttle_time_ms: 0,
+                error_code: error_code.clone(),
+                tag_buffer: TaggedFields::new()
+            }))
+        })
+    }
+
+    #[test]
+    fn test_coordinator_is_looked_up_again_after_it_moves() {
+        let lookups: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let old_coordinator: MockBroker = broker(lookups.clone(), ErrorCode::NotCoordinator);
+        let new_coordinator: MockBroker = broker(lookups.clone(), ErrorCode::None);
+        let cluster: ClusterMetadata = mock_broker_cluster("foo", &[(1, &old_coordinator), (2, &new_coordinator)], &[]);
+        let metadata: MetadataCache = MetadataCache::new(Box::new(StaticFetcher(cluster.clone())), MetadataCacheConfig::default());
+        metadata.update(cluster);
+        let pool: ConnectionPool = ConnectionPool::new(Arc::new(metadata), ConnectionPoolConfig::default());
+        let policy: RetryPolicy = RetryPolicy { retry_backoff: Duration::from_millis(1), ..RetryPolicy::default() };
+
+        let coordinator: Coordinator = Coordinator::new(COORDINATOR_TYPE_TRANSACTION, "txn");
+        let request: EndTxnRequestV3 = EndTxnRequestV3 {
+            transactional_id: CompactString(String::from("txn")),
+            producer_id: 42,
+            producer_epoch: 0,
+            committed: true,
+            tag_buffer: TaggedFields::new()
+        };
+        let response: EndTxnResponseV3 = coordinator.send(&pool, request, &policy, |response: &EndTxnResponseV3| response.error_code.clone()).unwrap();
+        assert_eq!(response.error_code, ErrorCode::None);
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+        assert_eq!(coordinator.node_id(&pool).unwrap(), 2);
+    }
+}
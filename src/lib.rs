@@ -1,17 +1,16 @@
-mod client;
-mod protocol;
-
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub mod bootstrap;
+pub mod cluster;
+pub mod connection_pool;
+pub mod consumer;
+pub mod coordinator;
+pub mod metadata_cache;
+pub mod metrics;
+pub mod partitioner;
+pub mod producer;
+pub mod oauth;
+pub mod protocol;
+pub mod retry;
+pub mod sasl;
+pub mod schema_registry;
+pub mod serialization;
+pub mod tls;
@@ -0,0 +1,701 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use kafka_encode::primitives::{CompactNullableArray, CompactNullableString, CompactString, NullableArray};
+use rand::Rng;
+use tracing::{debug, warn};
+use uuid::Uuid;
+use crate::bootstrap::{ClientDnsLookup, ResolvedAddresses};
+use crate::cluster::{ClusterMetadata, Node, PartitionMetadata, TopicPartition};
+use crate::protocol::ApiVersion;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::metadata::{
+    MetadataRequestTopicV0V9, MetadataRequestTopicV10V12, MetadataRequestTopicV9, MetadataRequestV1V3, MetadataRequestV10,
+    MetadataRequestV11V12, MetadataRequestV4V7, MetadataRequestV8, MetadataRequestV9, MetadataResponseV10, MetadataResponseV11V12,
+    MetadataResponseV3V4, MetadataResponseV7, MetadataResponseV8, MetadataResponseV9
+};
+use crate::protocol::networking::{ConnectionConfig, KafkaConnection};
+use crate::protocol::tags::TaggedFields;
+
+/// Bootstrap servers have no node id until metadata tells us which broker they are.
+const BOOTSTRAP_NODE_ID: i32 = -1;
+
+/// The Metadata versions this crate can send. Version 0 isn't one of them, since it can't ask for
+/// only the brokers: an empty topic list asks for every topic.
+const METADATA_VERSIONS: [ApiVersion; 6] = [3, 7, 8, 9, 10, 12];
+
+/// Where a `MetadataCache` gets fresh metadata from.
+pub trait MetadataFetcher: Send + Sync {
+    /// Fetches metadata for the given topics. An empty slice asks only for the brokers.
+    fn fetch_metadata(&self, topics: &[String]) -> Result<ClusterMetadata>;
+}
+
+/// Fetches metadata with the newest Metadata version that the broker supports. Once metadata has
+/// named the brokers, requests go to the least-loaded of them: the one the fetcher is already
+/// connected to, or else any of them, starting at a random one. The bootstrap servers are only
+/// used when none of the known brokers answers, moving on to the next bootstrap address whenever
+/// one fails.
+#[derive(Debug)]
+pub struct NetworkMetadataFetcher {
+    pub connection: ConnectionConfig,
+    pub allow_auto_topic_creation: bool,
+    state: Mutex<FetcherState>
+}
+
+#[derive(Debug)]
+struct FetcherState {
+    bootstrap_addresses: ResolvedAddresses,
+    /// The brokers named by the last metadata.
+    brokers: Vec<Node>,
+    /// The connection the last metadata came through, if its broker is one of `brokers`.
+    connection: Option<KafkaConnection>
+}
+
+impl FetcherState {
+    /// Remembers the brokers a response named, and keeps the connection it came through if it is
+    /// to one of them rather than to a bootstrap server.
+    fn update(&mut self, connection: KafkaConnection, cluster: ClusterMetadata) -> ClusterMetadata {
+        self.brokers = cluster.brokers.values().cloned().collect();
+        self.brokers.sort_by_key(|node| node.node_id);
+        if cluster.brokers.contains_key(&connection.node_id) {
+            self.connection = Some(connection);
+        }
+        cluster
+    }
+}
+
+impl NetworkMetadataFetcher {
+    /// `bootstrap_servers` is a comma-separated list of `host:port` pairs.
+    pub fn new(bootstrap_servers: &str, client_id: &str, client_dns_lookup: ClientDnsLookup) -> Result<Self> {
+        Ok(NetworkMetadataFetcher {
+            connection: ConnectionConfig {
+                client_id: String::from(client_id),
+                ..ConnectionConfig::default()
+            },
+            allow_auto_topic_creation: true,
+            state: Mutex::new(FetcherState {
+                bootstrap_addresses: ResolvedAddresses::resolve_bootstrap_servers(bootstrap_servers, client_dns_lookup)?,
+                brokers: Vec::new(),
+                connection: None
+            })
+        })
+    }
+
+    fn connect(&self, node: &Node) -> Result<KafkaConnection> {
+        let port: u16 = u16::try_from(node.port)
+            .map_err(|_| anyhow!("Node {} has an invalid port: {}", node.node_id, node.port))?;
+        ResolvedAddresses::resolve_host(&node.host, port)?.try_each(|address| {
+            KafkaConnection::connect(node.node_id, address, &self.connection)
+        })
+    }
+
+    /// Sends the newest Metadata version that the broker supports.
+    fn send_metadata_request(&self, connection: &mut KafkaConnection, topics: &[String]) -> Result<ClusterMetadata> {
+        let node_id: i32 = connection.node_id;
+        let version: ApiVersion = connection.api_versions()?
+            .highest_common_version(ApiKey::Metadata, &METADATA_VERSIONS)
+            .ok_or_else(|| anyhow!("Node {} supports none of the Metadata versions this client can send", node_id))?;
+        debug!("Fetching metadata from node {} with Metadata v{}", node_id, version);
+        let allow_auto_topic_creation: bool = self.allow_auto_topic_creation;
+        let names = || NullableArray(Some(topics.iter()
+            .map(|topic| MetadataRequestTopicV0V9 { name: topic.clone() })
+            .collect()));
+        let ids_or_names = || CompactNullableArray(Some(topics.iter()
+            .map(|topic| MetadataRequestTopicV10V12 {
+                topic_id: Uuid::nil(),
+                name: CompactNullableString(Some(topic.clone())),
+                tag_buffer: TaggedFields::new()
+            })
+            .collect()));
+        let cluster: ClusterMetadata = match version {
+            12 => ClusterMetadata::from(connection.send_request_and_get_response::<MetadataRequestV11V12, MetadataResponseV11V12>(MetadataRequestV11V12 {
+                topics: ids_or_names(),
+                allow_auto_topic_creation,
+                include_topic_authorized_operations: false,
+                tag_buffer: TaggedFields::new()
+            })?),
+            10 => ClusterMetadata::from(connection.send_request_and_get_response::<MetadataRequestV10, MetadataResponseV10>(MetadataRequestV10 {
+                topics: ids_or_names(),
+                allow_auto_topic_creation,
+                include_cluster_authorized_operations: false,
+                include_topic_authorized_operations: false,
+                tag_buffer: TaggedFields::new()
+            })?),
+            9 => ClusterMetadata::from(connection.send_request_and_get_response::<MetadataRequestV9, MetadataResponseV9>(MetadataRequestV9 {
+                topics: CompactNullableArray(Some(topics.iter()
+                    .map(|topic| MetadataRequestTopicV9 { name: CompactString(topic.clone()), tag_buffer: TaggedFields::new() })
+                    .collect())),
+                allow_auto_topic_creation,
+                include_cluster_authorized_operations: false,
+                include_topic_authorized_operations: false,
+                tag_buffer: TaggedFields::new()
+            })?),
+            8 => ClusterMetadata::from(connection.send_request_and_get_response::<MetadataRequestV8, MetadataResponseV8>(MetadataRequestV8 {
+                topics: names(),
+                allow_auto_topic_creation,
+                include_cluster_authorized_operations: false,
+                include_topic_authorized_operations: false
+            })?),
+            7 => ClusterMetadata::from(connection.send_request_and_get_response::<MetadataRequestV4V7, MetadataResponseV7>(MetadataRequestV4V7 {
+                topics: names(),
+                allow_auto_topic_creation
+            })?),
+            // before version 4 the broker decides whether to create topics
+            _ => ClusterMetadata::from(connection.send_request_and_get_response::<MetadataRequestV1V3, MetadataResponseV3V4>(MetadataRequestV1V3 {
+                topics: names()
+            })?)
+        };
+        Ok(cluster)
+    }
+}
+
+impl MetadataFetcher for NetworkMetadataFetcher {
+    fn fetch_metadata(&self, topics: &[String]) -> Result<ClusterMetadata> {
+        let mut state: MutexGuard<FetcherState> = self.state.lock().expect("Metadata fetcher lock was poisoned");
+        if let Some(mut connection) = state.connection.take() {
+            match self.send_metadata_request(&mut connection, topics) {
+                Ok(cluster) => return Ok(state.update(connection, cluster)),
+                Err(e) => debug!("Failed to fetch metadata from node {}: {:#}", connection.node_id, e)
+            }
+        }
+        let mut brokers: Vec<Node> = state.brokers.clone();
+        if !brokers.is_empty() {
+            // spread the load of new connections between clients
+            let offset: usize = rand::thread_rng().gen_range(0..brokers.len());
+            brokers.rotate_left(offset);
+        }
+        for node in &brokers {
+            let fetched: Result<(KafkaConnection, ClusterMetadata)> = self.connect(node).and_then(|mut connection| {
+                let cluster: ClusterMetadata = self.send_metadata_request(&mut connection, topics)?;
+                Ok((connection, cluster))
+            });
+            match fetched {
+                Ok((connection, cluster)) => return Ok(state.update(connection, cluster)),
+                Err(e) => debug!("Failed to fetch metadata from node {}: {:#}", node.node_id, e)
+            }
+        }
+        if !brokers.is_empty() {
+            warn!("None of the {} known brokers answered a Metadata request, so falling back to the bootstrap servers", brokers.len());
+        }
+        let (connection, cluster): (KafkaConnection, ClusterMetadata) = state.bootstrap_addresses.try_each(|address| {
+            let mut connection: KafkaConnection = KafkaConnection::connect(BOOTSTRAP_NODE_ID, address, &self.connection)?;
+            let cluster: ClusterMetadata = self.send_metadata_request(&mut connection, topics)?;
+            Ok((connection, cluster))
+        })?;
+        Ok(state.update(connection, cluster))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetadataCacheConfig {
+    /// `metadata.max.age.ms`: metadata is refreshed at least this often, even if nothing has gone wrong.
+    pub metadata_max_age: Duration,
+    /// `retry.backoff.ms`: the minimum time between two refresh attempts.
+    pub retry_backoff: Duration
+}
+
+impl Default for MetadataCacheConfig {
+    fn default() -> Self {
+        MetadataCacheConfig {
+            metadata_max_age: Duration::from_millis(300_000),
+            retry_backoff: Duration::from_millis(100)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MetadataState {
+    cluster: Arc<ClusterMetadata>,
+    /// Incremented on every accepted update.
+    version: u64,
+    topics: HashSet<String>,
+    last_seen_leader_epochs: HashMap<TopicPartition, i32>,
+    last_refresh_attempt: Option<Instant>,
+    last_successful_refresh: Option<Instant>,
+    needs_update: bool,
+    closed: bool
+}
+
+impl MetadataState {
+    /// When the refresh thread should next fetch metadata.
+    fn next_refresh(&self, config: &MetadataCacheConfig) -> Option<Instant> {
+        let backoff_expiry: Option<Instant> = self.last_refresh_attempt.map(|attempt| attempt + config.retry_backoff);
+        let due: Option<Instant> = if self.needs_update {
+            None
+        } else {
+            self.last_successful_refresh.map(|refresh| refresh + config.metadata_max_age)
+        };
+        match (due, backoff_expiry) {
+            (Some(due), Some(backoff_expiry)) => Some(due.max(backoff_expiry)),
+            (due, backoff_expiry) => due.or(backoff_expiry)
+        }
+    }
+
+    /// Replaces the cached metadata, keeping the cached state of any partition whose leader epoch
+    /// went backwards. Returns true if any partition was stale.
+    fn update(&mut self, mut cluster: ClusterMetadata) -> bool {
+        let mut saw_stale_partition: bool = false;
+        for topic in cluster.topics.values_mut() {
+            for partition in topic.partitions.iter_mut() {
+                let topic_partition: TopicPartition = TopicPartition::new(&topic.name, partition.partition);
+                let leader_epoch: i32 = match partition.leader_epoch {
+                    Some(leader_epoch) => leader_epoch,
+                    None => continue
+                };
+                match self.last_seen_leader_epochs.get(&topic_partition) {
+                    Some(last_seen_epoch) if leader_epoch < *last_seen_epoch => {
+                        debug!("Ignoring stale metadata for {:?}: leader epoch {} is older than {}",
+                            topic_partition, leader_epoch, last_seen_epoch);
+                        saw_stale_partition = true;
+                        if let Some(cached) = self.cluster.partition(&topic_partition) {
+                            let cached: PartitionMetadata = cached.clone();
+                            *partition = cached;
+                        }
+                    },
+                    _ => {
+                        self.last_seen_leader_epochs.insert(topic_partition, leader_epoch);
+                    }
+                }
+            }
+        }
+
+        self.cluster = Arc::new(cluster);
+        self.version += 1;
+        self.last_successful_refresh = Some(Instant::now());
+        // keep asking until the brokers catch up with the epochs we have already seen
+        if saw_stale_partition {
+            self.needs_update = true;
+        }
+        saw_stale_partition
+    }
+}
+
+#[derive(Debug)]
+struct SharedMetadata {
+    state: Mutex<MetadataState>,
+    /// Signalled whenever the state changes, which wakes both the refresh thread and any waiters.
+    changed: Condvar
+}
+
+impl SharedMetadata {
+    fn lock(&self) -> MutexGuard<'_, MetadataState> {
+        self.state.lock().expect("Metadata cache lock was poisoned")
+    }
+}
+
+/// Cluster metadata shared by the producer, consumer and admin paths. A background thread refreshes
+/// it every `metadata_max_age`, or as soon as an update is requested.
+#[derive(Debug)]
+pub struct MetadataCache {
+    shared: Arc<SharedMetadata>,
+    refresh_thread: Option<JoinHandle<()>>
+}
+
+impl MetadataCache {
+    pub fn new(fetcher: Box<dyn MetadataFetcher>, config: MetadataCacheConfig) -> Self {
+        let shared: Arc<SharedMetadata> = Arc::new(SharedMetadata {
+            state: Mutex::new(MetadataState {
+                cluster: Arc::new(ClusterMetadata::default()),
+                version: 0,
+                topics: HashSet::new(),
+                last_seen_leader_epochs: HashMap::new(),
+                last_refresh_attempt: None,
+                last_successful_refresh: None,
+                needs_update: true,
+                closed: false
+            }),
+            changed: Condvar::new()
+        });
+        let thread_shared: Arc<SharedMetadata> = shared.clone();
+        let refresh_thread: JoinHandle<()> = thread::Builder::new()
+            .name(String::from("kafkart-metadata"))
+            .spawn(move || refresh_loop(thread_shared, fetcher, config))
+            .expect("Failed to spawn the metadata refresh thread");
+        MetadataCache {
+            shared,
+            refresh_thread: Some(refresh_thread)
+        }
+    }
+
+    /// A snapshot of the latest accepted metadata.
+    pub fn cluster(&self) -> Arc<ClusterMetadata> {
+        self.shared.lock().cluster.clone()
+    }
+
+    /// The number of updates accepted so far.
+    pub fn version(&self) -> u64 {
+        self.shared.lock().version
+    }
+
+    /// Includes a topic in future refreshes, requesting an immediate refresh if it is new.
+    pub fn add_topic(&self, topic: &str) {
+        let mut state: MutexGuard<MetadataState> = self.shared.lock();
+        if state.topics.insert(String::from(topic)) {
+            state.needs_update = true;
+            self.shared.changed.notify_all();
+        }
+    }
+
+    pub fn topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self.shared.lock().topics.iter().cloned().collect();
+        topics.sort();
+        topics
+    }
+
+    /// Asks the refresh thread to fetch metadata as soon as `retry_backoff` allows.
+    pub fn request_update(&self) {
+        let mut state: MutexGuard<MetadataState> = self.shared.lock();
+        state.needs_update = true;
+        self.shared.changed.notify_all();
+    }
+
+    /// Requests an update if an error code in a response means the metadata is out of date.
+    /// Returns whether an update was requested.
+    pub fn handle_error_code(&self, error_code: &ErrorCode) -> bool {
+        if error_code.is_invalid_metadata() {
+            debug!("Requesting a metadata update after error: {:?}", error_code);
+            self.request_update();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Records a leader epoch seen outside of a Metadata response, such as in a Fetch response. If it
+    /// is newer than the cached one, an update is requested and true is returned.
+    pub fn update_last_seen_epoch_if_newer(&self, topic_partition: &TopicPartition, leader_epoch: i32) -> bool {
+        let mut state: MutexGuard<MetadataState> = self.shared.lock();
+        let is_newer: bool = match state.last_seen_leader_epochs.get(topic_partition) {
+            Some(last_seen_epoch) => leader_epoch > *last_seen_epoch,
+            None => true
+        };
+        if is_newer {
+            state.last_seen_leader_epochs.insert(topic_partition.clone(), leader_epoch);
+            state.needs_update = true;
+            self.shared.changed.notify_all();
+        }
+        is_newer
+    }
+
+    pub fn last_seen_leader_epoch(&self, topic_partition: &TopicPartition) -> Option<i32> {
+        self.shared.lock().last_seen_leader_epochs.get(topic_partition).copied()
+    }
+
+    /// Applies metadata fetched by the refresh thread or by a caller which sent its own request.
+    pub fn update(&self, cluster: ClusterMetadata) {
+        let mut state: MutexGuard<MetadataState> = self.shared.lock();
+        state.update(cluster);
+        self.shared.changed.notify_all();
+    }
+
+    /// Blocks until the cache holds usable metadata for a topic, adding the topic to the cache if
+    /// necessary. Fails on timeout or if the broker reports an error which retrying cannot fix.
+    pub fn wait_for_topic(&self, topic: &str, timeout: Duration) -> Result<Arc<ClusterMetadata>> {
+        let deadline: Instant = Instant::now() + timeout;
+        self.add_topic(topic);
+
+        let mut state: MutexGuard<MetadataState> = self.shared.lock();
+        loop {
+            if state.closed {
+                return Err(anyhow!("The metadata cache was closed while waiting for topic {}", topic));
+            }
+            if let Some(topic_metadata) = state.cluster.topic(topic) {
+                match &topic_metadata.error_code {
+                    ErrorCode::None if !topic_metadata.partitions.is_empty() => return Ok(state.cluster.clone()),
+                    ErrorCode::None => {},
+                    error_code if error_code.is_invalid_metadata() => {},
+                    error_code => return Err(anyhow!("Failed to get metadata for topic {}: {}", topic, error_code))
+                }
+            }
+
+            let now: Instant = Instant::now();
+            if now >= deadline {
+                return Err(anyhow!("Topic {} not present in metadata after {:?}", topic, timeout));
+            }
+            if !state.needs_update && state.topics.contains(topic) {
+                state.needs_update = true;
+                self.shared.changed.notify_all();
+            }
+            state = self.shared.changed.wait_timeout(state, deadline - now)
+                .expect("Metadata cache lock was poisoned").0;
+        }
+    }
+
+    /// Blocks until an update newer than `version` is accepted. Returns false on timeout.
+    pub fn wait_for_update(&self, version: u64, timeout: Duration) -> bool {
+        let state: MutexGuard<MetadataState> = self.shared.lock();
+        let (state, _) = self.shared.changed
+            .wait_timeout_while(state, timeout, |state| state.version <= version && !state.closed)
+            .expect("Metadata cache lock was poisoned");
+        state.version > version
+    }
+}
+
+impl Drop for MetadataCache {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(refresh_thread) = self.refresh_thread.take() {
+            let _ = refresh_thread.join();
+        }
+    }
+}
+
+fn refresh_loop(shared: Arc<SharedMetadata>, fetcher: Box<dyn MetadataFetcher>, config: MetadataCacheConfig) {
+    loop {
+        let topics: Vec<String> = {
+            let mut state: MutexGuard<MetadataState> = shared.lock();
+            loop {
+                if state.closed {
+                    return;
+                }
+                let now: Instant = Instant::now();
+                match state.next_refresh(&config) {
+                    Some(next_refresh) if next_refresh > now => {
+                        state = shared.changed.wait_timeout(state, next_refresh - now)
+                            .expect("Metadata cache lock was poisoned").0;
+                    },
+                    _ => break
+                }
+            }
+            state.last_refresh_attempt = Some(Instant::now());
+            state.needs_update = false;
+            state.topics.iter().cloned().collect()
+        };
+
+        match fetcher.fetch_metadata(&topics) {
+            Ok(cluster) => {
+                let mut state: MutexGuard<MetadataState> = shared.lock();
+                state.update(cluster);
+                shared.changed.notify_all();
+            },
+            Err(e) => {
+                warn!("Failed to refresh metadata: {:?}", e);
+                shared.lock().needs_update = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use anyhow::{anyhow, Result};
+    use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+    use crate::bootstrap::ClientDnsLookup;
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::cluster::tests::one_topic_cluster;
+    use crate::metadata_cache::{MetadataCache, MetadataCacheConfig, MetadataFetcher, NetworkMetadataFetcher};
+    use crate::protocol::ApiVersion;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::metadata::{
+        AUTHORIZED_OPERATIONS_OMITTED, MetadataRequestV11V12, MetadataRequestV9, MetadataResponseBrokerV9V12, MetadataResponseV11V12, MetadataResponseV9
+    };
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::tags::TaggedFields;
+
+    /// Always returns the same cluster.
+    pub(crate) struct StaticFetcher(pub ClusterMetadata);
+
+    impl MetadataFetcher for StaticFetcher {
+        fn fetch_metadata(&self, _topics: &[String]) -> Result<ClusterMetadata> {
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Hands out queued responses in order, repeating the last one once the queue runs dry.
+    #[derive(Clone)]
+    struct FakeFetcher {
+        responses: Arc<Mutex<VecDeque<ClusterMetadata>>>,
+        requested_topics: Arc<Mutex<Vec<Vec<String>>>>
+    }
+
+    impl FakeFetcher {
+        fn new(responses: Vec<ClusterMetadata>) -> Self {
+            FakeFetcher {
+                responses: Arc::new(Mutex::new(responses.into())),
+                requested_topics: Arc::new(Mutex::new(Vec::new()))
+            }
+        }
+
+        fn fetch_count(&self) -> usize {
+            self.requested_topics.lock().unwrap().len()
+        }
+    }
+
+    impl MetadataFetcher for FakeFetcher {
+        fn fetch_metadata(&self, topics: &[String]) -> Result<ClusterMetadata> {
+            self.requested_topics.lock().unwrap().push(topics.to_vec());
+            let mut responses = self.responses.lock().unwrap();
+            let mut cluster: ClusterMetadata = match responses.len() {
+                0 => return Err(anyhow!("No metadata queued")),
+                1 => responses.front().unwrap().clone(),
+                _ => responses.pop_front().unwrap()
+            };
+            cluster.topics.retain(|name, _| topics.contains(name));
+            Ok(cluster)
+        }
+    }
+
+    /// A cluster of nodes 1 and 2 with one partition, led by one of them.
+    fn cluster(topic: &str, leader_id: i32, leader_epoch: i32) -> ClusterMetadata {
+        let mut cluster: ClusterMetadata = one_topic_cluster(topic, &[leader_id]);
+        cluster.brokers = one_topic_cluster(topic, &[1, 2]).brokers;
+        cluster.topics.get_mut(topic).unwrap().partitions[0].leader_epoch = Some(leader_epoch);
+        cluster
+    }
+
+    fn config(metadata_max_age: Duration) -> MetadataCacheConfig {
+        MetadataCacheConfig {
+            metadata_max_age,
+            retry_backoff: Duration::from_millis(1)
+        }
+    }
+
+    #[test]
+    fn test_wait_for_topic() {
+        let fetcher: FakeFetcher = FakeFetcher::new(vec![cluster("events", 1, 0)]);
+        let cache: MetadataCache = MetadataCache::new(Box::new(fetcher.clone()), config(Duration::from_secs(60)));
+        let metadata: Arc<ClusterMetadata> = cache.wait_for_topic("events", Duration::from_secs(5)).unwrap();
+        assert_eq!(metadata.leader(&TopicPartition::new("events", 0)).unwrap().node_id, 1);
+        assert!(fetcher.requested_topics.lock().unwrap().contains(&vec![String::from("events")]));
+    }
+
+    #[test]
+    fn test_wait_for_missing_topic_times_out() {
+        let fetcher: FakeFetcher = FakeFetcher::new(vec![cluster("events", 1, 0)]);
+        let cache: MetadataCache = MetadataCache::new(Box::new(fetcher), config(Duration::from_secs(60)));
+        assert!(cache.wait_for_topic("missing", Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_refresh_after_max_age() {
+        let fetcher: FakeFetcher = FakeFetcher::new(vec![cluster("events", 1, 0), cluster("events", 2, 1)]);
+        let cache: MetadataCache = MetadataCache::new(Box::new(fetcher), config(Duration::from_millis(20)));
+        cache.wait_for_topic("events", Duration::from_secs(5)).unwrap();
+        assert!(cache.wait_for_update(cache.version(), Duration::from_secs(5)));
+        assert_eq!(cache.cluster().leader(&TopicPartition::new("events", 0)).unwrap().node_id, 2);
+    }
+
+    #[test]
+    fn test_invalid_metadata_error_triggers_refresh() {
+        let fetcher: FakeFetcher = FakeFetcher::new(vec![cluster("events", 1, 0)]);
+        let cache: MetadataCache = MetadataCache::new(Box::new(fetcher.clone()), config(Duration::from_secs(60)));
+        assert!(cache.wait_for_update(0, Duration::from_secs(5)));
+        let fetches: usize = fetcher.fetch_count();
+
+        assert!(!cache.handle_error_code(&ErrorCode::InvalidFetchSize));
+        assert!(cache.handle_error_code(&ErrorCode::NotLeaderOrFollower));
+        assert!(cache.wait_for_update(cache.version(), Duration::from_secs(5)));
+        assert!(fetcher.fetch_count() > fetches);
+    }
+
+    #[test]
+    fn test_stale_leader_epoch_is_rejected() {
+        // nothing to fetch, and a long backoff so the refresh thread stays out of the way
+        let fetcher: FakeFetcher = FakeFetcher::new(vec![]);
+        let cache: MetadataCache = MetadataCache::new(Box::new(fetcher), MetadataCacheConfig {
+            metadata_max_age: Duration::from_secs(60),
+            retry_backoff: Duration::from_secs(60)
+        });
+        let topic_partition: TopicPartition = TopicPartition::new("events", 0);
+
+        cache.update(cluster("events", 2, 5));
+        cache.update(cluster("events", 1, 4));
+        assert_eq!(cache.cluster().leader(&topic_partition).unwrap().node_id, 2);
+        assert_eq!(cache.last_seen_leader_epoch(&topic_partition), Some(5));
+
+        cache.update(cluster("events", 1, 6));
+        assert_eq!(cache.cluster().leader(&topic_partition).unwrap().node_id, 1);
+        assert!(!cache.update_last_seen_epoch_if_newer(&topic_partition, 6));
+        assert!(cache.update_last_seen_epoch_if_newer(&topic_partition, 7));
+    }
+
+    /// A broker which supports Metadata up to `max_version`, and answers v9 and v12 requests for
+    /// no topics with the brokers listening on 127.0.0.1 at the given node ids and ports.
+    fn metadata_broker(max_version: ApiVersion, brokers: Arc<Mutex<Vec<(i32, i32)>>>) -> MockBroker {
+        MockBroker::start_with_api_versions(vec![(ApiKey::Metadata, max_version)], move |request: &MockRequest| {
+            let brokers: Vec<MetadataResponseBrokerV9V12> = brokers.lock().unwrap().iter()
+                .map(|(node_id, port)| MetadataResponseBrokerV9V12 {
+                    node_id: *node_id,
+                    host: CompactString(String::from("127.0.0.1")),
+                    port: *port,
+                    rack: CompactNullableString(None),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect();
+            let cluster_id: CompactNullableString = CompactNullableString(Some(String::from("cluster")));
+            match request.api_version {
+                9 => {
+                    assert_eq!(request.decode::<MetadataRequestV9>().unwrap().topics.0, Some(vec![]));
+                    Some(request.respond::<MetadataRequestV9, MetadataResponseV9>(MetadataResponseV9 {
+                        throttle_time_ms: 0,
+                        brokers: CompactArray(brokers),
+                        cluster_id,
+                        controller_id: 1,
+                        topics: CompactArray(vec![]),
+                        cluster_authorized_operations: AUTHORIZED_OPERATIONS_OMITTED,
+                        tag_buffer: TaggedFields::new()
+                    }))
+                },
+                _ => {
+                    assert_eq!(request.decode::<MetadataRequestV11V12>().unwrap().topics.0, Some(vec![]));
+                    Some(request.respond::<MetadataRequestV11V12, MetadataResponseV11V12>(MetadataResponseV11V12 {
+                        throttle_time_ms: 0,
+                        brokers: CompactArray(brokers),
+                        cluster_id,
+                        controller_id: 1,
+                        topics: CompactArray(vec![]),
+                        tag_buffer: TaggedFields::new()
+                    }))
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_network_fetcher_moves_past_dead_bootstrap_servers() {
+        let dead_port: i32 = MockBroker::start(|_| None).port();
+        let broker: MockBroker = metadata_broker(12, Arc::new(Mutex::new(vec![(1, dead_port)])));
+
+        let bootstrap_servers: String = format!("127.0.0.1:{}, 127.0.0.1:{}", dead_port, broker.port());
+        let fetcher: NetworkMetadataFetcher = NetworkMetadataFetcher::new(&bootstrap_servers, "kafkart", ClientDnsLookup::UseAllDnsIps).unwrap();
+        for _ in 0..2 {
+            let metadata: ClusterMetadata = fetcher.fetch_metadata(&[]).unwrap();
+            assert_eq!(metadata.cluster_id, Some(String::from("cluster")));
+            assert_eq!(metadata.node(1).unwrap().address(), format!("127.0.0.1:{}", dead_port));
+        }
+        // node 1 can't be reached either, so the second fetch goes back to the bootstrap servers
+        assert_eq!(broker.accepted_connections(), 2);
+    }
+
+    #[test]
+    fn test_network_fetcher_negotiates_the_metadata_version() {
+        let broker: MockBroker = metadata_broker(9, Arc::new(Mutex::new(vec![(1, 9092)])));
+        let fetcher: NetworkMetadataFetcher = NetworkMetadataFetcher::new(&format!("127.0.0.1:{}", broker.port()), "kafkart", ClientDnsLookup::UseAllDnsIps).unwrap();
+        let metadata: ClusterMetadata = fetcher.fetch_metadata(&[]).unwrap();
+        assert_eq!(metadata.node(1).unwrap().address(), "127.0.0.1:9092");
+    }
+
+    #[test]
+    fn test_network_fetcher_moves_from_the_bootstrap_servers_to_known_brokers() {
+        let brokers: Arc<Mutex<Vec<(i32, i32)>>> = Arc::new(Mutex::new(Vec::new()));
+        let bootstrap: MockBroker = metadata_broker(12, brokers.clone());
+        let known: MockBroker = metadata_broker(12, brokers.clone());
+        *brokers.lock().unwrap() = vec![(1, known.port())];
+
+        let fetcher: NetworkMetadataFetcher = NetworkMetadataFetcher::new(&format!("127.0.0.1:{}", bootstrap.port()), "kafkart", ClientDnsLookup::UseAllDnsIps).unwrap();
+        for _ in 0..3 {
+            assert_eq!(fetcher.fetch_metadata(&[]).unwrap().node(1).unwrap().port, known.port());
+        }
+        // the connection to the known broker is kept for the next fetch
+        assert_eq!((bootstrap.accepted_connections(), known.accepted_connections()), (1, 1));
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use crate::protocol::api_key::ApiKey;
+
+/// Throttling of one kind of request, like the Java client's `produce-throttle-time-avg` and
+/// `produce-throttle-time-max` metrics.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ThrottleStats {
+    pub responses: u64,
+    /// Responses with a throttle time above zero.
+    pub throttled_responses: u64,
+    pub total_throttle_time: Duration,
+    pub max_throttle_time: Duration
+}
+
+impl ThrottleStats {
+    /// The average throttle time across every response, throttled or not.
+    pub fn avg_throttle_time(&self) -> Duration {
+        match self.responses {
+            0 => Duration::ZERO,
+            responses => self.total_throttle_time / responses as u32
+        }
+    }
+
+    fn record(&mut self, throttle_time: Duration) {
+        self.responses += 1;
+        if !throttle_time.is_zero() {
+            self.throttled_responses += 1;
+        }
+        self.total_throttle_time += throttle_time;
+        self.max_throttle_time = self.max_throttle_time.max(throttle_time);
+    }
+
+    fn merge(&mut self, other: &ThrottleStats) {
+        self.responses += other.responses;
+        self.throttled_responses += other.throttled_responses;
+        self.total_throttle_time += other.total_throttle_time;
+        self.max_throttle_time = self.max_throttle_time.max(other.max_throttle_time);
+    }
+}
+
+/// The throttle times brokers have reported, by the API of the request which was throttled.
+#[derive(Debug, Default)]
+pub struct ThrottleMetrics {
+    by_api_key: Mutex<HashMap<ApiKey, ThrottleStats>>
+}
+
+impl ThrottleMetrics {
+    fn lock(&self) -> MutexGuard<'_, HashMap<ApiKey, ThrottleStats>> {
+        self.by_api_key.lock().expect("Throttle metrics lock was poisoned")
+    }
+
+    pub fn record(&self, api_key: ApiKey, throttle_time: Duration) {
+        self.lock().entry(api_key).or_default().record(throttle_time);
+    }
+
+    pub fn for_api_key(&self, api_key: &ApiKey) -> ThrottleStats {
+        self.lock().get(api_key).cloned().unwrap_or_default()
+    }
+
+    /// Every API's throttling combined.
+    pub fn total(&self) -> ThrottleStats {
+        let mut total: ThrottleStats = ThrottleStats::default();
+        for stats in self.lock().values() {
+            total.merge(stats);
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::metrics::{ThrottleMetrics, ThrottleStats};
+    use crate::protocol::api_key::ApiKey;
+
+    #[test]
+    fn test_throttle_metrics() {
+        let metrics: ThrottleMetrics = ThrottleMetrics::default();
+        metrics.record(ApiKey::Fetch, Duration::ZERO);
+        metrics.record(ApiKey::Fetch, Duration::from_millis(300));
+        metrics.record(ApiKey::Produce, Duration::from_millis(500));
+
+        let fetch: ThrottleStats = metrics.for_api_key(&ApiKey::Fetch);
+        assert_eq!(fetch.responses, 2);
+        assert_eq!(fetch.throttled_responses, 1);
+        assert_eq!(fetch.avg_throttle_time(), Duration::from_millis(150));
+        assert_eq!(fetch.max_throttle_time, Duration::from_millis(300));
+
+        let total: ThrottleStats = metrics.total();
+        assert_eq!(total.responses, 3);
+        assert_eq!(total.max_throttle_time, Duration::from_millis(500));
+        assert_eq!(metrics.for_api_key(&ApiKey::Metadata), ThrottleStats::default());
+    }
+}
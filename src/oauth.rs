@@ -0,0 +1,427 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use rand::Rng;
+use serde::Deserialize;
+use tracing::{debug, warn};
+use crate::sasl::{SaslExchange, SaslMechanism};
+
+/// The separator between the key/value pairs of an OAUTHBEARER message (RFC 7628).
+const KVSEP: char = '\u{1}';
+
+/// An OAuth access token and when it stops being valid.
+#[derive(Clone)]
+pub struct OAuthBearerToken {
+    pub value: String,
+    pub expires_at: SystemTime
+}
+
+impl Debug for OAuthBearerToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthBearerToken")
+            .field("value", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Supplies the tokens which SASL/OAUTHBEARER presents to brokers.
+pub trait TokenProvider: Send + Sync + Debug {
+    /// Fetches a new token. This is called again each time the previous token is due to be refreshed.
+    fn fetch_token(&self) -> Result<OAuthBearerToken>;
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>
+}
+
+#[derive(Deserialize)]
+struct JwtClaims {
+    exp: Option<u64>
+}
+
+/// Reads the `exp` claim of a JWT, for token endpoints which don't send `expires_in`.
+fn jwt_expiry(token: &str) -> Result<SystemTime> {
+    let payload: &str = token.split('.').nth(1)
+        .ok_or_else(|| anyhow!("The access token has no expires_in and is not a JWT"))?;
+    let claims: JwtClaims = serde_json::from_slice(&BASE64_URL.decode(payload.trim_end_matches('='))?)?;
+    let exp: u64 = claims.exp
+        .ok_or_else(|| anyhow!("The access token has no expires_in and no exp claim"))?;
+    Ok(UNIX_EPOCH + Duration::from_secs(exp))
+}
+
+/// Fetches tokens from an OAuth token endpoint with the client credentials grant, as configured with
+/// `sasl.oauthbearer.token.endpoint.url`.
+#[derive(Clone)]
+pub struct ClientCredentialsTokenProvider {
+    pub token_endpoint_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// `sasl.oauthbearer.scope`
+    pub scope: Option<String>,
+    http_client: reqwest::blocking::Client
+}
+
+impl ClientCredentialsTokenProvider {
+    pub fn new(token_endpoint_url: &str, client_id: &str, client_secret: &str) -> Result<Self> {
+        Ok(ClientCredentialsTokenProvider {
+            token_endpoint_url: String::from(token_endpoint_url),
+            client_id: String::from(client_id),
+            client_secret: String::from(client_secret),
+            scope: None,
+            http_client: reqwest::blocking::Client::builder()
+                .timeout(Duration::from_millis(10_000))
+                .build()?
+        })
+    }
+
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scope = Some(String::from(scope));
+        self
+    }
+}
+
+impl Debug for ClientCredentialsTokenProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientCredentialsTokenProvider")
+            .field("token_endpoint_url", &self.token_endpoint_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+impl TokenProvider for ClientCredentialsTokenProvider {
+    fn fetch_token(&self) -> Result<OAuthBearerToken> {
+        let mut form: Vec<(&str, &str)> = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        let requested_at: SystemTime = SystemTime::now();
+        let response: reqwest::blocking::Response = self.http_client.post(&self.token_endpoint_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&form)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!("The token endpoint {} responded with {}: {}",
+                self.token_endpoint_url, response.status(), response.text().unwrap_or_default()));
+        }
+        let token: TokenResponse = response.json()?;
+        let expires_at: SystemTime = match token.expires_in {
+            Some(expires_in) => requested_at + Duration::from_secs(expires_in),
+            None => jwt_expiry(&token.access_token)?
+        };
+        debug!("Fetched an access token from {} which expires at {:?}", self.token_endpoint_url, expires_at);
+        Ok(OAuthBearerToken {
+            value: token.access_token,
+            expires_at
+        })
+    }
+}
+
+/// When to replace a token with a fresh one, as with `sasl.login.refresh.window.factor` and
+/// `sasl.login.refresh.window.jitter`.
+#[derive(Debug, Clone)]
+pub struct TokenRefreshConfig {
+    /// The fraction of a token's lifetime after which it is refreshed.
+    pub window_factor: f64,
+    /// Up to this fraction of the lifetime is randomly added to the refresh time.
+    pub window_jitter: f64
+}
+
+impl Default for TokenRefreshConfig {
+    fn default() -> Self {
+        TokenRefreshConfig {
+            window_factor: 0.8,
+            window_jitter: 0.05
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    token: OAuthBearerToken,
+    refresh_at: SystemTime
+}
+
+/// SASL/OAUTHBEARER, which presents a token from a `TokenProvider`. The token is shared by every
+/// connection and is refreshed once it is part of the way through its lifetime.
+#[derive(Debug)]
+pub struct OAuthBearerMechanism {
+    provider: Arc<dyn TokenProvider>,
+    refresh: TokenRefreshConfig,
+    token: Mutex<Option<CachedToken>>
+}
+
+impl OAuthBearerMechanism {
+    pub fn new(provider: Arc<dyn TokenProvider>) -> Self {
+        OAuthBearerMechanism::with_refresh_config(provider, TokenRefreshConfig::default())
+    }
+
+    pub fn with_refresh_config(provider: Arc<dyn TokenProvider>, refresh: TokenRefreshConfig) -> Self {
+        OAuthBearerMechanism {
+            provider,
+            refresh,
+            token: Mutex::new(None)
+        }
+    }
+
+    /// The current token, fetching a new one first if it is due to be refreshed. If the refresh
+    /// fails, the old token is used for as long as it remains valid.
+    pub fn token(&self) -> Result<OAuthBearerToken> {
+        let mut cached: MutexGuard<Option<CachedToken>> = self.token.lock().expect("Token lock was poisoned");
+        let now: SystemTime = SystemTime::now();
+        if let Some(cached_token) = cached.as_ref() {
+            if now < cached_token.refresh_at {
+                return Ok(cached_token.token.clone());
+            }
+        }
+        match self.provider.fetch_token() {
+            Ok(token) => {
+                let lifetime: Duration = token.expires_at.duration_since(now).unwrap_or(Duration::ZERO);
+                let refresh_factor: f64 = self.refresh.window_factor
+                    + rand::thread_rng().gen_range(0.0..=self.refresh.window_jitter);
+                let refresh_at: SystemTime = now + lifetime.mul_f64(refresh_factor.min(1.0));
+                *cached = Some(CachedToken { token: token.clone(), refresh_at });
+                Ok(token)
+            },
+            Err(e) => match cached.as_ref() {
+                Some(cached_token) if now < cached_token.token.expires_at => {
+                    warn!("Failed to refresh the OAuth token, so the current one will be used until it expires: {:?}", e);
+                    Ok(cached_token.token.clone())
+                },
+                _ => Err(e)
+            }
+        }
+    }
+}
+
+impl SaslMechanism for OAuthBearerMechanism {
+    fn name(&self) -> &str {
+        "OAUTHBEARER"
+    }
+
+    fn start_exchange(&self) -> Result<Box<dyn SaslExchange>> {
+        let token: OAuthBearerToken = self.token()?;
+        let message: String = format!("n,,{}auth=Bearer {}{}{}", KVSEP, token.value, KVSEP, KVSEP);
+        Ok(Box::new(OAuthBearerExchange { message: Some(message.into_bytes()) }))
+    }
+}
+
+struct OAuthBearerExchange {
+    message: Option<Vec<u8>>
+}
+
+impl SaslExchange for OAuthBearerExchange {
+    fn evaluate_challenge(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(message) = self.message.take() {
+            return Ok(Some(message));
+        }
+        if challenge.is_empty() {
+            return Ok(None);
+        }
+        // the broker describes why it rejected the token, and waits for a lone separator before
+        // failing the authentication
+        warn!("The broker rejected the OAuth token: {}", String::from_utf8_lossy(challenge));
+        Ok(Some(KVSEP.to_string().into_bytes()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use anyhow::anyhow;
+    use base64::Engine;
+    use crate::oauth::{ClientCredentialsTokenProvider, OAuthBearerMechanism, OAuthBearerToken, TokenProvider, TokenRefreshConfig, BASE64_URL};
+    use crate::protocol::api_versions::ApiVersionsResponseV3;
+    use crate::protocol::mock_broker::MockBroker;
+    use crate::protocol::networking::{ConnectionConfig, KafkaConnection};
+    use crate::sasl::{SaslExchange, SaslMechanism};
+    use crate::sasl::tests::{api_versions_request, connect, sasl_broker};
+
+    /// A token endpoint which answers every request with `body`, recording the requests it receives.
+    struct MockTokenEndpoint {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>
+    }
+
+    impl MockTokenEndpoint {
+        fn start(body: &'static str) -> Self {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url: String = format!("http://{}/oauth2/token", listener.local_addr().unwrap());
+            let requests: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+            let recorded_requests: Arc<Mutex<Vec<String>>> = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else { break };
+                    let request: String = read_http_request(&stream);
+                    recorded_requests.lock().unwrap().push(request);
+                    let mut stream: TcpStream = stream;
+                    let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                   body.len(), body);
+                }
+            });
+            MockTokenEndpoint { url, requests }
+        }
+
+        fn request_count(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+    }
+
+    pub(crate) fn read_http_request(stream: &TcpStream) -> String {
+        let mut reader: BufReader<&TcpStream> = BufReader::new(stream);
+        let mut request: String = String::new();
+        let mut content_length: usize = 0;
+        loop {
+            let mut line: String = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(length) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                content_length = length.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+        }
+        let mut body: Vec<u8> = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        request.push_str(&String::from_utf8(body).unwrap());
+        request
+    }
+
+    /// Hands out numbered tokens which each last `lifetime`.
+    #[derive(Debug)]
+    struct CountingTokenProvider {
+        lifetime: Duration,
+        fetches: AtomicUsize,
+        fail: bool
+    }
+
+    impl TokenProvider for CountingTokenProvider {
+        fn fetch_token(&self) -> anyhow::Result<OAuthBearerToken> {
+            let fetch: usize = self.fetches.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.fail && fetch > 1 {
+                return Err(anyhow!("The token endpoint is down"));
+            }
+            Ok(OAuthBearerToken {
+                value: format!("token-{}", fetch),
+                expires_at: SystemTime::now() + self.lifetime
+            })
+        }
+    }
+
+    #[test]
+    fn test_client_credentials_provider() {
+        let endpoint: MockTokenEndpoint = MockTokenEndpoint::start(r#"{"access_token":"abc.def.ghi","token_type":"Bearer","expires_in":3600}"#);
+        let provider: ClientCredentialsTokenProvider = ClientCredentialsTokenProvider::new(&endpoint.url, "kafka-client", "s3cret")
+            .unwrap()
+            .with_scope("kafka");
+        let token: OAuthBearerToken = provider.fetch_token().unwrap();
+        assert_eq!(token.value, "abc.def.ghi");
+        let lifetime: Duration = token.expires_at.duration_since(SystemTime::now()).unwrap();
+        assert!(lifetime > Duration::from_secs(3590) && lifetime <= Duration::from_secs(3600));
+
+        let request: String = endpoint.requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /oauth2/token "));
+        let credentials: String = base64::engine::general_purpose::STANDARD.encode("kafka-client:s3cret");
+        assert!(request.contains(&format!("authorization: Basic {}", credentials)));
+        assert!(request.ends_with("grant_type=client_credentials&scope=kafka"));
+    }
+
+    #[test]
+    fn test_token_expiry_is_read_from_the_jwt() {
+        let exp: u64 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 600;
+        let payload: String = BASE64_URL.encode(format!(r#"{{"sub":"kafka-client","exp":{}}}"#, exp));
+        let body: &'static str = Box::leak(format!(r#"{{"access_token":"eyJhbGciOiJub25lIn0.{}.","token_type":"Bearer"}}"#, payload).into_boxed_str());
+        let endpoint: MockTokenEndpoint = MockTokenEndpoint::start(body);
+        let provider: ClientCredentialsTokenProvider = ClientCredentialsTokenProvider::new(&endpoint.url, "kafka-client", "s3cret").unwrap();
+        assert_eq!(provider.fetch_token().unwrap().expires_at, UNIX_EPOCH + Duration::from_secs(exp));
+    }
+
+    #[test]
+    fn test_tokens_are_cached_and_refreshed_before_they_expire() {
+        let provider: Arc<CountingTokenProvider> = Arc::new(CountingTokenProvider {
+            lifetime: Duration::from_millis(300),
+            fetches: AtomicUsize::new(0),
+            fail: false
+        });
+        let mechanism: OAuthBearerMechanism = OAuthBearerMechanism::with_refresh_config(provider.clone(), TokenRefreshConfig {
+            window_factor: 0.5,
+            window_jitter: 0.0
+        });
+        assert_eq!(mechanism.token().unwrap().value, "token-1");
+        assert_eq!(mechanism.token().unwrap().value, "token-1");
+        thread::sleep(Duration::from_millis(170));
+        assert_eq!(mechanism.token().unwrap().value, "token-2");
+        assert_eq!(provider.fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_failed_refresh_keeps_the_valid_token() {
+        let provider: Arc<CountingTokenProvider> = Arc::new(CountingTokenProvider {
+            lifetime: Duration::from_millis(300),
+            fetches: AtomicUsize::new(0),
+            fail: true
+        });
+        let mechanism: OAuthBearerMechanism = OAuthBearerMechanism::with_refresh_config(provider, TokenRefreshConfig {
+            window_factor: 0.1,
+            window_jitter: 0.0
+        });
+        assert_eq!(mechanism.token().unwrap().value, "token-1");
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(mechanism.token().unwrap().value, "token-1");
+        thread::sleep(Duration::from_millis(300));
+        assert!(mechanism.token().is_err());
+    }
+
+    #[test]
+    fn test_exchange_messages() {
+        let mechanism: OAuthBearerMechanism = OAuthBearerMechanism::new(Arc::new(CountingTokenProvider {
+            lifetime: Duration::from_secs(60),
+            fetches: AtomicUsize::new(0),
+            fail: false
+        }));
+        let mut exchange: Box<dyn SaslExchange> = mechanism.start_exchange().unwrap();
+        assert_eq!(exchange.evaluate_challenge(&[]).unwrap(), Some(b"n,,\x01auth=Bearer token-1\x01\x01".to_vec()));
+        assert_eq!(exchange.evaluate_challenge(&[]).unwrap(), None);
+
+        let mut exchange: Box<dyn SaslExchange> = mechanism.start_exchange().unwrap();
+        exchange.evaluate_challenge(&[]).unwrap();
+        assert_eq!(exchange.evaluate_challenge(br#"{"status":"invalid_token"}"#).unwrap(), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_connections_authenticate_with_fetched_tokens() {
+        let endpoint: MockTokenEndpoint = MockTokenEndpoint::start(r#"{"access_token":"abc.def.ghi","token_type":"Bearer","expires_in":3600}"#);
+        let provider: ClientCredentialsTokenProvider = ClientCredentialsTokenProvider::new(&endpoint.url, "kafka-client", "s3cret").unwrap();
+        let handshakes: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let broker: MockBroker = sasl_broker(None, "OAUTHBEARER", 200, handshakes.clone(), |message: &[u8]| {
+            message == b"n,,\x01auth=Bearer abc.def.ghi\x01\x01"
+        });
+        let config: ConnectionConfig = ConnectionConfig {
+            sasl: Some(Arc::new(OAuthBearerMechanism::new(Arc::new(provider)))),
+            ..ConnectionConfig::default()
+        };
+
+        let mut connection: KafkaConnection = connect(&broker, &config).unwrap();
+        let mut other_connection: KafkaConnection = connect(&broker, &config).unwrap();
+        thread::sleep(Duration::from_millis(190));
+        let _: ApiVersionsResponseV3 = connection.send_request_and_get_response(api_versions_request()).unwrap();
+        let _: ApiVersionsResponseV3 = other_connection.send_request_and_get_response(api_versions_request()).unwrap();
+        // both connections re-authenticated with the cached token
+        assert_eq!(handshakes.load(Ordering::SeqCst), 4);
+        assert_eq!(endpoint.request_count(), 1);
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use crate::cluster::{ClusterMetadata, TopicPartition};
+
+/// Chooses which partition of a topic each record is sent to, like `partitioner.class`.
+pub trait Partitioner: Send + Sync + Debug {
+    /// Returns the partition for a record with the given serialized key and value.
+    fn partition(&self, topic: &str, key: Option<&[u8]>, value: Option<&[u8]>, cluster: &ClusterMetadata) -> Result<i32>;
+}
+
+/// The 32-bit murmur2 hash, bit-exact with Java's `org.apache.kafka.common.utils.Utils.murmur2`.
+pub fn murmur2(data: &[u8]) -> i32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+
+    let length: usize = data.len();
+    let mut h: u32 = SEED ^ length as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k: u32 = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let tail: &[u8] = chunks.remainder();
+    if tail.len() == 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+/// Like Java's `Utils.toPositive`, clears the sign bit rather than taking the absolute value.
+pub fn to_positive(number: i32) -> i32 {
+    number & 0x7fffffff
+}
+
+/// The partition Java clients choose for a keyed record.
+pub fn partition_for_key(key: &[u8], num_partitions: i32) -> i32 {
+    to_positive(murmur2(key)) % num_partitions
+}
+
+fn num_partitions(topic: &str, cluster: &ClusterMetadata) -> Result<i32> {
+    match cluster.partitions_for_topic(topic).len() {
+        0 => Err(anyhow!("Topic {} has no known partitions", topic)),
+        num_partitions => Ok(num_partitions as i32)
+    }
+}
+
+/// Picks a random partition, preferring ones which currently have a leader.
+fn random_partition(topic: &str, cluster: &ClusterMetadata, excluding: Option<i32>) -> Result<i32> {
+    let mut candidates: Vec<TopicPartition> = cluster.available_partitions_for_topic(topic);
+    if candidates.is_empty() {
+        candidates = cluster.partitions_for_topic(topic);
+    }
+    if candidates.len() > 1 {
+        candidates.retain(|topic_partition| Some(topic_partition.partition) != excluding);
+    }
+    if candidates.is_empty() {
+        return Err(anyhow!("Topic {} has no known partitions", topic));
+    }
+    Ok(candidates[rand::thread_rng().gen_range(0..candidates.len())].partition)
+}
+
+#[derive(Debug)]
+struct StickyPartition {
+    partition: i32,
+    produced_bytes: usize
+}
+
+/// The uniform sticky partitioner of KIP-794 for records without a key. Records stick to one
+/// partition until `batch_size` bytes have been sent to it, then move to another random partition
+/// with a leader, so that batches fill up while load still spreads evenly.
+#[derive(Debug)]
+pub struct UniformStickyPartitioner {
+    batch_size: usize,
+    sticky_partitions: Mutex<HashMap<String, StickyPartition>>
+}
+
+impl UniformStickyPartitioner {
+    pub fn new(batch_size: usize) -> Self {
+        UniformStickyPartitioner {
+            batch_size,
+            sticky_partitions: Mutex::new(HashMap::new())
+        }
+    }
+}
+
+impl Partitioner for UniformStickyPartitioner {
+    fn partition(&self, topic: &str, key: Option<&[u8]>, value: Option<&[u8]>, cluster: &ClusterMetadata) -> Result<i32> {
+        let record_size: usize = key.map(<[u8]>::len).unwrap_or(0) + value.map(<[u8]>::len).unwrap_or(0);
+        let mut sticky_partitions = self.sticky_partitions.lock().expect("Sticky partition lock was poisoned");
+        let sticky: Option<&StickyPartition> = sticky_partitions.get(topic);
+        let needs_new_partition: bool = match sticky {
+            Some(sticky) => sticky.produced_bytes >= self.batch_size
+                || cluster.leader(&TopicPartition::new(topic, sticky.partition)).is_none(),
+            None => true
+        };
+        if needs_new_partition {
+            let previous_partition: Option<i32> = sticky.map(|sticky| sticky.partition);
+            let partition: i32 = random_partition(topic, cluster, previous_partition)?;
+            sticky_partitions.insert(String::from(topic), StickyPartition { partition, produced_bytes: 0 });
+        }
+        let sticky: &mut StickyPartition = sticky_partitions.get_mut(topic).expect("Sticky partition was just chosen");
+        sticky.produced_bytes += record_size;
+        Ok(sticky.partition)
+    }
+}
+
+/// The default partitioner: keyed records go to the partition Java clients would choose for the
+/// key, and records without a key use the uniform sticky partitioner.
+#[derive(Debug)]
+pub struct DefaultPartitioner {
+    sticky: UniformStickyPartitioner
+}
+
+impl DefaultPartitioner {
+    /// `batch_size` is the producer's `batch.size`, which decides when unkeyed records move on to
+    /// another partition.
+    pub fn new(batch_size: usize) -> Self {
+        DefaultPartitioner {
+            sticky: UniformStickyPartitioner::new(batch_size)
+        }
+    }
+}
+
+impl Partitioner for DefaultPartitioner {
+    fn partition(&self, topic: &str, key: Option<&[u8]>, value: Option<&[u8]>, cluster: &ClusterMetadata) -> Result<i32> {
+        match key {
+            Some(key) => Ok(partition_for_key(key, num_partitions(topic, cluster)?)),
+            None => self.sticky.partition(topic, key, value, cluster)
+        }
+    }
+}
+
+/// Spreads records over partitions in turn regardless of their keys, like Java's
+/// `RoundRobinPartitioner`. Partitions with a leader are preferred.
+#[derive(Debug, Default)]
+pub struct RoundRobinPartitioner {
+    counters: Mutex<HashMap<String, u32>>
+}
+
+impl Partitioner for RoundRobinPartitioner {
+    fn partition(&self, topic: &str, _key: Option<&[u8]>, _value: Option<&[u8]>, cluster: &ClusterMetadata) -> Result<i32> {
+        let next: u32 = {
+            let mut counters = self.counters.lock().expect("Round robin counter lock was poisoned");
+            let counter: &mut u32 = counters.entry(String::from(topic)).or_insert(0);
+            let next: u32 = *counter;
+            *counter = counter.wrapping_add(1);
+            next
+        };
+        let available_partitions: Vec<TopicPartition> = cluster.available_partitions_for_topic(topic);
+        if available_partitions.is_empty() {
+            Ok(to_positive(next as i32) % num_partitions(topic, cluster)?)
+        } else {
+            let index: usize = to_positive(next as i32) as usize % available_partitions.len();
+            Ok(available_partitions[index].partition)
+        }
+    }
+}
+
+/// A partitioner made from a function, for partitioning logic which doesn't need its own type.
+pub struct FnPartitioner<F> {
+    partition: F
+}
+
+impl<F> FnPartitioner<F> where F: Fn(&str, Option<&[u8]>, Option<&[u8]>, &ClusterMetadata) -> Result<i32> + Send + Sync {
+    pub fn new(partition: F) -> Self {
+        FnPartitioner { partition }
+    }
+}
+
+impl<F> Debug for FnPartitioner<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FnPartitioner")
+    }
+}
+
+impl<F> Partitioner for FnPartitioner<F> where F: Fn(&str, Option<&[u8]>, Option<&[u8]>, &ClusterMetadata) -> Result<i32> + Send + Sync {
+    fn partition(&self, topic: &str, key: Option<&[u8]>, value: Option<&[u8]>, cluster: &ClusterMetadata) -> Result<i32> {
+        (self.partition)(topic, key, value, cluster)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use anyhow::anyhow;
+    use crate::cluster::ClusterMetadata;
+    use crate::cluster::tests::one_topic_cluster as cluster;
+    use crate::partitioner::{DefaultPartitioner, FnPartitioner, murmur2, partition_for_key, Partitioner, RoundRobinPartitioner, UniformStickyPartitioner};
+
+    // the cases of Java's UtilsTest.testMurmur2
+    #[test]
+    fn test_murmur2_matches_java() {
+        let cases: [(&[u8], i32); 6] = [
+            (b"21", -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8", -58897971),
+            (b"abc", 479470107)
+        ];
+        for (data, expected) in cases {
+            assert_eq!(murmur2(data), expected, "murmur2({:?})", String::from_utf8_lossy(data));
+        }
+    }
+
+    // the partitions Java's DefaultPartitioner chooses for these keys
+    #[test]
+    fn test_keyed_partitions_match_java() {
+        assert_eq!(partition_for_key(b"21", 3), 0);
+        assert_eq!(partition_for_key(b"foobar", 3), 0);
+        assert_eq!(partition_for_key(b"a-little-bit-long-string", 10), 2);
+        assert_eq!(partition_for_key(b"abc", 6), 3);
+        assert_eq!(partition_for_key(b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8", 100), 77);
+
+        // keyed records ignore whether the partition has a leader
+        let cluster: ClusterMetadata = cluster("events", &[0, -1, 2]);
+        let partitioner: DefaultPartitioner = DefaultPartitioner::new(16384);
+        assert_eq!(partitioner.partition("events", Some(b"21"), Some(b"value"), &cluster).unwrap(), 0);
+        assert!(partitioner.partition("unknown", Some(b"21"), None, &cluster).is_err());
+    }
+
+    #[test]
+    fn test_sticky_partitioner_switches_after_a_batch() {
+        let cluster: ClusterMetadata = cluster("events", &[0, 1, 2, -1]);
+        let partitioner: UniformStickyPartitioner = UniformStickyPartitioner::new(100);
+        let first: i32 = partitioner.partition("events", None, Some(&[0; 10]), &cluster).unwrap();
+        assert_ne!(first, 3);
+        for _ in 0..9 {
+            assert_eq!(partitioner.partition("events", None, Some(&[0; 10]), &cluster).unwrap(), first);
+        }
+        let second: i32 = partitioner.partition("events", None, Some(&[0; 10]), &cluster).unwrap();
+        assert_ne!(second, first);
+        assert_ne!(second, 3);
+
+        let mut chosen: HashSet<i32> = HashSet::new();
+        for _ in 0..200 {
+            chosen.insert(partitioner.partition("events", None, Some(&[0; 100]), &cluster).unwrap());
+        }
+        assert_eq!(chosen, HashSet::from([0, 1, 2]));
+    }
+
+    #[test]
+    fn test_sticky_partitioner_leaves_partitions_which_lose_their_leader() {
+        let partitioner: UniformStickyPartitioner = UniformStickyPartitioner::new(16384);
+        let first: i32 = partitioner.partition("events", None, Some(b"value"), &cluster("events", &[0, 1])).unwrap();
+        let mut leaders: [i32; 2] = [0, 1];
+        leaders[first as usize] = -1;
+        assert_eq!(partitioner.partition("events", None, Some(b"value"), &cluster("events", &leaders)).unwrap(), 1 - first);
+    }
+
+    #[test]
+    fn test_round_robin_partitioner() {
+        let cluster: ClusterMetadata = cluster("events", &[0, -1, 2, 1]);
+        let partitioner: RoundRobinPartitioner = RoundRobinPartitioner::default();
+        let partitions: Vec<i32> = (0..6)
+            .map(|_| partitioner.partition("events", Some(b"same-key"), None, &cluster).unwrap())
+            .collect();
+        assert_eq!(partitions, vec![0, 2, 3, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_custom_partitioner() {
+        let cluster: ClusterMetadata = cluster("events", &[0, 1, 2]);
+        let partitioner: Box<dyn Partitioner> = Box::new(FnPartitioner::new(|_topic: &str, key: Option<&[u8]>, _value: Option<&[u8]>, _cluster: &ClusterMetadata| {
+            match key {
+                Some(b"vip") => Ok(0),
+                Some(_) => Ok(2),
+                None => Err(anyhow!("Records must have a key"))
+            }
+        }));
+        assert_eq!(partitioner.partition("events", Some(b"vip"), None, &cluster).unwrap(), 0);
+        assert_eq!(partitioner.partition("events", Some(b"other"), None, &cluster).unwrap(), 2);
+        assert!(partitioner.partition("events", None, None, &cluster).is_err());
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use kafka_encode::KafkaEncodable;
+use tracing::{debug, warn};
+use crate::cluster::{ClusterMetadata, Node, TopicPartition};
+use crate::producer::buffer_pool::BufferPool;
+use crate::producer::future::{Callback, PendingRecord, RecordFuture};
+use crate::producer::transaction_manager::ProducerIdAndEpoch;
+use crate::producer::ProducerError;
+use crate::protocol::records::{Record, RecordBatch, RECORD_BATCH_OVERHEAD};
+use crate::retry::RetryPolicy;
+
+/// Where the broker wrote a batch.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct BatchMetadata {
+    pub base_offset: i64,
+    /// -1 unless the topic uses LogAppendTime.
+    pub log_append_time_ms: i64
+}
+
+/// Records bound for one topic-partition which are sent to the broker together.
+#[derive(Debug)]
+pub(crate) struct ProducerBatch {
+    pub topic_partition: TopicPartition,
+    /// Increases with every batch, so that retried batches keep their place in the queue.
+    id: u64,
+    created: Instant,
+    base_timestamp: i64,
+    /// The encoded records, in a buffer from the buffer pool.
+    buffer: Vec<u8>,
+    /// The size the buffer was allocated with, which is returned to the pool with it.
+    buffer_size: usize,
+    record_count: usize,
+    /// The futures of the records in the batch, in offset order.
+    pending: Vec<PendingRecord>,
+    /// Set when the batch is first drained. No records can be added after that, and retries send
+    /// the same bytes without computing the CRC again.
+    sealed: Option<RecordBatch>,
+    /// The producer id, epoch and base sequence of an idempotent producer's batch, once it is sent.
+    producer_state: Option<(ProducerIdAndEpoch, i32)>,
+    /// How many times the batch has been sent.
+    pub attempts: u32,
+    retry_after: Option<Instant>
+}
+
+impl ProducerBatch {
+    fn new(topic_partition: TopicPartition, id: u64, base_timestamp: i64, buffer: Vec<u8>, buffer_size: usize) -> Self {
+        ProducerBatch {
+            topic_partition,
+            id,
+            created: Instant::now(),
+            base_timestamp,
+            buffer,
+            buffer_size,
+            record_count: 0,
+            pending: Vec::new(),
+            sealed: None,
+            producer_state: None,
+            attempts: 0,
+            retry_after: None
+        }
+    }
+
+    /// Adds a record unless it would take the batch past `batch_size`. A record is always added to
+    /// an empty batch, so a record bigger than `batch_size` gets a batch of its own. A record which
+    /// doesn't fit is handed back.
+    fn try_append(&mut self, timestamp: i64, record: Record, batch_size: usize) -> Result<Option<Record>> {
+        let record: Record = Record::new(
+            self.record_count as i32,
+            timestamp - self.base_timestamp,
+            record.key.0,
+            record.value.0,
+            record.headers.0
+        )?;
+        if self.record_count > 0 && self.size_in_bytes() + record.size_in_bytes() > batch_size {
+            return Ok(Some(record));
+        }
+        record.to_kafka_bytes(&mut self.buffer)?;
+        self.record_count += 1;
+        Ok(None)
+    }
+
+    /// Returns the future of the record which was just appended.
+    fn track(&mut self, timestamp: i64, serialized_sizes: (i32, i32), callback: Option<Callback>) -> RecordFuture {
+        let offset_delta: i64 = self.record_count as i64 - 1;
+        let (pending, future) = PendingRecord::new(offset_delta, timestamp, serialized_sizes.0, serialized_sizes.1, callback);
+        self.pending.push(pending);
+        future
+    }
+
+    fn is_full(&self, batch_size: usize) -> bool {
+        self.size_in_bytes() >= batch_size
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.record_count
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        RECORD_BATCH_OVERHEAD + self.buffer.len()
+    }
+
+    /// How long ago the first record was added.
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// The batch as it goes on the wire.
+    pub fn record_batch(&mut self) -> Result<RecordBatch> {
+        if self.sealed.is_none() {
+            let mut reader: &[u8] = &self.buffer;
+            let records: Vec<Record> = (0..self.record_count)
+                .map(|_| Record::from_kafka_bytes(&mut reader))
+                .collect::<Result<Vec<Record>>>()?;
+            let mut record_batch: RecordBatch = RecordBatch::new(self.base_timestamp, records);
+            if let Some((producer, base_sequence)) = self.producer_state {
+                record_batch.producer_id = producer.producer_id;
+                record_batch.producer_epoch_offset = producer.epoch;
+                record_batch.base_sequence = base_sequence;
+            }
+            self.sealed = Some(record_batch.seal()?);
+        }
+        Ok(self.sealed.clone().expect("The batch was just sealed"))
+    }
+
+    pub fn producer_state(&self) -> Option<(ProducerIdAndEpoch, i32)> {
+        self.producer_state
+    }
+
+    /// Stamps the batch with an idempotent producer's id, epoch and sequence. A batch which was
+    /// already sealed is sealed again, as its CRC covers these fields.
+    pub fn set_producer_state(&mut self, producer: ProducerIdAndEpoch, base_sequence: i32) -> Result<()> {
+        self.producer_state = Some((producer, base_sequence));
+        if let Some(mut record_batch) = self.sealed.take() {
+            record_batch.producer_id = producer.producer_id;
+            record_batch.producer_epoch_offset = producer.epoch;
+            record_batch.base_sequence = base_sequence;
+            self.sealed = Some(record_batch.seal()?);
+        }
+        Ok(())
+    }
+
+    fn is_backing_off(&self, now: Instant) -> bool {
+        self.retry_after.is_some_and(|retry_after| now < retry_after)
+    }
+
+    /// Completes the future of every record in the batch.
+    fn done(self, result: Result<BatchMetadata>) {
+        let result: Result<BatchMetadata, ProducerError> = match result {
+            Ok(metadata) => {
+                debug!("Wrote {} records to {:?} at offset {}", self.record_count, self.topic_partition, metadata.base_offset);
+                Ok(metadata)
+            },
+            Err(e) => {
+                warn!("Failed to send {} records to {:?}: {:?}", self.record_count, self.topic_partition, e);
+                Err(ProducerError::from_send_error(e, &self.topic_partition))
+            }
+        };
+        for pending in self.pending {
+            pending.complete(&self.topic_partition, &result);
+        }
+    }
+}
+
+/// The nodes which have batches to send, from `RecordAccumulator::ready`.
+#[derive(Debug, Default)]
+pub(crate) struct ReadyCheck {
+    pub ready_nodes: HashSet<i32>,
+    /// How long until a batch which isn't ready yet will be, if there is one.
+    pub next_ready_check: Option<Duration>,
+    /// Whether some partition with batches has no known leader.
+    pub unknown_leaders: bool
+}
+
+/// Batches which outlived `delivery.timeout.ms`, from `RecordAccumulator::abort_expired_batches`.
+#[derive(Debug)]
+pub(crate) struct ExpiredBatches {
+    /// The error the first of them failed with.
+    pub error: ProducerError,
+    /// Whether any of them had been sent with a sequence number.
+    pub had_sequences: bool
+}
+
+#[derive(Debug, Default)]
+struct AccumulatorState {
+    batches: HashMap<TopicPartition, VecDeque<ProducerBatch>>,
+    /// Batches which have been drained and whose requests haven't completed.
+    in_flight: usize,
+    next_batch_id: u64,
+    flushes_in_progress: usize,
+    closed: bool,
+    /// Set once the producer gave up waiting for its batches to be sent while closing.
+    aborted: bool
+}
+
+impl AccumulatorState {
+    fn is_empty(&self) -> bool {
+        self.in_flight == 0 && self.batches.values().all(VecDeque::is_empty)
+    }
+}
+
+/// Collects records into batches per topic-partition until the sender thread drains them.
+#[derive(Debug)]
+pub(crate) struct RecordAccumulator {
+    /// `batch.size`
+    batch_size: usize,
+    /// `linger.ms`
+    linger: Duration,
+    retry: RetryPolicy,
+    buffer_pool: BufferPool,
+    state: Mutex<AccumulatorState>,
+    changed: Condvar
+}
+
+impl RecordAccumulator {
+    pub fn new(batch_size: usize, linger: Duration, retry: RetryPolicy, buffer_pool: BufferPool) -> Self {
+        RecordAccumulator {
+            batch_size,
+            linger,
+            retry,
+            buffer_pool,
+            state: Mutex::new(AccumulatorState::default()),
+            changed: Condvar::new()
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, AccumulatorState> {
+        self.state.lock().expect("Record accumulator lock was poisoned")
+    }
+
+    /// Adds a record to the last open batch for its partition, or to a new batch if it doesn't fit.
+    /// A new batch needs a buffer from the pool, which may block for up to `max_block`. The sender
+    /// is woken when a batch fills up or a new batch starts lingering.
+    pub fn append(
+        &self,
+        topic_partition: &TopicPartition,
+        timestamp: i64,
+        mut record: Record,
+        mut callback: Option<Callback>,
+        max_block: Duration
+    ) -> Result<RecordFuture> {
+        let serialized_sizes: (i32, i32) = (serialized_size(&record.key.0), serialized_size(&record.value.0));
+        let mut buffer: Option<(Vec<u8>, usize)> = None;
+        loop {
+            let mut state: MutexGuard<AccumulatorState> = self.lock();
+            if state.closed {
+                self.release(buffer);
+                return Err(anyhow::Error::new(ProducerError::Closed));
+            }
+            let batches: &mut VecDeque<ProducerBatch> = state.batches.entry(topic_partition.clone()).or_default();
+            if let Some(batch) = batches.back_mut().filter(|batch| batch.sealed.is_none()) {
+                match batch.try_append(timestamp, record, self.batch_size) {
+                    Ok(None) => {
+                        if batch.is_full(self.batch_size) {
+                            self.changed.notify_all();
+                        }
+                        // another thread started a batch while this one waited for a buffer
+                        self.release(buffer);
+                        return Ok(batch.track(timestamp, serialized_sizes, callback.take()));
+                    },
+                    Ok(Some(rejected)) => record = rejected,
+                    Err(e) => {
+                        self.release(buffer);
+                        return Err(e);
+                    }
+                }
+            }
+            if let Some((buffer, buffer_size)) = buffer.take() {
+                let id: u64 = state.next_batch_id;
+                state.next_batch_id += 1;
+                let batches: &mut VecDeque<ProducerBatch> = state.batches.entry(topic_partition.clone()).or_default();
+                let mut batch: ProducerBatch = ProducerBatch::new(topic_partition.clone(), id, timestamp, buffer, buffer_size);
+                if let Err(e) = batch.try_append(timestamp, record, self.batch_size) {
+                    self.release(Some((batch.buffer, batch.buffer_size)));
+                    return Err(e);
+                }
+                let future: RecordFuture = batch.track(timestamp, serialized_sizes, callback.take());
+                batches.push_back(batch);
+                self.changed.notify_all();
+                return Ok(future);
+            }
+            drop(state);
+
+            // allocating may block, so the lock isn't held while waiting for memory
+            let buffer_size: usize = self.batch_size.max(record.size_in_bytes());
+            buffer = Some((self.buffer_pool.allocate(buffer_size, max_block)?, buffer_size));
+        }
+    }
+
+    fn release(&self, buffer: Option<(Vec<u8>, usize)>) {
+        if let Some((buffer, buffer_size)) = buffer {
+            self.buffer_pool.deallocate(buffer, buffer_size);
+        }
+    }
+
+    /// Returns a finished batch's buffer to the pool and reports its result.
+    fn finish(&self, mut batch: ProducerBatch, result: Result<BatchMetadata>) {
+        self.buffer_pool.deallocate(std::mem::take(&mut batch.buffer), batch.buffer_size);
+        batch.done(result);
+    }
+
+    /// Finds the leaders of partitions whose first batch should be sent now: because it is full,
+    /// has lingered for `linger.ms`, has finished backing off after a failure, or because the
+    /// producer is flushing or closing.
+    pub fn ready(&self, cluster: &ClusterMetadata) -> ReadyCheck {
+        let state: MutexGuard<AccumulatorState> = self.lock();
+        let now: Instant = Instant::now();
+        let send_now: bool = state.flushes_in_progress > 0 || state.closed;
+        let mut ready_check: ReadyCheck = ReadyCheck::default();
+        for (topic_partition, batches) in state.batches.iter() {
+            let Some(batch) = batches.front() else {
+                continue;
+            };
+            let Some(leader) = cluster.leader(topic_partition) else {
+                ready_check.unknown_leaders = true;
+                continue;
+            };
+            let wait: Duration = match batch.retry_after {
+                Some(retry_after) => retry_after.saturating_duration_since(now),
+                None if send_now || batches.len() > 1 || batch.is_full(self.batch_size) => Duration::ZERO,
+                None => self.linger.saturating_sub(now.duration_since(batch.created))
+            };
+            if wait.is_zero() {
+                ready_check.ready_nodes.insert(leader.node_id);
+            } else {
+                ready_check.next_ready_check = Some(ready_check.next_ready_check.map_or(wait, |next| next.min(wait)));
+            }
+        }
+        ready_check
+    }
+
+    /// Takes the first batch of every partition led by one of the ready nodes, keeping each node's
+    /// batches within `max_request_size`. Only one batch per partition is drained at a time, so
+    /// that batches are written in order even when some are retried.
+    pub fn drain(&self, cluster: &ClusterMetadata, ready_nodes: &HashSet<i32>, max_request_size: usize) -> HashMap<i32, Vec<ProducerBatch>> {
+        let mut state: MutexGuard<AccumulatorState> = self.lock();
+        let now: Instant = Instant::now();
+        let mut request_sizes: HashMap<i32, usize> = HashMap::new();
+        let mut drained: HashMap<i32, Vec<ProducerBatch>> = HashMap::new();
+        let mut drained_count: usize = 0;
+        for (topic_partition, batches) in state.batches.iter_mut() {
+            let Some(leader) = cluster.leader(topic_partition).map(|leader: &Node| leader.node_id) else {
+                continue;
+            };
+            if !ready_nodes.contains(&leader) || batches.front().is_none_or(|batch| batch.is_backing_off(now)) {
+                continue;
+            }
+            let request_size: &mut usize = request_sizes.entry(leader).or_default();
+            let batch_size: usize = batches.front().map_or(0, ProducerBatch::size_in_bytes);
+            if *request_size > 0 && *request_size + batch_size > max_request_size {
+                continue;
+            }
+            *request_size += batch_size;
+            drained.entry(leader).or_default().push(batches.pop_front().expect("The batch was just checked"));
+            drained_count += 1;
+        }
+        state.in_flight += drained_count;
+        drained
+    }
+
+    /// Puts a failed batch back into its partition's queue, to be retried after a backoff. It goes
+    /// ahead of every batch created after it, which keeps the partition's batches in order when
+    /// several of them were in flight.
+    pub fn reenqueue(&self, mut batch: ProducerBatch) {
+        let mut state: MutexGuard<AccumulatorState> = self.lock();
+        if state.aborted {
+            drop(state);
+            self.complete(batch, Err(anyhow::Error::new(ProducerError::Closed)));
+            return;
+        }
+        batch.retry_after = Some(Instant::now() + self.retry.backoff(batch.attempts));
+        let batches: &mut VecDeque<ProducerBatch> = state.batches.entry(batch.topic_partition.clone()).or_default();
+        let position: usize = batches.iter().position(|queued| queued.id > batch.id).unwrap_or(batches.len());
+        batches.insert(position, batch);
+        state.in_flight -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Finishes a drained batch, which was either written or failed for good.
+    pub fn complete(&self, batch: ProducerBatch, result: Result<BatchMetadata>) {
+        self.finish(batch, result);
+        self.lock().in_flight -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Fails queued batches which have outlived `delivery.timeout.ms`.
+    pub fn abort_expired_batches(&self) -> Option<ExpiredBatches> {
+        let mut expired: Vec<ProducerBatch> = Vec::new();
+        {
+            let mut state: MutexGuard<AccumulatorState> = self.lock();
+            for batches in state.batches.values_mut() {
+                while batches.front().is_some_and(|batch| batch.age() >= self.retry.delivery_timeout) {
+                    expired.push(batches.pop_front().expect("The batch was just checked"));
+                }
+            }
+            // counted as in flight until their futures are done, so that a flush waits for them
+            state.in_flight += expired.len();
+        }
+        if expired.is_empty() {
+            return None;
+        }
+        let expired_count: usize = expired.len();
+        let had_sequences: bool = expired.iter().any(|batch| batch.producer_state.is_some());
+        let mut first_error: Option<ProducerError> = None;
+        for batch in expired {
+            let error: ProducerError = ProducerError::DeliveryTimedOut {
+                topic: batch.topic_partition.topic.clone(),
+                partition: batch.topic_partition.partition,
+                timeout: self.retry.delivery_timeout
+            };
+            first_error.get_or_insert_with(|| error.clone());
+            self.finish(batch, Err(anyhow::Error::new(error)));
+        }
+        self.lock().in_flight -= expired_count;
+        self.changed.notify_all();
+        first_error.map(|error| ExpiredBatches { error, had_sequences })
+    }
+
+    /// Blocks the sender thread until a record is appended, a batch completes or `timeout` passes.
+    pub fn wait_for_change(&self, timeout: Duration) {
+        let state: MutexGuard<AccumulatorState> = self.lock();
+        let _ = self.changed.wait_timeout(state, timeout).expect("Record accumulator lock was poisoned");
+    }
+
+    /// Makes every batch ready immediately and blocks until all of them, including ones appended
+    /// while flushing, have been sent and the futures of their records are done.
+    pub fn flush(&self) {
+        let mut state: MutexGuard<AccumulatorState> = self.lock();
+        state.flushes_in_progress += 1;
+        self.changed.notify_all();
+        state = self.changed.wait_while(state, |state| !state.is_empty())
+            .expect("Record accumulator lock was poisoned");
+        state.flushes_in_progress -= 1;
+    }
+
+    /// Stops accepting records. Batches already queued are still sent.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// Blocks until every batch has been sent or has failed, or until `timeout` passes. Returns
+    /// whether every batch is done.
+    pub fn await_completion(&self, timeout: Duration) -> bool {
+        let state: MutexGuard<AccumulatorState> = self.lock();
+        let (state, _) = self.changed.wait_timeout_while(state, timeout, |state| !state.is_empty())
+            .expect("Record accumulator lock was poisoned");
+        state.is_empty()
+    }
+
+    /// Fails every queued batch with `ProducerError::Closed`, along with any batch put back to be
+    /// retried from now on, so that only the requests already in flight are left to finish.
+    pub fn abort_incomplete_batches(&self) {
+        let aborted: Vec<ProducerBatch> = {
+            let mut state: MutexGuard<AccumulatorState> = self.lock();
+            state.aborted = true;
+            let aborted: Vec<ProducerBatch> = state.batches.values_mut().flat_map(|batches| batches.drain(..)).collect();
+            // counted as in flight until their futures are done, so that a flush waits for them
+            state.in_flight += aborted.len();
+            aborted
+        };
+        for batch in aborted {
+            self.complete(batch, Err(anyhow::Error::new(ProducerError::Closed)));
+        }
+    }
+
+    /// Whether the producer is closed and there is nothing left to send.
+    pub fn is_closed_and_empty(&self) -> bool {
+        let state: MutexGuard<AccumulatorState> = self.lock();
+        state.closed && state.is_empty()
+    }
+}
+
+/// The size of a key or value as it was given to the producer, or -1 for null.
+fn serialized_size(bytes: &Option<Vec<u8>>) -> i32 {
+    bytes.as_ref().map_or(-1, |bytes| bytes.len() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::thread;
+    use std::time::Duration;
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::cluster::tests::one_topic_cluster as cluster;
+    use crate::producer::accumulator::{BatchMetadata, ProducerBatch, ReadyCheck, RecordAccumulator};
+    use crate::producer::buffer_pool::BufferPool;
+    use crate::producer::ProducerError;
+    use crate::protocol::records::{Record, RecordBatch, RECORD_BATCH_OVERHEAD};
+    use crate::retry::RetryPolicy;
+
+    fn append(accumulator: &RecordAccumulator, partition: i32, value_size: usize) {
+        let record: Record = Record::new(0, 0, None, Some(vec![7; value_size]), vec![]).unwrap();
+        accumulator.append(&TopicPartition::new("foo", partition), 1_000, record, None, Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn test_batches_are_limited_by_batch_size() {
+        let accumulator: RecordAccumulator = RecordAccumulator::new(1024, Duration::from_secs(60), RetryPolicy::default(), BufferPool::new(1 << 20, 1024));
+        let cluster: ClusterMetadata = cluster("foo", &[1]);
+        // each record takes 109 bytes, so 8 fit after the batch overhead
+        for _ in 0..20 {
+            append(&accumulator, 0, 100);
+        }
+        let ready: ReadyCheck = accumulator.ready(&cluster);
+        assert_eq!(ready.ready_nodes, HashSet::from([1]));
+
+        let mut drained: HashMap<i32, Vec<ProducerBatch>> = accumulator.drain(&cluster, &ready.ready_nodes, 1 << 20);
+        let mut batch: ProducerBatch = drained.remove(&1).unwrap().remove(0);
+        assert_eq!(batch.record_count(), 8);
+        assert_eq!(batch.size_in_bytes(), RECORD_BATCH_OVERHEAD + 8 * 109);
+        let record_batch: RecordBatch = batch.record_batch().unwrap();
+        assert_eq!(record_batch.last_offset_delta, 7);
+        assert_eq!(record_batch.batch_length as usize, batch.size_in_bytes() - 12);
+        assert!(record_batch.is_valid().unwrap());
+        accumulator.complete(batch, Ok(BatchMetadata { base_offset: 0, log_append_time_ms: -1 }));
+
+        // the second batch is full too, but the third is still lingering
+        let ready: ReadyCheck = accumulator.ready(&cluster);
+        assert_eq!(ready.ready_nodes, HashSet::from([1]));
+        assert_eq!(accumulator.drain(&cluster, &ready.ready_nodes, 1 << 20)[&1][0].record_count(), 8);
+        assert!(accumulator.ready(&cluster).ready_nodes.is_empty());
+    }
+
+    #[test]
+    fn test_batches_are_ready_after_linger() {
+        let accumulator: RecordAccumulator = RecordAccumulator::new(16384, Duration::from_millis(50), RetryPolicy::default(), BufferPool::new(1 << 20, 16384));
+        let cluster: ClusterMetadata = cluster("foo", &[1, 2, -1]);
+        append(&accumulator, 0, 10);
+        append(&accumulator, 1, 10);
+        append(&accumulator, 2, 10);
+
+        let ready: ReadyCheck = accumulator.ready(&cluster);
+        assert!(ready.ready_nodes.is_empty());
+        assert!(ready.next_ready_check.unwrap() <= Duration::from_millis(50));
+        assert!(ready.unknown_leaders);
+
+        thread::sleep(Duration::from_millis(60));
+        let ready: ReadyCheck = accumulator.ready(&cluster);
+        assert_eq!(ready.ready_nodes, HashSet::from([1, 2]));
+        let drained: HashMap<i32, Vec<ProducerBatch>> = accumulator.drain(&cluster, &ready.ready_nodes, 1 << 20);
+        assert_eq!(drained[&1][0].topic_partition, TopicPartition::new("foo", 0));
+        assert_eq!(drained[&2][0].topic_partition, TopicPartition::new("foo", 1));
+    }
+
+    #[test]
+    fn test_drain_groups_batches_by_leader_within_max_request_size() {
+        let accumulator: RecordAccumulator = RecordAccumulator::new(200, Duration::ZERO, RetryPolicy::default(), BufferPool::new(1 << 20, 200));
+        let cluster: ClusterMetadata = cluster("foo", &[1, 1, 1, 2]);
+        for partition in 0..4 {
+            append(&accumulator, partition, 100);
+        }
+        let ready: ReadyCheck = accumulator.ready(&cluster);
+        assert_eq!(ready.ready_nodes, HashSet::from([1, 2]));
+        // each batch is 168 bytes, so only two fit into a request
+        let drained: HashMap<i32, Vec<ProducerBatch>> = accumulator.drain(&cluster, &ready.ready_nodes, 400);
+        assert_eq!(drained[&1].len(), 2);
+        assert_eq!(drained[&2].len(), 1);
+        assert_eq!(accumulator.drain(&cluster, &ready.ready_nodes, 400)[&1].len(), 1);
+    }
+
+    #[test]
+    fn test_reenqueued_batches_back_off_and_keep_their_place() {
+        let policy: RetryPolicy = RetryPolicy {
+            retry_backoff: Duration::from_millis(50),
+            retry_backoff_max: Duration::from_millis(50),
+            ..RetryPolicy::default()
+        };
+        let accumulator: RecordAccumulator = RecordAccumulator::new(16384, Duration::ZERO, policy, BufferPool::new(1 << 20, 16384));
+        let cluster: ClusterMetadata = cluster("foo", &[1]);
+        append(&accumulator, 0, 10);
+        let ready_nodes: HashSet<i32> = accumulator.ready(&cluster).ready_nodes;
+        let mut batch: ProducerBatch = accumulator.drain(&cluster, &ready_nodes, 1 << 20).remove(&1).unwrap().remove(0);
+        let sent: RecordBatch = batch.record_batch().unwrap();
+        batch.attempts += 1;
+        append(&accumulator, 0, 10);
+        accumulator.reenqueue(batch);
+
+        let ready: ReadyCheck = accumulator.ready(&cluster);
+        assert!(ready.ready_nodes.is_empty());
+        assert!(ready.next_ready_check.unwrap() > Duration::from_millis(20));
+
+        thread::sleep(Duration::from_millis(70));
+        let ready_nodes: HashSet<i32> = accumulator.ready(&cluster).ready_nodes;
+        let mut retried: ProducerBatch = accumulator.drain(&cluster, &ready_nodes, 1 << 20).remove(&1).unwrap().remove(0);
+        assert_eq!(retried.attempts, 1);
+        assert_eq!(retried.record_batch().unwrap(), sent);
+        // the record appended while the batch was in flight went into a batch of its own
+        accumulator.complete(retried, Ok(BatchMetadata { base_offset: 0, log_append_time_ms: -1 }));
+        let ready_nodes: HashSet<i32> = accumulator.ready(&cluster).ready_nodes;
+        assert_eq!(accumulator.drain(&cluster, &ready_nodes, 1 << 20)[&1][0].record_count(), 1);
+    }
+
+    #[test]
+    fn test_batches_hold_buffer_memory_until_they_are_done() {
+        let accumulator: RecordAccumulator = RecordAccumulator::new(1024, Duration::ZERO, RetryPolicy::default(), BufferPool::new(2048, 1024));
+        let cluster: ClusterMetadata = cluster("foo", &[1, 1, 1]);
+        append(&accumulator, 0, 10);
+        append(&accumulator, 1, 10);
+        assert_eq!(accumulator.buffer_pool.available_memory(), 0);
+
+        let record: Record = Record::new(0, 0, None, Some(vec![7; 10]), vec![]).unwrap();
+        let exhausted: anyhow::Error = accumulator.append(&TopicPartition::new("foo", 2), 1_000, record.clone(), None, Duration::from_millis(20)).unwrap_err();
+        assert!(matches!(exhausted.downcast_ref::<ProducerError>(), Some(ProducerError::BufferExhausted { .. })));
+
+        let ready_nodes: HashSet<i32> = accumulator.ready(&cluster).ready_nodes;
+        for batch in accumulator.drain(&cluster, &ready_nodes, 1 << 20).remove(&1).unwrap() {
+            accumulator.complete(batch, Ok(BatchMetadata { base_offset: 0, log_append_time_ms: -1 }));
+        }
+        assert_eq!(accumulator.buffer_pool.available_memory(), 2048);
+        accumulator.append(&TopicPartition::new("foo", 2), 1_000, record, None, Duration::ZERO).unwrap();
+    }
+}
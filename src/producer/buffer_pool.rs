@@ -0,0 +1,162 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use crate::producer::ProducerError;
+
+#[derive(Debug)]
+struct PoolState {
+    /// Buffers of `poolable_size` which are waiting to be reused.
+    free: Vec<Vec<u8>>,
+    /// Memory which is neither in a free buffer nor held by a batch.
+    unallocated: usize,
+    /// Threads blocked in `allocate`.
+    waiting: usize
+}
+
+/// The memory for batches waiting to be sent, bounded by `buffer.memory`. Buffers of `batch.size`
+/// are kept for reuse when their batch is done, so that steady producing doesn't allocate; other
+/// sizes are freed.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    total_memory: usize,
+    poolable_size: usize,
+    state: Mutex<PoolState>,
+    freed: Condvar
+}
+
+impl BufferPool {
+    pub fn new(total_memory: usize, poolable_size: usize) -> Self {
+        BufferPool {
+            total_memory,
+            poolable_size,
+            state: Mutex::new(PoolState { free: Vec::new(), unallocated: total_memory, waiting: 0 }),
+            freed: Condvar::new()
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().expect("Buffer pool lock was poisoned")
+    }
+
+    /// Hands out an empty buffer with room for `size` bytes, blocking for up to `max_block` until
+    /// enough memory has been returned to the pool.
+    pub fn allocate(&self, size: usize, max_block: Duration) -> Result<Vec<u8>> {
+        if size > self.total_memory {
+            return Err(anyhow::Error::new(ProducerError::RecordTooLarge { size, max_size: self.total_memory, setting: "buffer.memory" }));
+        }
+        let deadline: Instant = Instant::now() + max_block;
+        let mut state: MutexGuard<PoolState> = self.lock();
+        loop {
+            if size == self.poolable_size {
+                if let Some(buffer) = state.free.pop() {
+                    return Ok(buffer);
+                }
+            }
+            if state.unallocated + state.free.len() * self.poolable_size >= size {
+                // give up pooled buffers until there is enough unallocated memory
+                while state.unallocated < size {
+                    state.free.pop();
+                    state.unallocated += self.poolable_size;
+                }
+                state.unallocated -= size;
+                return Ok(Vec::with_capacity(size));
+            }
+
+            let now: Instant = Instant::now();
+            if now >= deadline {
+                return Err(anyhow::Error::new(ProducerError::BufferExhausted { buffer_memory: self.total_memory, max_block }));
+            }
+            state.waiting += 1;
+            state = self.freed.wait_timeout(state, deadline - now).expect("Buffer pool lock was poisoned").0;
+            state.waiting -= 1;
+        }
+    }
+
+    /// Returns a buffer which was allocated with room for `size` bytes.
+    pub fn deallocate(&self, mut buffer: Vec<u8>, size: usize) {
+        let mut state: MutexGuard<PoolState> = self.lock();
+        if size == self.poolable_size {
+            buffer.clear();
+            state.free.push(buffer);
+        } else {
+            state.unallocated += size;
+        }
+        self.freed.notify_all();
+    }
+
+    /// Memory which isn't held by a batch, like Java's `buffer-available-bytes`.
+    #[cfg(test)]
+    pub fn available_memory(&self) -> usize {
+        let state: MutexGuard<PoolState> = self.lock();
+        state.unallocated + state.free.len() * self.poolable_size
+    }
+
+    /// Threads blocked waiting for memory, like Java's `waiting-threads`.
+    #[cfg(test)]
+    pub fn waiting_threads(&self) -> usize {
+        self.lock().waiting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+    use crate::producer::buffer_pool::BufferPool;
+    use crate::producer::ProducerError;
+
+    #[test]
+    fn test_batch_sized_buffers_are_reused() {
+        let pool: BufferPool = BufferPool::new(4096, 1024);
+        let mut buffer: Vec<u8> = pool.allocate(1024, Duration::ZERO).unwrap();
+        assert!(buffer.capacity() >= 1024);
+        assert_eq!(pool.available_memory(), 3072);
+        buffer.extend_from_slice(&[1, 2, 3]);
+        let address: *const u8 = buffer.as_ptr();
+        pool.deallocate(buffer, 1024);
+        assert_eq!(pool.available_memory(), 4096);
+
+        let reused: Vec<u8> = pool.allocate(1024, Duration::ZERO).unwrap();
+        assert_eq!(reused.as_ptr(), address);
+        assert!(reused.is_empty());
+    }
+
+    #[test]
+    fn test_other_sizes_reclaim_free_buffers() {
+        let pool: BufferPool = BufferPool::new(4096, 1024);
+        let buffers: Vec<Vec<u8>> = (0..4).map(|_| pool.allocate(1024, Duration::ZERO).unwrap()).collect();
+        for buffer in buffers {
+            pool.deallocate(buffer, 1024);
+        }
+        let large: Vec<u8> = pool.allocate(3000, Duration::ZERO).unwrap();
+        assert_eq!(pool.available_memory(), 1096);
+        pool.deallocate(large, 3000);
+        assert_eq!(pool.available_memory(), 4096);
+
+        let too_large: anyhow::Error = pool.allocate(5000, Duration::from_secs(10)).unwrap_err();
+        assert!(matches!(too_large.downcast_ref::<ProducerError>(), Some(ProducerError::RecordTooLarge { max_size: 4096, .. })));
+    }
+
+    #[test]
+    fn test_allocate_blocks_until_memory_is_returned() {
+        let pool: Arc<BufferPool> = Arc::new(BufferPool::new(2048, 1024));
+        let first: Vec<u8> = pool.allocate(1024, Duration::ZERO).unwrap();
+        let _second: Vec<u8> = pool.allocate(1024, Duration::ZERO).unwrap();
+
+        let started: Instant = Instant::now();
+        let exhausted: anyhow::Error = pool.allocate(1024, Duration::from_millis(50)).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(matches!(exhausted.downcast_ref::<ProducerError>(), Some(ProducerError::BufferExhausted { buffer_memory: 2048, .. })));
+
+        let waiting_pool: Arc<BufferPool> = pool.clone();
+        let waiter: JoinHandle<usize> = thread::spawn(move || waiting_pool.allocate(1024, Duration::from_secs(10)).unwrap().capacity());
+        while pool.waiting_threads() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        pool.deallocate(first, 1024);
+        assert!(waiter.join().unwrap() >= 1024);
+        assert_eq!(pool.waiting_threads(), 0);
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use crate::cluster::TopicPartition;
+use crate::producer::accumulator::BatchMetadata;
+use crate::producer::ProducerError;
+
+/// Where a record was written, once the broker has acknowledged it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RecordMetadata {
+    pub topic: String,
+    pub partition: i32,
+    /// -1 when `acks` is 0, because the broker doesn't say where the record went.
+    pub offset: i64,
+    /// The broker's time if the topic uses LogAppendTime, otherwise the record's own timestamp.
+    pub timestamp: i64,
+    /// -1 for a null key.
+    pub serialized_key_size: i32,
+    /// -1 for a null value.
+    pub serialized_value_size: i32
+}
+
+/// The outcome of sending one record.
+pub type DeliveryResult = Result<RecordMetadata, ProducerError>;
+
+/// Called with the outcome of a send on the producer's sender thread, so it should be quick and
+/// must not block on the producer.
+pub type Callback = Box<dyn FnOnce(&DeliveryResult) + Send>;
+
+#[derive(Debug, Default)]
+struct DeliveryState {
+    result: Option<DeliveryResult>,
+    waker: Option<Waker>
+}
+
+#[derive(Debug, Default)]
+struct Delivery {
+    state: Mutex<DeliveryState>,
+    done: Condvar
+}
+
+impl Delivery {
+    fn lock(&self) -> MutexGuard<'_, DeliveryState> {
+        self.state.lock().expect("Record future lock was poisoned")
+    }
+}
+
+/// Resolves to where a record was written, or to why it couldn't be. It can be awaited, or
+/// waited on from a thread with `get`.
+#[derive(Debug, Clone)]
+pub struct RecordFuture {
+    delivery: Arc<Delivery>
+}
+
+impl RecordFuture {
+    /// Blocks until the record has been written or has failed.
+    pub fn get(&self) -> DeliveryResult {
+        let state: MutexGuard<DeliveryState> = self.delivery.lock();
+        let state: MutexGuard<DeliveryState> = self.delivery.done.wait_while(state, |state| state.result.is_none())
+            .expect("Record future lock was poisoned");
+        state.result.clone().expect("The record is done")
+    }
+
+    /// Like `get`, but gives up after `timeout` and returns `None`.
+    pub fn get_timeout(&self, timeout: Duration) -> Option<DeliveryResult> {
+        let state: MutexGuard<DeliveryState> = self.delivery.lock();
+        let (state, _) = self.delivery.done.wait_timeout_while(state, timeout, |state| state.result.is_none())
+            .expect("Record future lock was poisoned");
+        state.result.clone()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.delivery.lock().result.is_some()
+    }
+}
+
+impl Future for RecordFuture {
+    type Output = DeliveryResult;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<DeliveryResult> {
+        let mut state: MutexGuard<DeliveryState> = self.delivery.lock();
+        match &state.result {
+            Some(result) => Poll::Ready(result.clone()),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A record in a batch, waiting for the batch to be done so that its future can be completed.
+pub(crate) struct PendingRecord {
+    delivery: Arc<Delivery>,
+    callback: Option<Callback>,
+    offset_delta: i64,
+    timestamp: i64,
+    serialized_key_size: i32,
+    serialized_value_size: i32
+}
+
+impl PendingRecord {
+    pub fn new(offset_delta: i64, timestamp: i64, serialized_key_size: i32, serialized_value_size: i32, callback: Option<Callback>) -> (Self, RecordFuture) {
+        let delivery: Arc<Delivery> = Arc::new(Delivery::default());
+        let pending: PendingRecord = PendingRecord {
+            delivery: delivery.clone(),
+            callback,
+            offset_delta,
+            timestamp,
+            serialized_key_size,
+            serialized_value_size
+        };
+        (pending, RecordFuture { delivery })
+    }
+
+    /// Completes the future with the record's place in the batch, then runs the callback.
+    pub fn complete(self, topic_partition: &TopicPartition, batch_result: &Result<BatchMetadata, ProducerError>) {
+        let result: DeliveryResult = batch_result.clone().map(|batch| RecordMetadata {
+            topic: topic_partition.topic.clone(),
+            partition: topic_partition.partition,
+            offset: if batch.base_offset < 0 { -1 } else { batch.base_offset + self.offset_delta },
+            timestamp: if batch.log_append_time_ms < 0 { self.timestamp } else { batch.log_append_time_ms },
+            serialized_key_size: self.serialized_key_size,
+            serialized_value_size: self.serialized_value_size
+        });
+        let waker: Option<Waker> = {
+            let mut state: MutexGuard<DeliveryState> = self.delivery.lock();
+            state.result = Some(result.clone());
+            state.waker.take()
+        };
+        self.delivery.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        if let Some(callback) = self.callback {
+            callback(&result);
+        }
+    }
+}
+
+impl Debug for PendingRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingRecord")
+            .field("offset_delta", &self.offset_delta)
+            .field("timestamp", &self.timestamp)
+            .field("has_callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+    use std::time::Duration;
+    use crate::cluster::TopicPartition;
+    use crate::producer::accumulator::BatchMetadata;
+    use crate::producer::future::{DeliveryResult, PendingRecord, RecordFuture, RecordMetadata};
+    use crate::producer::ProducerError;
+
+    struct FlagWaker(AtomicBool);
+
+    impl Wake for FlagWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_offsets_come_from_the_base_offset_and_offset_delta() {
+        let topic_partition: TopicPartition = TopicPartition::new("foo", 2);
+        let (pending, future) = PendingRecord::new(3, 1_000, -1, 5, None);
+        assert!(future.get_timeout(Duration::from_millis(1)).is_none());
+        pending.complete(&topic_partition, &Ok(BatchMetadata { base_offset: 40, log_append_time_ms: -1 }));
+        assert_eq!(future.get(), Ok(RecordMetadata {
+            topic: String::from("foo"),
+            partition: 2,
+            offset: 43,
+            timestamp: 1_000,
+            serialized_key_size: -1,
+            serialized_value_size: 5
+        }));
+
+        // acks=0 has no offsets, and LogAppendTime replaces the record's timestamp
+        let (pending, future) = PendingRecord::new(3, 1_000, 3, 5, None);
+        pending.complete(&topic_partition, &Ok(BatchMetadata { base_offset: -1, log_append_time_ms: 2_000 }));
+        let metadata: RecordMetadata = future.get().unwrap();
+        assert_eq!((metadata.offset, metadata.timestamp), (-1, 2_000));
+    }
+
+    #[test]
+    fn test_callbacks_and_wakers_see_the_result() {
+        let results: Arc<Mutex<Vec<DeliveryResult>>> = Arc::new(Mutex::new(Vec::new()));
+        let callback_results: Arc<Mutex<Vec<DeliveryResult>>> = results.clone();
+        let (pending, mut future) = PendingRecord::new(0, 1_000, -1, 1, Some(Box::new(move |result: &DeliveryResult| {
+            callback_results.lock().unwrap().push(result.clone());
+        })));
+
+        let flag: Arc<FlagWaker> = Arc::new(FlagWaker(AtomicBool::new(false)));
+        let waker: Waker = Waker::from(flag.clone());
+        let mut context: Context = Context::from_waker(&waker);
+        assert!(Pin::new(&mut future).poll(&mut context).is_pending());
+
+        let waiting: RecordFuture = future.clone();
+        let waiter: thread::JoinHandle<DeliveryResult> = thread::spawn(move || waiting.get());
+        pending.complete(&TopicPartition::new("foo", 0), &Err(ProducerError::Closed));
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert!(matches!(Pin::new(&mut future).poll(&mut context), Poll::Ready(Err(ProducerError::Closed))));
+        assert!(matches!(waiter.join().unwrap(), Err(ProducerError::Closed)));
+        assert!(matches!(results.lock().unwrap()[..], [Err(ProducerError::Closed)]));
+    }
+}
@@ -0,0 +1,1237 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use kafka_encode::primitives::{VarIntNullableBytes, VarIntString};
+use thiserror::Error;
+use tracing::warn;
+use crate::bootstrap::ClientDnsLookup;
+use crate::cluster::{ClusterMetadata, TopicPartition};
+use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+use crate::coordinator::{ConsumerGroupMetadata, OffsetAndMetadata};
+use crate::metadata_cache::{MetadataCache, MetadataCacheConfig, MetadataFetcher, NetworkMetadataFetcher};
+use crate::partitioner::{DefaultPartitioner, Partitioner};
+use crate::producer::accumulator::RecordAccumulator;
+use crate::producer::buffer_pool::BufferPool;
+use crate::producer::sender::Sender;
+use crate::producer::transaction_manager::{ProducerIdAndEpoch, TransactionManager};
+use crate::producer::transactions::TransactionClient;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::produce::ACKS_ALL;
+use crate::protocol::records::{Record, RecordHeader, RECORD_BATCH_OVERHEAD};
+use crate::retry::RetryPolicy;
+use crate::serialization::{BytesSerde, Header, SerializationError, Serializer};
+
+mod accumulator;
+mod buffer_pool;
+mod future;
+mod sender;
+mod transaction_manager;
+mod transactions;
+
+pub use crate::producer::future::{Callback, DeliveryResult, RecordFuture, RecordMetadata};
+
+/// The most requests an idempotent producer may have in flight to one broker, as brokers only
+/// remember the sequence numbers of each producer's last 5 batches per partition.
+const MAX_IDEMPOTENT_IN_FLIGHT_REQUESTS: usize = 5;
+
+/// Why a record couldn't be sent. Errors from brokers and connections are reported as `SendFailed`.
+#[derive(Debug, Clone, Error, Eq, PartialEq)]
+pub enum ProducerError {
+    #[error("The record is {size} bytes when serialized, which is larger than {setting} of {max_size}")]
+    RecordTooLarge { size: usize, max_size: usize, setting: &'static str },
+    #[error("Failed to allocate memory for a batch within max.block.ms of {max_block:?}, because all {buffer_memory} bytes of buffer.memory are in use")]
+    BufferExhausted { buffer_memory: usize, max_block: Duration },
+    #[error("Partition {partition} of topic {topic} does not exist")]
+    UnknownPartition { topic: String, partition: i32 },
+    #[error("Records for partition {partition} of topic {topic} were not written within delivery.timeout.ms of {timeout:?}")]
+    DeliveryTimedOut { topic: String, partition: i32, timeout: Duration },
+    #[error("The producer is closed")]
+    Closed,
+    #[error("Failed to send records to partition {partition} of topic {topic}: {message}")]
+    SendFailed { topic: String, partition: i32, error_code: Option<ErrorCode>, message: String },
+    #[error("Transactions need a transactional.id")]
+    NotTransactional,
+    #[error("Cannot {operation} while the transactional producer is {state}")]
+    InvalidTransactionState { operation: &'static str, state: &'static str },
+    #[error("The producer with transactional.id {transactional_id} was fenced by a newer producer with the same id")]
+    ProducerFenced { transactional_id: String },
+    #[error("The transaction failed: {message}")]
+    TransactionFailed { error_code: Option<ErrorCode>, message: String },
+    #[error("Failed to serialize the record's {field}: {error}")]
+    Serialization { field: &'static str, error: SerializationError }
+}
+
+impl ProducerError {
+    /// Turns the error a batch failed with into one which can be handed to each of its records.
+    pub(crate) fn from_send_error(error: anyhow::Error, topic_partition: &TopicPartition) -> Self {
+        match error.downcast::<ProducerError>() {
+            Ok(producer_error) => producer_error,
+            Err(error) => ProducerError::SendFailed {
+                topic: topic_partition.topic.clone(),
+                partition: topic_partition.partition,
+                error_code: error.chain().find_map(|cause| cause.downcast_ref::<ErrorCode>()).cloned(),
+                message: format!("{:#}", error)
+            }
+        }
+    }
+
+    /// Turns an error from a transaction coordinator into one which the transaction can fail with.
+    pub(crate) fn from_transaction_error(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<ProducerError>() {
+            Some(producer_error) => producer_error.clone(),
+            None => ProducerError::TransactionFailed {
+                error_code: error.chain().find_map(|cause| cause.downcast_ref::<ErrorCode>()).cloned(),
+                message: format!("{:#}", error)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProducerConfig {
+    /// `bootstrap.servers`: a comma-separated list of `host:port` pairs.
+    pub bootstrap_servers: String,
+    pub client_dns_lookup: ClientDnsLookup,
+    /// `acks`: -1 to wait for every in-sync replica, 1 to wait for the leader only, or 0 for no response.
+    pub acks: i16,
+    /// `batch.size`: the most bytes of records collected into one batch for a partition.
+    pub batch_size: usize,
+    /// `linger.ms`: how long a batch waits for more records before it is sent anyway.
+    pub linger: Duration,
+    /// `max.request.size`: the largest Produce request, and so the largest record, that is sent.
+    pub max_request_size: usize,
+    /// `buffer.memory`: the most memory used by batches waiting to be sent.
+    pub buffer_memory: usize,
+    /// `max.in.flight.requests.per.connection`: how many Produce requests may be sent to a broker
+    /// before its responses are read. Without idempotence, retries can reorder records when this is
+    /// more than 1.
+    pub max_in_flight_requests_per_connection: usize,
+    /// `enable.idempotence`: write each record exactly once and in order, even when batches are
+    /// retried. Requires `acks` of -1, retries, and at most 5 requests in flight.
+    pub enable_idempotence: bool,
+    /// `transactional.id`: lets the producer write to several partitions in transactions, which
+    /// are committed or aborted as a whole. Implies `enable_idempotence`.
+    pub transactional_id: Option<String>,
+    /// `transaction.timeout.ms`: how long the transaction coordinator waits for a transaction to
+    /// end before it aborts it.
+    pub transaction_timeout: Duration,
+    /// `max.block.ms`: how long `send` may block waiting for metadata and for room in `buffer.memory`.
+    pub max_block: Duration,
+    pub retry: RetryPolicy,
+    /// The connections to brokers, including `client.id` and `request.timeout.ms`.
+    pub connection_pool: ConnectionPoolConfig,
+    pub metadata: MetadataCacheConfig,
+    /// `partitioner.class`: `None` uses a `DefaultPartitioner`.
+    pub partitioner: Option<Arc<dyn Partitioner>>
+}
+
+impl ProducerConfig {
+    pub fn new(bootstrap_servers: &str) -> Self {
+        ProducerConfig {
+            bootstrap_servers: String::from(bootstrap_servers),
+            client_dns_lookup: ClientDnsLookup::default(),
+            acks: ACKS_ALL,
+            batch_size: 16384,
+            linger: Duration::from_millis(5),
+            max_request_size: 1_048_576,
+            buffer_memory: 33_554_432,
+            max_in_flight_requests_per_connection: 5,
+            enable_idempotence: false,
+            transactional_id: None,
+            transaction_timeout: Duration::from_millis(60_000),
+            max_block: Duration::from_millis(60_000),
+            retry: RetryPolicy::default(),
+            connection_pool: ConnectionPoolConfig::default(),
+            metadata: MetadataCacheConfig::default(),
+            partitioner: None
+        }
+    }
+}
+
+/// A record to send, before it has been given a partition. A `None` value is a tombstone.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProducerRecord<K = Vec<u8>, V = Vec<u8>> {
+    pub topic: String,
+    /// `None` lets the partitioner choose.
+    pub partition: Option<i32>,
+    pub key: Option<K>,
+    pub value: Option<V>,
+    pub headers: Vec<Header>,
+    /// Milliseconds since the epoch. `None` uses the time the record is sent.
+    pub timestamp: Option<i64>
+}
+
+impl<K, V> ProducerRecord<K, V> {
+    pub fn new(topic: &str, value: V) -> Self {
+        ProducerRecord {
+            topic: String::from(topic),
+            partition: None,
+            key: None,
+            value: Some(value),
+            headers: Vec::new(),
+            timestamp: None
+        }
+    }
+
+    /// A record without a value, which deletes the key from compacted topics.
+    pub fn tombstone(topic: &str, key: K) -> Self {
+        ProducerRecord {
+            topic: String::from(topic),
+            partition: None,
+            key: Some(key),
+            value: None,
+            headers: Vec::new(),
+            timestamp: None
+        }
+    }
+
+    pub fn with_key(mut self, key: K) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn with_partition(mut self, partition: i32) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    pub fn with_header(mut self, key: &str, value: Option<Vec<u8>>) -> Self {
+        self.headers.push((String::from(key), value));
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: i64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+}
+
+/// Sends records to Kafka. Records are collected into batches per partition, and a background
+/// thread sends the batches which are ready, grouping those for the same leader into one request.
+/// Keys and values are serialized as they are sent.
+#[derive(Debug)]
+pub struct KafkaProducer<K = Vec<u8>, V = Vec<u8>> {
+    config: ProducerConfig,
+    key_serializer: Box<dyn Serializer<K>>,
+    value_serializer: Box<dyn Serializer<V>>,
+    metadata: Arc<MetadataCache>,
+    accumulator: Arc<RecordAccumulator>,
+    partitioner: Arc<dyn Partitioner>,
+    transaction_manager: Option<Arc<TransactionManager>>,
+    transactions: Option<Arc<TransactionClient>>,
+    sender_thread: Option<JoinHandle<()>>
+}
+
+impl KafkaProducer {
+    /// A producer of records whose keys and values are already bytes.
+    pub fn new(config: ProducerConfig) -> Result<Self> {
+        KafkaProducer::with_serializers(config, BytesSerde, BytesSerde)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_metadata_fetcher(fetcher: Box<dyn MetadataFetcher>, config: ProducerConfig) -> Result<Self> {
+        KafkaProducer::with_metadata_fetcher_and_serializers(fetcher, config, Box::new(BytesSerde), Box::new(BytesSerde))
+    }
+}
+
+impl<K, V> KafkaProducer<K, V> {
+    pub fn with_serializers<KS, VS>(config: ProducerConfig, key_serializer: KS, value_serializer: VS) -> Result<Self>
+        where KS: Serializer<K> + 'static, VS: Serializer<V> + 'static {
+        let mut fetcher: NetworkMetadataFetcher = NetworkMetadataFetcher::new(
+            &config.bootstrap_servers, &config.connection_pool.connection.client_id, config.client_dns_lookup
+        )?;
+        fetcher.connection = config.connection_pool.connection.clone();
+        KafkaProducer::with_metadata_fetcher_and_serializers(Box::new(fetcher), config, Box::new(key_serializer), Box::new(value_serializer))
+    }
+
+    pub(crate) fn with_metadata_fetcher_and_serializers(
+        fetcher: Box<dyn MetadataFetcher>,
+        config: ProducerConfig,
+        key_serializer: Box<dyn Serializer<K>>,
+        value_serializer: Box<dyn Serializer<V>>
+    ) -> Result<Self> {
+        if !(-1..=1).contains(&config.acks) {
+            return Err(anyhow!("Invalid acks: {}. Must be -1, 0 or 1", config.acks));
+        }
+        if config.max_in_flight_requests_per_connection == 0 {
+            return Err(anyhow!("max.in.flight.requests.per.connection must be at least 1"));
+        }
+        if config.enable_idempotence || config.transactional_id.is_some() {
+            if config.acks != ACKS_ALL {
+                return Err(anyhow!("acks must be -1 to enable idempotence, but it is {}", config.acks));
+            }
+            if config.retry.retries == 0 {
+                return Err(anyhow!("retries must be more than 0 to enable idempotence"));
+            }
+            if config.max_in_flight_requests_per_connection > MAX_IDEMPOTENT_IN_FLIGHT_REQUESTS {
+                return Err(anyhow!(
+                    "max.in.flight.requests.per.connection must be at most {} to enable idempotence, but it is {}",
+                    MAX_IDEMPOTENT_IN_FLIGHT_REQUESTS, config.max_in_flight_requests_per_connection
+                ));
+            }
+        }
+        let metadata: Arc<MetadataCache> = Arc::new(MetadataCache::new(fetcher, config.metadata.clone()));
+        let pool: Arc<ConnectionPool> = Arc::new(ConnectionPool::new(metadata.clone(), config.connection_pool.clone()));
+        let buffer_pool: BufferPool = BufferPool::new(config.buffer_memory, config.batch_size);
+        let accumulator: Arc<RecordAccumulator> = Arc::new(RecordAccumulator::new(config.batch_size, config.linger, config.retry.clone(), buffer_pool));
+        let partitioner: Arc<dyn Partitioner> = config.partitioner.clone()
+            .unwrap_or_else(|| Arc::new(DefaultPartitioner::new(config.batch_size)));
+        let transaction_manager: Option<Arc<TransactionManager>> = (config.enable_idempotence || config.transactional_id.is_some())
+            .then(|| Arc::new(TransactionManager::new(config.transactional_id.clone(), config.transaction_timeout)));
+        let transactions: Option<Arc<TransactionClient>> = transaction_manager.as_ref()
+            .zip(config.transactional_id.as_deref())
+            .map(|(transaction_manager, transactional_id)| Arc::new(TransactionClient::new(pool.clone(), transaction_manager.clone(), transactional_id)));
+        let sender: Sender = Sender {
+            accumulator: accumulator.clone(),
+            metadata: metadata.clone(),
+            pool,
+            transaction_manager: transaction_manager.clone(),
+            transactions: transactions.clone(),
+            acks: config.acks,
+            timeout: config.connection_pool.connection.request_timeout,
+            max_request_size: config.max_request_size,
+            max_in_flight_requests: config.max_in_flight_requests_per_connection,
+            retry: config.retry.clone()
+        };
+        let sender_thread: JoinHandle<()> = thread::Builder::new()
+            .name(String::from("kafkart-producer-sender"))
+            .spawn(move || sender.run())?;
+        Ok(KafkaProducer {
+            config,
+            key_serializer,
+            value_serializer,
+            metadata,
+            accumulator,
+            partitioner,
+            transaction_manager,
+            transactions,
+            sender_thread: Some(sender_thread)
+        })
+    }
+
+    /// Adds a record to the batch for its partition, waiting up to `max.block.ms` in total for
+    /// metadata about the topic and for room in `buffer.memory`. The record is sent in the background,
+    /// and the returned future resolves once the broker has written it.
+    pub fn send(&self, record: ProducerRecord<K, V>) -> Result<RecordFuture> {
+        self.send_record(record, None)
+    }
+
+    /// Like `send`, and also calls `callback` on the sender thread once the record is done.
+    pub fn send_with_callback<F>(&self, record: ProducerRecord<K, V>, callback: F) -> Result<RecordFuture> where F: FnOnce(&DeliveryResult) + Send + 'static {
+        self.send_record(record, Some(Box::new(callback)))
+    }
+
+    fn send_record(&self, record: ProducerRecord<K, V>, callback: Option<Callback>) -> Result<RecordFuture> {
+        let deadline: Instant = Instant::now() + self.config.max_block;
+        let cluster: Arc<ClusterMetadata> = self.metadata.wait_for_topic(&record.topic, self.config.max_block)?;
+        let mut headers: Vec<Header> = record.headers;
+        let key: Option<Vec<u8>> = match &record.key {
+            Some(key) => self.key_serializer.serialize(&record.topic, &mut headers, key)
+                .map_err(|error| ProducerError::Serialization { field: "key", error })?,
+            None => None
+        };
+        let value: Option<Vec<u8>> = match &record.value {
+            Some(value) => self.value_serializer.serialize(&record.topic, &mut headers, value)
+                .map_err(|error| ProducerError::Serialization { field: "value", error })?,
+            None => None
+        };
+        let partition: i32 = match record.partition {
+            Some(partition) => {
+                if cluster.partition(&TopicPartition::new(&record.topic, partition)).is_none() {
+                    return Err(anyhow::Error::new(ProducerError::UnknownPartition { topic: record.topic, partition }));
+                }
+                partition
+            },
+            None => self.partitioner.partition(&record.topic, key.as_deref(), value.as_deref(), &cluster)?
+        };
+        let timestamp: i64 = record.timestamp.unwrap_or_else(now_ms);
+        let headers: Vec<RecordHeader> = headers.into_iter()
+            .map(|(key, value)| RecordHeader { key: VarIntString(key), value: VarIntNullableBytes(value) })
+            .collect();
+        let batch_record: Record = Record::new(0, 0, key, value, headers)?;
+        let size: usize = RECORD_BATCH_OVERHEAD + batch_record.size_in_bytes();
+        if size > self.config.max_request_size {
+            return Err(anyhow::Error::new(ProducerError::RecordTooLarge { size, max_size: self.config.max_request_size, setting: "max.request.size" }));
+        }
+        let topic_partition: TopicPartition = TopicPartition::new(&record.topic, partition);
+        if let Some(transaction_manager) = &self.transaction_manager {
+            transaction_manager.maybe_add_partition(&topic_partition)?;
+        }
+        let max_block: Duration = deadline.saturating_duration_since(Instant::now());
+        self.accumulator.append(&topic_partition, timestamp, batch_record, callback, max_block)
+    }
+
+    fn transactions(&self) -> Result<(&TransactionManager, &TransactionClient)> {
+        match (&self.transaction_manager, &self.transactions) {
+            (Some(transaction_manager), Some(transactions)) => Ok((transaction_manager, transactions)),
+            _ => Err(anyhow::Error::new(ProducerError::NotTransactional))
+        }
+    }
+
+    /// How requests to the transaction coordinator are retried: for up to `max.block.ms`.
+    fn transaction_retry(&self) -> RetryPolicy {
+        RetryPolicy { delivery_timeout: self.config.max_block, ..self.config.retry.clone() }
+    }
+
+    /// Gets the producer id for `transactional.id`, which fences off any earlier producer with the
+    /// same id and completes its open transaction. Must be called once before any transaction.
+    pub fn init_transactions(&self) -> Result<()> {
+        let (transaction_manager, transactions) = self.transactions()?;
+        transaction_manager.check_can_initialize()?;
+        if self.metadata.version() == 0 {
+            // the coordinator is found through any broker, so wait for the first metadata
+            self.metadata.wait_for_update(0, self.config.max_block);
+        }
+        let producer: ProducerIdAndEpoch = transactions.init_producer_id(&self.transaction_retry())?;
+        transaction_manager.initialize(producer)?;
+        Ok(())
+    }
+
+    /// Starts a transaction, which the records sent until it is committed or aborted belong to.
+    pub fn begin_transaction(&self) -> Result<()> {
+        let (transaction_manager, _) = self.transactions()?;
+        transaction_manager.begin_transaction()?;
+        Ok(())
+    }
+
+    /// Commits a consumer group's offsets as part of the transaction, so that the records it
+    /// consumed count as consumed only if the transaction commits.
+    pub fn send_offsets_to_transaction(&self, offsets: &HashMap<TopicPartition, OffsetAndMetadata>, group_metadata: &ConsumerGroupMetadata) -> Result<()> {
+        let (transaction_manager, transactions) = self.transactions()?;
+        transaction_manager.check_in_transaction("send offsets to a transaction")?;
+        if offsets.is_empty() {
+            return Ok(());
+        }
+        transactions.send_offsets(offsets, group_metadata, &self.transaction_retry())
+    }
+
+    /// Flushes the transaction's records and commits it. If this fails with an error which can be
+    /// retried, such as a timeout, it may be called again; otherwise the transaction has to be aborted.
+    pub fn commit_transaction(&self) -> Result<()> {
+        let (transaction_manager, transactions) = self.transactions()?;
+        transaction_manager.begin_commit()?;
+        self.flush();
+        if let Some(error) = transaction_manager.error() {
+            return Err(anyhow::Error::new(error));
+        }
+        if transaction_manager.has_partitions_or_offsets() {
+            transactions.end_txn(true, &self.transaction_retry())?;
+        }
+        transaction_manager.complete_transaction();
+        Ok(())
+    }
+
+    /// Aborts the transaction, after its records have been sent or have failed. Records already
+    /// written in it are never seen by consumers reading committed records.
+    pub fn abort_transaction(&self) -> Result<()> {
+        let (transaction_manager, transactions) = self.transactions()?;
+        transaction_manager.begin_abort()?;
+        self.flush();
+        let policy: RetryPolicy = self.transaction_retry();
+        if transaction_manager.has_partitions_or_offsets() {
+            transactions.end_txn(false, &policy)?;
+        }
+        if transaction_manager.is_epoch_bump_required() {
+            // a failed batch left a gap in its partition's sequence numbers
+            let producer: ProducerIdAndEpoch = transactions.init_producer_id(&policy)?;
+            transaction_manager.set_producer_id_and_epoch(producer);
+        }
+        transaction_manager.complete_transaction();
+        Ok(())
+    }
+
+    /// Sends every batch immediately, ignoring `linger.ms`, and blocks until they have been written
+    /// or have failed, so that the future of every record sent before is done.
+    pub fn flush(&self) {
+        self.accumulator.flush();
+    }
+
+    /// Sends any remaining records and stops the sender thread, waiting as long as that takes.
+    pub fn close(self) {
+        self.close_timeout(Duration::MAX);
+    }
+
+    /// Stops accepting records and waits up to `timeout` for the records already sent to be
+    /// written. Records which haven't been sent by then fail with `ProducerError::Closed`, and the
+    /// sender thread stops once the requests in flight have completed.
+    pub fn close_timeout(mut self, timeout: Duration) {
+        self.accumulator.close();
+        if !self.accumulator.await_completion(timeout) {
+            warn!("Failing the records which were not sent within the close timeout of {:?}", timeout);
+            self.accumulator.abort_incomplete_batches();
+        }
+        if let Some(sender_thread) = self.sender_thread.take() {
+            let _ = sender_thread.join();
+        }
+    }
+}
+
+impl<K, V> Drop for KafkaProducer<K, V> {
+    fn drop(&mut self) {
+        self.accumulator.close();
+        if let Some(sender_thread) = self.sender_thread.take() {
+            let _ = sender_thread.join();
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableString, CompactString, NullableString};
+    use crate::cluster::{ClusterMetadata, TopicPartition};
+    use crate::cluster::tests::mock_broker_cluster as cluster;
+    use crate::coordinator::{ConsumerGroupMetadata, OffsetAndMetadata};
+    use crate::metadata_cache::tests::StaticFetcher;
+    use crate::producer::future::{DeliveryResult, RecordFuture, RecordMetadata};
+    use crate::producer::{KafkaProducer, ProducerConfig, ProducerError, ProducerRecord};
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::add_offsets_to_txn::{AddOffsetsToTxnRequestV3, AddOffsetsToTxnResponseV3};
+    use crate::protocol::add_partitions_to_txn::{AddPartitionsToTxnPartitionResultV3, AddPartitionsToTxnRequestV3, AddPartitionsToTxnResponseV3, AddPartitionsToTxnTopicResultV3};
+    use crate::protocol::end_txn::{EndTxnRequestV3, EndTxnResponseV3};
+    use crate::protocol::find_coordinator::{FindCoordinatorRequestV3, FindCoordinatorResponseV3};
+    use crate::protocol::init_producer_id::{InitProducerIdRequestV3V4, InitProducerIdResponseV2V4};
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::produce::{PartitionProduceResponseV8, PartitionProduceResponseV9, ProduceRequestV3V8, ProduceRequestV9, ProduceResponseV8, ProduceResponseV9, TopicProduceResponseV8, TopicProduceResponseV9};
+    use crate::protocol::records::{Record, RecordBatch};
+    use crate::protocol::tags::TaggedFields;
+    use crate::protocol::txn_offset_commit::{TxnOffsetCommitRequestV3, TxnOffsetCommitResponsePartitionV3, TxnOffsetCommitResponseTopicV3, TxnOffsetCommitResponseV3};
+    use crate::retry::RetryPolicy;
+    use crate::serialization::{Header, IntegerSerde, SerializationError, Serializer};
+
+    fn produce_response(request: &ProduceRequestV9, error_code: ErrorCode) -> ProduceResponseV9 {
+        produce_response_at(request, error_code, 0)
+    }
+
+    fn produce_response_at(request: &ProduceRequestV9, error_code: ErrorCode, base_offset: i64) -> ProduceResponseV9 {
+        ProduceResponseV9 {
+            responses: CompactArray(request.topic_data.0.iter()
+                .map(|topic| TopicProduceResponseV9 {
+                    name: topic.name.clone(),
+                    partition_responses: CompactArray(topic.partition_data.0.iter()
+                        .map(|partition| PartitionProduceResponseV9 {
+                            index: partition.index,
+                            error_code: error_code.clone(),
+                            base_offset,
+                            log_append_time_ms: -1,
+                            log_start_offset: 0,
+                            record_errors: CompactArray(vec![]),
+                            error_message: CompactNullableString(None),
+                            tag_buffer: TaggedFields::new()
+                        })
+                        .collect()),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            throttle_time_ms: 0,
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    /// A broker which accepts every Produce request and keeps them for the test to inspect.
+    fn produce_broker() -> (MockBroker, Arc<Mutex<Vec<ProduceRequestV9>>>) {
+        let requests: Arc<Mutex<Vec<ProduceRequestV9>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_requests: Arc<Mutex<Vec<ProduceRequestV9>>> = requests.clone();
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], move |request: &MockRequest| {
+            let produce_request: ProduceRequestV9 = request.decode().unwrap();
+            let response: ProduceResponseV9 = produce_response(&produce_request, ErrorCode::None);
+            broker_requests.lock().unwrap().push(produce_request);
+            Some(request.respond::<ProduceRequestV9, ProduceResponseV9>(response))
+        });
+        (broker, requests)
+    }
+
+    fn batches(request: &ProduceRequestV9) -> Vec<(String, i32, RecordBatch)> {
+        let mut batches: Vec<(String, i32, RecordBatch)> = Vec::new();
+        for topic in &request.topic_data.0 {
+            for partition in &topic.partition_data.0 {
+                for batch in partition.records.0.clone().unwrap() {
+                    batches.push((topic.name.0.clone(), partition.index, batch));
+                }
+            }
+        }
+        batches
+    }
+
+    /// A broker which gives out producer id 42, bumping its epoch on every later InitProducerId, and
+    /// answers Produce requests with `produce`. The InitProducerId requests are kept for the test.
+    fn idempotent_broker<F>(produce: F) -> (MockBroker, Arc<Mutex<Vec<InitProducerIdRequestV3V4>>>)
+        where F: Fn(&ProduceRequestV9) -> ProduceResponseV9 + Send + Sync + 'static {
+        let init_requests: Arc<Mutex<Vec<InitProducerIdRequestV3V4>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_init_requests: Arc<Mutex<Vec<InitProducerIdRequestV3V4>>> = init_requests.clone();
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], move |request: &MockRequest| {
+            if request.api_key == ApiKey::InitProducerId as i16 {
+                let init_request: InitProducerIdRequestV3V4 = request.decode().unwrap();
+                let producer_epoch: i16 = if init_request.producer_id == -1 { 0 } else { init_request.producer_epoch + 1 };
+                broker_init_requests.lock().unwrap().push(init_request);
+                return Some(request.respond::<InitProducerIdRequestV3V4, InitProducerIdResponseV2V4>(InitProducerIdResponseV2V4 {
+                    throttle_time_ms: 0,
+                    error_code: ErrorCode::None,
+                    producer_id: 42,
+                    producer_epoch,
+                    tag_buffer: TaggedFields::new()
+                }));
+            }
+            let produce_request: ProduceRequestV9 = request.decode().unwrap();
+            Some(request.respond::<ProduceRequestV9, ProduceResponseV9>(produce(&produce_request)))
+        });
+        (broker, init_requests)
+    }
+
+    /// A broker which coordinates every transaction and group and answers InitProducerId like
+    /// `idempotent_broker`. Other requests succeed unless `error` picks an error code for them.
+    /// Every request is kept for the test to inspect.
+    fn transactional_broker<F>(error: F) -> (MockBroker, Arc<Mutex<Vec<MockRequest>>>)
+        where F: Fn(&MockRequest) -> ErrorCode + Send + Sync + 'static {
+        let requests: Arc<Mutex<Vec<MockRequest>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_requests: Arc<Mutex<Vec<MockRequest>>> = requests.clone();
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], move |request: &MockRequest| {
+            broker_requests.lock().unwrap().push(request.clone());
+            let error_code: ErrorCode = error(request);
+            let response: Vec<u8> = match ApiKey::try_from(request.api_key).unwrap() {
+                ApiKey::FindCoordinator => request.respond::<FindCoordinatorRequestV3, FindCoordinatorResponseV3>(FindCoordinatorResponseV3 {
+                    throttle_time_ms: 0,
+                    error_code: ErrorCode::None,
+                    error_message: CompactNullableString(None),
+                    node_id: 1,
+                    host: CompactString(String::from("127.0.0.1")),
+                    port: 0,
+                    tag_buffer: TaggedFields::new()
+                }),
+                ApiKey::InitProducerId => {
+                    let init_request: InitProducerIdRequestV3V4 = request.decode().unwrap();
+                    request.respond::<InitProducerIdRequestV3V4, InitProducerIdResponseV2V4>(InitProducerIdResponseV2V4 {
+                        throttle_time_ms: 0,
+                        error_code,
+                        producer_id: 42,
+                        producer_epoch: if init_request.producer_id == -1 { 0 } else { init_request.producer_epoch + 1 },
+                        tag_buffer: TaggedFields::new()
+                    })
+                },
+                ApiKey::AddPartitionsToTxn => {
+                    let add_request: AddPartitionsToTxnRequestV3 = request.decode().unwrap();
+                    request.respond::<AddPartitionsToTxnRequestV3, AddPartitionsToTxnResponseV3>(AddPartitionsToTxnResponseV3 {
+                        throttle_time_ms: 0,
+                        results: CompactArray(add_request.topics.0.into_iter()
+                            .map(|topic| AddPartitionsToTxnTopicResultV3 {
+                                name: topic.name,
+                                results: CompactArray(topic.partitions.0.into_iter()
+                                    .map(|partition_index| AddPartitionsToTxnPartitionResultV3 {
+                                        partition_index,
+                                        error_code: error_code.clone(),
+                                        tag_buffer: TaggedFields::new()
+                                    })
+                                    .collect()),
+                                tag_buffer: TaggedFields::new()
+                            })
+                            .collect()),
+                        tag_buffer: TaggedFields::new()
+                    })
+                },
+                ApiKey::AddOffsetsToTxn => request.respond::<AddOffsetsToTxnRequestV3, AddOffsetsToTxnResponseV3>(AddOffsetsToTxnResponseV3 {
+                    throttle_time_ms: 0,
+                    error_code,
+                    tag_buffer: TaggedFields::new()
+                }),
+                ApiKey::TxnOffsetCommit => {
+                    let commit_request: TxnOffsetCommitRequestV3 = request.decode().unwrap();
+                    request.respond::<TxnOffsetCommitRequestV3, TxnOffsetCommitResponseV3>(TxnOffsetCommitResponseV3 {
+                        throttle_time_ms: 0,
+                        topics: CompactArray(commit_request.topics.0.into_iter()
+                            .map(|topic| TxnOffsetCommitResponseTopicV3 {
+                                name: topic.name,
+                                partitions: CompactArray(topic.partitions.0.into_iter()
+                                    .map(|partition| TxnOffsetCommitResponsePartitionV3 {
+                                        partition_index: partition.partition_index,
+                                        error_code: error_code.clone(),
+                                        tag_buffer: TaggedFields::new()
+                                    })
+                                    .collect()),
+                                tag_buffer: TaggedFields::new()
+                            })
+                            .collect()),
+                        tag_buffer: TaggedFields::new()
+                    })
+                },
+                ApiKey::EndTxn => request.respond::<EndTxnRequestV3, EndTxnResponseV3>(EndTxnResponseV3 {
+                    throttle_time_ms: 0,
+                    error_code,
+                    tag_buffer: TaggedFields::new()
+                }),
+                _ => {
+                    let produce_request: ProduceRequestV9 = request.decode().unwrap();
+                    request.respond::<ProduceRequestV9, ProduceResponseV9>(produce_response(&produce_request, error_code))
+                }
+            };
+            Some(response)
+        });
+        (broker, requests)
+    }
+
+    fn transactional_config() -> ProducerConfig {
+        ProducerConfig {
+            transactional_id: Some(String::from("txn")),
+            ..config()
+        }
+    }
+
+    fn api_keys(requests: &Mutex<Vec<MockRequest>>) -> Vec<ApiKey> {
+        requests.lock().unwrap().iter()
+            .map(|request| ApiKey::try_from(request.api_key).unwrap())
+            .collect()
+    }
+
+    fn config() -> ProducerConfig {
+        ProducerConfig {
+            linger: Duration::from_millis(20),
+            retry: RetryPolicy {
+                retry_backoff: Duration::from_millis(10),
+                retry_backoff_max: Duration::from_millis(40),
+                ..RetryPolicy::default()
+            },
+            ..ProducerConfig::new("unused:9092")
+        }
+    }
+
+    #[test]
+    fn test_batches_for_the_same_leader_share_a_request() {
+        let (broker_1, requests_1) = produce_broker();
+        let (broker_2, requests_2) = produce_broker();
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker_1), (2, &broker_2)], &[1, 1, 2]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config()).unwrap();
+        for partition in [0, 1, 2, 0] {
+            let record: ProducerRecord = ProducerRecord::new("foo", format!("value-{}", partition).into_bytes())
+                .with_partition(partition)
+                .with_key(b"key".to_vec())
+                .with_header("h", Some(b"x".to_vec()))
+                .with_timestamp(1_000 + partition as i64);
+            producer.send(record).unwrap();
+        }
+        producer.flush();
+
+        let requests_1: Vec<ProduceRequestV9> = requests_1.lock().unwrap().clone();
+        assert_eq!(requests_1.len(), 1);
+        assert_eq!(requests_1[0].acks, -1);
+        let mut batches_1: Vec<(String, i32, RecordBatch)> = batches(&requests_1[0]);
+        batches_1.sort_by_key(|(_, partition, _)| *partition);
+        assert_eq!(batches_1.iter().map(|(topic, partition, _)| (topic.as_str(), *partition)).collect::<Vec<_>>(), vec![("foo", 0), ("foo", 1)]);
+
+        let partition_0: &RecordBatch = &batches_1[0].2;
+        assert!(partition_0.is_valid().unwrap());
+        assert_eq!(partition_0.records.0.len(), 2);
+        assert_eq!(partition_0.records.0[1].value.0, Some(b"value-0".to_vec()));
+        assert_eq!(partition_0.records.0[1].offset_delta.0, 1);
+        assert_eq!(partition_0.records.0[0].headers.0[0].key.0, "h");
+        assert_eq!(partition_0.base_timestamp, 1_000);
+
+        let requests_2: Vec<ProduceRequestV9> = requests_2.lock().unwrap().clone();
+        assert_eq!(requests_2.len(), 1);
+        assert_eq!(batches(&requests_2[0])[0].1, 2);
+    }
+
+    #[test]
+    fn test_full_batches_are_sent_without_waiting_for_linger() {
+        let (broker, requests) = produce_broker();
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            batch_size: 1024,
+            linger: Duration::from_secs(60),
+            ..config()
+        }).unwrap();
+        for _ in 0..20 {
+            producer.send(ProducerRecord::new("foo", vec![7; 100])).unwrap();
+        }
+
+        let started: Instant = Instant::now();
+        while requests.lock().unwrap().len() < 2 && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let requests: Vec<ProduceRequestV9> = requests.lock().unwrap().clone();
+        // two full batches of 8 records have been sent, while the last 4 records linger
+        assert_eq!(requests.len(), 2);
+        assert_eq!(batches(&requests[0])[0].2.records.0.len(), 8);
+        assert_eq!(batches(&requests[1])[0].2.records.0.len(), 8);
+    }
+
+    #[test]
+    fn test_retriable_errors_are_retried() {
+        let attempts: Arc<Mutex<Vec<ProduceRequestV9>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_attempts: Arc<Mutex<Vec<ProduceRequestV9>>> = attempts.clone();
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], move |request: &MockRequest| {
+            let produce_request: ProduceRequestV9 = request.decode().unwrap();
+            let mut attempts = broker_attempts.lock().unwrap();
+            let error_code: ErrorCode = match attempts.len() {
+                0 => ErrorCode::NotEnoughReplicas,
+                _ => ErrorCode::None
+            };
+            let response: ProduceResponseV9 = produce_response(&produce_request, error_code);
+            attempts.push(produce_request);
+            Some(request.respond::<ProduceRequestV9, ProduceResponseV9>(response))
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config()).unwrap();
+        producer.send(ProducerRecord::new("foo", b"hello".to_vec())).unwrap();
+        producer.flush();
+
+        let attempts: Vec<ProduceRequestV9> = attempts.lock().unwrap().clone();
+        assert_eq!(attempts.len(), 2);
+        // the retry sends exactly the same batch
+        assert_eq!(batches(&attempts[0]), batches(&attempts[1]));
+    }
+
+    #[test]
+    fn test_acks_none_sends_without_waiting_for_a_response() {
+        let requests: Arc<Mutex<Vec<ProduceRequestV9>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_requests: Arc<Mutex<Vec<ProduceRequestV9>>> = requests.clone();
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], move |request: &MockRequest| {
+            broker_requests.lock().unwrap().push(request.decode().unwrap());
+            Some(Vec::new())
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            acks: 0,
+            ..config()
+        }).unwrap();
+        producer.send(ProducerRecord::new("foo", b"first".to_vec())).unwrap();
+        producer.flush();
+        producer.send(ProducerRecord::new("foo", b"second".to_vec())).unwrap();
+        producer.close();
+
+        let requests: Vec<ProduceRequestV9> = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].acks, 0);
+        assert_eq!(broker.accepted_connections(), 1);
+    }
+
+    #[test]
+    fn test_close_fails_the_records_not_written_within_its_timeout() {
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], |request: &MockRequest| {
+            let produce_request: ProduceRequestV9 = request.decode().unwrap();
+            let response: ProduceResponseV9 = produce_response(&produce_request, ErrorCode::NotEnoughReplicas);
+            Some(request.respond::<ProduceRequestV9, ProduceResponseV9>(response))
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config()).unwrap();
+        let future: RecordFuture = producer.send(ProducerRecord::new("foo", b"stuck".to_vec())).unwrap();
+
+        let started: Instant = Instant::now();
+        producer.close_timeout(Duration::from_millis(100));
+        assert!(started.elapsed() >= Duration::from_millis(100) && started.elapsed() < Duration::from_secs(5));
+        assert_eq!(future.get(), Err(ProducerError::Closed));
+    }
+
+    #[test]
+    fn test_invalid_records_are_rejected() {
+        let (broker, requests) = produce_broker();
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            max_request_size: 1024,
+            ..config()
+        }).unwrap();
+
+        let too_large: anyhow::Error = producer.send(ProducerRecord::new("foo", vec![0; 1024])).unwrap_err();
+        assert!(matches!(too_large.downcast_ref::<ProducerError>(), Some(ProducerError::RecordTooLarge { max_size: 1024, .. })));
+        let unknown_partition: anyhow::Error = producer.send(ProducerRecord::new("foo", vec![0]).with_partition(3)).unwrap_err();
+        assert!(matches!(unknown_partition.downcast_ref::<ProducerError>(), Some(ProducerError::UnknownPartition { partition: 3, .. })));
+        producer.flush();
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_send_blocks_while_buffer_memory_is_full() {
+        let (broker, requests) = produce_broker();
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1, 1, 1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            batch_size: 1024,
+            buffer_memory: 2048,
+            linger: Duration::from_secs(60),
+            max_block: Duration::from_millis(50),
+            ..config()
+        }).unwrap();
+        // each lingering batch holds a buffer of batch.size
+        producer.send(ProducerRecord::new("foo", vec![1]).with_partition(0)).unwrap();
+        producer.send(ProducerRecord::new("foo", vec![1]).with_partition(1)).unwrap();
+
+        let started: Instant = Instant::now();
+        let exhausted: anyhow::Error = producer.send(ProducerRecord::new("foo", vec![1]).with_partition(2)).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(matches!(exhausted.downcast_ref::<ProducerError>(), Some(ProducerError::BufferExhausted { buffer_memory: 2048, .. })));
+        // records for partitions which already have a batch still fit
+        producer.send(ProducerRecord::new("foo", vec![2]).with_partition(0)).unwrap();
+
+        producer.flush();
+        producer.send(ProducerRecord::new("foo", vec![1]).with_partition(2)).unwrap();
+        let too_large: anyhow::Error = producer.send(ProducerRecord::new("foo", vec![0; 4096])).unwrap_err();
+        assert!(matches!(too_large.downcast_ref::<ProducerError>(), Some(ProducerError::RecordTooLarge { .. })));
+        producer.flush();
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    /// Serializes strings, except for ones which aren't allowed.
+    #[derive(Debug)]
+    struct PickySerializer;
+
+    impl Serializer<String> for PickySerializer {
+        fn serialize(&self, topic: &str, headers: &mut Vec<Header>, data: &String) -> Result<Option<Vec<u8>>, SerializationError> {
+            if data == "forbidden" {
+                return Err(SerializationError::new(topic, "forbidden"));
+            }
+            headers.push((String::from("serialized-by"), Some(b"picky".to_vec())));
+            Ok(Some(data.as_bytes().to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_keys_and_values_are_serialized_per_record() {
+        let (broker, requests) = produce_broker();
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer<i32, String> = KafkaProducer::with_metadata_fetcher_and_serializers(
+            Box::new(StaticFetcher(cluster)), config(), Box::new(IntegerSerde), Box::new(PickySerializer)
+        ).unwrap();
+        let error: anyhow::Error = producer.send(ProducerRecord::new("foo", String::from("forbidden")).with_key(1)).unwrap_err();
+        assert_eq!(error.downcast_ref::<ProducerError>(), Some(&ProducerError::Serialization {
+            field: "value",
+            error: SerializationError::new("foo", "forbidden")
+        }));
+        producer.send(ProducerRecord::new("foo", String::from("allowed")).with_key(2)).unwrap();
+        producer.send(ProducerRecord::tombstone("foo", 3)).unwrap();
+        producer.flush();
+
+        let records: Vec<Record> = requests.lock().unwrap().iter()
+            .flat_map(batches)
+            .flat_map(|(_, _, batch)| batch.records.0)
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].key.0, Some(vec![0, 0, 0, 2]));
+        assert_eq!(records[0].value.0, Some(b"allowed".to_vec()));
+        assert_eq!(records[0].headers.0[0].key.0, "serialized-by");
+        assert_eq!(records[1].key.0, Some(vec![0, 0, 0, 3]));
+        assert_eq!(records[1].value.0, None);
+    }
+
+    #[test]
+    fn test_futures_resolve_to_where_records_were_written() {
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], |request: &MockRequest| {
+            let produce_request: ProduceRequestV9 = request.decode().unwrap();
+            let response: ProduceResponseV9 = produce_response_at(&produce_request, ErrorCode::None, 100);
+            Some(request.respond::<ProduceRequestV9, ProduceResponseV9>(response))
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config()).unwrap();
+        let called_back: Arc<Mutex<Vec<DeliveryResult>>> = Arc::new(Mutex::new(Vec::new()));
+        let callback_results: Arc<Mutex<Vec<DeliveryResult>>> = called_back.clone();
+
+        let first: RecordFuture = producer.send(ProducerRecord::new("foo", b"first".to_vec()).with_timestamp(1_000)).unwrap();
+        let second: RecordFuture = producer.send_with_callback(
+            ProducerRecord::new("foo", b"second".to_vec()).with_key(b"key".to_vec()).with_timestamp(1_001),
+            move |result: &DeliveryResult| callback_results.lock().unwrap().push(result.clone())
+        ).unwrap();
+        producer.flush();
+
+        assert!(first.is_done());
+        assert_eq!(first.get().unwrap().offset, 100);
+        let expected: RecordMetadata = RecordMetadata {
+            topic: String::from("foo"),
+            partition: 0,
+            offset: 101,
+            timestamp: 1_001,
+            serialized_key_size: 3,
+            serialized_value_size: 6
+        };
+        assert_eq!(second.get(), Ok(expected.clone()));
+        assert_eq!(*called_back.lock().unwrap(), vec![Ok(expected)]);
+    }
+
+    #[test]
+    fn test_brokers_without_flexible_produce_versions_are_sent_v8() {
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 8)], |request: &MockRequest| {
+            let produce_request: ProduceRequestV3V8 = request.decode().unwrap();
+            let response: ProduceResponseV8 = ProduceResponseV8 {
+                responses: Array(produce_request.topic_data.0.iter()
+                    .map(|topic| TopicProduceResponseV8 {
+                        name: topic.name.clone(),
+                        partition_responses: Array(topic.partition_data.0.iter()
+                            .map(|partition| PartitionProduceResponseV8 {
+                                index: partition.index,
+                                error_code: ErrorCode::None,
+                                base_offset: 7,
+                                log_append_time_ms: -1,
+                                log_start_offset: 0,
+                                record_errors: Array(vec![]),
+                                error_message: NullableString(None)
+                            })
+                            .collect())
+                    })
+                    .collect()),
+                throttle_time_ms: 0
+            };
+            Some(request.respond::<ProduceRequestV3V8, ProduceResponseV8>(response))
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config()).unwrap();
+
+        let future: RecordFuture = producer.send(ProducerRecord::new("foo", b"old".to_vec())).unwrap();
+        producer.flush();
+        assert_eq!(future.get_timeout(Duration::from_secs(5)).unwrap().unwrap().offset, 7);
+    }
+
+    #[test]
+    fn test_futures_fail_with_the_error_of_their_batch() {
+        let broker: MockBroker = MockBroker::start_with_api_versions(vec![(ApiKey::Produce, 9)], |request: &MockRequest| {
+            let produce_request: ProduceRequestV9 = request.decode().unwrap();
+            let response: ProduceResponseV9 = produce_response(&produce_request, ErrorCode::TopicAuthorizationFailed);
+            Some(request.respond::<ProduceRequestV9, ProduceResponseV9>(response))
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config()).unwrap();
+        let futures: Vec<RecordFuture> = (0..2)
+            .map(|_| producer.send(ProducerRecord::new("foo", b"denied".to_vec())).unwrap())
+            .collect();
+        for future in futures {
+            let result: DeliveryResult = future.get_timeout(Duration::from_secs(5)).unwrap();
+            assert!(matches!(result, Err(ProducerError::SendFailed { partition: 0, error_code: Some(ErrorCode::TopicAuthorizationFailed), .. })));
+        }
+    }
+
+    #[test]
+    fn test_idempotent_batches_carry_the_producer_id_and_sequences() {
+        let requests: Arc<Mutex<Vec<ProduceRequestV9>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_requests: Arc<Mutex<Vec<ProduceRequestV9>>> = requests.clone();
+        let (broker, init_requests) = idempotent_broker(move |request: &ProduceRequestV9| {
+            broker_requests.lock().unwrap().push(request.clone());
+            produce_response(request, ErrorCode::None)
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            enable_idempotence: true,
+            ..config()
+        }).unwrap();
+        producer.send(ProducerRecord::new("foo", b"first".to_vec())).unwrap();
+        producer.send(ProducerRecord::new("foo", b"second".to_vec())).unwrap();
+        producer.flush();
+        producer.send(ProducerRecord::new("foo", b"third".to_vec())).unwrap();
+        producer.flush();
+
+        let init_requests: Vec<InitProducerIdRequestV3V4> = init_requests.lock().unwrap().clone();
+        assert_eq!(init_requests.len(), 1);
+        assert_eq!((init_requests[0].producer_id, init_requests[0].producer_epoch), (-1, -1));
+        let sent: Vec<RecordBatch> = requests.lock().unwrap().iter()
+            .flat_map(|request| batches(request).into_iter().map(|(_, _, batch)| batch))
+            .collect();
+        let producer_states: Vec<(i64, i16, i32)> = sent.iter()
+            .map(|batch| (batch.producer_id, batch.producer_epoch_offset, batch.base_sequence))
+            .collect();
+        assert_eq!(producer_states, vec![(42, 0, 0), (42, 0, 2)]);
+        assert!(sent.iter().all(|batch| batch.is_valid().unwrap()));
+    }
+
+    #[test]
+    fn test_batches_rejected_out_of_order_are_retried_in_order() {
+        // like a broker, accept only the next sequence, and fail its first attempt
+        let log: Arc<Mutex<(i32, bool, Vec<i32>)>> = Arc::new(Mutex::new((0, false, Vec::new())));
+        let broker_log: Arc<Mutex<(i32, bool, Vec<i32>)>> = log.clone();
+        let (broker, init_requests) = idempotent_broker(move |request: &ProduceRequestV9| {
+            let (_, _, batch) = batches(request).remove(0);
+            let mut log = broker_log.lock().unwrap();
+            let (next_sequence, failed_once, attempts) = &mut *log;
+            attempts.push(batch.base_sequence);
+            if batch.base_sequence != *next_sequence {
+                return produce_response(request, ErrorCode::OutOfOrderSequenceNumber);
+            }
+            if !*failed_once {
+                *failed_once = true;
+                return produce_response(request, ErrorCode::NotEnoughReplicas);
+            }
+            let base_offset: i64 = *next_sequence as i64;
+            *next_sequence += batch.records.0.len() as i32;
+            produce_response_at(request, ErrorCode::None, base_offset)
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            enable_idempotence: true,
+            batch_size: 1024,
+            linger: Duration::from_secs(60),
+            ..config()
+        }).unwrap();
+        // three batches of 8 records, of which the first two are full and sent together
+        let futures: Vec<RecordFuture> = (0..20)
+            .map(|_| producer.send(ProducerRecord::new("foo", vec![7; 100])).unwrap())
+            .collect();
+        producer.flush();
+
+        let offsets: Vec<i64> = futures.iter().map(|future| future.get().unwrap().offset).collect();
+        assert_eq!(offsets, (0..20).collect::<Vec<i64>>());
+        let (next_sequence, _, attempts) = log.lock().unwrap().clone();
+        assert_eq!(next_sequence, 20);
+        assert_eq!(attempts.iter().filter(|sequence| **sequence == 0).count(), 2);
+        assert_eq!(init_requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_producer_ids_bump_the_epoch() {
+        let requests: Arc<Mutex<Vec<ProduceRequestV9>>> = Arc::new(Mutex::new(Vec::new()));
+        let broker_requests: Arc<Mutex<Vec<ProduceRequestV9>>> = requests.clone();
+        let (broker, init_requests) = idempotent_broker(move |request: &ProduceRequestV9| {
+            let mut requests = broker_requests.lock().unwrap();
+            requests.push(request.clone());
+            match requests.len() {
+                1 => produce_response(request, ErrorCode::UnknownProducerId),
+                _ => produce_response(request, ErrorCode::None)
+            }
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), ProducerConfig {
+            enable_idempotence: true,
+            ..config()
+        }).unwrap();
+        let future: RecordFuture = producer.send(ProducerRecord::new("foo", b"hello".to_vec())).unwrap();
+        producer.flush();
+        future.get().unwrap();
+
+        let init_requests: Vec<InitProducerIdRequestV3V4> = init_requests.lock().unwrap().clone();
+        let init_producers: Vec<(i64, i16)> = init_requests.iter().map(|request| (request.producer_id, request.producer_epoch)).collect();
+        assert_eq!(init_producers, vec![(-1, -1), (42, 0)]);
+        let requests: Vec<ProduceRequestV9> = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        let retried: RecordBatch = batches(&requests[1]).remove(0).2;
+        assert_eq!((retried.producer_id, retried.producer_epoch_offset, retried.base_sequence), (42, 1, 0));
+        assert!(retried.is_valid().unwrap());
+    }
+
+    #[test]
+    fn test_idempotence_needs_acks_all() {
+        let invalid: Vec<ProducerConfig> = vec![
+            ProducerConfig { enable_idempotence: true, acks: 1, ..config() },
+            ProducerConfig { enable_idempotence: true, max_in_flight_requests_per_connection: 6, ..config() },
+            ProducerConfig { max_in_flight_requests_per_connection: 0, ..config() }
+        ];
+        for config in invalid {
+            let cluster: ClusterMetadata = ClusterMetadata::default();
+            assert!(KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_transactions_add_partitions_and_offsets_before_they_commit() {
+        let (broker, requests) = transactional_broker(|_| ErrorCode::None);
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1, 1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), transactional_config()).unwrap();
+        assert!(producer.send(ProducerRecord::new("foo", b"too early".to_vec())).is_err());
+        producer.init_transactions().unwrap();
+        producer.begin_transaction().unwrap();
+        let future: RecordFuture = producer.send(ProducerRecord::new("foo", b"first".to_vec()).with_partition(1)).unwrap();
+        let mut offsets: HashMap<TopicPartition, OffsetAndMetadata> = HashMap::new();
+        offsets.insert(TopicPartition::new("bar", 0), OffsetAndMetadata::new(10));
+        producer.send_offsets_to_transaction(&offsets, &ConsumerGroupMetadata::new("group")).unwrap();
+        producer.commit_transaction().unwrap();
+        assert_eq!(future.get().unwrap().partition, 1);
+
+        let keys: Vec<ApiKey> = api_keys(&requests);
+        let position = |api_key: ApiKey| keys.iter().position(|key| *key == api_key).unwrap();
+        assert_eq!(&keys[..2], &[ApiKey::FindCoordinator, ApiKey::InitProducerId]);
+        assert!(position(ApiKey::AddPartitionsToTxn) < position(ApiKey::Produce));
+        assert!(position(ApiKey::AddOffsetsToTxn) < position(ApiKey::TxnOffsetCommit));
+        assert_eq!(keys.last(), Some(&ApiKey::EndTxn));
+
+        let requests: Vec<MockRequest> = requests.lock().unwrap().clone();
+        let find = |api_key: ApiKey| {
+            let api_key: i16 = api_key as i16;
+            requests.iter().find(|request| request.api_key == api_key).unwrap()
+        };
+        let init_request: InitProducerIdRequestV3V4 = find(ApiKey::InitProducerId).decode().unwrap();
+        assert_eq!(init_request.transactional_id.0.as_deref(), Some("txn"));
+        let add_request: AddPartitionsToTxnRequestV3 = find(ApiKey::AddPartitionsToTxn).decode().unwrap();
+        assert_eq!(add_request.topics.0[0].partitions.0, vec![1]);
+        let produce_request: ProduceRequestV9 = find(ApiKey::Produce).decode().unwrap();
+        assert_eq!(produce_request.transactional_id.0.as_deref(), Some("txn"));
+        let commit_request: TxnOffsetCommitRequestV3 = find(ApiKey::TxnOffsetCommit).decode().unwrap();
+        assert_eq!(commit_request.topics.0[0].partitions.0[0].committed_offset, 10);
+        let end_request: EndTxnRequestV3 = find(ApiKey::EndTxn).decode().unwrap();
+        assert!(end_request.committed);
+    }
+
+    #[test]
+    fn test_concurrent_transactions_are_retried() {
+        let attempts: Arc<Mutex<HashMap<i16, usize>>> = Arc::new(Mutex::new(HashMap::new()));
+        let (broker, requests) = transactional_broker(move |request: &MockRequest| {
+            let mut attempts = attempts.lock().unwrap();
+            let attempt: &mut usize = attempts.entry(request.api_key).or_default();
+            *attempt += 1;
+            // the coordinator is still completing the previous transaction at first
+            let retried: bool = [ApiKey::AddPartitionsToTxn as i16, ApiKey::EndTxn as i16].contains(&request.api_key);
+            if retried && *attempt == 1 { ErrorCode::ConcurrentTransactions } else { ErrorCode::None }
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), transactional_config()).unwrap();
+        producer.init_transactions().unwrap();
+        producer.begin_transaction().unwrap();
+        let future: RecordFuture = producer.send(ProducerRecord::new("foo", b"first".to_vec())).unwrap();
+        producer.commit_transaction().unwrap();
+        assert!(future.get().is_ok());
+
+        let keys: Vec<ApiKey> = api_keys(&requests);
+        assert_eq!(keys.iter().filter(|key| **key == ApiKey::AddPartitionsToTxn).count(), 2);
+        assert_eq!(keys.iter().filter(|key| **key == ApiKey::EndTxn).count(), 2);
+    }
+
+    #[test]
+    fn test_fenced_producers_cannot_go_on() {
+        let (broker, _) = transactional_broker(|request: &MockRequest| {
+            if request.api_key == ApiKey::EndTxn as i16 { ErrorCode::ProducerFenced } else { ErrorCode::None }
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), transactional_config()).unwrap();
+        producer.init_transactions().unwrap();
+        producer.begin_transaction().unwrap();
+        producer.send(ProducerRecord::new("foo", b"first".to_vec())).unwrap();
+        let fenced: ProducerError = ProducerError::ProducerFenced { transactional_id: String::from("txn") };
+        let error: anyhow::Error = producer.commit_transaction().unwrap_err();
+        assert_eq!(error.downcast_ref::<ProducerError>(), Some(&fenced));
+        let error: anyhow::Error = producer.abort_transaction().unwrap_err();
+        assert_eq!(error.downcast_ref::<ProducerError>(), Some(&fenced));
+    }
+
+    #[test]
+    fn test_failed_batches_abort_the_transaction_with_a_new_epoch() {
+        let (broker, requests) = transactional_broker(|request: &MockRequest| {
+            if request.api_key == ApiKey::Produce as i16 { ErrorCode::InvalidRecord } else { ErrorCode::None }
+        });
+        let cluster: ClusterMetadata = cluster("foo", &[(1, &broker)], &[1]);
+        let producer: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(cluster)), transactional_config()).unwrap();
+        producer.init_transactions().unwrap();
+        producer.begin_transaction().unwrap();
+        let future: RecordFuture = producer.send(ProducerRecord::new("foo", b"invalid".to_vec())).unwrap();
+        assert!(producer.commit_transaction().is_err());
+        assert!(future.get().is_err());
+        producer.abort_transaction().unwrap();
+        producer.begin_transaction().unwrap();
+
+        let requests: Vec<MockRequest> = requests.lock().unwrap().clone();
+        let end_request: EndTxnRequestV3 = requests.iter()
+            .find(|request| request.api_key == ApiKey::EndTxn as i16)
+            .unwrap()
+            .decode().unwrap();
+        assert!(!end_request.committed);
+        let epochs: Vec<i16> = requests.iter()
+            .filter(|request| request.api_key == ApiKey::InitProducerId as i16)
+            .map(|request| request.decode::<InitProducerIdRequestV3V4>().unwrap().producer_epoch)
+            .collect();
+        assert_eq!(epochs, vec![-1, 0]);
+
+        let idempotent: KafkaProducer = KafkaProducer::with_metadata_fetcher(Box::new(StaticFetcher(ClusterMetadata::default())), config()).unwrap();
+        let error: anyhow::Error = idempotent.begin_transaction().unwrap_err();
+        assert_eq!(error.downcast_ref::<ProducerError>(), Some(&ProducerError::NotTransactional));
+    }
+}
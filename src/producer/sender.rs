@@ -0,0 +1,370 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use anyhow::anyhow;
+use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+use tracing::{debug, warn};
+use crate::cluster::{ClusterMetadata, TopicPartition};
+use crate::connection_pool::ConnectionPool;
+use crate::metadata_cache::MetadataCache;
+use crate::producer::ProducerError;
+use crate::producer::accumulator::{BatchMetadata, ProducerBatch, ReadyCheck, RecordAccumulator};
+use crate::producer::transaction_manager::{is_fatal_error, ProducerIdAndEpoch, TransactionManager};
+use crate::producer::transactions::TransactionClient;
+use crate::protocol::ApiVersion;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::init_producer_id::{InitProducerIdRequestV3V4, InitProducerIdResponseV2V4};
+use crate::protocol::produce::{ACKS_NONE, PartitionDataV9, PartitionProduceResponseV9, ProduceRequestV3V8, ProduceRequestV9, ProduceResponseV8, ProduceResponseV9, TopicDataV9};
+use crate::protocol::records::CompactRecords;
+use crate::protocol::tags::TaggedFields;
+use crate::retry;
+use crate::retry::RetryPolicy;
+
+/// The longest the sender sleeps with nothing to do, so that expired batches are noticed.
+const MAX_IDLE_WAIT: Duration = Duration::from_millis(100);
+
+/// The Produce versions the sender can send. Requests are built as v9 and converted to v8 for
+/// brokers which don't support flexible versions of Produce.
+const PRODUCE_VERSIONS: [ApiVersion; 2] = [8, 9];
+
+/// Drains ready batches from the accumulator and sends them to the leaders of their partitions.
+/// Each leader is sent up to `max_in_flight_requests` Produce requests at a time on its connection.
+#[derive(Debug)]
+pub(crate) struct Sender {
+    pub accumulator: Arc<RecordAccumulator>,
+    pub metadata: Arc<MetadataCache>,
+    pub pool: Arc<ConnectionPool>,
+    /// The producer id and sequence numbers, if idempotence is enabled.
+    pub transaction_manager: Option<Arc<TransactionManager>>,
+    /// Adds partitions to the transaction, if the producer is transactional.
+    pub transactions: Option<Arc<TransactionClient>>,
+    /// `acks`
+    pub acks: i16,
+    /// How long the broker may wait for replication before answering, from `request.timeout.ms`.
+    pub timeout: Duration,
+    /// `max.request.size`
+    pub max_request_size: usize,
+    /// `max.in.flight.requests.per.connection`
+    pub max_in_flight_requests: usize,
+    pub retry: RetryPolicy
+}
+
+impl Sender {
+    /// Sends batches until the producer is closed and every batch has been sent or has failed.
+    pub fn run(&self) {
+        while !self.accumulator.is_closed_and_empty() {
+            self.run_once();
+        }
+        debug!("The producer's sender thread has finished");
+    }
+
+    fn run_once(&self) {
+        if let Some(expired) = self.accumulator.abort_expired_batches() {
+            if let Some(transaction_manager) = &self.transaction_manager {
+                transaction_manager.batch_failed(expired.had_sequences, expired.error);
+            }
+        }
+        if let Some(transaction_manager) = &self.transaction_manager {
+            if transaction_manager.producer_id_and_epoch().is_none() {
+                if transaction_manager.is_transactional() {
+                    // init_transactions gets the producer id
+                    self.accumulator.wait_for_change(MAX_IDLE_WAIT);
+                } else if let Err(e) = self.init_producer_id(transaction_manager) {
+                    warn!("Failed to get a producer id: {:#}", e);
+                    thread::sleep(self.retry.retry_backoff);
+                }
+                return;
+            }
+        }
+        self.add_partitions_to_transaction();
+        let cluster: Arc<ClusterMetadata> = self.metadata.cluster();
+        let mut requests: HashMap<i32, Vec<Vec<ProducerBatch>>> = HashMap::new();
+        // each round drains the next batch of every ready partition into another request
+        for _ in 0..self.max_in_flight_requests {
+            let mut ready: ReadyCheck = self.accumulator.ready(&cluster);
+            if ready.unknown_leaders {
+                self.metadata.request_update();
+            }
+            // the batches of a throttled leader wait in the accumulator until it stops throttling
+            ready.ready_nodes.retain(|node_id| !self.pool.is_throttled(*node_id));
+            let drained: HashMap<i32, Vec<ProducerBatch>> = self.accumulator.drain(&cluster, &ready.ready_nodes, self.max_request_size);
+            if drained.is_empty() {
+                if requests.is_empty() {
+                    let mut wait: Duration = ready.next_ready_check.unwrap_or(MAX_IDLE_WAIT).min(MAX_IDLE_WAIT);
+                    if ready.unknown_leaders {
+                        wait = wait.min(self.retry.retry_backoff);
+                    }
+                    self.accumulator.wait_for_change(wait);
+                    return;
+                }
+                break;
+            }
+            for (node_id, batches) in drained {
+                requests.entry(node_id).or_default().push(batches);
+            }
+        }
+        // the leaders are sent to at the same time
+        thread::scope(|scope| {
+            for (node_id, node_requests) in requests {
+                scope.spawn(move || self.send_requests(node_id, node_requests));
+            }
+        });
+    }
+
+    /// Gets a producer id, or bumps the epoch of the current one after a reset (KIP-360).
+    fn init_producer_id(&self, transaction_manager: &TransactionManager) -> anyhow::Result<()> {
+        let request: InitProducerIdRequestV3V4 = transaction_manager.init_producer_id_request();
+        let (_, response) = self.pool.send_to_any::<InitProducerIdRequestV3V4, InitProducerIdResponseV2V4>(request)?;
+        if response.error_code != ErrorCode::None {
+            return Err(anyhow::Error::new(response.error_code));
+        }
+        transaction_manager.set_producer_id_and_epoch(ProducerIdAndEpoch { producer_id: response.producer_id, epoch: response.producer_epoch });
+        Ok(())
+    }
+
+    /// Adds the partitions which records were sent to in the current transaction, before their
+    /// batches are drained. Failures are retried by the next call.
+    fn add_partitions_to_transaction(&self) {
+        let (Some(transaction_manager), Some(transactions)) = (&self.transaction_manager, &self.transactions) else {
+            return;
+        };
+        let partitions: Vec<TopicPartition> = transaction_manager.pending_partitions();
+        if partitions.is_empty() || transaction_manager.error().is_some() {
+            return;
+        }
+        let policy: RetryPolicy = RetryPolicy { retries: 0, ..self.retry.clone() };
+        if let Err(e) = transactions.add_partitions(&partitions, &policy) {
+            warn!("Failed to add partitions to the transaction: {:#}", e);
+            thread::sleep(self.retry.retry_backoff);
+        }
+    }
+
+    /// Sends a leader's requests one after the other on its connection, before reading any response.
+    fn send_requests(&self, node_id: i32, node_requests: Vec<Vec<ProducerBatch>>) {
+        let (requests, sent): (Vec<ProduceRequestV9>, Vec<Vec<ProducerBatch>>) = node_requests.into_iter()
+            .filter_map(|batches| self.build_request(batches))
+            .unzip();
+        if requests.is_empty() {
+            return;
+        }
+        let version: ApiVersion = match self.produce_version(node_id) {
+            Ok(version) => version,
+            Err(e) => {
+                self.fail_request(node_id, sent.into_iter().flatten().collect(), e);
+                return;
+            }
+        };
+        if self.acks == ACKS_NONE {
+            for (request, batches) in requests.into_iter().zip(sent) {
+                let result: anyhow::Result<()> = match version {
+                    9 => self.pool.send_without_response(node_id, request),
+                    _ => self.pool.send_without_response(node_id, ProduceRequestV3V8::from(request))
+                };
+                match result {
+                    // the broker doesn't say where the records went
+                    Ok(()) => for batch in batches {
+                        self.accumulator.complete(batch, Ok(BatchMetadata { base_offset: -1, log_append_time_ms: -1 }));
+                    },
+                    Err(e) => self.fail_request(node_id, batches, e)
+                }
+            }
+            return;
+        }
+        let responses: anyhow::Result<Vec<ProduceResponseV9>> = match version {
+            9 => self.pool.send_pipelined::<ProduceRequestV9, ProduceResponseV9>(node_id, requests),
+            _ => self.pool.send_pipelined::<ProduceRequestV3V8, ProduceResponseV8>(node_id, requests.into_iter().map(ProduceRequestV3V8::from).collect())
+                .map(|responses| responses.into_iter().map(ProduceResponseV9::from).collect())
+        };
+        match responses {
+            Ok(responses) => for (response, batches) in responses.into_iter().zip(sent) {
+                self.handle_response(batches, response);
+            },
+            Err(e) => self.fail_request(node_id, sent.into_iter().flatten().collect(), e)
+        }
+    }
+
+    /// The newest Produce version both the sender and the leader support.
+    fn produce_version(&self, node_id: i32) -> anyhow::Result<ApiVersion> {
+        self.pool.api_versions(node_id)?
+            .highest_common_version(ApiKey::Produce, &PRODUCE_VERSIONS)
+            .ok_or_else(|| anyhow!("Node {} supports none of the Produce versions this client can send", node_id))
+    }
+
+    /// Builds a Produce request from batches for one leader, giving the batches of an idempotent
+    /// producer their sequence numbers. Returns `None` if none of the batches can be sent.
+    fn build_request(&self, batches: Vec<ProducerBatch>) -> Option<(ProduceRequestV9, Vec<ProducerBatch>)> {
+        let mut sent: Vec<ProducerBatch> = Vec::new();
+        let mut topic_data: BTreeMap<String, Vec<PartitionDataV9>> = BTreeMap::new();
+        for mut batch in batches {
+            if let Some(transaction_manager) = &self.transaction_manager {
+                if let Some(error) = transaction_manager.error() {
+                    // the transaction has to be aborted, so nothing more is sent in it
+                    self.accumulator.complete(batch, Err(anyhow::Error::new(error)));
+                    continue;
+                }
+                if !transaction_manager.is_partition_added(&batch.topic_partition) {
+                    self.accumulator.reenqueue(batch);
+                    continue;
+                }
+            }
+            if !self.assign_sequence(&mut batch) {
+                // the producer id was reset by another leader's response, so wait for a new one
+                self.accumulator.reenqueue(batch);
+                continue;
+            }
+            match batch.record_batch() {
+                Ok(record_batch) => {
+                    topic_data.entry(batch.topic_partition.topic.clone()).or_default().push(PartitionDataV9 {
+                        index: batch.topic_partition.partition,
+                        records: CompactRecords(Some(vec![record_batch])),
+                        tag_buffer: TaggedFields::new()
+                    });
+                    batch.attempts += 1;
+                    sent.push(batch);
+                },
+                Err(e) => self.fail(batch, e)
+            }
+        }
+        if sent.is_empty() {
+            return None;
+        }
+        let transactional_id: Option<String> = self.transaction_manager.as_ref()
+            .and_then(|transaction_manager| transaction_manager.transactional_id().map(String::from));
+        let request: ProduceRequestV9 = ProduceRequestV9 {
+            transactional_id: CompactNullableString(transactional_id),
+            acks: self.acks,
+            timeout_ms: self.timeout.as_millis().min(i32::MAX as u128) as i32,
+            topic_data: CompactArray(topic_data.into_iter()
+                .map(|(topic, partition_data)| TopicDataV9 {
+                    name: CompactString(topic),
+                    partition_data: CompactArray(partition_data),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            tag_buffer: TaggedFields::new()
+        };
+        Some((request, sent))
+    }
+
+    /// Gives a batch sequence numbers from the current producer id and epoch, unless it already has
+    /// them. A retried batch keeps its sequence numbers, so that the broker can recognize it as a
+    /// duplicate if the earlier attempt was written. Returns false if there is no producer id.
+    fn assign_sequence(&self, batch: &mut ProducerBatch) -> bool {
+        let Some(transaction_manager) = &self.transaction_manager else {
+            return true;
+        };
+        let current: Option<ProducerIdAndEpoch> = transaction_manager.producer_id_and_epoch();
+        if current.is_some() && batch.producer_state().map(|(producer, _)| producer) == current {
+            return true;
+        }
+        let Some((producer, base_sequence)) = transaction_manager.assign_sequence(&batch.topic_partition, batch.record_count()) else {
+            return false;
+        };
+        if let Err(e) = batch.set_producer_state(producer, base_sequence) {
+            warn!("Failed to set the sequence of a batch for {:?}: {:#}", batch.topic_partition, e);
+            return false;
+        }
+        true
+    }
+
+    fn handle_response(&self, batches: Vec<ProducerBatch>, response: ProduceResponseV9) {
+        let mut partition_responses: HashMap<TopicPartition, PartitionProduceResponseV9> = HashMap::new();
+        for topic in response.responses.0 {
+            for partition in topic.partition_responses.0 {
+                partition_responses.insert(TopicPartition::new(&topic.name.0, partition.index), partition);
+            }
+        }
+        for batch in batches {
+            let Some(partition) = partition_responses.remove(&batch.topic_partition) else {
+                let error: anyhow::Error = anyhow!("The Produce response had no result for {:?}", batch.topic_partition);
+                self.fail(batch, error);
+                continue;
+            };
+            if partition.error_code == ErrorCode::DuplicateSequenceNumber {
+                // an earlier attempt was written, but its response was lost
+                debug!("The batch for {:?} was already written", batch.topic_partition);
+            }
+            if matches!(partition.error_code, ErrorCode::None | ErrorCode::DuplicateSequenceNumber) {
+                if let (Some(transaction_manager), Some((producer, base_sequence))) = (&self.transaction_manager, batch.producer_state()) {
+                    transaction_manager.complete(&batch.topic_partition, producer, base_sequence, batch.record_count());
+                }
+                let metadata: BatchMetadata = BatchMetadata {
+                    base_offset: partition.base_offset,
+                    log_append_time_ms: partition.log_append_time_ms
+                };
+                self.accumulator.complete(batch, Ok(metadata));
+                continue;
+            }
+            self.metadata.handle_error_code(&partition.error_code);
+            if let Some(transaction_manager) = &self.transaction_manager {
+                if let Some(transactional_id) = transaction_manager.transactional_id().filter(|_| is_fatal_error(&partition.error_code)) {
+                    transaction_manager.fatal_error(ProducerError::ProducerFenced { transactional_id: String::from(transactional_id) });
+                }
+            }
+            let retriable: bool = self.is_retriable(&batch, &partition.error_code);
+            let mut error: anyhow::Error = anyhow::Error::new(partition.error_code);
+            if let Some(message) = partition.error_message.0 {
+                error = error.context(message);
+            }
+            self.retry_or_fail(batch, error, retriable);
+        }
+    }
+
+    /// Whether a batch which failed with an error code may be sent again. An idempotent producer's
+    /// batch which is out of order was sent behind a batch which failed and will be retried, so it
+    /// can be retried after that one. If it was next in order, the broker lost track of the
+    /// producer, as it does when it has an unknown producer id, and the epoch is bumped so that
+    /// sequences start again from 0. A transaction can't go on with a new epoch, so it fails.
+    fn is_retriable(&self, batch: &ProducerBatch, error_code: &ErrorCode) -> bool {
+        let (Some(transaction_manager), Some((producer, base_sequence))) = (&self.transaction_manager, batch.producer_state()) else {
+            return error_code.is_retriable();
+        };
+        match error_code {
+            ErrorCode::OutOfOrderSequenceNumber => {
+                !transaction_manager.is_next_sequence(&batch.topic_partition, producer, base_sequence)
+            },
+            ErrorCode::UnknownProducerId => {
+                if transaction_manager.is_transactional() {
+                    return false;
+                }
+                transaction_manager.reset_producer_id();
+                true
+            },
+            _ => error_code.is_retriable()
+        }
+    }
+
+    /// Handles a request which failed as a whole, such as when the connection was lost.
+    fn fail_request(&self, node_id: i32, batches: Vec<ProducerBatch>, error: anyhow::Error) {
+        // the leader may have moved
+        self.metadata.request_update();
+        let retriable: bool = retry::is_retriable(&error);
+        for batch in batches {
+            self.retry_or_fail(batch, anyhow!("Produce request to node {} failed: {:#}", node_id, error), retriable);
+        }
+    }
+
+    fn retry_or_fail(&self, batch: ProducerBatch, error: anyhow::Error, retriable: bool) {
+        if retriable && batch.attempts <= self.retry.retries && batch.age() < self.retry.delivery_timeout {
+            debug!("Retrying a batch for {:?} after a retriable error: {:#}", batch.topic_partition, error);
+            self.accumulator.reenqueue(batch);
+        } else {
+            self.fail(batch, error);
+        }
+    }
+
+    /// Fails a batch for good. If it had sequence numbers, its partition now has a gap which the
+    /// broker won't accept later batches after, so the producer id is reset, or the transaction
+    /// fails and the epoch is bumped when it is aborted.
+    fn fail(&self, batch: ProducerBatch, error: anyhow::Error) {
+        let Some(transaction_manager) = &self.transaction_manager else {
+            self.accumulator.complete(batch, Err(error));
+            return;
+        };
+        let error: ProducerError = ProducerError::from_send_error(error, &batch.topic_partition);
+        transaction_manager.batch_failed(batch.producer_state().is_some(), error.clone());
+        self.accumulator.complete(batch, Err(anyhow::Error::new(error)));
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use kafka_encode::primitives::CompactNullableString;
+use tracing::{debug, info, warn};
+use crate::cluster::TopicPartition;
+use crate::producer::ProducerError;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::init_producer_id::InitProducerIdRequestV3V4;
+use crate::protocol::records::{NO_PRODUCER_EPOCH, NO_PRODUCER_ID};
+use crate::protocol::tags::TaggedFields;
+
+/// The id and epoch which brokers use to recognize the batches of one producer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct ProducerIdAndEpoch {
+    pub producer_id: i64,
+    pub epoch: i16
+}
+
+impl ProducerIdAndEpoch {
+    pub const NONE: ProducerIdAndEpoch = ProducerIdAndEpoch { producer_id: NO_PRODUCER_ID, epoch: NO_PRODUCER_EPOCH };
+}
+
+/// The sequence numbers of one partition, which start from 0 for every producer id and epoch.
+#[derive(Debug, Clone, Copy)]
+struct PartitionSequences {
+    next_sequence: i32,
+    /// The sequence of the last record the broker acknowledged, or -1.
+    last_acked_sequence: i32
+}
+
+impl Default for PartitionSequences {
+    fn default() -> Self {
+        PartitionSequences { next_sequence: 0, last_acked_sequence: -1 }
+    }
+}
+
+/// Where a transactional producer is in its transactions.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum TransactionStatus {
+    Uninitialized,
+    Ready,
+    InTransaction,
+    Committing,
+    Aborting,
+    /// Something in the transaction failed, so it can only be aborted.
+    AbortableError(ProducerError),
+    /// The producer was fenced or isn't authorized, and can only be closed.
+    FatalError(ProducerError)
+}
+
+impl TransactionStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            TransactionStatus::Uninitialized => "uninitialized",
+            TransactionStatus::Ready => "ready",
+            TransactionStatus::InTransaction => "in a transaction",
+            TransactionStatus::Committing => "committing",
+            TransactionStatus::Aborting => "aborting",
+            TransactionStatus::AbortableError(_) => "failed and must be aborted",
+            TransactionStatus::FatalError(_) => "failed"
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TransactionState {
+    /// `None` until InitProducerId succeeds, and again when the epoch has to be bumped.
+    producer: Option<ProducerIdAndEpoch>,
+    /// The id and epoch to bump, after a reset (KIP-360).
+    previous_producer: ProducerIdAndEpoch,
+    sequences: HashMap<TopicPartition, PartitionSequences>,
+    status: TransactionStatus,
+    /// Partitions which records were sent to, but which haven't been added to the transaction yet.
+    new_partitions: BTreeSet<TopicPartition>,
+    added_partitions: HashSet<TopicPartition>,
+    /// Whether consumer offsets were added to the transaction.
+    offsets_added: bool,
+    /// Whether a batch with sequence numbers failed in the transaction, which leaves a gap that
+    /// only a new epoch gets past.
+    epoch_bump_required: bool
+}
+
+/// Tracks the producer id and the per-partition sequence numbers of an idempotent producer, so
+/// that brokers can discard retried batches they already wrote and reject batches which arrive
+/// out of order. A transactional producer also tracks its transaction here.
+#[derive(Debug)]
+pub(crate) struct TransactionManager {
+    /// `transactional.id`, if the producer is transactional.
+    transactional_id: Option<String>,
+    /// `transaction.timeout.ms`
+    transaction_timeout: Duration,
+    state: Mutex<TransactionState>
+}
+
+impl TransactionManager {
+    pub fn new(transactional_id: Option<String>, transaction_timeout: Duration) -> Self {
+        TransactionManager {
+            transactional_id,
+            transaction_timeout,
+            state: Mutex::new(TransactionState {
+                producer: None,
+                previous_producer: ProducerIdAndEpoch::NONE,
+                sequences: HashMap::new(),
+                status: TransactionStatus::Uninitialized,
+                new_partitions: BTreeSet::new(),
+                added_partitions: HashSet::new(),
+                offsets_added: false,
+                epoch_bump_required: false
+            })
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TransactionState> {
+        self.state.lock().expect("Transaction manager lock was poisoned")
+    }
+
+    pub fn transactional_id(&self) -> Option<&str> {
+        self.transactional_id.as_deref()
+    }
+
+    pub fn is_transactional(&self) -> bool {
+        self.transactional_id.is_some()
+    }
+
+    pub fn producer_id_and_epoch(&self) -> Option<ProducerIdAndEpoch> {
+        self.lock().producer
+    }
+
+    /// An InitProducerId request which bumps the epoch of the previous producer id, if there is one
+    /// (KIP-360).
+    pub fn init_producer_id_request(&self) -> InitProducerIdRequestV3V4 {
+        let previous: ProducerIdAndEpoch = self.lock().previous_producer;
+        InitProducerIdRequestV3V4 {
+            transactional_id: CompactNullableString(self.transactional_id.clone()),
+            transaction_timeout_ms: self.transaction_timeout.as_millis().min(i32::MAX as u128) as i32,
+            producer_id: previous.producer_id,
+            producer_epoch: previous.epoch,
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    /// Starts using a new producer id or epoch, whose sequence numbers start again from 0.
+    pub fn set_producer_id_and_epoch(&self, producer: ProducerIdAndEpoch) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        info!("Using producer id {} with epoch {}", producer.producer_id, producer.epoch);
+        state.producer = Some(producer);
+        state.previous_producer = producer;
+        state.sequences.clear();
+        state.epoch_bump_required = false;
+    }
+
+    /// Gives up the current epoch after a batch with a sequence number failed for good, which left
+    /// a gap the broker would never accept batches after. Batches which already have sequence
+    /// numbers from the old epoch are given new ones when they are next sent.
+    pub fn reset_producer_id(&self) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        if state.producer.take().is_some() {
+            debug!("Resetting the producer id and sequence numbers");
+        }
+    }
+
+    /// Handles a batch which failed for good. An idempotent producer starts a new epoch if the
+    /// batch had sequence numbers, while a transaction can only be aborted, which bumps the epoch.
+    pub fn batch_failed(&self, had_sequence: bool, error: ProducerError) {
+        if !self.is_transactional() {
+            if had_sequence {
+                self.reset_producer_id();
+            }
+            return;
+        }
+        if had_sequence {
+            self.lock().epoch_bump_required = true;
+        }
+        self.abortable_error(error);
+    }
+
+    /// Assigns sequence numbers to the records of a batch which is about to be sent, returning
+    /// the producer the sequences belong to and the batch's base sequence.
+    pub fn assign_sequence(&self, topic_partition: &TopicPartition, record_count: usize) -> Option<(ProducerIdAndEpoch, i32)> {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        let producer: ProducerIdAndEpoch = state.producer?;
+        let sequences: &mut PartitionSequences = state.sequences.entry(topic_partition.clone()).or_default();
+        let base_sequence: i32 = sequences.next_sequence;
+        sequences.next_sequence = increment_sequence(base_sequence, record_count as i32);
+        Some((producer, base_sequence))
+    }
+
+    /// Records that the broker wrote a batch, unless its producer has been replaced since.
+    pub fn complete(&self, topic_partition: &TopicPartition, producer: ProducerIdAndEpoch, base_sequence: i32, record_count: usize) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        if state.producer != Some(producer) {
+            return;
+        }
+        let sequences: &mut PartitionSequences = state.sequences.entry(topic_partition.clone()).or_default();
+        sequences.last_acked_sequence = increment_sequence(base_sequence, record_count as i32 - 1);
+    }
+
+    /// Whether a batch is the next one the broker expects for its partition. A batch which isn't
+    /// was sent behind one which failed and is being retried, so it can be retried too.
+    pub fn is_next_sequence(&self, topic_partition: &TopicPartition, producer: ProducerIdAndEpoch, base_sequence: i32) -> bool {
+        let state: MutexGuard<TransactionState> = self.lock();
+        if state.producer != Some(producer) {
+            return false;
+        }
+        let last_acked_sequence: i32 = state.sequences.get(topic_partition).map_or(-1, |sequences| sequences.last_acked_sequence);
+        base_sequence == increment_sequence(last_acked_sequence, 1)
+    }
+
+    fn invalid_state(operation: &'static str, status: &TransactionStatus) -> ProducerError {
+        match status {
+            TransactionStatus::AbortableError(error) | TransactionStatus::FatalError(error) => error.clone(),
+            _ => ProducerError::InvalidTransactionState { operation, state: status.name() }
+        }
+    }
+
+    /// The transactional producer got its producer id and can start transactions.
+    pub fn initialize(&self, producer: ProducerIdAndEpoch) -> Result<(), ProducerError> {
+        self.set_producer_id_and_epoch(producer);
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        match state.status {
+            TransactionStatus::Uninitialized => {
+                state.status = TransactionStatus::Ready;
+                Ok(())
+            },
+            ref status => Err(TransactionManager::invalid_state("initialize transactions", status))
+        }
+    }
+
+    pub fn check_can_initialize(&self) -> Result<(), ProducerError> {
+        match &self.lock().status {
+            TransactionStatus::Uninitialized => Ok(()),
+            status => Err(TransactionManager::invalid_state("initialize transactions", status))
+        }
+    }
+
+    pub fn begin_transaction(&self) -> Result<(), ProducerError> {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        match state.status {
+            TransactionStatus::Ready => {
+                state.status = TransactionStatus::InTransaction;
+                Ok(())
+            },
+            ref status => Err(TransactionManager::invalid_state("begin a transaction", status))
+        }
+    }
+
+    /// Fails unless a transaction has begun and nothing in it has failed.
+    pub fn check_in_transaction(&self, operation: &'static str) -> Result<(), ProducerError> {
+        match &self.lock().status {
+            TransactionStatus::InTransaction => Ok(()),
+            status => Err(TransactionManager::invalid_state(operation, status))
+        }
+    }
+
+    /// Notes a partition a record is being sent to, which is added to the transaction before the
+    /// record is sent. Producers which aren't transactional can always send.
+    pub fn maybe_add_partition(&self, topic_partition: &TopicPartition) -> Result<(), ProducerError> {
+        if !self.is_transactional() {
+            return Ok(());
+        }
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        match &state.status {
+            TransactionStatus::InTransaction => {},
+            status => return Err(TransactionManager::invalid_state("send", status))
+        }
+        if !state.added_partitions.contains(topic_partition) {
+            state.new_partitions.insert(topic_partition.clone());
+        }
+        Ok(())
+    }
+
+    /// Partitions which need to be added to the transaction before their batches can be sent.
+    pub fn pending_partitions(&self) -> Vec<TopicPartition> {
+        self.lock().new_partitions.iter().cloned().collect()
+    }
+
+    pub fn partitions_added(&self, partitions: &[TopicPartition]) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        for partition in partitions {
+            state.new_partitions.remove(partition);
+            state.added_partitions.insert(partition.clone());
+        }
+    }
+
+    /// Whether batches for a partition can be sent, which a transactional producer can only do
+    /// once the partition is in the transaction.
+    pub fn is_partition_added(&self, topic_partition: &TopicPartition) -> bool {
+        !self.is_transactional() || self.lock().added_partitions.contains(topic_partition)
+    }
+
+    pub fn offsets_added(&self) {
+        self.lock().offsets_added = true;
+    }
+
+    /// Whether the transaction coordinator knows about the transaction, and so has to end it.
+    pub fn has_partitions_or_offsets(&self) -> bool {
+        let state: MutexGuard<TransactionState> = self.lock();
+        !state.added_partitions.is_empty() || state.offsets_added
+    }
+
+    /// Starts committing, which may be retried after it failed. A transaction with an error has
+    /// to be aborted instead.
+    pub fn begin_commit(&self) -> Result<(), ProducerError> {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        match state.status {
+            TransactionStatus::InTransaction | TransactionStatus::Committing => {
+                state.status = TransactionStatus::Committing;
+                Ok(())
+            },
+            ref status => Err(TransactionManager::invalid_state("commit a transaction", status))
+        }
+    }
+
+    pub fn begin_abort(&self) -> Result<(), ProducerError> {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        match state.status {
+            TransactionStatus::InTransaction | TransactionStatus::AbortableError(_) | TransactionStatus::Aborting => {
+                state.status = TransactionStatus::Aborting;
+                Ok(())
+            },
+            ref status => Err(TransactionManager::invalid_state("abort a transaction", status))
+        }
+    }
+
+    /// The transaction was committed or aborted, and the next one can begin.
+    pub fn complete_transaction(&self) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        state.status = TransactionStatus::Ready;
+        state.new_partitions.clear();
+        state.added_partitions.clear();
+        state.offsets_added = false;
+    }
+
+    /// Whether the epoch has to be bumped before the next transaction, because a batch with
+    /// sequence numbers failed in the one which was aborted.
+    pub fn is_epoch_bump_required(&self) -> bool {
+        self.lock().epoch_bump_required
+    }
+
+    /// The error which the current transaction failed with, if it has.
+    pub fn error(&self) -> Option<ProducerError> {
+        match &self.lock().status {
+            TransactionStatus::AbortableError(error) | TransactionStatus::FatalError(error) => Some(error.clone()),
+            _ => None
+        }
+    }
+
+    /// Fails the current transaction, which can then only be aborted.
+    pub fn abortable_error(&self, error: ProducerError) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        if matches!(state.status, TransactionStatus::InTransaction | TransactionStatus::Committing) {
+            warn!("The transaction must be aborted: {}", error);
+            state.status = TransactionStatus::AbortableError(error);
+        }
+    }
+
+    /// Fails the producer, which can then only be closed.
+    pub fn fatal_error(&self, error: ProducerError) {
+        let mut state: MutexGuard<TransactionState> = self.lock();
+        if !matches!(state.status, TransactionStatus::FatalError(_)) {
+            warn!("The transactional producer failed: {}", error);
+            state.status = TransactionStatus::FatalError(error);
+        }
+    }
+}
+
+/// Whether an error means that another producer with the same transactional id has taken over, or
+/// that this one may not use it, so that the producer can't go on.
+pub(crate) fn is_fatal_error(error_code: &ErrorCode) -> bool {
+    matches!(error_code, ErrorCode::ProducerFenced | ErrorCode::InvalidProducerEpoch | ErrorCode::TransactionalIdAuthorizationFailed)
+}
+
+/// Sequence numbers wrap around to 0 after `i32::MAX`, as brokers expect.
+fn increment_sequence(sequence: i32, increment: i32) -> i32 {
+    if sequence > i32::MAX - increment {
+        increment - (i32::MAX - sequence) - 1
+    } else {
+        sequence + increment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::cluster::TopicPartition;
+    use crate::producer::ProducerError;
+    use crate::producer::transaction_manager::{increment_sequence, ProducerIdAndEpoch, TransactionManager};
+    use crate::protocol::init_producer_id::InitProducerIdRequestV3V4;
+
+    #[test]
+    fn test_sequences_are_assigned_per_partition() {
+        let transaction_manager: TransactionManager = TransactionManager::new(None, Duration::from_secs(60));
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        assert_eq!(transaction_manager.assign_sequence(&foo_0, 3), None);
+
+        let producer: ProducerIdAndEpoch = ProducerIdAndEpoch { producer_id: 7, epoch: 0 };
+        transaction_manager.set_producer_id_and_epoch(producer);
+        assert_eq!(transaction_manager.assign_sequence(&foo_0, 3), Some((producer, 0)));
+        assert_eq!(transaction_manager.assign_sequence(&foo_0, 2), Some((producer, 3)));
+        assert_eq!(transaction_manager.assign_sequence(&TopicPartition::new("foo", 1), 1), Some((producer, 0)));
+
+        assert!(transaction_manager.is_next_sequence(&foo_0, producer, 0));
+        assert!(!transaction_manager.is_next_sequence(&foo_0, producer, 3));
+        transaction_manager.complete(&foo_0, producer, 0, 3);
+        assert!(transaction_manager.is_next_sequence(&foo_0, producer, 3));
+    }
+
+    #[test]
+    fn test_reset_bumps_the_epoch_and_restarts_sequences() {
+        let transaction_manager: TransactionManager = TransactionManager::new(None, Duration::from_secs(60));
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        let producer: ProducerIdAndEpoch = ProducerIdAndEpoch { producer_id: 7, epoch: 0 };
+        transaction_manager.set_producer_id_and_epoch(producer);
+        transaction_manager.assign_sequence(&foo_0, 5);
+
+        transaction_manager.reset_producer_id();
+        assert_eq!(transaction_manager.producer_id_and_epoch(), None);
+        // the next InitProducerId bumps the epoch of the old producer id
+        let request: InitProducerIdRequestV3V4 = transaction_manager.init_producer_id_request();
+        assert_eq!((request.producer_id, request.producer_epoch), (7, 0));
+        // batches of the old epoch don't move the new epoch's sequences
+        let bumped: ProducerIdAndEpoch = ProducerIdAndEpoch { producer_id: 7, epoch: 1 };
+        transaction_manager.set_producer_id_and_epoch(bumped);
+        transaction_manager.complete(&foo_0, producer, 0, 5);
+        assert_eq!(transaction_manager.assign_sequence(&foo_0, 1), Some((bumped, 0)));
+    }
+
+    #[test]
+    fn test_transactions_move_through_their_states() {
+        let transaction_manager: TransactionManager = TransactionManager::new(Some(String::from("txn")), Duration::from_secs(60));
+        let foo_0: TopicPartition = TopicPartition::new("foo", 0);
+        assert!(matches!(transaction_manager.begin_transaction(), Err(ProducerError::InvalidTransactionState { .. })));
+        transaction_manager.initialize(ProducerIdAndEpoch { producer_id: 7, epoch: 0 }).unwrap();
+        assert!(transaction_manager.maybe_add_partition(&foo_0).is_err());
+
+        transaction_manager.begin_transaction().unwrap();
+        transaction_manager.maybe_add_partition(&foo_0).unwrap();
+        assert_eq!(transaction_manager.pending_partitions(), vec![foo_0.clone()]);
+        assert!(!transaction_manager.is_partition_added(&foo_0));
+        transaction_manager.partitions_added(std::slice::from_ref(&foo_0));
+        assert!(transaction_manager.pending_partitions().is_empty());
+        assert!(transaction_manager.is_partition_added(&foo_0));
+        assert!(transaction_manager.has_partitions_or_offsets());
+
+        // a batch with a sequence failed, so the transaction has to be aborted with a new epoch
+        let error: ProducerError = ProducerError::Closed;
+        transaction_manager.batch_failed(true, error.clone());
+        assert_eq!(transaction_manager.error(), Some(error.clone()));
+        assert_eq!(transaction_manager.begin_commit(), Err(error));
+        transaction_manager.begin_abort().unwrap();
+        assert!(transaction_manager.is_epoch_bump_required());
+        transaction_manager.complete_transaction();
+        assert!(!transaction_manager.has_partitions_or_offsets());
+        transaction_manager.begin_transaction().unwrap();
+
+        transaction_manager.fatal_error(ProducerError::ProducerFenced { transactional_id: String::from("txn") });
+        assert!(matches!(transaction_manager.begin_abort(), Err(ProducerError::ProducerFenced { .. })));
+    }
+
+    #[test]
+    fn test_sequences_wrap_around() {
+        assert_eq!(increment_sequence(5, 3), 8);
+        assert_eq!(increment_sequence(i32::MAX, 1), 0);
+        assert_eq!(increment_sequence(i32::MAX - 1, 3), 1);
+    }
+}
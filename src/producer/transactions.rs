@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use anyhow::Result;
+use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+use crate::cluster::TopicPartition;
+use crate::connection_pool::ConnectionPool;
+use crate::coordinator::{ConsumerGroupMetadata, Coordinator, OffsetAndMetadata};
+use crate::producer::ProducerError;
+use crate::producer::transaction_manager::{is_fatal_error, ProducerIdAndEpoch, TransactionManager};
+use crate::protocol::add_offsets_to_txn::{AddOffsetsToTxnRequestV3, AddOffsetsToTxnResponseV3};
+use crate::protocol::add_partitions_to_txn::{AddPartitionsToTxnRequestV3, AddPartitionsToTxnResponseV3, AddPartitionsToTxnTopicV3};
+use crate::protocol::end_txn::{EndTxnRequestV3, EndTxnResponseV3};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::find_coordinator::{COORDINATOR_TYPE_GROUP, COORDINATOR_TYPE_TRANSACTION};
+use crate::protocol::init_producer_id::{InitProducerIdRequestV3V4, InitProducerIdResponseV2V4};
+use crate::protocol::tags::TaggedFields;
+use crate::protocol::txn_offset_commit::{TxnOffsetCommitRequestPartitionV3, TxnOffsetCommitRequestTopicV3, TxnOffsetCommitRequestV3, TxnOffsetCommitResponseV3};
+use crate::protocol::{KafkaRequest, KafkaResponse};
+use crate::retry;
+use crate::retry::RetryPolicy;
+
+/// Sends a transactional producer's requests to its transaction coordinator, and its offsets to
+/// the coordinators of consumer groups. Errors which fence the producer make it fail for good,
+/// and other errors which can't be retried fail the current transaction.
+#[derive(Debug)]
+pub(crate) struct TransactionClient {
+    pool: Arc<ConnectionPool>,
+    transaction_manager: Arc<TransactionManager>,
+    transactional_id: String,
+    coordinator: Coordinator
+}
+
+impl TransactionClient {
+    pub fn new(pool: Arc<ConnectionPool>, transaction_manager: Arc<TransactionManager>, transactional_id: &str) -> Self {
+        TransactionClient {
+            pool,
+            transaction_manager,
+            transactional_id: String::from(transactional_id),
+            coordinator: Coordinator::new(COORDINATOR_TYPE_TRANSACTION, transactional_id)
+        }
+    }
+
+    fn producer(&self) -> Result<ProducerIdAndEpoch> {
+        self.transaction_manager.producer_id_and_epoch()
+            .ok_or_else(|| anyhow::Error::new(ProducerError::InvalidTransactionState { operation: "use a transaction", state: "uninitialized" }))
+    }
+
+    /// Gets the producer id and epoch for the transactional id, which fences any older producer
+    /// with the same id and aborts its open transaction.
+    pub fn init_producer_id(&self, policy: &RetryPolicy) -> Result<ProducerIdAndEpoch> {
+        let request: InitProducerIdRequestV3V4 = self.transaction_manager.init_producer_id_request();
+        let response: InitProducerIdResponseV2V4 = self.send(&self.coordinator, request, policy, |response: &InitProducerIdResponseV2V4| response.error_code.clone())?;
+        Ok(ProducerIdAndEpoch { producer_id: response.producer_id, epoch: response.producer_epoch })
+    }
+
+    /// Adds partitions to the transaction, which has to happen before records are sent to them.
+    pub fn add_partitions(&self, partitions: &[TopicPartition], policy: &RetryPolicy) -> Result<()> {
+        let producer: ProducerIdAndEpoch = self.producer()?;
+        let mut topics: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+        for partition in partitions {
+            topics.entry(partition.topic.clone()).or_default().push(partition.partition);
+        }
+        let request: AddPartitionsToTxnRequestV3 = AddPartitionsToTxnRequestV3 {
+            transactional_id: CompactString(self.transactional_id.clone()),
+            producer_id: producer.producer_id,
+            producer_epoch: producer.epoch,
+            topics: CompactArray(topics.into_iter()
+                .map(|(name, partitions)| AddPartitionsToTxnTopicV3 {
+                    name: CompactString(name),
+                    partitions: CompactArray(partitions),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            tag_buffer: TaggedFields::new()
+        };
+        self.send(&self.coordinator, request, policy, |response: &AddPartitionsToTxnResponseV3| {
+            // partitions which weren't attempted because of another partition's error say so
+            let error_codes: Vec<&ErrorCode> = response.results.0.iter()
+                .flat_map(|topic| topic.results.0.iter().map(|result| &result.error_code))
+                .filter(|error_code| **error_code != ErrorCode::None)
+                .collect();
+            error_codes.iter().find(|error_code| ***error_code != ErrorCode::OperationNotAttempted)
+                .or(error_codes.first())
+                .map_or(ErrorCode::None, |error_code| (**error_code).clone())
+        })?;
+        self.transaction_manager.partitions_added(partitions);
+        Ok(())
+    }
+
+    /// Adds a consumer group's offsets to the transaction, then commits them to the group's
+    /// coordinator so that they become visible when the transaction commits.
+    pub fn send_offsets(&self, offsets: &HashMap<TopicPartition, OffsetAndMetadata>, group_metadata: &ConsumerGroupMetadata, policy: &RetryPolicy) -> Result<()> {
+        let producer: ProducerIdAndEpoch = self.producer()?;
+        let request: AddOffsetsToTxnRequestV3 = AddOffsetsToTxnRequestV3 {
+            transactional_id: CompactString(self.transactional_id.clone()),
+            producer_id: producer.producer_id,
+            producer_epoch: producer.epoch,
+            group_id: CompactString(group_metadata.group_id.clone()),
+            tag_buffer: TaggedFields::new()
+        };
+        self.send(&self.coordinator, request, policy, |response: &AddOffsetsToTxnResponseV3| response.error_code.clone())?;
+        self.transaction_manager.offsets_added();
+
+        let mut topics: BTreeMap<String, Vec<TxnOffsetCommitRequestPartitionV3>> = BTreeMap::new();
+        for (topic_partition, offset) in offsets {
+            topics.entry(topic_partition.topic.clone()).or_default().push(TxnOffsetCommitRequestPartitionV3 {
+                partition_index: topic_partition.partition,
+                committed_offset: offset.offset,
+                committed_leader_epoch: offset.leader_epoch.unwrap_or(-1),
+                committed_metadata: CompactNullableString(Some(offset.metadata.clone())),
+                tag_buffer: TaggedFields::new()
+            });
+        }
+        let request: TxnOffsetCommitRequestV3 = TxnOffsetCommitRequestV3 {
+            transactional_id: CompactString(self.transactional_id.clone()),
+            group_id: CompactString(group_metadata.group_id.clone()),
+            producer_id: producer.producer_id,
+            producer_epoch: producer.epoch,
+            generation_id: group_metadata.generation_id,
+            member_id: CompactString(group_metadata.member_id.clone()),
+            group_instance_id: CompactNullableString(group_metadata.group_instance_id.clone()),
+            topics: CompactArray(topics.into_iter()
+                .map(|(name, partitions)| TxnOffsetCommitRequestTopicV3 {
+                    name: CompactString(name),
+                    partitions: CompactArray(partitions),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            tag_buffer: TaggedFields::new()
+        };
+        let group_coordinator: Coordinator = Coordinator::new(COORDINATOR_TYPE_GROUP, &group_metadata.group_id);
+        self.send(&group_coordinator, request, policy, |response: &TxnOffsetCommitResponseV3| {
+            response.topics.0.iter()
+                .flat_map(|topic| topic.partitions.0.iter())
+                .find(|partition| partition.error_code != ErrorCode::None)
+                .map_or(ErrorCode::None, |partition| partition.error_code.clone())
+        })?;
+        Ok(())
+    }
+
+    /// Commits or aborts the transaction.
+    pub fn end_txn(&self, committed: bool, policy: &RetryPolicy) -> Result<()> {
+        let producer: ProducerIdAndEpoch = self.producer()?;
+        let request: EndTxnRequestV3 = EndTxnRequestV3 {
+            transactional_id: CompactString(self.transactional_id.clone()),
+            producer_id: producer.producer_id,
+            producer_epoch: producer.epoch,
+            committed,
+            tag_buffer: TaggedFields::new()
+        };
+        self.send(&self.coordinator, request, policy, |response: &EndTxnResponseV3| response.error_code.clone())?;
+        Ok(())
+    }
+
+    /// Sends a request to a coordinator, and fails the producer or the transaction if it fails
+    /// with an error which retrying won't fix.
+    fn send<Request, Response, F>(&self, coordinator: &Coordinator, request: Request, policy: &RetryPolicy, error_code: F) -> Result<Response>
+        where Request: KafkaRequest + Clone, Response: KafkaResponse, F: Fn(&Response) -> ErrorCode {
+        coordinator.send(&self.pool, request, policy, error_code).map_err(|e| {
+            let fatal_error_code: Option<ErrorCode> = e.chain()
+                .find_map(|cause| cause.downcast_ref::<ErrorCode>())
+                .filter(|error_code| is_fatal_error(error_code))
+                .cloned();
+            if let Some(error_code) = fatal_error_code {
+                let error: ProducerError = match error_code {
+                    ErrorCode::TransactionalIdAuthorizationFailed => ProducerError::from_transaction_error(&e),
+                    _ => ProducerError::ProducerFenced { transactional_id: self.transactional_id.clone() }
+                };
+                self.transaction_manager.fatal_error(error.clone());
+                return anyhow::Error::new(error);
+            }
+            if !retry::is_retriable(&e) {
+                self.transaction_manager.abortable_error(ProducerError::from_transaction_error(&e));
+            }
+            e
+        })
+    }
+}
@@ -0,0 +1,87 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::CompactString;
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+/// Adds a consumer group's offsets topic partition to a transaction, before TxnOffsetCommit.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddOffsetsToTxnRequestV3 {
+    pub transactional_id: CompactString,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub group_id: CompactString,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for AddOffsetsToTxnRequestV3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::AddOffsetsToTxn
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddOffsetsToTxnResponseV3 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for AddOffsetsToTxnResponseV3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::CompactString;
+    use crate::protocol::add_offsets_to_txn::{AddOffsetsToTxnRequestV3, AddOffsetsToTxnResponseV3};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_add_offsets_to_txn_request() {
+        let request: AddOffsetsToTxnRequestV3 = AddOffsetsToTxnRequestV3 {
+            transactional_id: CompactString(String::from("txn")),
+            producer_id: 42,
+            producer_epoch: 1,
+            group_id: CompactString(String::from("g")),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![4, 116, 120, 110, 0, 0, 0, 0, 0, 0, 0, 42, 0, 1, 2, 103, 0]);
+    }
+
+    #[test]
+    fn test_decode_add_offsets_to_txn_response() {
+        let bytes: Vec<u8> = vec![0, 0, 0, 5, 0, 51, 0];
+        let response: AddOffsetsToTxnResponseV3 = AddOffsetsToTxnResponseV3::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(response, AddOffsetsToTxnResponseV3 {
+            throttle_time_ms: 5,
+            error_code: ErrorCode::ConcurrentTransactions,
+            tag_buffer: TaggedFields::new()
+        });
+    }
+}
@@ -0,0 +1,126 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddPartitionsToTxnTopicV3 {
+    pub name: CompactString,
+    pub partitions: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+/// Version 4 lets brokers add partitions for several transactions at once (KIP-890), which
+/// clients don't use.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddPartitionsToTxnRequestV3 {
+    pub transactional_id: CompactString,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub topics: CompactArray<AddPartitionsToTxnTopicV3>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for AddPartitionsToTxnRequestV3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::AddPartitionsToTxn
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddPartitionsToTxnPartitionResultV3 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddPartitionsToTxnTopicResultV3 {
+    pub name: CompactString,
+    pub results: CompactArray<AddPartitionsToTxnPartitionResultV3>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AddPartitionsToTxnResponseV3 {
+    pub throttle_time_ms: i32,
+    pub results: CompactArray<AddPartitionsToTxnTopicResultV3>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for AddPartitionsToTxnResponseV3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactString};
+    use crate::protocol::add_partitions_to_txn::{AddPartitionsToTxnPartitionResultV3, AddPartitionsToTxnRequestV3, AddPartitionsToTxnResponseV3, AddPartitionsToTxnTopicResultV3, AddPartitionsToTxnTopicV3};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_add_partitions_to_txn_request() {
+        let request: AddPartitionsToTxnRequestV3 = AddPartitionsToTxnRequestV3 {
+            transactional_id: CompactString(String::from("txn")),
+            producer_id: 42,
+            producer_epoch: 1,
+            topics: CompactArray(vec![AddPartitionsToTxnTopicV3 {
+                name: CompactString(String::from("foo")),
+                partitions: CompactArray(vec![0, 2]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![
+            4, 116, 120, 110, 0, 0, 0, 0, 0, 0, 0, 42, 0, 1,
+            2, 4, 102, 111, 111, 3, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0
+        ]);
+        assert_eq!(AddPartitionsToTxnRequestV3::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_add_partitions_to_txn_response_round_trip() {
+        let response: AddPartitionsToTxnResponseV3 = AddPartitionsToTxnResponseV3 {
+            throttle_time_ms: 0,
+            results: CompactArray(vec![AddPartitionsToTxnTopicResultV3 {
+                name: CompactString(String::from("foo")),
+                results: CompactArray(vec![AddPartitionsToTxnPartitionResultV3 {
+                    partition_index: 2,
+                    error_code: ErrorCode::ConcurrentTransactions,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(AddPartitionsToTxnResponseV3::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
@@ -1,10 +1,9 @@
 use std::fmt::Debug;
 use anyhow::{anyhow, Result};
-use std::io::{Error, ErrorKind, Read, Write};
-use bytes::{Bytes, BytesMut};
+use std::io::{Read, Write};
 use kafka_encode::KafkaEncodable;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub enum ApiKey {
     Produce = 0,
     Fetch = 1,
@@ -67,6 +66,8 @@ pub enum ApiKey {
     DescribeTransactions = 65,
     ListTransactions = 66,
     AllocateProducerIds = 67,
+    ConsumerGroupHeartbeat = 68,
+    ConsumerGroupDescribe = 69,
 }
 impl TryFrom<i16> for ApiKey {
     type Error = anyhow::Error;
@@ -133,6 +134,8 @@ impl TryFrom<i16> for ApiKey {
             i if i == ApiKey::DescribeTransactions as i16 => Ok(ApiKey::DescribeTransactions),
             i if i == ApiKey::ListTransactions as i16 => Ok(ApiKey::ListTransactions),
             i if i == ApiKey::AllocateProducerIds as i16 => Ok(ApiKey::AllocateProducerIds),
+            i if i == ApiKey::ConsumerGroupHeartbeat as i16 => Ok(ApiKey::ConsumerGroupHeartbeat),
+            i if i == ApiKey::ConsumerGroupDescribe as i16 => Ok(ApiKey::ConsumerGroupDescribe),
             _ => Err(anyhow!("Unable to determine api key for value: {}", value))
         }
     }
@@ -1,10 +1,7 @@
 use std::fmt::Debug;
-use std::io::{Read, Write};
-use bytes::Bytes;
 use kafka_encode::KafkaEncodable;
 use kafka_encode::primitives::{Array, CompactArray, CompactString, UnsignedVarInt32};
 use kafka_encode_derive::KafkaEncodable;
-use anyhow::{anyhow, Result};
 use crate::protocol::api_key::ApiKey;
 use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
 use crate::protocol::err::ErrorCode;
@@ -17,15 +14,19 @@ pub struct ApiVersionsRequestV0V2 {
 
 impl KafkaRequest for ApiVersionsRequestV0V2 {
     fn get_api_key() -> ApiKey {
-        return ApiKey::ApiVersions;
+        ApiKey::ApiVersions
     }
 
     fn get_version() -> ApiVersion {
         2
     }
 
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
     fn get_response_header_version() -> ApiVersion {
-        return 0;
+        0
     }
 }
 
@@ -38,13 +39,17 @@ pub struct ApiVersionsRequestV3 {
 
 impl KafkaRequest for ApiVersionsRequestV3 {
     fn get_api_key() -> ApiKey {
-        return ApiKey::ApiVersions;
+        ApiKey::ApiVersions
     }
 
     fn get_version() -> ApiVersion {
         3
     }
 
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
     fn get_response_header_version() -> ApiVersion {
         0
     }
@@ -57,6 +62,9 @@ pub struct ApiVersionsResponseV0 {
     pub api_keys: Array<SupportedApiKeyVersionsV0V2>
 }
 
+impl KafkaResponse for ApiVersionsResponseV0 {
+}
+
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct SupportedApiKeyVersionsV0V2 {
     pub api_key: ApiKey,
@@ -71,6 +79,16 @@ pub struct ApiVersionsResponseV1V2 {
     pub throttle_time_ms: i32
 }
 
+impl KafkaResponse for ApiVersionsResponseV1V2 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
 // latest version of the response
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct ApiVersionsResponseV3 {
@@ -81,6 +99,13 @@ pub struct ApiVersionsResponseV3 {
 }
 
 impl KafkaResponse for ApiVersionsResponseV3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
@@ -91,6 +116,34 @@ pub struct SupportedApiKeyVersionsV3 {
     pub tag_buffer: TaggedFields
 }
 
+/// `ApiVersionsResponseV3` with its API keys left as numbers and its tagged fields undecoded, so
+/// that versions can be negotiated with brokers which support APIs this crate doesn't know.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ApiVersionsResponseV3ByKeyId {
+    pub error_code: ErrorCode,
+    pub api_keys: CompactArray<SupportedApiKeyIdVersionsV3>,
+    pub throttle_time_ms: i32,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for ApiVersionsResponseV3ByKeyId {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SupportedApiKeyIdVersionsV3 {
+    pub api_key: i16,
+    pub min_version: ApiVersion,
+    pub max_version: ApiVersion,
+    pub tag_buffer: TaggedFields
+}
+
 #[derive(Debug, Eq, KafkaEncodable, PartialEq, Clone)]
 #[kafka_encodable_tagged_fields]
 pub struct ApiVersionsResponseV3TaggedFields {
@@ -0,0 +1,128 @@
+use std::io::Read;
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use lz4_flex::frame::FrameDecoder;
+use ruzstd::decoding::StreamingDecoder;
+
+/// The header the Java client's snappy codec writes before its blocks.
+const XERIAL_SNAPPY_MAGIC: [u8; 8] = [0x82, b'S', b'N', b'A', b'P', b'P', b'Y', 0];
+
+/// The magic, then a version and a compatible version.
+const XERIAL_SNAPPY_HEADER_LENGTH: usize = 16;
+
+/// The codec a record batch's records are compressed with, from the low three bits of its
+/// attributes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum CompressionType {
+    #[default]
+    None = 0,
+    Gzip = 1,
+    Snappy = 2,
+    Lz4 = 3,
+    Zstd = 4
+}
+
+impl CompressionType {
+    pub fn from_id(id: i16) -> Option<CompressionType> {
+        match id {
+            0 => Some(CompressionType::None),
+            1 => Some(CompressionType::Gzip),
+            2 => Some(CompressionType::Snappy),
+            3 => Some(CompressionType::Lz4),
+            4 => Some(CompressionType::Zstd),
+            _ => None
+        }
+    }
+
+    /// Decompresses the records of a batch. Snappy may be framed the way the Java client frames
+    /// it, or be a single raw block.
+    pub fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed: Vec<u8> = Vec::new();
+        match self {
+            CompressionType::None => decompressed.extend_from_slice(bytes),
+            CompressionType::Gzip => {
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+            },
+            CompressionType::Snappy if bytes.starts_with(&XERIAL_SNAPPY_MAGIC) => {
+                let mut blocks: &[u8] = bytes.get(XERIAL_SNAPPY_HEADER_LENGTH..)
+                    .ok_or_else(|| anyhow!("Truncated snappy header"))?;
+                while !blocks.is_empty() {
+                    let length: usize = blocks.get(..4)
+                        .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+                        .ok_or_else(|| anyhow!("Truncated snappy block length"))?;
+                    let block: &[u8] = blocks.get(4..4 + length)
+                        .ok_or_else(|| anyhow!("Truncated snappy block"))?;
+                    decompressed.extend(snap::raw::Decoder::new().decompress_vec(block)?);
+                    blocks = &blocks[4 + length..];
+                }
+            },
+            CompressionType::Snappy => decompressed = snap::raw::Decoder::new().decompress_vec(bytes)?,
+            CompressionType::Lz4 => {
+                FrameDecoder::new(bytes).read_to_end(&mut decompressed)?;
+            },
+            CompressionType::Zstd => {
+                StreamingDecoder::new(bytes)?.read_to_end(&mut decompressed)?;
+            }
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use lz4_flex::frame::FrameEncoder;
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+    use crate::protocol::compression::{CompressionType, XERIAL_SNAPPY_MAGIC};
+
+    /// Compresses `bytes` the way the Java client does.
+    pub(crate) fn compress(compression: CompressionType, bytes: &[u8]) -> Vec<u8> {
+        match compression {
+            CompressionType::None => bytes.to_vec(),
+            CompressionType::Gzip => {
+                let mut encoder: GzEncoder<Vec<u8>> = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes).unwrap();
+                encoder.finish().unwrap()
+            },
+            CompressionType::Snappy => {
+                let block: Vec<u8> = snap::raw::Encoder::new().compress_vec(bytes).unwrap();
+                let mut framed: Vec<u8> = XERIAL_SNAPPY_MAGIC.to_vec();
+                framed.extend_from_slice(&1i32.to_be_bytes());
+                framed.extend_from_slice(&1i32.to_be_bytes());
+                framed.extend_from_slice(&(block.len() as u32).to_be_bytes());
+                framed.extend(block);
+                framed
+            },
+            CompressionType::Lz4 => {
+                let mut encoder: FrameEncoder<Vec<u8>> = FrameEncoder::new(Vec::new());
+                encoder.write_all(bytes).unwrap();
+                encoder.finish().unwrap()
+            },
+            CompressionType::Zstd => compress_to_vec(bytes, CompressionLevel::Fastest)
+        }
+    }
+
+    #[test]
+    fn test_every_codec_decompresses_what_it_compressed() {
+        let bytes: Vec<u8> = b"records records records".repeat(10);
+        for compression in [CompressionType::None, CompressionType::Gzip, CompressionType::Snappy, CompressionType::Lz4, CompressionType::Zstd] {
+            let compressed: Vec<u8> = compress(compression, &bytes);
+            assert_eq!(compression.decompress(&compressed).unwrap(), bytes, "{:?}", compression);
+        }
+        // snappy without the Java client's framing
+        let raw: Vec<u8> = snap::raw::Encoder::new().compress_vec(&bytes).unwrap();
+        assert_eq!(CompressionType::Snappy.decompress(&raw).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_unknown_codecs_and_corrupt_data_are_errors() {
+        assert_eq!(CompressionType::from_id(4), Some(CompressionType::Zstd));
+        assert_eq!(CompressionType::from_id(5), None);
+        let mut compressed: Vec<u8> = compress(CompressionType::Snappy, b"records");
+        compressed.truncate(compressed.len() - 2);
+        assert!(CompressionType::Snappy.decompress(&compressed).is_err());
+        assert!(CompressionType::Gzip.decompress(b"not gzip").is_err());
+    }
+}
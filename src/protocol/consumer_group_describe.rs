@@ -0,0 +1,165 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use uuid::Uuid;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+/// Describes groups using the consumer group protocol from KIP-848, including each member's
+/// current and target assignment.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupDescribeRequestV0 {
+    pub group_ids: CompactArray<CompactString>,
+    pub include_authorized_operations: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for ConsumerGroupDescribeRequestV0 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ConsumerGroupDescribe
+    }
+
+    fn get_version() -> ApiVersion {
+        0
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupDescribeTopicPartitionsV0 {
+    pub topic_id: Uuid,
+    pub topic_name: CompactString,
+    pub partitions: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupDescribeAssignmentV0 {
+    pub topic_partitions: CompactArray<ConsumerGroupDescribeTopicPartitionsV0>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupDescribeMemberV0 {
+    pub member_id: CompactString,
+    pub instance_id: CompactNullableString,
+    pub rack_id: CompactNullableString,
+    pub member_epoch: i32,
+    pub client_id: CompactString,
+    pub client_host: CompactString,
+    pub subscribed_topic_names: CompactArray<CompactString>,
+    pub subscribed_topic_regex: CompactNullableString,
+    /// The partitions the member owns now.
+    pub assignment: ConsumerGroupDescribeAssignmentV0,
+    /// The partitions the coordinator is moving the member towards.
+    pub target_assignment: ConsumerGroupDescribeAssignmentV0,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupDescribeGroupV0 {
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+    pub group_id: CompactString,
+    pub group_state: CompactString,
+    pub group_epoch: i32,
+    pub assignment_epoch: i32,
+    pub assignor_name: CompactString,
+    pub members: CompactArray<ConsumerGroupDescribeMemberV0>,
+    pub authorized_operations: i32,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupDescribeResponseV0 {
+    pub throttle_time_ms: i32,
+    pub groups: CompactArray<ConsumerGroupDescribeGroupV0>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for ConsumerGroupDescribeResponseV0 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+    use uuid::Uuid;
+    use crate::protocol::consumer_group_describe::{ConsumerGroupDescribeAssignmentV0, ConsumerGroupDescribeGroupV0, ConsumerGroupDescribeMemberV0, ConsumerGroupDescribeRequestV0, ConsumerGroupDescribeResponseV0, ConsumerGroupDescribeTopicPartitionsV0};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_consumer_group_describe_request() {
+        let request: ConsumerGroupDescribeRequestV0 = ConsumerGroupDescribeRequestV0 {
+            group_ids: CompactArray(vec![CompactString(String::from("g"))]),
+            include_authorized_operations: true,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![2, 2, 103, 1, 0]);
+    }
+
+    #[test]
+    fn test_consumer_group_describe_response_round_trip() {
+        let assignment: ConsumerGroupDescribeAssignmentV0 = ConsumerGroupDescribeAssignmentV0 {
+            topic_partitions: CompactArray(vec![ConsumerGroupDescribeTopicPartitionsV0 {
+                topic_id: Uuid::from_u128(7),
+                topic_name: CompactString(String::from("t")),
+                partitions: CompactArray(vec![0, 1]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let response: ConsumerGroupDescribeResponseV0 = ConsumerGroupDescribeResponseV0 {
+            throttle_time_ms: 0,
+            groups: CompactArray(vec![ConsumerGroupDescribeGroupV0 {
+                error_code: ErrorCode::None,
+                error_message: CompactNullableString(None),
+                group_id: CompactString(String::from("g")),
+                group_state: CompactString(String::from("Stable")),
+                group_epoch: 4,
+                assignment_epoch: 4,
+                assignor_name: CompactString(String::from("uniform")),
+                members: CompactArray(vec![ConsumerGroupDescribeMemberV0 {
+                    member_id: CompactString(String::from("m")),
+                    instance_id: CompactNullableString(None),
+                    rack_id: CompactNullableString(Some(String::from("r"))),
+                    member_epoch: 4,
+                    client_id: CompactString(String::from("c")),
+                    client_host: CompactString(String::from("/127.0.0.1")),
+                    subscribed_topic_names: CompactArray(vec![CompactString(String::from("t"))]),
+                    subscribed_topic_regex: CompactNullableString(None),
+                    assignment: assignment.clone(),
+                    target_assignment: assignment,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                authorized_operations: i32::MIN,
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(ConsumerGroupDescribeResponseV0::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
@@ -0,0 +1,143 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactNullableArray, CompactNullableString, CompactString, NullableStruct};
+use kafka_encode_derive::KafkaEncodable;
+use uuid::Uuid;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupHeartbeatTopicPartitionsV0 {
+    pub topic_id: Uuid,
+    pub partitions: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+/// The single request of the consumer group protocol from KIP-848, which joins, heartbeats and
+/// leaves. Fields which haven't changed since the last request can be sent as null, or -1.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupHeartbeatRequestV0 {
+    pub group_id: CompactString,
+    /// Empty when joining, after which the coordinator gives the member its id.
+    pub member_id: CompactString,
+    /// 0 to join, -1 to leave, and -2 for a static member to leave without giving up its partitions.
+    pub member_epoch: i32,
+    pub instance_id: CompactNullableString,
+    pub rack_id: CompactNullableString,
+    pub rebalance_timeout_ms: i32,
+    pub subscribed_topic_names: CompactNullableArray<CompactString>,
+    pub server_assignor: CompactNullableString,
+    /// The partitions the member owns, sent once it has reconciled a new assignment.
+    pub topic_partitions: CompactNullableArray<ConsumerGroupHeartbeatTopicPartitionsV0>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for ConsumerGroupHeartbeatRequestV0 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ConsumerGroupHeartbeat
+    }
+
+    fn get_version() -> ApiVersion {
+        0
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupHeartbeatAssignmentV0 {
+    pub topic_partitions: CompactArray<ConsumerGroupHeartbeatTopicPartitionsV0>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerGroupHeartbeatResponseV0 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+    pub member_id: CompactNullableString,
+    pub member_epoch: i32,
+    pub heartbeat_interval_ms: i32,
+    /// The member's target assignment, null when it hasn't changed.
+    pub assignment: NullableStruct<ConsumerGroupHeartbeatAssignmentV0>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for ConsumerGroupHeartbeatResponseV0 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactNullableArray, CompactNullableString, CompactString, NullableStruct};
+    use uuid::Uuid;
+    use crate::protocol::consumer_group_heartbeat::{ConsumerGroupHeartbeatAssignmentV0, ConsumerGroupHeartbeatRequestV0, ConsumerGroupHeartbeatResponseV0, ConsumerGroupHeartbeatTopicPartitionsV0};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_consumer_group_heartbeat_request() {
+        let request: ConsumerGroupHeartbeatRequestV0 = ConsumerGroupHeartbeatRequestV0 {
+            group_id: CompactString(String::from("g")),
+            member_id: CompactString(String::new()),
+            member_epoch: 0,
+            instance_id: CompactNullableString(None),
+            rack_id: CompactNullableString(None),
+            rebalance_timeout_ms: 300000,
+            subscribed_topic_names: CompactNullableArray(Some(vec![CompactString(String::from("t"))])),
+            server_assignor: CompactNullableString(None),
+            topic_partitions: CompactNullableArray(Some(vec![])),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![2, 103, 1, 0, 0, 0, 0, 0, 0, 0, 4, 147, 224, 2, 2, 116, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_consumer_group_heartbeat_response_round_trip() {
+        let with_assignment: ConsumerGroupHeartbeatResponseV0 = ConsumerGroupHeartbeatResponseV0 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            error_message: CompactNullableString(None),
+            member_id: CompactNullableString(Some(String::from("m"))),
+            member_epoch: 3,
+            heartbeat_interval_ms: 5000,
+            assignment: NullableStruct(Some(ConsumerGroupHeartbeatAssignmentV0 {
+                topic_partitions: CompactArray(vec![ConsumerGroupHeartbeatTopicPartitionsV0 {
+                    topic_id: Uuid::from_u128(7),
+                    partitions: CompactArray(vec![0, 2]),
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            })),
+            tag_buffer: TaggedFields::new()
+        };
+        let without_assignment: ConsumerGroupHeartbeatResponseV0 = ConsumerGroupHeartbeatResponseV0 {
+            error_code: ErrorCode::FencedMemberEpoch,
+            assignment: NullableStruct(None),
+            ..with_assignment.clone()
+        };
+        for response in [with_assignment, without_assignment] {
+            let mut bytes: Vec<u8> = Vec::new();
+            response.clone().to_kafka_bytes(&mut bytes).unwrap();
+            assert_eq!(ConsumerGroupHeartbeatResponseV0::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{Array, NullableBytes, NullableString};
+use kafka_encode_derive::KafkaEncodable;
+
+// The schemas consumers put in the metadata of JoinGroup and the assignments of SyncGroup. Each is
+// prefixed with its version, and later versions only add fields to the end, so a member can read
+// the fields it knows of any newer version.
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerProtocolTopicPartitionsV0 {
+    pub topic: String,
+    pub partitions: Array<i32>
+}
+
+/// The subscription a consumer joins its group with.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerProtocolSubscriptionV0 {
+    pub topics: Array<String>,
+    /// Data for the assignment strategy.
+    pub user_data: NullableBytes
+}
+
+/// Version 1 added the partitions the member owns, for cooperative rebalancing (KIP-429).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerProtocolSubscriptionV1 {
+    pub topics: Array<String>,
+    pub user_data: NullableBytes,
+    pub owned_partitions: Array<ConsumerProtocolTopicPartitionsV0>
+}
+
+/// Version 2 added the generation the member owned its partitions in (KIP-792).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerProtocolSubscriptionV2 {
+    pub topics: Array<String>,
+    pub user_data: NullableBytes,
+    pub owned_partitions: Array<ConsumerProtocolTopicPartitionsV0>,
+    /// -1 if the member hasn't been in a generation.
+    pub generation_id: i32
+}
+
+/// Version 3 added the member's rack, for rack-aware assignment (KIP-881).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerProtocolSubscriptionV3 {
+    pub topics: Array<String>,
+    pub user_data: NullableBytes,
+    pub owned_partitions: Array<ConsumerProtocolTopicPartitionsV0>,
+    pub generation_id: i32,
+    pub rack_id: NullableString
+}
+
+/// The partitions the group leader assigned to a member. Versions 1 to 3 are the same.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ConsumerProtocolAssignmentV0 {
+    pub assigned_partitions: Array<ConsumerProtocolTopicPartitionsV0>,
+    pub user_data: NullableBytes
+}
+
+/// The user data of the sticky assignor: the partitions the member held, without a version
+/// prefix. Version 1 added the generation they were held in, and is told apart by its length.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct StickyAssignorUserDataV0 {
+    pub previous_assignment: Array<ConsumerProtocolTopicPartitionsV0>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct StickyAssignorUserDataV1 {
+    pub previous_assignment: Array<ConsumerProtocolTopicPartitionsV0>,
+    pub generation: i32
+}
+
+/// Encodes a subscription or assignment with its version.
+pub fn encode_versioned<T: KafkaEncodable>(version: i16, value: T) -> Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    version.to_kafka_bytes(&mut bytes)?;
+    value.to_kafka_bytes(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Splits an encoded subscription or assignment into its version and its fields.
+pub fn split_version(bytes: &[u8]) -> Result<(i16, &[u8])> {
+    let mut reader: &[u8] = bytes;
+    let version: i16 = i16::from_kafka_bytes(&mut reader)?;
+    if version < 0 {
+        return Err(anyhow!("Unsupported consumer protocol version: {}", version));
+    }
+    Ok((version, reader))
+}
+
+/// Decodes a subscription or assignment, and returns it with the version it was encoded with.
+/// Fields of versions newer than `T` are ignored.
+pub fn decode_versioned<T: KafkaEncodable>(bytes: &[u8]) -> Result<(i16, T)> {
+    let (version, mut reader): (i16, &[u8]) = split_version(bytes)?;
+    Ok((version, T::from_kafka_bytes(&mut reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::primitives::{Array, NullableBytes, NullableString};
+    use crate::protocol::consumer_protocol::{ConsumerProtocolAssignmentV0, ConsumerProtocolSubscriptionV0, ConsumerProtocolSubscriptionV3, ConsumerProtocolTopicPartitionsV0, decode_versioned, encode_versioned};
+
+    #[test]
+    fn test_encode_subscription() {
+        let subscription: ConsumerProtocolSubscriptionV0 = ConsumerProtocolSubscriptionV0 {
+            topics: Array(vec![String::from("foo")]),
+            user_data: NullableBytes(None)
+        };
+        let bytes: Vec<u8> = encode_versioned(0, subscription.clone()).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 0, 1, 0, 3, 102, 111, 111, 255, 255, 255, 255]);
+        assert_eq!(decode_versioned(&bytes).unwrap(), (0, subscription));
+    }
+
+    #[test]
+    fn test_encode_subscription_v3() {
+        let subscription: ConsumerProtocolSubscriptionV3 = ConsumerProtocolSubscriptionV3 {
+            topics: Array(vec![String::from("foo")]),
+            user_data: NullableBytes(None),
+            owned_partitions: Array(vec![ConsumerProtocolTopicPartitionsV0 { topic: String::from("foo"), partitions: Array(vec![1]) }]),
+            generation_id: 4,
+            rack_id: NullableString(Some(String::from("a")))
+        };
+        let bytes: Vec<u8> = encode_versioned(3, subscription.clone()).unwrap();
+        assert_eq!(&bytes[15..], &[0, 0, 0, 1, 0, 3, 102, 111, 111, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 4, 0, 1, 97]);
+        assert_eq!(decode_versioned(&bytes).unwrap(), (3, subscription));
+    }
+
+    #[test]
+    fn test_decode_fields_of_a_newer_assignment_version() {
+        let assignment: ConsumerProtocolAssignmentV0 = ConsumerProtocolAssignmentV0 {
+            assigned_partitions: Array(vec![ConsumerProtocolTopicPartitionsV0 { topic: String::from("foo"), partitions: Array(vec![0, 2]) }]),
+            user_data: NullableBytes(Some(vec![7]))
+        };
+        let mut bytes: Vec<u8> = encode_versioned(3, assignment.clone()).unwrap();
+        bytes.extend([0, 0, 0, 1]);
+        assert_eq!(decode_versioned(&bytes).unwrap(), (3, assignment));
+        assert!(decode_versioned::<ConsumerProtocolAssignmentV0>(&[255, 255]).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::CompactString;
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct EndTxnRequestV3 {
+    pub transactional_id: CompactString,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    /// True to commit the transaction, false to abort it.
+    pub committed: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for EndTxnRequestV3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::EndTxn
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct EndTxnResponseV3 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for EndTxnResponseV3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::CompactString;
+    use crate::protocol::end_txn::{EndTxnRequestV3, EndTxnResponseV3};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_end_txn_request() {
+        let request: EndTxnRequestV3 = EndTxnRequestV3 {
+            transactional_id: CompactString(String::from("txn")),
+            producer_id: 42,
+            producer_epoch: 1,
+            committed: true,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![4, 116, 120, 110, 0, 0, 0, 0, 0, 0, 0, 42, 0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_decode_end_txn_response() {
+        let bytes: Vec<u8> = vec![0, 0, 0, 0, 0, 90, 0];
+        let response: EndTxnResponseV3 = EndTxnResponseV3::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(response.error_code, ErrorCode::ProducerFenced);
+    }
+}
@@ -1,9 +1,7 @@
-use anyhow::{anyhow, Error, Result};
+use anyhow::{anyhow, Result};
 use thiserror::Error;
-use std::collections::HashMap;
 use std::fmt::Debug;
 use std::io::{Read, Write};
-use bytes::{Bytes, BytesMut};
 use kafka_encode::KafkaEncodable;
 
 #[derive(Debug, Clone, Error, Eq, PartialEq)]
@@ -228,6 +226,64 @@ pub enum ErrorCode {
     IneligibleReplica = 107,
     #[error("The AlterPartition request successfully updated the partition state but the leader has changed.")]
     NewLeaderElected = 108,
+    #[error("The requested offset is moved to tiered storage.")]
+    OffsetMovedToTieredStorage = 109,
+    #[error("The member epoch is fenced by the group coordinator. The member must abandon all its partitions and rejoin.")]
+    FencedMemberEpoch = 110,
+    #[error("The instance ID is still used by another member in the consumer group. That member must leave first.")]
+    UnreleasedInstanceId = 111,
+    #[error("The assignor or its version range is not supported by the consumer group.")]
+    UnsupportedAssignor = 112,
+    #[error("The member epoch is stale. The member must retry after receiving its updated member epoch via the ConsumerGroupHeartbeat API.")]
+    StaleMemberEpoch = 113,
+}
+
+impl ErrorCode {
+    /// Whether the error means the client's view of the cluster is out of date, so metadata
+    /// should be refreshed before the request is retried.
+    pub fn is_invalid_metadata(&self) -> bool {
+        matches!(self,
+            ErrorCode::UnknownTopicOrPartition
+            | ErrorCode::LeaderNotAvailable
+            | ErrorCode::NotLeaderOrFollower
+            | ErrorCode::KafkaStorageError
+            | ErrorCode::FencedLeaderEpoch
+            | ErrorCode::UnknownTopicId)
+    }
+
+    /// Whether a request which failed with this error may succeed if it is sent again, matching the
+    /// errors the Java client treats as retriable.
+    pub fn is_retriable(&self) -> bool {
+        matches!(self,
+            ErrorCode::CorruptMessage
+            | ErrorCode::UnknownTopicOrPartition
+            | ErrorCode::LeaderNotAvailable
+            | ErrorCode::NotLeaderOrFollower
+            | ErrorCode::RequestTimedOut
+            | ErrorCode::ReplicaNotAvailable
+            | ErrorCode::NetworkException
+            | ErrorCode::CoordinatorLoadInProgress
+            | ErrorCode::CoordinatorNotAvailable
+            | ErrorCode::NotCoordinator
+            | ErrorCode::NotEnoughReplicas
+            | ErrorCode::NotEnoughReplicasAfterAppend
+            | ErrorCode::NotController
+            | ErrorCode::ConcurrentTransactions
+            | ErrorCode::KafkaStorageError
+            | ErrorCode::FetchSessionIdNotFound
+            | ErrorCode::InvalidFetchSessionEpoch
+            | ErrorCode::ListenerNotFound
+            | ErrorCode::FencedLeaderEpoch
+            | ErrorCode::UnknownLeaderEpoch
+            | ErrorCode::OffsetNotAvailable
+            | ErrorCode::PreferredLeaderNotAvailable
+            | ErrorCode::EligibleLeadersNotAvailable
+            | ErrorCode::UnstableOffsetCommit
+            | ErrorCode::ThrottlingQuotaExceeded
+            | ErrorCode::UnknownTopicId
+            | ErrorCode::InconsistentTopicId
+            | ErrorCode::FetchSessionTopicIdError)
+    }
 }
 
 impl TryFrom<i16> for ErrorCode {
@@ -345,6 +401,11 @@ impl TryFrom<i16> for ErrorCode {
             i if i == ErrorCode::FetchSessionTopicIdError as i16 => Ok(ErrorCode::FetchSessionTopicIdError),
             i if i == ErrorCode::IneligibleReplica as i16 => Ok(ErrorCode::IneligibleReplica),
             i if i == ErrorCode::NewLeaderElected as i16 => Ok(ErrorCode::NewLeaderElected),
+            i if i == ErrorCode::OffsetMovedToTieredStorage as i16 => Ok(ErrorCode::OffsetMovedToTieredStorage),
+            i if i == ErrorCode::FencedMemberEpoch as i16 => Ok(ErrorCode::FencedMemberEpoch),
+            i if i == ErrorCode::UnreleasedInstanceId as i16 => Ok(ErrorCode::UnreleasedInstanceId),
+            i if i == ErrorCode::UnsupportedAssignor as i16 => Ok(ErrorCode::UnsupportedAssignor),
+            i if i == ErrorCode::StaleMemberEpoch as i16 => Ok(ErrorCode::StaleMemberEpoch),
             _ => Err(anyhow!("Unable to determine error code for value {}", value))
         }
     }
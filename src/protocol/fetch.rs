@@ -0,0 +1,857 @@
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use anyhow::{anyhow, Result};
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactString, NullableArray, UnsignedVarInt32};
+use kafka_encode_derive::KafkaEncodable;
+use uuid::Uuid;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::records::{CompactRecords, Records};
+use crate::protocol::tags::TaggedFields;
+
+/// Consumers always send -1 as their replica id; only followers send a broker id.
+pub const CONSUMER_REPLICA_ID: i32 = -1;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum IsolationLevel {
+    ReadUncommitted = 0,
+    ReadCommitted = 1
+}
+
+impl TryFrom<i8> for IsolationLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            i if i == IsolationLevel::ReadUncommitted as i8 => Ok(IsolationLevel::ReadUncommitted),
+            i if i == IsolationLevel::ReadCommitted as i8 => Ok(IsolationLevel::ReadCommitted),
+            _ => Err(anyhow!("Unable to determine isolation level for value: {}", value))
+        }
+    }
+}
+
+impl KafkaEncodable for IsolationLevel {
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        (self as i8).to_kafka_bytes(writer)
+    }
+
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<IsolationLevel> {
+        let isolation_level: i8 = i8::from_kafka_bytes(reader)?;
+        IsolationLevel::try_from(isolation_level)
+    }
+}
+
+// request partitions
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionV0V4 {
+    pub partition: i32,
+    pub fetch_offset: i64,
+    pub partition_max_bytes: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionV5V8 {
+    pub partition: i32,
+    pub fetch_offset: i64,
+    pub log_start_offset: i64,
+    pub partition_max_bytes: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionV9V11 {
+    pub partition: i32,
+    pub current_leader_epoch: i32,
+    pub fetch_offset: i64,
+    pub log_start_offset: i64,
+    pub partition_max_bytes: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionV12V13 {
+    pub partition: i32,
+    pub current_leader_epoch: i32,
+    pub fetch_offset: i64,
+    pub last_fetched_epoch: i32,
+    pub log_start_offset: i64,
+    pub partition_max_bytes: i32,
+    pub tag_buffer: TaggedFields
+}
+
+// request topics
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchTopicV0V4 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionV0V4>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchTopicV5V8 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionV5V8>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchTopicV9V11 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionV9V11>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchTopicV12 {
+    pub topic: CompactString,
+    pub partitions: CompactArray<FetchPartitionV12V13>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchTopicV13 {
+    pub topic_id: Uuid,
+    pub partitions: CompactArray<FetchPartitionV12V13>,
+    pub tag_buffer: TaggedFields
+}
+
+// partitions removed from an incremental fetch session
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ForgottenTopicV7V11 {
+    pub topic: String,
+    pub partitions: Array<i32>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ForgottenTopicV12 {
+    pub topic: CompactString,
+    pub partitions: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ForgottenTopicV13 {
+    pub topic_id: Uuid,
+    pub partitions: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV0V2 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub topics: Array<FetchTopicV0V4>
+}
+
+impl KafkaRequest for FetchRequestV0V2 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        2
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV3 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub topics: Array<FetchTopicV0V4>
+}
+
+impl KafkaRequest for FetchRequestV3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV4 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub topics: Array<FetchTopicV0V4>
+}
+
+impl KafkaRequest for FetchRequestV4 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        4
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV5V6 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub topics: Array<FetchTopicV5V8>
+}
+
+impl KafkaRequest for FetchRequestV5V6 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        6
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV7V8 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub session_id: i32,
+    pub session_epoch: i32,
+    pub topics: Array<FetchTopicV5V8>,
+    pub forgotten_topics_data: Array<ForgottenTopicV7V11>
+}
+
+impl KafkaRequest for FetchRequestV7V8 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        8
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV9V10 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub session_id: i32,
+    pub session_epoch: i32,
+    pub topics: Array<FetchTopicV9V11>,
+    pub forgotten_topics_data: Array<ForgottenTopicV7V11>
+}
+
+impl KafkaRequest for FetchRequestV9V10 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        10
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV11 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub session_id: i32,
+    pub session_epoch: i32,
+    pub topics: Array<FetchTopicV9V11>,
+    pub forgotten_topics_data: Array<ForgottenTopicV7V11>,
+    pub rack_id: String
+}
+
+impl KafkaRequest for FetchRequestV11 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        11
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV12 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub session_id: i32,
+    pub session_epoch: i32,
+    pub topics: CompactArray<FetchTopicV12>,
+    pub forgotten_topics_data: CompactArray<ForgottenTopicV12>,
+    pub rack_id: CompactString,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for FetchRequestV12 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        12
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchRequestV13 {
+    pub replica_id: i32,
+    pub max_wait_ms: i32,
+    pub min_bytes: i32,
+    pub max_bytes: i32,
+    pub isolation_level: IsolationLevel,
+    pub session_id: i32,
+    pub session_epoch: i32,
+    pub topics: CompactArray<FetchTopicV13>,
+    pub forgotten_topics_data: CompactArray<ForgottenTopicV13>,
+    pub rack_id: CompactString,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for FetchRequestV13 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Fetch
+    }
+
+    fn get_version() -> ApiVersion {
+        13
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// aborted transactions, which read_committed consumers use to skip aborted records
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AbortedTransactionV4V11 {
+    pub producer_id: i64,
+    pub first_offset: i64
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct AbortedTransactionV12V13 {
+    pub producer_id: i64,
+    pub first_offset: i64,
+    pub tag_buffer: TaggedFields
+}
+
+// response partitions
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionDataV0V3 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub records: Records
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionDataV4 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub aborted_transactions: NullableArray<AbortedTransactionV4V11>,
+    pub records: Records
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionDataV5V10 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    pub aborted_transactions: NullableArray<AbortedTransactionV4V11>,
+    pub records: Records
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionDataV11 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    pub aborted_transactions: NullableArray<AbortedTransactionV4V11>,
+    pub preferred_read_replica: i32,
+    pub records: Records
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchPartitionDataV12V13 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    pub aborted_transactions: CompactNullableArray<AbortedTransactionV12V13>,
+    pub preferred_read_replica: i32,
+    pub records: CompactRecords,
+    pub tag_buffer: FetchPartitionDataV12V13TaggedFields
+}
+
+#[derive(Debug, Eq, KafkaEncodable, PartialEq, Clone)]
+#[kafka_encodable_tagged_fields]
+pub struct FetchPartitionDataV12V13TaggedFields {
+    pub diverging_epoch: Option<EpochEndOffsetV12V13>,
+    pub current_leader: Option<LeaderIdAndEpochV12V13>,
+    pub snapshot_id: Option<SnapshotIdV12V13>
+}
+
+impl FetchPartitionDataV12V13TaggedFields {
+    pub fn new() -> Self {
+        FetchPartitionDataV12V13TaggedFields {
+            diverging_epoch: None,
+            current_leader: None,
+            snapshot_id: None
+        }
+    }
+}
+
+impl Default for FetchPartitionDataV12V13TaggedFields {
+    fn default() -> Self {
+        FetchPartitionDataV12V13TaggedFields::new()
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct EpochEndOffsetV12V13 {
+    pub epoch: i32,
+    pub end_offset: i64,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct LeaderIdAndEpochV12V13 {
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SnapshotIdV12V13 {
+    pub end_offset: i64,
+    pub epoch: i32,
+    pub tag_buffer: TaggedFields
+}
+
+// response topics
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchableTopicResponseV0V3 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionDataV0V3>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchableTopicResponseV4 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionDataV4>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchableTopicResponseV5V10 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionDataV5V10>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchableTopicResponseV11 {
+    pub topic: String,
+    pub partitions: Array<FetchPartitionDataV11>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchableTopicResponseV12 {
+    pub topic: CompactString,
+    pub partitions: CompactArray<FetchPartitionDataV12V13>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchableTopicResponseV13 {
+    pub topic_id: Uuid,
+    pub partitions: CompactArray<FetchPartitionDataV12V13>,
+    pub tag_buffer: TaggedFields
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV0 {
+    pub responses: Array<FetchableTopicResponseV0V3>
+}
+
+impl KafkaResponse for FetchResponseV0 {
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV1V3 {
+    pub throttle_time_ms: i32,
+    pub responses: Array<FetchableTopicResponseV0V3>
+}
+
+impl KafkaResponse for FetchResponseV1V3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV4 {
+    pub throttle_time_ms: i32,
+    pub responses: Array<FetchableTopicResponseV4>
+}
+
+impl KafkaResponse for FetchResponseV4 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV5V6 {
+    pub throttle_time_ms: i32,
+    pub responses: Array<FetchableTopicResponseV5V10>
+}
+
+impl KafkaResponse for FetchResponseV5V6 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV7V10 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub session_id: i32,
+    pub responses: Array<FetchableTopicResponseV5V10>
+}
+
+impl KafkaResponse for FetchResponseV7V10 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV11 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub session_id: i32,
+    pub responses: Array<FetchableTopicResponseV11>
+}
+
+impl KafkaResponse for FetchResponseV11 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV12 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub session_id: i32,
+    pub responses: CompactArray<FetchableTopicResponseV12>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for FetchResponseV12 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+// latest version of the response
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FetchResponseV13 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub session_id: i32,
+    pub responses: CompactArray<FetchableTopicResponseV13>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for FetchResponseV13 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactString, NullableArray, VarI32, VarI64, VarIntArray, VarIntNullableBytes};
+    use uuid::Uuid;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::fetch::{AbortedTransactionV12V13, CONSUMER_REPLICA_ID, FetchPartitionDataV12V13, FetchPartitionDataV12V13TaggedFields, FetchPartitionDataV4, FetchPartitionV0V4, FetchPartitionV12V13, FetchRequestV13, FetchRequestV4, FetchResponseV13, FetchResponseV4, FetchTopicV0V4, FetchTopicV13, FetchableTopicResponseV13, FetchableTopicResponseV4, ForgottenTopicV13, IsolationLevel, LeaderIdAndEpochV12V13};
+    use crate::protocol::records::{CompactRecords, Record, RecordBatch, Records};
+    use crate::protocol::tags::TaggedFields;
+
+    fn record_batch(base_offset: i64) -> RecordBatch {
+        RecordBatch {
+            base_offset,
+            batch_length: 58,
+            partition_leader_epoch: 0,
+            magic: 2,
+            crc: 0,
+            attributes: 0,
+            last_offset_delta: 0,
+            base_timestamp: 0,
+            max_timestamp: 0,
+            producer_id: -1,
+            producer_epoch_offset: -1,
+            base_sequence: -1,
+            records: Array(vec![
+                Record {
+                    length: VarI32(8),
+                    attributes: 0,
+                    timestamp_delta: VarI64(0),
+                    offset_delta: VarI32(0),
+                    key: VarIntNullableBytes(None),
+                    value: VarIntNullableBytes(Some(vec![1, 2])),
+                    headers: VarIntArray(vec![])
+                }
+            ]),
+            raw_records: None,
+            decode_error: None
+        }
+    }
+
+    #[test]
+    fn test_encode_fetch_request_v4() {
+        let request: FetchRequestV4 = FetchRequestV4 {
+            replica_id: CONSUMER_REPLICA_ID,
+            max_wait_ms: 500,
+            min_bytes: 1,
+            max_bytes: 1024,
+            isolation_level: IsolationLevel::ReadCommitted,
+            topics: Array(vec![
+                FetchTopicV0V4 {
+                    topic: String::from("foo"),
+                    partitions: Array(vec![
+                        FetchPartitionV0V4 { partition: 2, fetch_offset: 10, partition_max_bytes: 512 }
+                    ])
+                }
+            ])
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(
+            bytes,
+            vec![255, 255, 255, 255, 0, 0, 1, 244, 0, 0, 0, 1, 0, 0, 4, 0, 1,
+                 0, 0, 0, 1, 0, 3, 102, 111, 111,
+                 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 2, 0]
+        );
+    }
+
+    #[test]
+    fn test_fetch_request_v13_round_trip() {
+        let topic_id: Uuid = Uuid::from_u128(7);
+        let request: FetchRequestV13 = FetchRequestV13 {
+            replica_id: CONSUMER_REPLICA_ID,
+            max_wait_ms: 500,
+            min_bytes: 1,
+            max_bytes: 52428800,
+            isolation_level: IsolationLevel::ReadUncommitted,
+            session_id: 12,
+            session_epoch: 3,
+            topics: CompactArray(vec![
+                FetchTopicV13 {
+                    topic_id,
+                    partitions: CompactArray(vec![
+                        FetchPartitionV12V13 {
+                            partition: 0,
+                            current_leader_epoch: 5,
+                            fetch_offset: 100,
+                            last_fetched_epoch: -1,
+                            log_start_offset: -1,
+                            partition_max_bytes: 1048576,
+                            tag_buffer: TaggedFields::new()
+                        }
+                    ]),
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            forgotten_topics_data: CompactArray(vec![
+                ForgottenTopicV13 { topic_id, partitions: CompactArray(vec![1, 2]), tag_buffer: TaggedFields::new() }
+            ]),
+            rack_id: CompactString(String::from("rack-a")),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(FetchRequestV13::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_fetch_response_v4_round_trip() {
+        let response: FetchResponseV4 = FetchResponseV4 {
+            throttle_time_ms: 0,
+            responses: Array(vec![
+                FetchableTopicResponseV4 {
+                    topic: String::from("foo"),
+                    partitions: Array(vec![
+                        FetchPartitionDataV4 {
+                            partition_index: 0,
+                            error_code: ErrorCode::None,
+                            high_watermark: 2,
+                            last_stable_offset: 2,
+                            aborted_transactions: NullableArray(None),
+                            records: Records(Some(vec![record_batch(0), record_batch(1)]))
+                        }
+                    ])
+                }
+            ])
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(FetchResponseV4::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+
+    #[test]
+    fn test_fetch_response_v13_round_trip() {
+        let response: FetchResponseV13 = FetchResponseV13 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            session_id: 12,
+            responses: CompactArray(vec![
+                FetchableTopicResponseV13 {
+                    topic_id: Uuid::from_u128(7),
+                    partitions: CompactArray(vec![
+                        FetchPartitionDataV12V13 {
+                            partition_index: 0,
+                            error_code: ErrorCode::None,
+                            high_watermark: 10,
+                            last_stable_offset: 8,
+                            log_start_offset: 0,
+                            aborted_transactions: CompactNullableArray(Some(vec![
+                                AbortedTransactionV12V13 { producer_id: 99, first_offset: 3, tag_buffer: TaggedFields::new() }
+                            ])),
+                            preferred_read_replica: -1,
+                            records: CompactRecords(Some(vec![record_batch(0)])),
+                            tag_buffer: FetchPartitionDataV12V13TaggedFields {
+                                current_leader: Some(LeaderIdAndEpochV12V13 { leader_id: 2, leader_epoch: 6, tag_buffer: TaggedFields::new() }),
+                                ..FetchPartitionDataV12V13TaggedFields::new()
+                            }
+                        },
+                        FetchPartitionDataV12V13 {
+                            partition_index: 1,
+                            error_code: ErrorCode::OffsetOutOfRange,
+                            high_watermark: -1,
+                            last_stable_offset: -1,
+                            log_start_offset: -1,
+                            aborted_transactions: CompactNullableArray(None),
+                            preferred_read_replica: -1,
+                            records: CompactRecords(None),
+                            tag_buffer: FetchPartitionDataV12V13TaggedFields::new()
+                        }
+                    ]),
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(FetchResponseV13::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
@@ -0,0 +1,97 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+/// `key_type` for the coordinator of a consumer group.
+pub const COORDINATOR_TYPE_GROUP: i8 = 0;
+/// `key_type` for the coordinator of a transactional id.
+pub const COORDINATOR_TYPE_TRANSACTION: i8 = 1;
+
+// requests
+/// Version 4 looks up several keys at once, which a single coordinator lookup doesn't need.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FindCoordinatorRequestV3 {
+    /// The group id or transactional id.
+    pub key: CompactString,
+    pub key_type: i8,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for FindCoordinatorRequestV3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::FindCoordinator
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct FindCoordinatorResponseV3 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+    pub node_id: i32,
+    pub host: CompactString,
+    pub port: i32,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for FindCoordinatorResponseV3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactNullableString, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::find_coordinator::{COORDINATOR_TYPE_TRANSACTION, FindCoordinatorRequestV3, FindCoordinatorResponseV3};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_find_coordinator_request() {
+        let request: FindCoordinatorRequestV3 = FindCoordinatorRequestV3 {
+            key: CompactString(String::from("txn")),
+            key_type: COORDINATOR_TYPE_TRANSACTION,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![4, 116, 120, 110, 1, 0]);
+    }
+
+    #[test]
+    fn test_decode_find_coordinator_response() {
+        let bytes: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 10, 108, 111, 99, 97, 108, 104, 111, 115, 116, 0, 0, 35, 132, 0];
+        let response: FindCoordinatorResponseV3 = FindCoordinatorResponseV3::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(response, FindCoordinatorResponseV3 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            error_message: CompactNullableString(None),
+            node_id: 3,
+            host: CompactString(String::from("localhost")),
+            port: 9092,
+            tag_buffer: TaggedFields::new()
+        });
+    }
+}
@@ -1,11 +1,15 @@
-use kafka_encode::primitives::{NullableString, VarArray};
+use kafka_encode::primitives::NullableString;
+#[cfg(test)]
+use kafka_encode::primitives::VarArray;
 use kafka_encode::KafkaEncodable;
 use kafka_encode_derive::KafkaEncodable;
 use crate::protocol::api_key::ApiKey;
 use crate::protocol::{ApiVersion};
-use crate::protocol::api_key::ApiKey::{ApiVersions, Produce};
+#[cfg(test)]
+use crate::protocol::api_key::ApiKey::ApiVersions;
 use crate::protocol::tags::TaggedFields;
 
+#[cfg(test)]
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct RequestHeaderV0 {
     pub request_api_key: ApiKey,
@@ -30,33 +34,17 @@ pub struct RequestHeaderV2 {
     pub tag_buffer: TaggedFields
 }
 
-pub trait ResponseHeader {
-    fn get_correlation_id(&self) -> i32;
-}
-
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct ResponseHeaderV0 {
     pub correlation_id: i32
 }
 
-impl ResponseHeader for ResponseHeaderV0 {
-    fn get_correlation_id(&self) -> i32 {
-        self.correlation_id
-    }
-}
-
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct ResponseHeaderV1 {
     pub correlation_id: i32,
     pub tag_buffer: TaggedFields
 }
 
-impl ResponseHeader for ResponseHeaderV1 {
-    fn get_correlation_id(&self) -> i32 {
-        self.correlation_id
-    }
-}
-
 
 
 #[test]
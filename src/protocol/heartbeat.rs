@@ -0,0 +1,86 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct HeartbeatRequestV4 {
+    pub group_id: CompactString,
+    pub generation_id: i32,
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for HeartbeatRequestV4 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Heartbeat
+    }
+
+    fn get_version() -> ApiVersion {
+        4
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct HeartbeatResponseV4 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for HeartbeatResponseV4 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactNullableString, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::heartbeat::{HeartbeatRequestV4, HeartbeatResponseV4};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_heartbeat_request() {
+        let request: HeartbeatRequestV4 = HeartbeatRequestV4 {
+            group_id: CompactString(String::from("g")),
+            generation_id: 2,
+            member_id: CompactString(String::from("m")),
+            group_instance_id: CompactNullableString(Some(String::from("i"))),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![2, 103, 0, 0, 0, 2, 2, 109, 2, 105, 0]);
+    }
+
+    #[test]
+    fn test_decode_heartbeat_response() {
+        let bytes: Vec<u8> = vec![0, 0, 0, 0, 0, 22, 0];
+        let response: HeartbeatResponseV4 = HeartbeatResponseV4::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(response, HeartbeatResponseV4 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::IllegalGeneration,
+            tag_buffer: TaggedFields::new()
+        });
+    }
+}
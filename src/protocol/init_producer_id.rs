@@ -0,0 +1,96 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::CompactNullableString;
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+/// Version 3 added the current producer id and epoch, so that a producer can bump its epoch
+/// instead of being given a new id (KIP-360).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct InitProducerIdRequestV3V4 {
+    /// `None` for an idempotent producer which isn't transactional.
+    pub transactional_id: CompactNullableString,
+    pub transaction_timeout_ms: i32,
+    /// -1 for a producer without an id yet.
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for InitProducerIdRequestV3V4 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::InitProducerId
+    }
+
+    fn get_version() -> ApiVersion {
+        4
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// latest version of the response
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct InitProducerIdResponseV2V4 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for InitProducerIdResponseV2V4 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::CompactNullableString;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::init_producer_id::{InitProducerIdRequestV3V4, InitProducerIdResponseV2V4};
+    use crate::protocol::records::{NO_PRODUCER_EPOCH, NO_PRODUCER_ID};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_init_producer_id_request() {
+        let request: InitProducerIdRequestV3V4 = InitProducerIdRequestV3V4 {
+            transactional_id: CompactNullableString(None),
+            transaction_timeout_ms: 60_000,
+            producer_id: NO_PRODUCER_ID,
+            producer_epoch: NO_PRODUCER_EPOCH,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 234, 96, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0]);
+    }
+
+    #[test]
+    fn test_decode_init_producer_id_response() {
+        let bytes: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 42, 0, 3, 0];
+        let response: InitProducerIdResponseV2V4 = InitProducerIdResponseV2V4::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(response, InitProducerIdResponseV2V4 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            producer_id: 42,
+            producer_epoch: 3,
+            tag_buffer: TaggedFields::new()
+        });
+    }
+}
@@ -0,0 +1,154 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactBytes, CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+/// `protocol_type` of groups whose members are consumers.
+pub const PROTOCOL_TYPE_CONSUMER: &str = "consumer";
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct JoinGroupRequestProtocolV9 {
+    /// The name of an assignment strategy the member supports.
+    pub name: CompactString,
+    /// The member's subscription, encoded as the strategy expects.
+    pub metadata: CompactBytes,
+    pub tag_buffer: TaggedFields
+}
+
+/// Version 8 added the reason for joining, and version 9 lets the broker tell the leader not to
+/// compute an assignment.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct JoinGroupRequestV9 {
+    pub group_id: CompactString,
+    pub session_timeout_ms: i32,
+    /// How long the coordinator waits for every member to rejoin during a rebalance.
+    pub rebalance_timeout_ms: i32,
+    /// Empty when the member joins for the first time.
+    pub member_id: CompactString,
+    /// Set for static members (KIP-345).
+    pub group_instance_id: CompactNullableString,
+    pub protocol_type: CompactString,
+    /// The assignment strategies the member supports, in order of preference.
+    pub protocols: CompactArray<JoinGroupRequestProtocolV9>,
+    pub reason: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for JoinGroupRequestV9 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::JoinGroup
+    }
+
+    fn get_version() -> ApiVersion {
+        9
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct JoinGroupResponseMemberV9 {
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    /// The member's metadata for the chosen protocol.
+    pub metadata: CompactBytes,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct JoinGroupResponseV9 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub generation_id: i32,
+    pub protocol_type: CompactNullableString,
+    /// The assignment strategy the coordinator chose.
+    pub protocol_name: CompactNullableString,
+    /// The member id of the group leader.
+    pub leader: CompactString,
+    /// True if the broker assigns the partitions itself, and the leader should only sync.
+    pub skip_assignment: bool,
+    /// The member id the coordinator gave this member.
+    pub member_id: CompactString,
+    /// Every member of the group, if this member is the leader.
+    pub members: CompactArray<JoinGroupResponseMemberV9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for JoinGroupResponseV9 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactBytes, CompactNullableString, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::join_group::{JoinGroupRequestProtocolV9, JoinGroupRequestV9, JoinGroupResponseMemberV9, JoinGroupResponseV9, PROTOCOL_TYPE_CONSUMER};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_join_group_request() {
+        let request: JoinGroupRequestV9 = JoinGroupRequestV9 {
+            group_id: CompactString(String::from("g")),
+            session_timeout_ms: 45_000,
+            rebalance_timeout_ms: 300_000,
+            member_id: CompactString(String::new()),
+            group_instance_id: CompactNullableString(None),
+            protocol_type: CompactString(String::from(PROTOCOL_TYPE_CONSUMER)),
+            protocols: CompactArray(vec![JoinGroupRequestProtocolV9 {
+                name: CompactString(String::from("range")),
+                metadata: CompactBytes(vec![1, 2]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            reason: CompactNullableString(None),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![
+            2, 103, 0, 0, 175, 200, 0, 4, 147, 224, 1, 0, 9, 99, 111, 110, 115, 117, 109, 101, 114,
+            2, 6, 114, 97, 110, 103, 101, 3, 1, 2, 0, 0, 0
+        ]);
+        assert_eq!(JoinGroupRequestV9::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_join_group_response_round_trip() {
+        let response: JoinGroupResponseV9 = JoinGroupResponseV9 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            generation_id: 3,
+            protocol_type: CompactNullableString(Some(String::from(PROTOCOL_TYPE_CONSUMER))),
+            protocol_name: CompactNullableString(Some(String::from("range"))),
+            leader: CompactString(String::from("m-1")),
+            skip_assignment: false,
+            member_id: CompactString(String::from("m-1")),
+            members: CompactArray(vec![JoinGroupResponseMemberV9 {
+                member_id: CompactString(String::from("m-1")),
+                group_instance_id: CompactNullableString(None),
+                metadata: CompactBytes(vec![0, 0]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(JoinGroupResponseV9::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
@@ -0,0 +1,113 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct LeaveGroupRequestMemberV5 {
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    /// Why the member is leaving, which the coordinator logs.
+    pub reason: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+/// Version 3 lets several members leave at once, and version 5 added the reason for leaving.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct LeaveGroupRequestV5 {
+    pub group_id: CompactString,
+    pub members: CompactArray<LeaveGroupRequestMemberV5>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for LeaveGroupRequestV5 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::LeaveGroup
+    }
+
+    fn get_version() -> ApiVersion {
+        5
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct LeaveGroupResponseMemberV5 {
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct LeaveGroupResponseV5 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub members: CompactArray<LeaveGroupResponseMemberV5>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for LeaveGroupResponseV5 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::leave_group::{LeaveGroupRequestMemberV5, LeaveGroupRequestV5, LeaveGroupResponseMemberV5, LeaveGroupResponseV5};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_leave_group_request() {
+        let request: LeaveGroupRequestV5 = LeaveGroupRequestV5 {
+            group_id: CompactString(String::from("g")),
+            members: CompactArray(vec![LeaveGroupRequestMemberV5 {
+                member_id: CompactString(String::from("m")),
+                group_instance_id: CompactNullableString(None),
+                reason: CompactNullableString(Some(String::from("r"))),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![2, 103, 2, 2, 109, 0, 2, 114, 0, 0]);
+    }
+
+    #[test]
+    fn test_leave_group_response_round_trip() {
+        let response: LeaveGroupResponseV5 = LeaveGroupResponseV5 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::None,
+            members: CompactArray(vec![LeaveGroupResponseMemberV5 {
+                member_id: CompactString(String::from("m")),
+                group_instance_id: CompactNullableString(None),
+                error_code: ErrorCode::UnknownMemberId,
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(LeaveGroupResponseV5::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
@@ -0,0 +1,469 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{Array, CompactArray, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::fetch::IsolationLevel;
+use crate::protocol::tags::TaggedFields;
+
+/// Looks up the offset after the last record, which is the high watermark, or the last stable
+/// offset when reading committed records.
+pub const LATEST_TIMESTAMP: i64 = -1;
+/// Looks up the log start offset.
+pub const EARLIEST_TIMESTAMP: i64 = -2;
+/// Looks up the offset of the record with the largest timestamp (KIP-734). Needs version 7.
+pub const MAX_TIMESTAMP: i64 = -3;
+
+// request partitions
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestPartitionV0 {
+    pub partition_index: i32,
+    /// A timestamp in milliseconds, or one of the `*_TIMESTAMP` constants.
+    pub timestamp: i64,
+    pub max_num_offsets: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestPartitionV1V3 {
+    pub partition_index: i32,
+    pub timestamp: i64
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestPartitionV4V5 {
+    pub partition_index: i32,
+    /// The leader epoch the consumer knows, which lets a stale leader be fenced, or -1.
+    pub current_leader_epoch: i32,
+    pub timestamp: i64
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestPartitionV6V7 {
+    pub partition_index: i32,
+    pub current_leader_epoch: i32,
+    pub timestamp: i64,
+    pub tag_buffer: TaggedFields
+}
+
+// request topics
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestTopicV0 {
+    pub name: String,
+    pub partitions: Array<ListOffsetsRequestPartitionV0>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestTopicV1V3 {
+    pub name: String,
+    pub partitions: Array<ListOffsetsRequestPartitionV1V3>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestTopicV4V5 {
+    pub name: String,
+    pub partitions: Array<ListOffsetsRequestPartitionV4V5>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestTopicV6V7 {
+    pub name: CompactString,
+    pub partitions: CompactArray<ListOffsetsRequestPartitionV6V7>,
+    pub tag_buffer: TaggedFields
+}
+
+// requests
+/// Version 0 returns up to `max_num_offsets` segment start offsets before the timestamp.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestV0 {
+    pub replica_id: i32,
+    pub topics: Array<ListOffsetsRequestTopicV0>
+}
+
+impl KafkaRequest for ListOffsetsRequestV0 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ListOffsets
+    }
+
+    fn get_version() -> ApiVersion {
+        0
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// Since version 1 the offset of the first record at or after the timestamp is returned.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestV1 {
+    pub replica_id: i32,
+    pub topics: Array<ListOffsetsRequestTopicV1V3>
+}
+
+impl KafkaRequest for ListOffsetsRequestV1 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ListOffsets
+    }
+
+    fn get_version() -> ApiVersion {
+        1
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestV2V3 {
+    pub replica_id: i32,
+    pub isolation_level: IsolationLevel,
+    pub topics: Array<ListOffsetsRequestTopicV1V3>
+}
+
+impl KafkaRequest for ListOffsetsRequestV2V3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ListOffsets
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestV4V5 {
+    pub replica_id: i32,
+    pub isolation_level: IsolationLevel,
+    pub topics: Array<ListOffsetsRequestTopicV4V5>
+}
+
+impl KafkaRequest for ListOffsetsRequestV4V5 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ListOffsets
+    }
+
+    fn get_version() -> ApiVersion {
+        5
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsRequestV6V7 {
+    pub replica_id: i32,
+    pub isolation_level: IsolationLevel,
+    pub topics: CompactArray<ListOffsetsRequestTopicV6V7>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for ListOffsetsRequestV6V7 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::ListOffsets
+    }
+
+    fn get_version() -> ApiVersion {
+        7
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponsePartitionV0 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub old_style_offsets: Array<i64>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseTopicV0 {
+    pub name: String,
+    pub partitions: Array<ListOffsetsResponsePartitionV0>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseV0 {
+    pub topics: Array<ListOffsetsResponseTopicV0>
+}
+
+impl KafkaResponse for ListOffsetsResponseV0 {
+    fn throttle_time_ms(&self) -> i32 {
+        0
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        false
+    }
+}
+
+/// An offset of -1 means no record matched the timestamp.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponsePartitionV1V3 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    /// The timestamp of the record at the offset, or -1.
+    pub timestamp: i64,
+    pub offset: i64
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseTopicV1V3 {
+    pub name: String,
+    pub partitions: Array<ListOffsetsResponsePartitionV1V3>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseV1 {
+    pub topics: Array<ListOffsetsResponseTopicV1V3>
+}
+
+impl KafkaResponse for ListOffsetsResponseV1 {
+    fn throttle_time_ms(&self) -> i32 {
+        0
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseV2V3 {
+    pub throttle_time_ms: i32,
+    pub topics: Array<ListOffsetsResponseTopicV1V3>
+}
+
+impl KafkaResponse for ListOffsetsResponseV2V3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponsePartitionV4V5 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub timestamp: i64,
+    pub offset: i64,
+    /// The leader epoch of the record at the offset, or -1.
+    pub leader_epoch: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseTopicV4V5 {
+    pub name: String,
+    pub partitions: Array<ListOffsetsResponsePartitionV4V5>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseV4V5 {
+    pub throttle_time_ms: i32,
+    pub topics: Array<ListOffsetsResponseTopicV4V5>
+}
+
+impl KafkaResponse for ListOffsetsResponseV4V5 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponsePartitionV6V7 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub timestamp: i64,
+    pub offset: i64,
+    pub leader_epoch: i32,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseTopicV6V7 {
+    pub name: CompactString,
+    pub partitions: CompactArray<ListOffsetsResponsePartitionV6V7>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ListOffsetsResponseV6V7 {
+    pub throttle_time_ms: i32,
+    pub topics: CompactArray<ListOffsetsResponseTopicV6V7>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for ListOffsetsResponseV6V7 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, CompactArray, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::fetch::{CONSUMER_REPLICA_ID, IsolationLevel};
+    use crate::protocol::list_offsets::{EARLIEST_TIMESTAMP, LATEST_TIMESTAMP, ListOffsetsRequestPartitionV0, ListOffsetsRequestPartitionV1V3, ListOffsetsRequestPartitionV4V5, ListOffsetsRequestPartitionV6V7, ListOffsetsRequestTopicV0, ListOffsetsRequestTopicV1V3, ListOffsetsRequestTopicV4V5, ListOffsetsRequestTopicV6V7, ListOffsetsRequestV0, ListOffsetsRequestV2V3, ListOffsetsRequestV4V5, ListOffsetsRequestV6V7, ListOffsetsResponsePartitionV0, ListOffsetsResponsePartitionV1V3, ListOffsetsResponsePartitionV4V5, ListOffsetsResponsePartitionV6V7, ListOffsetsResponseTopicV0, ListOffsetsResponseTopicV1V3, ListOffsetsResponseTopicV4V5, ListOffsetsResponseTopicV6V7, ListOffsetsResponseV0, ListOffsetsResponseV2V3, ListOffsetsResponseV4V5, ListOffsetsResponseV6V7, MAX_TIMESTAMP};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_list_offsets_requests_before_flexible_versions() {
+        let mut v0: Vec<u8> = Vec::new();
+        ListOffsetsRequestV0 {
+            replica_id: CONSUMER_REPLICA_ID,
+            topics: Array(vec![ListOffsetsRequestTopicV0 {
+                name: String::from("t"),
+                partitions: Array(vec![ListOffsetsRequestPartitionV0 { partition_index: 1, timestamp: LATEST_TIMESTAMP, max_num_offsets: 1 }])
+            }])
+        }.to_kafka_bytes(&mut v0).unwrap();
+        assert_eq!(v0, vec![
+            255, 255, 255, 255, 0, 0, 0, 1, 0, 1, 116, 0, 0, 0, 1, 0, 0, 0, 1, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 1
+        ]);
+
+        let mut v3: Vec<u8> = Vec::new();
+        ListOffsetsRequestV2V3 {
+            replica_id: CONSUMER_REPLICA_ID,
+            isolation_level: IsolationLevel::ReadCommitted,
+            topics: Array(vec![ListOffsetsRequestTopicV1V3 {
+                name: String::from("t"),
+                partitions: Array(vec![ListOffsetsRequestPartitionV1V3 { partition_index: 0, timestamp: EARLIEST_TIMESTAMP }])
+            }])
+        }.to_kafka_bytes(&mut v3).unwrap();
+        assert_eq!(v3, vec![255, 255, 255, 255, 1, 0, 0, 0, 1, 0, 1, 116, 0, 0, 0, 1, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 254]);
+
+        let mut v5: Vec<u8> = Vec::new();
+        ListOffsetsRequestV4V5 {
+            replica_id: CONSUMER_REPLICA_ID,
+            isolation_level: IsolationLevel::ReadUncommitted,
+            topics: Array(vec![ListOffsetsRequestTopicV4V5 {
+                name: String::from("t"),
+                partitions: Array(vec![ListOffsetsRequestPartitionV4V5 { partition_index: 0, current_leader_epoch: 3, timestamp: 1000 }])
+            }])
+        }.to_kafka_bytes(&mut v5).unwrap();
+        assert_eq!(&v5[v5.len() - 16..], &[0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 3, 232]);
+    }
+
+    #[test]
+    fn test_encode_list_offsets_request_v7_with_max_timestamp() {
+        let request: ListOffsetsRequestV6V7 = ListOffsetsRequestV6V7 {
+            replica_id: CONSUMER_REPLICA_ID,
+            isolation_level: IsolationLevel::ReadUncommitted,
+            topics: CompactArray(vec![ListOffsetsRequestTopicV6V7 {
+                name: CompactString(String::from("t")),
+                partitions: CompactArray(vec![ListOffsetsRequestPartitionV6V7 {
+                    partition_index: 2,
+                    current_leader_epoch: -1,
+                    timestamp: MAX_TIMESTAMP,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![
+            255, 255, 255, 255, 0, 2, 2, 116, 2, 0, 0, 0, 2, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 253, 0, 0, 0
+        ]);
+        assert_eq!(ListOffsetsRequestV6V7::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_list_offsets_responses_round_trip() {
+        let v0: ListOffsetsResponseV0 = ListOffsetsResponseV0 {
+            topics: Array(vec![ListOffsetsResponseTopicV0 {
+                name: String::from("t"),
+                partitions: Array(vec![ListOffsetsResponsePartitionV0 { partition_index: 0, error_code: ErrorCode::None, old_style_offsets: Array(vec![42, 0]) }])
+            }])
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v0.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(ListOffsetsResponseV0::from_kafka_bytes(&mut &*bytes).unwrap(), v0);
+
+        let v3: ListOffsetsResponseV2V3 = ListOffsetsResponseV2V3 {
+            throttle_time_ms: 0,
+            topics: Array(vec![ListOffsetsResponseTopicV1V3 {
+                name: String::from("t"),
+                partitions: Array(vec![ListOffsetsResponsePartitionV1V3 { partition_index: 0, error_code: ErrorCode::None, timestamp: -1, offset: 42 }])
+            }])
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v3.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(ListOffsetsResponseV2V3::from_kafka_bytes(&mut &*bytes).unwrap(), v3);
+
+        let v5: ListOffsetsResponseV4V5 = ListOffsetsResponseV4V5 {
+            throttle_time_ms: 0,
+            topics: Array(vec![ListOffsetsResponseTopicV4V5 {
+                name: String::from("t"),
+                partitions: Array(vec![ListOffsetsResponsePartitionV4V5 { partition_index: 0, error_code: ErrorCode::OffsetNotAvailable, timestamp: -1, offset: -1, leader_epoch: -1 }])
+            }])
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v5.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(ListOffsetsResponseV4V5::from_kafka_bytes(&mut &*bytes).unwrap(), v5);
+
+        let v7: ListOffsetsResponseV6V7 = ListOffsetsResponseV6V7 {
+            throttle_time_ms: 0,
+            topics: CompactArray(vec![ListOffsetsResponseTopicV6V7 {
+                name: CompactString(String::from("t")),
+                partitions: CompactArray(vec![ListOffsetsResponsePartitionV6V7 {
+                    partition_index: 2,
+                    error_code: ErrorCode::None,
+                    timestamp: 1_700_000_000_000,
+                    offset: 17,
+                    leader_epoch: 4,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v7.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(ListOffsetsResponseV6V7::from_kafka_bytes(&mut &*bytes).unwrap(), v7);
+    }
+}
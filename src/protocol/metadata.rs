@@ -0,0 +1,647 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactNullableString, CompactString, NullableArray, NullableString};
+use kafka_encode_derive::KafkaEncodable;
+use uuid::Uuid;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+/// The value brokers send in an authorized-operations bitfield when the client did not ask for it.
+pub const AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestTopicV0V9 {
+    pub name: String
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV0 {
+    pub topics: Array<MetadataRequestTopicV0V9>
+}
+
+impl KafkaRequest for MetadataRequestV0 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        0
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// A null `topics` array requests metadata for every topic in the cluster.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV1V3 {
+    pub topics: NullableArray<MetadataRequestTopicV0V9>
+}
+
+impl KafkaRequest for MetadataRequestV1V3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV4V7 {
+    pub topics: NullableArray<MetadataRequestTopicV0V9>,
+    pub allow_auto_topic_creation: bool
+}
+
+impl KafkaRequest for MetadataRequestV4V7 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        7
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV8 {
+    pub topics: NullableArray<MetadataRequestTopicV0V9>,
+    pub allow_auto_topic_creation: bool,
+    pub include_cluster_authorized_operations: bool,
+    pub include_topic_authorized_operations: bool
+}
+
+impl KafkaRequest for MetadataRequestV8 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        8
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestTopicV9 {
+    pub name: CompactString,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV9 {
+    pub topics: CompactNullableArray<MetadataRequestTopicV9>,
+    pub allow_auto_topic_creation: bool,
+    pub include_cluster_authorized_operations: bool,
+    pub include_topic_authorized_operations: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for MetadataRequestV9 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        9
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+/// From version 10 onwards, topics may be requested either by name or by id. Unused ids are
+/// sent as `Uuid::nil()`.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestTopicV10V12 {
+    pub topic_id: Uuid,
+    pub name: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV10 {
+    pub topics: CompactNullableArray<MetadataRequestTopicV10V12>,
+    pub allow_auto_topic_creation: bool,
+    pub include_cluster_authorized_operations: bool,
+    pub include_topic_authorized_operations: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for MetadataRequestV10 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        10
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataRequestV11V12 {
+    pub topics: CompactNullableArray<MetadataRequestTopicV10V12>,
+    pub allow_auto_topic_creation: bool,
+    pub include_topic_authorized_operations: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for MetadataRequestV11V12 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Metadata
+    }
+
+    fn get_version() -> ApiVersion {
+        12
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// brokers
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseBrokerV0 {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseBrokerV1V8 {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+    pub rack: NullableString
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseBrokerV9V12 {
+    pub node_id: i32,
+    pub host: CompactString,
+    pub port: i32,
+    pub rack: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+// partitions
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponsePartitionV0V4 {
+    pub error_code: ErrorCode,
+    pub partition_index: i32,
+    pub leader_id: i32,
+    pub replica_nodes: Array<i32>,
+    pub isr_nodes: Array<i32>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponsePartitionV5V6 {
+    pub error_code: ErrorCode,
+    pub partition_index: i32,
+    pub leader_id: i32,
+    pub replica_nodes: Array<i32>,
+    pub isr_nodes: Array<i32>,
+    pub offline_replicas: Array<i32>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponsePartitionV7V8 {
+    pub error_code: ErrorCode,
+    pub partition_index: i32,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub replica_nodes: Array<i32>,
+    pub isr_nodes: Array<i32>,
+    pub offline_replicas: Array<i32>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponsePartitionV9V12 {
+    pub error_code: ErrorCode,
+    pub partition_index: i32,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub replica_nodes: CompactArray<i32>,
+    pub isr_nodes: CompactArray<i32>,
+    pub offline_replicas: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+// topics
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV0 {
+    pub error_code: ErrorCode,
+    pub name: String,
+    pub partitions: Array<MetadataResponsePartitionV0V4>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV1V4 {
+    pub error_code: ErrorCode,
+    pub name: String,
+    pub is_internal: bool,
+    pub partitions: Array<MetadataResponsePartitionV0V4>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV5V6 {
+    pub error_code: ErrorCode,
+    pub name: String,
+    pub is_internal: bool,
+    pub partitions: Array<MetadataResponsePartitionV5V6>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV7 {
+    pub error_code: ErrorCode,
+    pub name: String,
+    pub is_internal: bool,
+    pub partitions: Array<MetadataResponsePartitionV7V8>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV8 {
+    pub error_code: ErrorCode,
+    pub name: String,
+    pub is_internal: bool,
+    pub partitions: Array<MetadataResponsePartitionV7V8>,
+    pub topic_authorized_operations: i32
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV9 {
+    pub error_code: ErrorCode,
+    pub name: CompactString,
+    pub is_internal: bool,
+    pub partitions: CompactArray<MetadataResponsePartitionV9V12>,
+    pub topic_authorized_operations: i32,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseTopicV10V12 {
+    pub error_code: ErrorCode,
+    pub name: CompactNullableString,
+    pub topic_id: Uuid,
+    pub is_internal: bool,
+    pub partitions: CompactArray<MetadataResponsePartitionV9V12>,
+    pub topic_authorized_operations: i32,
+    pub tag_buffer: TaggedFields
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV0 {
+    pub brokers: Array<MetadataResponseBrokerV0>,
+    pub topics: Array<MetadataResponseTopicV0>
+}
+
+impl KafkaResponse for MetadataResponseV0 {
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV1 {
+    pub brokers: Array<MetadataResponseBrokerV1V8>,
+    pub controller_id: i32,
+    pub topics: Array<MetadataResponseTopicV1V4>
+}
+
+impl KafkaResponse for MetadataResponseV1 {
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV2 {
+    pub brokers: Array<MetadataResponseBrokerV1V8>,
+    pub cluster_id: NullableString,
+    pub controller_id: i32,
+    pub topics: Array<MetadataResponseTopicV1V4>
+}
+
+impl KafkaResponse for MetadataResponseV2 {
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV3V4 {
+    pub throttle_time_ms: i32,
+    pub brokers: Array<MetadataResponseBrokerV1V8>,
+    pub cluster_id: NullableString,
+    pub controller_id: i32,
+    pub topics: Array<MetadataResponseTopicV1V4>
+}
+
+impl KafkaResponse for MetadataResponseV3V4 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV5V6 {
+    pub throttle_time_ms: i32,
+    pub brokers: Array<MetadataResponseBrokerV1V8>,
+    pub cluster_id: NullableString,
+    pub controller_id: i32,
+    pub topics: Array<MetadataResponseTopicV5V6>
+}
+
+impl KafkaResponse for MetadataResponseV5V6 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV7 {
+    pub throttle_time_ms: i32,
+    pub brokers: Array<MetadataResponseBrokerV1V8>,
+    pub cluster_id: NullableString,
+    pub controller_id: i32,
+    pub topics: Array<MetadataResponseTopicV7>
+}
+
+impl KafkaResponse for MetadataResponseV7 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV8 {
+    pub throttle_time_ms: i32,
+    pub brokers: Array<MetadataResponseBrokerV1V8>,
+    pub cluster_id: NullableString,
+    pub controller_id: i32,
+    pub topics: Array<MetadataResponseTopicV8>,
+    pub cluster_authorized_operations: i32
+}
+
+impl KafkaResponse for MetadataResponseV8 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV9 {
+    pub throttle_time_ms: i32,
+    pub brokers: CompactArray<MetadataResponseBrokerV9V12>,
+    pub cluster_id: CompactNullableString,
+    pub controller_id: i32,
+    pub topics: CompactArray<MetadataResponseTopicV9>,
+    pub cluster_authorized_operations: i32,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for MetadataResponseV9 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV10 {
+    pub throttle_time_ms: i32,
+    pub brokers: CompactArray<MetadataResponseBrokerV9V12>,
+    pub cluster_id: CompactNullableString,
+    pub controller_id: i32,
+    pub topics: CompactArray<MetadataResponseTopicV10V12>,
+    pub cluster_authorized_operations: i32,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for MetadataResponseV10 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+// latest version of the response
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct MetadataResponseV11V12 {
+    pub throttle_time_ms: i32,
+    pub brokers: CompactArray<MetadataResponseBrokerV9V12>,
+    pub cluster_id: CompactNullableString,
+    pub controller_id: i32,
+    pub topics: CompactArray<MetadataResponseTopicV10V12>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for MetadataResponseV11V12 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactNullableString, CompactString};
+    use uuid::Uuid;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::metadata::{MetadataRequestTopicV0V9, MetadataRequestTopicV10V12, MetadataRequestV0, MetadataRequestV11V12, MetadataResponseBrokerV0, MetadataResponseBrokerV9V12, MetadataResponsePartitionV0V4, MetadataResponsePartitionV9V12, MetadataResponseTopicV0, MetadataResponseTopicV10V12, MetadataResponseV0, MetadataResponseV11V12};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_metadata_request_v0() {
+        let request: MetadataRequestV0 = MetadataRequestV0 {
+            topics: Array(vec![MetadataRequestTopicV0V9 { name: String::from("foo") }])
+        };
+        let mut writer = Vec::new().writer();
+        request.to_kafka_bytes(&mut writer).unwrap();
+        assert_eq!(writer.into_inner(), vec![0, 0, 0, 1, 0, 3, 102, 111, 111]);
+    }
+
+    #[test]
+    fn test_encode_metadata_request_v12() {
+        let request: MetadataRequestV11V12 = MetadataRequestV11V12 {
+            topics: CompactNullableArray(Some(vec![
+                MetadataRequestTopicV10V12 {
+                    topic_id: Uuid::nil(),
+                    name: CompactNullableString(Some(String::from("foo"))),
+                    tag_buffer: TaggedFields::new()
+                }
+            ])),
+            allow_auto_topic_creation: false,
+            include_topic_authorized_operations: true,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut writer = Vec::new().writer();
+        request.to_kafka_bytes(&mut writer).unwrap();
+        assert_eq!(
+            writer.into_inner(),
+            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                 4, 102, 111, 111, 0, 0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn test_encode_metadata_request_v12_for_all_topics() {
+        let request: MetadataRequestV11V12 = MetadataRequestV11V12 {
+            topics: CompactNullableArray(None),
+            allow_auto_topic_creation: true,
+            include_topic_authorized_operations: false,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut writer = Vec::new().writer();
+        request.to_kafka_bytes(&mut writer).unwrap();
+        assert_eq!(writer.into_inner(), vec![0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_metadata_response_v0() {
+        let bytes: Vec<u8> = vec![
+            // brokers
+            0, 0, 0, 1, 0, 0, 0, 1, 0, 9, 108, 111, 99, 97, 108, 104, 111, 115, 116, 0, 0, 35, 132,
+            // topics
+            0, 0, 0, 1, 0, 0, 0, 3, 102, 111, 111,
+            // partitions
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1
+        ];
+        let response: MetadataResponseV0 = MetadataResponseV0::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(
+            response,
+            MetadataResponseV0 {
+                brokers: Array(vec![
+                    MetadataResponseBrokerV0 { node_id: 1, host: String::from("localhost"), port: 9092 }
+                ]),
+                topics: Array(vec![
+                    MetadataResponseTopicV0 {
+                        error_code: ErrorCode::None,
+                        name: String::from("foo"),
+                        partitions: Array(vec![
+                            MetadataResponsePartitionV0V4 {
+                                error_code: ErrorCode::None,
+                                partition_index: 0,
+                                leader_id: 1,
+                                replica_nodes: Array(vec![1]),
+                                isr_nodes: Array(vec![1])
+                            }
+                        ])
+                    }
+                ])
+            }
+        );
+    }
+
+    #[test]
+    fn test_metadata_response_v12_round_trip() {
+        let topic_id: Uuid = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+        let response: MetadataResponseV11V12 = MetadataResponseV11V12 {
+            throttle_time_ms: 0,
+            brokers: CompactArray(vec![
+                MetadataResponseBrokerV9V12 {
+                    node_id: 1,
+                    host: CompactString(String::from("localhost")),
+                    port: 9092,
+                    rack: CompactNullableString(Some(String::from("rack-a"))),
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            cluster_id: CompactNullableString(Some(String::from("cluster"))),
+            controller_id: 1,
+            topics: CompactArray(vec![
+                MetadataResponseTopicV10V12 {
+                    error_code: ErrorCode::None,
+                    name: CompactNullableString(Some(String::from("foo"))),
+                    topic_id,
+                    is_internal: false,
+                    partitions: CompactArray(vec![
+                        MetadataResponsePartitionV9V12 {
+                            error_code: ErrorCode::LeaderNotAvailable,
+                            partition_index: 0,
+                            leader_id: -1,
+                            leader_epoch: 4,
+                            replica_nodes: CompactArray(vec![1, 2]),
+                            isr_nodes: CompactArray(vec![1]),
+                            offline_replicas: CompactArray(vec![2]),
+                            tag_buffer: TaggedFields::new()
+                        }
+                    ]),
+                    topic_authorized_operations: 0b1_1111_1000,
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            tag_buffer: TaggedFields::new()
+        };
+
+        let mut writer = Vec::new().writer();
+        response.clone().to_kafka_bytes(&mut writer).unwrap();
+        let bytes: Vec<u8> = writer.into_inner();
+        let decoded: MetadataResponseV11V12 = MetadataResponseV11V12::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(decoded, response);
+    }
+}
@@ -0,0 +1,193 @@
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use anyhow::{anyhow, Result};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::CompactArray;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::api_versions::{ApiVersionsRequestV3, ApiVersionsResponseV3ByKeyId, SupportedApiKeyIdVersionsV3};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::headers::{RequestHeaderV1, RequestHeaderV2, ResponseHeaderV0, ResponseHeaderV1};
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::tags::TaggedFields;
+
+/// A request frame received by a `MockBroker`, without its size prefix.
+#[derive(Debug, Clone)]
+pub(crate) struct MockRequest {
+    pub api_key: i16,
+    pub api_version: ApiVersion,
+    pub correlation_id: i32,
+    pub frame: Vec<u8>
+}
+
+impl MockRequest {
+    fn from_frame(frame: Vec<u8>) -> Result<Self> {
+        let mut reader: &[u8] = &frame;
+        Ok(MockRequest {
+            api_key: i16::from_kafka_bytes(&mut reader)?,
+            api_version: i16::from_kafka_bytes(&mut reader)?,
+            correlation_id: i32::from_kafka_bytes(&mut reader)?,
+            frame
+        })
+    }
+
+    pub(crate) fn decode<Request: KafkaRequest>(&self) -> Result<Request> {
+        if self.api_version != Request::get_version() {
+            return Err(anyhow!("Expected version {} of API key {}, but the request is version {}", Request::get_version(), self.api_key, self.api_version));
+        }
+        let mut reader: &[u8] = &self.frame;
+        match Request::get_request_header_version() {
+            1 => { RequestHeaderV1::from_kafka_bytes(&mut reader)?; },
+            2 => { RequestHeaderV2::from_kafka_bytes(&mut reader)?; },
+            any_other_version => return Err(anyhow!("Unrecognized request header version: {}", any_other_version))
+        }
+        Request::from_kafka_bytes(&mut reader)
+    }
+
+    /// Encodes a response frame, with the header that `Request` expects, for this request.
+    pub(crate) fn respond<Request: KafkaRequest, Response: KafkaResponse>(&self, response: Response) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        match Request::get_response_header_version() {
+            0 => ResponseHeaderV0 { correlation_id: self.correlation_id }.to_kafka_bytes(&mut bytes).unwrap(),
+            _ => ResponseHeaderV1 { correlation_id: self.correlation_id, tag_buffer: TaggedFields::new() }
+                .to_kafka_bytes(&mut bytes).unwrap()
+        }
+        response.to_kafka_bytes(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Answers an ApiVersions request as a broker which supports each of `api_keys` from version 0
+    /// up to the version given.
+    pub(crate) fn respond_with_api_versions(&self, api_keys: &[(ApiKey, ApiVersion)]) -> Vec<u8> {
+        self.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3ByKeyId>(ApiVersionsResponseV3ByKeyId {
+            error_code: ErrorCode::None,
+            api_keys: CompactArray(api_keys.iter()
+                .map(|(api_key, max_version)| SupportedApiKeyIdVersionsV3 {
+                    api_key: api_key.clone() as i16,
+                    min_version: 0,
+                    max_version: *max_version,
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            throttle_time_ms: 0,
+            tag_buffer: TaggedFields::new()
+        })
+    }
+}
+
+type MockHandler = dyn Fn(&MockRequest) -> Option<Vec<u8>> + Send + Sync;
+
+/// A stand-in for a broker which listens on a local port and answers each request with whatever
+/// the handler returns. Returning `None` closes the connection, and returning an empty response
+/// sends nothing back, as for a Produce request with acks=0.
+pub(crate) struct MockBroker {
+    pub address: SocketAddr,
+    accepted_connections: Arc<AtomicUsize>,
+    stopped: Arc<AtomicBool>
+}
+
+impl MockBroker {
+    pub(crate) fn start<F>(handler: F) -> Self where F: Fn(&MockRequest) -> Option<Vec<u8>> + Send + Sync + 'static {
+        MockBroker::start_with_tls(None, handler)
+    }
+
+    /// Like `start`, but answers ApiVersions requests itself, as a broker which supports each of
+    /// `api_keys` up to the version given, for clients which negotiate versions before sending.
+    pub(crate) fn start_with_api_versions<F>(api_keys: Vec<(ApiKey, ApiVersion)>, handler: F) -> Self
+        where F: Fn(&MockRequest) -> Option<Vec<u8>> + Send + Sync + 'static {
+        MockBroker::start(move |request: &MockRequest| match request.api_key == ApiKey::ApiVersions as i16 {
+            true => Some(request.respond_with_api_versions(&api_keys)),
+            false => handler(request)
+        })
+    }
+
+    /// Like `start`, but every connection must begin with a TLS handshake.
+    pub(crate) fn start_with_tls<F>(tls: Option<Arc<ServerConfig>>, handler: F) -> Self
+        where F: Fn(&MockRequest) -> Option<Vec<u8>> + Send + Sync + 'static {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let accepted_connections: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let stopped: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+        let handler: Arc<MockHandler> = Arc::new(handler);
+
+        let thread_accepted_connections: Arc<AtomicUsize> = accepted_connections.clone();
+        let thread_stopped: Arc<AtomicBool> = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                let stream: TcpStream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                thread_accepted_connections.fetch_add(1, Ordering::SeqCst);
+                let handler: Arc<MockHandler> = handler.clone();
+                match &tls {
+                    Some(tls) => {
+                        let connection: ServerConnection = ServerConnection::new(tls.clone()).unwrap();
+                        thread::spawn(move || serve_connection(StreamOwned::new(connection, stream), handler));
+                    },
+                    None => {
+                        thread::spawn(move || serve_connection(stream, handler));
+                    }
+                }
+            }
+        });
+
+        MockBroker {
+            address,
+            accepted_connections,
+            stopped
+        }
+    }
+
+    pub(crate) fn port(&self) -> i32 {
+        self.address.port() as i32
+    }
+
+    pub(crate) fn accepted_connections(&self) -> usize {
+        self.accepted_connections.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake the accept loop so it notices it has been stopped
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+fn serve_connection<S: Read + Write + Debug>(mut stream: S, handler: Arc<MockHandler>) {
+    loop {
+        let frame_size: i32 = match i32::from_kafka_bytes(&mut stream) {
+            Ok(frame_size) => frame_size,
+            Err(_) => return
+        };
+        let mut frame: Vec<u8> = vec![0; frame_size as usize];
+        if stream.read_exact(&mut frame).is_err() {
+            return;
+        }
+        let request: MockRequest = match MockRequest::from_frame(frame) {
+            Ok(request) => request,
+            Err(_) => return
+        };
+        let response: Vec<u8> = match handler(&request) {
+            Some(response) => response,
+            None => return
+        };
+        if response.is_empty() {
+            continue;
+        }
+        let mut bytes: Vec<u8> = (response.len() as i32).to_be_bytes().to_vec();
+        bytes.extend(response);
+        if stream.write_all(&bytes).and_then(|_| stream.flush()).is_err() {
+            return;
+        }
+    }
+}
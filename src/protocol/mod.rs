@@ -1,39 +1,88 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::intrinsics::write_bytes;
-use std::io::{Error, ErrorKind};
 use std::iter::Iterator;
-use std::net::TcpStream;
-use bytes::{BufMut, Bytes, BytesMut};
-use crate::protocol::err::ErrorCode;
-use kafka_encode_derive::KafkaEncodable;
 use kafka_encode::KafkaEncodable;
-use kafka_encode::primitives::*;
 use crate::protocol::api_key::ApiKey;
+use crate::protocol::api_versions::ApiVersionsResponseV3ByKeyId;
 
-mod err;
+pub mod err;
 #[cfg(test)]
 mod tests;
-mod api_key;
+#[cfg(test)]
+pub(crate) mod mock_broker;
+pub mod api_key;
 mod headers;
-mod records;
-mod tags;
-mod produce;
-mod api_versions;
+pub mod records;
+pub mod compression;
+pub mod tags;
+pub mod produce;
+pub mod init_producer_id;
+pub mod find_coordinator;
+pub mod join_group;
+pub mod sync_group;
+pub mod heartbeat;
+pub mod leave_group;
+pub mod consumer_group_heartbeat;
+pub mod consumer_group_describe;
+pub mod consumer_protocol;
+pub mod add_partitions_to_txn;
+pub mod add_offsets_to_txn;
+pub mod end_txn;
+pub mod txn_offset_commit;
+pub mod offset_commit;
+pub mod offset_fetch;
+pub mod api_versions;
 mod requests;
-mod networking;
+pub(crate) mod networking;
+pub mod metadata;
+pub mod fetch;
+pub mod list_offsets;
+pub mod sasl_handshake;
+pub mod sasl_authenticate;
 
 pub(crate) type ApiVersion = i16;
 
 pub(crate) trait KafkaRequest: KafkaEncodable + Debug + PartialEq {
     fn get_api_key() -> ApiKey;
     fn get_version() -> ApiVersion;
+    fn get_request_header_version() -> ApiVersion;
     fn get_response_header_version() -> ApiVersion;
 }
 
 pub(crate) trait KafkaResponse: KafkaEncodable + Debug + PartialEq {
+    /// How long the broker throttled this client for exceeding a quota, or 0 for responses which
+    /// don't carry a throttle time.
+    fn throttle_time_ms(&self) -> i32 {
+        0
+    }
+
+    /// Whether the client must hold off sending on the connection for the throttle time (KIP-219).
+    /// Before each response's KIP-219 version the broker delayed the response itself instead.
+    fn should_client_throttle(&self) -> bool {
+        false
+    }
 }
 
-struct SupportedApiVersions {
+/// The versions of each API that a broker supports, from its ApiVersions response.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct SupportedApiVersions {
+    versions: HashMap<i16, (ApiVersion, ApiVersion)>
+}
+
+impl SupportedApiVersions {
+    /// The highest of `versions` that the broker supports for `api_key`, if it supports any of them.
+    pub(crate) fn highest_common_version(&self, api_key: ApiKey, versions: &[ApiVersion]) -> Option<ApiVersion> {
+        let (min_version, max_version): (ApiVersion, ApiVersion) = *self.versions.get(&(api_key as i16))?;
+        versions.iter().copied().filter(|version| (min_version..=max_version).contains(version)).max()
+    }
+}
 
+impl From<ApiVersionsResponseV3ByKeyId> for SupportedApiVersions {
+    fn from(response: ApiVersionsResponseV3ByKeyId) -> Self {
+        SupportedApiVersions {
+            versions: response.api_keys.0.into_iter()
+                .map(|api_key| (api_key.api_key, (api_key.min_version, api_key.max_version)))
+                .collect()
+        }
+    }
 }
\ No newline at end of file
@@ -1,24 +1,42 @@
 use std::fmt::Debug;
+#[cfg(test)]
 use ctor::ctor;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use bytes::Buf;
 use bytes::buf::Reader;
-use tracing::{debug, info, instrument, Level, trace};
+use tracing::{debug, instrument, trace};
+#[cfg(test)]
+use tracing::Level;
+#[cfg(test)]
 use tracing_subscriber::fmt::format;
-use tracing_subscriber::fmt::format::FmtSpan;
 use kafka_encode::KafkaEncodable;
-use kafka_encode::primitives::{CompactString, NullableString, VarArray};
+use kafka_encode::primitives::{CompactString, NullableString};
+#[cfg(test)]
+use kafka_encode::primitives::VarArray;
 use anyhow::{anyhow, Result};
+use thiserror::Error;
+#[cfg(test)]
 use rand::RngCore;
+#[cfg(test)]
 use rand::rngs::ThreadRng;
-use crate::protocol::api_key::ApiKey;
-use crate::protocol::api_versions::{ApiVersionsRequestV3, ApiVersionsResponseV3};
-use crate::protocol::headers::{RequestHeaderV2, ResponseHeader, ResponseHeaderV0, ResponseHeaderV1};
-use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::bootstrap::ResolvedAddress;
+#[cfg(test)]
+use crate::bootstrap::{ClientDnsLookup, ResolvedAddresses};
+use crate::sasl::SaslMechanism;
+use crate::tls::{TlsConnector, TlsStream};
+use crate::protocol::api_versions::{ApiVersionsRequestV3, ApiVersionsResponseV3ByKeyId};
+#[cfg(test)]
+use crate::protocol::api_versions::ApiVersionsResponseV3;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::headers::{RequestHeaderV1, RequestHeaderV2, ResponseHeaderV0, ResponseHeaderV1};
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse, SupportedApiVersions};
 use crate::protocol::requests::PairWithI32EncodedSize;
 use crate::protocol::tags::TaggedFields;
 
+#[cfg(test)]
 #[ctor]
 fn print_spans_and_events_during_tests() {
     tracing_subscriber::fmt::fmt()
@@ -38,62 +56,458 @@ fn deserialize_response_header_and_get_correlation_id<R: Read + Debug>(mut reade
     }
 }
 
+fn serialize_request_with_header<W: Write + Debug, Request: KafkaRequest>(writer: &mut W, request: Request, correlation_id: i32, client_id: &str) -> Result<()> {
+    match Request::get_request_header_version() {
+        1 => {
+            let header: RequestHeaderV1 = RequestHeaderV1 {
+                request_api_key: Request::get_api_key(),
+                request_api_version: Request::get_version(),
+                correlation_id,
+                client_id: NullableString(Some(String::from(client_id)))
+            };
+            PairWithI32EncodedSize(header, request).to_kafka_bytes(writer)
+        },
+        2 => {
+            let header: RequestHeaderV2 = RequestHeaderV2 {
+                request_api_key: Request::get_api_key(),
+                request_api_version: Request::get_version(),
+                correlation_id,
+                client_id: NullableString(Some(String::from(client_id))),
+                tag_buffer: TaggedFields::new()
+            };
+            PairWithI32EncodedSize(header, request).to_kafka_bytes(writer)
+        },
+        any_other_version => Err(anyhow!("Unrecognized request header version: {}", any_other_version))
+    }
+}
+
+#[cfg(test)]
 #[derive(Debug)]
 pub(crate) struct SingleUseKafkaNetworkingClient<'a> {
-    pub bootstrap_url: &'a str,
+    /// A comma-separated list of `host:port` pairs.
+    pub bootstrap_servers: &'a str,
     pub client_id: &'a str,
+    pub client_dns_lookup: ClientDnsLookup,
+    /// `request.timeout.ms`: how long to wait for the response before giving up.
+    pub request_timeout: Duration,
+    /// Resolved the first time a request is sent.
+    bootstrap_addresses: Option<ResolvedAddresses>,
     random: ThreadRng
 }
 
+#[cfg(test)]
 impl<'a> SingleUseKafkaNetworkingClient<'a> {
-    fn new(bootstrap_url: &'a str, client_id: &'a str) -> Self {
+    pub(crate) fn new(bootstrap_servers: &'a str, client_id: &'a str) -> Self {
         SingleUseKafkaNetworkingClient {
-            bootstrap_url,
+            bootstrap_servers,
             client_id,
+            client_dns_lookup: ClientDnsLookup::default(),
+            request_timeout: Duration::from_millis(30_000),
+            bootstrap_addresses: None,
             random: rand::thread_rng()
         }
     }
 
     #[instrument]
-    fn send_request_and_get_response<Request: KafkaRequest, Response: KafkaResponse>(&mut self, request: Request) -> Result<Response> {
-        let mut tcp_stream: TcpStream = TcpStream::connect(self.bootstrap_url)?;
+    pub(crate) fn send_request_and_get_response<Request: KafkaRequest, Response: KafkaResponse>(&mut self, request: Request) -> Result<Response> {
+        if self.bootstrap_addresses.is_none() {
+            self.bootstrap_addresses = Some(ResolvedAddresses::resolve_bootstrap_servers(self.bootstrap_servers, self.client_dns_lookup)?);
+        }
+        let mut tcp_stream: TcpStream = self.bootstrap_addresses.as_mut()
+            .expect("Bootstrap addresses were just resolved")
+            .try_each(|resolved| Ok(TcpStream::connect(resolved.address)?))?;
+        tcp_stream.set_read_timeout(Some(self.request_timeout))?;
+        tcp_stream.set_write_timeout(Some(self.request_timeout))?;
         let correlation_id = self.random.next_u32() as i32;
+        send_request_and_receive_response(&mut tcp_stream, request, correlation_id, self.client_id)
+    }
+}
 
-        let header: RequestHeaderV2 = RequestHeaderV2 {
-            request_api_key: Request::get_api_key(),
-            request_api_version: Request::get_version(),
-            correlation_id,
-            client_id: NullableString(Some(String::from(self.client_id))),
-            tag_buffer: TaggedFields::new()
-        };
+/// Writes one request to the stream and blocks until its response has been read.
+fn send_request_and_receive_response<S: Read + Write + Debug, Request: KafkaRequest, Response: KafkaResponse>(
+    stream: &mut S, request: Request, correlation_id: i32, client_id: &str) -> Result<Response> {
+    serialize_request_with_header(stream, request, correlation_id, client_id)?;
+    receive_response::<S, Request, Response>(stream, correlation_id)
+}
+
+/// Blocks until the response to the request with `correlation_id` has been read. Brokers answer the
+/// requests on a connection in the order they were sent.
+fn receive_response<S: Read + Debug, Request: KafkaRequest, Response: KafkaResponse>(stream: &mut S, correlation_id: i32) -> Result<Response> {
+    let response_size: usize = u32::from_kafka_bytes(stream)? as usize;
+
+    let mut response_vec: Vec<u8> = vec![0u8; response_size];
+    stream.read_exact(response_vec.as_mut_slice())?;
+    debug!("response_vec: {:?}", response_vec);
+    let mut response_reader: Reader<&[u8]> = response_vec.reader();
+
+    // decode errors lose their io::Error, so that a response which can't be read isn't mistaken
+    // for a broken connection
+    let response_correlation_id: i32 = deserialize_response_header_and_get_correlation_id(
+        &mut response_reader, Request::get_response_header_version()
+    ).map_err(|e| anyhow!("Failed to decode the {:?} response header: {:#}", Request::get_api_key(), e))?;
+
+    if response_correlation_id != correlation_id {
+        return Err(
+            anyhow!("Correlation id mismatch. Request correlation id was {} and response correlation id was {}",
+                    correlation_id, response_correlation_id)
+        );
+    }
+
+    let response = Response::from_kafka_bytes(&mut response_reader)
+        .map_err(|e| anyhow!("Failed to decode the {:?} response: {:#}", Request::get_api_key(), e))?;
+    trace!("response: {:?}", response);
+    Ok(response)
+}
+
+/// Failures of the connection to a broker, rather than errors returned by the broker.
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("No response from node {node_id} within the request timeout of {timeout:?}")]
+    RequestTimedOut { node_id: i32, timeout: Duration },
+    #[error("Node {node_id} is waiting to reconnect after {failed_attempts} failed attempts")]
+    NodeBackingOff { node_id: i32, failed_attempts: u32 },
+    #[error("Node {node_id} asked for nothing to be sent to it for another {remaining:?}")]
+    NodeThrottled { node_id: i32, remaining: Duration },
+    #[error("No brokers are available to send a request to")]
+    NoNodeAvailable
+}
+
+/// Whether an error was caused by a socket read or write running out of time.
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.downcast_ref::<std::io::Error>()
+        .is_some_and(|io_error| io_error.kind() == ErrorKind::TimedOut))
+}
+
+/// A connection to a broker, either plaintext or wrapped in TLS.
+#[derive(Debug)]
+pub(crate) enum KafkaStream {
+    Plaintext(TcpStream),
+    Tls(Box<TlsStream>)
+}
+
+impl Read for KafkaStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            KafkaStream::Plaintext(stream) => stream.read(buf),
+            KafkaStream::Tls(stream) => stream.read(buf)
+        }
+    }
+}
 
-        let pair: PairWithI32EncodedSize<RequestHeaderV2, Request> = PairWithI32EncodedSize(header, request);
-        pair.to_kafka_bytes(&mut tcp_stream)?;
-        let response_size: usize = u32::from_kafka_bytes(&mut tcp_stream).unwrap() as usize;
+impl KafkaStream {
+    fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            KafkaStream::Plaintext(stream) => stream,
+            KafkaStream::Tls(stream) => &stream.sock
+        }
+    }
+}
 
-        let mut response_vec: Vec<u8> = vec![0u8; response_size];
-        tcp_stream.read(response_vec.as_mut_slice()).unwrap();
-        debug!("response_vec: {:?}", response_vec);
-        let mut response_reader: Reader<&[u8]> = response_vec.reader();
+/// Bounds every read and write on a stream by a deadline, failing with `ErrorKind::TimedOut` once
+/// it has passed.
+#[derive(Debug)]
+struct DeadlineStream<'a> {
+    stream: &'a mut KafkaStream,
+    deadline: Instant
+}
 
-        let response_correlation_id: i32 = deserialize_response_header_and_get_correlation_id(
-            &mut response_reader, Request::get_response_header_version()
-        )?;
+impl DeadlineStream<'_> {
+    fn set_socket_timeouts(&self) -> std::io::Result<()> {
+        let remaining: Duration = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(ErrorKind::TimedOut, "The request deadline has passed"));
+        }
+        self.stream.tcp_stream().set_read_timeout(Some(remaining))?;
+        self.stream.tcp_stream().set_write_timeout(Some(remaining))
+    }
+}
+
+/// Sockets report an expired timeout as `WouldBlock` on some platforms.
+fn timed_out_as_error_kind(error: std::io::Error) -> std::io::Error {
+    match error.kind() {
+        ErrorKind::WouldBlock => std::io::Error::new(ErrorKind::TimedOut, error),
+        _ => error
+    }
+}
 
-        if response_correlation_id != correlation_id {
-            return Err(
-                anyhow!("Correlation id mismatch. Request correlation id was {} and response correlation id was {}",
-                        correlation_id, response_correlation_id)
-            );
+impl Read for DeadlineStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.set_socket_timeouts()?;
+        self.stream.read(buf).map_err(timed_out_as_error_kind)
+    }
+}
+
+impl Write for DeadlineStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.set_socket_timeouts()?;
+        self.stream.write(buf).map_err(timed_out_as_error_kind)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.set_socket_timeouts()?;
+        self.stream.flush().map_err(timed_out_as_error_kind)
+    }
+}
+
+impl Write for KafkaStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            KafkaStream::Plaintext(stream) => stream.write(buf),
+            KafkaStream::Tls(stream) => stream.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            KafkaStream::Plaintext(stream) => stream.flush(),
+            KafkaStream::Tls(stream) => stream.flush()
         }
+    }
+}
 
-        let response = Response::from_kafka_bytes(&mut response_reader)?;
-        trace!("response: {:?}", response);
-        Ok(response)
+/// How connections to brokers are opened and authenticated.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    pub client_id: String,
+    /// `socket.connection.setup.timeout.ms`
+    pub connection_setup_timeout: Duration,
+    /// `request.timeout.ms`: how long to wait for each response before the request fails and the
+    /// connection is abandoned.
+    pub request_timeout: Duration,
+    /// Connections use TLS when this is set, as with `security.protocol=SSL`.
+    pub tls: Option<TlsConnector>,
+    /// Connections authenticate with this mechanism as soon as they are opened, as with
+    /// `security.protocol=SASL_PLAINTEXT` or `SASL_SSL`.
+    pub sasl: Option<Arc<dyn SaslMechanism>>
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            client_id: String::from("kafkart"),
+            connection_setup_timeout: Duration::from_millis(10_000),
+            request_timeout: Duration::from_millis(30_000),
+            tls: None,
+            sasl: None
+        }
     }
 }
 
+/// A long-lived connection to one broker. Requests are sent one at a time, and each waits for its
+/// response before the next can be sent.
+#[derive(Debug)]
+pub(crate) struct KafkaConnection {
+    pub node_id: i32,
+    client_id: String,
+    request_timeout: Duration,
+    stream: KafkaStream,
+    next_correlation_id: i32,
+    last_used: Instant,
+    sasl: Option<Arc<dyn SaslMechanism>>,
+    /// When the broker will close the connection unless it re-authenticates, if SASL authentication
+    /// granted a limited session.
+    session_expires_at: Option<Instant>,
+    /// When the next request should first re-authenticate the connection (KIP-368).
+    reauthenticate_at: Option<Instant>,
+    /// Until when the broker asked for nothing more to be sent on this connection (KIP-219).
+    throttled_until: Option<Instant>,
+    /// The API versions the broker supports, once they have been asked for.
+    api_versions: Option<SupportedApiVersions>
+}
+
+impl KafkaConnection {
+    /// Connects to a resolved address, performing a TLS handshake against the address's hostname
+    /// if TLS is configured, and then authenticating if SASL is configured.
+    pub(crate) fn connect(node_id: i32, address: &ResolvedAddress, config: &ConnectionConfig) -> Result<Self> {
+        let timeout: Duration = config.connection_setup_timeout;
+        let tcp_stream: TcpStream = TcpStream::connect_timeout(&address.address, timeout)?;
+        tcp_stream.set_nodelay(true)?;
+        let stream: KafkaStream = match &config.tls {
+            Some(tls) => {
+                // bound the handshake by the same timeout as the connection itself
+                tcp_stream.set_read_timeout(Some(timeout))?;
+                tcp_stream.set_write_timeout(Some(timeout))?;
+                let tls_stream: TlsStream = tls.connect(&address.host, tcp_stream)?;
+                tls_stream.sock.set_read_timeout(None)?;
+                tls_stream.sock.set_write_timeout(None)?;
+                KafkaStream::Tls(Box::new(tls_stream))
+            },
+            None => KafkaStream::Plaintext(tcp_stream)
+        };
+        debug!("Connected to node {} at {} ({})", node_id, address.address, address.host);
+        let mut connection: KafkaConnection = KafkaConnection {
+            node_id,
+            client_id: config.client_id.clone(),
+            request_timeout: config.request_timeout,
+            stream,
+            next_correlation_id: 0,
+            last_used: Instant::now(),
+            sasl: config.sasl.clone(),
+            session_expires_at: None,
+            reauthenticate_at: None,
+            throttled_until: None,
+            api_versions: None
+        };
+        connection.authenticate()?;
+        Ok(connection)
+    }
+
+    /// Runs a SASL exchange if SASL is configured, either on a new connection or to re-authenticate
+    /// an existing one before its session expires.
+    fn authenticate(&mut self) -> Result<()> {
+        let Some(mechanism) = self.sasl.clone() else {
+            return Ok(());
+        };
+        // the exchange's own requests must not trigger another re-authentication
+        self.reauthenticate_at = None;
+        let authenticated_at: Instant = Instant::now();
+        let session_lifetime: Option<Duration> = crate::sasl::authenticate(self, mechanism.as_ref())?;
+        self.session_expires_at = session_lifetime.map(|lifetime| authenticated_at + lifetime);
+        self.reauthenticate_at = session_lifetime.map(|lifetime| authenticated_at + crate::sasl::reauthentication_delay(lifetime));
+        Ok(())
+    }
+
+    pub(crate) fn send_request_and_get_response<Request: KafkaRequest, Response: KafkaResponse>(&mut self, request: Request) -> Result<Response> {
+        self.send_request_and_get_response_within(request, self.request_timeout)
+    }
+
+    /// The API versions the broker supports, which an ApiVersions request asks for the first time
+    /// they are needed on the connection.
+    pub(crate) fn api_versions(&mut self) -> Result<&SupportedApiVersions> {
+        if self.api_versions.is_none() {
+            let request: ApiVersionsRequestV3 = ApiVersionsRequestV3 {
+                client_software_name: CompactString(String::from("kafkart")),
+                client_software_version: CompactString(String::from(env!("CARGO_PKG_VERSION"))),
+                tag_buffer: TaggedFields::new()
+            };
+            let response: ApiVersionsResponseV3ByKeyId = self.send_request_and_get_response(request)?;
+            if response.error_code != ErrorCode::None {
+                return Err(anyhow!("Node {} failed to list its API versions: {}", self.node_id, response.error_code));
+            }
+            debug!("Node {} supports {} APIs", self.node_id, response.api_keys.0.len());
+            self.api_versions = Some(SupportedApiVersions::from(response));
+        }
+        Ok(self.api_versions.as_ref().expect("API versions were just fetched"))
+    }
+
+    /// Like `send_request_and_get_response`, but waits up to `timeout` rather than the request timeout.
+    #[instrument]
+    pub(crate) fn send_request_and_get_response_within<Request: KafkaRequest, Response: KafkaResponse>(&mut self, request: Request, timeout: Duration) -> Result<Response> {
+        self.prepare_to_send()?;
+        let correlation_id: i32 = self.next_correlation_id();
+        let mut stream: DeadlineStream = DeadlineStream {
+            stream: &mut self.stream,
+            deadline: Instant::now() + timeout
+        };
+        let response: Result<Response> = send_request_and_receive_response(&mut stream, request, correlation_id, &self.client_id)
+            .map_err(|e| self.timeout_as_network_error(e, timeout));
+        self.last_used = Instant::now();
+        if let Ok(response) = &response {
+            self.record_throttle(response);
+        }
+        response
+    }
+
+    /// Writes every request before reading any response, so that up to `requests.len()` requests
+    /// are in flight on the connection at once. The responses are in the order of the requests, and
+    /// the request timeout applies to the whole exchange.
+    #[instrument]
+    pub(crate) fn send_requests_and_get_responses<Request: KafkaRequest, Response: KafkaResponse>(&mut self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        self.prepare_to_send()?;
+        let correlation_ids: Vec<i32> = requests.iter().map(|_| self.next_correlation_id()).collect();
+        let mut stream: DeadlineStream = DeadlineStream {
+            stream: &mut self.stream,
+            deadline: Instant::now() + self.request_timeout
+        };
+        let client_id: &str = &self.client_id;
+        let responses: Result<Vec<Response>> = requests.into_iter().zip(&correlation_ids)
+            .try_for_each(|(request, correlation_id)| serialize_request_with_header(&mut stream, request, *correlation_id, client_id))
+            .and_then(|_| Ok(stream.flush()?))
+            .and_then(|_| correlation_ids.iter()
+                .map(|correlation_id| receive_response::<DeadlineStream, Request, Response>(&mut stream, *correlation_id))
+                .collect());
+        let responses: Vec<Response> = responses.map_err(|e| self.timeout_as_network_error(e, self.request_timeout))?;
+        self.last_used = Instant::now();
+        for response in &responses {
+            self.record_throttle(response);
+        }
+        Ok(responses)
+    }
+
+    fn record_throttle<Response: KafkaResponse>(&mut self, response: &Response) {
+        if response.should_client_throttle() && response.throttle_time_ms() > 0 {
+            debug!("Node {} throttled the connection for {}ms", self.node_id, response.throttle_time_ms());
+            self.throttled_until = Some(self.last_used + Duration::from_millis(response.throttle_time_ms() as u64));
+        }
+    }
+
+    /// Sends a request which the broker won't answer, such as a Produce request with acks=0.
+    #[instrument]
+    pub(crate) fn send_request<Request: KafkaRequest>(&mut self, request: Request) -> Result<()> {
+        self.prepare_to_send()?;
+        let correlation_id: i32 = self.next_correlation_id();
+        let mut stream: DeadlineStream = DeadlineStream {
+            stream: &mut self.stream,
+            deadline: Instant::now() + self.request_timeout
+        };
+        let result: Result<()> = serialize_request_with_header(&mut stream, request, correlation_id, &self.client_id)
+            .and_then(|_| Ok(stream.flush()?))
+            .map_err(|e| self.timeout_as_network_error(e, self.request_timeout));
+        self.last_used = Instant::now();
+        result
+    }
+
+    /// Refuses to send while the broker is throttling the connection, and re-authenticates if it
+    /// is time to.
+    fn prepare_to_send(&mut self) -> Result<()> {
+        let throttle_remaining: Duration = self.throttle_remaining();
+        if !throttle_remaining.is_zero() {
+            return Err(anyhow::Error::new(NetworkError::NodeThrottled { node_id: self.node_id, remaining: throttle_remaining }));
+        }
+        self.throttled_until = None;
+        if self.reauthenticate_at.is_some_and(|reauthenticate_at| Instant::now() >= reauthenticate_at) {
+            debug!("Re-authenticating the connection to node {}", self.node_id);
+            self.authenticate()?;
+        }
+        Ok(())
+    }
+
+    fn next_correlation_id(&mut self) -> i32 {
+        let correlation_id: i32 = self.next_correlation_id;
+        self.next_correlation_id = self.next_correlation_id.wrapping_add(1);
+        self.last_used = Instant::now();
+        correlation_id
+    }
+
+    /// Replaces a socket timeout with the timeout the request was given.
+    fn timeout_as_network_error(&self, error: anyhow::Error, timeout: Duration) -> anyhow::Error {
+        if is_timeout(&error) {
+            anyhow::Error::new(NetworkError::RequestTimedOut { node_id: self.node_id, timeout })
+        } else {
+            error
+        }
+    }
+
+    /// How much longer the broker has asked the client not to send on this connection.
+    pub(crate) fn throttle_remaining(&self) -> Duration {
+        self.throttled_until
+            .map(|throttled_until| throttled_until.saturating_duration_since(Instant::now()))
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// How long it has been since a request was last sent or received on this connection.
+    pub(crate) fn idle_time(&self) -> Duration {
+        self.last_used.elapsed()
+    }
+
+    /// Whether the SASL session ran out before the connection could re-authenticate, in which case
+    /// the broker will have closed it.
+    pub(crate) fn is_session_expired(&self) -> bool {
+        self.session_expires_at.is_some_and(|session_expires_at| Instant::now() >= session_expires_at)
+    }
+}
 
+#[cfg(test)]
 pub(crate) fn test_request_and_response<Request: KafkaRequest, Response: KafkaResponse>(request: Request, expected_response: Response) {
     let mut networking_client: SingleUseKafkaNetworkingClient = SingleUseKafkaNetworkingClient::new("127.0.0.1:9092", "rusty");
     let response: Response = networking_client.send_request_and_get_response(request)
@@ -0,0 +1,489 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{Array, CompactArray, CompactNullableString, CompactString, NullableString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// request partitions
+/// Also used by versions 2 to 5, which dropped the commit timestamp of version 1.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestPartitionV0 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    pub committed_metadata: NullableString
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestPartitionV1 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    /// -1 to use the time the coordinator received the commit.
+    pub commit_timestamp: i64,
+    pub committed_metadata: NullableString
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestPartitionV6V7 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    /// The leader epoch of the last consumed record, or -1 if unknown.
+    pub committed_leader_epoch: i32,
+    pub committed_metadata: NullableString
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestPartitionV8V9 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    pub committed_metadata: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+// request topics
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestTopicV0 {
+    pub name: String,
+    pub partitions: Array<OffsetCommitRequestPartitionV0>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestTopicV1 {
+    pub name: String,
+    pub partitions: Array<OffsetCommitRequestPartitionV1>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestTopicV6V7 {
+    pub name: String,
+    pub partitions: Array<OffsetCommitRequestPartitionV6V7>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestTopicV8V9 {
+    pub name: CompactString,
+    pub partitions: CompactArray<OffsetCommitRequestPartitionV8V9>,
+    pub tag_buffer: TaggedFields
+}
+
+// requests
+/// Version 0 commits to ZooKeeper, and later versions to the `__consumer_offsets` topic.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV0 {
+    pub group_id: String,
+    pub topics: Array<OffsetCommitRequestTopicV0>
+}
+
+impl KafkaRequest for OffsetCommitRequestV0 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        0
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// Version 1 added the group membership, which the coordinator fences commits with.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV1 {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub topics: Array<OffsetCommitRequestTopicV1>
+}
+
+impl KafkaRequest for OffsetCommitRequestV1 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        1
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV2V4 {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    /// How long the coordinator keeps the offsets, or -1 for the broker's retention.
+    pub retention_time_ms: i64,
+    pub topics: Array<OffsetCommitRequestTopicV0>
+}
+
+impl KafkaRequest for OffsetCommitRequestV2V4 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        4
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// Version 5 dropped the retention time, leaving it to the broker (KIP-211).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV5 {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub topics: Array<OffsetCommitRequestTopicV0>
+}
+
+impl KafkaRequest for OffsetCommitRequestV5 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        5
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// Version 6 added the committed leader epoch (KIP-320).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV6 {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub topics: Array<OffsetCommitRequestTopicV6V7>
+}
+
+impl KafkaRequest for OffsetCommitRequestV6 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        6
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// Version 7 added the group instance id of static members (KIP-345).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV7 {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: NullableString,
+    pub topics: Array<OffsetCommitRequestTopicV6V7>
+}
+
+impl KafkaRequest for OffsetCommitRequestV7 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        7
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV8 {
+    pub group_id: CompactString,
+    pub generation_id: i32,
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    pub topics: CompactArray<OffsetCommitRequestTopicV8V9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for OffsetCommitRequestV8 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        8
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+/// Version 9 is sent by members of groups using the consumer group protocol (KIP-848), with their
+/// member epoch in place of the generation id.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitRequestV9 {
+    pub group_id: CompactString,
+    pub generation_id_or_member_epoch: i32,
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    pub topics: CompactArray<OffsetCommitRequestTopicV8V9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for OffsetCommitRequestV9 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        9
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponsePartitionV0V7 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponseTopicV0V7 {
+    pub name: String,
+    pub partitions: Array<OffsetCommitResponsePartitionV0V7>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponseV0V2 {
+    pub topics: Array<OffsetCommitResponseTopicV0V7>
+}
+
+impl KafkaResponse for OffsetCommitResponseV0V2 {
+    fn throttle_time_ms(&self) -> i32 {
+        0
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponseV3V7 {
+    pub throttle_time_ms: i32,
+    pub topics: Array<OffsetCommitResponseTopicV0V7>
+}
+
+impl KafkaResponse for OffsetCommitResponseV3V7 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponsePartitionV8V9 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponseTopicV8V9 {
+    pub name: CompactString,
+    pub partitions: CompactArray<OffsetCommitResponsePartitionV8V9>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetCommitResponseV8V9 {
+    pub throttle_time_ms: i32,
+    pub topics: CompactArray<OffsetCommitResponseTopicV8V9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for OffsetCommitResponseV8V9 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableString, CompactString, NullableString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::offset_commit::{OffsetCommitRequestPartitionV0, OffsetCommitRequestPartitionV1, OffsetCommitRequestPartitionV6V7, OffsetCommitRequestPartitionV8V9, OffsetCommitRequestTopicV0, OffsetCommitRequestTopicV1, OffsetCommitRequestTopicV6V7, OffsetCommitRequestTopicV8V9, OffsetCommitRequestV0, OffsetCommitRequestV1, OffsetCommitRequestV2V4, OffsetCommitRequestV7, OffsetCommitRequestV9, OffsetCommitResponsePartitionV0V7, OffsetCommitResponsePartitionV8V9, OffsetCommitResponseTopicV0V7, OffsetCommitResponseTopicV8V9, OffsetCommitResponseV0V2, OffsetCommitResponseV3V7, OffsetCommitResponseV8V9};
+    use crate::protocol::tags::TaggedFields;
+
+    fn encode<T: KafkaEncodable>(value: T) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        value.to_kafka_bytes(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_encode_offset_commit_requests_before_flexible_versions() {
+        let partition: OffsetCommitRequestPartitionV0 = OffsetCommitRequestPartitionV0 {
+            partition_index: 1,
+            committed_offset: 10,
+            committed_metadata: NullableString(Some(String::from("m")))
+        };
+        let v0: Vec<u8> = encode(OffsetCommitRequestV0 {
+            group_id: String::from("g"),
+            topics: Array(vec![OffsetCommitRequestTopicV0 { name: String::from("t"), partitions: Array(vec![partition.clone()]) }])
+        });
+        assert_eq!(v0, vec![0, 1, 103, 0, 0, 0, 1, 0, 1, 116, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 10, 0, 1, 109]);
+
+        let v1: Vec<u8> = encode(OffsetCommitRequestV1 {
+            group_id: String::from("g"),
+            generation_id: 2,
+            member_id: String::from("m"),
+            topics: Array(vec![OffsetCommitRequestTopicV1 {
+                name: String::from("t"),
+                partitions: Array(vec![OffsetCommitRequestPartitionV1 {
+                    partition_index: 1,
+                    committed_offset: 10,
+                    commit_timestamp: -1,
+                    committed_metadata: NullableString(None)
+                }])
+            }])
+        });
+        assert_eq!(&v1[v1.len() - 10..], &[255, 255, 255, 255, 255, 255, 255, 255, 255, 255]);
+
+        let v2: Vec<u8> = encode(OffsetCommitRequestV2V4 {
+            group_id: String::from("g"),
+            generation_id: 2,
+            member_id: String::from("m"),
+            retention_time_ms: -1,
+            topics: Array(vec![OffsetCommitRequestTopicV0 { name: String::from("t"), partitions: Array(vec![partition]) }])
+        });
+        assert_eq!(&v2[..18], &[0, 1, 103, 0, 0, 0, 2, 0, 1, 109, 255, 255, 255, 255, 255, 255, 255, 255]);
+
+        let v7: Vec<u8> = encode(OffsetCommitRequestV7 {
+            group_id: String::from("g"),
+            generation_id: 2,
+            member_id: String::from("m"),
+            group_instance_id: NullableString(Some(String::from("i"))),
+            topics: Array(vec![OffsetCommitRequestTopicV6V7 {
+                name: String::from("t"),
+                partitions: Array(vec![OffsetCommitRequestPartitionV6V7 {
+                    partition_index: 1,
+                    committed_offset: 10,
+                    committed_leader_epoch: 3,
+                    committed_metadata: NullableString(Some(String::new()))
+                }])
+            }])
+        });
+        assert_eq!(&v7[10..13], &[0, 1, 105]);
+        assert_eq!(&v7[v7.len() - 6..], &[0, 0, 0, 3, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_offset_commit_request_v9() {
+        let request: OffsetCommitRequestV9 = OffsetCommitRequestV9 {
+            group_id: CompactString(String::from("g")),
+            generation_id_or_member_epoch: 5,
+            member_id: CompactString(String::from("m")),
+            group_instance_id: CompactNullableString(None),
+            topics: CompactArray(vec![OffsetCommitRequestTopicV8V9 {
+                name: CompactString(String::from("t")),
+                partitions: CompactArray(vec![OffsetCommitRequestPartitionV8V9 {
+                    partition_index: 0,
+                    committed_offset: 7,
+                    committed_leader_epoch: -1,
+                    committed_metadata: CompactNullableString(Some(String::new())),
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        assert_eq!(encode(request.clone()), vec![
+            2, 103, 0, 0, 0, 5, 2, 109, 0, 2, 2, 116, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 255, 255, 255, 255, 1, 0, 0, 0
+        ]);
+        assert_eq!(OffsetCommitRequestV9::from_kafka_bytes(&mut &*encode(request.clone())).unwrap(), request);
+    }
+
+    #[test]
+    fn test_offset_commit_responses_round_trip() {
+        let topics: Array<OffsetCommitResponseTopicV0V7> = Array(vec![OffsetCommitResponseTopicV0V7 {
+            name: String::from("t"),
+            partitions: Array(vec![OffsetCommitResponsePartitionV0V7 { partition_index: 0, error_code: ErrorCode::OffsetMetadataTooLarge }])
+        }]);
+        let v0: OffsetCommitResponseV0V2 = OffsetCommitResponseV0V2 { topics: topics.clone() };
+        assert_eq!(OffsetCommitResponseV0V2::from_kafka_bytes(&mut &*encode(v0.clone())).unwrap(), v0);
+        let v3: OffsetCommitResponseV3V7 = OffsetCommitResponseV3V7 { throttle_time_ms: 10, topics };
+        assert_eq!(OffsetCommitResponseV3V7::from_kafka_bytes(&mut &*encode(v3.clone())).unwrap(), v3);
+        let v8: OffsetCommitResponseV8V9 = OffsetCommitResponseV8V9 {
+            throttle_time_ms: 0,
+            topics: CompactArray(vec![OffsetCommitResponseTopicV8V9 {
+                name: CompactString(String::from("t")),
+                partitions: CompactArray(vec![OffsetCommitResponsePartitionV8V9 {
+                    partition_index: 0,
+                    error_code: ErrorCode::StaleMemberEpoch,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        assert_eq!(OffsetCommitResponseV8V9::from_kafka_bytes(&mut &*encode(v8.clone())).unwrap(), v8);
+    }
+}
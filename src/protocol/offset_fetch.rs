@@ -0,0 +1,489 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactNullableString, CompactString, NullableArray, NullableString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestTopicV0V5 {
+    pub name: String,
+    pub partition_indexes: Array<i32>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestV0V1 {
+    pub group_id: String,
+    pub topics: Array<OffsetFetchRequestTopicV0V5>
+}
+
+impl KafkaRequest for OffsetFetchRequestV0V1 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetFetch
+    }
+
+    fn get_version() -> ApiVersion {
+        1
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+/// Since version 2 the topics can be null to fetch the offsets of every partition of the group.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestV2V5 {
+    pub group_id: String,
+    pub topics: NullableArray<OffsetFetchRequestTopicV0V5>
+}
+
+impl KafkaRequest for OffsetFetchRequestV2V5 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetFetch
+    }
+
+    fn get_version() -> ApiVersion {
+        5
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestTopicV6V9 {
+    pub name: CompactString,
+    pub partition_indexes: CompactArray<i32>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestV6 {
+    pub group_id: CompactString,
+    pub topics: CompactNullableArray<OffsetFetchRequestTopicV6V9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for OffsetFetchRequestV6 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetFetch
+    }
+
+    fn get_version() -> ApiVersion {
+        6
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestV7 {
+    pub group_id: CompactString,
+    pub topics: CompactNullableArray<OffsetFetchRequestTopicV6V9>,
+    /// Whether partitions with pending transactional offsets fail with `UnstableOffsetCommit`
+    /// rather than return the last stable offset (KIP-447).
+    pub require_stable: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for OffsetFetchRequestV7 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetFetch
+    }
+
+    fn get_version() -> ApiVersion {
+        7
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestGroupV8 {
+    pub group_id: CompactString,
+    pub topics: CompactNullableArray<OffsetFetchRequestTopicV6V9>,
+    pub tag_buffer: TaggedFields
+}
+
+/// Version 8 fetches the offsets of several groups in one request (KIP-709).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestV8 {
+    pub groups: CompactArray<OffsetFetchRequestGroupV8>,
+    pub require_stable: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for OffsetFetchRequestV8 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetFetch
+    }
+
+    fn get_version() -> ApiVersion {
+        8
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+/// Version 9 lets members of groups using the consumer group protocol (KIP-848) fetch with their
+/// member id and epoch, which the coordinator fences stale members with.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestGroupV9 {
+    pub group_id: CompactString,
+    pub member_id: CompactNullableString,
+    /// -1 when fetching from outside the group.
+    pub member_epoch: i32,
+    pub topics: CompactNullableArray<OffsetFetchRequestTopicV6V9>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchRequestV9 {
+    pub groups: CompactArray<OffsetFetchRequestGroupV9>,
+    pub require_stable: bool,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for OffsetFetchRequestV9 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::OffsetFetch
+    }
+
+    fn get_version() -> ApiVersion {
+        9
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// responses
+/// A committed offset of -1 means the group has no offset for the partition.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponsePartitionV0V4 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    pub metadata: NullableString,
+    pub error_code: ErrorCode
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseTopicV0V4 {
+    pub name: String,
+    pub partitions: Array<OffsetFetchResponsePartitionV0V4>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseV0V1 {
+    pub topics: Array<OffsetFetchResponseTopicV0V4>
+}
+
+impl KafkaResponse for OffsetFetchResponseV0V1 {
+    fn throttle_time_ms(&self) -> i32 {
+        0
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        false
+    }
+}
+
+/// Version 2 added a top level error for the errors which aren't specific to a partition.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseV2 {
+    pub topics: Array<OffsetFetchResponseTopicV0V4>,
+    pub error_code: ErrorCode
+}
+
+impl KafkaResponse for OffsetFetchResponseV2 {
+    fn throttle_time_ms(&self) -> i32 {
+        0
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseV3V4 {
+    pub throttle_time_ms: i32,
+    pub topics: Array<OffsetFetchResponseTopicV0V4>,
+    pub error_code: ErrorCode
+}
+
+impl KafkaResponse for OffsetFetchResponseV3V4 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponsePartitionV5 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    /// -1 if the offset was committed without a leader epoch.
+    pub committed_leader_epoch: i32,
+    pub metadata: NullableString,
+    pub error_code: ErrorCode
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseTopicV5 {
+    pub name: String,
+    pub partitions: Array<OffsetFetchResponsePartitionV5>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseV5 {
+    pub throttle_time_ms: i32,
+    pub topics: Array<OffsetFetchResponseTopicV5>,
+    pub error_code: ErrorCode
+}
+
+impl KafkaResponse for OffsetFetchResponseV5 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponsePartitionV6V9 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    pub metadata: CompactNullableString,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseTopicV6V9 {
+    pub name: CompactString,
+    pub partitions: CompactArray<OffsetFetchResponsePartitionV6V9>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseV6V7 {
+    pub throttle_time_ms: i32,
+    pub topics: CompactArray<OffsetFetchResponseTopicV6V9>,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for OffsetFetchResponseV6V7 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseGroupV8V9 {
+    pub group_id: CompactString,
+    pub topics: CompactArray<OffsetFetchResponseTopicV6V9>,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct OffsetFetchResponseV8V9 {
+    pub throttle_time_ms: i32,
+    pub groups: CompactArray<OffsetFetchResponseGroupV8V9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for OffsetFetchResponseV8V9 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactNullableString, CompactString, NullableArray, NullableString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::offset_fetch::{OffsetFetchRequestGroupV8, OffsetFetchRequestGroupV9, OffsetFetchRequestTopicV0V5, OffsetFetchRequestTopicV6V9, OffsetFetchRequestV0V1, OffsetFetchRequestV2V5, OffsetFetchRequestV8, OffsetFetchRequestV9, OffsetFetchResponseGroupV8V9, OffsetFetchResponsePartitionV0V4, OffsetFetchResponsePartitionV5, OffsetFetchResponsePartitionV6V9, OffsetFetchResponseTopicV0V4, OffsetFetchResponseTopicV5, OffsetFetchResponseTopicV6V9, OffsetFetchResponseV3V4, OffsetFetchResponseV5, OffsetFetchResponseV8V9};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_offset_fetch_requests_before_flexible_versions() {
+        let topic: OffsetFetchRequestTopicV0V5 = OffsetFetchRequestTopicV0V5 { name: String::from("t"), partition_indexes: Array(vec![0, 1]) };
+        let mut v1: Vec<u8> = Vec::new();
+        OffsetFetchRequestV0V1 { group_id: String::from("g"), topics: Array(vec![topic]) }.to_kafka_bytes(&mut v1).unwrap();
+        assert_eq!(v1, vec![0, 1, 103, 0, 0, 0, 1, 0, 1, 116, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let mut all_partitions: Vec<u8> = Vec::new();
+        OffsetFetchRequestV2V5 { group_id: String::from("g"), topics: NullableArray(None) }.to_kafka_bytes(&mut all_partitions).unwrap();
+        assert_eq!(all_partitions, vec![0, 1, 103, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_encode_offset_fetch_request_v8_with_several_groups() {
+        let request: OffsetFetchRequestV8 = OffsetFetchRequestV8 {
+            groups: CompactArray(vec![
+                OffsetFetchRequestGroupV8 {
+                    group_id: CompactString(String::from("a")),
+                    topics: CompactNullableArray(Some(vec![OffsetFetchRequestTopicV6V9 {
+                        name: CompactString(String::from("t")),
+                        partition_indexes: CompactArray(vec![3]),
+                        tag_buffer: TaggedFields::new()
+                    }])),
+                    tag_buffer: TaggedFields::new()
+                },
+                OffsetFetchRequestGroupV8 {
+                    group_id: CompactString(String::from("b")),
+                    topics: CompactNullableArray(None),
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            require_stable: true,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![3, 2, 97, 2, 2, 116, 2, 0, 0, 0, 3, 0, 0, 2, 98, 0, 0, 1, 0]);
+        assert_eq!(OffsetFetchRequestV8::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_encode_offset_fetch_request_v9() {
+        let request: OffsetFetchRequestV9 = OffsetFetchRequestV9 {
+            groups: CompactArray(vec![OffsetFetchRequestGroupV9 {
+                group_id: CompactString(String::from("g")),
+                member_id: CompactNullableString(Some(String::from("m"))),
+                member_epoch: 4,
+                topics: CompactNullableArray(None),
+                tag_buffer: TaggedFields::new()
+            }]),
+            require_stable: false,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![2, 2, 103, 2, 109, 0, 0, 0, 4, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_offset_fetch_responses_round_trip() {
+        let v3: OffsetFetchResponseV3V4 = OffsetFetchResponseV3V4 {
+            throttle_time_ms: 0,
+            topics: Array(vec![OffsetFetchResponseTopicV0V4 {
+                name: String::from("t"),
+                partitions: Array(vec![OffsetFetchResponsePartitionV0V4 {
+                    partition_index: 0,
+                    committed_offset: -1,
+                    metadata: NullableString(Some(String::new())),
+                    error_code: ErrorCode::None
+                }])
+            }]),
+            error_code: ErrorCode::None
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v3.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(OffsetFetchResponseV3V4::from_kafka_bytes(&mut &*bytes).unwrap(), v3);
+
+        let v5: OffsetFetchResponseV5 = OffsetFetchResponseV5 {
+            throttle_time_ms: 0,
+            topics: Array(vec![OffsetFetchResponseTopicV5 {
+                name: String::from("t"),
+                partitions: Array(vec![OffsetFetchResponsePartitionV5 {
+                    partition_index: 0,
+                    committed_offset: 12,
+                    committed_leader_epoch: 2,
+                    metadata: NullableString(None),
+                    error_code: ErrorCode::None
+                }])
+            }]),
+            error_code: ErrorCode::None
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v5.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(OffsetFetchResponseV5::from_kafka_bytes(&mut &*bytes).unwrap(), v5);
+
+        let v8: OffsetFetchResponseV8V9 = OffsetFetchResponseV8V9 {
+            throttle_time_ms: 0,
+            groups: CompactArray(vec![
+                OffsetFetchResponseGroupV8V9 {
+                    group_id: CompactString(String::from("a")),
+                    topics: CompactArray(vec![OffsetFetchResponseTopicV6V9 {
+                        name: CompactString(String::from("t")),
+                        partitions: CompactArray(vec![OffsetFetchResponsePartitionV6V9 {
+                            partition_index: 3,
+                            committed_offset: 42,
+                            committed_leader_epoch: 1,
+                            metadata: CompactNullableString(Some(String::from("m"))),
+                            error_code: ErrorCode::None,
+                            tag_buffer: TaggedFields::new()
+                        }]),
+                        tag_buffer: TaggedFields::new()
+                    }]),
+                    error_code: ErrorCode::None,
+                    tag_buffer: TaggedFields::new()
+                },
+                OffsetFetchResponseGroupV8V9 {
+                    group_id: CompactString(String::from("b")),
+                    topics: CompactArray(vec![]),
+                    error_code: ErrorCode::GroupAuthorizationFailed,
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        v8.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(OffsetFetchResponseV8V9::from_kafka_bytes(&mut &*bytes).unwrap(), v8);
+    }
+}
@@ -1,36 +1,51 @@
-use std::io::{Read, Write};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+#[cfg(test)]
+use bytes::{Buf, Bytes};
+#[cfg(test)]
 use bytes::buf::Reader;
+#[cfg(test)]
 use tracing::info;
-use kafka_encode::primitives::{Array, CompactArray, CompactNullableArray, CompactNullableString, CompactString, NullableArray, NullableString, VarArray};
+use kafka_encode::primitives::{Array, CompactArray, CompactNullableString, CompactString, NullableString};
+#[cfg(test)]
+use kafka_encode::primitives::VarArray;
 use kafka_encode::KafkaEncodable;
 use kafka_encode_derive::KafkaEncodable;
 use crate::protocol::api_key::ApiKey;
+#[cfg(test)]
 use crate::protocol::api_versions::{ApiVersionsRequestV3, ApiVersionsResponseV3};
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+#[cfg(test)]
 use crate::protocol::headers::{RequestHeaderV2, ResponseHeaderV1};
-use crate::protocol::records::Record;
+use crate::protocol::records::{CompactRecords, Records};
 use crate::protocol::tags::TaggedFields;
 
+/// Acks which mean the leader waits for every in-sync replica before responding.
+pub const ACKS_ALL: i16 = -1;
+
+/// Acks which mean the broker sends no response at all.
+pub const ACKS_NONE: i16 = 0;
+
+// request partitions
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct PartitionDataV0V8 {
     pub index: i32,
-    records: NullableArray<Record>
+    pub records: Records
 }
 
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct PartitionDataV9 {
     pub index: i32,
-    pub records: CompactNullableArray<Record>,
+    pub records: CompactRecords,
     pub tag_buffer: TaggedFields
 }
 
+// request topics
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct TopicDataV0V8 {
     pub name: String,
-    partition_data: Array<PartitionDataV0V8>
+    pub partition_data: Array<PartitionDataV0V8>
 }
 
-
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct TopicDataV9 {
     pub name: CompactString,
@@ -38,6 +53,7 @@ pub struct TopicDataV9 {
     pub tag_buffer: TaggedFields
 }
 
+// requests
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct ProduceRequestV0V2 {
     pub acks: i16,
@@ -56,15 +72,191 @@ pub struct ProduceRequestV3V8 {
     pub topic_data: Array<TopicDataV0V8>
 }
 
+impl KafkaRequest for ProduceRequestV3V8 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Produce
+    }
+
+    fn get_version() -> ApiVersion {
+        8
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct ProduceRequestV9 {
     pub transactional_id: CompactNullableString,
     pub acks: i16,
     pub timeout_ms: i32,
-    pub topic_data: CompactArray<PartitionDataV0V8>,
+    pub topic_data: CompactArray<TopicDataV9>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for ProduceRequestV9 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::Produce
+    }
+
+    fn get_version() -> ApiVersion {
+        9
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// the batches of a request which the broker rejected
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct BatchIndexAndErrorMessageV8 {
+    pub batch_index: i32,
+    pub batch_index_error_message: NullableString
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct BatchIndexAndErrorMessageV9 {
+    pub batch_index: i32,
+    pub batch_index_error_message: CompactNullableString,
     pub tag_buffer: TaggedFields
 }
 
+// response partitions
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct PartitionProduceResponseV8 {
+    pub index: i32,
+    pub error_code: ErrorCode,
+    pub base_offset: i64,
+    /// -1 unless the topic uses LogAppendTime, in which case the broker's timestamp for the batch.
+    pub log_append_time_ms: i64,
+    pub log_start_offset: i64,
+    pub record_errors: Array<BatchIndexAndErrorMessageV8>,
+    pub error_message: NullableString
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct PartitionProduceResponseV9 {
+    pub index: i32,
+    pub error_code: ErrorCode,
+    pub base_offset: i64,
+    /// -1 unless the topic uses LogAppendTime, in which case the broker's timestamp for the batch.
+    pub log_append_time_ms: i64,
+    pub log_start_offset: i64,
+    pub record_errors: CompactArray<BatchIndexAndErrorMessageV9>,
+    pub error_message: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+// response topics
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TopicProduceResponseV8 {
+    pub name: String,
+    pub partition_responses: Array<PartitionProduceResponseV8>
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TopicProduceResponseV9 {
+    pub name: CompactString,
+    pub partition_responses: CompactArray<PartitionProduceResponseV9>,
+    pub tag_buffer: TaggedFields
+}
+
+// responses
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ProduceResponseV8 {
+    pub responses: Array<TopicProduceResponseV8>,
+    pub throttle_time_ms: i32
+}
+
+impl KafkaResponse for ProduceResponseV8 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct ProduceResponseV9 {
+    pub responses: CompactArray<TopicProduceResponseV9>,
+    pub throttle_time_ms: i32,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for ProduceResponseV9 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+// for brokers which don't support the flexible versions of Produce
+impl From<ProduceRequestV9> for ProduceRequestV3V8 {
+    fn from(request: ProduceRequestV9) -> Self {
+        ProduceRequestV3V8 {
+            transactional_id: NullableString(request.transactional_id.0),
+            acks: request.acks,
+            timeout_ms: request.timeout_ms,
+            topic_data: Array(request.topic_data.0.into_iter()
+                .map(|topic| TopicDataV0V8 {
+                    name: topic.name.0,
+                    partition_data: Array(topic.partition_data.0.into_iter()
+                        .map(|partition| PartitionDataV0V8 { index: partition.index, records: Records(partition.records.0) })
+                        .collect())
+                })
+                .collect())
+        }
+    }
+}
+
+impl From<ProduceResponseV8> for ProduceResponseV9 {
+    fn from(response: ProduceResponseV8) -> Self {
+        ProduceResponseV9 {
+            responses: CompactArray(response.responses.0.into_iter()
+                .map(|topic| TopicProduceResponseV9 {
+                    name: CompactString(topic.name),
+                    partition_responses: CompactArray(topic.partition_responses.0.into_iter()
+                        .map(|partition| PartitionProduceResponseV9 {
+                            index: partition.index,
+                            error_code: partition.error_code,
+                            base_offset: partition.base_offset,
+                            log_append_time_ms: partition.log_append_time_ms,
+                            log_start_offset: partition.log_start_offset,
+                            record_errors: CompactArray(partition.record_errors.0.into_iter()
+                                .map(|record_error| BatchIndexAndErrorMessageV9 {
+                                    batch_index: record_error.batch_index,
+                                    batch_index_error_message: CompactNullableString(record_error.batch_index_error_message.0),
+                                    tag_buffer: TaggedFields::new()
+                                })
+                                .collect()),
+                            error_message: CompactNullableString(partition.error_message.0),
+                            tag_buffer: TaggedFields::new()
+                        })
+                        .collect()),
+                    tag_buffer: TaggedFields::new()
+                })
+                .collect()),
+            throttle_time_ms: response.throttle_time_ms,
+            tag_buffer: TaggedFields::new()
+        }
+    }
+}
+
 #[test]
 fn test_stuff() {
     info!("try this");
@@ -102,3 +294,74 @@ fn test_serialization_stuff() {
         tag_buffer: TaggedFields { tags: VarArray(Vec::new()) },
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, CompactArray, CompactNullableString, CompactString, NullableString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::produce::{ACKS_ALL, BatchIndexAndErrorMessageV8, PartitionDataV9, PartitionProduceResponseV8, ProduceRequestV9, ProduceResponseV8, TopicDataV9, TopicProduceResponseV8};
+    use crate::protocol::records::{CompactRecords, Record, RecordBatch};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_produce_request_v9_round_trip() {
+        let record: Record = Record::new(0, 0, None, Some(b"hello".to_vec()), vec![]).unwrap();
+        let request: ProduceRequestV9 = ProduceRequestV9 {
+            transactional_id: CompactNullableString(None),
+            acks: ACKS_ALL,
+            timeout_ms: 30_000,
+            topic_data: CompactArray(vec![
+                TopicDataV9 {
+                    name: CompactString(String::from("foo")),
+                    partition_data: CompactArray(vec![
+                        PartitionDataV9 {
+                            index: 3,
+                            records: CompactRecords(Some(vec![RecordBatch::new(1_000, vec![record]).seal().unwrap()])),
+                            tag_buffer: TaggedFields::new()
+                        }
+                    ]),
+                    tag_buffer: TaggedFields::new()
+                }
+            ]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(&bytes[..13], &[0, 255, 255, 0, 0, 117, 48, 2, 4, 102, 111, 111, 2]);
+        assert_eq!(ProduceRequestV9::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_decode_produce_response_v8() {
+        let bytes: Vec<u8> = vec![
+            0, 0, 0, 1, 0, 3, 102, 111, 111,
+            0, 0, 0, 1, 0, 0, 0, 2, 0, 87, 0, 0, 0, 0, 0, 0, 0, 0,
+            255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 1, 0, 0, 0, 4, 0, 3, 98, 97, 100, 0, 3, 98, 97, 100,
+            0, 0, 0, 50
+        ];
+        let response: ProduceResponseV8 = ProduceResponseV8::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(response, ProduceResponseV8 {
+            responses: Array(vec![
+                TopicProduceResponseV8 {
+                    name: String::from("foo"),
+                    partition_responses: Array(vec![
+                        PartitionProduceResponseV8 {
+                            index: 2,
+                            error_code: ErrorCode::InvalidRecord,
+                            base_offset: 0,
+                            log_append_time_ms: -1,
+                            log_start_offset: 0,
+                            record_errors: Array(vec![
+                                BatchIndexAndErrorMessageV8 { batch_index: 4, batch_index_error_message: NullableString(Some(String::from("bad"))) }
+                            ]),
+                            error_message: NullableString(Some(String::from("bad")))
+                        }
+                    ])
+                }
+            ]),
+            throttle_time_ms: 50
+        });
+    }
+}
@@ -1,11 +1,37 @@
-use kafka_encode::primitives::{Array, CompactNullableBytes, CompactString, NullableArray, VarI32, VarI64};
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use anyhow::{anyhow, Result};
+use kafka_encode::primitives::{Array, CompactNullableBytes, NullableBytes, VarI32, VarI64, VarIntArray, VarIntNullableBytes, VarIntString};
 use kafka_encode::KafkaEncodable;
 use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::compression::CompressionType;
+
+/// The only record batch format this crate understands. Older message sets use magic 0 or 1.
+pub const RECORD_BATCH_MAGIC: i8 = 2;
+
+/// The number of bytes in `base_offset` and `batch_length`, which are not counted by `batch_length`.
+pub const RECORD_BATCH_LOG_OVERHEAD: usize = 12;
+
+/// The size of a batch with no records: every field before the records, plus the record count.
+pub const RECORD_BATCH_OVERHEAD: usize = 61;
+
+/// The CRC covers everything from `attributes` to the end of the batch.
+const RECORD_BATCH_CRC_START: usize = 21;
+
+/// Producers which don't use idempotence send -1 for the producer id, epoch and base sequence.
+pub const NO_PRODUCER_ID: i64 = -1;
+pub const NO_PRODUCER_EPOCH: i16 = -1;
+pub const NO_SEQUENCE: i32 = -1;
+
+const COMPRESSION_CODEC_MASK: i16 = 0x07;
+const TIMESTAMP_TYPE_MASK: i16 = 0x08;
+const TRANSACTIONAL_FLAG_MASK: i16 = 0x10;
+const CONTROL_FLAG_MASK: i16 = 0x20;
 
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
 pub struct RecordHeader {
-    pub key: CompactString,
-    pub value: CompactNullableBytes
+    pub key: VarIntString,
+    pub value: VarIntNullableBytes
 }
 
 #[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
@@ -14,12 +40,40 @@ pub struct Record {
     pub attributes: u8,
     pub timestamp_delta: VarI64,
     pub offset_delta: VarI32,
-    pub key: CompactNullableBytes,
-    pub value: CompactNullableBytes,
-    pub headers: NullableArray<RecordHeader>
+    pub key: VarIntNullableBytes,
+    pub value: VarIntNullableBytes,
+    pub headers: VarIntArray<RecordHeader>
 }
 
-#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+impl Record {
+    /// A record `offset_delta` records and `timestamp_delta` milliseconds after the start of its
+    /// batch, with its `length` filled in.
+    pub fn new(offset_delta: i32, timestamp_delta: i64, key: Option<Vec<u8>>, value: Option<Vec<u8>>, headers: Vec<RecordHeader>) -> Result<Record> {
+        let mut record: Record = Record {
+            length: VarI32(0),
+            attributes: 0,
+            timestamp_delta: VarI64(timestamp_delta),
+            offset_delta: VarI32(offset_delta),
+            key: VarIntNullableBytes(key),
+            value: VarIntNullableBytes(value),
+            headers: VarIntArray(headers)
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        record.clone().to_kafka_bytes(&mut bytes)?;
+        // a length of 0 takes up a single byte, which the length itself doesn't count
+        record.length = VarI32(bytes.len() as i32 - 1);
+        Ok(record)
+    }
+
+    /// The encoded size of the record, including its length.
+    pub fn size_in_bytes(&self) -> usize {
+        let mut length_bytes: Vec<u8> = Vec::new();
+        self.length.clone().to_kafka_bytes(&mut length_bytes).expect("Writing to a Vec can't fail");
+        length_bytes.len() + self.length.0 as usize
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct RecordBatch {
     pub base_offset: i64,
     pub batch_length: i32,
@@ -33,5 +87,416 @@ pub struct RecordBatch {
     pub producer_id: i64,
     pub producer_epoch_offset: i16,
     pub base_sequence: i32,
-    pub records: Array<Record>
+    pub records: Array<Record>,
+    /// The rest of the batch as it was read, when its records are compressed or it is in an older
+    /// format: everything from the record count on, or from after `magic`. It is written back as
+    /// it is, so that the CRC still matches, while `records` holds what could be decoded.
+    pub raw_records: Option<Vec<u8>>,
+    /// Why `records` was left empty: the batch is in an older format, or its records are
+    /// compressed with an unknown codec or are corrupt.
+    pub decode_error: Option<String>
+}
+
+impl RecordBatch {
+    /// An uncompressed batch of records without a producer id, whose first record was created at
+    /// `base_timestamp`. `seal` must be called once the batch's fields are final.
+    pub fn new(base_timestamp: i64, records: Vec<Record>) -> RecordBatch {
+        RecordBatch {
+            base_offset: 0,
+            batch_length: 0,
+            partition_leader_epoch: -1,
+            magic: RECORD_BATCH_MAGIC,
+            crc: 0,
+            attributes: 0,
+            last_offset_delta: records.iter().map(|record| record.offset_delta.0).max().unwrap_or(0),
+            base_timestamp,
+            max_timestamp: base_timestamp + records.iter().map(|record| record.timestamp_delta.0).max().unwrap_or(0),
+            producer_id: NO_PRODUCER_ID,
+            producer_epoch_offset: NO_PRODUCER_EPOCH,
+            base_sequence: NO_SEQUENCE,
+            records: Array(records),
+            raw_records: None,
+            decode_error: None
+        }
+    }
+
+    /// Fills in `batch_length` and the CRC-32C checksum from the rest of the batch.
+    pub fn seal(mut self) -> Result<RecordBatch> {
+        let bytes: Vec<u8> = self.to_bytes()?;
+        self.batch_length = (bytes.len() - RECORD_BATCH_LOG_OVERHEAD) as i32;
+        self.crc = crc32c::crc32c(&bytes[RECORD_BATCH_CRC_START..]) as i32;
+        Ok(self)
+    }
+
+    /// Whether the batch's CRC matches its contents.
+    pub fn is_valid(&self) -> Result<bool> {
+        let bytes: Vec<u8> = self.to_bytes()?;
+        Ok(crc32c::crc32c(&bytes[RECORD_BATCH_CRC_START..]) as i32 == self.crc)
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes: Vec<u8> = Vec::new();
+        self.clone().to_kafka_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// The codec the records were compressed with, or `None` for a codec this crate doesn't know.
+    pub fn compression_type(&self) -> Option<CompressionType> {
+        CompressionType::from_id(self.attributes & COMPRESSION_CODEC_MASK)
+    }
+
+    /// Whether the broker set the timestamps when it wrote the batch, in which case every record
+    /// has the batch's `max_timestamp`.
+    pub fn is_log_append_time(&self) -> bool {
+        self.attributes & TIMESTAMP_TYPE_MASK != 0
+    }
+
+    pub fn is_transactional(&self) -> bool {
+        self.attributes & TRANSACTIONAL_FLAG_MASK != 0
+    }
+
+    /// Control batches hold transaction markers rather than application records.
+    pub fn is_control_batch(&self) -> bool {
+        self.attributes & CONTROL_FLAG_MASK != 0
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.last_offset_delta as i64
+    }
+}
+
+impl KafkaEncodable for RecordBatch {
+    #[tracing::instrument]
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        self.base_offset.to_kafka_bytes(writer)?;
+        self.batch_length.to_kafka_bytes(writer)?;
+        self.partition_leader_epoch.to_kafka_bytes(writer)?;
+        self.magic.to_kafka_bytes(writer)?;
+        if let (false, Some(raw_records)) = (self.magic == RECORD_BATCH_MAGIC, &self.raw_records) {
+            writer.write_all(raw_records)?;
+            return Ok(());
+        }
+        self.crc.to_kafka_bytes(writer)?;
+        self.attributes.to_kafka_bytes(writer)?;
+        self.last_offset_delta.to_kafka_bytes(writer)?;
+        self.base_timestamp.to_kafka_bytes(writer)?;
+        self.max_timestamp.to_kafka_bytes(writer)?;
+        self.producer_id.to_kafka_bytes(writer)?;
+        self.producer_epoch_offset.to_kafka_bytes(writer)?;
+        self.base_sequence.to_kafka_bytes(writer)?;
+        match self.raw_records {
+            Some(raw_records) => Ok(writer.write_all(&raw_records)?),
+            None => self.records.to_kafka_bytes(writer)
+        }
+    }
+
+    #[tracing::instrument]
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<RecordBatch> {
+        let base_offset: i64 = i64::from_kafka_bytes(reader)?;
+        let batch_length: i32 = i32::from_kafka_bytes(reader)?;
+        if batch_length < 0 {
+            return Err(anyhow!("Invalid record batch length: {}", batch_length));
+        }
+
+        // read the whole batch up front so a malformed record can't consume the next batch
+        let mut batch_bytes: Vec<u8> = vec![0; batch_length as usize];
+        reader.read_exact(&mut batch_bytes)?;
+        let mut batch_reader: &[u8] = &batch_bytes;
+
+        let partition_leader_epoch: i32 = i32::from_kafka_bytes(&mut batch_reader)?;
+        let magic: i8 = i8::from_kafka_bytes(&mut batch_reader)?;
+        if magic != RECORD_BATCH_MAGIC {
+            // an older message set, whose first 4 bytes were its CRC rather than a leader epoch
+            return Ok(RecordBatch {
+                base_offset,
+                batch_length,
+                partition_leader_epoch,
+                magic,
+                crc: 0,
+                attributes: 0,
+                last_offset_delta: 0,
+                base_timestamp: -1,
+                max_timestamp: -1,
+                producer_id: -1,
+                producer_epoch_offset: -1,
+                base_sequence: -1,
+                records: Array(Vec::new()),
+                raw_records: Some(batch_reader.to_vec()),
+                decode_error: Some(format!("Unsupported record batch magic: {}", magic))
+            });
+        }
+        let crc: i32 = i32::from_kafka_bytes(&mut batch_reader)?;
+        let attributes: i16 = i16::from_kafka_bytes(&mut batch_reader)?;
+        let last_offset_delta: i32 = i32::from_kafka_bytes(&mut batch_reader)?;
+        let base_timestamp: i64 = i64::from_kafka_bytes(&mut batch_reader)?;
+        let max_timestamp: i64 = i64::from_kafka_bytes(&mut batch_reader)?;
+        let producer_id: i64 = i64::from_kafka_bytes(&mut batch_reader)?;
+        let producer_epoch_offset: i16 = i16::from_kafka_bytes(&mut batch_reader)?;
+        let base_sequence: i32 = i32::from_kafka_bytes(&mut batch_reader)?;
+        let (records, raw_records, decode_error): (Vec<Record>, Option<Vec<u8>>, Option<String>) = match attributes & COMPRESSION_CODEC_MASK {
+            0 => match Array::<Record>::from_kafka_bytes(&mut &*batch_reader) {
+                Ok(records) => (records.0, None, None),
+                Err(e) => (Vec::new(), Some(batch_reader.to_vec()), Some(format!("Corrupt records: {:#}", e)))
+            },
+            codec => match decompress_records(codec, batch_reader) {
+                Ok(records) => (records, Some(batch_reader.to_vec()), None),
+                Err(e) => (Vec::new(), Some(batch_reader.to_vec()), Some(format!("{:#}", e)))
+            }
+        };
+
+        Ok(RecordBatch {
+            base_offset,
+            batch_length,
+            partition_leader_epoch,
+            magic,
+            crc,
+            attributes,
+            last_offset_delta,
+            base_timestamp,
+            max_timestamp,
+            producer_id,
+            producer_epoch_offset,
+            base_sequence,
+            records: Array(records),
+            raw_records,
+            decode_error
+        })
+    }
+}
+
+/// Reads the records of a compressed batch: a record count, then the records compressed together.
+fn decompress_records(codec: i16, mut bytes: &[u8]) -> Result<Vec<Record>> {
+    let compression: CompressionType = CompressionType::from_id(codec)
+        .ok_or_else(|| anyhow!("Unsupported record batch compression codec: {}", codec))?;
+    let count: i32 = i32::from_kafka_bytes(&mut bytes)?;
+    let decompressed: Vec<u8> = compression.decompress(bytes)
+        .map_err(|e| e.context(format!("Failed to decompress {:?} records", compression)))?;
+    let mut reader: &[u8] = &decompressed;
+    let mut records: Vec<Record> = Vec::new();
+    for _ in 0..count {
+        records.push(Record::from_kafka_bytes(&mut reader)?);
+    }
+    Ok(records)
+}
+
+fn encode_record_batches(batches: Vec<RecordBatch>) -> Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for batch in batches {
+        batch.to_kafka_bytes(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Brokers may truncate the last batch in a fetch response to respect the requested size limits,
+/// so a trailing partial batch is silently dropped.
+fn decode_record_batches(bytes: Vec<u8>) -> Result<Vec<RecordBatch>> {
+    let mut batches: Vec<RecordBatch> = Vec::new();
+    let mut remaining: &[u8] = &bytes;
+
+    while remaining.len() >= RECORD_BATCH_LOG_OVERHEAD {
+        let batch_length: i32 = i32::from_be_bytes([remaining[8], remaining[9], remaining[10], remaining[11]]);
+        if batch_length < 0 || remaining.len() < RECORD_BATCH_LOG_OVERHEAD + batch_length as usize {
+            break;
+        }
+        batches.push(RecordBatch::from_kafka_bytes(&mut remaining)?);
+    }
+    Ok(batches)
+}
+
+// RECORDS
+/// Zero or more record batches, encoded back-to-back as NULLABLE_BYTES.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Records(pub Option<Vec<RecordBatch>>);
+
+impl KafkaEncodable for Records {
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        match self.0 {
+            Some(batches) => NullableBytes(Some(encode_record_batches(batches)?)).to_kafka_bytes(writer),
+            None => NullableBytes(None).to_kafka_bytes(writer)
+        }
+    }
+
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<Records> {
+        match NullableBytes::from_kafka_bytes(reader)?.0 {
+            Some(bytes) => Ok(Records(Some(decode_record_batches(bytes)?))),
+            None => Ok(Records(None))
+        }
+    }
+}
+
+// COMPACT_RECORDS
+/// Zero or more record batches, encoded back-to-back as COMPACT_NULLABLE_BYTES.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CompactRecords(pub Option<Vec<RecordBatch>>);
+
+impl KafkaEncodable for CompactRecords {
+    fn to_kafka_bytes<W: Write + Debug>(self, writer: &mut W) -> Result<()> {
+        match self.0 {
+            Some(batches) => CompactNullableBytes(Some(encode_record_batches(batches)?)).to_kafka_bytes(writer),
+            None => CompactNullableBytes(None).to_kafka_bytes(writer)
+        }
+    }
+
+    fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<CompactRecords> {
+        match CompactNullableBytes::from_kafka_bytes(reader)?.0 {
+            Some(bytes) => Ok(CompactRecords(Some(decode_record_batches(bytes)?))),
+            None => Ok(CompactRecords(None))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{Array, VarI32, VarI64, VarIntArray, VarIntNullableBytes, VarIntString};
+    use crate::protocol::compression::CompressionType;
+    use crate::protocol::compression::tests::compress;
+    use crate::protocol::records::{Record, RecordBatch, RecordHeader, Records, COMPRESSION_CODEC_MASK, RECORD_BATCH_OVERHEAD};
+
+    // an uncompressed batch with one record: key "k", value "v" and header "h" => "x"
+    const SINGLE_RECORD_BATCH: [u8; 74] = [
+        0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 62, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 1, 134, 160, 0, 0, 0, 0, 0, 1, 134, 160, 255, 255, 255, 255, 255, 255,
+        255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 1, 24, 0, 0, 0, 2, 107, 2, 118, 2,
+        2, 104, 2, 120
+    ];
+
+    fn single_record_batch() -> RecordBatch {
+        RecordBatch {
+            base_offset: 5,
+            batch_length: 62,
+            partition_leader_epoch: 0,
+            magic: 2,
+            crc: 0,
+            attributes: 0,
+            last_offset_delta: 0,
+            base_timestamp: 100_000,
+            max_timestamp: 100_000,
+            producer_id: -1,
+            producer_epoch_offset: -1,
+            base_sequence: -1,
+            records: Array(vec![
+                Record {
+                    length: VarI32(12),
+                    attributes: 0,
+                    timestamp_delta: VarI64(0),
+                    offset_delta: VarI32(0),
+                    key: VarIntNullableBytes(Some(b"k".to_vec())),
+                    value: VarIntNullableBytes(Some(b"v".to_vec())),
+                    headers: VarIntArray(vec![
+                        RecordHeader {
+                            key: VarIntString(String::from("h")),
+                            value: VarIntNullableBytes(Some(b"x".to_vec()))
+                        }
+                    ])
+                }
+            ]),
+            raw_records: None,
+            decode_error: None
+        }
+    }
+
+    /// `batch` with its records compressed with `compression`.
+    pub(crate) fn compressed(batch: RecordBatch, compression: CompressionType) -> RecordBatch {
+        let mut records: Vec<u8> = Vec::new();
+        batch.records.clone().to_kafka_bytes(&mut records).unwrap();
+        let mut raw_records: Vec<u8> = records[..4].to_vec();
+        raw_records.extend(compress(compression, &records[4..]));
+        RecordBatch {
+            batch_length: batch.batch_length - records.len() as i32 + raw_records.len() as i32,
+            attributes: batch.attributes & !COMPRESSION_CODEC_MASK | compression as i16,
+            raw_records: Some(raw_records),
+            ..batch
+        }
+    }
+
+    #[test]
+    fn test_decode_record_batch() {
+        let batch: RecordBatch = RecordBatch::from_kafka_bytes(&mut &SINGLE_RECORD_BATCH[..]).unwrap();
+        assert_eq!(batch, single_record_batch());
+        assert_eq!(batch.last_offset(), 5);
+        assert!(!batch.is_control_batch());
+    }
+
+    #[test]
+    fn test_records_drop_truncated_trailing_batch() {
+        let mut bytes: Vec<u8> = vec![0, 0, 0, 84];
+        bytes.extend_from_slice(&SINGLE_RECORD_BATCH);
+        // the start of a second batch which the broker cut short
+        bytes.extend_from_slice(&SINGLE_RECORD_BATCH[..10]);
+        let records: Records = Records::from_kafka_bytes(&mut &*bytes).unwrap();
+        assert_eq!(records, Records(Some(vec![single_record_batch()])));
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let records: Records = Records(Some(vec![single_record_batch(), single_record_batch()]));
+        let mut bytes: Vec<u8> = Vec::new();
+        records.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(Records::from_kafka_bytes(&mut &*bytes).unwrap(), records);
+    }
+
+    #[test]
+    fn test_seal_fills_in_length_and_crc() {
+        let record: Record = Record::new(0, 0, Some(b"k".to_vec()), Some(b"v".to_vec()), vec![
+            RecordHeader {
+                key: VarIntString(String::from("h")),
+                value: VarIntNullableBytes(Some(b"x".to_vec()))
+            }
+        ]).unwrap();
+        assert_eq!(record.length, VarI32(12));
+        assert_eq!(record.size_in_bytes(), 13);
+
+        let batch: RecordBatch = RecordBatch {
+            base_offset: 5,
+            partition_leader_epoch: 0,
+            ..RecordBatch::new(100_000, vec![record])
+        }.seal().unwrap();
+        assert_eq!(batch.batch_length as usize, RECORD_BATCH_OVERHEAD + 13 - 12);
+        assert_eq!(batch.crc, i32::from_be_bytes([213, 75, 131, 1]));
+        assert_eq!(batch, RecordBatch { crc: batch.crc, ..single_record_batch() });
+        assert!(batch.is_valid().unwrap());
+        assert!(!single_record_batch().is_valid().unwrap());
+    }
+
+    #[test]
+    fn test_compressed_record_batches_are_decompressed() {
+        for compression in [CompressionType::Gzip, CompressionType::Snappy, CompressionType::Lz4, CompressionType::Zstd] {
+            let batch: RecordBatch = compressed(single_record_batch(), compression);
+            let mut bytes: Vec<u8> = Vec::new();
+            batch.clone().to_kafka_bytes(&mut bytes).unwrap();
+            let decoded: RecordBatch = RecordBatch::from_kafka_bytes(&mut &*bytes).unwrap();
+            assert_eq!(decoded, batch, "{:?}", compression);
+            assert_eq!((decoded.compression_type(), decoded.records), (Some(compression), single_record_batch().records));
+        }
+    }
+
+    #[test]
+    fn test_undecodable_batches_keep_their_header_without_failing_the_records() {
+        let mut unknown_codec: Vec<u8> = SINGLE_RECORD_BATCH.to_vec();
+        unknown_codec[22] = 6;
+        let mut older_format: Vec<u8> = SINGLE_RECORD_BATCH.to_vec();
+        older_format[16] = 1;
+        let mut corrupt: Vec<u8> = SINGLE_RECORD_BATCH.to_vec();
+        // a record count which runs past the end of the batch
+        corrupt[60] = 2;
+        let mut bytes: Vec<u8> = vec![0, 0, 1, 40];
+        bytes.extend_from_slice(&unknown_codec);
+        bytes.extend_from_slice(&older_format);
+        bytes.extend_from_slice(&corrupt);
+        bytes.extend_from_slice(&SINGLE_RECORD_BATCH);
+
+        let records: Records = Records::from_kafka_bytes(&mut &*bytes).unwrap();
+        let batches: Vec<RecordBatch> = records.0.clone().unwrap();
+        assert_eq!(batches.len(), 4);
+        assert_eq!((batches[0].base_offset, batches[0].compression_type(), batches[0].records.0.len()), (5, None, 0));
+        assert_eq!(batches[0].decode_error.as_deref(), Some("Unsupported record batch compression codec: 6"));
+        assert_eq!((batches[1].magic, batches[1].decode_error.as_deref()), (1, Some("Unsupported record batch magic: 1")));
+        assert!(batches[2].decode_error.as_deref().unwrap().starts_with("Corrupt records"));
+        assert_eq!(batches[3], single_record_batch());
+        // undecodable batches are written back as they were read
+        let mut encoded: Vec<u8> = Vec::new();
+        records.to_kafka_bytes(&mut encoded).unwrap();
+        assert_eq!(encoded, bytes);
+    }
 }
@@ -1,13 +1,22 @@
 use anyhow::Result;
 use std::fmt::Debug;
 use std::io::{Read, Write};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
-use bytes::buf::{Reader, Writer};
+use bytes::{BufMut, Bytes, BytesMut};
+use bytes::buf::Writer;
+#[cfg(test)]
+use bytes::Buf;
+#[cfg(test)]
+use bytes::buf::Reader;
 use kafka_encode::KafkaEncodable;
+#[cfg(test)]
 use kafka_encode::primitives::{CompactString, NullableString, VarArray};
+#[cfg(test)]
 use crate::protocol::api_key::ApiKey::ApiVersions;
+#[cfg(test)]
 use crate::protocol::api_versions::ApiVersionsRequestV3;
+#[cfg(test)]
 use crate::protocol::headers::RequestHeaderV2;
+#[cfg(test)]
 use crate::protocol::tags::TaggedFields;
 
 pub struct PairWithI32EncodedSize<T1, T2>(pub T1, pub T2) where
@@ -31,7 +40,7 @@ impl<T1, T2> KafkaEncodable for PairWithI32EncodedSize<T1, T2> where
     }
 
     fn from_kafka_bytes<R: Read + Debug>(reader: &mut R) -> Result<Self> {
-        let size: i32 = i32::from_kafka_bytes(reader)?;
+        let _size: i32 = i32::from_kafka_bytes(reader)?;
         let pair: PairWithI32EncodedSize<T1, T2> = PairWithI32EncodedSize(
             T1::from_kafka_bytes(reader)?,
             T2::from_kafka_bytes(reader)?
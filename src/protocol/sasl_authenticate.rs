@@ -0,0 +1,139 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactBytes, CompactNullableString, NullableString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslAuthenticateRequestV0V1 {
+    pub auth_bytes: Vec<u8>
+}
+
+impl KafkaRequest for SaslAuthenticateRequestV0V1 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::SaslAuthenticate
+    }
+
+    fn get_version() -> ApiVersion {
+        1
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslAuthenticateRequestV2 {
+    pub auth_bytes: CompactBytes,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for SaslAuthenticateRequestV2 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::SaslAuthenticate
+    }
+
+    fn get_version() -> ApiVersion {
+        2
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+// earlier versions of the response
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslAuthenticateResponseV0 {
+    pub error_code: ErrorCode,
+    pub error_message: NullableString,
+    pub auth_bytes: Vec<u8>
+}
+
+impl KafkaResponse for SaslAuthenticateResponseV0 {
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslAuthenticateResponseV1 {
+    pub error_code: ErrorCode,
+    pub error_message: NullableString,
+    pub auth_bytes: Vec<u8>,
+    /// How long the authenticated session may last before the client must re-authenticate, or 0 if
+    /// it doesn't expire.
+    pub session_lifetime_ms: i64
+}
+
+impl KafkaResponse for SaslAuthenticateResponseV1 {
+}
+
+// latest version of the response
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslAuthenticateResponseV2 {
+    pub error_code: ErrorCode,
+    pub error_message: CompactNullableString,
+    pub auth_bytes: CompactBytes,
+    pub session_lifetime_ms: i64,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for SaslAuthenticateResponseV2 {
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactBytes, CompactNullableString, NullableString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::sasl_authenticate::{SaslAuthenticateRequestV2, SaslAuthenticateResponseV1, SaslAuthenticateResponseV2};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_sasl_authenticate_request_v2() {
+        let mut bytes: Vec<u8> = Vec::new();
+        SaslAuthenticateRequestV2 {
+            auth_bytes: CompactBytes(b"\0u\0p".to_vec()),
+            tag_buffer: TaggedFields::new()
+        }.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![5, 0, 117, 0, 112, 0]);
+    }
+
+    #[test]
+    fn test_decode_sasl_authenticate_response_v1() {
+        let bytes: Vec<u8> = vec![0, 58, 0, 3, 98, 97, 100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            SaslAuthenticateResponseV1::from_kafka_bytes(&mut &*bytes).unwrap(),
+            SaslAuthenticateResponseV1 {
+                error_code: ErrorCode::SaslAuthenticationFailed,
+                error_message: NullableString(Some(String::from("bad"))),
+                auth_bytes: vec![],
+                session_lifetime_ms: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_sasl_authenticate_response_v2_round_trip() {
+        let response: SaslAuthenticateResponseV2 = SaslAuthenticateResponseV2 {
+            error_code: ErrorCode::None,
+            error_message: CompactNullableString(None),
+            auth_bytes: CompactBytes(b"v=signature".to_vec()),
+            session_lifetime_ms: 3_600_000,
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(SaslAuthenticateResponseV2::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
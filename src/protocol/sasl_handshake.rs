@@ -0,0 +1,70 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::Array;
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+
+// requests
+/// Version 0 is followed by raw SASL tokens on the socket, while version 1 is followed by
+/// SaslAuthenticate requests.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslHandshakeRequestV0V1 {
+    pub mechanism: String
+}
+
+impl KafkaRequest for SaslHandshakeRequestV0V1 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::SaslHandshake
+    }
+
+    fn get_version() -> ApiVersion {
+        1
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        1
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        0
+    }
+}
+
+// latest version of the response
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SaslHandshakeResponseV0V1 {
+    pub error_code: ErrorCode,
+    /// The mechanisms enabled on the broker.
+    pub mechanisms: Array<String>
+}
+
+impl KafkaResponse for SaslHandshakeResponseV0V1 {
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::Array;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::sasl_handshake::{SaslHandshakeRequestV0V1, SaslHandshakeResponseV0V1};
+
+    #[test]
+    fn test_encode_sasl_handshake_request() {
+        let mut bytes: Vec<u8> = Vec::new();
+        SaslHandshakeRequestV0V1 { mechanism: String::from("PLAIN") }.to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![0, 5, 80, 76, 65, 73, 78]);
+    }
+
+    #[test]
+    fn test_decode_sasl_handshake_response() {
+        let bytes: Vec<u8> = vec![0, 33, 0, 0, 0, 1, 0, 5, 80, 76, 65, 73, 78];
+        assert_eq!(
+            SaslHandshakeResponseV0V1::from_kafka_bytes(&mut &*bytes).unwrap(),
+            SaslHandshakeResponseV0V1 {
+                error_code: ErrorCode::UnsupportedSaslMechanism,
+                mechanisms: Array(vec![String::from("PLAIN")])
+            }
+        );
+    }
+}
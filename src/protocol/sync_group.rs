@@ -0,0 +1,116 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactBytes, CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SyncGroupRequestAssignmentV5 {
+    pub member_id: CompactString,
+    pub assignment: CompactBytes,
+    pub tag_buffer: TaggedFields
+}
+
+/// Version 5 added the protocol type and name, which the coordinator checks against the ones it chose.
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SyncGroupRequestV5 {
+    pub group_id: CompactString,
+    pub generation_id: i32,
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    pub protocol_type: CompactNullableString,
+    pub protocol_name: CompactNullableString,
+    /// Every member's assignment if this member is the leader, and empty otherwise.
+    pub assignments: CompactArray<SyncGroupRequestAssignmentV5>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for SyncGroupRequestV5 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::SyncGroup
+    }
+
+    fn get_version() -> ApiVersion {
+        5
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct SyncGroupResponseV5 {
+    pub throttle_time_ms: i32,
+    pub error_code: ErrorCode,
+    pub protocol_type: CompactNullableString,
+    pub protocol_name: CompactNullableString,
+    /// This member's assignment, from the leader.
+    pub assignment: CompactBytes,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for SyncGroupResponseV5 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactBytes, CompactNullableString, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::sync_group::{SyncGroupRequestAssignmentV5, SyncGroupRequestV5, SyncGroupResponseV5};
+    use crate::protocol::tags::TaggedFields;
+
+    #[test]
+    fn test_encode_sync_group_request() {
+        let request: SyncGroupRequestV5 = SyncGroupRequestV5 {
+            group_id: CompactString(String::from("g")),
+            generation_id: 1,
+            member_id: CompactString(String::from("m")),
+            group_instance_id: CompactNullableString(None),
+            protocol_type: CompactNullableString(Some(String::from("consumer"))),
+            protocol_name: CompactNullableString(Some(String::from("range"))),
+            assignments: CompactArray(vec![SyncGroupRequestAssignmentV5 {
+                member_id: CompactString(String::from("m")),
+                assignment: CompactBytes(vec![0, 0]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(&bytes[..9], &[2, 103, 0, 0, 0, 1, 2, 109, 0]);
+        assert_eq!(&bytes[bytes.len() - 8..], &[2, 2, 109, 3, 0, 0, 0, 0]);
+        assert_eq!(SyncGroupRequestV5::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_sync_group_response_round_trip() {
+        let response: SyncGroupResponseV5 = SyncGroupResponseV5 {
+            throttle_time_ms: 0,
+            error_code: ErrorCode::RebalanceInProgress,
+            protocol_type: CompactNullableString(None),
+            protocol_name: CompactNullableString(None),
+            assignment: CompactBytes(vec![]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 0, 27, 0, 0, 1, 0]);
+        assert_eq!(SyncGroupResponseV5::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
@@ -20,3 +20,9 @@ impl TaggedFields {
         }
     }
 }
+
+impl Default for TaggedFields {
+    fn default() -> Self {
+        TaggedFields::new()
+    }
+}
@@ -0,0 +1,147 @@
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+use kafka_encode_derive::KafkaEncodable;
+use crate::protocol::api_key::ApiKey;
+use crate::protocol::{ApiVersion, KafkaRequest, KafkaResponse};
+use crate::protocol::err::ErrorCode;
+use crate::protocol::tags::TaggedFields;
+
+// requests
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TxnOffsetCommitRequestPartitionV3 {
+    pub partition_index: i32,
+    pub committed_offset: i64,
+    /// -1 if unknown.
+    pub committed_leader_epoch: i32,
+    pub committed_metadata: CompactNullableString,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TxnOffsetCommitRequestTopicV3 {
+    pub name: CompactString,
+    pub partitions: CompactArray<TxnOffsetCommitRequestPartitionV3>,
+    pub tag_buffer: TaggedFields
+}
+
+/// Version 3 added the consumer's group membership, so that the group coordinator can fence
+/// commits from zombie consumers (KIP-447).
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TxnOffsetCommitRequestV3 {
+    pub transactional_id: CompactString,
+    pub group_id: CompactString,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub generation_id: i32,
+    pub member_id: CompactString,
+    pub group_instance_id: CompactNullableString,
+    pub topics: CompactArray<TxnOffsetCommitRequestTopicV3>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaRequest for TxnOffsetCommitRequestV3 {
+    fn get_api_key() -> ApiKey {
+        ApiKey::TxnOffsetCommit
+    }
+
+    fn get_version() -> ApiVersion {
+        3
+    }
+
+    fn get_request_header_version() -> ApiVersion {
+        2
+    }
+
+    fn get_response_header_version() -> ApiVersion {
+        1
+    }
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TxnOffsetCommitResponsePartitionV3 {
+    pub partition_index: i32,
+    pub error_code: ErrorCode,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TxnOffsetCommitResponseTopicV3 {
+    pub name: CompactString,
+    pub partitions: CompactArray<TxnOffsetCommitResponsePartitionV3>,
+    pub tag_buffer: TaggedFields
+}
+
+#[derive(Debug, KafkaEncodable, Eq, PartialEq, Clone)]
+pub struct TxnOffsetCommitResponseV3 {
+    pub throttle_time_ms: i32,
+    pub topics: CompactArray<TxnOffsetCommitResponseTopicV3>,
+    pub tag_buffer: TaggedFields
+}
+
+impl KafkaResponse for TxnOffsetCommitResponseV3 {
+    fn throttle_time_ms(&self) -> i32 {
+        self.throttle_time_ms
+    }
+
+    fn should_client_throttle(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_encode::KafkaEncodable;
+    use kafka_encode::primitives::{CompactArray, CompactNullableString, CompactString};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::tags::TaggedFields;
+    use crate::protocol::txn_offset_commit::{TxnOffsetCommitRequestPartitionV3, TxnOffsetCommitRequestTopicV3, TxnOffsetCommitRequestV3, TxnOffsetCommitResponsePartitionV3, TxnOffsetCommitResponseTopicV3, TxnOffsetCommitResponseV3};
+
+    #[test]
+    fn test_encode_txn_offset_commit_request() {
+        let request: TxnOffsetCommitRequestV3 = TxnOffsetCommitRequestV3 {
+            transactional_id: CompactString(String::from("t")),
+            group_id: CompactString(String::from("g")),
+            producer_id: 42,
+            producer_epoch: 1,
+            generation_id: -1,
+            member_id: CompactString(String::new()),
+            group_instance_id: CompactNullableString(None),
+            topics: CompactArray(vec![TxnOffsetCommitRequestTopicV3 {
+                name: CompactString(String::from("foo")),
+                partitions: CompactArray(vec![TxnOffsetCommitRequestPartitionV3 {
+                    partition_index: 0,
+                    committed_offset: 10,
+                    committed_leader_epoch: -1,
+                    committed_metadata: CompactNullableString(Some(String::new())),
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        request.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(&bytes[..21], &[2, 116, 2, 103, 0, 0, 0, 0, 0, 0, 0, 42, 0, 1, 255, 255, 255, 255, 1, 0, 2]);
+        assert_eq!(TxnOffsetCommitRequestV3::from_kafka_bytes(&mut &*bytes).unwrap(), request);
+    }
+
+    #[test]
+    fn test_txn_offset_commit_response_round_trip() {
+        let response: TxnOffsetCommitResponseV3 = TxnOffsetCommitResponseV3 {
+            throttle_time_ms: 0,
+            topics: CompactArray(vec![TxnOffsetCommitResponseTopicV3 {
+                name: CompactString(String::from("foo")),
+                partitions: CompactArray(vec![TxnOffsetCommitResponsePartitionV3 {
+                    partition_index: 0,
+                    error_code: ErrorCode::None,
+                    tag_buffer: TaggedFields::new()
+                }]),
+                tag_buffer: TaggedFields::new()
+            }]),
+            tag_buffer: TaggedFields::new()
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        response.clone().to_kafka_bytes(&mut bytes).unwrap();
+        assert_eq!(TxnOffsetCommitResponseV3::from_kafka_bytes(&mut &*bytes).unwrap(), response);
+    }
+}
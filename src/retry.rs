@@ -0,0 +1,275 @@
+use std::io::ErrorKind;
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use rand::Rng;
+use tracing::debug;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::networking::NetworkError;
+
+/// How much a backoff may be randomly shortened or lengthened, so that clients which failed at the
+/// same moment don't all retry at the same moment.
+const BACKOFF_JITTER: f64 = 0.2;
+
+/// A backoff which starts at `initial` and doubles with every consecutive failure up to `max`,
+/// before jitter.
+pub(crate) fn exponential_backoff(initial: Duration, max: Duration, failed_attempts: u32) -> Duration {
+    let exponent: u32 = failed_attempts.saturating_sub(1).min(31);
+    initial.saturating_mul(1 << exponent).min(max)
+}
+
+/// Randomly scales a backoff by up to `BACKOFF_JITTER` in either direction.
+pub(crate) fn with_jitter(backoff: Duration) -> Duration {
+    backoff.mul_f64(rand::thread_rng().gen_range(1.0 - BACKOFF_JITTER..=1.0 + BACKOFF_JITTER))
+}
+
+/// Whether a failed request may succeed if it is sent again: the broker returned a retriable
+/// `ErrorCode`, or the connection failed or timed out. Other I/O errors, such as a failed TLS
+/// handshake or a response which can't be decoded, would only fail again.
+pub fn is_retriable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error_code) = cause.downcast_ref::<ErrorCode>() {
+            error_code.is_retriable()
+        } else if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            is_connection_error(io_error.kind())
+        } else {
+            cause.is::<NetworkError>()
+        }
+    })
+}
+
+/// Whether an I/O error means the connection couldn't be made, was lost or timed out.
+fn is_connection_error(kind: ErrorKind) -> bool {
+    matches!(kind,
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::HostUnreachable
+        | ErrorKind::NetworkUnreachable
+        | ErrorKind::BrokenPipe
+        | ErrorKind::TimedOut
+        | ErrorKind::UnexpectedEof)
+}
+
+/// One of the attempts made by `RetryPolicy::run`.
+#[derive(Debug, Clone, Copy)]
+pub struct Attempt {
+    /// The number of retries before this attempt.
+    pub retry: u32,
+    /// When the delivery timeout runs out.
+    pub deadline: Instant
+}
+
+impl Attempt {
+    /// `timeout`, or less if the delivery timeout runs out first.
+    pub fn timeout(&self, timeout: Duration) -> Duration {
+        timeout.min(self.deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// When and how often failed requests are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// `retries`: the most times a request is retried after its first attempt.
+    pub retries: u32,
+    /// `retry.backoff.ms`: the backoff before the first retry.
+    pub retry_backoff: Duration,
+    /// `retry.backoff.max.ms`: the backoff doubles with every retry up to this.
+    pub retry_backoff_max: Duration,
+    /// `delivery.timeout.ms`: the total time allowed for every attempt and the backoffs between them.
+    pub delivery_timeout: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: i32::MAX as u32,
+            retry_backoff: Duration::from_millis(100),
+            retry_backoff_max: Duration::from_millis(1000),
+            delivery_timeout: Duration::from_millis(120_000)
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The backoff before retry number `retry`, counting from 1, including jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        with_jitter(exponential_backoff(self.retry_backoff, self.retry_backoff_max, retry))
+    }
+
+    /// Runs `attempt` until it succeeds, fails with an error which isn't retriable, runs out of
+    /// retries or would run past the delivery timeout. Requests sent by an attempt should wait no
+    /// longer than `Attempt::timeout` allows, so that the last one doesn't outlast the delivery timeout.
+    pub fn run<T, F>(&self, mut attempt: F) -> Result<T> where F: FnMut(Attempt) -> Result<T> {
+        let deadline: Instant = Instant::now() + self.delivery_timeout;
+        let mut retry: u32 = 0;
+        loop {
+            // an attempt with no time left could only time out, and would close its connection
+            if Instant::now() >= deadline {
+                let timed_out: std::io::Error = std::io::Error::new(ErrorKind::TimedOut,
+                    format!("Delivery timed out after {} attempts within {:?}", retry, self.delivery_timeout));
+                return Err(anyhow::Error::new(timed_out));
+            }
+            let error: anyhow::Error = match attempt(Attempt { retry, deadline }) {
+                Ok(result) => return Ok(result),
+                Err(e) => e
+            };
+            if !is_retriable(&error) {
+                return Err(error);
+            }
+            if retry >= self.retries {
+                return Err(error.context(format!("Giving up after {} retries", retry)));
+            }
+            retry += 1;
+            let backoff: Duration = self.backoff(retry);
+            if Instant::now() + backoff >= deadline {
+                return Err(error.context(format!("Delivery timed out after {} attempts within {:?}", retry, self.delivery_timeout)));
+            }
+            debug!("Retrying in {:?} after a retriable error: {}", backoff, error);
+            thread::sleep(backoff);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+    use anyhow::anyhow;
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::networking::NetworkError;
+    use crate::retry::{is_retriable, Attempt, RetryPolicy};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            retries: 5,
+            retry_backoff: Duration::from_millis(1),
+            retry_backoff_max: Duration::from_millis(4),
+            delivery_timeout: Duration::from_secs(10)
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_with_jitter() {
+        let policy: RetryPolicy = RetryPolicy {
+            retry_backoff: Duration::from_millis(100),
+            retry_backoff_max: Duration::from_millis(1000),
+            ..RetryPolicy::default()
+        };
+        for (retry, expected_millis) in [(1, 100.0), (2, 200.0), (3, 400.0), (4, 800.0), (5, 1000.0), (40, 1000.0)] {
+            let backoff: f64 = policy.backoff(retry).as_secs_f64() * 1000.0;
+            assert!(backoff >= expected_millis * 0.8 && backoff <= expected_millis * 1.2, "retry {} backed off {}ms", retry, backoff);
+        }
+    }
+
+    #[test]
+    fn test_retriable_errors() {
+        assert!(is_retriable(&anyhow::Error::new(ErrorCode::NotLeaderOrFollower)));
+        assert!(is_retriable(&anyhow::Error::new(ErrorCode::NotEnoughReplicas).context("Produce failed")));
+        assert!(!is_retriable(&anyhow::Error::new(ErrorCode::TopicAuthorizationFailed)));
+        assert!(is_retriable(&anyhow::Error::new(std::io::Error::from(ErrorKind::ConnectionReset))));
+        assert!(is_retriable(&anyhow::Error::new(NetworkError::RequestTimedOut { node_id: 1, timeout: Duration::from_secs(30) })));
+        assert!(!is_retriable(&anyhow!("Unrecognized response header version: 7")));
+    }
+
+    #[test]
+    fn test_tls_and_decoding_errors_are_not_retried() {
+        let certificate: rustls::Error = rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer);
+        let tls_error: anyhow::Error = anyhow::Error::new(std::io::Error::new(ErrorKind::InvalidData, certificate))
+            .context("Failed to connect to node 1");
+        assert!(!is_retriable(&tls_error));
+        assert!(!is_retriable(&anyhow::Error::new(std::io::Error::from(ErrorKind::InvalidData))));
+        assert!(is_retriable(&anyhow::Error::new(std::io::Error::from(ErrorKind::UnexpectedEof))));
+
+        let mut attempts: u32 = 0;
+        let error: anyhow::Error = policy().run(|_| {
+            attempts += 1;
+            Err::<(), anyhow::Error>(anyhow::Error::new(std::io::Error::new(ErrorKind::InvalidData, "invalid peer certificate")))
+        }).unwrap_err();
+        assert_eq!(attempts, 1);
+        assert_eq!(error.to_string(), "invalid peer certificate");
+    }
+
+    #[test]
+    fn test_retries_until_success() {
+        let mut attempts: u32 = 0;
+        let result: u32 = policy().run(|attempt: Attempt| {
+            attempts += 1;
+            match attempt.retry {
+                0 => Err(anyhow::Error::new(std::io::Error::from(ErrorKind::ConnectionRefused))),
+                1 => Err(anyhow::Error::new(ErrorCode::LeaderNotAvailable)),
+                _ => Ok(attempt.retry)
+            }
+        }).unwrap();
+        assert_eq!(result, 2);
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn test_fatal_errors_are_not_retried() {
+        let mut attempts: u32 = 0;
+        let error: anyhow::Error = policy().run(|_| {
+            attempts += 1;
+            Err::<(), anyhow::Error>(anyhow::Error::new(ErrorCode::InvalidRequest))
+        }).unwrap_err();
+        assert_eq!(error.downcast_ref::<ErrorCode>(), Some(&ErrorCode::InvalidRequest));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_retries_are_limited() {
+        let mut attempts: u32 = 0;
+        let error: anyhow::Error = RetryPolicy { retries: 2, ..policy() }.run(|_| {
+            attempts += 1;
+            Err::<(), anyhow::Error>(anyhow::Error::new(ErrorCode::NotCoordinator))
+        }).unwrap_err();
+        assert_eq!(attempts, 3);
+        assert_eq!(error.root_cause().downcast_ref::<ErrorCode>(), Some(&ErrorCode::NotCoordinator));
+    }
+
+    #[test]
+    fn test_delivery_timeout_bounds_retries() {
+        let policy: RetryPolicy = RetryPolicy {
+            retries: u32::MAX,
+            retry_backoff: Duration::from_millis(20),
+            retry_backoff_max: Duration::from_millis(20),
+            delivery_timeout: Duration::from_millis(100)
+        };
+        let started: Instant = Instant::now();
+        let mut attempts: u32 = 0;
+        let error: anyhow::Error = policy.run(|_| {
+            attempts += 1;
+            Err::<(), anyhow::Error>(anyhow::Error::new(ErrorCode::RequestTimedOut))
+        }).unwrap_err();
+        assert!(started.elapsed() < Duration::from_millis(150));
+        assert!((3..=7).contains(&attempts), "made {} attempts", attempts);
+        assert!(error.to_string().starts_with("Delivery timed out"));
+    }
+
+    #[test]
+    fn test_attempt_timeouts_end_at_the_delivery_timeout() {
+        let policy: RetryPolicy = RetryPolicy {
+            retries: u32::MAX,
+            retry_backoff: Duration::from_millis(20),
+            retry_backoff_max: Duration::from_millis(20),
+            delivery_timeout: Duration::from_millis(100)
+        };
+        let mut timeouts: Vec<Duration> = Vec::new();
+        policy.run(|attempt: Attempt| {
+            timeouts.push(attempt.timeout(Duration::from_secs(30)));
+            Err::<(), anyhow::Error>(anyhow::Error::new(ErrorCode::RequestTimedOut))
+        }).unwrap_err();
+        assert!(timeouts[0] <= Duration::from_millis(100));
+        assert!(timeouts.windows(2).all(|pair| pair[1] < pair[0]), "timeouts {:?}", timeouts);
+        assert_eq!(Attempt { retry: 0, deadline: Instant::now() + Duration::from_secs(60) }.timeout(Duration::from_secs(30)), Duration::from_secs(30));
+
+        let mut attempts: u32 = 0;
+        let error: anyhow::Error = RetryPolicy { delivery_timeout: Duration::ZERO, ..policy }.run(|_| {
+            attempts += 1;
+            Ok(())
+        }).unwrap_err();
+        assert_eq!(attempts, 0);
+        assert!(is_retriable(&error));
+    }
+}
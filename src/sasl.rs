@@ -0,0 +1,517 @@
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use kafka_encode::primitives::{Array, CompactBytes};
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256, Sha512};
+use tracing::debug;
+use crate::protocol::err::ErrorCode;
+use crate::protocol::networking::KafkaConnection;
+use crate::protocol::sasl_authenticate::{SaslAuthenticateRequestV2, SaslAuthenticateResponseV2};
+use crate::protocol::sasl_handshake::{SaslHandshakeRequestV0V1, SaslHandshakeResponseV0V1};
+use crate::protocol::tags::TaggedFields;
+
+/// Brokers refuse SCRAM credentials hashed with fewer iterations than this.
+const SCRAM_MIN_ITERATIONS: u32 = 4096;
+
+/// Like the Java client, connections re-authenticate somewhere between 85% and 95% of the way
+/// through their session, so that they don't all re-authenticate at once.
+const REAUTHENTICATION_WINDOW: std::ops::RangeInclusive<f64> = 0.85..=0.95;
+
+/// A SASL mechanism, as chosen with `sasl.mechanism`.
+pub trait SaslMechanism: Send + Sync + Debug {
+    /// The name sent in the SaslHandshake request, such as `PLAIN` or `SCRAM-SHA-256`.
+    fn name(&self) -> &str;
+
+    /// Begins authenticating a new connection.
+    fn start_exchange(&self) -> Result<Box<dyn SaslExchange>>;
+}
+
+/// The client side of one SASL authentication.
+pub trait SaslExchange {
+    /// Receives the broker's last message, which is empty at the start of the exchange, and
+    /// returns the next message to send, or `None` once authentication is complete.
+    fn evaluate_challenge(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>>;
+}
+
+/// Authenticates a freshly opened connection with SaslHandshake followed by as many SaslAuthenticate
+/// round trips as the mechanism needs. Returns the session lifetime the broker granted, if any.
+pub(crate) fn authenticate(connection: &mut KafkaConnection, mechanism: &dyn SaslMechanism) -> Result<Option<Duration>> {
+    let handshake: SaslHandshakeResponseV0V1 = connection.send_request_and_get_response(SaslHandshakeRequestV0V1 {
+        mechanism: String::from(mechanism.name())
+    })?;
+    if handshake.error_code != ErrorCode::None {
+        let Array(enabled_mechanisms) = handshake.mechanisms;
+        return Err(anyhow!("The broker does not accept SASL mechanism {} ({}). Enabled mechanisms: {:?}",
+            mechanism.name(), handshake.error_code, enabled_mechanisms));
+    }
+
+    let mut exchange: Box<dyn SaslExchange> = mechanism.start_exchange()?;
+    let mut challenge: Vec<u8> = Vec::new();
+    let mut session_lifetime_ms: i64 = 0;
+    while let Some(message) = exchange.evaluate_challenge(&challenge)? {
+        let response: SaslAuthenticateResponseV2 = connection.send_request_and_get_response(SaslAuthenticateRequestV2 {
+            auth_bytes: CompactBytes(message),
+            tag_buffer: TaggedFields::new()
+        })?;
+        if response.error_code != ErrorCode::None {
+            return Err(anyhow!("SASL {} authentication failed: {}", mechanism.name(),
+                response.error_message.0.unwrap_or_else(|| response.error_code.to_string())));
+        }
+        challenge = response.auth_bytes.0;
+        session_lifetime_ms = response.session_lifetime_ms;
+    }
+    debug!("Authenticated node {} with SASL {}", connection.node_id, mechanism.name());
+
+    if session_lifetime_ms > 0 {
+        Ok(Some(Duration::from_millis(session_lifetime_ms as u64)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// How long after authenticating a connection should re-authenticate, given the session lifetime
+/// the broker granted.
+pub(crate) fn reauthentication_delay(session_lifetime: Duration) -> Duration {
+    session_lifetime.mul_f64(rand::thread_rng().gen_range(REAUTHENTICATION_WINDOW))
+}
+
+/// SASL/PLAIN, which sends the username and password as they are. Only use it over TLS.
+#[derive(Clone)]
+pub struct PlainMechanism {
+    pub username: String,
+    pub password: String
+}
+
+impl PlainMechanism {
+    pub fn new(username: &str, password: &str) -> Self {
+        PlainMechanism {
+            username: String::from(username),
+            password: String::from(password)
+        }
+    }
+}
+
+impl Debug for PlainMechanism {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PlainMechanism")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl SaslMechanism for PlainMechanism {
+    fn name(&self) -> &str {
+        "PLAIN"
+    }
+
+    fn start_exchange(&self) -> Result<Box<dyn SaslExchange>> {
+        // an empty authorization id, then the authentication id and the password
+        let message: Vec<u8> = format!("\0{}\0{}", self.username, self.password).into_bytes();
+        Ok(Box::new(PlainExchange { message: Some(message) }))
+    }
+}
+
+struct PlainExchange {
+    message: Option<Vec<u8>>
+}
+
+impl SaslExchange for PlainExchange {
+    fn evaluate_challenge(&mut self, _challenge: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.message.take())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ScramAlgorithm {
+    Sha256,
+    Sha512
+}
+
+impl ScramAlgorithm {
+    pub fn mechanism_name(&self) -> &'static str {
+        match self {
+            ScramAlgorithm::Sha256 => "SCRAM-SHA-256",
+            ScramAlgorithm::Sha512 => "SCRAM-SHA-512"
+        }
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            ScramAlgorithm::Sha512 => Sha512::digest(data).to_vec()
+        }
+    }
+
+    fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramAlgorithm::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            },
+            ScramAlgorithm::Sha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    fn salted_password(&self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        match self {
+            ScramAlgorithm::Sha256 => {
+                let mut salted_password: Vec<u8> = vec![0; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut salted_password);
+                salted_password
+            },
+            ScramAlgorithm::Sha512 => {
+                let mut salted_password: Vec<u8> = vec![0; 64];
+                pbkdf2::pbkdf2_hmac::<Sha512>(password, salt, iterations, &mut salted_password);
+                salted_password
+            }
+        }
+    }
+}
+
+/// SASL/SCRAM-SHA-256 and SASL/SCRAM-SHA-512, as described in RFC 5802.
+#[derive(Clone)]
+pub struct ScramMechanism {
+    pub algorithm: ScramAlgorithm,
+    pub username: String,
+    pub password: String
+}
+
+impl ScramMechanism {
+    pub fn new(algorithm: ScramAlgorithm, username: &str, password: &str) -> Self {
+        ScramMechanism {
+            algorithm,
+            username: String::from(username),
+            password: String::from(password)
+        }
+    }
+
+    fn start_exchange_with_nonce(&self, client_nonce: String) -> ScramExchange {
+        ScramExchange {
+            algorithm: self.algorithm,
+            username: self.username.clone(),
+            password: self.password.clone(),
+            state: ScramState::Initial { client_nonce }
+        }
+    }
+}
+
+impl Debug for ScramMechanism {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScramMechanism")
+            .field("algorithm", &self.algorithm)
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl SaslMechanism for ScramMechanism {
+    fn name(&self) -> &str {
+        self.algorithm.mechanism_name()
+    }
+
+    fn start_exchange(&self) -> Result<Box<dyn SaslExchange>> {
+        let mut nonce_bytes: [u8; 24] = [0; 24];
+        rand::thread_rng().fill_bytes(&mut nonce_bytes);
+        Ok(Box::new(self.start_exchange_with_nonce(BASE64.encode(nonce_bytes))))
+    }
+}
+
+enum ScramState {
+    Initial { client_nonce: String },
+    ClientFirstSent { client_nonce: String, client_first_bare: String },
+    ClientFinalSent { server_signature: Vec<u8> },
+    Complete
+}
+
+struct ScramExchange {
+    algorithm: ScramAlgorithm,
+    username: String,
+    password: String,
+    state: ScramState
+}
+
+/// Escapes a username for use in a SCRAM message.
+fn scram_sasl_name(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
+
+/// Splits a SCRAM message like `r=abc,s=def,i=4096` into its attributes.
+fn scram_attributes(message: &str) -> Result<Vec<(char, &str)>> {
+    message.split(',')
+        .map(|attribute| {
+            let mut chars = attribute.chars();
+            match (chars.next(), chars.next()) {
+                (Some(key), Some('=')) => Ok((key, &attribute[2..])),
+                _ => Err(anyhow!("Malformed SCRAM attribute: {}", attribute))
+            }
+        })
+        .collect()
+}
+
+fn scram_attribute<'a>(attributes: &[(char, &'a str)], key: char) -> Result<&'a str> {
+    attributes.iter()
+        .find(|(attribute_key, _)| *attribute_key == key)
+        .map(|(_, value)| *value)
+        .ok_or_else(|| anyhow!("SCRAM message is missing attribute {}", key))
+}
+
+impl ScramExchange {
+    fn client_final(&self, client_nonce: &str, client_first_bare: &str, server_first: &str) -> Result<(String, Vec<u8>)> {
+        let attributes: Vec<(char, &str)> = scram_attributes(server_first)?;
+        if attributes.first().map(|(key, _)| *key) == Some('m') {
+            return Err(anyhow!("The broker requires an unsupported SCRAM extension"));
+        }
+        let nonce: &str = scram_attribute(&attributes, 'r')?;
+        if !nonce.starts_with(client_nonce) || nonce.len() == client_nonce.len() {
+            return Err(anyhow!("The broker's SCRAM nonce does not extend the client nonce"));
+        }
+        let salt: Vec<u8> = BASE64.decode(scram_attribute(&attributes, 's')?)?;
+        let iterations: u32 = scram_attribute(&attributes, 'i')?.parse()?;
+        if iterations < SCRAM_MIN_ITERATIONS {
+            return Err(anyhow!("The broker asked for {} SCRAM iterations, but at least {} are required", iterations, SCRAM_MIN_ITERATIONS));
+        }
+
+        let salted_password: Vec<u8> = self.algorithm.salted_password(self.password.as_bytes(), &salt, iterations);
+        let client_key: Vec<u8> = self.algorithm.hmac(&salted_password, b"Client Key");
+        let stored_key: Vec<u8> = self.algorithm.hash(&client_key);
+        // "biws" is the base64 encoding of the GS2 header "n,,"
+        let client_final_without_proof: String = format!("c=biws,r={}", nonce);
+        let auth_message: String = format!("{},{},{}", client_first_bare, server_first, client_final_without_proof);
+        let client_signature: Vec<u8> = self.algorithm.hmac(&stored_key, auth_message.as_bytes());
+        let client_proof: Vec<u8> = client_key.iter().zip(client_signature.iter())
+            .map(|(key_byte, signature_byte)| key_byte ^ signature_byte)
+            .collect();
+
+        let server_key: Vec<u8> = self.algorithm.hmac(&salted_password, b"Server Key");
+        let server_signature: Vec<u8> = self.algorithm.hmac(&server_key, auth_message.as_bytes());
+        Ok((format!("{},p={}", client_final_without_proof, BASE64.encode(client_proof)), server_signature))
+    }
+}
+
+impl SaslExchange for ScramExchange {
+    fn evaluate_challenge(&mut self, challenge: &[u8]) -> Result<Option<Vec<u8>>> {
+        match std::mem::replace(&mut self.state, ScramState::Complete) {
+            ScramState::Initial { client_nonce } => {
+                let client_first_bare: String = format!("n={},r={}", scram_sasl_name(&self.username), client_nonce);
+                let client_first: String = format!("n,,{}", client_first_bare);
+                self.state = ScramState::ClientFirstSent { client_nonce, client_first_bare };
+                Ok(Some(client_first.into_bytes()))
+            },
+            ScramState::ClientFirstSent { client_nonce, client_first_bare } => {
+                let server_first: &str = std::str::from_utf8(challenge)?;
+                let (client_final, server_signature) = self.client_final(&client_nonce, &client_first_bare, server_first)?;
+                self.state = ScramState::ClientFinalSent { server_signature };
+                Ok(Some(client_final.into_bytes()))
+            },
+            ScramState::ClientFinalSent { server_signature } => {
+                let server_final: &str = std::str::from_utf8(challenge)?;
+                let attributes: Vec<(char, &str)> = scram_attributes(server_final)?;
+                if let Ok(error) = scram_attribute(&attributes, 'e') {
+                    return Err(anyhow!("The broker rejected SCRAM authentication: {}", error));
+                }
+                if BASE64.decode(scram_attribute(&attributes, 'v')?)? != server_signature {
+                    return Err(anyhow!("The broker's SCRAM server signature is invalid"));
+                }
+                Ok(None)
+            },
+            ScramState::Complete => Err(anyhow!("The SCRAM exchange is already complete"))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use kafka_encode::primitives::{Array, CompactArray, CompactBytes, CompactNullableString, CompactString};
+    use crate::bootstrap::ResolvedAddress;
+    use crate::protocol::api_key::ApiKey;
+    use crate::protocol::api_versions::{ApiVersionsRequestV3, ApiVersionsResponseV3, ApiVersionsResponseV3TaggedFields};
+    use crate::protocol::err::ErrorCode;
+    use crate::protocol::mock_broker::{MockBroker, MockRequest};
+    use crate::protocol::networking::{ConnectionConfig, KafkaConnection};
+    use crate::protocol::sasl_authenticate::{SaslAuthenticateRequestV2, SaslAuthenticateResponseV2};
+    use crate::protocol::sasl_handshake::{SaslHandshakeRequestV0V1, SaslHandshakeResponseV0V1};
+    use crate::protocol::tags::TaggedFields;
+    use crate::sasl::{PlainMechanism, SaslExchange, SaslMechanism, ScramAlgorithm, ScramMechanism};
+    use crate::tls::tests::TestPki;
+
+    #[test]
+    fn test_plain_exchange() {
+        let mut exchange: Box<dyn SaslExchange> = PlainMechanism::new("alice", "secret").start_exchange().unwrap();
+        assert_eq!(exchange.evaluate_challenge(&[]).unwrap(), Some(b"\0alice\0secret".to_vec()));
+        assert_eq!(exchange.evaluate_challenge(&[]).unwrap(), None);
+    }
+
+    // the SCRAM-SHA-256 example exchange from RFC 7677
+    #[test]
+    fn test_scram_sha_256_exchange() {
+        let mechanism: ScramMechanism = ScramMechanism::new(ScramAlgorithm::Sha256, "user", "pencil");
+        let mut exchange = mechanism.start_exchange_with_nonce(String::from("rOprNGfwEbeRWgbNEkqO"));
+        assert_eq!(
+            exchange.evaluate_challenge(&[]).unwrap(),
+            Some(b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO".to_vec())
+        );
+        assert_eq!(
+            exchange.evaluate_challenge(b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096").unwrap(),
+            Some(b"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=".to_vec())
+        );
+        assert_eq!(exchange.evaluate_challenge(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=").unwrap(), None);
+    }
+
+    #[test]
+    fn test_scram_rejects_bad_server_messages() {
+        let mechanism: ScramMechanism = ScramMechanism::new(ScramAlgorithm::Sha256, "user", "pencil");
+
+        let mut exchange = mechanism.start_exchange_with_nonce(String::from("rOprNGfwEbeRWgbNEkqO"));
+        exchange.evaluate_challenge(&[]).unwrap();
+        exchange.evaluate_challenge(b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096").unwrap();
+        assert!(exchange.evaluate_challenge(b"v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=").is_err());
+
+        let mut exchange = mechanism.start_exchange_with_nonce(String::from("rOprNGfwEbeRWgbNEkqO"));
+        exchange.evaluate_challenge(&[]).unwrap();
+        assert!(exchange.evaluate_challenge(b"r=someone-elses-nonce,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096").is_err());
+
+        let mut exchange = mechanism.start_exchange_with_nonce(String::from("rOprNGfwEbeRWgbNEkqO"));
+        exchange.evaluate_challenge(&[]).unwrap();
+        assert!(exchange.evaluate_challenge(b"r=rOprNGfwEbeRWgbNEkqOabc,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=1024").is_err());
+    }
+
+    #[test]
+    fn test_scram_sha_512_proof_length() {
+        let mechanism: ScramMechanism = ScramMechanism::new(ScramAlgorithm::Sha512, "us=er,1", "pencil");
+        assert_eq!(mechanism.name(), "SCRAM-SHA-512");
+        let mut exchange = mechanism.start_exchange_with_nonce(String::from("nonce"));
+        assert_eq!(exchange.evaluate_challenge(&[]).unwrap(), Some(b"n,,n=us=3Der=2C1,r=nonce".to_vec()));
+        let client_final: Vec<u8> = exchange.evaluate_challenge(b"r=nonce123,s=c2FsdA==,i=4096").unwrap().unwrap();
+        let client_final: String = String::from_utf8(client_final).unwrap();
+        let proof: &str = client_final.rsplit_once(",p=").unwrap().1;
+        assert_eq!(base64::Engine::decode(&super::BASE64, proof).unwrap().len(), 64);
+    }
+
+    /// A broker which offers one mechanism and accepts clients whose single SASL message `accept`
+    /// approves, then answers ApiVersions requests. Counts the SaslHandshake requests it receives.
+    pub(crate) fn sasl_broker<F>(tls: Option<Arc<rustls::ServerConfig>>, mechanism: &'static str, session_lifetime_ms: i64,
+                                 handshakes: Arc<AtomicUsize>, accept: F) -> MockBroker
+        where F: Fn(&[u8]) -> bool + Send + Sync + 'static {
+        MockBroker::start_with_tls(tls, move |request: &MockRequest| {
+            if request.api_key == ApiKey::SaslHandshake as i16 {
+                handshakes.fetch_add(1, Ordering::SeqCst);
+                let handshake: SaslHandshakeRequestV0V1 = request.decode().unwrap();
+                let error_code: ErrorCode = if handshake.mechanism == mechanism { ErrorCode::None } else { ErrorCode::UnsupportedSaslMechanism };
+                Some(request.respond::<SaslHandshakeRequestV0V1, SaslHandshakeResponseV0V1>(SaslHandshakeResponseV0V1 {
+                    error_code,
+                    mechanisms: Array(vec![String::from(mechanism)])
+                }))
+            } else if request.api_key == ApiKey::SaslAuthenticate as i16 {
+                let authenticate: SaslAuthenticateRequestV2 = request.decode().unwrap();
+                let error_code: ErrorCode = if accept(&authenticate.auth_bytes.0) { ErrorCode::None } else { ErrorCode::SaslAuthenticationFailed };
+                Some(request.respond::<SaslAuthenticateRequestV2, SaslAuthenticateResponseV2>(SaslAuthenticateResponseV2 {
+                    error_code,
+                    error_message: CompactNullableString(None),
+                    auth_bytes: CompactBytes(vec![]),
+                    session_lifetime_ms,
+                    tag_buffer: TaggedFields::new()
+                }))
+            } else {
+                Some(request.respond::<ApiVersionsRequestV3, ApiVersionsResponseV3>(ApiVersionsResponseV3 {
+                    error_code: ErrorCode::None,
+                    api_keys: CompactArray(vec![]),
+                    throttle_time_ms: 0,
+                    tag_buffer: ApiVersionsResponseV3TaggedFields {
+                        supported_features: None,
+                        finalized_features_epoch: None,
+                        finalized_features: None
+                    }
+                }))
+            }
+        })
+    }
+
+    fn plain_broker(tls: Option<Arc<rustls::ServerConfig>>) -> MockBroker {
+        sasl_broker(tls, "PLAIN", 0, Arc::new(AtomicUsize::new(0)), |message: &[u8]| message == b"\0alice\0secret")
+    }
+
+    pub(crate) fn connect(broker: &MockBroker, config: &ConnectionConfig) -> anyhow::Result<KafkaConnection> {
+        let address: ResolvedAddress = ResolvedAddress {
+            host: String::from("127.0.0.1"),
+            address: SocketAddr::from(([127, 0, 0, 1], broker.port() as u16))
+        };
+        KafkaConnection::connect(1, &address, config)
+    }
+
+    pub(crate) fn api_versions_request() -> ApiVersionsRequestV3 {
+        ApiVersionsRequestV3 {
+            client_software_name: CompactString(String::from("kafkart")),
+            client_software_version: CompactString(String::from("0.0.1")),
+            tag_buffer: TaggedFields::new()
+        }
+    }
+
+    #[test]
+    fn test_connections_authenticate_with_sasl() {
+        let broker: MockBroker = plain_broker(None);
+
+        let mut connection: KafkaConnection = connect(&broker, &ConnectionConfig {
+            sasl: Some(Arc::new(PlainMechanism::new("alice", "secret"))),
+            ..ConnectionConfig::default()
+        }).unwrap();
+        let response: ApiVersionsResponseV3 = connection.send_request_and_get_response(api_versions_request()).unwrap();
+        assert_eq!(response.error_code, ErrorCode::None);
+        assert!(!connection.is_session_expired());
+
+        assert!(connect(&broker, &ConnectionConfig {
+            sasl: Some(Arc::new(PlainMechanism::new("alice", "guess"))),
+            ..ConnectionConfig::default()
+        }).is_err());
+        assert!(connect(&broker, &ConnectionConfig {
+            sasl: Some(Arc::new(ScramMechanism::new(ScramAlgorithm::Sha256, "alice", "secret"))),
+            ..ConnectionConfig::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn test_sasl_over_tls() {
+        let pki: TestPki = TestPki::new("127.0.0.1");
+        let broker: MockBroker = plain_broker(Some(pki.server_config(false)));
+        let mut connection: KafkaConnection = connect(&broker, &ConnectionConfig {
+            tls: Some(pki.client_config().build().unwrap()),
+            sasl: Some(Arc::new(PlainMechanism::new("alice", "secret"))),
+            ..ConnectionConfig::default()
+        }).unwrap();
+        let response: ApiVersionsResponseV3 = connection.send_request_and_get_response(api_versions_request()).unwrap();
+        assert_eq!(response.error_code, ErrorCode::None);
+    }
+
+    #[test]
+    fn test_connections_reauthenticate_before_the_session_expires() {
+        let handshakes: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let broker: MockBroker = sasl_broker(None, "PLAIN", 200, handshakes.clone(), |message: &[u8]| message == b"\0alice\0secret");
+        let mut connection: KafkaConnection = connect(&broker, &ConnectionConfig {
+            sasl: Some(Arc::new(PlainMechanism::new("alice", "secret"))),
+            ..ConnectionConfig::default()
+        }).unwrap();
+        let _: ApiVersionsResponseV3 = connection.send_request_and_get_response(api_versions_request()).unwrap();
+        assert_eq!(handshakes.load(Ordering::SeqCst), 1);
+
+        thread::sleep(Duration::from_millis(190));
+        let _: ApiVersionsResponseV3 = connection.send_request_and_get_response(api_versions_request()).unwrap();
+        assert_eq!(handshakes.load(Ordering::SeqCst), 2);
+        assert!(!connection.is_session_expired());
+        assert_eq!(broker.accepted_connections(), 1);
+    }
+}
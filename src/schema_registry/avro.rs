@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use anyhow::Result;
+use apache_avro::types::Value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::schema_registry::{read, Schema, SchemaRegistryClient, SchemaSerializerConfig, SchemaType, SchemaWriter};
+use crate::serialization::{Deserializer, Header, SerializationError, Serializer};
+
+/// Writes values which serde can handle as Avro with a schema from the registry.
+#[derive(Debug)]
+pub struct AvroSerializer {
+    writer: SchemaWriter,
+    schema: apache_avro::Schema
+}
+
+impl AvroSerializer {
+    /// Fails if `schema` isn't a valid Avro schema.
+    pub fn new(client: Arc<SchemaRegistryClient>, config: SchemaSerializerConfig, schema: &str) -> Result<Self> {
+        let parsed: apache_avro::Schema = apache_avro::Schema::parse_str(schema)?;
+        // primitive schemas have no name, so they are known by their type
+        let record_name: String = match parsed.name() {
+            Some(name) => name.fullname(None),
+            None => parsed.canonical_form().trim_matches('"').to_string()
+        };
+        Ok(AvroSerializer {
+            writer: SchemaWriter { client, config, schema: Schema::new(SchemaType::Avro, schema), record_name },
+            schema: parsed
+        })
+    }
+}
+
+impl<T> Serializer<T> for AvroSerializer where T: Serialize {
+    fn serialize(&self, topic: &str, _headers: &mut Vec<Header>, data: &T) -> Result<Option<Vec<u8>>, SerializationError> {
+        let value: Value = apache_avro::to_value(data).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        let value: Value = value.resolve(&self.schema).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        let payload: Vec<u8> = apache_avro::to_avro_datum(&self.schema, value).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        self.writer.write(topic, &payload).map(Some)
+    }
+}
+
+/// Reads Avro written with any schema in the registry, resolving it to a reader schema if there
+/// is one, so that readers can evolve separately from writers.
+#[derive(Debug)]
+pub struct AvroDeserializer {
+    client: Arc<SchemaRegistryClient>,
+    reader_schema: Option<apache_avro::Schema>,
+    writer_schemas: Mutex<HashMap<i32, apache_avro::Schema>>
+}
+
+impl AvroDeserializer {
+    pub fn new(client: Arc<SchemaRegistryClient>) -> Self {
+        AvroDeserializer { client, reader_schema: None, writer_schemas: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn with_reader_schema(mut self, schema: &str) -> Result<Self> {
+        self.reader_schema = Some(apache_avro::Schema::parse_str(schema)?);
+        Ok(self)
+    }
+
+    fn writer_schema(&self, id: i32, schema: &Schema) -> Result<apache_avro::Schema> {
+        let mut writer_schemas: MutexGuard<HashMap<i32, apache_avro::Schema>> = self.writer_schemas.lock().expect("Avro schema cache lock was poisoned");
+        if let Some(parsed) = writer_schemas.get(&id) {
+            return Ok(parsed.clone());
+        }
+        let parsed: apache_avro::Schema = apache_avro::Schema::parse_str(&schema.schema)?;
+        writer_schemas.insert(id, parsed.clone());
+        Ok(parsed)
+    }
+}
+
+impl<T> Deserializer<T> for AvroDeserializer where T: DeserializeOwned {
+    fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<T, SerializationError> {
+        let (id, schema, mut payload) = read(&self.client, topic, data, SchemaType::Avro)?;
+        let writer_schema: apache_avro::Schema = self.writer_schema(id, &schema)
+            .map_err(|e| SerializationError::new(topic, &format!("Schema {} is invalid: {:#}", id, e)))?;
+        let value: Value = apache_avro::from_avro_datum(&writer_schema, &mut payload, self.reader_schema.as_ref())
+            .map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        apache_avro::from_value(&value).map_err(|e| SerializationError::new(topic, &e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde::{Deserialize, Serialize};
+    use crate::schema_registry::avro::{AvroDeserializer, AvroSerializer};
+    use crate::schema_registry::tests::MockRegistry;
+    use crate::schema_registry::{SchemaRegistryClient, SchemaSerializerConfig, SubjectNameStrategy};
+    use crate::serialization::{Deserializer, Serializer};
+
+    const ORDER_SCHEMA: &str = r#"{
+        "type": "record", "name": "Order", "namespace": "com.example",
+        "fields": [{"name": "id", "type": "long"}, {"name": "item", "type": "string"}]
+    }"#;
+
+    #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+    struct Order {
+        id: i64,
+        item: String
+    }
+
+    #[derive(Debug, Deserialize, Eq, PartialEq)]
+    struct OrderWithQuantity {
+        id: i64,
+        item: String,
+        quantity: i32
+    }
+
+    #[test]
+    fn test_avro_round_trip() {
+        let registry: MockRegistry = MockRegistry::start();
+        let client: Arc<SchemaRegistryClient> = registry.client();
+        let serializer: AvroSerializer = AvroSerializer::new(client.clone(), SchemaSerializerConfig::default(), ORDER_SCHEMA).unwrap();
+        let order: Order = Order { id: 7, item: String::from("book") };
+        let bytes: Vec<u8> = serializer.serialize("orders", &mut Vec::new(), &order).unwrap().unwrap();
+        // the magic byte, schema id 1, then the zigzag-encoded id and the item
+        assert_eq!(bytes, vec![0, 0, 0, 0, 1, 14, 8, b'b', b'o', b'o', b'k']);
+        assert_eq!(registry.subjects(), vec!["orders-value"]);
+
+        // a new client has to fetch the schema
+        let deserializer: AvroDeserializer = AvroDeserializer::new(registry.client());
+        assert_eq!(deserializer.deserialize("orders", &[], &bytes), Ok(order));
+        assert!(Deserializer::<Order>::deserialize(&deserializer, "orders", &[], &bytes[..7]).is_err());
+    }
+
+    #[test]
+    fn test_readers_resolve_the_writer_schema() {
+        let registry: MockRegistry = MockRegistry::start();
+        let config: SchemaSerializerConfig = SchemaSerializerConfig { subject_name_strategy: SubjectNameStrategy::Record, ..SchemaSerializerConfig::default() };
+        let serializer: AvroSerializer = AvroSerializer::new(registry.client(), config, ORDER_SCHEMA).unwrap();
+        let bytes: Vec<u8> = serializer.serialize("orders", &mut Vec::new(), &Order { id: 7, item: String::from("book") }).unwrap().unwrap();
+        assert_eq!(registry.subjects(), vec!["com.example.Order"]);
+
+        // the reader added a field with a default
+        let deserializer: AvroDeserializer = AvroDeserializer::new(registry.client()).with_reader_schema(r#"{
+            "type": "record", "name": "Order", "namespace": "com.example",
+            "fields": [
+                {"name": "id", "type": "long"}, {"name": "item", "type": "string"},
+                {"name": "quantity", "type": "int", "default": 1}
+            ]
+        }"#).unwrap();
+        assert_eq!(deserializer.deserialize("orders", &[], &bytes), Ok(OrderWithQuantity { id: 7, item: String::from("book"), quantity: 1 }));
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::schema_registry::{read, Schema, SchemaRegistryClient, SchemaSerializerConfig, SchemaType, SchemaWriter};
+use crate::serialization::{Deserializer, Header, SerializationError, Serializer};
+
+/// Writes values which serde can handle as JSON with a JSON Schema from the registry. Values are
+/// not validated against the schema, so the schema only documents them.
+#[derive(Debug)]
+pub struct JsonSchemaSerializer {
+    writer: SchemaWriter
+}
+
+impl JsonSchemaSerializer {
+    /// Fails if `schema` isn't a JSON object. The schema's `title` names its record, which is
+    /// needed by `SubjectNameStrategy::Record` and `SubjectNameStrategy::TopicRecord`.
+    pub fn new(client: Arc<SchemaRegistryClient>, config: SchemaSerializerConfig, schema: &str) -> Result<Self> {
+        let parsed: serde_json::Value = serde_json::from_str(schema)?;
+        if !parsed.is_object() {
+            return Err(anyhow!("A JSON Schema has to be an object"));
+        }
+        let record_name: String = parsed.get("title").and_then(|title| title.as_str()).unwrap_or_default().to_string();
+        Ok(JsonSchemaSerializer {
+            writer: SchemaWriter { client, config, schema: Schema::new(SchemaType::Json, schema), record_name }
+        })
+    }
+}
+
+impl<T> Serializer<T> for JsonSchemaSerializer where T: Serialize {
+    fn serialize(&self, topic: &str, _headers: &mut Vec<Header>, data: &T) -> Result<Option<Vec<u8>>, SerializationError> {
+        let payload: Vec<u8> = serde_json::to_vec(data).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        self.writer.write(topic, &payload).map(Some)
+    }
+}
+
+/// Reads JSON written with a JSON Schema from the registry.
+pub struct JsonSchemaDeserializer<T> {
+    client: Arc<SchemaRegistryClient>,
+    value: PhantomData<fn() -> T>
+}
+
+impl<T> Debug for JsonSchemaDeserializer<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonSchemaDeserializer").field("client", &self.client).finish_non_exhaustive()
+    }
+}
+
+impl<T> JsonSchemaDeserializer<T> {
+    pub fn new(client: Arc<SchemaRegistryClient>) -> Self {
+        JsonSchemaDeserializer { client, value: PhantomData }
+    }
+}
+
+impl<T> Deserializer<T> for JsonSchemaDeserializer<T> where T: DeserializeOwned {
+    fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<T, SerializationError> {
+        let (_, _, payload) = read(&self.client, topic, data, SchemaType::Json)?;
+        serde_json::from_slice(payload).map_err(|e| SerializationError::new(topic, &e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use crate::schema_registry::json_schema::{JsonSchemaDeserializer, JsonSchemaSerializer};
+    use crate::schema_registry::protobuf::ProtobufDeserializer;
+    use crate::schema_registry::tests::MockRegistry;
+    use crate::schema_registry::{SchemaSerializerConfig, SubjectNameStrategy};
+    use crate::serialization::{Deserializer, SerializationError, Serializer};
+
+    const SCHEMA: &str = r#"{"title": "com.example.Order", "type": "object", "properties": {"id": {"type": "integer"}}}"#;
+
+    #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+    struct Order {
+        id: i64
+    }
+
+    #[test]
+    fn test_json_schema_round_trip() {
+        let registry: MockRegistry = MockRegistry::start();
+        let config: SchemaSerializerConfig = SchemaSerializerConfig { subject_name_strategy: SubjectNameStrategy::TopicRecord, ..SchemaSerializerConfig::default() };
+        let serializer: JsonSchemaSerializer = JsonSchemaSerializer::new(registry.client(), config, SCHEMA).unwrap();
+        let bytes: Vec<u8> = serializer.serialize("orders", &mut Vec::new(), &Order { id: 7 }).unwrap().unwrap();
+        assert_eq!(bytes, b"\0\0\0\0\x01{\"id\":7}");
+        assert_eq!(registry.subjects(), vec!["orders-com.example.Order"]);
+
+        let deserializer: JsonSchemaDeserializer<Order> = JsonSchemaDeserializer::new(registry.client());
+        assert_eq!(deserializer.deserialize("orders", &[], &bytes), Ok(Order { id: 7 }));
+        // the schema says the data is JSON
+        let protobuf: ProtobufDeserializer<()> = ProtobufDeserializer::new(registry.client());
+        assert!(protobuf.deserialize("orders", &[], &bytes).unwrap_err().message.contains("Json"));
+    }
+
+    #[test]
+    fn test_unregistered_schemas_fail_without_auto_registration() {
+        let registry: MockRegistry = MockRegistry::start();
+        let config: SchemaSerializerConfig = SchemaSerializerConfig { auto_register_schemas: false, ..SchemaSerializerConfig::default() };
+        let serializer: JsonSchemaSerializer = JsonSchemaSerializer::new(registry.client(), config, SCHEMA).unwrap();
+        let error: SerializationError = serializer.serialize("orders", &mut Vec::new(), &Order { id: 7 }).unwrap_err();
+        assert!(error.message.contains("orders-value"));
+        assert!(registry.subjects().is_empty());
+        assert!(JsonSchemaSerializer::new(registry.client(), SchemaSerializerConfig::default(), "[]").is_err());
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use crate::serialization::SerializationError;
+
+pub mod avro;
+pub mod json_schema;
+pub mod protobuf;
+
+/// The first byte of every key or value in the Confluent wire format, before the schema id.
+const MAGIC_BYTE: u8 = 0;
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+/// The format of a schema, as the registry names it in `schemaType`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SchemaType {
+    Avro,
+    Protobuf,
+    Json
+}
+
+impl SchemaType {
+    fn name(&self) -> &'static str {
+        match self {
+            SchemaType::Avro => "AVRO",
+            SchemaType::Protobuf => "PROTOBUF",
+            SchemaType::Json => "JSON"
+        }
+    }
+
+    /// The registry leaves out `schemaType` for Avro schemas.
+    fn from_name(name: Option<&str>) -> Result<Self> {
+        match name {
+            None | Some("AVRO") => Ok(SchemaType::Avro),
+            Some("PROTOBUF") => Ok(SchemaType::Protobuf),
+            Some("JSON") => Ok(SchemaType::Json),
+            Some(other) => Err(anyhow!("Unknown schema type: {}", other))
+        }
+    }
+}
+
+/// A schema as it is stored in the registry.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Schema {
+    pub schema_type: SchemaType,
+    pub schema: String
+}
+
+impl Schema {
+    pub fn new(schema_type: SchemaType, schema: &str) -> Self {
+        Schema { schema_type, schema: String::from(schema) }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SchemaRegistryConfig {
+    /// `schema.registry.url`
+    pub url: String,
+    /// `basic.auth.user.info`: a user name and password.
+    pub basic_auth: Option<(String, String)>,
+    pub request_timeout: Duration
+}
+
+impl SchemaRegistryConfig {
+    pub fn new(url: &str) -> Self {
+        SchemaRegistryConfig {
+            url: String::from(url.trim_end_matches('/')),
+            basic_auth: None,
+            request_timeout: Duration::from_millis(30_000)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SchemaRequest<'a> {
+    schema: &'a str,
+    #[serde(rename = "schemaType")]
+    schema_type: &'a str
+}
+
+#[derive(Deserialize)]
+struct IdResponse {
+    id: i32
+}
+
+#[derive(Deserialize)]
+struct SchemaResponse {
+    schema: String,
+    #[serde(rename = "schemaType")]
+    schema_type: Option<String>
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error_code: i32,
+    message: String
+}
+
+/// Registers and looks up schemas over the Schema Registry's REST API. Ids and schemas never
+/// change once registered, so each is only requested once.
+pub struct SchemaRegistryClient {
+    config: SchemaRegistryConfig,
+    http_client: reqwest::blocking::Client,
+    ids: Mutex<HashMap<(String, Schema), i32>>,
+    schemas: Mutex<HashMap<i32, Schema>>
+}
+
+impl Debug for SchemaRegistryClient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SchemaRegistryClient")
+            .field("url", &self.config.url)
+            .field("basic_auth", &self.config.basic_auth.as_ref().map(|(user, _)| (user, "<redacted>")))
+            .finish()
+    }
+}
+
+impl SchemaRegistryClient {
+    pub fn new(config: SchemaRegistryConfig) -> Result<Self> {
+        let http_client: reqwest::blocking::Client = reqwest::blocking::Client::builder()
+            .timeout(config.request_timeout)
+            .build()?;
+        Ok(SchemaRegistryClient {
+            config,
+            http_client,
+            ids: Mutex::new(HashMap::new()),
+            schemas: Mutex::new(HashMap::new())
+        })
+    }
+
+    fn send(&self, request: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response> {
+        let request: reqwest::blocking::RequestBuilder = match &self.config.basic_auth {
+            Some((user, password)) => request.basic_auth(user, Some(password)),
+            None => request
+        };
+        let response: reqwest::blocking::Response = request.header(reqwest::header::ACCEPT, CONTENT_TYPE).send()?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status: reqwest::StatusCode = response.status();
+        let body: String = response.text().unwrap_or_default();
+        Err(match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(error) => anyhow!("The schema registry responded with {} (error code {}): {}", status, error.error_code, error.message),
+            Err(_) => anyhow!("The schema registry responded with {}: {}", status, body)
+        })
+    }
+
+    fn post_schema(&self, path: &str, schema: &Schema) -> Result<i32> {
+        let request: SchemaRequest = SchemaRequest { schema: &schema.schema, schema_type: schema.schema_type.name() };
+        let response: IdResponse = self.send(self.http_client.post(format!("{}{}", self.config.url, path))
+            .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(serde_json::to_vec(&request)?))?
+            .json()?;
+        Ok(response.id)
+    }
+
+    fn cached_id(&self, subject: &str, schema: &Schema) -> Option<i32> {
+        self.ids.lock().expect("Schema registry cache lock was poisoned").get(&(String::from(subject), schema.clone())).copied()
+    }
+
+    fn cache(&self, subject: &str, schema: &Schema, id: i32) {
+        self.ids.lock().expect("Schema registry cache lock was poisoned").insert((String::from(subject), schema.clone()), id);
+        self.schemas.lock().expect("Schema registry cache lock was poisoned").insert(id, schema.clone());
+    }
+
+    /// Registers a schema under a subject, unless it already is, and returns its id.
+    pub fn register(&self, subject: &str, schema: &Schema) -> Result<i32> {
+        if let Some(id) = self.cached_id(subject, schema) {
+            return Ok(id);
+        }
+        let id: i32 = self.post_schema(&format!("/subjects/{}/versions", subject), schema)
+            .map_err(|e| e.context(format!("Failed to register a schema under subject {}", subject)))?;
+        debug!("Registered schema {} under subject {}", id, subject);
+        self.cache(subject, schema, id);
+        Ok(id)
+    }
+
+    /// The id of a schema which is already registered under a subject.
+    pub fn id(&self, subject: &str, schema: &Schema) -> Result<i32> {
+        if let Some(id) = self.cached_id(subject, schema) {
+            return Ok(id);
+        }
+        let id: i32 = self.post_schema(&format!("/subjects/{}", subject), schema)
+            .map_err(|e| e.context(format!("Failed to look up a schema under subject {}", subject)))?;
+        self.cache(subject, schema, id);
+        Ok(id)
+    }
+
+    /// The schema with an id, as written in the header of a key or value.
+    pub fn schema(&self, id: i32) -> Result<Schema> {
+        if let Some(schema) = self.schemas.lock().expect("Schema registry cache lock was poisoned").get(&id) {
+            return Ok(schema.clone());
+        }
+        let response: SchemaResponse = self.send(self.http_client.get(format!("{}/schemas/ids/{}", self.config.url, id)))
+            .map_err(|e| e.context(format!("Failed to fetch schema {}", id)))?
+            .json()?;
+        let schema: Schema = Schema { schema_type: SchemaType::from_name(response.schema_type.as_deref())?, schema: response.schema };
+        self.schemas.lock().expect("Schema registry cache lock was poisoned").insert(id, schema.clone());
+        Ok(schema)
+    }
+}
+
+/// How the subject a schema is registered under is named, like `key.subject.name.strategy` and
+/// `value.subject.name.strategy`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum SubjectNameStrategy {
+    /// `<topic>-key` or `<topic>-value`, so that a topic has one schema for its keys and one for
+    /// its values.
+    #[default]
+    Topic,
+    /// The fully qualified name of the record, so that a topic can hold several types of record.
+    Record,
+    /// `<topic>-<record name>`
+    TopicRecord
+}
+
+impl SubjectNameStrategy {
+    pub fn subject(&self, topic: &str, is_key: bool, record_name: &str) -> String {
+        match self {
+            SubjectNameStrategy::Topic => format!("{}-{}", topic, if is_key { "key" } else { "value" }),
+            SubjectNameStrategy::Record => String::from(record_name),
+            SubjectNameStrategy::TopicRecord => format!("{}-{}", topic, record_name)
+        }
+    }
+}
+
+/// Settings shared by the serializers of every format.
+#[derive(Debug, Clone)]
+pub struct SchemaSerializerConfig {
+    /// Whether the serializer is for keys, which matters to `SubjectNameStrategy::Topic`.
+    pub is_key: bool,
+    /// `auto.register.schemas`: register the schema if it isn't already, rather than failing.
+    pub auto_register_schemas: bool,
+    pub subject_name_strategy: SubjectNameStrategy
+}
+
+impl Default for SchemaSerializerConfig {
+    fn default() -> Self {
+        SchemaSerializerConfig {
+            is_key: false,
+            auto_register_schemas: true,
+            subject_name_strategy: SubjectNameStrategy::Topic
+        }
+    }
+}
+
+/// The schema a serializer writes with, and how it finds the schema's id for a topic.
+#[derive(Debug)]
+struct SchemaWriter {
+    client: Arc<SchemaRegistryClient>,
+    config: SchemaSerializerConfig,
+    schema: Schema,
+    /// The fully qualified name of the record the schema describes.
+    record_name: String
+}
+
+impl SchemaWriter {
+    /// Prefixes a payload with the magic byte and the schema's id.
+    fn write(&self, topic: &str, payload: &[u8]) -> Result<Vec<u8>, SerializationError> {
+        let subject: String = self.config.subject_name_strategy.subject(topic, self.config.is_key, &self.record_name);
+        let id: i32 = if self.config.auto_register_schemas {
+            self.client.register(&subject, &self.schema)
+        } else {
+            self.client.id(&subject, &self.schema)
+        }.map_err(|e| SerializationError::new(topic, &format!("{:#}", e)))?;
+        let mut bytes: Vec<u8> = Vec::with_capacity(5 + payload.len());
+        bytes.push(MAGIC_BYTE);
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend_from_slice(payload);
+        Ok(bytes)
+    }
+}
+
+/// Splits a key or value into the schema it was written with and its payload, checking that the
+/// schema is of the expected type.
+fn read<'a>(client: &SchemaRegistryClient, topic: &str, data: &'a [u8], schema_type: SchemaType) -> Result<(i32, Schema, &'a [u8]), SerializationError> {
+    if data.len() < 5 || data[0] != MAGIC_BYTE {
+        return Err(SerializationError::new(topic, "Unknown magic byte, so the data isn't in the schema registry's wire format"));
+    }
+    let id: i32 = i32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+    let schema: Schema = client.schema(id).map_err(|e| SerializationError::new(topic, &format!("{:#}", e)))?;
+    if schema.schema_type != schema_type {
+        return Err(SerializationError::new(topic, &format!("Schema {} is {:?}, not {:?}", id, schema.schema_type, schema_type)));
+    }
+    Ok((id, schema, &data[5..]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use serde_json::{json, Value};
+    use crate::oauth::tests::read_http_request;
+    use crate::schema_registry::{read, Schema, SchemaRegistryClient, SchemaRegistryConfig, SchemaType, SubjectNameStrategy};
+    use crate::serialization::SerializationError;
+
+    #[derive(Default)]
+    struct RegistryState {
+        /// Schemas and their types, whose ids are their positions plus one.
+        schemas: Vec<(String, Option<String>)>,
+        subjects: HashMap<String, Vec<i32>>,
+        requests: Vec<String>
+    }
+
+    /// A schema registry which keeps its schemas in memory and records the requests it receives.
+    pub(crate) struct MockRegistry {
+        pub url: String,
+        state: Arc<Mutex<RegistryState>>
+    }
+
+    impl MockRegistry {
+        pub(crate) fn start() -> Self {
+            let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url: String = format!("http://{}", listener.local_addr().unwrap());
+            let state: Arc<Mutex<RegistryState>> = Arc::new(Mutex::new(RegistryState::default()));
+            let registry_state: Arc<Mutex<RegistryState>> = state.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else { break };
+                    let request: String = read_http_request(&stream);
+                    let (status, body) = handle(&mut registry_state.lock().unwrap(), &request);
+                    let mut stream: TcpStream = stream;
+                    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                   status, body.len(), body);
+                }
+            });
+            MockRegistry { url, state }
+        }
+
+        pub(crate) fn client(&self) -> Arc<SchemaRegistryClient> {
+            Arc::new(SchemaRegistryClient::new(SchemaRegistryConfig::new(&self.url)).unwrap())
+        }
+
+        /// The method and path of every request so far.
+        pub(crate) fn requests(&self) -> Vec<String> {
+            self.state.lock().unwrap().requests.clone()
+        }
+
+        pub(crate) fn subjects(&self) -> Vec<String> {
+            let mut subjects: Vec<String> = self.state.lock().unwrap().subjects.keys().cloned().collect();
+            subjects.sort();
+            subjects
+        }
+    }
+
+    fn handle(state: &mut RegistryState, request: &str) -> (&'static str, String) {
+        let request_line: &str = request.lines().next().unwrap_or_default();
+        let mut parts = request_line.split(' ');
+        let method: &str = parts.next().unwrap_or_default();
+        let path: &str = parts.next().unwrap_or_default();
+        state.requests.push(format!("{} {}", method, path));
+        let body: Value = request.split("\r\n\r\n").nth(1)
+            .and_then(|body| serde_json::from_str(body).ok())
+            .unwrap_or(Value::Null);
+        let schema: (String, Option<String>) = (
+            body["schema"].as_str().unwrap_or_default().to_string(),
+            body["schemaType"].as_str().filter(|schema_type| *schema_type != "AVRO").map(String::from)
+        );
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("POST", ["subjects", subject, "versions"]) => {
+                let id: i32 = match state.schemas.iter().position(|registered| *registered == schema) {
+                    Some(index) => index as i32 + 1,
+                    None => {
+                        state.schemas.push(schema);
+                        state.schemas.len() as i32
+                    }
+                };
+                let ids: &mut Vec<i32> = state.subjects.entry(subject.to_string()).or_default();
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+                ("200 OK", json!({ "id": id }).to_string())
+            },
+            ("POST", ["subjects", subject]) => {
+                let found: Option<i32> = state.subjects.get(*subject).and_then(|ids| {
+                    ids.iter().copied().find(|id| state.schemas[*id as usize - 1] == schema)
+                });
+                match found {
+                    Some(id) => ("200 OK", json!({ "subject": subject, "id": id, "version": 1, "schema": schema.0 }).to_string()),
+                    None => ("404 Not Found", json!({ "error_code": 40403, "message": "Schema not found" }).to_string())
+                }
+            },
+            ("GET", ["schemas", "ids", id]) => match id.parse::<usize>().ok().and_then(|id| state.schemas.get(id.wrapping_sub(1))) {
+                Some((schema, Some(schema_type))) => ("200 OK", json!({ "schema": schema, "schemaType": schema_type }).to_string()),
+                Some((schema, None)) => ("200 OK", json!({ "schema": schema }).to_string()),
+                None => ("404 Not Found", json!({ "error_code": 40403, "message": "Schema not found" }).to_string())
+            },
+            _ => ("404 Not Found", json!({ "error_code": 404, "message": "HTTP 404 Not Found" }).to_string())
+        }
+    }
+
+    #[test]
+    fn test_schemas_and_ids_are_cached() {
+        let registry: MockRegistry = MockRegistry::start();
+        let client: Arc<SchemaRegistryClient> = registry.client();
+        let schema: Schema = Schema::new(SchemaType::Json, r#"{"type":"string"}"#);
+        assert!(client.id("foo-value", &schema).unwrap_err().to_string().contains("foo-value"));
+
+        assert_eq!(client.register("foo-value", &schema).unwrap(), 1);
+        assert_eq!(client.register("foo-value", &schema).unwrap(), 1);
+        assert_eq!(client.schema(1).unwrap(), schema);
+        assert_eq!(client.id("bar-value", &schema).map_err(|e| format!("{:#}", e)),
+                   Err(String::from("Failed to look up a schema under subject bar-value: The schema registry responded with 404 Not Found (error code 40403): Schema not found")));
+
+        let other_client: Arc<SchemaRegistryClient> = registry.client();
+        assert_eq!(other_client.id("foo-value", &schema).unwrap(), 1);
+        assert_eq!(other_client.schema(1).unwrap(), schema);
+        assert_eq!(registry.requests(), vec![
+            "POST /subjects/foo-value",
+            "POST /subjects/foo-value/versions",
+            "POST /subjects/bar-value",
+            "POST /subjects/foo-value"
+        ]);
+    }
+
+    #[test]
+    fn test_subject_name_strategies() {
+        assert_eq!(SubjectNameStrategy::Topic.subject("orders", true, "com.example.Order"), "orders-key");
+        assert_eq!(SubjectNameStrategy::Topic.subject("orders", false, "com.example.Order"), "orders-value");
+        assert_eq!(SubjectNameStrategy::Record.subject("orders", false, "com.example.Order"), "com.example.Order");
+        assert_eq!(SubjectNameStrategy::TopicRecord.subject("orders", false, "com.example.Order"), "orders-com.example.Order");
+    }
+
+    #[test]
+    fn test_data_without_the_magic_byte_is_rejected() {
+        let registry: MockRegistry = MockRegistry::start();
+        let client: Arc<SchemaRegistryClient> = registry.client();
+        let error: SerializationError = read(&client, "foo", b"{\"plain\": \"json\"}", SchemaType::Json).unwrap_err();
+        assert!(error.message.contains("magic byte"));
+        assert!(read(&client, "foo", &[0, 0, 0, 0, 9, 1], SchemaType::Json).is_err());
+        assert!(registry.requests().iter().all(|request| request == "GET /schemas/ids/9"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::{Debug, Formatter};
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use anyhow::Result;
+use kafka_encode::KafkaEncodable;
+use kafka_encode::primitives::VarI32;
+use crate::schema_registry::{read, Schema, SchemaRegistryClient, SchemaSerializerConfig, SchemaType, SchemaWriter};
+use crate::serialization::{Deserializer, Header, SerializationError, Serializer};
+
+/// Writes prost messages with a `.proto` schema from the registry.
+#[derive(Debug)]
+pub struct ProtobufSerializer {
+    writer: SchemaWriter,
+    /// The path to the message in the schema: the index of a top-level message, then of each
+    /// nested message.
+    message_indexes: Vec<i32>
+}
+
+impl ProtobufSerializer {
+    /// Writes the first message in `schema`, whose fully qualified name is `message_name`.
+    pub fn new(client: Arc<SchemaRegistryClient>, config: SchemaSerializerConfig, schema: &str, message_name: &str) -> Self {
+        ProtobufSerializer {
+            writer: SchemaWriter {
+                client,
+                config,
+                schema: Schema::new(SchemaType::Protobuf, schema),
+                record_name: String::from(message_name)
+            },
+            message_indexes: vec![0]
+        }
+    }
+
+    /// For a message other than the first one in the schema.
+    pub fn with_message_indexes(mut self, message_indexes: Vec<i32>) -> Self {
+        self.message_indexes = message_indexes;
+        self
+    }
+}
+
+/// Encodes message indexes as zigzag varints, with their count first. The common case of the
+/// first message is a single zero.
+fn write_message_indexes(message_indexes: &[i32], bytes: &mut Vec<u8>) -> Result<()> {
+    if message_indexes == [0] {
+        bytes.push(0);
+        return Ok(());
+    }
+    VarI32(message_indexes.len() as i32).to_kafka_bytes(bytes)?;
+    for index in message_indexes {
+        VarI32(*index).to_kafka_bytes(bytes)?;
+    }
+    Ok(())
+}
+
+fn read_message_indexes(reader: &mut Cursor<&[u8]>) -> Result<Vec<i32>> {
+    let count: i32 = VarI32::from_kafka_bytes(reader)?.0;
+    if count == 0 {
+        return Ok(vec![0]);
+    }
+    if count < 0 || count as usize > reader.get_ref().len() {
+        return Err(anyhow::anyhow!("Invalid number of message indexes: {}", count));
+    }
+    (0..count).map(|_| Ok(VarI32::from_kafka_bytes(reader)?.0)).collect()
+}
+
+impl<T> Serializer<T> for ProtobufSerializer where T: prost::Message {
+    fn serialize(&self, topic: &str, _headers: &mut Vec<Header>, data: &T) -> Result<Option<Vec<u8>>, SerializationError> {
+        let mut payload: Vec<u8> = Vec::new();
+        write_message_indexes(&self.message_indexes, &mut payload).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        data.encode(&mut payload).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        self.writer.write(topic, &payload).map(Some)
+    }
+}
+
+/// Reads prost messages written with a Protobuf schema from the registry. The message indexes are
+/// skipped, since the type to decode is fixed.
+pub struct ProtobufDeserializer<T> {
+    client: Arc<SchemaRegistryClient>,
+    message: PhantomData<fn() -> T>
+}
+
+impl<T> Debug for ProtobufDeserializer<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProtobufDeserializer").field("client", &self.client).finish_non_exhaustive()
+    }
+}
+
+impl<T> ProtobufDeserializer<T> {
+    pub fn new(client: Arc<SchemaRegistryClient>) -> Self {
+        ProtobufDeserializer { client, message: PhantomData }
+    }
+}
+
+impl<T> Deserializer<T> for ProtobufDeserializer<T> where T: prost::Message + Default {
+    fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<T, SerializationError> {
+        let (_, _, payload) = read(&self.client, topic, data, SchemaType::Protobuf)?;
+        let mut reader: Cursor<&[u8]> = Cursor::new(payload);
+        read_message_indexes(&mut reader).map_err(|e| SerializationError::new(topic, &e.to_string()))?;
+        let message: &[u8] = &payload[reader.position() as usize..];
+        T::decode(message).map_err(|e| SerializationError::new(topic, &e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::schema_registry::protobuf::{read_message_indexes, write_message_indexes, ProtobufDeserializer, ProtobufSerializer};
+    use crate::schema_registry::tests::MockRegistry;
+    use crate::schema_registry::{SchemaSerializerConfig, SchemaType};
+    use crate::serialization::{Deserializer, Serializer};
+
+    const SCHEMA: &str = r#"syntax = "proto3";
+package com.example;
+
+message Order {
+  int64 id = 1;
+  string item = 2;
+}
+
+message Refund {
+  int64 order_id = 1;
+}
+"#;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Refund {
+        #[prost(int64, tag = "1")]
+        order_id: i64
+    }
+
+    #[test]
+    fn test_message_indexes() {
+        for (indexes, expected) in [(vec![0], vec![0]), (vec![1], vec![2, 2]), (vec![1, 0, 2], vec![6, 2, 0, 4])] {
+            let mut bytes: Vec<u8> = Vec::new();
+            write_message_indexes(&indexes, &mut bytes).unwrap();
+            assert_eq!(bytes, expected);
+            assert_eq!(read_message_indexes(&mut Cursor::new(&bytes[..])).unwrap(), indexes);
+        }
+        assert!(read_message_indexes(&mut Cursor::new(&[20_u8][..])).is_err());
+    }
+
+    #[test]
+    fn test_protobuf_round_trip() {
+        let registry: MockRegistry = MockRegistry::start();
+        let serializer: ProtobufSerializer = ProtobufSerializer::new(registry.client(), SchemaSerializerConfig::default(), SCHEMA, "com.example.Refund")
+            .with_message_indexes(vec![1]);
+        let refund: Refund = Refund { order_id: 7 };
+        let bytes: Vec<u8> = serializer.serialize("refunds", &mut Vec::new(), &refund).unwrap().unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 0, 1, 2, 2, 8, 7]);
+        assert_eq!(registry.subjects(), vec!["refunds-value"]);
+        assert_eq!(registry.client().schema(1).unwrap().schema_type, SchemaType::Protobuf);
+
+        let deserializer: ProtobufDeserializer<Refund> = ProtobufDeserializer::new(registry.client());
+        assert_eq!(deserializer.deserialize("refunds", &[], &bytes), Ok(refund));
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::Debug;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+/// A record header: a key and an optional value.
+pub type Header = (String, Option<Vec<u8>>);
+
+/// Why a key or value couldn't be serialized or deserialized.
+#[derive(Debug, Clone, Error, Eq, PartialEq)]
+#[error("Invalid data for topic {topic}: {message}")]
+pub struct SerializationError {
+    pub topic: String,
+    pub message: String
+}
+
+impl SerializationError {
+    pub fn new(topic: &str, message: &str) -> Self {
+        SerializationError { topic: String::from(topic), message: String::from(message) }
+    }
+}
+
+/// Turns keys or values into bytes, like `key.serializer` and `value.serializer`. Serializers may
+/// add headers to the record. Returning `None` sends a null, as for a tombstone.
+pub trait Serializer<T>: Send + Sync + Debug {
+    fn serialize(&self, topic: &str, headers: &mut Vec<Header>, data: &T) -> Result<Option<Vec<u8>>, SerializationError>;
+}
+
+/// Turns bytes back into keys or values, like `key.deserializer` and `value.deserializer`. Null
+/// keys and values are never deserialized.
+pub trait Deserializer<T>: Send + Sync + Debug {
+    fn deserialize(&self, topic: &str, headers: &[Header], data: &[u8]) -> Result<T, SerializationError>;
+}
+
+/// Passes bytes through unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytesSerde;
+
+impl Serializer<Vec<u8>> for BytesSerde {
+    fn serialize(&self, _topic: &str, _headers: &mut Vec<Header>, data: &Vec<u8>) -> Result<Option<Vec<u8>>, SerializationError> {
+        Ok(Some(data.clone()))
+    }
+}
+
+impl Deserializer<Vec<u8>> for BytesSerde {
+    fn deserialize(&self, _topic: &str, _headers: &[Header], data: &[u8]) -> Result<Vec<u8>, SerializationError> {
+        Ok(data.to_vec())
+    }
+}
+
+/// UTF-8 strings.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StringSerde;
+
+impl Serializer<String> for StringSerde {
+    fn serialize(&self, _topic: &str, _headers: &mut Vec<Header>, data: &String) -> Result<Option<Vec<u8>>, SerializationError> {
+        Ok(Some(data.as_bytes().to_vec()))
+    }
+}
+
+impl Deserializer<String> for StringSerde {
+    fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<String, SerializationError> {
+        String::from_utf8(data.to_vec()).map_err(|e| SerializationError::new(topic, &e.to_string()))
+    }
+}
+
+/// Big-endian integers, compatible with Java's `ShortSerializer`, `IntegerSerializer` and
+/// `LongSerializer`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntegerSerde;
+
+macro_rules! integer_serde {
+    ($($integer:ty),*) => {
+        $(
+            impl Serializer<$integer> for IntegerSerde {
+                fn serialize(&self, _topic: &str, _headers: &mut Vec<Header>, data: &$integer) -> Result<Option<Vec<u8>>, SerializationError> {
+                    Ok(Some(data.to_be_bytes().to_vec()))
+                }
+            }
+
+            impl Deserializer<$integer> for IntegerSerde {
+                fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<$integer, SerializationError> {
+                    let bytes: [u8; std::mem::size_of::<$integer>()] = data.try_into().map_err(|_| SerializationError::new(
+                        topic, &format!("Expected {} bytes for {}, but got {}", std::mem::size_of::<$integer>(), stringify!($integer), data.len())
+                    ))?;
+                    Ok(<$integer>::from_be_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+integer_serde!(i16, i32, i64, u16, u32, u64);
+
+/// JSON for any type serde can handle.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonSerde;
+
+impl<T> Serializer<T> for JsonSerde where T: Serialize {
+    fn serialize(&self, topic: &str, _headers: &mut Vec<Header>, data: &T) -> Result<Option<Vec<u8>>, SerializationError> {
+        serde_json::to_vec(data).map(Some).map_err(|e| SerializationError::new(topic, &e.to_string()))
+    }
+}
+
+impl<T> Deserializer<T> for JsonSerde where T: DeserializeOwned {
+    fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<T, SerializationError> {
+        serde_json::from_slice(data).map_err(|e| SerializationError::new(topic, &e.to_string()))
+    }
+}
+
+/// For keys or values which are always null, like Java's `VoidSerializer`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnitSerde;
+
+impl Serializer<()> for UnitSerde {
+    fn serialize(&self, _topic: &str, _headers: &mut Vec<Header>, _data: &()) -> Result<Option<Vec<u8>>, SerializationError> {
+        Ok(None)
+    }
+}
+
+impl Deserializer<()> for UnitSerde {
+    fn deserialize(&self, topic: &str, _headers: &[Header], data: &[u8]) -> Result<(), SerializationError> {
+        Err(SerializationError::new(topic, &format!("Expected null data, but got {} bytes", data.len())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use crate::serialization::{BytesSerde, Deserializer, Header, IntegerSerde, JsonSerde, SerializationError, Serializer, StringSerde, UnitSerde};
+
+    fn round_trip<T, S>(serde: &S, data: T) -> T where S: Serializer<T> + Deserializer<T> {
+        let mut headers: Vec<Header> = Vec::new();
+        let bytes: Vec<u8> = serde.serialize("foo", &mut headers, &data).unwrap().unwrap();
+        serde.deserialize("foo", &headers, &bytes).unwrap()
+    }
+
+    #[test]
+    fn test_built_in_serdes_round_trip() {
+        assert_eq!(round_trip(&BytesSerde, vec![0, 1, 255]), vec![0, 1, 255]);
+        assert_eq!(round_trip(&StringSerde, String::from("grüße")), "grüße");
+        assert_eq!(round_trip(&IntegerSerde, -2_i16), -2);
+        assert_eq!(round_trip(&IntegerSerde, u64::MAX), u64::MAX);
+
+        #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+        struct Order { id: u32, item: String }
+        let order: Order = Order { id: 7, item: String::from("book") };
+        assert_eq!(round_trip(&JsonSerde, order), Order { id: 7, item: String::from("book") });
+    }
+
+    #[test]
+    fn test_integers_are_big_endian() {
+        let bytes: Option<Vec<u8>> = IntegerSerde.serialize("foo", &mut Vec::new(), &258_i32).unwrap();
+        assert_eq!(bytes, Some(vec![0, 0, 1, 2]));
+        let error: SerializationError = Deserializer::<i64>::deserialize(&IntegerSerde, "foo", &[], &[0, 0, 1, 2]).unwrap_err();
+        assert_eq!(error, SerializationError::new("foo", "Expected 8 bytes for i64, but got 4"));
+    }
+
+    #[test]
+    fn test_invalid_data_fails_to_deserialize() {
+        assert!(StringSerde.deserialize("foo", &[], &[0xff, 0xfe]).is_err());
+        assert!(Deserializer::<Vec<u32>>::deserialize(&JsonSerde, "foo", &[], b"{").is_err());
+        assert_eq!(UnitSerde.serialize("foo", &mut Vec::new(), &()).unwrap(), None);
+        assert!(UnitSerde.deserialize("foo", &[], b"x").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use std::fmt::Debug;
+use std::fs;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use p12_keystore::{KeyStore, KeyStoreEntry};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+use tracing::debug;
+
+/// The `ssl.*` settings of a client: which certificate authorities to trust, the certificate to
+/// present for mutual TLS, and whether the broker's certificate must match its hostname.
+#[derive(Debug)]
+pub struct TlsConfig {
+    /// `ssl.truststore.*`
+    pub ca_certificates: Vec<CertificateDer<'static>>,
+    /// `ssl.keystore.*`: the client certificate followed by any intermediates.
+    pub client_certificate_chain: Vec<CertificateDer<'static>>,
+    pub client_private_key: Option<PrivateKeyDer<'static>>,
+    /// Whether `ssl.endpoint.identification.algorithm` is `https`. The certificate chain is always
+    /// verified, but turning this off accepts a certificate issued for a different hostname.
+    pub verify_hostname: bool
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            ca_certificates: Vec::new(),
+            client_certificate_chain: Vec::new(),
+            client_private_key: None,
+            verify_hostname: true
+        }
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        TlsConfig::default()
+    }
+
+    /// Trusts every certificate in a PEM bundle.
+    pub fn add_ca_certificates_pem(&mut self, pem: &[u8]) -> Result<()> {
+        let certificates: Vec<CertificateDer<'static>> = parse_pem_certificates(pem)?;
+        if certificates.is_empty() {
+            return Err(anyhow!("The CA bundle contained no certificates"));
+        }
+        self.ca_certificates.extend(certificates);
+        Ok(())
+    }
+
+    pub fn add_ca_certificates_pem_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.add_ca_certificates_pem(&fs::read(path)?)
+    }
+
+    /// Trusts every certificate in a PKCS#12 truststore.
+    pub fn add_ca_certificates_pkcs12(&mut self, pkcs12: &[u8], password: &str) -> Result<()> {
+        let key_store: KeyStore = KeyStore::from_pkcs12(pkcs12, password)
+            .map_err(|e| anyhow!("Failed to read the PKCS#12 truststore: {:?}", e))?;
+        let mut found_certificate: bool = false;
+        for (_, entry) in key_store.entries() {
+            if let KeyStoreEntry::Certificate(certificate) = entry {
+                self.ca_certificates.push(CertificateDer::from(certificate.as_der().to_vec()));
+                found_certificate = true;
+            }
+        }
+        if !found_certificate {
+            return Err(anyhow!("The PKCS#12 truststore contained no certificates"));
+        }
+        Ok(())
+    }
+
+    /// Presents a client certificate for mutual TLS. `certificate_chain_pem` holds the client
+    /// certificate followed by any intermediates, and `private_key_pem` holds a PKCS#1, PKCS#8 or
+    /// SEC1 key.
+    pub fn set_client_identity_pem(&mut self, certificate_chain_pem: &[u8], private_key_pem: &[u8]) -> Result<()> {
+        let certificate_chain: Vec<CertificateDer<'static>> = parse_pem_certificates(certificate_chain_pem)?;
+        if certificate_chain.is_empty() {
+            return Err(anyhow!("The client certificate chain contained no certificates"));
+        }
+        let private_key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut &*private_key_pem)?
+            .ok_or_else(|| anyhow!("No private key was found in the client key PEM"))?;
+        self.client_certificate_chain = certificate_chain;
+        self.client_private_key = Some(private_key);
+        Ok(())
+    }
+
+    pub fn set_client_identity_pem_files<P: AsRef<Path>>(&mut self, certificate_chain_path: P, private_key_path: P) -> Result<()> {
+        self.set_client_identity_pem(&fs::read(certificate_chain_path)?, &fs::read(private_key_path)?)
+    }
+
+    /// Presents the first private key and certificate chain in a PKCS#12 keystore for mutual TLS.
+    pub fn set_client_identity_pkcs12(&mut self, pkcs12: &[u8], password: &str) -> Result<()> {
+        let key_store: KeyStore = KeyStore::from_pkcs12(pkcs12, password)
+            .map_err(|e| anyhow!("Failed to read the PKCS#12 keystore: {:?}", e))?;
+        let (alias, key_chain) = key_store.private_key_chain()
+            .ok_or_else(|| anyhow!("The PKCS#12 keystore contained no private key"))?;
+        debug!("Using client identity {} from the PKCS#12 keystore", alias);
+        self.client_certificate_chain = key_chain.chain().iter()
+            .map(|certificate| CertificateDer::from(certificate.as_der().to_vec()))
+            .collect();
+        self.client_private_key = Some(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_chain.key().to_vec())));
+        Ok(())
+    }
+
+    pub fn set_client_identity_pkcs12_file<P: AsRef<Path>>(&mut self, path: P, password: &str) -> Result<()> {
+        self.set_client_identity_pkcs12(&fs::read(path)?, password)
+    }
+
+    pub fn build(&self) -> Result<TlsConnector> {
+        let provider: Arc<CryptoProvider> = Arc::new(rustls::crypto::ring::default_provider());
+
+        let mut roots: RootCertStore = RootCertStore::empty();
+        for certificate in &self.ca_certificates {
+            roots.add(certificate.clone())?;
+        }
+        let verifier: Arc<WebPkiServerVerifier> = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()?;
+
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?;
+        let builder = if self.verify_hostname {
+            builder.with_webpki_verifier(verifier)
+        } else {
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(SkipHostnameVerification(verifier)))
+        };
+        let config: ClientConfig = match &self.client_private_key {
+            Some(private_key) => builder.with_client_auth_cert(self.client_certificate_chain.clone(), private_key.clone_key())?,
+            None => builder.with_no_client_auth()
+        };
+        Ok(TlsConnector {
+            config: Arc::new(config)
+        })
+    }
+}
+
+fn parse_pem_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    Ok(rustls_pemfile::certs(&mut &*pem).collect::<Result<Vec<CertificateDer<'static>>, std::io::Error>>()?)
+}
+
+/// Verifies the certificate chain as usual, but accepts a certificate which was issued for a
+/// different hostname.
+#[derive(Debug)]
+struct SkipHostnameVerification(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for SkipHostnameVerification {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, intermediates: &[CertificateDer<'_>],
+                          server_name: &ServerName<'_>, ocsp_response: &[u8], now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        match self.0.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+            | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. })) => Ok(ServerCertVerified::assertion()),
+            result => result
+        }
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], certificate: &CertificateDer<'_>,
+                              signature: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, certificate, signature)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], certificate: &CertificateDer<'_>,
+                              signature: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, certificate, signature)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+pub(crate) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// A built `TlsConfig`, cheap to clone and share between connections.
+#[derive(Debug, Clone)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>
+}
+
+impl TlsConnector {
+    /// Performs the TLS handshake over a connected socket, verifying the server against `host`.
+    pub(crate) fn connect(&self, host: &str, mut tcp_stream: TcpStream) -> Result<TlsStream> {
+        let server_name: ServerName<'static> = ServerName::try_from(String::from(host))
+            .map_err(|_| anyhow!("{} is not a valid TLS server name", host))?;
+        let mut connection: ClientConnection = ClientConnection::new(self.config.clone(), server_name)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut tcp_stream)?;
+        }
+        Ok(StreamOwned::new(connection, tcp_stream))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+    use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, DnType, IsCa, KeyPair};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+    use crate::tls::{TlsConfig, TlsConnector};
+
+    /// A throwaway certificate authority with a server certificate and a client certificate.
+    pub(crate) struct TestPki {
+        pub ca: CertifiedKey,
+        pub server: CertifiedKey,
+        pub client: CertifiedKey
+    }
+
+    impl TestPki {
+        pub(crate) fn new(server_host: &str) -> Self {
+            let ca_key: KeyPair = KeyPair::generate().unwrap();
+            let mut ca_params: CertificateParams = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            ca_params.distinguished_name.push(DnType::CommonName, "kafkart test CA");
+            let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+            let server_key: KeyPair = KeyPair::generate().unwrap();
+            let server_cert = CertificateParams::new(vec![String::from(server_host)]).unwrap()
+                .signed_by(&server_key, &ca_cert, &ca_key).unwrap();
+
+            let client_key: KeyPair = KeyPair::generate().unwrap();
+            let mut client_params: CertificateParams = CertificateParams::new(Vec::<String>::new()).unwrap();
+            client_params.distinguished_name.push(DnType::CommonName, "kafkart-client");
+            let client_cert = client_params.signed_by(&client_key, &ca_cert, &ca_key).unwrap();
+
+            TestPki {
+                ca: CertifiedKey { cert: ca_cert, key_pair: ca_key },
+                server: CertifiedKey { cert: server_cert, key_pair: server_key },
+                client: CertifiedKey { cert: client_cert, key_pair: client_key }
+            }
+        }
+
+        pub(crate) fn client_config(&self) -> TlsConfig {
+            let mut config: TlsConfig = TlsConfig::new();
+            config.add_ca_certificates_pem(self.ca.cert.pem().as_bytes()).unwrap();
+            config
+        }
+
+        /// A server config presenting the server certificate, optionally requiring a client
+        /// certificate issued by the test CA.
+        pub(crate) fn server_config(&self, require_client_certificate: bool) -> Arc<ServerConfig> {
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let builder = ServerConfig::builder_with_provider(provider.clone())
+                .with_safe_default_protocol_versions().unwrap();
+            let builder = if require_client_certificate {
+                let mut roots: RootCertStore = RootCertStore::empty();
+                roots.add(self.ca.cert.der().clone()).unwrap();
+                builder.with_client_cert_verifier(
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap()
+                )
+            } else {
+                builder.with_no_client_auth()
+            };
+            let key: PrivateKeyDer<'static> = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.server.key_pair.serialize_der()));
+            Arc::new(builder.with_single_cert(vec![self.server.cert.der().clone()], key).unwrap())
+        }
+    }
+
+    /// Echoes back whatever the first read returns, over TLS.
+    fn start_echo_server(server_config: Arc<ServerConfig>) -> u16 {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port: u16 = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let connection: ServerConnection = ServerConnection::new(server_config.clone()).unwrap();
+                let mut tls_stream = StreamOwned::new(connection, stream.unwrap());
+                let mut buffer: [u8; 64] = [0; 64];
+                if let Ok(read) = tls_stream.read(&mut buffer) {
+                    let _ = tls_stream.write_all(&buffer[..read]);
+                    let _ = tls_stream.flush();
+                }
+            }
+        });
+        port
+    }
+
+    fn echo(connector: &TlsConnector, host: &str, port: u16) -> anyhow::Result<Vec<u8>> {
+        let mut tls_stream = connector.connect(host, TcpStream::connect(("127.0.0.1", port))?)?;
+        tls_stream.write_all(b"kafka")?;
+        tls_stream.flush()?;
+        let mut buffer: [u8; 5] = [0; 5];
+        tls_stream.read_exact(&mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    #[test]
+    fn test_connect_with_ca_bundle() {
+        let pki: TestPki = TestPki::new("localhost");
+        let port: u16 = start_echo_server(pki.server_config(false));
+        let connector: TlsConnector = pki.client_config().build().unwrap();
+        assert_eq!(echo(&connector, "localhost", port).unwrap(), b"kafka");
+    }
+
+    #[test]
+    fn test_untrusted_certificate_is_rejected() {
+        let pki: TestPki = TestPki::new("localhost");
+        let port: u16 = start_echo_server(pki.server_config(false));
+        let connector: TlsConnector = TestPki::new("localhost").client_config().build().unwrap();
+        assert!(echo(&connector, "localhost", port).is_err());
+    }
+
+    #[test]
+    fn test_hostname_verification_can_be_disabled() {
+        let pki: TestPki = TestPki::new("broker.example.com");
+        let port: u16 = start_echo_server(pki.server_config(false));
+        let connector: TlsConnector = pki.client_config().build().unwrap();
+        assert!(echo(&connector, "localhost", port).is_err());
+
+        let mut config: TlsConfig = pki.client_config();
+        config.verify_hostname = false;
+        let connector: TlsConnector = config.build().unwrap();
+        assert_eq!(echo(&connector, "localhost", port).unwrap(), b"kafka");
+    }
+
+    #[test]
+    fn test_mutual_tls_with_pem_identity() {
+        let pki: TestPki = TestPki::new("localhost");
+        let port: u16 = start_echo_server(pki.server_config(true));
+        let connector: TlsConnector = pki.client_config().build().unwrap();
+        assert!(echo(&connector, "localhost", port).is_err());
+
+        let mut config: TlsConfig = pki.client_config();
+        config.set_client_identity_pem(pki.client.cert.pem().as_bytes(), pki.client.key_pair.serialize_pem().as_bytes()).unwrap();
+        let connector: TlsConnector = config.build().unwrap();
+        assert_eq!(echo(&connector, "localhost", port).unwrap(), b"kafka");
+    }
+
+    #[test]
+    fn test_mutual_tls_with_pkcs12_keystore_and_truststore() {
+        let pki: TestPki = TestPki::new("localhost");
+        let port: u16 = start_echo_server(pki.server_config(true));
+
+        let ca_certificate: Certificate = Certificate::from_der(pki.ca.cert.der()).unwrap();
+        let mut key_store: KeyStore = KeyStore::new();
+        key_store.add_entry("client", KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(
+            pki.client.key_pair.serialize_der(),
+            [1],
+            vec![Certificate::from_der(pki.client.cert.der()).unwrap(), ca_certificate.clone()]
+        )));
+        let key_store_bytes: Vec<u8> = key_store.writer("keystore-password").write().unwrap();
+
+        let mut trust_store: KeyStore = KeyStore::new();
+        trust_store.add_entry("ca", KeyStoreEntry::Certificate(ca_certificate));
+        let trust_store_bytes: Vec<u8> = trust_store.writer("truststore-password").write().unwrap();
+
+        let mut config: TlsConfig = TlsConfig::new();
+        assert!(config.add_ca_certificates_pkcs12(&trust_store_bytes, "wrong-password").is_err());
+        config.add_ca_certificates_pkcs12(&trust_store_bytes, "truststore-password").unwrap();
+        config.set_client_identity_pkcs12(&key_store_bytes, "keystore-password").unwrap();
+        assert_eq!(config.client_certificate_chain.len(), 2);
+        assert_eq!(config.client_certificate_chain[0], CertificateDer::from(pki.client.cert.der().to_vec()));
+
+        let connector: TlsConnector = config.build().unwrap();
+        assert_eq!(echo(&connector, "localhost", port).unwrap(), b"kafka");
+    }
+}